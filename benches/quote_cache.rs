@@ -0,0 +1,158 @@
+// benches/quote_cache.rs
+//! Время жадного солвера на 10 000 чанков с кэшем котировок пулов и без него
+//!
+//! Рынок - восемь синтетических пулов USDC/WETH разной глубины и комиссии.
+//! Оба случая идут через одну обертку `Bench`, чтобы отличалась только
+//! котировка: в случае без кэша обертка после каждого изменения резервов
+//! ставит кэш, построенный не из них, и `raw_amount_out` считает по прямой
+//! формуле `math::get_amount_out_with_fee`.
+use alloy::primitives::{Address, U256};
+use criterion::{criterion_group, criterion_main, Criterion};
+use swap_aggregator::amm::AmmPool;
+use swap_aggregator::config::{ConfigContext, DexId, TokenId};
+use swap_aggregator::pool::{PoolError, PoolState, QuoteCache};
+use swap_aggregator::solver::{find_best_routes, SolverConfig, Strategy};
+
+const NUM_CHUNKS: u64 = 10_000;
+
+/// Пул с кэшем котировок (`cached`) или с кэшем, который никогда не совпадает
+#[derive(Debug, Clone)]
+struct Bench {
+    pool: PoolState,
+    cached: bool,
+}
+
+impl Bench {
+    fn new(pool: PoolState, cached: bool) -> Self {
+        let mut bench = Bench { pool, cached };
+        bench.reset_cache();
+        bench
+    }
+
+    fn reset_cache(&mut self) {
+        if !self.cached {
+            self.pool.quote_cache = QuoteCache::new(U256::ZERO, U256::ZERO, self.pool.fee_bps);
+        }
+    }
+}
+
+impl AmmPool for Bench {
+    fn name(&self) -> &str {
+        self.pool.name()
+    }
+
+    fn address(&self) -> Address {
+        self.pool.address()
+    }
+
+    fn dex(&self) -> DexId {
+        self.pool.dex()
+    }
+
+    fn tokens(&self) -> (TokenId, TokenId) {
+        self.pool.tokens()
+    }
+
+    fn decimals(&self, token: TokenId) -> u8 {
+        self.pool.decimals(token)
+    }
+
+    fn reserves(&self, token_in: TokenId) -> (U256, U256) {
+        self.pool.reserves(token_in)
+    }
+
+    fn quote(&self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        AmmPool::quote(&self.pool, amount_in, token_in)
+    }
+
+    fn apply(&mut self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        let amount_out = self.pool.apply(amount_in, token_in);
+        self.reset_cache();
+        amount_out
+    }
+
+    fn spot_price(&self, token_in: TokenId) -> f64 {
+        AmmPool::spot_price(&self.pool, token_in)
+    }
+
+    fn price_impact(&self, amount_in: U256, token_in: TokenId) -> f64 {
+        AmmPool::price_impact(&self.pool, amount_in, token_in)
+    }
+
+    fn apply_reserve_haircut(&mut self, haircut_bps: u32, token_in: TokenId) {
+        AmmPool::apply_reserve_haircut(&mut self.pool, haircut_bps, token_in);
+        self.reset_cache();
+    }
+
+    fn swap_fee_amount(&self, amount_in: U256) -> U256 {
+        AmmPool::swap_fee_amount(&self.pool, amount_in)
+    }
+
+    fn protocol_fee_enabled(&self) -> bool {
+        self.pool.protocol_fee_enabled()
+    }
+
+    fn constant_product_fee_bps(&self) -> Option<u32> {
+        self.pool.constant_product_fee_bps()
+    }
+
+    fn clone_box(&self) -> Box<dyn AmmPool> {
+        Box::new(self.clone())
+    }
+}
+
+/// Пул USDC/WETH с резервами в decimal
+fn state(address_byte: u8, reserve_usdc: u64, reserve_weth: u64, fee_bps: u32) -> PoolState {
+    let mut pool = PoolState::new(
+        Address::repeat_byte(address_byte),
+        TokenId::USDC,
+        TokenId::WETH,
+        DexId::QUICKSWAP,
+        format!("USDC/WETH {}", address_byte),
+    );
+    let usdc = U256::from(reserve_usdc) * U256::from(10u64).pow(U256::from(6u64));
+    let weth = U256::from(reserve_weth) * U256::from(10u64).pow(U256::from(18u64));
+    (pool.reserve_token0, pool.reserve_token1) = if pool.token0 == TokenId::USDC { (usdc, weth) } else { (weth, usdc) };
+    pool.with_fee_bps(fee_bps)
+}
+
+fn market(cached: bool) -> Vec<Bench> {
+    [
+        (0x11, 3_000_000, 1_200, 30),
+        (0x12, 1_000_000, 402, 30),
+        (0x13, 5_000_000, 1_995, 25),
+        (0x14, 250_000, 101, 30),
+        (0x15, 800_000, 319, 20),
+        (0x16, 2_000_000, 803, 30),
+        (0x17, 120_000, 48, 100),
+        (0x18, 4_000_000, 1_601, 5),
+    ]
+    .into_iter()
+    .map(|(byte, reserve_usdc, reserve_weth, fee_bps)| Bench::new(state(byte, reserve_usdc, reserve_weth, fee_bps), cached))
+    .collect()
+}
+
+fn quote_cache(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let ctx = ConfigContext::default();
+    let solver_config = SolverConfig {
+        total_amount_in: U256::from(2_000_000u64) * U256::from(10u64).pow(U256::from(6u64)),
+        num_chunks: NUM_CHUNKS,
+        verbose: false,
+        strategy: Strategy::Greedy,
+        ..SolverConfig::default()
+    };
+
+    let mut group = c.benchmark_group("solve_10k_chunks");
+    group.sample_size(20);
+    for (name, cached) in [("quote_cache", true), ("direct_formula", false)] {
+        let pools = market(cached);
+        group.bench_function(name, |b| {
+            b.iter(|| runtime.block_on(find_best_routes(pools.clone(), &ctx, &solver_config)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, quote_cache);
+criterion_main!(benches);
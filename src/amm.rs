@@ -0,0 +1,483 @@
+// src/amm.rs
+//! Интерфейс пула для солвера
+//!
+//! Солвер работает с любым пулом, реализующим `AmmPool`: котировка и свап
+//! по входному токену, резервы для оценки спота и метаданные для маршрута.
+//! Аналитические стратегии дополнительно требуют constant product кривую
+//! (`constant_product_fee_bps`). Пулы разных типов можно смешивать через
+//! `Vec<Box<dyn AmmPool>>`.
+//!
+//! Не каждый пул можно точно симулировать локально: `fidelity` сообщает
+//! солверу, насколько `apply` совпадает с контрактом (см. `SimulationFidelity`).
+use crate::config::{DexId, TokenId};
+use crate::pool::{PoolError, PoolState};
+use alloy::primitives::{Address, U256};
+use std::fmt;
+
+/// Насколько точно `AmmPool::apply` воспроизводит свапы в сети
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SimulationFidelity {
+    /// Резервы меняются так же, как в контракте (constant product, взвешенные пулы)
+    #[default]
+    Exact,
+    /// Локальная модель с ошибкой выхода не больше `max_error_bps`; солвер
+    /// закладывает ее в `min_amount_out` и в `SolverResult::confidence`
+    Approximate { max_error_bps: u32 },
+    /// Локальная модель годится только для выбора между пулами: накопленный
+    /// вход пула перекотируется у источника (`AmmPool::requote`) каждый раз,
+    /// когда он вырос на `SolverConfig::requote_step_bps` от суммы обмена,
+    /// и в конце решения
+    RequoteOnly,
+}
+
+impl SimulationFidelity {
+    /// Бюджет ошибки выхода в bps (0 для точных и перекотируемых пулов)
+    pub fn error_bps(&self) -> u32 {
+        match self {
+            SimulationFidelity::Approximate { max_error_bps } => *max_error_bps,
+            SimulationFidelity::Exact | SimulationFidelity::RequoteOnly => 0,
+        }
+    }
+}
+
+/// Пул, по которому солвер может котировать и симулировать свапы
+pub trait AmmPool: fmt::Debug + Send + Sync {
+    /// Имя пула для маршрута и статистики
+    fn name(&self) -> &str;
+    fn address(&self) -> Address;
+    fn dex(&self) -> DexId;
+    /// Токены пула (token0, token1)
+    fn tokens(&self) -> (TokenId, TokenId);
+    /// Decimals токена пула
+    fn decimals(&self, token: TokenId) -> u8;
+    /// Резервы (reserve_in, reserve_out) для свапа из `token_in`
+    fn reserves(&self, token_in: TokenId) -> (U256, U256);
+    /// Выход свапа без изменения состояния; совпадает с результатом `apply`
+    fn quote(&self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError>;
+    /// Симулирует свап и обновляет состояние пула
+    fn apply(&mut self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError>;
+    /// Выходной токен за один входной до сделки (с учетом decimals)
+    fn spot_price(&self, token_in: TokenId) -> f64;
+    /// Price impact сделки (0.0 - 1.0)
+    fn price_impact(&self, amount_in: U256, token_in: TokenId) -> f64;
+    /// Уменьшает выходной резерв для консервативной котировки
+    fn apply_reserve_haircut(&mut self, haircut_bps: u32, token_in: TokenId);
+    /// Комиссия свапа во входном токене (raw units)
+    fn swap_fee_amount(&self, amount_in: U256) -> U256;
+    /// Часть комиссии LP уходит протоколу
+    fn protocol_fee_enabled(&self) -> bool {
+        false
+    }
+    /// Комиссия в bps, если пул constant product (x * y = k); иначе None
+    fn constant_product_fee_bps(&self) -> Option<u32> {
+        None
+    }
+    /// Точность локальной симуляции свапа
+    fn fidelity(&self) -> SimulationFidelity {
+        SimulationFidelity::Exact
+    }
+    /// Выход источника (контракта, квотера) за весь вход `cumulative_in`,
+    /// отданный пулу с начала решения; пул подстраивает под ответ локальную
+    /// модель. Вызывается солвером только для `SimulationFidelity::RequoteOnly`
+    fn requote(&mut self, _cumulative_in: U256, _token_in: TokenId) -> Result<U256, PoolError> {
+        Err(PoolError::MissingQuote)
+    }
+    fn clone_box(&self) -> Box<dyn AmmPool>;
+
+    /// Второй токен пары для `token`
+    fn other_token(&self, token: TokenId) -> Option<TokenId> {
+        let (token0, token1) = self.tokens();
+        if token == token0 {
+            Some(token1)
+        } else if token == token1 {
+            Some(token0)
+        } else {
+            None
+        }
+    }
+}
+
+impl AmmPool for PoolState {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn address(&self) -> Address {
+        self.pool_address
+    }
+
+    fn dex(&self) -> DexId {
+        self.dex
+    }
+
+    fn tokens(&self) -> (TokenId, TokenId) {
+        (self.token0, self.token1)
+    }
+
+    fn decimals(&self, token: TokenId) -> u8 {
+        if token == self.token0 { self.token0_decimals } else { self.token1_decimals }
+    }
+
+    fn reserves(&self, token_in: TokenId) -> (U256, U256) {
+        self.reserves_for(token_in == self.token0)
+    }
+
+    fn quote(&self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        self.quote_by_token(token_in, amount_in)
+    }
+
+    fn apply(&mut self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        self.swap_by_token(token_in, amount_in)
+    }
+
+    fn spot_price(&self, token_in: TokenId) -> f64 {
+        PoolState::spot_price(self, token_in == self.token0)
+    }
+
+    fn price_impact(&self, amount_in: U256, token_in: TokenId) -> f64 {
+        PoolState::price_impact(self, amount_in, token_in == self.token0)
+    }
+
+    fn apply_reserve_haircut(&mut self, haircut_bps: u32, token_in: TokenId) {
+        PoolState::apply_reserve_haircut(self, haircut_bps, token_in == self.token0)
+    }
+
+    fn swap_fee_amount(&self, amount_in: U256) -> U256 {
+        PoolState::swap_fee_amount(self, amount_in)
+    }
+
+    fn protocol_fee_enabled(&self) -> bool {
+        self.protocol_fee_enabled
+    }
+
+    fn constant_product_fee_bps(&self) -> Option<u32> {
+        self.is_constant_product().then_some(self.fee_bps)
+    }
+
+    fn clone_box(&self) -> Box<dyn AmmPool> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn AmmPool> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl AmmPool for Box<dyn AmmPool> {
+    fn name(&self) -> &str {
+        self.as_ref().name()
+    }
+
+    fn address(&self) -> Address {
+        self.as_ref().address()
+    }
+
+    fn dex(&self) -> DexId {
+        self.as_ref().dex()
+    }
+
+    fn tokens(&self) -> (TokenId, TokenId) {
+        self.as_ref().tokens()
+    }
+
+    fn decimals(&self, token: TokenId) -> u8 {
+        self.as_ref().decimals(token)
+    }
+
+    fn reserves(&self, token_in: TokenId) -> (U256, U256) {
+        self.as_ref().reserves(token_in)
+    }
+
+    fn quote(&self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        self.as_ref().quote(amount_in, token_in)
+    }
+
+    fn apply(&mut self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        self.as_mut().apply(amount_in, token_in)
+    }
+
+    fn spot_price(&self, token_in: TokenId) -> f64 {
+        self.as_ref().spot_price(token_in)
+    }
+
+    fn price_impact(&self, amount_in: U256, token_in: TokenId) -> f64 {
+        self.as_ref().price_impact(amount_in, token_in)
+    }
+
+    fn apply_reserve_haircut(&mut self, haircut_bps: u32, token_in: TokenId) {
+        self.as_mut().apply_reserve_haircut(haircut_bps, token_in)
+    }
+
+    fn swap_fee_amount(&self, amount_in: U256) -> U256 {
+        self.as_ref().swap_fee_amount(amount_in)
+    }
+
+    fn protocol_fee_enabled(&self) -> bool {
+        self.as_ref().protocol_fee_enabled()
+    }
+
+    fn constant_product_fee_bps(&self) -> Option<u32> {
+        self.as_ref().constant_product_fee_bps()
+    }
+
+    fn fidelity(&self) -> SimulationFidelity {
+        self.as_ref().fidelity()
+    }
+
+    fn requote(&mut self, cumulative_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        self.as_mut().requote(cumulative_in, token_in)
+    }
+
+    fn clone_box(&self) -> Box<dyn AmmPool> {
+        self.as_ref().clone_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigContext;
+    use crate::solver::{find_best_routes, SkipReason, SolverConfig, Strategy};
+
+    /// Пул с фиксированным курсом и ограниченным запасом выходного токена
+    #[derive(Debug, Clone)]
+    struct FixedRatePool {
+        name: String,
+        // Выход = вход * numerator / denominator (raw units)
+        rate: (U256, U256),
+        remaining_out: U256,
+        fidelity: SimulationFidelity,
+        // Суммы, котированные у "источника", который дает `source_bps` от локальной модели
+        requotes: std::sync::Arc<std::sync::Mutex<Vec<U256>>>,
+        source_bps: u64,
+    }
+
+    impl FixedRatePool {
+        /// 1 USDC -> 1/2400 WETH, запас `capacity_weth` WETH
+        fn usdc_weth(capacity_weth: u64) -> Self {
+            FixedRatePool {
+                name: "Fixed".to_string(),
+                rate: (U256::from(10u64).pow(U256::from(12u64)), U256::from(2_400u64)),
+                remaining_out: U256::from(capacity_weth) * U256::from(10u64).pow(U256::from(18u64)),
+                fidelity: SimulationFidelity::Exact,
+                requotes: Default::default(),
+                source_bps: 9_900,
+            }
+        }
+
+        fn with_fidelity(self, fidelity: SimulationFidelity) -> Self {
+            FixedRatePool { fidelity, ..self }
+        }
+
+        fn with_source_bps(self, source_bps: u64) -> Self {
+            FixedRatePool { source_bps, ..self }
+        }
+
+        fn source_out(&self, amount_in: U256) -> U256 {
+            amount_in * self.rate.0 / self.rate.1 * U256::from(self.source_bps) / U256::from(10_000u64)
+        }
+    }
+
+    impl AmmPool for FixedRatePool {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn address(&self) -> Address {
+            Address::repeat_byte(0xfe)
+        }
+
+        fn dex(&self) -> DexId {
+            DexId::QUICKSWAP
+        }
+
+        fn tokens(&self) -> (TokenId, TokenId) {
+            (TokenId::USDC, TokenId::WETH)
+        }
+
+        fn decimals(&self, token: TokenId) -> u8 {
+            token.decimals()
+        }
+
+        fn reserves(&self, _token_in: TokenId) -> (U256, U256) {
+            (self.remaining_out * self.rate.1 / self.rate.0, self.remaining_out)
+        }
+
+        fn quote(&self, amount_in: U256, _token_in: TokenId) -> Result<U256, PoolError> {
+            if amount_in.is_zero() {
+                return Err(PoolError::ZeroAmount);
+            }
+            let amount_out = amount_in * self.rate.0 / self.rate.1;
+            if amount_out > self.remaining_out {
+                return Err(PoolError::InsufficientLiquidity);
+            }
+            Ok(amount_out)
+        }
+
+        fn apply(&mut self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+            let amount_out = self.quote(amount_in, token_in)?;
+            self.remaining_out -= amount_out;
+            Ok(amount_out)
+        }
+
+        fn spot_price(&self, token_in: TokenId) -> f64 {
+            if token_in == TokenId::USDC { 1.0 / 2_400.0 } else { 2_400.0 }
+        }
+
+        fn price_impact(&self, _amount_in: U256, _token_in: TokenId) -> f64 {
+            0.0
+        }
+
+        fn apply_reserve_haircut(&mut self, haircut_bps: u32, _token_in: TokenId) {
+            self.remaining_out -= self.remaining_out * U256::from(haircut_bps) / U256::from(10_000u64);
+        }
+
+        fn swap_fee_amount(&self, _amount_in: U256) -> U256 {
+            U256::ZERO
+        }
+
+        fn fidelity(&self) -> SimulationFidelity {
+            self.fidelity
+        }
+
+        fn requote(&mut self, cumulative_in: U256, _token_in: TokenId) -> Result<U256, PoolError> {
+            self.requotes.lock().unwrap().push(cumulative_in);
+            Ok(self.source_out(cumulative_in))
+        }
+
+        fn clone_box(&self) -> Box<dyn AmmPool> {
+            Box::new(self.clone())
+        }
+    }
+
+    fn constant_product_pool() -> PoolState {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        crate::pool::test_pool(0x11, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(800u64) * weth)
+    }
+
+    fn quiet_config(amount_usdc: u64, num_chunks: u64) -> SolverConfig {
+        SolverConfig {
+            total_amount_in: U256::from(amount_usdc) * U256::from(1_000_000u64),
+            num_chunks,
+            verbose: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pool_quote_matches_apply_and_keeps_state() {
+        let mut pool = constant_product_pool();
+        let amount_in = U256::from(5_000_000_000u64);
+        let before = pool.snapshot();
+
+        let quoted = AmmPool::quote(&pool, amount_in, TokenId::USDC).unwrap();
+        assert_eq!(pool.snapshot(), before);
+        assert_eq!(AmmPool::apply(&mut pool, amount_in, TokenId::USDC).unwrap(), quoted);
+        assert_ne!(pool.snapshot(), before);
+
+        assert_eq!(pool.other_token(TokenId::USDC), Some(TokenId::WETH));
+        assert_eq!(pool.constant_product_fee_bps(), Some(crate::math::DEFAULT_FEE_BPS));
+    }
+
+    #[tokio::test]
+    async fn solver_routes_across_mixed_pool_types() {
+        let pools: Vec<Box<dyn AmmPool>> = vec![Box::new(constant_product_pool()), Box::new(FixedRatePool::usdc_weth(2))];
+        let result = find_best_routes(pools, &ConfigContext::default(), &quiet_config(10_000, 10)).await.unwrap();
+
+        // Фиксированный курс лучше, пока не кончится запас: 4 чанка по 1000 USDC
+        let fixed_chunks: Vec<_> = result.chunk_routes.iter().filter(|route| route.best_pool_name == "Fixed").collect();
+        assert_eq!(fixed_chunks.len(), 4);
+        assert!(result.chunk_routes[..4].iter().all(|route| route.best_pool_name == "Fixed"));
+        assert!(result.chunk_routes[4].skipped_pools.iter()
+            .any(|skip| skip.pool_name == "Fixed" && skip.reason == SkipReason::SwapRejected));
+
+        let sum: U256 = result.chunk_routes.iter().map(|route| route.amount_out).sum();
+        assert_eq!(result.total_weth_out, sum);
+    }
+
+    #[tokio::test]
+    async fn analytic_strategy_falls_back_to_greedy_for_non_constant_product() {
+        let pools: Vec<Box<dyn AmmPool>> = vec![Box::new(constant_product_pool()), Box::new(FixedRatePool::usdc_weth(2))];
+        let ctx = ConfigContext::default();
+        let greedy = find_best_routes(pools.clone(), &ctx, &quiet_config(10_000, 10)).await.unwrap();
+        let analytic_config = SolverConfig { strategy: Strategy::MarginalEqualization, ..quiet_config(10_000, 10) };
+        let analytic = find_best_routes(pools, &ctx, &analytic_config).await.unwrap();
+        assert_eq!(analytic.total_weth_out, greedy.total_weth_out);
+    }
+
+    #[tokio::test]
+    async fn boxed_pools_quote_like_concrete_pools() {
+        let ctx = ConfigContext::default();
+        let concrete = find_best_routes(vec![constant_product_pool()], &ctx, &quiet_config(50_000, 20)).await.unwrap();
+        let boxed: Vec<Box<dyn AmmPool>> = vec![Box::new(constant_product_pool())];
+        let boxed = find_best_routes(boxed, &ctx, &quiet_config(50_000, 20)).await.unwrap();
+        assert_eq!(boxed.total_weth_out, concrete.total_weth_out);
+        let outputs = |result: &crate::solver::SolverResult| -> Vec<U256> {
+            result.chunk_routes.iter().map(|route| route.amount_out).collect()
+        };
+        assert_eq!(outputs(&boxed), outputs(&concrete));
+    }
+
+    #[tokio::test]
+    async fn requote_only_pool_is_requoted_per_step_and_totals_use_source() {
+        let pool = FixedRatePool::usdc_weth(10).with_fidelity(SimulationFidelity::RequoteOnly);
+        let requotes = pool.requotes.clone();
+        let source = pool.clone();
+        let solver_config = SolverConfig { requote_step_bps: 2_500, ..quiet_config(10_000, 10) };
+        let result = find_best_routes(vec![pool], &ConfigContext::default(), &solver_config).await.unwrap();
+
+        // Шаг 2500 USDC: перекотировки на 3000, 6000, 9000 и в конце на 10000 USDC
+        let usdc = |amount: u64| U256::from(amount) * U256::from(1_000_000u64);
+        assert_eq!(*requotes.lock().unwrap(), vec![usdc(3_000), usdc(6_000), usdc(9_000), usdc(10_000)]);
+        assert_eq!(result.diagnostics.requotes, 4);
+
+        // Итог совпадает с ответом источника за весь вход, а не с локальной моделью
+        assert_eq!(result.total_weth_out, source.source_out(usdc(10_000)));
+        let sum: U256 = result.chunk_routes.iter().map(|route| route.amount_out).sum();
+        assert_eq!(sum, result.total_weth_out);
+        // Поправка относится к чанку, на котором сделана перекотировка
+        let local_chunk = source.quote(usdc(1_000), TokenId::USDC).unwrap();
+        assert_eq!(result.chunk_routes[0].amount_out, local_chunk);
+        assert!(result.chunk_routes[2].amount_out < local_chunk);
+        assert_eq!(result.confidence, 1.0);
+    }
+
+    #[tokio::test]
+    async fn requote_below_earlier_chunks_keeps_pool_total_equal_to_source() {
+        // Источник дает вдвое меньше модели: на первой перекотировке (3000 USDC)
+        // ответ меньше выхода двух уже записанных чанков
+        let pool = FixedRatePool::usdc_weth(10).with_fidelity(SimulationFidelity::RequoteOnly).with_source_bps(5_000);
+        let source = pool.clone();
+        let solver_config = SolverConfig { requote_step_bps: 2_500, ..quiet_config(10_000, 10) };
+        let result = find_best_routes(vec![pool], &ConfigContext::default(), &solver_config).await.unwrap();
+
+        let usdc = |amount: u64| U256::from(amount) * U256::from(1_000_000u64);
+        let sum: U256 = result.chunk_routes.iter().map(|route| route.amount_out).sum();
+        assert_eq!(sum, source.source_out(usdc(10_000)));
+        assert_eq!(result.total_weth_out, sum);
+        // Недостача снята с ранних чанков, а не потеряна
+        let local_chunk = source.quote(usdc(1_000), TokenId::USDC).unwrap();
+        assert!(result.chunk_routes[1].amount_out < local_chunk);
+        assert_eq!(result.chunk_routes[2].amount_out, U256::ZERO);
+    }
+
+    #[tokio::test]
+    async fn approximate_pool_pads_min_out_and_lowers_confidence() {
+        let pool = FixedRatePool::usdc_weth(10).with_fidelity(SimulationFidelity::Approximate { max_error_bps: 100 });
+        let solver_config = SolverConfig { slippage_bps: 50, ..quiet_config(10_000, 10) };
+        let result = find_best_routes(vec![pool.clone()], &ConfigContext::default(), &solver_config).await.unwrap();
+
+        for route in &result.chunk_routes {
+            assert_eq!(Some(route.min_amount_out), crate::math::apply_slippage(route.amount_out, 150));
+        }
+        assert!((result.confidence - 0.99).abs() < 1e-9);
+        assert!(pool.requotes.lock().unwrap().is_empty());
+
+        // Точный пул не снижает уверенность и не расширяет min_amount_out
+        let exact = find_best_routes(vec![constant_product_pool()], &ConfigContext::default(), &solver_config).await.unwrap();
+        assert_eq!(exact.confidence, 1.0);
+        assert_eq!(exact.diagnostics.requotes, 0);
+    }
+}
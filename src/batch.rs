@@ -0,0 +1,171 @@
+// src/batch.rs
+//! Пакетный режим: много независимых котировок над одним набором пулов
+//!
+//! Discovery выполняется один раз, каждая котировка решается на своей копии
+//! пулов. Количество одновременно решаемых запросов ограничено семафором,
+//! результаты отдаются по мере готовности, а не в порядке запросов.
+use crate::log;
+use crate::config::{format_units, usdc_from_decimal, ConfigContext, WETH_DECIMALS};
+use crate::pool::PoolState;
+use crate::solver::{find_best_routes, SolverConfig, Strategy};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Запрос котировки для пары из профиля конфигурации
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct QuoteRequest {
+    pub id: String,
+    /// Сумма обмена в USDC (decimal)
+    pub amount_usdc: f64,
+    /// Количество чанков; по умолчанию из профиля
+    #[serde(default)]
+    pub num_chunks: Option<u64>,
+    #[serde(default)]
+    pub strategy: Strategy,
+}
+
+/// Результат одной котировки (строка JSONL)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuoteResponse {
+    pub id: String,
+    /// Общий выход WETH в raw units
+    pub total_weth_out: Option<String>,
+    /// Общий выход WETH в человекочитаемом виде
+    pub total_weth_out_decimal: Option<String>,
+    pub execution_price: Option<f64>,
+    /// Время решения запроса, включая ожидание семафора
+    pub elapsed_ms: f64,
+    pub error: Option<String>,
+}
+
+/// Решает все запросы над одним набором пулов, не более `parallelism` одновременно
+///
+/// `on_result` вызывается для каждого результата по мере готовности.
+/// Ошибки отдельных запросов попадают в `QuoteResponse::error` и не прерывают пакет.
+pub async fn solve_batch(
+    pools: &[PoolState],
+    ctx: &ConfigContext,
+    requests: Vec<QuoteRequest>,
+    parallelism: usize,
+    mut on_result: impl FnMut(QuoteResponse),
+) {
+    let pools: Arc<[PoolState]> = pools.into();
+    let ctx = Arc::new(ctx.clone());
+    let semaphore = Arc::new(Semaphore::new(parallelism.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for request in requests {
+        let pools = Arc::clone(&pools);
+        let ctx = Arc::clone(&ctx);
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let started = Instant::now();
+            let _permit = semaphore.acquire_owned().await.expect("семафор не закрывается");
+            let solver_config = SolverConfig {
+                total_amount_in: usdc_from_decimal(request.amount_usdc),
+                num_chunks: request.num_chunks.unwrap_or(ctx.num_chunks),
+                verbose: false,
+                strategy: request.strategy,
+                ..SolverConfig::from_context(&ctx)
+            };
+            let outcome = find_best_routes(pools.to_vec(), &ctx, &solver_config).await;
+            let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+            match outcome {
+                Ok(result) => QuoteResponse {
+                    id: request.id,
+                    total_weth_out: Some(result.total_weth_out.to_string()),
+                    total_weth_out_decimal: Some(format_units(result.total_weth_out, WETH_DECIMALS)),
+                    execution_price: result.execution_price,
+                    elapsed_ms,
+                    error: None,
+                },
+                Err(e) => QuoteResponse {
+                    id: request.id,
+                    total_weth_out: None,
+                    total_weth_out_decimal: None,
+                    execution_price: None,
+                    elapsed_ms,
+                    error: Some(e.to_string()),
+                },
+            }
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(response) => on_result(response),
+            Err(e) => log!("Ошибка задачи пакетного режима: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenId;
+    use alloy::primitives::U256;
+
+    fn test_pools() -> Vec<PoolState> {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        vec![
+            crate::pool::test_pool(0x11, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(800u64) * weth),
+            crate::pool::test_pool(0x12, TokenId::USDC, TokenId::WETH, U256::from(1_000_000_000_000u64), U256::from(405u64) * weth),
+        ]
+    }
+
+    #[tokio::test]
+    async fn all_results_arrive_and_match_single_runs() {
+        let pools = test_pools();
+        let ctx = ConfigContext::default();
+        let requests: Vec<QuoteRequest> = (0..20)
+            .map(|i| QuoteRequest {
+                id: format!("q{}", i),
+                amount_usdc: 1_000.0 * (i + 1) as f64,
+                num_chunks: Some(10),
+                strategy: Strategy::Greedy,
+            })
+            .collect();
+
+        let mut responses = Vec::new();
+        solve_batch(&pools, &ctx, requests.clone(), 4, |response| responses.push(response)).await;
+        assert_eq!(responses.len(), 20);
+
+        for request in &requests {
+            let response = responses.iter().find(|response| response.id == request.id).unwrap();
+            assert!(response.error.is_none());
+            let solver_config = SolverConfig {
+                total_amount_in: usdc_from_decimal(request.amount_usdc),
+                num_chunks: 10,
+                verbose: false,
+                ..Default::default()
+            };
+            let single = find_best_routes(pools.clone(), &ctx, &solver_config).await.unwrap();
+            assert_eq!(response.total_weth_out, Some(single.total_weth_out.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_request_does_not_stop_batch() {
+        let requests = vec![
+            QuoteRequest { id: "zero".to_string(), amount_usdc: 0.0, num_chunks: None, strategy: Strategy::Greedy },
+            QuoteRequest { id: "ok".to_string(), amount_usdc: 5_000.0, num_chunks: None, strategy: Strategy::Greedy },
+        ];
+        let mut responses = Vec::new();
+        solve_batch(&test_pools(), &ConfigContext::default(), requests, 1, |response| responses.push(response)).await;
+
+        assert_eq!(responses.len(), 2);
+        let zero = responses.iter().find(|response| response.id == "zero").unwrap();
+        assert!(zero.error.is_some() && zero.total_weth_out.is_none());
+        assert!(responses.iter().any(|response| response.id == "ok" && response.error.is_none()));
+    }
+
+    #[test]
+    fn request_defaults() {
+        let request: QuoteRequest = serde_json::from_str(r#"{"id": "a", "amount_usdc": 2500.5}"#).unwrap();
+        assert_eq!(request.num_chunks, None);
+        assert_eq!(request.strategy, Strategy::Greedy);
+    }
+}
@@ -0,0 +1,181 @@
+// src/bench.rs
+//! Бенчмарк маршрутизации на встроенном наборе рынков (swap_aggregator bench-routing)
+//!
+//! Набор - обезличенные снимки рынка USDC/WETH разной глубины и с разным
+//! числом пулов в формате `market_snapshot`. Файлы снимков вшиты в бинарник
+//! (`bench/*.sams`) и разбираются при запуске, так что результаты можно
+//! воспроизвести без сети и сравнить с другими агрегаторами на тех же данных.
+//!
+//! Каждый снимок решается на суммах `BENCH_AMOUNTS_USDC` всеми стратегиями
+//! солвера. Качество стратегии - отклонение ее выхода в bps от лучшего
+//! известного выхода на случае (лучшего среди стратегий), посчитанное тем же
+//! `regress::delta_bps`, что и регрессионный прогон.
+use crate::config::{format_units, ConfigContext, USDC_DECIMALS, WETH_DECIMALS};
+use crate::market_snapshot::MarketSnapshot;
+use crate::pool::PoolState;
+use crate::regress::{decimal, delta_bps};
+use crate::solver::{find_best_routes, SolverConfig, Strategy};
+use alloy::primitives::U256;
+use clap::ValueEnum;
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::time::Instant;
+
+/// Встроенный набор: имя снимка и байты файла снимка
+const DATASET: [(&str, &[u8]); 4] = [
+    ("two-deep", include_bytes!("../bench/two_deep.sams")),
+    ("deep-and-shallow", include_bytes!("../bench/deep_and_shallow.sams")),
+    ("weighted-mix", include_bytes!("../bench/weighted_mix.sams")),
+    ("fragmented", include_bytes!("../bench/fragmented.sams")),
+];
+
+/// Суммы обмена для каждого снимка набора, в USDC
+pub const BENCH_AMOUNTS_USDC: [u64; 3] = [1_000, 100_000, 2_000_000];
+
+/// Результат одной стратегии на одном случае
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchRow {
+    pub case: String,
+    pub pools: usize,
+    #[serde(with = "decimal")]
+    pub amount_in: U256,
+    pub strategy: Strategy,
+    #[serde(with = "decimal")]
+    pub total_out: U256,
+    pub delta_bps: f64,  // Отклонение от лучшего известного выхода на случае (0 - лучший, отрицательное - хуже)
+    pub runtime_us: u64, // Время решения
+}
+
+/// Разбирает встроенные снимки набора
+pub fn dataset() -> Result<Vec<(&'static str, MarketSnapshot)>> {
+    DATASET
+        .iter()
+        .map(|(name, bytes)| {
+            let snapshot = MarketSnapshot::from_bytes(bytes).wrap_err_with(|| format!("встроенный снимок {} поврежден", name))?;
+            Ok((*name, snapshot))
+        })
+        .collect()
+}
+
+/// Решает случай всеми стратегиями и сравнивает их с лучшим выходом
+pub async fn bench_case(case: &str, pools: &[PoolState], amount_in: U256, num_chunks: u64) -> Result<Vec<BenchRow>> {
+    let ctx = ConfigContext::default();
+    let mut rows = Vec::new();
+    for strategy in Strategy::value_variants() {
+        let solver_config = SolverConfig {
+            total_amount_in: amount_in,
+            num_chunks,
+            verbose: false,
+            strategy: *strategy,
+            ..SolverConfig::default()
+        };
+        let started = Instant::now();
+        let result = find_best_routes(pools.to_vec(), &ctx, &solver_config).await?;
+        rows.push(BenchRow {
+            case: case.to_string(),
+            pools: pools.len(),
+            amount_in,
+            strategy: *strategy,
+            total_out: result.total_weth_out,
+            delta_bps: 0.0,
+            runtime_us: started.elapsed().as_micros() as u64,
+        });
+    }
+
+    let best_out = rows.iter().map(|row| row.total_out).max().unwrap_or_default();
+    for row in &mut rows {
+        row.delta_bps = delta_bps(best_out, row.total_out);
+    }
+    Ok(rows)
+}
+
+/// Прогоняет весь набор: каждый снимок на каждой сумме всеми стратегиями
+pub async fn run(num_chunks: u64) -> Result<Vec<BenchRow>> {
+    let unit = U256::from(10u64).pow(U256::from(USDC_DECIMALS));
+    let mut rows = Vec::new();
+    for (name, snapshot) in dataset()? {
+        let pools = snapshot.to_pools();
+        for amount in BENCH_AMOUNTS_USDC {
+            let case = format!("{}/{}", name, amount);
+            rows.extend(bench_case(&case, &pools, U256::from(amount) * unit, num_chunks).await?);
+        }
+    }
+    Ok(rows)
+}
+
+/// Таблица результатов бенчмарка
+pub fn format_table(rows: &[BenchRow]) -> String {
+    let mut out = format!("  {:<26} | {:>5} | {:<22} | {:>26} | {:>10} | {:>10}\n",
+        "Случай", "Пулов", "Стратегия", "Выход WETH", "Δ bps", "Время, мкс");
+    for row in rows {
+        let strategy = row.strategy.to_possible_value().map_or_else(String::new, |value| value.get_name().to_string());
+        let _ = writeln!(out, "  {:<26} | {:>5} | {:<22} | {:>26} | {:>10.4} | {:>10}",
+            row.case,
+            row.pools,
+            strategy,
+            format_units(row.total_out, WETH_DECIMALS),
+            row.delta_bps,
+            row.runtime_us);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DexId, TokenId};
+    use alloy::primitives::Address;
+
+    #[test]
+    fn embedded_dataset_covers_various_depths_and_counts() {
+        let dataset = dataset().unwrap();
+        assert_eq!(dataset.len(), DATASET.len());
+        let counts: Vec<usize> = dataset.iter().map(|(_, snapshot)| snapshot.pools.len()).collect();
+        assert!(counts.iter().min() < counts.iter().max(), "{:?}", counts);
+        for (_, snapshot) in &dataset {
+            assert!(snapshot.to_pools().iter().all(|pool| !pool.reserve_token0.is_zero() && !pool.reserve_token1.is_zero()));
+        }
+    }
+
+    #[tokio::test]
+    async fn single_pool_case_scores_every_strategy_as_best() {
+        let mut pool = PoolState::new(Address::with_last_byte(1), TokenId::USDC, TokenId::WETH, DexId::QUICKSWAP, "A".to_string());
+        (pool.reserve_token0, pool.reserve_token1) = if pool.token0 == TokenId::USDC {
+            (U256::from(5_000_000_000_000u64), U256::from(2_000u64) * U256::from(10u64).pow(U256::from(18)))
+        } else {
+            (U256::from(2_000u64) * U256::from(10u64).pow(U256::from(18)), U256::from(5_000_000_000_000u64))
+        };
+        pool.invalidate_quote_cache();
+        let amount_in = U256::from(10_000_000_000u64);
+
+        let rows = bench_case("single", std::slice::from_ref(&pool), amount_in, 1).await.unwrap();
+        assert_eq!(rows.len(), Strategy::value_variants().len());
+        // Один пул и один чанк: любая стратегия дает выход одного свапа на всю сумму
+        let expected = pool.quote_by_token(TokenId::USDC, amount_in).unwrap();
+        for row in &rows {
+            assert_eq!(row.total_out, expected, "{:?}", row.strategy);
+            assert_eq!(row.delta_bps, 0.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn benchmark_completes_with_consistent_quality_metrics() {
+        let rows = run(20).await.unwrap();
+        assert_eq!(rows.len(), DATASET.len() * BENCH_AMOUNTS_USDC.len() * Strategy::value_variants().len());
+
+        for case in rows.chunks(Strategy::value_variants().len()) {
+            let best = case.iter().map(|row| row.total_out).max().unwrap();
+            assert!(case.iter().any(|row| row.delta_bps == 0.0));
+            for row in case {
+                assert!(row.delta_bps <= 0.0, "{}: {:?}", row.case, row.strategy);
+                assert_eq!(row.delta_bps, delta_bps(best, row.total_out));
+            }
+        }
+
+        let json = serde_json::to_value(&rows[0]).unwrap();
+        assert_eq!(json["strategy"], "greedy");
+        assert!(json["amount_in"].is_string());
+        assert!(format_table(&rows).lines().count() == rows.len() + 1);
+    }
+}
@@ -0,0 +1,315 @@
+// src/breaker.rs
+//! Автоматические выключатели (circuit breaker) для пулов и DEX
+//!
+//! В режиме `--watch` резервы перечитываются многократно. Пул, чтение которого
+//! раз за разом падает (контракт уничтожен, узел отклоняет вызов), не нужно
+//! опрашивать на каждом круге: после `failure_threshold` сбоев подряд
+//! выключатель размыкается на паузу, которая удваивается при каждом повторном
+//! размыкании до `max_backoff`. Пока выключатель не замкнут, пул не
+//! перечитывается и не участвует в выборе лучшей цены. По истечении паузы
+//! выключатель переходит в half-open и пропускает одну пробу: успех замыкает
+//! его, сбой снова размыкает с удвоенной паузой.
+//!
+//! Выключатель DEX считает круги, на которых не удалось прочитать ни один
+//! пул этого DEX (например, у форка сломан контракт), и выключает все его пулы сразу.
+use crate::config::DexId;
+use alloy::primitives::Address;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Сбоев подряд до размыкания
+pub const FAILURE_THRESHOLD: u32 = 3;
+/// Первая пауза после размыкания
+pub const BASE_BACKOFF: Duration = Duration::from_secs(30);
+/// Предел удваивающейся паузы
+pub const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// Параметры выключателей
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    pub failure_threshold: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig { failure_threshold: FAILURE_THRESHOLD, base_backoff: BASE_BACKOFF, max_backoff: MAX_BACKOFF }
+    }
+}
+
+impl BreakerConfig {
+    /// Пауза для `trips`-го размыкания подряд (с нуля): `base * 2^trips`, не больше `max_backoff`
+    pub fn backoff(&self, trips: u32) -> Duration {
+        self.base_backoff.saturating_mul(1u32 << trips.min(16)).min(self.max_backoff)
+    }
+}
+
+/// Состояние выключателя
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Запросы проходят
+    Closed,
+    /// Запросы не проходят до `until`
+    Open { until: Instant },
+    /// Пауза истекла: следующий запрос - проба
+    HalfOpen,
+}
+
+/// Выключатель одного пула или DEX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    trips: u32, // Размыканий без успеха между ними: показатель паузы
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker { state: BreakerState::Closed, consecutive_failures: 0, trips: 0 }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state == BreakerState::Closed
+    }
+
+    /// Пропускает ли выключатель запрос в момент `now`
+    ///
+    /// Разомкнутый выключатель с истекшей паузой переходит в half-open.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open { until } if now >= until => {
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } => false,
+        }
+    }
+
+    /// Учитывает успешный запрос; `true`, если выключатель был не замкнут
+    pub fn record_success(&mut self) -> bool {
+        let was_closed = self.is_closed();
+        *self = CircuitBreaker::default();
+        !was_closed
+    }
+
+    /// Учитывает сбой; возвращает паузу, если выключатель разомкнулся
+    pub fn record_failure(&mut self, now: Instant, config: &BreakerConfig) -> Option<Duration> {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let probe_failed = self.state == BreakerState::HalfOpen;
+        if !probe_failed && self.consecutive_failures < config.failure_threshold.max(1) {
+            return None;
+        }
+        let backoff = config.backoff(self.trips);
+        self.trips = self.trips.saturating_add(1);
+        self.state = BreakerState::Open { until: now + backoff };
+        Some(backoff)
+    }
+}
+
+/// Объект выключателя
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BreakerTarget {
+    Pool(Address),
+    Dex(DexId),
+}
+
+impl fmt::Display for BreakerTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerTarget::Pool(address) => write!(f, "пул {:?}", address),
+            BreakerTarget::Dex(dex) => write!(f, "DEX {}", dex),
+        }
+    }
+}
+
+/// Изменение состояния выключателя за круг обновления
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerEvent {
+    Opened { target: BreakerTarget, backoff: Duration },
+    Closed { target: BreakerTarget },
+}
+
+impl fmt::Display for BreakerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerEvent::Opened { target, backoff } => write!(f, "{} выключен на {} с после повторных сбоев", target, backoff.as_secs()),
+            BreakerEvent::Closed { target } => write!(f, "{} снова доступен", target),
+        }
+    }
+}
+
+/// Выключатели всех пулов и DEX
+#[derive(Debug, Clone, Default)]
+pub struct Breakers {
+    config: BreakerConfig,
+    pools: HashMap<Address, CircuitBreaker>,
+    dexes: HashMap<DexId, CircuitBreaker>,
+}
+
+impl Breakers {
+    pub fn new(config: BreakerConfig) -> Self {
+        Breakers { config, pools: HashMap::new(), dexes: HashMap::new() }
+    }
+
+    /// Можно ли обращаться к пулу: пропускают и выключатель пула, и выключатель DEX
+    ///
+    /// Пул проверяется первым: пока он разомкнут, выключатель DEX не
+    /// переходит в half-open и его проба достается другому пулу.
+    pub fn allow(&mut self, pool: Address, dex: DexId, now: Instant) -> bool {
+        self.pools.entry(pool).or_default().allow(now) && self.dexes.entry(dex).or_default().allow(now)
+    }
+
+    /// Пул исключен из маршрутизации: выключатель пула или его DEX не замкнут
+    pub fn excluded(&self, pool: Address, dex: DexId) -> bool {
+        let open = |breaker: Option<&CircuitBreaker>| breaker.is_some_and(|breaker| !breaker.is_closed());
+        open(self.pools.get(&pool)) || open(self.dexes.get(&dex))
+    }
+
+    /// Учитывает результаты круга обновления: `(пул, DEX, успех)` для каждого опрошенного пула
+    ///
+    /// DEX засчитывается успех, если прочитан хотя бы один его пул, и сбой,
+    /// если не прочитан ни один.
+    pub fn record_round(&mut self, outcomes: &[(Address, DexId, bool)], now: Instant) -> Vec<BreakerEvent> {
+        let mut events = Vec::new();
+        let mut dex_success: Vec<(DexId, bool)> = Vec::new();
+        for &(pool, dex, success) in outcomes {
+            let target = BreakerTarget::Pool(pool);
+            record(self.pools.entry(pool).or_default(), success, target, now, &self.config, &mut events);
+            match dex_success.iter_mut().find(|(id, _)| *id == dex) {
+                Some((_, any)) => *any |= success,
+                None => dex_success.push((dex, success)),
+            }
+        }
+        for (dex, success) in dex_success {
+            record(self.dexes.entry(dex).or_default(), success, BreakerTarget::Dex(dex), now, &self.config, &mut events);
+        }
+        events
+    }
+
+    /// Незамкнутые выключатели для отчета о состоянии, по объекту
+    pub fn tripped(&self) -> Vec<(BreakerTarget, BreakerState)> {
+        let pools = self.pools.iter().map(|(address, breaker)| (BreakerTarget::Pool(*address), breaker.state()));
+        let dexes = self.dexes.iter().map(|(dex, breaker)| (BreakerTarget::Dex(*dex), breaker.state()));
+        let mut tripped: Vec<_> = pools.chain(dexes).filter(|(_, state)| *state != BreakerState::Closed).collect();
+        tripped.sort_by_key(|(target, _)| *target);
+        tripped
+    }
+}
+
+fn record(
+    breaker: &mut CircuitBreaker,
+    success: bool,
+    target: BreakerTarget,
+    now: Instant,
+    config: &BreakerConfig,
+    events: &mut Vec<BreakerEvent>,
+) {
+    if success {
+        if breaker.record_success() {
+            events.push(BreakerEvent::Closed { target });
+        }
+    } else if let Some(backoff) = breaker.record_failure(now, config) {
+        events.push(BreakerEvent::Opened { target, backoff });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BreakerConfig {
+        BreakerConfig { failure_threshold: 3, base_backoff: Duration::from_secs(10), max_backoff: Duration::from_secs(35) }
+    }
+
+    #[test]
+    fn opens_after_threshold_and_closes_after_successful_probe() {
+        let config = config();
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+
+        assert_eq!(breaker.record_failure(start, &config), None);
+        assert_eq!(breaker.record_failure(start, &config), None);
+        assert!(breaker.allow(start));
+        assert_eq!(breaker.record_failure(start, &config), Some(Duration::from_secs(10)));
+        assert_eq!(breaker.state(), BreakerState::Open { until: start + Duration::from_secs(10) });
+        assert!(!breaker.allow(start + Duration::from_secs(9)));
+
+        // Пауза истекла: одна проба
+        assert!(breaker.allow(start + Duration::from_secs(10)));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(breaker.record_success());
+        assert!(breaker.is_closed());
+        // Успех сбрасывает счетчик: снова нужно три сбоя подряд
+        assert_eq!(breaker.record_failure(start, &config), None);
+        assert!(!breaker.record_success());
+    }
+
+    #[test]
+    fn failed_probe_reopens_with_doubled_capped_backoff() {
+        let config = config();
+        let mut now = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..3 {
+            breaker.record_failure(now, &config);
+        }
+
+        let mut backoffs = Vec::new();
+        for _ in 0..3 {
+            let BreakerState::Open { until } = breaker.state() else { panic!("{:?}", breaker.state()) };
+            now = until;
+            assert!(breaker.allow(now));
+            // В half-open достаточно одного сбоя
+            backoffs.push(breaker.record_failure(now, &config).unwrap());
+        }
+        assert_eq!(backoffs, vec![Duration::from_secs(20), Duration::from_secs(35), Duration::from_secs(35)]);
+        assert_eq!(config.backoff(u32::MAX), Duration::from_secs(35));
+    }
+
+    #[test]
+    fn dex_breaker_trips_only_when_every_pool_of_the_dex_fails() {
+        let mut breakers = Breakers::new(BreakerConfig { failure_threshold: 1, ..config() });
+        let now = Instant::now();
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+
+        let events = breakers.record_round(&[(a, DexId::QUICKSWAP, false), (b, DexId::QUICKSWAP, true)], now);
+        assert_eq!(events, vec![BreakerEvent::Opened { target: BreakerTarget::Pool(a), backoff: Duration::from_secs(10) }]);
+        assert!(breakers.excluded(a, DexId::QUICKSWAP));
+        assert!(!breakers.excluded(b, DexId::QUICKSWAP));
+        assert!(!breakers.allow(a, DexId::QUICKSWAP, now));
+
+        let events = breakers.record_round(&[(b, DexId::QUICKSWAP, false)], now);
+        assert!(events.contains(&BreakerEvent::Opened { target: BreakerTarget::Dex(DexId::QUICKSWAP), backoff: Duration::from_secs(10) }));
+        // Выключенный DEX исключает все свои пулы, включая новые
+        assert!(breakers.excluded(Address::repeat_byte(3), DexId::QUICKSWAP));
+        assert!(!breakers.allow(Address::repeat_byte(3), DexId::QUICKSWAP, now));
+        assert!(breakers.allow(Address::repeat_byte(3), DexId::SUSHISWAP, now));
+        assert_eq!(breakers.tripped().len(), 3);
+    }
+
+    #[test]
+    fn open_pool_does_not_spend_the_dex_probe() {
+        let config = BreakerConfig { failure_threshold: 1, ..config() };
+        let mut breakers = Breakers::new(config);
+        let start = Instant::now();
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+
+        breakers.record_round(&[(a, DexId::QUICKSWAP, false)], start);
+        // Пауза пула длиннее паузы DEX
+        breakers.pools.get_mut(&a).unwrap().record_failure(start + Duration::from_secs(5), &config);
+
+        let now = start + Duration::from_secs(10);
+        assert!(!breakers.allow(a, DexId::QUICKSWAP, now));
+        assert!(matches!(breakers.dexes[&DexId::QUICKSWAP].state(), BreakerState::Open { .. }));
+        assert!(breakers.allow(b, DexId::QUICKSWAP, now));
+        assert_eq!(breakers.dexes[&DexId::QUICKSWAP].state(), BreakerState::HalfOpen);
+    }
+}
@@ -0,0 +1,265 @@
+// src/chain.rs
+//! Чтение состояния блокчейна, нужное для discovery и резервов пулов
+//!
+//! `Pool`, discovery (`get_all_pool_addresses`) и обновление резервов
+//! (`refresh_all_reserves`) обращаются к сети только через `ChainClient`.
+//! В работе это `RpcProvider` (каждый вызов с повторами `with_retry`),
+//! в тестах - `MockChainClient` с заданными ответами и сбоями, поэтому логика
+//! discovery проверяется без узла. Трейт возвращает упакованные futures,
+//! чтобы пулы могли хранить клиента как `Arc<dyn ChainClient>`.
+use crate::config::{BALANCER_V2_VAULT, MULTICALL3_ADDRESS};
+use crate::log;
+use crate::provider::{
+    decode_symbol, provider_config, with_retry, IBalancerVault, IERC20Metadata, IMulticall3, IUniswapV2Factory, IUniswapV2Pair,
+    IWeightedPool, ProviderConfig, RpcProvider,
+};
+use alloy::primitives::{Address, B256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::{TransactionInput, TransactionRequest};
+use alloy::sol_types::SolCall;
+use eyre::Result;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Future ответа `ChainClient`
+pub type ChainFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Резервы пары Uniswap V2: (reserve0, reserve1, blockTimestampLast)
+pub type PairReserves = (U256, U256, u32);
+
+/// Параметры взвешенного пула Balancer, кроме балансов
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightedPoolParams {
+    pub pool_id: B256,
+    pub weights: Vec<U256>, // Нормализованные веса (1e18 = 100%) в порядке токенов Vault
+    pub swap_fee: U256,     // Комиссия (1e18 = 100%)
+}
+
+/// Вызовы контрактов, из которых собираются пулы
+///
+/// Методы возвращают ответ контракта как есть: запасные значения (18
+/// decimals, символ из адреса, выключенный protocol fee) и кэши добавляют
+/// функции `provider.rs` поверх трейта. Реализация для `RpcProvider`
+/// повторяет временные ошибки RPC в каждом методе (`with_retry`), поэтому
+/// вызывающий код сам запросы не повторяет.
+pub trait ChainClient: fmt::Debug + Send + Sync {
+    /// `getReserves()` пары Uniswap V2
+    fn get_reserves(&self, pair: Address) -> ChainFuture<'_, PairReserves>;
+
+    /// Резервы нескольких пар одним запросом; `None` - вызов этой пары не удался
+    fn get_reserves_batch<'a>(&'a self, pairs: &'a [Address]) -> ChainFuture<'a, Vec<Option<PairReserves>>>;
+
+    /// `get_reserves_batch` и номер блока, на котором прочитаны резервы (из того же запроса)
+    fn get_reserves_batch_at_block<'a>(&'a self, pairs: &'a [Address]) -> ChainFuture<'a, (u64, Vec<Option<PairReserves>>)>;
+
+    /// Номер последнего блока
+    fn block_number(&self) -> ChainFuture<'_, u64>;
+
+    /// `getPair(token_a, token_b)` Factory; нулевой адрес - пары нет
+    fn get_pair(&self, factory: Address, token_a: Address, token_b: Address) -> ChainFuture<'_, Address>;
+
+    /// `token0()` и `token1()` пары
+    fn get_pair_tokens(&self, pair: Address) -> ChainFuture<'_, (Address, Address)>;
+
+    /// `factory()` пары
+    fn get_pair_factory(&self, pair: Address) -> ChainFuture<'_, Address>;
+
+    /// `feeTo()` Factory; нулевой адрес - protocol fee выключен
+    fn get_fee_to(&self, factory: Address) -> ChainFuture<'_, Address>;
+
+    /// `decimals()` токена ERC20
+    fn get_decimals(&self, token: Address) -> ChainFuture<'_, u8>;
+
+    /// `symbol()` токена (`string` или `bytes32`); `None` - ответ не текст
+    fn get_symbol(&self, token: Address) -> ChainFuture<'_, Option<String>>;
+
+    /// По адресу есть код контракта
+    fn is_contract(&self, address: Address) -> ChainFuture<'_, bool>;
+
+    /// poolId, веса и комиссия взвешенного пула
+    fn get_weighted_pool(&self, pool: Address) -> ChainFuture<'_, WeightedPoolParams>;
+
+    /// Токены и балансы пула в Balancer Vault
+    fn get_vault_pool_tokens(&self, pool_id: B256) -> ChainFuture<'_, (Vec<Address>, Vec<U256>)>;
+}
+
+impl ChainClient for RpcProvider {
+    fn get_reserves(&self, pair: Address) -> ChainFuture<'_, PairReserves> {
+        Box::pin(get_reserves_with(self, provider_config(), pair))
+    }
+
+    fn get_reserves_batch<'a>(&'a self, pairs: &'a [Address]) -> ChainFuture<'a, Vec<Option<PairReserves>>> {
+        Box::pin(async move { Ok(multicall_reserves(self, pairs, false).await?.1) })
+    }
+
+    fn get_reserves_batch_at_block<'a>(&'a self, pairs: &'a [Address]) -> ChainFuture<'a, (u64, Vec<Option<PairReserves>>)> {
+        Box::pin(async move {
+            let (block, reserves) = multicall_reserves(self, pairs, true).await?;
+            Ok((block.unwrap_or_default(), reserves))
+        })
+    }
+
+    fn block_number(&self) -> ChainFuture<'_, u64> {
+        Box::pin(async move {
+            let block = with_retry(&provider_config(), "eth_blockNumber", || async move {
+                self.get_block_number().await.map_err(alloy::contract::Error::from)
+            })
+            .await?;
+            Ok(block)
+        })
+    }
+
+    fn get_pair(&self, factory: Address, token_a: Address, token_b: Address) -> ChainFuture<'_, Address> {
+        Box::pin(async move {
+            let contract = &IUniswapV2Factory::IUniswapV2FactoryInstance::new(factory, self);
+            let what = format!("getPair {:?}", factory);
+            Ok(with_retry(&provider_config(), &what, || async move { contract.getPair(token_a, token_b).call().await }).await?.pair)
+        })
+    }
+
+    fn get_pair_tokens(&self, pair: Address) -> ChainFuture<'_, (Address, Address)> {
+        Box::pin(async move {
+            let contract = &IUniswapV2Pair::IUniswapV2PairInstance::new(pair, self);
+            let what = format!("token0/token1 {:?}", pair);
+            let token0 = with_retry(&provider_config(), &what, || async move { contract.token0().call().await }).await?._0;
+            let token1 = with_retry(&provider_config(), &what, || async move { contract.token1().call().await }).await?._0;
+            Ok((token0, token1))
+        })
+    }
+
+    fn get_pair_factory(&self, pair: Address) -> ChainFuture<'_, Address> {
+        Box::pin(async move {
+            let contract = &IUniswapV2Pair::IUniswapV2PairInstance::new(pair, self);
+            let what = format!("factory {:?}", pair);
+            Ok(with_retry(&provider_config(), &what, || async move { contract.factory().call().await }).await?._0)
+        })
+    }
+
+    fn get_fee_to(&self, factory: Address) -> ChainFuture<'_, Address> {
+        Box::pin(async move {
+            let contract = &IUniswapV2Factory::IUniswapV2FactoryInstance::new(factory, self);
+            let what = format!("feeTo {:?}", factory);
+            Ok(with_retry(&provider_config(), &what, || async move { contract.feeTo().call().await }).await?._0)
+        })
+    }
+
+    fn get_decimals(&self, token: Address) -> ChainFuture<'_, u8> {
+        Box::pin(async move {
+            let contract = &IERC20Metadata::IERC20MetadataInstance::new(token, self);
+            let what = format!("decimals {:?}", token);
+            Ok(with_retry(&provider_config(), &what, || async move { contract.decimals().call().await }).await?._0)
+        })
+    }
+
+    fn get_symbol(&self, token: Address) -> ChainFuture<'_, Option<String>> {
+        Box::pin(async move {
+            // Ответ декодируется вручную: ABI-декодер `string` не принимает bytes32
+            let request = &TransactionRequest::default()
+                .to(token)
+                .input(TransactionInput::new(IERC20Metadata::symbolCall {}.abi_encode().into()));
+            let what = format!("symbol {:?}", token);
+            let data = with_retry(&provider_config(), &what, || async move { Ok(self.call(request).await?) }).await?;
+            Ok(decode_symbol(&data))
+        })
+    }
+
+    fn is_contract(&self, address: Address) -> ChainFuture<'_, bool> {
+        Box::pin(async move {
+            let what = format!("getCode {:?}", address);
+            let code = with_retry(&provider_config(), &what, || async move { Ok(self.get_code_at(address).await?) }).await?;
+            Ok(!code.is_empty())
+        })
+    }
+
+    fn get_weighted_pool(&self, pool: Address) -> ChainFuture<'_, WeightedPoolParams> {
+        Box::pin(async move {
+            let contract = &IWeightedPool::IWeightedPoolInstance::new(pool, self);
+            let config = provider_config();
+            let what = format!("Balancer pool {:?}", pool);
+            Ok(WeightedPoolParams {
+                pool_id: with_retry(&config, &what, || async move { contract.getPoolId().call().await }).await?._0,
+                weights: with_retry(&config, &what, || async move { contract.getNormalizedWeights().call().await }).await?._0,
+                swap_fee: with_retry(&config, &what, || async move { contract.getSwapFeePercentage().call().await }).await?._0,
+            })
+        })
+    }
+
+    fn get_vault_pool_tokens(&self, pool_id: B256) -> ChainFuture<'_, (Vec<Address>, Vec<U256>)> {
+        Box::pin(async move {
+            let vault = &IBalancerVault::IBalancerVaultInstance::new(BALANCER_V2_VAULT, self);
+            let what = format!("getPoolTokens {}", pool_id);
+            let pool_tokens = with_retry(&provider_config(), &what, || async move { vault.getPoolTokens(pool_id).call().await }).await?;
+            Ok((pool_tokens.tokens, pool_tokens.balances))
+        })
+    }
+}
+
+/// `getReserves` пары с политикой повторов `config`
+pub(crate) async fn get_reserves_with(provider: &RpcProvider, config: ProviderConfig, pair: Address) -> Result<PairReserves> {
+    let contract = &IUniswapV2Pair::IUniswapV2PairInstance::new(pair, provider);
+    // Временные ошибки RPC повторяются
+    log!("Отправляем запрос к контракту по адресу: {:?}", pair);
+    let what = format!("getReserves {:?}", pair);
+    let reserves = with_retry(&config, &what, || async move { contract.getReserves().call().await }).await?;
+
+    // Конвертируем uint112 в U256 для большей совместимости
+    let reserve0 = U256::from(reserves.reserve0);
+    let reserve1 = U256::from(reserves.reserve1);
+    log!("Получены резервы: reserve0={}, reserve1={}, blockTimestampLast={}", reserve0, reserve1, reserves.blockTimestampLast);
+    Ok((reserve0, reserve1, reserves.blockTimestampLast))
+}
+
+/// Резервы пар одним `aggregate3` через Multicall3
+///
+/// С `with_block` первым вызовом идет `getBlockNumber()` самого Multicall3,
+/// поэтому блок относится ровно к тому состоянию, из которого прочитаны
+/// резервы. Блок `None`, если он не запрашивался.
+async fn multicall_reserves(
+    provider: &RpcProvider,
+    pairs: &[Address],
+    with_block: bool,
+) -> Result<(Option<u64>, Vec<Option<PairReserves>>)> {
+    let call_data: alloy::primitives::Bytes = IUniswapV2Pair::getReservesCall {}.abi_encode().into();
+    let block_call = with_block.then(|| IMulticall3::Call3 {
+        target: MULTICALL3_ADDRESS,
+        allowFailure: false,
+        callData: IMulticall3::getBlockNumberCall {}.abi_encode().into(),
+    });
+    let calls: &Vec<IMulticall3::Call3> = &block_call
+        .into_iter()
+        .chain(pairs.iter().map(|&target| IMulticall3::Call3 { target, allowFailure: true, callData: call_data.clone() }))
+        .collect();
+    let multicall = &IMulticall3::IMulticall3Instance::new(MULTICALL3_ADDRESS, provider);
+    log!("Запрашиваем резервы {} пулов одним multicall", pairs.len());
+    let mut results = with_retry(&provider_config(), "multicall getReserves", || async move {
+        multicall.aggregate3(calls.clone()).call().await
+    })
+    .await?
+    .returnData;
+    if results.len() != calls.len() {
+        return Err(eyre::eyre!("multicall вернул {} результатов на {} вызовов", results.len(), calls.len()));
+    }
+
+    let block = match with_block {
+        true => {
+            let result = results.remove(0);
+            let block = IMulticall3::getBlockNumberCall::abi_decode_returns(&result.returnData, true)
+                .map_err(|e| eyre::eyre!("multicall: не удалось разобрать getBlockNumber: {}", e))?
+                .blockNumber;
+            Some(u64::try_from(block).map_err(|_| eyre::eyre!("multicall: номер блока {} вне u64", block))?)
+        }
+        false => None,
+    };
+    let reserves = results
+        .into_iter()
+        .map(|result| {
+            if !result.success {
+                return None;
+            }
+            let reserves = IUniswapV2Pair::getReservesCall::abi_decode_returns(&result.returnData, true).ok()?;
+            Some((U256::from(reserves.reserve0), U256::from(reserves.reserve1), reserves.blockTimestampLast))
+        })
+        .collect();
+    Ok((block, reserves))
+}
@@ -0,0 +1,159 @@
+// src/chunk_guard.rs
+//! Ограничение цены исполнения отдельного чанка
+//!
+//! Общий `min_amount_out` защищает сумму сплита, но один очень плохой чанк
+//! может спрятаться в хорошем в среднем плане. `ChunkGuard` сравнивает цену
+//! исполнения каждого чанка с лучшей спот-ценой снимка (до первого чанка) и
+//! не дает закрепить чанк, отклонившийся больше чем на `max_deviation_bps`:
+//! чанк уходит в другой пул, уменьшается до границы или остается
+//! неисполненным (либо решение прерывается) - в зависимости от политики.
+use crate::math::{u256_to_f64, BPS_DENOMINATOR};
+use alloy::primitives::U256;
+use std::fmt;
+
+/// Чем исправляется чанк за пределом отклонения
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GuardRemedy {
+    /// Только другой пул с ценой в пределах
+    Reroute,
+    /// Другой пул, а если такого нет - наибольшая часть чанка в пределах; остаток не исполняется
+    #[default]
+    Shrink,
+}
+
+/// Что делать с чанком, который не исправить
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GuardFallback {
+    /// Чанк остается неисполненным, решение продолжается
+    #[default]
+    Partial,
+    /// Решение прерывается ошибкой `ChunkGuardAbort`
+    Abort,
+}
+
+/// Предел отклонения цены чанка от лучшей спот-цены снимка и политика его соблюдения
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkGuard {
+    pub max_deviation_bps: u32,
+    pub remedy: GuardRemedy,
+    pub fallback: GuardFallback,
+}
+
+impl ChunkGuard {
+    /// Цена чанка в пределах; чанк без цены (нулевой выход) предел не проходит
+    pub fn allows(&self, deviation_bps: Option<f64>) -> bool {
+        deviation_bps.is_some_and(|deviation| deviation >= -f64::from(self.max_deviation_bps))
+    }
+
+    /// Наибольший вход не больше `amount_in`, выход которого `quote` укладывается в предел
+    ///
+    /// Цена исполнения ухудшается с ростом входа, поэтому граница ищется
+    /// двоичным поиском; 0 - предел не проходит даже 1 raw unit.
+    pub fn max_input_within(&self, amount_in: U256, best_spot_raw: f64, quote: impl Fn(U256) -> U256) -> U256 {
+        let within = |amount: U256| self.allows(spot_deviation_bps(amount, quote(amount), best_spot_raw));
+        if within(amount_in) {
+            return amount_in;
+        }
+        let mut low = U256::ZERO;
+        let mut high = amount_in;
+        while high - low > U256::from(1u64) {
+            let middle = low + (high - low) / U256::from(2u64);
+            if within(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+/// Отклонение цены исполнения от лучшей спот-цены снимка в bps; отрицательное - хуже спота
+///
+/// Обе цены - выходной токен за входной в raw units, как `PreTradeSpot::best_raw`.
+/// `None` при нулевом входе, выходе или неизвестной спот-цене.
+pub fn spot_deviation_bps(amount_in: U256, amount_out: U256, best_spot_raw: f64) -> Option<f64> {
+    if amount_in.is_zero() || amount_out.is_zero() || best_spot_raw <= 0.0 {
+        return None;
+    }
+    Some((u256_to_f64(amount_out) / u256_to_f64(amount_in) / best_spot_raw - 1.0) * BPS_DENOMINATOR as f64)
+}
+
+/// Как ограничение обошлось с чанком
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuardAction {
+    /// Чанк ушел из лучшего пула в другой
+    Rerouted { from: String, to: String },
+    /// Исполнена только часть чанка
+    Shrunk { pool: String, amount_in: U256, unfilled_in: U256 },
+    /// Чанк не исполнен
+    Unfilled,
+}
+
+/// Срабатывание ограничения на чанке
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardEvent {
+    pub chunk_index: u64,
+    pub deviation_bps: f64, // Отклонение выбранного пула до вмешательства
+    pub action: GuardAction,
+}
+
+impl fmt::Display for GuardEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "чанк #{} (отклонение {:.2} bps): ", self.chunk_index, self.deviation_bps)?;
+        match &self.action {
+            GuardAction::Rerouted { from, to } => write!(f, "перенаправлен из {} в {}", from, to),
+            GuardAction::Shrunk { pool, amount_in, unfilled_in } => {
+                write!(f, "уменьшен до {} raw в {}, {} raw не исполнено", amount_in, pool, unfilled_in)
+            }
+            GuardAction::Unfilled => write!(f, "не исполнен"),
+        }
+    }
+}
+
+/// Решение прервано: чанк вышел за предел отклонения, а исправить его не удалось
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkGuardAbort {
+    pub chunk_index: u64,
+    pub deviation_bps: f64,
+    pub max_deviation_bps: u32,
+}
+
+impl fmt::Display for ChunkGuardAbort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "чанк #{}: цена исполнения отклоняется от лучшего спота на {:.2} bps при пределе {} bps",
+            self.chunk_index, -self.deviation_bps, self.max_deviation_bps)
+    }
+}
+
+impl std::error::Error for ChunkGuardAbort {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deviation_is_negative_when_worse_than_spot() {
+        assert_eq!(spot_deviation_bps(U256::from(4u64), U256::from(3u64), 1.0), Some(-2_500.0));
+        assert_eq!(spot_deviation_bps(U256::from(100u64), U256::from(200u64), 2.0), Some(0.0));
+        assert_eq!(spot_deviation_bps(U256::ZERO, U256::from(1u64), 1.0), None);
+        assert_eq!(spot_deviation_bps(U256::from(1u64), U256::ZERO, 1.0), None);
+
+        let guard = ChunkGuard { max_deviation_bps: 100, remedy: GuardRemedy::Reroute, fallback: GuardFallback::Partial };
+        assert!(guard.allows(Some(-100.0)));
+        assert!(!guard.allows(Some(-100.5)));
+        assert!(!guard.allows(None));
+    }
+
+    #[test]
+    fn max_input_within_is_the_tight_bound() {
+        // Выход a * 1000 / (1000 + a): цена 1000 / (1000 + a), предел 1.05% - вход до 10
+        let quote = |amount: U256| amount * U256::from(1_000_000u64) / (U256::from(1_000u64) + amount);
+        let guard = ChunkGuard { max_deviation_bps: 105, remedy: GuardRemedy::Shrink, fallback: GuardFallback::Partial };
+        assert_eq!(guard.max_input_within(U256::from(500u64), 1_000.0, quote), U256::from(10u64));
+        assert_eq!(guard.max_input_within(U256::from(5u64), 1_000.0, quote), U256::from(5u64));
+
+        let strict = ChunkGuard { max_deviation_bps: 0, ..guard };
+        assert_eq!(strict.max_input_within(U256::from(500u64), 1_000.0, quote), U256::ZERO);
+    }
+}
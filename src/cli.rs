@@ -0,0 +1,300 @@
+// src/cli.rs
+use alloy::primitives::{Address, U256};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::path::PathBuf;
+use crate::chunk_guard::{GuardFallback, GuardRemedy};
+use crate::config::{parse_units, DEFAULT_COMMIT_SWITCH_BPS, DEFAULT_CORRELATION_SHARE_BPS, DEFAULT_EXPLORATION_BUDGET, DEFAULT_PREFETCH_TIMEOUT_MS, DEFAULT_SLIPPAGE_BPS, NUM_CHUNKS, RESERVE_RECORD_MAX_BYTES, RESERVE_RECORD_MAX_SECS, STALE_RESERVES_WARN_SECS, USDC_DECIMALS};
+use crate::dex_registry::REGISTRY_CACHE_TTL_SECS;
+use crate::impact::OracleReferenceModel;
+use crate::solver::Strategy;
+
+/// Агрегатор для поиска оптимальных маршрутов обмена USDC на WETH в сети Polygon
+#[derive(Debug, Clone, Default, Parser)]
+#[command(name = "swap_aggregator", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Показать, как меняется общий выход при 1, 2, 4, ..., N чанках
+    #[arg(long)]
+    pub granularity_sweep: bool,
+
+    /// Алгоритм распределения: greedy, two-pool-analytic (только для двух пулов) или marginal-equalization
+    #[arg(long, value_enum, default_value_t = Strategy::Greedy)]
+    pub strategy: Strategy,
+
+    /// Консервативная котировка: выходные резервы каждого пула уменьшаются на N bps
+    #[arg(long, default_value_t = 0)]
+    pub reserve_haircut_bps: u32,
+
+    /// Допустимое проскальзывание для минимального выхода (amountOutMin) в bps
+    #[arg(long, default_value_t = DEFAULT_SLIPPAGE_BPS)]
+    pub slippage_bps: u32,
+
+    /// Закреплять пул, получивший больше N bps общей суммы, против мелких колебаний лучшего маршрута
+    #[arg(long, value_name = "BPS")]
+    pub commit_threshold_bps: Option<u32>,
+
+    /// Насколько (в bps выхода) другой пул должен быть лучше закрепленного, чтобы перейти на него
+    #[arg(long, value_name = "BPS", default_value_t = DEFAULT_COMMIT_SWITCH_BPS)]
+    pub commit_switch_bps: u32,
+
+    /// Предел отклонения цены исполнения каждого чанка от лучшей спот-цены до сделки (bps);
+    /// чанк за пределом исправляется по --chunk-guard-remedy и --chunk-guard-fallback
+    #[arg(long, value_name = "BPS")]
+    pub max_chunk_price_deviation_bps: Option<u32>,
+
+    /// Исправление чанка за пределом: reroute (другой пул) или shrink (другой пул или часть чанка)
+    #[arg(long, value_enum, default_value_t = GuardRemedy::Shrink, requires = "max_chunk_price_deviation_bps")]
+    pub chunk_guard_remedy: GuardRemedy,
+
+    /// Если чанк не исправить: partial (чанк не исполняется) или abort (ошибка)
+    #[arg(long, value_enum, default_value_t = GuardFallback::Partial, requires = "max_chunk_price_deviation_bps")]
+    pub chunk_guard_fallback: GuardFallback,
+
+    /// Считать price impact относительно цены оракула (WETH за один USDC, например 0.0004)
+    /// вместо спот-цены пула; влияет на impact чанков, сплита и таблицу глубины
+    #[arg(long, value_name = "PRICE", value_parser = parse_oracle_price)]
+    pub impact_oracle_price: Option<OracleReferenceModel>,
+
+    /// Укрупнить чанки, если на них комиссия пула округляется до нуля (amount_in * fee_bps / 10000 == 0)
+    #[arg(long)]
+    pub bump_tiny_chunks: bool,
+
+    /// Псевдослучайно сдвинуть размер каждого чанка в пределах ±N bps (сумма сохраняется),
+    /// чтобы чанки маршрута не были одинаковыми
+    #[arg(long, value_name = "BPS")]
+    pub chunk_jitter_bps: Option<u32>,
+
+    /// Seed разброса чанков для воспроизведения разбиения (по умолчанию - от текущего времени)
+    #[arg(long, value_name = "SEED", requires = "chunk_jitter_bps")]
+    pub chunk_jitter_seed: Option<u64>,
+
+    /// Снять накопительные цены пулов дважды с интервалом N секунд и показать TWAP рядом со спот-ценой
+    #[arg(long, value_name = "SECONDS")]
+    pub twap_window: Option<u64>,
+
+    /// Добавить пулы Uniswap V3 (0.05%, 0.3%, 1%) с котировками через QuoterV2
+    #[arg(long)]
+    pub uniswap_v3: bool,
+
+    /// Сохранить найденные пулы (адреса, токены, decimals, комиссии, резервы, блок) в JSON-файл
+    #[arg(long, value_name = "PATH")]
+    pub save_pools: Option<PathBuf>,
+
+    /// Загрузить пулы из JSON-файла вместо discovery через Factory
+    #[arg(long, value_name = "PATH")]
+    pub load_pools: Option<PathBuf>,
+
+    /// Файл кэша discovery: найденные пулы и отсутствующие уровни комиссии
+    #[arg(long, value_name = "PATH", default_value = ".discovery_cache.json")]
+    pub discovery_cache: PathBuf,
+
+    /// Дополнить список DEX из ончейн-реестра команды (getDexCount/getDex); при конфликте побеждает локальная конфигурация
+    #[arg(long, value_name = "ADDRESS")]
+    pub registry: Option<Address>,
+
+    /// Файл кэша реестра DEX
+    #[arg(long, value_name = "PATH", default_value = ".dex_registry_cache.json")]
+    pub registry_cache: PathBuf,
+
+    /// Сколько секунд кэш реестра DEX считается актуальным
+    #[arg(long, value_name = "SECONDS", default_value_t = REGISTRY_CACHE_TTL_SECS)]
+    pub registry_ttl_secs: u64,
+
+    /// Сколько отрицательных записей кэша discovery перепроверять за запуск
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_EXPLORATION_BUDGET)]
+    pub exploration_budget: usize,
+
+    /// Предупреждать о пулах, чей blockTimestampLast старше N секунд относительно последнего блока
+    #[arg(long, value_name = "SECS", default_value_t = STALE_RESERVES_WARN_SECS)]
+    pub stale_warn_secs: u64,
+
+    /// Исключить из расчета пулы, чьи резервы не менялись дольше N секунд
+    #[arg(long, value_name = "SECS")]
+    pub max_staleness_secs: Option<u64>,
+
+    /// Не сверять токены найденных пар с token0()/token1() контракта (экономит два запроса на пул)
+    #[arg(long)]
+    pub no_verify_pairs: bool,
+
+    /// Добавить пару Uniswap V2 по адресу (токены читаются из контракта); можно указать несколько раз
+    #[arg(long = "extra-pool", value_name = "ADDRESS")]
+    pub extra_pools: Vec<Address>,
+
+    /// Подменить резервы пулов из JSON-файла "адрес пула -> [reserve0, reserve1]" (анализ "что если");
+    /// пулы с подменой помечаются в выводе
+    #[arg(long, value_name = "PATH")]
+    pub override_reserves: Option<PathBuf>,
+
+    /// Адрес отправителя (казначейства): токены найденного маршрута проверяются через eth_call
+    /// от его имени (transfer/approve на ноль), запрет токена завершает запуск ошибкой
+    #[arg(long, value_name = "ADDRESS")]
+    pub sender: Option<Address>,
+
+    /// Машинный режим для cron: в stdout ровно одна строка `OK <total_out_raw> <effective_price> <block>`
+    /// или `ERR <kind>`, весь остальной вывод - в stderr
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// Следить за резервами по событиям Sync через WebSocket и печатать цену при каждом обновлении
+    #[arg(long)]
+    pub watch: bool,
+
+    /// WebSocket RPC для --watch (по умолчанию из INFURA_POLYGON_WS_URL)
+    #[arg(long, value_name = "URL")]
+    pub ws_url: Option<String>,
+
+    /// Записывать ряд резервов пулов (блок, время, резервы, цена) в CSV-файлы каталога
+    #[arg(long, value_name = "DIR", requires = "watch")]
+    pub record_reserves: Option<PathBuf>,
+
+    /// Начинать новый файл ряда после этого размера в МБ
+    #[arg(long, value_name = "MB", default_value_t = RESERVE_RECORD_MAX_BYTES / (1024 * 1024), requires = "record_reserves")]
+    pub record_rotate_mb: u64,
+
+    /// Начинать новый файл ряда после этого возраста в секундах
+    #[arg(long, value_name = "SECS", default_value_t = RESERVE_RECORD_MAX_SECS, requires = "record_reserves")]
+    pub record_rotate_secs: u64,
+
+    /// Повторов вызова контракта после временной ошибки RPC (502, таймаут);
+    /// по умолчанию из RPC_MAX_RETRIES или 3
+    #[arg(long, value_name = "N")]
+    pub rpc_max_retries: Option<u32>,
+
+    /// Пауза перед первым повтором в мс, удваивается с каждым повтором
+    /// (по умолчанию из RPC_BASE_DELAY_MS или 250)
+    #[arg(long, value_name = "MS")]
+    pub rpc_base_delay_ms: Option<u64>,
+
+    /// Предел паузы между повторами в мс (по умолчанию из RPC_MAX_DELAY_MS или 5000)
+    #[arg(long, value_name = "MS")]
+    pub rpc_max_delay_ms: Option<u64>,
+
+    /// Предел запросов к RPC в секунду на весь процесс, 0 - без предела
+    /// (по умолчанию из RPC_MAX_RPS или без предела)
+    #[arg(long, value_name = "N")]
+    pub rpc_max_rps: Option<u32>,
+
+    /// Проверить пулы на общего основного поставщика ликвидности по выпускам LP
+    /// за последние N блоков; пары таких пулов помечаются предупреждением
+    #[arg(long, value_name = "BLOCKS")]
+    pub correlation_window: Option<u64>,
+
+    /// Минимальная доля выпуска LP за окно (bps), с которой поставщик считается основным
+    #[arg(long, value_name = "BPS", default_value_t = DEFAULT_CORRELATION_SHARE_BPS)]
+    pub correlation_share_bps: u32,
+
+    /// Исключить из расчета менее глубокий пул каждой коррелированной пары
+    #[arg(long, requires = "correlation_window")]
+    pub dedupe_correlated: bool,
+}
+
+/// Подкоманды; без подкоманды выполняется полный анализ свапа
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Интерактивный режим: котировки и фильтры над уже найденными пулами
+    Repl {
+        /// Время на фоновое обновление резервов после запуска (0 - без предзагрузки)
+        #[arg(long, value_name = "MS", default_value_t = DEFAULT_PREFETCH_TIMEOUT_MS)]
+        prefetch_timeout_ms: u64,
+        /// Файл накопительной статистики (stats): загружается при запуске и сохраняется после каждого изменения
+        #[arg(long, value_name = "PATH")]
+        stats_file: Option<PathBuf>,
+    },
+    /// Прогнать записанные манифесты текущим кодом и сравнить с ожидаемым результатом
+    Regress {
+        /// Каталог с манифестами (*.json)
+        #[arg(long, default_value = "regress")]
+        corpus: PathBuf,
+        /// Допустимое отклонение выхода в bps
+        #[arg(long, default_value_t = 0.0)]
+        tolerance_bps: f64,
+        /// Записать текущие результаты в манифесты как ожидаемые
+        #[arg(long)]
+        update: bool,
+    },
+    /// Разобрать решение жадного алгоритма для одного чанка записанного манифеста (без сети)
+    ExplainChunk {
+        /// Манифест прогона (см. regress/)
+        #[arg(long)]
+        manifest: PathBuf,
+        /// Номер чанка, с 1
+        #[arg(long)]
+        chunk: u64,
+        /// Печатать разбор в JSON вместо текста
+        #[arg(long)]
+        json: bool,
+    },
+    /// Бенчмарк всех стратегий на встроенном наборе рынков (без сети): качество в bps и время
+    BenchRouting {
+        /// Количество чанков для каждого случая
+        #[arg(long, default_value_t = NUM_CHUNKS)]
+        num_chunks: u64,
+        /// Печатать результаты в JSON вместо таблицы
+        #[arg(long)]
+        json: bool,
+    },
+    /// Конвертация без сети: десятичная сумма <-> raw units токена, адрес -> EIP-55
+    #[command(group(ArgGroup::new("conversion").required(true).args(["to_raw", "to_decimal", "checksum"])))]
+    Convert {
+        /// Сумма или адрес
+        value: String,
+        /// Символ токена для сумм (usdc, usdc.e, weth)
+        token: Option<String>,
+        /// Десятичная сумма -> raw units
+        #[arg(long)]
+        to_raw: bool,
+        /// Raw units -> десятичная сумма
+        #[arg(long)]
+        to_decimal: bool,
+        /// Адрес с контрольной суммой EIP-55
+        #[arg(long)]
+        checksum: bool,
+    },
+    /// Бинарный снимок состояния рынка: сохранить текущее или котировать по сохраненному
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotCommand,
+    },
+    /// Пакет котировок из JSON-файла; результаты пишутся в JSONL по мере готовности
+    Batch {
+        /// JSON-массив запросов: {"id", "amount_usdc", "num_chunks"?, "strategy"?}
+        #[arg(long)]
+        input: PathBuf,
+        /// Файл результатов (JSONL)
+        #[arg(long)]
+        output: PathBuf,
+        /// Сколько запросов решается одновременно
+        #[arg(long, default_value_t = 4)]
+        parallelism: usize,
+    },
+}
+
+/// Действия со снимком рынка
+#[derive(Debug, Clone, Subcommand)]
+pub enum SnapshotCommand {
+    /// Найти пулы и сохранить их состояние вместе с номером и временем блока
+    Save {
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Загрузить снимок и рассчитать маршрут по нему без сети
+    Load {
+        #[arg(long)]
+        input: PathBuf,
+        /// Сумма обмена в USDC; по умолчанию из профиля
+        #[arg(long, value_name = "USDC", value_parser = parse_usdc_amount)]
+        amount_usdc: Option<U256>,
+    },
+}
+
+/// Разбирает сумму USDC для `--amount-usdc` точно, без f64
+fn parse_usdc_amount(value: &str) -> Result<U256, String> {
+    parse_units(value, USDC_DECIMALS).map_err(|e| e.to_string())
+}
+
+/// Разбирает цену оракула для `--impact-oracle-price`
+fn parse_oracle_price(value: &str) -> Result<OracleReferenceModel, String> {
+    OracleReferenceModel::from_decimal(value).map_err(|e| e.to_string())
+}
@@ -0,0 +1,204 @@
+// src/compliance.rs
+//! Предторговая проверка отправителя (--sender)
+//!
+//! Для каждого токена маршрута выполняются минимальные `transfer` и `approve`
+//! на ноль через `eth_call` с `from`, равным реальному отправителю, без
+//! подмены состояния. Так проверяются ограничения самого токена: черный
+//! список USDC ("Blacklistable: account is blacklisted"), пауза и т.п. Первый
+//! откат дает `ComplianceError` с токеном и причиной из данных revert.
+//! Токены, запрещающие перевод нуля, тоже попадут в ошибку: причина видна
+//! в сообщении.
+use crate::config::TokenId;
+use crate::provider::{RpcProvider, IERC20};
+use crate::solver::ChunkRoute;
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::sol_types::{decode_revert_reason, Revert, SolError};
+use eyre::Result;
+use std::fmt;
+use std::sync::Arc;
+
+/// Проверяемое действие отправителя с токеном
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenAction {
+    /// `transfer(to, 0)`
+    Transfer(Address),
+    /// `approve(spender, 0)`
+    Approve(Address),
+}
+
+impl fmt::Display for TokenAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenAction::Transfer(to) => write!(f, "transfer на {:?}", to),
+            TokenAction::Approve(spender) => write!(f, "approve для {:?}", spender),
+        }
+    }
+}
+
+/// Токен маршрута запрещает отправителю действие, нужное для исполнения
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComplianceError {
+    pub sender: Address,
+    pub token: TokenId,
+    pub action: TokenAction,
+    pub reason: String,
+}
+
+impl fmt::Display for ComplianceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "токен {} ({:?}) запрещает {:?} {}: {}", self.token, self.token.address(), self.sender, self.action, self.reason)
+    }
+}
+
+impl std::error::Error for ComplianceError {}
+
+/// Действия, которые понадобятся отправителю для исполнения маршрута
+///
+/// Входной токен переводится в пул (как при прямом свапе в паре V2) и
+/// разрешается пулу; выходной токен проверяется переводом самому себе.
+/// Повторы убираются, порядок - порядок чанков.
+pub fn route_checks(routes: &[ChunkRoute], sender: Address) -> Vec<(TokenId, TokenAction)> {
+    let mut checks = Vec::new();
+    for route in routes {
+        let (Some(pool), Some(token_in), Some(token_out)) = (route.pool_address, route.token_in, route.token_out) else {
+            continue;
+        };
+        for check in [
+            (token_in, TokenAction::Transfer(pool)),
+            (token_in, TokenAction::Approve(pool)),
+            (token_out, TokenAction::Transfer(sender)),
+        ] {
+            if !checks.contains(&check) {
+                checks.push(check);
+            }
+        }
+    }
+    checks
+}
+
+/// Выполняет проверки через `eth_call` от имени `sender`
+///
+/// # Returns
+/// `ComplianceError` для первого отката или `false` из токена; ошибки
+/// сети возвращаются как есть
+pub async fn check_sender(
+    provider: Arc<RpcProvider>,
+    sender: Address,
+    checks: &[(TokenId, TokenAction)],
+) -> Result<()> {
+    for &(token, action) in checks {
+        let contract = IERC20::IERC20Instance::new(token.address(), provider.clone());
+        let result = match action {
+            TokenAction::Transfer(to) => contract.transfer(to, U256::ZERO).from(sender).call().await.map(|ok| ok._0),
+            TokenAction::Approve(spender) => contract.approve(spender, U256::ZERO).from(sender).call().await.map(|ok| ok._0),
+        };
+        let reason = match result {
+            Ok(true) => continue,
+            Ok(false) => "вызов вернул false".to_string(),
+            Err(alloy::contract::Error::TransportError(error)) => match error.as_error_resp() {
+                Some(payload) if payload.message.contains("revert") => revert_reason(payload.as_revert_data(), &payload.message),
+                _ => return Err(error.into()),
+            },
+            Err(error) => return Err(error.into()),
+        };
+        return Err(ComplianceError { sender, token, action, reason }.into());
+    }
+    Ok(())
+}
+
+/// Причина отката: строка `Error(string)`, иначе другая расшифровка или сообщение узла
+fn revert_reason(data: Option<Bytes>, message: &str) -> String {
+    match data.filter(|data| !data.is_empty()) {
+        Some(data) => Revert::abi_decode(&data, true)
+            .map(|revert| revert.reason)
+            .ok()
+            .or_else(|| decode_revert_reason(&data))
+            .unwrap_or_else(|| format!("{} (данные {})", message, data)),
+        None => message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_rpc::{call_from, call_selector, call_target, encode_word, revert_with, MockRpc};
+    use crate::provider::IERC20::{approveCall, transferCall};
+    use alloy::sol_types::SolCall;
+
+    fn route(pool: Address, token_in: TokenId) -> ChunkRoute {
+        ChunkRoute {
+            chunk_index: 1,
+            best_pool_name: "A".to_string(),
+            pool_address: Some(pool),
+            dex: None,
+            token_in: Some(token_in),
+            amount_in: U256::from(1u64),
+            amount_out: U256::from(1u64),
+            token_out: Some(TokenId::WETH),
+            amount_out_native: U256::from(1u64),
+            min_amount_out: U256::ZERO,
+            amount_in_decimal: 0.0,
+            amount_out_decimal: 0.0,
+            price_impact: 0.0,
+            execution_price: None,
+            spot_deviation_bps: None,
+            committed: false,
+            skipped_pools: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn route_checks_cover_each_token_once() {
+        let (pool_a, pool_b, sender) = (Address::repeat_byte(0xa1), Address::repeat_byte(0xb1), Address::repeat_byte(0x5e));
+        let routes = [route(pool_a, TokenId::USDC), route(pool_a, TokenId::USDC), route(pool_b, TokenId::USDC_E)];
+        assert_eq!(route_checks(&routes, sender), vec![
+            (TokenId::USDC, TokenAction::Transfer(pool_a)),
+            (TokenId::USDC, TokenAction::Approve(pool_a)),
+            (TokenId::WETH, TokenAction::Transfer(sender)),
+            (TokenId::USDC_E, TokenAction::Transfer(pool_b)),
+            (TokenId::USDC_E, TokenAction::Approve(pool_b)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn blacklisted_sender_fails_with_token_and_reason() {
+        let (treasury, blacklisted, pool) = (Address::repeat_byte(0x71), Address::repeat_byte(0xbb), Address::repeat_byte(0xa1));
+        let rpc = MockRpc::start(move |method, params| {
+            assert_eq!(method, "eth_call");
+            assert!(matches!(call_selector(params), Some(transferCall::SELECTOR | approveCall::SELECTOR)));
+            // USDC отклоняет отправителя из черного списка; WETH без ограничений
+            if call_target(params) == Some(TokenId::USDC.address()) && call_from(params) == Some(blacklisted) {
+                Err(revert_with("Blacklistable: account is blacklisted"))
+            } else {
+                Ok(encode_word(U256::from(1u64)))
+            }
+        }).await;
+        let checks = route_checks(&[route(pool, TokenId::USDC)], treasury);
+
+        check_sender(rpc.provider.clone(), treasury, &checks).await.unwrap();
+
+        let error = check_sender(rpc.provider.clone(), blacklisted, &checks).await.unwrap_err();
+        let error = error.downcast::<ComplianceError>().unwrap();
+        assert_eq!(error, ComplianceError {
+            sender: blacklisted,
+            token: TokenId::USDC,
+            action: TokenAction::Transfer(pool),
+            reason: "Blacklistable: account is blacklisted".to_string(),
+        });
+        assert!(error.to_string().contains("Blacklistable: account is blacklisted"));
+    }
+
+    #[tokio::test]
+    async fn paused_token_without_reason_and_false_return_are_restrictions() {
+        let sender = Address::repeat_byte(0x71);
+        let rpc = MockRpc::start(|_, params| match call_target(params) {
+            Some(target) if target == TokenId::USDC.address() => Err("execution reverted".to_string()),
+            _ => Ok(encode_word(U256::ZERO)),
+        }).await;
+
+        let paused = check_sender(rpc.provider.clone(), sender, &[(TokenId::USDC, TokenAction::Approve(sender))]).await.unwrap_err();
+        assert_eq!(paused.downcast::<ComplianceError>().unwrap().reason, "execution reverted");
+        let refused = check_sender(rpc.provider.clone(), sender, &[(TokenId::WETH, TokenAction::Transfer(sender))]).await.unwrap_err();
+        assert_eq!(refused.downcast::<ComplianceError>().unwrap().reason, "вызов вернул false");
+    }
+}
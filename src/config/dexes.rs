@@ -0,0 +1,169 @@
+// src/config/dexes.rs
+use alloy::primitives::{address, b256, keccak256, Address, B256};
+use std::fmt;
+use super::tokens::TokenId;
+use crate::math::DEFAULT_FEE_BPS;
+
+// Factory адреса для получения точных адресов пулов (для сети Polygon)
+pub const QUICKSWAP_V2_FACTORY: Address = address!("5757371414417b8C6CAad45bAeF941aBc7d3Ab32");
+pub const SUSHISWAP_V2_FACTORY: Address = address!("c35DADB65012eC5796536bD9864eD8773aBc74C4"); // Правильный адрес для Polygon
+// Хэш init code пар Quickswap для вычисления адреса пары через CREATE2 (см. `pair_for`)
+pub const QUICKSWAP_V2_INIT_CODE_HASH: B256 = b256!("96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f");
+pub const UNISWAP_V3_FACTORY: Address = address!("1F98431c8aD98523631AE4a59f267346ea31F984");
+pub const UNISWAP_V3_QUOTER_V2: Address = address!("61fFE014bA17989E743c5F6cB21bF9697530B21e"); // QuoterV2 для котировок через eth_call
+
+/// Уровни комиссии пулов Uniswap V3 в единицах 1e-6 (500 = 0.05%)
+pub const UNISWAP_V3_FEE_TIERS: [u32; 3] = [500, 3_000, 10_000];
+
+// Статические адреса пулов
+pub const UNISWAP_V2_POOL_ADDRESS: Address = address!("67473ebdBFD1e6Fc4367462d55eD1eE56e1963FA"); // Uniswap V2 USDC/WETH
+pub const MULTICALL3_ADDRESS: Address = address!("cA11bde05977b3631167028862bE2a173976CA11"); // Multicall3 (одинаковый адрес во всех сетях)
+pub const BALANCER_V2_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8"); // Balancer V2 Vault
+pub const CURVE_AAVE_POOL_ADDRESS: Address = address!("445FE580eF8d70FF569aB36e80c647af338db351"); // Curve aave (amDAI/amUSDC/amUSDT)
+
+/// Идентификатор DEX (человекочитаемое имя площадки)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DexId(pub &'static str);
+
+impl DexId {
+    pub const UNISWAP_V2: DexId = DexId("Uniswap V2");
+    pub const QUICKSWAP: DexId = DexId("Quickswap");
+    pub const SUSHISWAP: DexId = DexId("Sushiswap");
+    pub const CURVE: DexId = DexId("Curve");
+    pub const BALANCER_V2: DexId = DexId("Balancer V2");
+    pub const UNISWAP_V3: DexId = DexId("Uniswap V3");
+    /// Пул, добавленный по адресу, чья Factory не входит в конфигурацию
+    pub const EXTERNAL: DexId = DexId("External");
+
+    /// Все DEX, известные конфигурации
+    pub const KNOWN: [DexId; 7] = [
+        DexId::UNISWAP_V2,
+        DexId::QUICKSWAP,
+        DexId::SUSHISWAP,
+        DexId::CURVE,
+        DexId::BALANCER_V2,
+        DexId::UNISWAP_V3,
+        DexId::EXTERNAL,
+    ];
+
+    /// Находит известный DEX по имени
+    pub fn from_name(name: &str) -> Option<DexId> {
+        DexId::KNOWN.iter().copied().find(|dex| dex.0 == name)
+    }
+}
+
+impl fmt::Display for DexId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Откуда берутся адреса пулов DEX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DexSource {
+    /// Адрес пары вычисляется через CREATE2 (`DexConfig::pair_init_code_hash`)
+    /// или запрашивается через `getPair` Factory контракта
+    Factory(Address),
+    /// Заранее известный адрес пула
+    StaticPool(Address),
+    /// Заранее известный адрес взвешенного пула Balancer V2
+    WeightedPool(Address),
+}
+
+/// Доля протокола в комиссии при включенном `feeTo` (Uniswap V2: 1/6 комиссии LP)
+pub const UNISWAP_V2_PROTOCOL_FEE_SHARE: (u32, u32) = (1, 6);
+
+/// Конфигурация одного DEX
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DexConfig {
+    pub id: DexId,
+    pub source: DexSource,
+    /// Входные токены, для которых ищется пул с выходным токеном
+    pub input_tokens: Vec<TokenId>,
+    /// Доля протокола в комиссии (числитель, знаменатель), если у пула включен protocol fee
+    pub protocol_fee_share: (u32, u32),
+    /// Комиссия constant product пулов DEX в bps (у форков может отличаться от 30)
+    pub fee_bps: u32,
+    /// Хэш init code пары для `DexSource::Factory`: адрес пары считается локально
+    /// без `getPair` (None - адрес запрашивается у Factory)
+    pub pair_init_code_hash: Option<B256>,
+}
+
+/// DEX, используемые по умолчанию в сети Polygon
+pub fn default_dexes() -> Vec<DexConfig> {
+    vec![
+        DexConfig {
+            id: DexId::UNISWAP_V2,
+            source: DexSource::StaticPool(UNISWAP_V2_POOL_ADDRESS),
+            input_tokens: vec![TokenId::USDC],
+            protocol_fee_share: UNISWAP_V2_PROTOCOL_FEE_SHARE,
+            fee_bps: DEFAULT_FEE_BPS,
+            pair_init_code_hash: None,
+        },
+        DexConfig {
+            id: DexId::QUICKSWAP,
+            source: DexSource::Factory(QUICKSWAP_V2_FACTORY),
+            input_tokens: vec![TokenId::USDC],
+            protocol_fee_share: UNISWAP_V2_PROTOCOL_FEE_SHARE,
+            fee_bps: DEFAULT_FEE_BPS,
+            pair_init_code_hash: Some(QUICKSWAP_V2_INIT_CODE_HASH),
+        },
+        DexConfig {
+            id: DexId::SUSHISWAP,
+            source: DexSource::Factory(SUSHISWAP_V2_FACTORY),
+            input_tokens: vec![TokenId::USDC, TokenId::USDC_E],
+            protocol_fee_share: UNISWAP_V2_PROTOCOL_FEE_SHARE,
+            fee_bps: DEFAULT_FEE_BPS,
+            // Хэш не сверен с развернутыми парами: адрес запрашивается через getPair
+            pair_init_code_hash: None,
+        },
+    ]
+}
+
+/// Комиссия пулов DEX из конфигурации; для неизвестных DEX - DEFAULT_FEE_BPS
+pub fn dex_fee_bps(dexes: &[DexConfig], dex: DexId) -> u32 {
+    dexes.iter().find(|config| config.id == dex).map_or(DEFAULT_FEE_BPS, |config| config.fee_bps)
+}
+
+/// Адрес пары Uniswap V2-форка без обращения к сети
+/// 
+/// Factory создает пары через CREATE2 с солью `keccak256(token0 ++ token1)`
+/// (токены упорядочены по адресу), поэтому адрес определяется Factory и
+/// хэшем init code пары. Адрес вычисляется и для несуществующей пары:
+/// существование проверяется отдельно (например, чтением резервов).
+pub fn pair_for(factory: Address, init_code_hash: B256, token_a: TokenId, token_b: TokenId) -> Address {
+    let (token0, token1) = if token_a.address() < token_b.address() { (token_a, token_b) } else { (token_b, token_a) };
+    let mut tokens = [0u8; 40];
+    tokens[..20].copy_from_slice(token0.address().as_slice());
+    tokens[20..].copy_from_slice(token1.address().as_slice());
+    factory.create2(keccak256(tokens), init_code_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computed_pair_addresses_match_deployed_pairs() {
+        // Quickswap USDC.e/WETH на Polygon
+        assert_eq!(
+            pair_for(QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, TokenId::USDC_E, TokenId::WETH),
+            address!("853Ee4b2A13f8a742d64C8F088bE7bA2131f670d")
+        );
+        // Uniswap V2 USDC/WETH в Ethereum: тот же хэш init code, другая Factory
+        assert_eq!(
+            pair_for(
+                address!("5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f"),
+                QUICKSWAP_V2_INIT_CODE_HASH,
+                TokenId(address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48")),
+                TokenId(address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")),
+            ),
+            address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")
+        );
+        // Порядок токенов не влияет на адрес
+        assert_eq!(
+            pair_for(QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, TokenId::USDC, TokenId::WETH),
+            pair_for(QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, TokenId::WETH, TokenId::USDC)
+        );
+    }
+}
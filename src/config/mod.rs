@@ -0,0 +1,186 @@
+// src/config/mod.rs
+pub mod dexes;
+pub mod params;
+pub mod tokens;
+
+pub use dexes::*;
+pub use params::*;
+pub use tokens::*;
+
+use alloy::primitives::U256;
+
+/// Масштаб курса конвертации (fixed point 18 decimals)
+pub const CONVERSION_RATE_SCALE: u64 = 1_000_000_000_000_000_000;
+
+/// Токен, эквивалентный выходному с известным курсом (например, обертка над WETH)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputEquivalent {
+    pub token: TokenId,
+    /// Количество выходного токена (raw) за 10^18 raw единиц обертки
+    pub rate: U256,
+}
+
+/// Разрешенный профиль конфигурации, который передается явно
+/// вместо чтения глобальных констант в каждом модуле
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigContext {
+    /// Эквивалентные входные токены (USDC и USDC.e)
+    pub input_tokens: Vec<TokenId>,
+    /// Выходной токен
+    pub output_token: TokenId,
+    /// Эквивалентные выходные токены с курсом конвертации в выходной
+    pub output_equivalents: Vec<OutputEquivalent>,
+    /// DEX, в которых ищутся пулы
+    pub dexes: Vec<DexConfig>,
+    /// Общая сумма обмена в raw units входного токена
+    pub total_amount_in: U256,
+    /// Количество частей, на которые делится сумма
+    pub num_chunks: u64,
+    /// Сверять токены найденных пар с `token0()` / `token1()` контракта
+    pub verify_pairs: bool,
+}
+
+impl Default for ConfigContext {
+    fn default() -> Self {
+        ConfigContext {
+            input_tokens: vec![TokenId::USDC, TokenId::USDC_E],
+            output_token: TokenId::WETH,
+            output_equivalents: Vec::new(),
+            dexes: default_dexes(),
+            total_amount_in: usdc_from_decimal(TOTAL_USDC_DECIMAL),
+            num_chunks: NUM_CHUNKS,
+            verify_pairs: true,
+        }
+    }
+}
+
+impl ConfigContext {
+    /// Является ли токен одним из входных токенов профиля
+    pub fn is_input_token(&self, token: TokenId) -> bool {
+        self.input_tokens.contains(&token)
+    }
+
+    /// Выходной токен и все эквивалентные ему, в порядке профиля
+    pub fn output_tokens(&self) -> Vec<TokenId> {
+        std::iter::once(self.output_token)
+            .chain(self.output_equivalents.iter().map(|equivalent| equivalent.token))
+            .collect()
+    }
+
+    /// Курс конвертации токена в выходной (fixed point 18 decimals);
+    /// `None`, если токен не является выходным или эквивалентным ему
+    pub fn output_rate(&self, token: TokenId) -> Option<U256> {
+        if token == self.output_token {
+            return Some(U256::from(CONVERSION_RATE_SCALE));
+        }
+        self.output_equivalents
+            .iter()
+            .find(|equivalent| equivalent.token == token)
+            .map(|equivalent| equivalent.rate)
+    }
+
+    /// Переводит сумму в токене `token` в raw units выходного токена (с округлением вниз)
+    pub fn convert_output(&self, token: TokenId, amount: U256) -> Option<U256> {
+        let rate = self.output_rate(token)?;
+        crate::math::mul_div(amount, rate, U256::from(CONVERSION_RATE_SCALE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn token_id_metadata() {
+        assert_eq!(TokenId::USDC.decimals(), 6);
+        assert_eq!(TokenId::USDC_E.decimals(), 6);
+        assert_eq!(TokenId::WETH.decimals(), 18);
+        assert_eq!(TokenId::USDC_E.to_string(), "USDC.e");
+
+        // Неизвестный токен: 18 decimals и адрес вместо символа
+        let unknown = TokenId(alloy::primitives::Address::repeat_byte(0xab));
+        assert_eq!(unknown.decimals(), 18);
+        assert!(unknown.to_string().starts_with("0xabab"));
+    }
+
+    #[test]
+    fn typed_ids_as_hashmap_keys() {
+        let mut volume: HashMap<(DexId, TokenId), u64> = HashMap::new();
+        *volume.entry((DexId::SUSHISWAP, TokenId::USDC)).or_default() += 1;
+        *volume.entry((DexId::SUSHISWAP, TokenId::USDC_E)).or_default() += 2;
+        *volume.entry((DexId::SUSHISWAP, TokenId::USDC)).or_default() += 3;
+
+        assert_eq!(volume[&(DexId::SUSHISWAP, TokenId::USDC)], 4);
+        assert_eq!(volume[&(DexId::SUSHISWAP, TokenId::USDC_E)], 2);
+    }
+
+    #[test]
+    fn output_equivalents_convert_at_configured_rate() {
+        let wrapper = TokenId(alloy::primitives::Address::repeat_byte(0x5e));
+        let ctx = ConfigContext {
+            output_equivalents: vec![OutputEquivalent { token: wrapper, rate: U256::from(1_150_000_000_000_000_000u64) }],
+            ..ConfigContext::default()
+        };
+
+        assert_eq!(ctx.output_tokens(), vec![TokenId::WETH, wrapper]);
+        assert_eq!(ctx.convert_output(TokenId::WETH, U256::from(1_000u64)), Some(U256::from(1_000u64)));
+        assert_eq!(ctx.convert_output(wrapper, U256::from(1_000u64)), Some(U256::from(1_150u64)));
+        assert_eq!(ctx.convert_output(TokenId::USDC, U256::from(1_000u64)), None);
+    }
+
+    #[test]
+    fn default_context_matches_legacy_discovery() {
+        let ctx = ConfigContext::default();
+        assert!(ctx.is_input_token(TokenId::USDC));
+        assert!(ctx.is_input_token(TokenId::USDC_E));
+        assert!(!ctx.is_input_token(TokenId::WETH));
+
+        // Те же запросы пулов, что и до рефакторинга
+        let queries: Vec<(DexId, TokenId)> = ctx
+            .dexes
+            .iter()
+            .flat_map(|dex| dex.input_tokens.iter().map(move |&token| (dex.id, token)))
+            .collect();
+        assert_eq!(queries, vec![
+            (DexId::UNISWAP_V2, TokenId::USDC),
+            (DexId::QUICKSWAP, TokenId::USDC),
+            (DexId::SUSHISWAP, TokenId::USDC),
+            (DexId::SUSHISWAP, TokenId::USDC_E),
+        ]);
+    }
+
+    #[test]
+    fn dex_fee_comes_from_dex_config() {
+        let mut dexes = default_dexes();
+        assert_eq!(dex_fee_bps(&dexes, DexId::QUICKSWAP), 30);
+        assert_eq!(dex_fee_bps(&dexes, DexId::SUSHISWAP), 30);
+        dexes.iter_mut().find(|dex| dex.id == DexId::SUSHISWAP).unwrap().fee_bps = 25;
+        assert_eq!(dex_fee_bps(&dexes, DexId::SUSHISWAP), 25);
+        assert_eq!(dex_fee_bps(&dexes, DexId("Unknown fork")), crate::math::DEFAULT_FEE_BPS);
+    }
+
+    #[test]
+    fn display_amount_renders_decimal_and_raw_forms() {
+        assert_eq!(DisplayAmount::new(U256::from(1_999_998u64), TokenId::USDC).to_string(), "1.999998 USDC (raw 1999998)");
+        assert_eq!(
+            DisplayAmount::new(U256::from(1_500_000_000_000_000_000u64), TokenId::WETH).to_string(),
+            "1.500000000000000000 WETH (raw 1500000000000000000)"
+        );
+        assert_eq!(DisplayAmount::new(U256::ZERO, TokenId::USDC_E).to_string(), "0.000000 USDC.e (raw 0)");
+        // Decimals контракта важнее метаданных токена
+        let unknown = TokenId(alloy::primitives::Address::repeat_byte(0xaa));
+        assert_eq!(
+            DisplayAmount::with_decimals(U256::from(12_345u64), unknown, 2).to_string(),
+            format!("123.45 {:?} (raw 12345)", unknown.address())
+        );
+    }
+
+    #[test]
+    fn format_units_truncated_cuts_without_rounding() {
+        assert_eq!(format_units_truncated(U256::from(1_239_999u64), 6, 2), "1.23");
+        assert_eq!(format_units_truncated(U256::from(1_500_000u64), 6, 8), "1.500000");
+        assert_eq!(format_units_truncated(U256::from(1_999_999u64), 6, 0), "1");
+        assert_eq!(format_units_truncated(U256::from(42u64), 0, 6), "42");
+    }
+}
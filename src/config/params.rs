@@ -0,0 +1,20 @@
+// src/config/params.rs
+// Параметры свапа (decimal значения для удобства)
+pub const TOTAL_USDC_DECIMAL: f64 = 1000000.0;      // 1.0 USDC для обмена
+pub const NUM_CHUNKS: u64 = 100;                // Разделить на 100 частей
+pub const DEFAULT_SLIPPAGE_BPS: u32 = 50;       // Допустимое проскальзывание 0.5% для min_amount_out
+pub const DEFAULT_COMMIT_SWITCH_BPS: u32 = 10;   // Другой пул должен быть лучше закрепленного на 0.1%
+pub const DEFAULT_PREFETCH_TIMEOUT_MS: u64 = 3000; // Время на предзагрузку резервов в REPL
+pub const STATS_SAVE_INTERVAL_SECS: u64 = 30;    // REPL сохраняет статистику (--stats-file) не чаще раза в 30 с и при выходе
+pub const V3_QUOTE_STEPS: u64 = 16;             // Котировок QuoterV2 на пул V3 по сетке от 0 до суммы обмена
+pub const V3_REQUOTE_BPS: u32 = 50;             // Доп. котировка, если распределение дальше 0.5% от узла сетки
+pub const DEFAULT_REQUOTE_STEP_BPS: u32 = 1000;  // Пул RequoteOnly перекотируется после роста распределения на 10% суммы
+pub const NEGATIVE_PROBE_TTL_BLOCKS: u64 = 302_400; // Срок записи "пула нет" в кэше discovery (~7 дней на Polygon)
+pub const DEFAULT_EXPLORATION_BUDGET: usize = 2; // Отрицательных записей кэша discovery, перепроверяемых за запуск
+pub const STALE_RESERVES_WARN_SECS: u64 = 3600; // Предупреждать о пулах, чьи резервы не менялись дольше часа
+pub const TINY_POOL_DEPTH: f64 = 10_000.0;      // Пулы с резервом входного токена меньше 10k помечаются в списке как мелкие
+pub const DEFAULT_CORRELATION_SHARE_BPS: u32 = 5000; // Доля выпуска LP, с которой поставщик считается доминирующим в пуле
+pub const LP_LOG_WINDOW_BLOCKS: u64 = 2_000;     // Блоков в одном eth_getLogs при чтении выпусков LP (лимит публичных RPC)
+pub const RESERVE_RECORD_MAX_BYTES: u64 = 64 * 1024 * 1024; // Размер файла ряда резервов (--record-reserves), после которого начинается новый
+pub const RESERVE_RECORD_MAX_SECS: u64 = 3600;  // Возраст файла ряда резервов, после которого начинается новый
+pub const RESERVE_RECORD_QUEUE: usize = 4096;   // Образцов в очереди записи ряда; при заполнении новые отбрасываются
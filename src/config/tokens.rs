@@ -0,0 +1,215 @@
+// src/config/tokens.rs
+use crate::math::u256_to_f64;
+use alloy::primitives::{address, Address, U256};
+use std::fmt;
+
+// Адреса токенов в сети Polygon
+pub const USDC_ADDRESS: Address = address!("3c499c542cEF5E3811e1192ce70d8cC03d5c3359"); // USDC (USD Coin)
+pub const USDC_E_ADDRESS: Address = address!("2791bca1f2de4661ed88a30c99a7a9449aa84174"); // USDC.e (Bridged USDC)
+pub const WETH_ADDRESS: Address = address!("7ceB23fD6bC0adD59E62ac25578270cFf1b9f619"); // WETH (Wrapped ETH)
+
+// Decimals для токенов (количество знаков после запятой)
+pub const USDC_DECIMALS: u8 = 6;  // 1 USDC = 1,000,000 units
+pub const WETH_DECIMALS: u8 = 18; // 1 WETH = 1,000,000,000,000,000,000 units
+
+// Степени 10 для конвертации decimals
+pub const USDC_SCALE: U256 = U256::from_limbs([1_000_000, 0, 0, 0]); // 10^6
+pub const WETH_SCALE: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]); // 10^18
+
+/// Идентификатор токена (адрес контракта)
+/// 
+/// Используется вместо голых `Address` для сравнения и как ключ HashMap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TokenId(pub Address);
+
+impl TokenId {
+    pub const USDC: TokenId = TokenId(USDC_ADDRESS);
+    pub const USDC_E: TokenId = TokenId(USDC_E_ADDRESS);
+    pub const WETH: TokenId = TokenId(WETH_ADDRESS);
+
+    /// Адрес контракта токена
+    pub const fn address(self) -> Address {
+        self.0
+    }
+
+    /// Метаданные токена, если он известен конфигурации
+    pub fn info(self) -> Option<&'static TokenInfo> {
+        KNOWN_TOKENS.iter().find(|token| token.id == self)
+    }
+
+    /// Decimals токена (18 для неизвестных токенов)
+    pub fn decimals(self) -> u8 {
+        self.info().map_or(WETH_DECIMALS, |token| token.decimals)
+    }
+
+    /// Известный токен по символу без учета регистра ("weth", "USDC.e")
+    pub fn from_symbol(symbol: &str) -> Result<TokenId, UnitsError> {
+        KNOWN_TOKENS
+            .iter()
+            .find(|token| token.symbol.eq_ignore_ascii_case(symbol))
+            .map(|token| token.id)
+            .ok_or_else(|| UnitsError::UnknownToken(symbol.to_string()))
+    }
+}
+
+impl From<Address> for TokenId {
+    fn from(address: Address) -> Self {
+        TokenId(address)
+    }
+}
+
+impl fmt::Display for TokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.info() {
+            Some(token) => write!(f, "{}", token.symbol),
+            None => write!(f, "{:?}", self.0),
+        }
+    }
+}
+
+/// Статические метаданные известного токена
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub id: TokenId,
+    pub symbol: &'static str,
+    pub decimals: u8,
+}
+
+/// Токены, известные конфигурации Polygon
+pub const KNOWN_TOKENS: [TokenInfo; 3] = [
+    TokenInfo { id: TokenId::USDC, symbol: "USDC", decimals: USDC_DECIMALS },
+    TokenInfo { id: TokenId::USDC_E, symbol: "USDC.e", decimals: USDC_DECIMALS },
+    TokenInfo { id: TokenId::WETH, symbol: "WETH", decimals: WETH_DECIMALS },
+];
+
+/// Конвертирует raw units токена с заданными decimals в человекочитаемое значение
+/// 
+/// f64 годится только для приблизительного вывода; точное значение дает `format_units`
+pub fn to_decimal(raw_amount: U256, decimals: u8) -> f64 {
+    u256_to_f64(raw_amount) / 10f64.powi(decimals as i32)
+}
+
+/// Конвертирует USDC из raw units в человекочитаемое значение
+pub fn usdc_to_decimal(raw_amount: U256) -> f64 {
+    u256_to_f64(raw_amount) / u256_to_f64(USDC_SCALE)
+}
+
+/// Конвертирует WETH из raw units в человекочитаемое значение
+pub fn weth_to_decimal(raw_amount: U256) -> f64 {
+    u256_to_f64(raw_amount) / u256_to_f64(WETH_SCALE)
+}
+
+/// Конвертирует USDC из человекочитаемого значения в raw units
+pub fn usdc_from_decimal(decimal_amount: f64) -> U256 {
+    U256::from((decimal_amount * u256_to_f64(USDC_SCALE)) as u128)
+}
+
+/// Точно форматирует raw amount как число с фиксированной точкой
+/// 
+/// В отличие от конвертации в f64 не теряет младшие разряды на больших суммах:
+/// `format_units(U256::from(1_500_000u64), 6) == "1.500000"`
+pub fn format_units(raw_amount: U256, decimals: u8) -> String {
+    if decimals == 0 {
+        return raw_amount.to_string();
+    }
+    let scale = U256::from(10u64).pow(U256::from(decimals));
+    format!(
+        "{}.{:0>width$}",
+        raw_amount / scale,
+        (raw_amount % scale).to_string(),
+        width = decimals as usize
+    )
+}
+
+/// `format_units`, обрезанный до `precision` знаков после точки (без округления)
+///
+/// Для таблиц, где 18 знаков WETH не помещаются в колонку:
+/// `format_units_truncated(U256::from(1_234_567u64), 6, 2) == "1.23"`
+pub fn format_units_truncated(raw_amount: U256, decimals: u8, precision: u8) -> String {
+    let formatted = format_units(raw_amount, decimals);
+    match formatted.split_once('.') {
+        Some((whole, _)) if precision == 0 => whole.to_string(),
+        Some((whole, fraction)) => format!("{}.{}", whole, &fraction[..fraction.len().min(precision as usize)]),
+        None => formatted,
+    }
+}
+
+/// Сумма токена для сообщений: десятичная форма с символом и raw units
+///
+/// `1.500000 USDC (raw 1500000)`. Используется в ошибках и предупреждениях,
+/// чтобы по логу можно было и оценить порядок суммы, и найти точное значение.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayAmount {
+    pub amount: U256,
+    pub token: TokenId,
+    pub decimals: u8,
+}
+
+impl DisplayAmount {
+    /// Сумма с decimals из метаданных токена
+    pub fn new(amount: U256, token: TokenId) -> Self {
+        DisplayAmount { amount, token, decimals: token.decimals() }
+    }
+
+    /// Сумма с decimals, прочитанными у контракта (например, `Pool::decimals`)
+    pub fn with_decimals(amount: U256, token: TokenId, decimals: u8) -> Self {
+        DisplayAmount { amount, token, decimals }
+    }
+}
+
+impl fmt::Display for DisplayAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} (raw {})", format_units(self.amount, self.decimals), self.token, self.amount)
+    }
+}
+
+/// Ошибка разбора суммы или токена
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnitsError {
+    /// Строка не является неотрицательным десятичным числом
+    InvalidNumber(String),
+    /// Значащих знаков после точки больше, чем decimals токена
+    TooPrecise { value: String, decimals: u8 },
+    /// Сумма в raw units не помещается в U256
+    Overflow(String),
+    /// Символ не найден среди известных токенов
+    UnknownToken(String),
+}
+
+impl fmt::Display for UnitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitsError::InvalidNumber(value) => write!(f, "некорректная сумма: {}", value),
+            UnitsError::TooPrecise { value, decimals } => {
+                write!(f, "сумма {} точнее {} знаков после точки", value, decimals)
+            }
+            UnitsError::Overflow(value) => write!(f, "сумма {} не помещается в U256", value),
+            UnitsError::UnknownToken(symbol) => write!(f, "неизвестный токен: {}", symbol),
+        }
+    }
+}
+
+impl std::error::Error for UnitsError {}
+
+/// Точно разбирает десятичную сумму в raw units (обратная к `format_units`)
+/// 
+/// `parse_units("1.5", 6) == Ok(1_500_000)`. Нули в конце дробной части
+/// допускаются сверх `decimals`, значащие цифры - нет: сумма не округляется.
+pub fn parse_units(value: &str, decimals: u8) -> Result<U256, UnitsError> {
+    let invalid = || UnitsError::InvalidNumber(value.to_string());
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if integer.is_empty() && fraction.is_empty() || !all_digits(integer) || !all_digits(fraction) {
+        return Err(invalid());
+    }
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return Err(UnitsError::TooPrecise { value: value.to_string(), decimals });
+    }
+    let digits = format!("{}{:0<width$}", integer, fraction, width = decimals as usize);
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() {
+        return Ok(U256::ZERO);
+    }
+    U256::from_str_radix(digits, 10).map_err(|_| UnitsError::Overflow(value.to_string()))
+}
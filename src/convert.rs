@@ -0,0 +1,110 @@
+// src/convert.rs
+//! Конвертация сумм и адресов (swap_aggregator convert)
+//!
+//! Использует те же точные `parse_units` / `format_units` и реестр токенов,
+//! что и котировки, поэтому ошибки (лишние знаки после точки, неизвестный
+//! токен) совпадают с ошибками основного пути.
+use crate::config::{format_units, parse_units, TokenId};
+use alloy::primitives::{Address, U256};
+use eyre::{eyre, Result};
+use std::str::FromStr;
+
+/// Направление конвертации
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// Десятичная сумма токена в raw units: `1.5 weth` -> `1500000000000000000`
+    ToRaw,
+    /// Raw units в десятичную сумму: `2500000 usdc` -> `2.500000`
+    ToDecimal,
+    /// Адрес в форме EIP-55 с контрольной суммой в регистре букв
+    Checksum,
+}
+
+/// Выполняет конвертацию и возвращает строку результата
+///
+/// Для сумм нужен символ токена (`token`), для адреса он не используется.
+pub fn convert(value: &str, token: Option<&str>, conversion: Conversion) -> Result<String> {
+    match conversion {
+        Conversion::ToRaw => {
+            let token = token_for(token)?;
+            Ok(parse_units(value, token.decimals())?.to_string())
+        }
+        Conversion::ToDecimal => {
+            let token = token_for(token)?;
+            let raw = U256::from_str_radix(value, 10).map_err(|_| eyre!("некорректная сумма в raw units: {}", value))?;
+            Ok(format_units(raw, token.decimals()))
+        }
+        Conversion::Checksum => Ok(checksum_address(value)?.to_checksum(None)),
+    }
+}
+
+fn token_for(symbol: Option<&str>) -> Result<TokenId> {
+    let symbol = symbol.ok_or_else(|| eyre!("для конвертации суммы нужен символ токена (usdc, usdc.e, weth)"))?;
+    Ok(TokenId::from_symbol(symbol)?)
+}
+
+/// Разбирает адрес; адрес в смешанном регистре должен иметь верную контрольную сумму
+fn checksum_address(value: &str) -> Result<Address> {
+    let address = Address::from_str(value).map_err(|_| eyre!("некорректный адрес: {}", value))?;
+    let hex = value.trim_start_matches("0x");
+    let mixed_case = hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && address.to_checksum(None).trim_start_matches("0x") != hex {
+        return Err(eyre!("неверная контрольная сумма адреса: {}", value));
+    }
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UnitsError;
+
+    fn units_error(result: Result<String>) -> UnitsError {
+        result.unwrap_err().downcast::<UnitsError>().unwrap()
+    }
+
+    #[test]
+    fn converts_decimal_to_raw_and_back() {
+        assert_eq!(convert("1.5", Some("weth"), Conversion::ToRaw).unwrap(), "1500000000000000000");
+        assert_eq!(convert("2.5", Some("USDC"), Conversion::ToRaw).unwrap(), "2500000");
+        assert_eq!(convert("0.000001", Some("usdc.e"), Conversion::ToRaw).unwrap(), "1");
+        // Нули сверх decimals не меняют сумму
+        assert_eq!(convert("3.1000000", Some("usdc"), Conversion::ToRaw).unwrap(), "3100000");
+
+        assert_eq!(convert("2500000", Some("usdc"), Conversion::ToDecimal).unwrap(), "2.500000");
+        assert_eq!(convert("1500000000000000000", Some("weth"), Conversion::ToDecimal).unwrap(), "1.500000000000000000");
+        // Туда и обратно без потери точности на сумме больше 2^53
+        let raw = convert("123456789.123456789012345678", Some("weth"), Conversion::ToRaw).unwrap();
+        assert_eq!(convert(&raw, Some("weth"), Conversion::ToDecimal).unwrap(), "123456789.123456789012345678");
+    }
+
+    #[test]
+    fn amount_errors_match_the_units_parser() {
+        assert_eq!(
+            units_error(convert("1.0000001", Some("usdc"), Conversion::ToRaw)),
+            UnitsError::TooPrecise { value: "1.0000001".to_string(), decimals: 6 }
+        );
+        assert_eq!(units_error(convert("1", Some("dai"), Conversion::ToRaw)), UnitsError::UnknownToken("dai".to_string()));
+        assert_eq!(units_error(convert("1,5", Some("weth"), Conversion::ToRaw)), UnitsError::InvalidNumber("1,5".to_string()));
+        let huge = "1".repeat(70);
+        assert_eq!(units_error(convert(&huge, Some("weth"), Conversion::ToRaw)), UnitsError::Overflow(huge.clone()));
+        for invalid in ["", ".", "-1", "1.2.3", "1e6"] {
+            assert_eq!(parse_units(invalid, 6), Err(UnitsError::InvalidNumber(invalid.to_string())));
+        }
+
+        assert!(convert("1.5", Some("usdc"), Conversion::ToDecimal).is_err());
+        assert!(convert("1.5", None, Conversion::ToRaw).is_err());
+    }
+
+    #[test]
+    fn checksums_addresses_and_rejects_bad_checksums() {
+        let lower = "0x7ceb23fd6bc0add59e62ac25578270cff1b9f619";
+        assert_eq!(convert(lower, None, Conversion::Checksum).unwrap(), "0x7ceB23fD6bC0adD59E62ac25578270cFf1b9f619");
+        assert_eq!(
+            convert("0x7ceB23fD6bC0adD59E62ac25578270cFf1b9f619", None, Conversion::Checksum).unwrap(),
+            "0x7ceB23fD6bC0adD59E62ac25578270cFf1b9f619"
+        );
+        assert!(convert("0x7CEb23fD6bC0adD59E62ac25578270cFf1b9f619", None, Conversion::Checksum).is_err());
+        assert!(convert("0x1234", None, Conversion::Checksum).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+// src/correlation.rs
+//! Коррелированные пулы: общий доминирующий поставщик ликвидности
+//!
+//! Два "разных" пула с зеркальной ликвидностью одного владельца (частый
+//! случай у спам-форков) не дают диверсификации: разбиение между ними
+//! повторяет риск одного пула. Эвристика находит для каждой пары основного
+//! поставщика ликвидности за недавнее окно блоков и помечает пулы, у которых
+//! он общий; с `--dedupe-correlated` из каждой такой пары остается более
+//! глубокий пул.
+//!
+//! `Mint(sender, amount0, amount1)` пары для этого не годится: `sender` - это
+//! роутер, через который добавлена ликвидность, а не поставщик. Поставщика
+//! видно по выпуску LP токенов той же пары: в `mint` пара испускает
+//! `Transfer(0x0, provider, liquidity)` рядом с `Mint`. Первый выпуск
+//! отправляет MINIMUM_LIQUIDITY на нулевой адрес; такой перевод пропускается.
+use crate::config::{ConfigContext, LP_LOG_WINDOW_BLOCKS};
+use crate::math::BPS_DENOMINATOR;
+use crate::pool::PoolState;
+use crate::pool_registry::input_depth;
+use crate::provider::{provider_config, with_retry, IUniswapV2Pair, RpcProvider};
+use alloy::primitives::{Address, B256, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::{Filter, Log};
+use alloy::sol_types::SolEvent;
+use eyre::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Выпуск LP токенов пары поставщику
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LpMint {
+    pub pool_address: Address,
+    pub provider: Address,
+    pub liquidity: U256,
+}
+
+/// Разбирает выпуск LP (`Transfer` с нулевого адреса); `None` для других
+/// событий, переводов между держателями, MINIMUM_LIQUIDITY и отмененных логов
+pub fn decode_lp_mint(log: &Log) -> Option<LpMint> {
+    if log.removed {
+        return None;
+    }
+    let decoded = log.log_decode::<IUniswapV2Pair::Transfer>().ok()?;
+    let transfer = decoded.inner.data;
+    (transfer.from == Address::ZERO && transfer.to != Address::ZERO).then_some(LpMint {
+        pool_address: log.address(),
+        provider: transfer.to,
+        liquidity: transfer.value,
+    })
+}
+
+/// Основной поставщик ликвидности пула и его доля выпуска LP за окно
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DominantProvider {
+    pub provider: Address,
+    pub share_bps: u32,
+}
+
+/// Основной поставщик каждого пула с выпусками LP
+///
+/// При равных суммах выбирается меньший адрес, чтобы результат не зависел
+/// от порядка логов.
+pub fn dominant_providers(mints: &[LpMint]) -> HashMap<Address, DominantProvider> {
+    let mut minted: HashMap<Address, HashMap<Address, U256>> = HashMap::new();
+    for mint in mints {
+        let total = minted.entry(mint.pool_address).or_default().entry(mint.provider).or_default();
+        *total = total.saturating_add(mint.liquidity);
+    }
+    minted
+        .into_iter()
+        .filter_map(|(pool_address, providers)| {
+            let total = providers.values().fold(U256::ZERO, |total, amount| total.saturating_add(*amount));
+            let (&provider, &amount) = providers
+                .iter()
+                .max_by(|(a, amount_a), (b, amount_b)| amount_a.cmp(amount_b).then_with(|| b.cmp(a)))?;
+            if total.is_zero() {
+                return None;
+            }
+            let share_bps = amount.saturating_mul(U256::from(BPS_DENOMINATOR)) / total;
+            Some((pool_address, DominantProvider { provider, share_bps: share_bps.to::<u32>() }))
+        })
+        .collect()
+}
+
+/// Пара пулов с общим доминирующим поставщиком ликвидности
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorrelatedPair {
+    /// Более глубокий пул (по резерву входного токена профиля)
+    pub deeper: Address,
+    pub shallower: Address,
+    pub provider: Address,
+    pub deeper_share_bps: u32,
+    pub shallower_share_bps: u32,
+}
+
+/// Пары пулов, в каждом из которых один и тот же поставщик выпустил не
+/// меньше `min_share_bps` LP за окно
+///
+/// Пары упорядочены по позициям пулов в `pools`. Глубже - пул с большим
+/// резервом входного токена, при равенстве - с меньшим адресом.
+pub fn correlated_pairs(
+    pools: &[PoolState],
+    dominant: &HashMap<Address, DominantProvider>,
+    min_share_bps: u32,
+    ctx: &ConfigContext,
+) -> Vec<CorrelatedPair> {
+    let qualified: Vec<(&PoolState, DominantProvider)> = pools
+        .iter()
+        .filter_map(|pool| dominant.get(&pool.pool_address).map(|provider| (pool, *provider)))
+        .filter(|(_, provider)| provider.share_bps >= min_share_bps)
+        .collect();
+
+    let mut pairs = Vec::new();
+    for (i, &(a, provider_a)) in qualified.iter().enumerate() {
+        for &(b, provider_b) in &qualified[i + 1..] {
+            if provider_a.provider != provider_b.provider {
+                continue;
+            }
+            let depth = |pool: &PoolState| (input_depth(pool, ctx), std::cmp::Reverse(pool.pool_address));
+            let ((deeper, deeper_share), (shallower, shallower_share)) = if depth(a) >= depth(b) {
+                ((a, provider_a), (b, provider_b))
+            } else {
+                ((b, provider_b), (a, provider_a))
+            };
+            pairs.push(CorrelatedPair {
+                deeper: deeper.pool_address,
+                shallower: shallower.pool_address,
+                provider: provider_a.provider,
+                deeper_share_bps: deeper_share.share_bps,
+                shallower_share_bps: shallower_share.share_bps,
+            });
+        }
+    }
+    pairs
+}
+
+/// Пулы, которые `--dedupe-correlated` исключает: менее глубокие из каждой пары
+///
+/// Из группы пулов одного поставщика остается только самый глубокий.
+pub fn shallower_pools(pairs: &[CorrelatedPair]) -> Vec<Address> {
+    let mut addresses: Vec<Address> = pairs.iter().map(|pair| pair.shallower).collect();
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// Читает выпуски LP пар за блоки `from_block..=to_block`
+///
+/// Диапазон читается окнами по `LP_LOG_WINDOW_BLOCKS` блоков (публичные RPC
+/// ограничивают `eth_getLogs`); временные ошибки RPC повторяются по
+/// политике провайдера.
+pub async fn backfill_lp_mints(
+    provider: Arc<RpcProvider>,
+    pool_addresses: &[Address],
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<LpMint>> {
+    let mut mints = Vec::new();
+    if pool_addresses.is_empty() {
+        return Ok(mints);
+    }
+    let mut start = from_block;
+    while start <= to_block {
+        let end = start.saturating_add(LP_LOG_WINDOW_BLOCKS - 1).min(to_block);
+        let filter = &Filter::new()
+            .address(pool_addresses.to_vec())
+            .event_signature(IUniswapV2Pair::Transfer::SIGNATURE_HASH)
+            .topic1(B256::ZERO)
+            .from_block(start)
+            .to_block(end);
+        let provider = &provider;
+        let what = format!("eth_getLogs выпусков LP, блоки {}..={}", start, end);
+        let logs = with_retry(&provider_config(), &what, || async move {
+            provider.get_logs(filter).await.map_err(alloy::contract::Error::from)
+        })
+        .await?;
+        mints.extend(logs.iter().filter_map(decode_lp_mint));
+        start = end + 1;
+    }
+    Ok(mints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenId;
+    use crate::mock_rpc::MockRpc;
+    use crate::pool::test_pool;
+    use alloy::primitives::aliases::U112;
+    use alloy::primitives::LogData;
+    use std::sync::Mutex;
+
+    fn transfer_log(pool_byte: u8, from: Address, to: Address, value: u64) -> Log {
+        let event = IUniswapV2Pair::Transfer { from, to, value: U256::from(value) };
+        let data: LogData = event.encode_log_data();
+        Log {
+            inner: alloy::primitives::Log { address: Address::repeat_byte(pool_byte), data },
+            block_number: Some(100),
+            log_index: Some(0),
+            ..Default::default()
+        }
+    }
+
+    fn mint(pool_byte: u8, provider_byte: u8, liquidity: u64) -> LpMint {
+        LpMint {
+            pool_address: Address::repeat_byte(pool_byte),
+            provider: Address::repeat_byte(provider_byte),
+            liquidity: U256::from(liquidity),
+        }
+    }
+
+    #[test]
+    fn only_lp_mints_to_providers_are_decoded() {
+        let provider = Address::repeat_byte(0xa1);
+        assert_eq!(decode_lp_mint(&transfer_log(0x11, Address::ZERO, provider, 500)), Some(mint(0x11, 0xa1, 500)));
+
+        // Перевод между держателями, MINIMUM_LIQUIDITY и отмененный лог
+        assert_eq!(decode_lp_mint(&transfer_log(0x11, Address::repeat_byte(0xb2), provider, 500)), None);
+        assert_eq!(decode_lp_mint(&transfer_log(0x11, Address::ZERO, Address::ZERO, 1_000)), None);
+        let mut removed = transfer_log(0x11, Address::ZERO, provider, 500);
+        removed.removed = true;
+        assert_eq!(decode_lp_mint(&removed), None);
+
+        // Чужое событие
+        let sync = IUniswapV2Pair::Sync { reserve0: U112::from(1u64), reserve1: U112::from(2u64) };
+        let sync_log = Log {
+            inner: alloy::primitives::Log { address: Address::repeat_byte(0x11), data: sync.encode_log_data() },
+            ..Default::default()
+        };
+        assert_eq!(decode_lp_mint(&sync_log), None);
+    }
+
+    #[test]
+    fn dominant_provider_has_largest_minted_share() {
+        let mints = [mint(0x11, 0xa1, 300), mint(0x11, 0xa2, 200), mint(0x11, 0xa1, 500), mint(0x12, 0xa3, 50), mint(0x12, 0xa2, 50)];
+        let dominant = dominant_providers(&mints);
+
+        assert_eq!(dominant[&Address::repeat_byte(0x11)], DominantProvider { provider: Address::repeat_byte(0xa1), share_bps: 8_000 });
+        // Равные суммы: меньший адрес
+        assert_eq!(dominant[&Address::repeat_byte(0x12)], DominantProvider { provider: Address::repeat_byte(0xa2), share_bps: 5_000 });
+    }
+
+    #[test]
+    fn pools_sharing_dominant_provider_are_paired_deeper_first() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pool = |byte: u8, usdc: u64| test_pool(byte, TokenId::USDC, TokenId::WETH, U256::from(usdc), weth);
+        let pools = [pool(0x11, 1_000_000), pool(0x12, 5_000_000), pool(0x13, 9_000_000)];
+        // 0x11 и 0x12: 0xa1 выпустил 80% и 90%; у 0x13 основной поставщик другой
+        let mints = [
+            mint(0x11, 0xa1, 800), mint(0x11, 0xa2, 200),
+            mint(0x12, 0xa1, 900), mint(0x12, 0xa3, 100),
+            mint(0x13, 0xa2, 1_000),
+        ];
+        let dominant = dominant_providers(&mints);
+        let ctx = ConfigContext::default();
+
+        let pairs = correlated_pairs(&pools, &dominant, 5_000, &ctx);
+        assert_eq!(pairs, vec![CorrelatedPair {
+            deeper: Address::repeat_byte(0x12),
+            shallower: Address::repeat_byte(0x11),
+            provider: Address::repeat_byte(0xa1),
+            deeper_share_bps: 9_000,
+            shallower_share_bps: 8_000,
+        }]);
+        assert_eq!(shallower_pools(&pairs), vec![Address::repeat_byte(0x11)]);
+
+        // Порог выше доли в 0x11: корреляция не засчитывается
+        assert!(correlated_pairs(&pools, &dominant, 8_500, &ctx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn backfill_reads_windows_and_keeps_lp_mints() {
+        let pool = Address::repeat_byte(0x11);
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&ranges);
+        let rpc = MockRpc::start(move |method, params| {
+            assert_eq!(method, "eth_getLogs");
+            let block = |key: &str| u64::from_str_radix(params[0][key].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+            seen.lock().unwrap().push((block("fromBlock"), block("toBlock")));
+            // Выпуск LP и перевод между держателями в каждом окне
+            let logs = [
+                transfer_log(0x11, Address::ZERO, Address::repeat_byte(0xa1), 700),
+                transfer_log(0x11, Address::repeat_byte(0xa1), Address::repeat_byte(0xb2), 100),
+            ];
+            Ok(serde_json::to_value(logs).unwrap())
+        }).await;
+
+        let mints = backfill_lp_mints(rpc.provider.clone(), &[pool], 1_000, 1_000 + LP_LOG_WINDOW_BLOCKS + 10).await.unwrap();
+        assert_eq!(*ranges.lock().unwrap(), vec![
+            (1_000, 1_000 + LP_LOG_WINDOW_BLOCKS - 1),
+            (1_000 + LP_LOG_WINDOW_BLOCKS, 1_000 + LP_LOG_WINDOW_BLOCKS + 10),
+        ]);
+        assert_eq!(mints, vec![mint(0x11, 0xa1, 700), mint(0x11, 0xa1, 700)]);
+        assert!(backfill_lp_mints(rpc.provider.clone(), &[], 0, 10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+// src/dex_registry.rs
+//! Начальный список DEX из ончейн-реестра команды (--registry)
+//!
+//! Контракт реестра (`IDexRegistry`) перечисляет одобренные Factory и
+//! роутеры сети: `getDexCount()` и `getDex(i)`. Записи реестра сливаются с
+//! локальной конфигурацией (`default_dexes`):
+//!
+//! - DEX сопоставляется с локальным по имени (без учета регистра) или по Factory;
+//! - при расхождении Factory, комиссии или хэша init code побеждает локальная
+//!   конфигурация, расхождение выводится предупреждением;
+//! - новые DEX добавляются как Uniswap V2-форки с входными токенами профиля;
+//! - записи без Factory и повторы Factory пропускаются с предупреждением.
+//!
+//! Прочитанные записи кэшируются в JSON-файле на `REGISTRY_CACHE_TTL_SECS`.
+//! Если реестр прочитать не удалось, используется только локальная
+//! конфигурация: запуск не зависит от доступности реестра.
+use crate::config::{DexConfig, DexId, DexSource, TokenId, UNISWAP_V2_PROTOCOL_FEE_SHARE};
+use crate::log;
+use crate::math::DEFAULT_FEE_BPS;
+use crate::provider::{IDexRegistry, RpcProvider};
+use alloy::primitives::{Address, B256, U256};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Сколько секунд прочитанный реестр считается актуальным
+pub const REGISTRY_CACHE_TTL_SECS: u64 = 3_600;
+
+/// Больше записей не читается: защита от ошибочного счетчика в контракте
+pub const MAX_REGISTRY_ENTRIES: u64 = 64;
+
+/// Запись реестра
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub factory: Address,
+    pub router: Address, // Для исполнения; discovery использует только Factory
+    pub pair_init_code_hash: Option<B256>,
+    pub fee_bps: Option<u32>,
+}
+
+impl From<IDexRegistry::DexEntry> for RegistryEntry {
+    fn from(entry: IDexRegistry::DexEntry) -> Self {
+        RegistryEntry {
+            name: entry.name,
+            factory: entry.factory,
+            router: entry.router,
+            pair_init_code_hash: (!entry.pairInitCodeHash.is_zero()).then_some(entry.pairInitCodeHash),
+            fee_bps: (entry.feeBps != 0).then_some(u32::from(entry.feeBps)),
+        }
+    }
+}
+
+/// Расхождение реестра с локальной конфигурацией или негодная запись
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryWarning {
+    /// Поле DEX в реестре отличается от локального; используется локальное
+    Conflict { dex: DexId, field: &'static str, local: String, registry: String },
+    /// Запись пропущена
+    Skipped { index: usize, name: String, reason: &'static str },
+}
+
+impl fmt::Display for RegistryWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryWarning::Conflict { dex, field, local, registry } => {
+                write!(f, "{}: {} в реестре {}, локально {} - используется локальное значение", dex, field, registry, local)
+            }
+            RegistryWarning::Skipped { index, name, reason } => write!(f, "запись {} ({}) пропущена: {}", index, name, reason),
+        }
+    }
+}
+
+/// Итог слияния реестра с локальной конфигурацией
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryMerge {
+    pub dexes: Vec<DexConfig>,
+    pub added: Vec<DexId>,
+    pub warnings: Vec<RegistryWarning>,
+}
+
+/// Откуда взят список DEX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrySource {
+    /// Реестр прочитан из сети
+    Chain,
+    /// Реестр взят из кэша, срок которого не истек
+    Cache,
+    /// Реестр недоступен - только локальная конфигурация
+    LocalFallback,
+}
+
+/// Файл кэша: записи реестра и время их чтения
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryCache {
+    pub registry: Address,
+    pub fetched_at: u64, // Unix time чтения
+    pub entries: Vec<RegistryEntry>,
+}
+
+impl RegistryCache {
+    /// Загружает кэш; отсутствующий файл - `None`
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// Сохраняет кэш в файл
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Кэш того же реестра, прочитанный не раньше `ttl_secs` назад
+    pub fn is_fresh(&self, registry: Address, now: u64, ttl_secs: u64) -> bool {
+        self.registry == registry && now.saturating_sub(self.fetched_at) < ttl_secs
+    }
+}
+
+/// Читает все записи реестра
+pub async fn read_registry(provider: Arc<RpcProvider>, registry: Address) -> Result<Vec<RegistryEntry>> {
+    let contract = IDexRegistry::IDexRegistryInstance::new(registry, provider);
+    let count = contract.getDexCount().call().await?._0;
+    if count > U256::from(MAX_REGISTRY_ENTRIES) {
+        return Err(eyre!("реестр {:?} сообщает {} записей, больше предела {}", registry, count, MAX_REGISTRY_ENTRIES));
+    }
+    let mut entries = Vec::with_capacity(count.to::<usize>());
+    for index in 0..count.to::<u64>() {
+        entries.push(contract.getDex(U256::from(index)).call().await?.entry.into());
+    }
+    Ok(entries)
+}
+
+/// Сливает записи реестра с локальной конфигурацией (см. описание модуля)
+pub fn merge(local: &[DexConfig], entries: &[RegistryEntry], input_tokens: &[TokenId]) -> RegistryMerge {
+    let mut dexes = local.to_vec();
+    let mut added = Vec::new();
+    let mut warnings = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let skip = |reason| RegistryWarning::Skipped { index, name: entry.name.clone(), reason };
+        if entry.factory.is_zero() {
+            warnings.push(skip("нет адреса Factory"));
+            continue;
+        }
+        let matching = dexes.iter().position(|dex| {
+            dex.id.0.eq_ignore_ascii_case(&entry.name) || dex.source == DexSource::Factory(entry.factory)
+        });
+        match matching {
+            Some(position) if position >= local.len() => warnings.push(skip("DEX уже добавлен из реестра")),
+            Some(position) => warnings.extend(conflicts(&dexes[position], entry)),
+            None => {
+                let id = DexId::from_name(&entry.name).unwrap_or_else(|| DexId(Box::leak(entry.name.clone().into_boxed_str())));
+                dexes.push(DexConfig {
+                    id,
+                    source: DexSource::Factory(entry.factory),
+                    input_tokens: input_tokens.to_vec(),
+                    protocol_fee_share: UNISWAP_V2_PROTOCOL_FEE_SHARE,
+                    fee_bps: entry.fee_bps.unwrap_or(DEFAULT_FEE_BPS),
+                    pair_init_code_hash: entry.pair_init_code_hash,
+                });
+                added.push(id);
+            }
+        }
+    }
+    RegistryMerge { dexes, added, warnings }
+}
+
+/// Поля записи, которые расходятся с локальным DEX (не заданные в реестре не сравниваются)
+fn conflicts(local: &DexConfig, entry: &RegistryEntry) -> Vec<RegistryWarning> {
+    let dex = local.id;
+    let conflict = |field, local: String, registry: String| RegistryWarning::Conflict { dex, field, local, registry };
+    let mut warnings = Vec::new();
+    if local.source != DexSource::Factory(entry.factory) {
+        warnings.push(conflict("источник", format!("{:?}", local.source), format!("Factory({:?})", entry.factory)));
+    }
+    if let Some(fee_bps) = entry.fee_bps.filter(|&fee_bps| fee_bps != local.fee_bps) {
+        warnings.push(conflict("комиссия", format!("{} bps", local.fee_bps), format!("{} bps", fee_bps)));
+    }
+    if let Some(hash) = entry.pair_init_code_hash.filter(|&hash| Some(hash) != local.pair_init_code_hash) {
+        let local_hash = local.pair_init_code_hash.map_or_else(|| "не задан".to_string(), |hash| hash.to_string());
+        warnings.push(conflict("хэш init code", local_hash, hash.to_string()));
+    }
+    warnings
+}
+
+/// Список DEX запуска: локальная конфигурация, дополненная реестром
+///
+/// Реестр берется из кэша `cache_path`, если тот моложе `ttl_secs`, иначе
+/// читается из сети и кэш перезаписывается. Ошибка чтения реестра не
+/// прерывает запуск: используется локальная конфигурация.
+pub async fn bootstrap(
+    provider: Arc<RpcProvider>,
+    registry: Address,
+    cache_path: &Path,
+    ttl_secs: u64,
+    now: u64,
+    local: &[DexConfig],
+    input_tokens: &[TokenId],
+) -> (Vec<DexConfig>, RegistrySource) {
+    let cached = match RegistryCache::load(cache_path) {
+        Ok(cached) => cached,
+        Err(e) => {
+            log!("Кэш реестра {} не прочитан, реестр будет запрошен заново: {}", cache_path.display(), e);
+            None
+        }
+    };
+    let (entries, source) = match cached.filter(|cache| cache.is_fresh(registry, now, ttl_secs)) {
+        Some(cache) => (cache.entries, RegistrySource::Cache),
+        None => match read_registry(provider, registry).await {
+            Ok(entries) => {
+                let cache = RegistryCache { registry, fetched_at: now, entries };
+                if let Err(e) = cache.save(cache_path) {
+                    log!("Не удалось сохранить кэш реестра {}: {}", cache_path.display(), e);
+                }
+                (cache.entries, RegistrySource::Chain)
+            }
+            Err(e) => {
+                log!("⚠ Реестр DEX {:?} недоступен, используется локальная конфигурация: {}", registry, e);
+                return (local.to_vec(), RegistrySource::LocalFallback);
+            }
+        },
+    };
+
+    let merged = merge(local, &entries, input_tokens);
+    for warning in &merged.warnings {
+        log!("⚠ Реестр DEX: {}", warning);
+    }
+    if !merged.added.is_empty() {
+        log!("Из реестра добавлены DEX: {}", merged.added.iter().map(|dex| dex.0).collect::<Vec<_>>().join(", "));
+    }
+    (merged.dexes, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{default_dexes, SUSHISWAP_V2_FACTORY};
+    use crate::mock_rpc::{call_input, call_selector, call_target, encode_word, MockRpc};
+    use alloy::primitives::address;
+    use alloy::sol_types::SolCall;
+
+    const REGISTRY: Address = address!("00000000000000000000000000000000000f1a90");
+    const DFYN_FACTORY: Address = address!("E7Fb3e833eFE5F9c441105EB65Ef8b261266423B");
+
+    /// Реестр с двумя DEX: Sushiswap с чужой Factory и комиссией (конфликт) и новый Dfyn
+    fn registry_entries() -> Vec<IDexRegistry::DexEntry> {
+        vec![
+            IDexRegistry::DexEntry {
+                name: "SushiSwap".to_string(),
+                factory: Address::repeat_byte(0x5a),
+                router: Address::repeat_byte(0x5b),
+                pairInitCodeHash: B256::ZERO,
+                feeBps: 25,
+            },
+            IDexRegistry::DexEntry {
+                name: "Dfyn".to_string(),
+                factory: DFYN_FACTORY,
+                router: Address::repeat_byte(0xdf),
+                pairInitCodeHash: B256::repeat_byte(0xd1),
+                feeBps: 0,
+            },
+        ]
+    }
+
+    async fn mock_registry() -> MockRpc {
+        MockRpc::start(|method, params| {
+            assert_eq!(method, "eth_call");
+            assert_eq!(call_target(params), Some(REGISTRY));
+            let entries = registry_entries();
+            match call_selector(params) {
+                Some(IDexRegistry::getDexCountCall::SELECTOR) => Ok(encode_word(U256::from(entries.len()))),
+                Some(IDexRegistry::getDexCall::SELECTOR) => {
+                    let index = IDexRegistry::getDexCall::abi_decode(&call_input(params).unwrap(), true).unwrap().index;
+                    let entry = entries[index.to::<usize>()].clone();
+                    Ok(serde_json::json!(alloy::hex::encode_prefixed(IDexRegistry::getDexCall::abi_encode_returns(&(entry,)))))
+                }
+                selector => Err(format!("неизвестный селектор {:?}", selector)),
+            }
+        }).await
+    }
+
+    fn cache_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("swap_aggregator_registry_{}_{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn registry_dexes_merge_with_local_config_winning_conflicts() {
+        let rpc = mock_registry().await;
+        let entries = read_registry(rpc.provider.clone(), REGISTRY).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].fee_bps, entries[0].pair_init_code_hash), (Some(25), None));
+
+        let local = default_dexes();
+        let inputs = [TokenId::USDC, TokenId::USDC_E];
+        let merged = merge(&local, &entries, &inputs);
+
+        // Sushiswap остается локальным, оба расхождения в предупреждениях
+        assert_eq!(&merged.dexes[..local.len()], &local[..]);
+        let sushiswap = merged.dexes.iter().find(|dex| dex.id == DexId::SUSHISWAP).unwrap();
+        assert_eq!((sushiswap.source, sushiswap.fee_bps), (DexSource::Factory(SUSHISWAP_V2_FACTORY), DEFAULT_FEE_BPS));
+        let fields: Vec<&str> = merged.warnings.iter().map(|warning| match warning {
+            RegistryWarning::Conflict { dex, field, .. } if *dex == DexId::SUSHISWAP => *field,
+            other => panic!("{}", other),
+        }).collect();
+        assert_eq!(fields, ["источник", "комиссия"]);
+        assert!(merged.warnings[1].to_string().contains("комиссия в реестре 25 bps, локально 30 bps"));
+
+        // Dfyn добавлен как Factory-DEX с входными токенами профиля
+        let [dfyn] = &merged.added[..] else { panic!("{:?}", merged.added) };
+        assert_eq!(dfyn.0, "Dfyn");
+        let config = merged.dexes.last().unwrap();
+        assert_eq!(config.source, DexSource::Factory(DFYN_FACTORY));
+        assert_eq!((config.fee_bps, config.pair_init_code_hash), (DEFAULT_FEE_BPS, Some(B256::repeat_byte(0xd1))));
+        assert_eq!(config.input_tokens, inputs);
+
+        // Повтор Factory и запись без Factory пропускаются
+        let mut duplicated = entries.clone();
+        duplicated.push(RegistryEntry { name: "Dfyn v1".to_string(), ..entries[1].clone() });
+        duplicated.push(RegistryEntry { name: "Broken".to_string(), factory: Address::ZERO, ..entries[1].clone() });
+        let merged = merge(&local, &duplicated, &inputs);
+        assert_eq!(merged.dexes.len(), local.len() + 1);
+        assert!(matches!(&merged.warnings[2..], [RegistryWarning::Skipped { index: 2, .. }, RegistryWarning::Skipped { index: 3, .. }]));
+    }
+
+    #[tokio::test]
+    async fn bootstrap_caches_with_ttl_and_falls_back_to_local_config() {
+        let rpc = mock_registry().await;
+        let path = cache_path("ttl");
+        let _ = std::fs::remove_file(&path);
+        let local = default_dexes();
+        let inputs = [TokenId::USDC];
+        let run = |now| bootstrap(rpc.provider.clone(), REGISTRY, &path, REGISTRY_CACHE_TTL_SECS, now, &local, &inputs);
+
+        let (dexes, source) = run(1_000).await;
+        assert_eq!((dexes.len(), source), (local.len() + 1, RegistrySource::Chain));
+        let requests = rpc.request_count();
+
+        // В пределах TTL сеть не нужна
+        let (cached, source) = run(1_000 + REGISTRY_CACHE_TTL_SECS - 1).await;
+        assert_eq!((cached, source), (dexes.clone(), RegistrySource::Cache));
+        assert_eq!(rpc.request_count(), requests);
+
+        // После TTL реестр читается заново
+        let (_, source) = run(1_000 + REGISTRY_CACHE_TTL_SECS).await;
+        assert_eq!(source, RegistrySource::Chain);
+        assert!(rpc.request_count() > requests);
+        std::fs::remove_file(&path).unwrap();
+
+        // Недоступный реестр: только локальная конфигурация, кэш не создается
+        let broken = MockRpc::start(|_, _| Err("registry is down".to_string())).await;
+        let path = cache_path("down");
+        let (dexes, source) = bootstrap(broken.provider.clone(), REGISTRY, &path, REGISTRY_CACHE_TTL_SECS, 0, &local, &inputs).await;
+        assert_eq!((dexes, source), (local.clone(), RegistrySource::LocalFallback));
+        assert!(!path.exists());
+    }
+}
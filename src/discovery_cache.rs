@@ -0,0 +1,258 @@
+// src/discovery_cache.rs
+//! Кэш результатов discovery между запусками
+//!
+//! Запоминает, есть ли пул для (DEX, пара токенов, уровень комиссии).
+//! Найденный адрес не меняется (пулы создаются через CREATE2), поэтому
+//! положительные записи хранятся без срока. Отрицательные ("пула нет на
+//! блоке N") живут `NEGATIVE_PROBE_TTL_BLOCKS` блоков; чтобы новые пулы
+//! находились раньше, каждый запуск перепроверяет небольшую часть
+//! отрицательных записей по кругу (бюджет исследования).
+use alloy::primitives::Address;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Что ищется: пул DEX для пары токенов с уровнем комиссии
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ProbeKey {
+    pub dex: String,
+    pub token_a: Address,
+    pub token_b: Address,
+    pub fee: u32,
+}
+
+/// Результат проверки: адрес пула или его отсутствие на блоке
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProbeResult {
+    Found(Address),
+    Missing { checked_block: u64 },
+}
+
+/// Статистика обращений к кэшу за запуск
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiscoveryStats {
+    /// Пул взят из кэша
+    pub hits: usize,
+    /// Отсутствие пула взято из кэша, запрос не делался
+    pub negative_hits: usize,
+    /// Записи не было или она устарела - запрос к сети
+    pub misses: usize,
+    /// Отрицательные записи, перепроверенные в счет бюджета исследования
+    pub rechecked: usize,
+}
+
+/// Файл кэша
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    cursor: Option<ProbeKey>,
+    entries: Vec<(ProbeKey, ProbeResult)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryCache {
+    entries: BTreeMap<ProbeKey, ProbeResult>,
+    negative_ttl_blocks: u64,
+    /// Последняя перепроверенная отрицательная запись (начало следующего круга)
+    cursor: Option<ProbeKey>,
+    /// Отрицательные записи, выбранные для перепроверки в текущем запуске
+    recheck: BTreeSet<ProbeKey>,
+    stats: DiscoveryStats,
+}
+
+impl DiscoveryCache {
+    pub fn new(negative_ttl_blocks: u64) -> Self {
+        DiscoveryCache { negative_ttl_blocks, ..Default::default() }
+    }
+
+    /// Загружает кэш из файла; отсутствующий файл дает пустой кэш
+    pub fn load(path: &Path, negative_ttl_blocks: u64) -> Result<Self> {
+        let mut cache = DiscoveryCache::new(negative_ttl_blocks);
+        if !path.exists() {
+            return Ok(cache);
+        }
+        let file: CacheFile = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        cache.entries = file.entries.into_iter().collect();
+        cache.cursor = file.cursor;
+        Ok(cache)
+    }
+
+    /// Сохраняет кэш в файл
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = CacheFile {
+            cursor: self.cursor.clone(),
+            entries: self.entries.iter().map(|(key, result)| (key.clone(), *result)).collect(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// Начинает запуск: выбирает до `budget` действующих отрицательных записей
+    /// для перепроверки, продолжая круг с места, где остановился прошлый запуск
+    pub fn begin_run(&mut self, block: u64, budget: usize) {
+        self.stats = DiscoveryStats::default();
+        let negatives: Vec<ProbeKey> = self
+            .entries
+            .iter()
+            .filter(|(_, result)| !self.is_expired(result, block) && matches!(result, ProbeResult::Missing { .. }))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let start = self
+            .cursor
+            .as_ref()
+            .map_or(0, |cursor| negatives.iter().position(|key| key > cursor).unwrap_or(0));
+        self.recheck = negatives.iter().cycle().skip(start).take(budget.min(negatives.len())).cloned().collect();
+        if let Some(last) = negatives.iter().cycle().skip(start).take(budget.min(negatives.len())).last() {
+            self.cursor = Some(last.clone());
+        }
+    }
+
+    /// Результат из кэша или `None`, если пул нужно запросить в сети
+    pub fn lookup(&mut self, key: &ProbeKey, block: u64) -> Option<Option<Address>> {
+        if self.recheck.contains(key) {
+            self.stats.rechecked += 1;
+            return None;
+        }
+        match self.entries.get(key) {
+            Some(ProbeResult::Found(address)) => {
+                self.stats.hits += 1;
+                Some(Some(*address))
+            }
+            Some(result @ ProbeResult::Missing { .. }) if !self.is_expired(result, block) => {
+                self.stats.negative_hits += 1;
+                Some(None)
+            }
+            _ => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Запоминает результат запроса к сети
+    pub fn record(&mut self, key: ProbeKey, pool: Option<Address>, block: u64) {
+        let result = match pool {
+            Some(address) => ProbeResult::Found(address),
+            None => ProbeResult::Missing { checked_block: block },
+        };
+        self.entries.insert(key, result);
+    }
+
+    pub fn stats(&self) -> DiscoveryStats {
+        self.stats
+    }
+
+    fn is_expired(&self, result: &ProbeResult, block: u64) -> bool {
+        match result {
+            ProbeResult::Found(_) => false,
+            ProbeResult::Missing { checked_block } => block.saturating_sub(*checked_block) >= self.negative_ttl_blocks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(fee: u32) -> ProbeKey {
+        ProbeKey { dex: "Uniswap V3".to_string(), token_a: Address::repeat_byte(0x01), token_b: Address::repeat_byte(0x02), fee }
+    }
+
+    /// Один запуск discovery над `fees`: запрос к "сети" через `exists`
+    fn run(cache: &mut DiscoveryCache, fees: &[u32], block: u64, budget: usize, exists: impl Fn(u32) -> bool) -> (Vec<u32>, usize) {
+        cache.begin_run(block, budget);
+        let mut found = Vec::new();
+        let mut probes = 0;
+        for &fee in fees {
+            let pool = match cache.lookup(&key(fee), block) {
+                Some(pool) => pool,
+                None => {
+                    probes += 1;
+                    let pool = exists(fee).then(|| Address::repeat_byte(fee as u8));
+                    cache.record(key(fee), pool, block);
+                    pool
+                }
+            };
+            if pool.is_some() {
+                found.push(fee);
+            }
+        }
+        (found, probes)
+    }
+
+    #[test]
+    fn negative_results_are_reused_until_ttl() {
+        let fees = [1, 2, 3, 4, 5, 6];
+        let mut cache = DiscoveryCache::new(1_000);
+        let (found, probes) = run(&mut cache, &fees, 100, 0, |fee| fee == 1);
+        assert_eq!((found, probes), (vec![1], 6));
+        assert_eq!(cache.stats().misses, 6);
+
+        // Без бюджета исследования второй запуск не делает запросов
+        let (found, probes) = run(&mut cache, &fees, 200, 0, |fee| fee == 1);
+        assert_eq!((found, probes), (vec![1], 0));
+        assert_eq!(cache.stats(), DiscoveryStats { hits: 1, negative_hits: 5, misses: 0, rechecked: 0 });
+
+        // После TTL отрицательные записи запрашиваются заново
+        let (_, probes) = run(&mut cache, &fees, 1_100, 0, |fee| fee == 1);
+        assert_eq!(probes, 5);
+    }
+
+    #[test]
+    fn new_pool_is_found_within_rotation_window() {
+        let fees = [1, 2, 3, 4, 5, 6];
+        let mut cache = DiscoveryCache::new(1_000_000);
+        run(&mut cache, &fees, 100, 2, |fee| fee == 1);
+
+        // Пул с комиссией 6 появился; 5 отрицательных записей при бюджете 2 - круг за 3 запуска
+        let mut discovered_at = None;
+        for run_index in 1..=3 {
+            let (found, probes) = run(&mut cache, &fees, 100 + run_index, 2, |fee| fee == 1 || fee == 6);
+            assert_eq!(probes, 2);
+            assert_eq!(cache.stats().rechecked, 2);
+            if found.contains(&6) {
+                discovered_at = Some(run_index);
+                break;
+            }
+        }
+        assert!(discovered_at.is_some());
+
+        // Дальше пул берется из кэша
+        let (found, probes) = run(&mut cache, &fees, 200, 2, |fee| fee == 1 || fee == 6);
+        assert_eq!(found, vec![1, 6]);
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(probes, 2);
+    }
+
+    #[test]
+    fn rotation_visits_every_negative_entry() {
+        let fees = [1, 2, 3, 4, 5];
+        let mut cache = DiscoveryCache::new(1_000_000);
+        run(&mut cache, &fees, 1, 0, |_| false);
+
+        let mut visited = BTreeSet::new();
+        for block in 2..=4 {
+            cache.begin_run(block, 2);
+            visited.extend(cache.recheck.iter().map(|key| key.fee));
+        }
+        assert_eq!(visited, BTreeSet::from([1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn cache_survives_save_and_load() {
+        let mut cache = DiscoveryCache::new(50);
+        cache.record(key(500), Some(Address::repeat_byte(0x05)), 10);
+        cache.record(key(3_000), None, 10);
+        cache.begin_run(11, 1);
+
+        let path = std::env::temp_dir().join(format!("discovery_cache_{}.json", std::process::id()));
+        cache.save(&path).unwrap();
+        let mut loaded = DiscoveryCache::load(&path, 50).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.lookup(&key(500), 12), Some(Some(Address::repeat_byte(0x05))));
+        assert_eq!(loaded.lookup(&key(3_000), 12), Some(None));
+        assert_eq!(loaded.cursor, cache.cursor);
+        assert!(DiscoveryCache::load(Path::new("/nonexistent/cache.json"), 50).unwrap().entries.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+// src/explain.rs
+//! Разбор решения солвера для одного чанка (swap_aggregator explain-chunk)
+//!
+//! Заново решает записанный манифест жадным алгоритмом без сети и печатает
+//! все, что повлияло на выбор пула для чанка: пропущенные пулы с причинами,
+//! котировки с резервами и уже отданным пулу входом, применение правила
+//! "при равном выходе остается первый пул", закрепление и итоговую запись
+//! маршрута. Тот же разбор сериализуется в JSON.
+use crate::config::ConfigContext;
+use crate::regress::{decimal, route_hash, Manifest};
+use crate::solver::{trace_chunk, ChunkRoute, ChunkTrace, QuoteTrace};
+use alloy::primitives::{Address, U256};
+use eyre::Result;
+use serde::Serialize;
+use std::fmt::Write as _;
+
+/// Котировка пула в разборе
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExplainedQuote {
+    pub pool: String,
+    pub pool_address: Address,
+    pub token_in: String,
+    pub token_out: String,
+    #[serde(with = "decimal")]
+    pub reserve_in: U256,
+    #[serde(with = "decimal")]
+    pub reserve_out: U256,
+    #[serde(with = "decimal")]
+    pub allocated_in: U256,
+    #[serde(with = "decimal")]
+    pub amount_out: U256,
+    pub price_impact: f64,
+}
+
+/// Пул, пропущенный для чанка
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExplainedSkip {
+    pub pool: String,
+    pub reason: String,
+}
+
+/// Итоговая запись маршрута для чанка
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExplainedRoute {
+    pub pool: Option<String>,
+    pub pool_address: Option<Address>,
+    #[serde(with = "decimal")]
+    pub amount_in: U256,
+    #[serde(with = "decimal")]
+    pub amount_out: U256,
+    #[serde(with = "decimal")]
+    pub min_amount_out: U256,
+    pub price_impact: f64,
+    pub execution_price: Option<f64>,
+    pub committed: bool,
+}
+
+/// Разбор решения для одного чанка
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChunkExplanation {
+    pub manifest: String,
+    pub chunk_index: u64,
+    pub num_chunks: u64,
+    /// Прогон воспроизводит записанный маршрут (хэш совпадает с манифестом)
+    pub route_hash_matches: bool,
+    #[serde(with = "decimal")]
+    pub amount_in: U256,
+    pub skipped: Vec<ExplainedSkip>,
+    pub quotes: Vec<ExplainedQuote>,
+    pub best_pool: Option<String>,
+    pub tied_pools: Vec<String>,
+    pub committed_pool: Option<String>,
+    pub route: ExplainedRoute,
+}
+
+/// Решает манифест и разбирает решение для чанка `chunk_index` (с 1)
+pub fn explain_chunk(manifest: &Manifest, chunk_index: u64) -> Result<ChunkExplanation> {
+    let (result, trace) = trace_chunk(manifest.pool_states(), &ConfigContext::default(), &manifest.solver_config(), chunk_index)?;
+    let route_hash_matches = route_hash(&result) == manifest.expected.route_hash;
+    let num_chunks = result.chunk_routes.len() as u64;
+    let route = &result.chunk_routes[(chunk_index - 1) as usize];
+    Ok(build(manifest, trace, route, num_chunks, route_hash_matches))
+}
+
+fn build(manifest: &Manifest, trace: ChunkTrace, route: &ChunkRoute, num_chunks: u64, route_hash_matches: bool) -> ChunkExplanation {
+    let quote = |quote: QuoteTrace| ExplainedQuote {
+        pool: quote.pool_name,
+        pool_address: quote.pool_address,
+        token_in: quote.token_in.to_string(),
+        token_out: quote.token_out.to_string(),
+        reserve_in: quote.reserve_in,
+        reserve_out: quote.reserve_out,
+        allocated_in: quote.allocated_in,
+        amount_out: quote.output,
+        price_impact: quote.price_impact,
+    };
+    ChunkExplanation {
+        manifest: manifest.name.clone(),
+        chunk_index: trace.chunk_index,
+        num_chunks,
+        route_hash_matches,
+        amount_in: trace.amount_in,
+        skipped: trace
+            .skipped_pools
+            .into_iter()
+            .map(|skip| ExplainedSkip { pool: skip.pool_name, reason: skip.reason.to_string() })
+            .collect(),
+        quotes: trace.quotes.into_iter().map(quote).collect(),
+        best_pool: trace.best_pool,
+        tied_pools: trace.tied_pools,
+        committed_pool: trace.committed_pool,
+        route: ExplainedRoute {
+            pool: route.pool_address.map(|_| route.best_pool_name.clone()),
+            pool_address: route.pool_address,
+            amount_in: route.amount_in,
+            amount_out: route.amount_out,
+            min_amount_out: route.min_amount_out,
+            price_impact: route.price_impact,
+            execution_price: route.execution_price,
+            committed: route.committed,
+        },
+    }
+}
+
+/// Человекочитаемый разбор
+pub fn format_explanation(explanation: &ChunkExplanation) -> String {
+    let mut out = format!("Чанк {} из {} ({}), вход {} raw\n",
+        explanation.chunk_index, explanation.num_chunks, explanation.manifest, explanation.amount_in);
+    if !explanation.route_hash_matches {
+        out.push_str("  Внимание: маршрут отличается от записанного в манифесте\n");
+    }
+
+    out.push_str("Пропущенные пулы:\n");
+    if explanation.skipped.is_empty() {
+        out.push_str("  нет\n");
+    }
+    for skip in &explanation.skipped {
+        let _ = writeln!(out, "  {}: {}", skip.pool, skip.reason);
+    }
+
+    out.push_str("Котировки (резервы до чанка):\n");
+    for quote in &explanation.quotes {
+        let _ = writeln!(out, "  {} ({:?}): {} -> {} = {} raw, резервы {}/{}, уже отдано {} raw, impact {:.4}%",
+            quote.pool, quote.pool_address, quote.token_in, quote.token_out, quote.amount_out,
+            quote.reserve_in, quote.reserve_out, quote.allocated_in, quote.price_impact * 100.0);
+    }
+
+    match &explanation.best_pool {
+        Some(best) => {
+            let _ = writeln!(out, "Лучший выход: {}", best);
+        }
+        None => out.push_str("Лучший выход: ни один пул не дал котировки\n"),
+    }
+    if !explanation.tied_pools.is_empty() {
+        let _ = writeln!(out, "Равный выход у {}: остается первый пул", explanation.tied_pools.join(", "));
+    }
+    if let Some(committed) = &explanation.committed_pool {
+        let _ = writeln!(out, "Закрепление: выбран {} вместо лучшего", committed);
+    }
+
+    let route = &explanation.route;
+    let _ = writeln!(out, "Запись маршрута: {} вход {} -> выход {} raw (min {}), impact {:.4}%, цена {}{}",
+        route.pool.as_deref().unwrap_or("-"),
+        route.amount_in,
+        route.amount_out,
+        route.min_amount_out,
+        route.price_impact * 100.0,
+        route.execution_price.map_or_else(|| "-".to_string(), |price| format!("{:.2}", price)),
+        if route.committed { " [закреплен]" } else { "" });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regress::replay;
+
+    fn fixture() -> Manifest {
+        serde_json::from_str(include_str!("../regress/two_pools_greedy.json")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn explained_decision_matches_recorded_run() {
+        let manifest = fixture();
+        let recorded = replay(&manifest).await.unwrap();
+        assert_eq!(route_hash(&recorded), manifest.expected.route_hash);
+
+        for chunk_index in 1..=manifest.num_chunks {
+            let explanation = explain_chunk(&manifest, chunk_index).unwrap();
+            let route = &recorded.chunk_routes[(chunk_index - 1) as usize];
+            assert!(explanation.route_hash_matches);
+            assert_eq!(explanation.route.pool.as_deref(), Some(route.best_pool_name.as_str()));
+            assert_eq!((explanation.route.amount_in, explanation.route.amount_out), (route.amount_in, route.amount_out));
+            assert_eq!(explanation.route.min_amount_out, route.min_amount_out);
+
+            // Без закрепления выбран пул с лучшей котировкой, и его котировка равна выходу маршрута
+            assert_eq!(explanation.best_pool, explanation.route.pool);
+            let best = explanation.quotes.iter().max_by_key(|quote| quote.amount_out).unwrap();
+            assert_eq!(best.amount_out, route.amount_out);
+            assert_eq!(explanation.quotes.len(), manifest.pools.len());
+        }
+    }
+
+    #[test]
+    fn later_chunks_see_earlier_allocations() {
+        let manifest = fixture();
+        let first = explain_chunk(&manifest, 1).unwrap();
+        let last = explain_chunk(&manifest, manifest.num_chunks).unwrap();
+        assert!(first.quotes.iter().all(|quote| quote.allocated_in.is_zero()));
+        let allocated: U256 = last.quotes.iter().map(|quote| quote.allocated_in).sum();
+        assert_eq!(allocated, manifest.total_amount_in - last.amount_in);
+
+        let json: serde_json::Value = serde_json::to_value(&last).unwrap();
+        assert_eq!(json["chunk_index"], manifest.num_chunks);
+        assert_eq!(json["route"]["amount_out"], last.route.amount_out.to_string());
+        assert!(format_explanation(&last).contains(&format!("Чанк {} из {}", manifest.num_chunks, manifest.num_chunks)));
+    }
+
+    #[test]
+    fn chunk_outside_route_and_analytic_strategies_are_errors() {
+        let manifest = fixture();
+        assert!(explain_chunk(&manifest, manifest.num_chunks + 1).is_err());
+        assert!(explain_chunk(&manifest, 0).is_err());
+        let analytic: Manifest = serde_json::from_str(include_str!("../regress/three_pools_marginal.json")).unwrap();
+        assert!(explain_chunk(&analytic, 1).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+// src/impact.rs
+//! Модели price impact
+//!
+//! Price impact - потеря сделки относительно эталонной цены:
+//! `1 - цена исполнения / эталонная цена`. Модель определяет, что считать
+//! эталоном: спот-цену пула до сделки (`SpotReferenceModel`, по умолчанию)
+//! или внешнюю цену оракула (`OracleReferenceModel`). Все потребители impact
+//! (impact чанков и сплита в `SolverResult`, трасса решения, таблица глубины
+//! ликвидности) получают его через `ImpactModel`, поэтому смена модели в
+//! `SolverConfig::impact_model` меняет их согласованно.
+use crate::amm::AmmPool;
+use crate::config::{parse_units, TokenId, UnitsError};
+use crate::math::{self, u256_to_f64, BPS_DENOMINATOR};
+use alloy::primitives::U256;
+
+/// Способ оценки price impact сделки в пуле
+///
+/// Цены - в raw units: выходной токен за единицу входного без учета decimals,
+/// как `выход / вход` котировки.
+pub trait ImpactModel {
+    /// Эталонная цена для сделки в пуле из `token_in`; `None` - цена неизвестна
+    fn reference_price(&self, pool: &dyn AmmPool, token_in: TokenId) -> Option<f64>;
+
+    /// Эталонная цена всего сплита
+    ///
+    /// `best_spot` - лучшая спот-цена среди пулов до сделки (raw units),
+    /// `decimals_in` / `decimals_out` - decimals входного и выходного токенов.
+    fn split_reference_price(&self, best_spot: f64, decimals_in: u8, decimals_out: u8) -> Option<f64>;
+
+    /// Impact сделки от 0.0 до 1.0 (1.0 - пул не котирует сумму или эталон неизвестен)
+    ///
+    /// Исполнение лучше эталона дает 0.0, а не отрицательный impact.
+    fn price_impact(&self, pool: &dyn AmmPool, amount_in: U256, token_in: TokenId) -> f64 {
+        if amount_in == U256::ZERO {
+            return 0.0;
+        }
+        let Some(reference) = self.reference_price(pool, token_in).filter(|price| *price > 0.0) else {
+            return 1.0;
+        };
+        let output = pool.quote(amount_in, token_in).unwrap_or(U256::ZERO);
+        (1.0 - u256_to_f64(output) / u256_to_f64(amount_in) / reference).clamp(0.0, 1.0)
+    }
+
+    /// Максимальный вход, при котором impact не превышает `max_impact_bps`
+    ///
+    /// По умолчанию - двоичный поиск по `price_impact`: impact растет с входом,
+    /// поэтому граница единственна. `U256::MAX` для бюджета от 100%, 0 - если
+    /// бюджет превышен уже на 1 raw unit.
+    fn max_input_for_impact(&self, pool: &dyn AmmPool, max_impact_bps: u32, token_in: TokenId) -> U256 {
+        if max_impact_bps >= BPS_DENOMINATOR {
+            return U256::MAX;
+        }
+        let budget = max_impact_bps as f64 / BPS_DENOMINATOR as f64;
+        let within = |amount: U256| self.price_impact(pool, amount, token_in) <= budget;
+
+        // Удваиваем верхнюю границу, начиная с резерва, пока impact в бюджете
+        let mut low = U256::ZERO;
+        let mut high = pool.reserves(token_in).0.max(U256::from(1u64));
+        while within(high) {
+            low = high;
+            match high.checked_mul(U256::from(2u64)) {
+                Some(next) => high = next,
+                None => return U256::MAX,
+            }
+        }
+        while high - low > U256::from(1u64) {
+            let middle = low + (high - low) / U256::from(2u64);
+            if within(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+/// Impact относительно спот-цены пула до сделки (комиссия входит в impact)
+///
+/// Для constant product пулов совпадает с `math::price_impact` и
+/// `math::max_input_for_impact`; для остальных - с `AmmPool::price_impact`,
+/// а глубина ликвидности не определена (0).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpotReferenceModel;
+
+impl ImpactModel for SpotReferenceModel {
+    fn reference_price(&self, pool: &dyn AmmPool, token_in: TokenId) -> Option<f64> {
+        let token_out = pool.other_token(token_in)?;
+        let price = pool.spot_price(token_in) * decimals_scale(pool.decimals(token_in), pool.decimals(token_out));
+        (price > 0.0).then_some(price)
+    }
+
+    fn split_reference_price(&self, best_spot: f64, _decimals_in: u8, _decimals_out: u8) -> Option<f64> {
+        (best_spot > 0.0).then_some(best_spot)
+    }
+
+    fn price_impact(&self, pool: &dyn AmmPool, amount_in: U256, token_in: TokenId) -> f64 {
+        pool.price_impact(amount_in, token_in)
+    }
+
+    fn max_input_for_impact(&self, pool: &dyn AmmPool, max_impact_bps: u32, token_in: TokenId) -> U256 {
+        let Some(fee_bps) = pool.constant_product_fee_bps() else {
+            return U256::ZERO;
+        };
+        let (reserve_in, reserve_out) = pool.reserves(token_in);
+        math::max_input_for_impact(reserve_in, reserve_out, max_impact_bps, fee_bps)
+    }
+}
+
+/// Impact относительно внешней цены (оракул, TWAP, цена CEX)
+///
+/// Цена задается точно как дробь `numerator / denominator` выходного токена
+/// за один входной с учетом decimals. Пул, торгующий хуже оракула, получает
+/// impact даже на бесконечно малой сделке; торгующий лучше - нулевой impact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OracleReferenceModel {
+    pub numerator: U256,
+    pub denominator: U256,
+}
+
+impl OracleReferenceModel {
+    /// Знаков после точки в цене оракула из строки
+    pub const PRICE_DECIMALS: u8 = 18;
+
+    /// Цена из десятичной строки ("0.0004" WETH за USDC)
+    pub fn from_decimal(price: &str) -> Result<Self, UnitsError> {
+        Ok(OracleReferenceModel {
+            numerator: parse_units(price, Self::PRICE_DECIMALS)?,
+            denominator: U256::from(10u64).pow(U256::from(Self::PRICE_DECIMALS)),
+        })
+    }
+
+    /// Цена оракула в raw units для токенов с заданными decimals
+    fn raw_price(&self, decimals_in: u8, decimals_out: u8) -> Option<f64> {
+        if self.denominator == U256::ZERO {
+            return None;
+        }
+        let price = u256_to_f64(self.numerator) / u256_to_f64(self.denominator) * decimals_scale(decimals_in, decimals_out);
+        (price > 0.0).then_some(price)
+    }
+}
+
+impl ImpactModel for OracleReferenceModel {
+    fn reference_price(&self, pool: &dyn AmmPool, token_in: TokenId) -> Option<f64> {
+        let token_out = pool.other_token(token_in)?;
+        self.raw_price(pool.decimals(token_in), pool.decimals(token_out))
+    }
+
+    fn split_reference_price(&self, _best_spot: f64, decimals_in: u8, decimals_out: u8) -> Option<f64> {
+        self.raw_price(decimals_in, decimals_out)
+    }
+}
+
+/// Встроенная модель impact для `SolverConfig`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImpactModelChoice {
+    #[default]
+    SpotReference,
+    OracleReference(OracleReferenceModel),
+}
+
+impl ImpactModelChoice {
+    /// Выбранная модель
+    pub fn model(&self) -> &dyn ImpactModel {
+        match self {
+            ImpactModelChoice::SpotReference => &SpotReferenceModel,
+            ImpactModelChoice::OracleReference(model) => model,
+        }
+    }
+}
+
+/// Множитель перевода цены с учетом decimals в raw units: `10^(decimals_out - decimals_in)`
+fn decimals_scale(decimals_in: u8, decimals_out: u8) -> f64 {
+    10f64.powi(i32::from(decimals_out) - i32::from(decimals_in))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::{test_pool, PoolState};
+
+    /// 2 000 000 USDC / 1000 WETH (0.0005 WETH за USDC), комиссия 30 bps
+    fn scenario_pool() -> PoolState {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        test_pool(0x01, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(1_000u64) * weth)
+    }
+
+    const TRADE: u64 = 20_000_000_000; // 20 000 USDC
+
+    #[test]
+    fn spot_model_matches_pool_math() {
+        let pool = scenario_pool();
+        let model = SpotReferenceModel;
+        let amount = U256::from(TRADE);
+
+        // (30 * R + a * 9970) / (10000 * R + a * 9970) = 2.594e14 / 2.01994e16
+        let impact = model.price_impact(&pool, amount, TokenId::USDC);
+        assert!((impact - 0.012_841_965_602_938_701).abs() < 1e-12, "{}", impact);
+        assert_eq!(impact, pool.price_impact(amount, true));
+        assert_eq!(model.reference_price(&pool, TokenId::USDC), Some(5e8));
+        assert_eq!(model.max_input_for_impact(&pool, 100, TokenId::USDC), pool.max_input_for_impact(100, true));
+    }
+
+    #[test]
+    fn oracle_model_measures_against_external_price() {
+        let pool = scenario_pool();
+        let amount = U256::from(TRADE);
+        let spot_impact = SpotReferenceModel.price_impact(&pool, amount, TokenId::USDC);
+
+        // Оракул равен споту: то же значение с точностью f64
+        let at_spot = OracleReferenceModel::from_decimal("0.0005").unwrap();
+        assert!((at_spot.price_impact(&pool, amount, TokenId::USDC) - spot_impact).abs() < 1e-12);
+
+        // Оракул дороже пула на 20%: выход 9.871580...e18 за 2e10, 1 - 4.935790e8 / 6e8
+        let above = OracleReferenceModel::from_decimal("0.0006").unwrap();
+        let impact = above.price_impact(&pool, amount, TokenId::USDC);
+        assert!((impact - 0.177_368_304_669_115_6).abs() < 1e-9, "{}", impact);
+
+        // Пул лучше оракула: impact не отрицательный
+        let below = OracleReferenceModel::from_decimal("0.00049").unwrap();
+        assert_eq!(below.price_impact(&pool, amount, TokenId::USDC), 0.0);
+        assert_eq!(below.split_reference_price(5e8, 6, 18), Some(4.9e8));
+    }
+
+    #[test]
+    fn oracle_depth_is_tight_and_consistent_with_impact() {
+        let pool = scenario_pool();
+        let model = OracleReferenceModel::from_decimal("0.0005").unwrap();
+        let budget_bps = 100;
+        let budget = budget_bps as f64 / BPS_DENOMINATOR as f64;
+
+        let depth = model.max_input_for_impact(&pool, budget_bps, TokenId::USDC);
+        assert!(model.price_impact(&pool, depth, TokenId::USDC) <= budget);
+        assert!(model.price_impact(&pool, depth + U256::from(1u64), TokenId::USDC) > budget);
+
+        // Тот же эталон, что у спот-модели: граница почти совпадает с аналитической
+        let analytic = SpotReferenceModel.max_input_for_impact(&pool, budget_bps, TokenId::USDC);
+        assert!(depth.abs_diff(analytic) <= analytic / U256::from(1_000_000u64), "{} vs {}", depth, analytic);
+
+        // Оракул выше спота больше чем на бюджет: даже 1 raw unit за пределами
+        let far = OracleReferenceModel::from_decimal("0.0006").unwrap();
+        assert_eq!(far.max_input_for_impact(&pool, budget_bps, TokenId::USDC), U256::ZERO);
+        assert_eq!(far.max_input_for_impact(&pool, BPS_DENOMINATOR, TokenId::USDC), U256::MAX);
+    }
+}
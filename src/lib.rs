@@ -0,0 +1,43 @@
+pub mod amm;
+pub mod bench;
+pub mod breaker;
+pub mod batch;
+pub mod chain;
+pub mod chunk_guard;
+pub mod cli;
+pub mod compliance;
+pub mod config;
+pub mod convert;
+pub mod correlation;
+pub mod dex_registry;
+pub mod discovery_cache;
+pub mod explain;
+pub mod impact;
+pub mod market_snapshot;
+pub mod math;
+pub mod output;
+pub mod overrides;
+pub mod pool;
+pub mod pool_registry;
+pub mod prefetch;
+pub mod provider;
+pub mod refresher;
+pub mod regress;
+pub mod repl;
+pub mod reserve_recorder;
+pub mod route;
+pub mod solver;
+pub mod stable_pool;
+pub mod stats;
+pub mod twap;
+pub mod v3_pool;
+pub mod watch;
+
+#[cfg(test)]
+mod mock_chain;
+#[cfg(test)]
+mod mock_rpc;
+#[cfg(test)]
+mod whale_tests;
+#[cfg(test)]
+mod solver_offline_tests;
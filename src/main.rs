@@ -1,20 +1,176 @@
-mod config;
-mod math;
-mod pool;
-mod provider;
-mod solver;
-
 use std::env;
-use config::{USDC_ADDRESS, WETH_ADDRESS, TOTAL_USDC_DECIMAL, weth_to_decimal};
-use provider::{create_provider, get_all_pool_addresses};
-use solver::find_best_routes;
-use eyre::Result;
+use std::io::Write;
+use std::sync::Arc;
+use clap::Parser;
+use swap_aggregator::batch::{self, QuoteRequest};
+use swap_aggregator::chunk_guard::ChunkGuard;
+use swap_aggregator::cli::{Cli, Command, SnapshotCommand};
+use swap_aggregator::config::{
+    format_units, to_decimal, usdc_to_decimal, weth_to_decimal, ConfigContext, DexId, DisplayAmount, TokenId, UNISWAP_V3_FACTORY,
+    UNISWAP_V3_QUOTER_V2, USDC_DECIMALS, WETH_DECIMALS, NEGATIVE_PROBE_TTL_BLOCKS, TINY_POOL_DEPTH,
+};
+use swap_aggregator::convert::{self, Conversion};
+use swap_aggregator::correlation::{backfill_lp_mints, correlated_pairs, dominant_providers, shallower_pools, CorrelatedPair};
+use swap_aggregator::dex_registry;
+use swap_aggregator::discovery_cache::DiscoveryCache;
+use swap_aggregator::explain;
+use swap_aggregator::impact::ImpactModelChoice;
+use swap_aggregator::market_snapshot::MarketSnapshot;
+use swap_aggregator::math::{self, accumulator::Accumulator};
+use swap_aggregator::log;
+use swap_aggregator::overrides;
+use swap_aggregator::output::{self, machine_line, NoPoolsFound, RunSummary};
+use swap_aggregator::pool::{load_pools_at_block, refresh_reserves_at_block, save_pools, stale_reserves, Pool, PoolState};
+use swap_aggregator::pool_registry::{input_reserve, PoolRegistry};
+use swap_aggregator::provider::{create_provider, discover_v3_pools, get_all_pool_addresses, get_price_observation, load_extra_pool, set_provider_config, ProviderConfig, RpcProvider, RpcTransport};
+use swap_aggregator::bench;
+use swap_aggregator::compliance;
+use swap_aggregator::regress;
+use swap_aggregator::repl::{self, ReplSession};
+use swap_aggregator::reserve_recorder::{ReserveRecorder, ReserveSample, Rotation};
+use swap_aggregator::stats::UsageStats;
+use swap_aggregator::solver::{fee_revenue, ChunkJitter, find_best_routes, granularity_sweep, pool_usage, replay_routes, SolverConfig};
+use swap_aggregator::twap;
+use swap_aggregator::v3_pool::{combined_pools, solve_with_v3};
+use swap_aggregator::watch::{self, ReserveTracker};
+use alloy::eips::BlockNumberOrTag;
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use alloy::rpc::types::BlockTransactionsKind;
+use eyre::{eyre, Result, WrapErr};
 
 
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    println!("Добро пожаловать в Swap Aggregator для USDC/WETH на Polygon!");
+    let cli = Cli::parse();
+    // Машинный режим: весь лог уходит в stderr, в stdout - одна строка итога
+    if cli.quiet {
+        output::set_quiet(true);
+        let result = if cli.command.is_some() || cli.watch {
+            Err(eyre!("--quiet поддерживается только для анализа свапа без подкоманды и --watch"))
+        } else {
+            analyze(&cli).await.and_then(|summary| summary.ok_or_else(|| eyre!("анализ не дал итога")))
+        };
+        if let Err(e) = &result {
+            eprintln!("Ошибка: {:#}", e);
+        }
+        let line = machine_line(&result);
+        println!("{}", line);
+        std::process::exit(if line.starts_with("ERR") { 1 } else { 0 });
+    }
+    // Регрессионный прогон работает по записанным резервам и не требует сети
+    if let Some(Command::Regress { corpus, tolerance_bps, update }) = &cli.command {
+        let reports = regress::run_corpus(corpus, *tolerance_bps, *update).await?;
+        print!("{}", regress::format_report(&reports));
+        let regressions = reports.iter().filter(|report| report.regressed).count();
+        let algorithm_changes = reports.iter().filter(|report| report.algorithm_changed).count();
+        if *update {
+            log!("Ожидаемые результаты обновлены: {} манифестов", reports.len());
+        } else if regressions > 0 {
+            return Err(eyre!("регрессии: {} из {} случаев", regressions, reports.len()));
+        } else if algorithm_changes > 0 {
+            return Err(eyre!(
+                "алгоритм солвера изменен: {} из {} случаев записаны другой версией, перезапишите их с --update",
+                algorithm_changes,
+                reports.len()
+            ));
+        }
+        return Ok(());
+    }
+    if let Some(Command::ExplainChunk { manifest, chunk, json }) = &cli.command {
+        let manifest: regress::Manifest = serde_json::from_str(&std::fs::read_to_string(manifest)?)?;
+        let explanation = explain::explain_chunk(&manifest, *chunk)?;
+        if *json {
+            log!("{}", serde_json::to_string_pretty(&explanation)?);
+        } else {
+            print!("{}", explain::format_explanation(&explanation));
+        }
+        return Ok(());
+    }
+    if let Some(Command::BenchRouting { num_chunks, json }) = &cli.command {
+        let rows = bench::run(*num_chunks).await?;
+        if *json {
+            log!("{}", serde_json::to_string_pretty(&rows)?);
+        } else {
+            log!("{}", bench::format_table(&rows).trim_end());
+        }
+        return Ok(());
+    }
+    if let Some(Command::Convert { value, token, to_raw, to_decimal, .. }) = &cli.command {
+        let conversion = if *to_raw {
+            Conversion::ToRaw
+        } else if *to_decimal {
+            Conversion::ToDecimal
+        } else {
+            Conversion::Checksum
+        };
+        log!("{}", convert::convert(value, token.as_deref(), conversion)?);
+        return Ok(());
+    }
+    // Котировка по сохраненному снимку тоже не требует сети
+    if let Some(Command::Snapshot { action: SnapshotCommand::Load { input, amount_usdc } }) = &cli.command {
+        return quote_from_snapshot(input, *amount_usdc).await;
+    }
+    match analyze(&cli).await {
+        // Пулы не найдены: причины уже напечатаны, это не ошибка запуска
+        Err(e) if e.is::<NoPoolsFound>() => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+/// Ищет пулы с общим основным поставщиком ликвидности за последние `window` блоков
+///
+/// С `--dedupe-correlated` менее глубокий пул каждой пары исключается из
+/// расчета. Ошибка чтения логов не прерывает анализ: проверка пропускается.
+async fn check_correlated(
+    provider: Arc<RpcProvider>,
+    pools: &mut PoolRegistry,
+    window: u64,
+    cli: &Cli,
+    ctx: &ConfigContext,
+) -> Vec<CorrelatedPair> {
+    let to_block = match provider.get_block_number().await {
+        Ok(block) => block,
+        Err(e) => {
+            log!("Не удалось получить последний блок, проверка общих LP пропущена: {}", e);
+            return Vec::new();
+        }
+    };
+    let from_block = to_block.saturating_sub(window.saturating_sub(1));
+    let states = pools.states();
+    let addresses: Vec<Address> = states.iter().map(|pool| pool.pool_address).collect();
+    let mints = match backfill_lp_mints(provider, &addresses, from_block, to_block).await {
+        Ok(mints) => mints,
+        Err(e) => {
+            log!("Не удалось прочитать выпуски LP, проверка общих LP пропущена: {}", e);
+            return Vec::new();
+        }
+    };
+    let pairs = correlated_pairs(&states, &dominant_providers(&mints), cli.correlation_share_bps, ctx);
+    let name = |address: Address| pools.get(address).map_or_else(|| format!("{:?}", address), |pool| pool.name.clone());
+    for pair in &pairs {
+        log!("⚠ Пулы {} и {} с общим поставщиком LP {:?} ({:.2}% и {:.2}% выпуска за блоки {}..={})",
+            name(pair.deeper), name(pair.shallower), pair.provider,
+            pair.deeper_share_bps as f64 / 100.0, pair.shallower_share_bps as f64 / 100.0, from_block, to_block);
+    }
+    if cli.dedupe_correlated {
+        let removed = shallower_pools(&pairs);
+        for &address in &removed {
+            log!("Пул {} ({:?}) исключен как коррелированный (--dedupe-correlated)", name(address), address);
+        }
+        pools.retain(|pool| !removed.contains(&pool.pool_address));
+        return Vec::new();
+    }
+    pairs
+}
+
+/// Полный анализ свапа по сети (или по сохраненным пулам)
+/// 
+/// Возвращает итог для машинного режима; `None` - выполнена подкоманда
+/// (REPL, снимок, пакетный режим), а не анализ.
+async fn analyze(cli: &Cli) -> Result<Option<RunSummary>> {
+    log!("Добро пожаловать в Swap Aggregator для USDC/WETH на Polygon!");
     
     // Загружаем переменные окружения из .env файла
     dotenv::dotenv().ok();
@@ -23,64 +179,605 @@ async fn main() -> Result<()> {
     let rpc_url = env::var("INFURA_POLYGON_URL")
         .unwrap_or_else(|_| "https://polygon-mainnet.infura.io/v3/YOUR_PROJECT_ID".to_string());
     
-    println!("Подключаемся к сети Polygon через RPC: {}", rpc_url);
+    log!("Подключаемся к сети Polygon через RPC: {}", rpc_url);
+
+    // Политика повторов и предел частоты: CLI важнее переменных окружения
+    let env_policy = ProviderConfig::from_env();
+    set_provider_config(ProviderConfig {
+        max_retries: cli.rpc_max_retries.unwrap_or(env_policy.max_retries),
+        base_delay: cli.rpc_base_delay_ms.map_or(env_policy.base_delay, std::time::Duration::from_millis),
+        max_delay: cli.rpc_max_delay_ms.map_or(env_policy.max_delay, std::time::Duration::from_millis),
+        max_requests_per_sec: match cli.rpc_max_rps {
+            Some(0) => None,
+            Some(rps) => Some(rps),
+            None => env_policy.max_requests_per_sec,
+        },
+    });
     
     // Создаем провайдер
     let provider = create_provider(&rpc_url).await
-        .expect("Не удалось создать провайдер для подключения к Polygon");
-    println!("Провайдер создан успешно");
+        .wrap_err("не удалось создать провайдер для подключения к Polygon")?;
+    log!("Провайдер создан успешно");
     
 
     
-    // Получаем Pool объекты через Factory контракты
-    println!("\n=== Получение Pool объектов через Factory контракты ===");
-    let pools = get_all_pool_addresses(provider.clone(), USDC_ADDRESS, WETH_ADDRESS).await?;
+    // Получаем Pool объекты через Factory контракты (или из сохраненного файла)
+    log!("\n=== Получение Pool объектов через Factory контракты ===");
+    let mut ctx = ConfigContext { verify_pairs: !cli.no_verify_pairs, ..ConfigContext::default() };
+    if let Some(registry) = cli.registry {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        let (dexes, source) = dex_registry::bootstrap(provider.clone(), registry, &cli.registry_cache, cli.registry_ttl_secs, now,
+            &ctx.dexes, &ctx.input_tokens).await;
+        log!("Реестр DEX {:?}: {:?}, DEX в конфигурации: {}", registry, source, dexes.len());
+        ctx.dexes = dexes;
+    }
+    // Блок, на котором прочитаны резервы: из файла пулов, перечитанных для --save-pools или последний блок сети
+    let mut reserves_block = None;
+    let mut pools = match &cli.load_pools {
+        Some(path) => {
+            let (pools, block) = load_pools_at_block(path, provider.clone())?;
+            log!("Пулы загружены из {} без discovery", path.display());
+            reserves_block = Some(block);
+            PoolRegistry::from_pools(pools)
+        }
+        None => get_all_pool_addresses(provider.clone(), &ctx).await?,
+    };
+    for &address in &cli.extra_pools {
+        let pool = load_extra_pool(provider.clone(), address, &ctx)
+            .await
+            .wrap_err_with(|| format!("--extra-pool {:?}", address))?;
+        log!("Добавлен пул {} ({:?}) из --extra-pool", pool.name, address);
+        pools.insert(pool);
+    }
+    if let Some(path) = &cli.save_pools {
+        let block = match reserves_block {
+            Some(block) => block,
+            // Резервы discovery читались без номера блока: перечитываем их вместе с ним
+            None => {
+                let all: Vec<usize> = (0..pools.len()).collect();
+                let (block, failures) = refresh_reserves_at_block(provider.clone(), pools.pools_mut(), &all).await?;
+                for (address, e) in &failures {
+                    log!("Не удалось перечитать резервы пула {:?} перед сохранением: {}", address, e);
+                }
+                reserves_block = Some(block);
+                block
+            }
+        };
+        save_pools(path, &pools, block)?;
+        log!("{} пулов (блок {}) сохранены в {}", pools.len(), block, path.display());
+    }
+    if let Some(path) = &cli.override_reserves {
+        let overrides = overrides::load(path)?;
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        overrides::apply(&mut pools, &overrides, u32::try_from(now).unwrap_or(u32::MAX))?;
+        for entry in &overrides {
+            log!("Резервы {:?} подменены из {}: {} / {}", entry.pool_address, path.display(), entry.reserve0, entry.reserve1);
+        }
+    }
+    match provider.get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes).await {
+        Ok(Some(block)) => {
+            reserves_block.get_or_insert(block.header.number);
+            let stale = stale_reserves(&pools, block.header.timestamp, cli.stale_warn_secs);
+            for pool in &stale {
+                log!("⚠ Резервы {} ({:?}) не менялись {} с", pool.pool_name, pool.pool_address, pool.age_secs);
+            }
+            if let Some(max_age) = cli.max_staleness_secs {
+                let before = pools.len();
+                pools.retain(|pool| pool.reserves_age_secs(block.header.timestamp).is_none_or(|age| age <= max_age));
+                if pools.len() < before {
+                    log!("Исключено {} пулов с резервами старше {} с", before - pools.len(), max_age);
+                }
+            }
+        }
+        Ok(None) => log!("Последний блок не найден, проверка актуальности резервов пропущена"),
+        Err(e) => log!("Не удалось получить последний блок, проверка актуальности резервов пропущена: {}", e),
+    }
     
+    // Солвер перебирает пулы от самых глубоких: при равном выходе чанк получает более глубокий
+    pools.sort_by_liquidity(&ctx);
     if pools.is_empty() {
-        println!("\nНе найдено ни одного пула через Factory контракты!");
-        println!("Возможные причины:");
-        println!("  - Factory контракты не содержат пулы USDC/WETH");
-        println!("  - Неправильные адреса Factory контрактов"); 
-        println!("  - Проблемы с подключением к сети");
-        return Ok(());
+        log!("\nНе найдено ни одного пула через Factory контракты!");
+        log!("Возможные причины:");
+        log!("  - Factory контракты не содержат пулы USDC/WETH");
+        log!("  - Неправильные адреса Factory контрактов"); 
+        log!("  - Проблемы с подключением к сети");
+        return Err(NoPoolsFound.into());
     }
     
-    println!("✓ Найдено {} Pool объектов через Factory контракты", pools.len());
+    log!("✓ Найдено {} Pool объектов через Factory контракты", pools.len());
+    let correlated = match cli.correlation_window {
+        Some(window) => check_correlated(provider.clone(), &mut pools, window, cli, &ctx).await,
+        None => Vec::new(),
+    };
+    if let Some(Command::Repl { prefetch_timeout_ms, stats_file }) = cli.command.clone() {
+        let mut session = ReplSession::new(pools.into_pools(), ctx);
+        if let Some(path) = stats_file {
+            let stats = UsageStats::load(&path).wrap_err_with(|| format!("не удалось прочитать статистику {}", path.display()))?;
+            session = session.with_stats(stats, path);
+        }
+        if prefetch_timeout_ms > 0 {
+            session.start_prefetch(std::time::Duration::from_millis(prefetch_timeout_ms));
+        }
+        return repl::run(session).await.map(|_| None);
+    }
+    if cli.watch {
+        let ws_url = cli
+            .ws_url
+            .clone()
+            .or_else(|| env::var("INFURA_POLYGON_WS_URL").ok())
+            .or_else(|| (RpcTransport::detect(&rpc_url) == Some(RpcTransport::Ws)).then(|| rpc_url.clone()))
+            .ok_or_else(|| eyre!("для --watch нужен --ws-url, INFURA_POLYGON_WS_URL или ws(s):// в INFURA_POLYGON_URL"))?;
+        log!("\n=== Отслеживание резервов по событиям Sync ({}) ===", ws_url);
+        let mut tracker = ReserveTracker::new(pools.into_pools());
+        let recorder = match &cli.record_reserves {
+            Some(dir) => {
+                let rotation = Rotation {
+                    max_bytes: cli.record_rotate_mb.max(1).saturating_mul(1024 * 1024),
+                    max_age: std::time::Duration::from_secs(cli.record_rotate_secs.max(1)),
+                };
+                let recorder = ReserveRecorder::start(dir, rotation)
+                    .wrap_err_with(|| format!("не удалось создать каталог {}", dir.display()))?;
+                log!("Ряд резервов записывается в {}", dir.display());
+                Some(recorder)
+            }
+            None => None,
+        };
+        let watched = watch::watch(provider.clone(), &ws_url, &mut tracker, |tracker, index, block| {
+            if let Some(recorder) = &recorder {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+                recorder.record(ReserveSample::from_pool(&tracker.pools()[index], block, now));
+            }
+            print_watch_update(tracker, index, block, &ctx);
+        });
+        // Ctrl-C завершает наблюдение, но файлы ряда сначала закрываются
+        let result = tokio::select! {
+            result = watched => result,
+            _ = tokio::signal::ctrl_c() => Ok(()),
+        };
+        if let Some(recorder) = recorder {
+            let summary = recorder.finish().await?;
+            log!("Ряд резервов: записано {}, отброшено {}, файлов {}", summary.written, summary.dropped, summary.files.len());
+            if summary.dropped > 0 {
+                log!("Предупреждение: запись не успевала за событиями, {} образцов отброшено", summary.dropped);
+            }
+        }
+        result?;
+        return Ok(None);
+    }
+    if let Some(Command::Snapshot { action: SnapshotCommand::Save { output } }) = &cli.command {
+        let block = provider
+            .get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes)
+            .await?
+            .ok_or_else(|| eyre!("последний блок не найден"))?;
+        let snapshot = MarketSnapshot::from_pools(&pools.states(), block.header.number, block.header.timestamp);
+        snapshot.save(output)?;
+        log!("Снимок блока {} ({} пулов) сохранен в {}", snapshot.block_number, snapshot.pools.len(), output.display());
+        return Ok(None);
+    }
+    if let Some(Command::Batch { input, output, parallelism }) = &cli.command {
+        let requests: Vec<QuoteRequest> = serde_json::from_str(&std::fs::read_to_string(input)?)?;
+        log!("Пакетный режим: {} запросов, до {} одновременно", requests.len(), parallelism);
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(output)?);
+        let mut write_error = None;
+        batch::solve_batch(&pools.states(), &ctx, requests, *parallelism, |response| {
+            let line = serde_json::to_string(&response).expect("QuoteResponse сериализуется");
+            if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+                write_error.get_or_insert(e);
+            }
+        }).await;
+        if let Some(e) = write_error {
+            return Err(e.into());
+        }
+        log!("Результаты записаны в {}", output.display());
+        return Ok(None);
+    }
+
+    log!("  {} | {:>16} | {:>18}", PoolState::summary_header(), "Глубина", "Макс. выход");
     for pool in &pools {
-        println!("  Pool: {} - {:?} (tokens: {:?}/{:?})", 
-            pool.name, pool.pool_address, pool.token0_address, pool.token1_address);
+        // Глубина - резерв собственного входного токена пула в его decimals
+        let depth = input_reserve(pool, &ctx).map(|(token, reserve, decimals)| (token, to_decimal(reserve, decimals)));
+        let max_out = pool.max_output(ctx.output_token);
+        let partner = correlated.iter().find_map(|pair| match pool.pool_address {
+            address if address == pair.deeper => Some(pair.shallower),
+            address if address == pair.shallower => Some(pair.deeper),
+            _ => None,
+        });
+        let partner_name = partner.map(|address| pools.get(address).map_or_else(|| format!("{:?}", address), |pool| pool.name.clone()));
+        log!("  {} | {:>16} | {:>18}{}{}{}{}",
+            pool.summary_row(),
+            depth.map_or_else(|| "-".to_string(), |(token, depth)| format!("{:.2} {}", depth, token)),
+            if max_out.is_zero() { "-".to_string() } else { format!("{:.4} {}", to_decimal(max_out, ctx.output_token.decimals()), ctx.output_token) },
+            if pool.protocol_fee_enabled { " [protocol fee включен]" } else { "" },
+            if pool.reserves_overridden { " [резервы подменены]" } else { "" },
+            if depth.is_some_and(|(_, depth)| depth < TINY_POOL_DEPTH) { " ⚠ мелкий пул" } else { "" },
+            partner_name.map_or_else(String::new, |name| format!(" ⚠ общий LP с {}", name)));
+    }
+
+    let mut v3_pools = if cli.uniswap_v3 {
+        log!("\n=== Получение пулов Uniswap V3 ===");
+        let block = provider.get_block_number().await?;
+        let mut cache = DiscoveryCache::load(&cli.discovery_cache, NEGATIVE_PROBE_TTL_BLOCKS)?;
+        cache.begin_run(block, cli.exploration_budget);
+        let v3_pools = discover_v3_pools(provider.clone(), &ctx, UNISWAP_V3_FACTORY, UNISWAP_V3_QUOTER_V2, &mut cache, block).await;
+        let stats = cache.stats();
+        log!("Кэш discovery: {} найденных из кэша, {} отсутствующих из кэша, {} запросов, {} перепроверено",
+            stats.hits, stats.negative_hits, stats.misses, stats.rechecked);
+        if let Err(e) = cache.save(&cli.discovery_cache) {
+            log!("Не удалось сохранить кэш discovery {}: {}", cli.discovery_cache.display(), e);
+        }
+        v3_pools
+    } else {
+        Vec::new()
+    };
+    for pool in &v3_pools {
+        log!("  Pool: {} - {:?} (tokens: {:?}/{:?}, комиссия {}, котировки QuoterV2)",
+            pool.name, pool.pool_address, pool.token0, pool.token1, pool.fee_label());
+    }
+
+    if let Some(window) = cli.twap_window {
+        print_twap(provider.clone(), &pools, &ctx, window).await;
+    }
+
+    // Дальше сеть не нужна: солвер и таблицы работают с состояниями пулов
+    let states = pools.states();
+    let impact_model = cli.impact_oracle_price.map_or_else(ImpactModelChoice::default, ImpactModelChoice::OracleReference);
+    print_price_table(&states, &ctx);
+    print_depth_table(&states, &ctx, impact_model);
+    print_arbitrage_sizing(&states, &ctx);
+
+    if cli.granularity_sweep {
+        log!("\n=== Выигрыш от гранулярности ===");
+        let sweep = granularity_sweep(&states, &ctx, ctx.total_amount_in, ctx.num_chunks).await?;
+        log!("  {:>8} | {:>14}", "Чанков", "Выход WETH");
+        for point in &sweep.points {
+            log!("  {:>8} | {:>14.6}", point.num_chunks, weth_to_decimal(point.total_out));
+        }
+        log!("  Рекомендуемое количество чанков: {}", sweep.recommended_chunks);
+        log!("{}", serde_json::to_string(&sweep)?);
     }
 
     // Запускаем полный анализ свапа
-    println!("\n=== Запуск полного анализа свапа ===");
-    let result = find_best_routes(provider.clone(), pools).await?;
+    let solver_config = SolverConfig {
+        strategy: cli.strategy,
+        reserve_haircut_bps: cli.reserve_haircut_bps,
+        slippage_bps: cli.slippage_bps,
+        commit_threshold_bps: cli.commit_threshold_bps,
+        commit_switch_bps: cli.commit_switch_bps,
+        impact_model,
+        bump_tiny_chunks: cli.bump_tiny_chunks,
+        chunk_jitter: cli.chunk_jitter_bps.map(|bps| ChunkJitter { bps, seed: cli.chunk_jitter_seed.unwrap_or_else(jitter_seed) }),
+        chunk_guard: cli.max_chunk_price_deviation_bps.map(|max_deviation_bps| ChunkGuard {
+            max_deviation_bps,
+            remedy: cli.chunk_guard_remedy,
+            fallback: cli.chunk_guard_fallback,
+        }),
+        ..SolverConfig::from_context(&ctx)
+    };
+    log!("\n=== Запуск полного анализа свапа ===");
+    if let Some(jitter) = solver_config.chunk_jitter {
+        log!("Разброс чанков ±{} bps, seed {} (повторить: --chunk-jitter-seed {})", jitter.bps, jitter.seed, jitter.seed);
+    }
+    let result = if v3_pools.is_empty() {
+        find_best_routes(states.clone(), &ctx, &solver_config).await?
+    } else {
+        let (result, quoter_calls) = solve_with_v3(&states, &mut v3_pools, &ctx, &solver_config).await?;
+        log!("Вызовов QuoterV2: {}", quoter_calls);
+        result
+    };
+    
+    if let Some(sender) = cli.sender {
+        let checks = compliance::route_checks(&result.chunk_routes, sender);
+        compliance::check_sender(provider.clone(), sender, &checks).await?;
+        log!("Проверка отправителя {:?}: {} вызовов transfer/approve без ограничений", sender, checks.len());
+    }
+    
+    log!("Solver завершил работу успешно!");
+    log!("Результаты:");
+    log!("  Обработано частей: {}", result.chunk_routes.len());
+    log!("  Общий выход WETH: {}", DisplayAmount::new(result.total_weth_out, TokenId::WETH));
+    log!("  Входная сумма USDC: {} USDC", format_units(ctx.total_amount_in, USDC_DECIMALS));
+    // min_amount_out задан в токене пула, для итога переводим в WETH
+    let min_weth_out = Accumulator::sum("минимальный выход", result
+        .chunk_routes
+        .iter()
+        .filter_map(|route| ctx.convert_output(route.token_out?, route.min_amount_out)))?;
+    if result.output_by_token.iter().any(|(token, _)| *token != ctx.output_token) {
+        for (token, amount) in &result.output_by_token {
+            log!("  Выход в {} до конвертации: {}", token, format_units(*amount, token.decimals()));
+        }
+    }
+    log!("  Минимальный выход WETH (slippage {} bps): {} WETH",
+        solver_config.slippage_bps, format_units(min_weth_out, WETH_DECIMALS));
+    match (result.execution_price, result.price_deviation_bps) {
+        (Some(price), Some(deviation)) => log!("  Цена исполнения: {:.2} USDC/WETH (спот {:.2}, {:+.1} bps)",
+            price, result.spot_price, deviation),
+        (Some(price), None) => log!("  Цена исполнения: {:.2} USDC/WETH (спот недоступен)", price),
+        _ => log!("  Цена исполнения: нет (нулевой выход WETH)"),
+    }
+    if let Some(worst) = result.chunk_routes.iter().filter_map(|route| route.spot_deviation_bps).reduce(f64::min) {
+        log!("  Худший чанк: {:+.1} bps от лучшего спота снимка", worst);
+    }
+    if let Some(guard) = solver_config.chunk_guard {
+        log!("  Предел цены чанка {} bps: срабатываний {}, не исполнено {} USDC",
+            guard.max_deviation_bps, result.diagnostics.guard_events.len(), format_units(result.diagnostics.unfilled_in, USDC_DECIMALS));
+        for event in &result.diagnostics.guard_events {
+            log!("    {}", event);
+        }
+    }
+    if result.confidence < 1.0 {
+        log!("  Уверенность котировки: {:.2}% (часть выхода из приближенной модели пулов)", result.confidence * 100.0);
+    }
+    if result.diagnostics.requotes > 0 {
+        log!("  Перекотировок у источника: {}", result.diagnostics.requotes);
+    }
+    if result.reserve_haircut_bps > 0 {
+        log!("  КОНСЕРВАТИВНАЯ КОТИРОВКА: выходные резервы уменьшены на {} bps", result.reserve_haircut_bps);
+    }
+    if let Some(threshold) = solver_config.commit_threshold_bps {
+        log!("  Закрепление пулов (порог {} bps): изменило выбор в {} чанках",
+            threshold, result.diagnostics.committed_chunks);
+    }
+    
+    let skip_summary = result.diagnostics.top_skip_reasons(3);
+    if !skip_summary.is_empty() {
+        log!("\nПричины пропуска пулов:");
+        for line in skip_summary {
+            log!("  {}", line);
+        }
+    }
+    if !result.diagnostics.fee_rounding.is_empty() {
+        log!("\nОкругление комиссии на мелких чанках:");
+        for warning in &result.diagnostics.fee_rounding {
+            log!("  {}", warning);
+        }
+    }
     
-    let total_weth_decimal = weth_to_decimal(result.total_weth_out);
-   
-    println!("Solver завершил работу успешно!");
-    println!("Результаты:");
-    println!("  Обработано частей: {}", result.chunk_routes.len());
-    println!("  Общий выход WETH: {:.6} WETH (raw: {})", total_weth_decimal, result.total_weth_out);
-    println!("  Входная сумма USDC: {} USDC", TOTAL_USDC_DECIMAL);
+    log!("\nКомиссии маршрута (USDC):");
+    for revenue in fee_revenue(&result.chunk_routes, &combined_pools(&states, &v3_pools), &ctx)? {
+        log!("  {}: всего {}, LP {}, протокол {}", revenue.pool_name,
+            format_units(revenue.total_fee, USDC_DECIMALS),
+            format_units(revenue.lp_fee, USDC_DECIMALS),
+            format_units(revenue.protocol_fee, USDC_DECIMALS));
+    }
     
     // Показываем первые 5 результатов
-    println!("\nПервые 5 результатов:");
+    log!("\nПервые 5 результатов:");
     for (i, route) in result.chunk_routes.iter().take(5).enumerate() {
-        println!("  {}. Часть {}: {} -> {:.6} WETH", 
+        log!("  {}. Часть {}: {} -> {:.6} WETH", 
             i + 1, route.chunk_index, route.best_pool_name, route.amount_out_decimal);
     }
     
-    // Подсчитываем и показываем статистику использования пулов
-    let mut pool_usage = std::collections::HashMap::new();
-    for route in &result.chunk_routes {
-        *pool_usage.entry(route.best_pool_name.clone()).or_insert(0) += 1;
+    // Статистика по адресам пулов: имена только для вывода (символы токенов могут совпадать)
+    log!("\nСтатистика использования пулов:");
+    for usage in pool_usage(&result.chunk_routes) {
+        // Доля по объему: с разбросом чанки разного размера
+        let percentage = math::u256_to_f64(usage.amount_in) / math::u256_to_f64(solver_config.total_amount_in) * 100.0;
+        let overridden = states.iter().any(|state| state.pool_address == usage.pool_address && state.reserves_overridden);
+        log!("  {} ({:?}): {} раз ({:.1}%){}", usage.pool_name, usage.pool_address, usage.chunks, percentage,
+            if overridden { " [резервы подменены]" } else { "" });
     }
-    
-    println!("\nСтатистика использования пулов:");
-    for (pool_name, count) in pool_usage {
-        let percentage = (count as f64 / result.chunk_routes.len() as f64) * 100.0;
-        println!("  {}: {} раз ({:.1}%)", pool_name, count, percentage);
+
+    // Повтор маршрута по пулам с журналом свапов: итог каждого пула и сверка выходов с чанками
+    if solver_config.reserve_haircut_bps == 0 {
+        match replay_routes(&states, &result.chunk_routes) {
+            Ok(replayed) => {
+                log!("\nСвапы по пулам:");
+                for pool in &replayed {
+                    print_swap_history(pool, &ctx);
+                }
+            }
+            Err(e) => log!("⚠ Повтор маршрута по пулам не сошелся с чанками: {}", e),
+        }
+    }
+
+    let block = match reserves_block {
+        Some(block) => block,
+        None => provider.get_block_number().await?,
+    };
+    Ok(Some(RunSummary {
+        total_out: result.total_weth_out,
+        execution_price: result.execution_price,
+        block,
+        unfilled_in: result.diagnostics.unfilled_in,
+    }))
+}
+
+/// Seed разброса чанков по умолчанию: от текущего времени, печатается для воспроизведения
+fn jitter_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Загружает снимок рынка и печатает итог маршрута по нему
+async fn quote_from_snapshot(input: &std::path::Path, amount_usdc: Option<U256>) -> Result<()> {
+    let snapshot = MarketSnapshot::load(input)?;
+    log!("Снимок блока {} (timestamp {}): {} пулов", snapshot.block_number, snapshot.timestamp, snapshot.pools.len());
+
+    let pools = snapshot.to_pools();
+    let ctx = ConfigContext::default();
+    let solver_config = SolverConfig {
+        total_amount_in: amount_usdc.unwrap_or(ctx.total_amount_in),
+        verbose: false,
+        ..SolverConfig::from_context(&ctx)
+    };
+    let result = find_best_routes(pools, &ctx, &solver_config).await?;
+    log!("  Вход: {} USDC", format_units(solver_config.total_amount_in, USDC_DECIMALS));
+    log!("  Выход: {} WETH", format_units(result.total_weth_out, WETH_DECIMALS));
+    if let Some(price) = result.execution_price {
+        log!("  Цена исполнения: {:.2} USDC/WETH", price);
     }
-    
     Ok(())
 }
+
+/// Печатает TWAP каждого пула constant product за окно `window_secs` рядом со спот-ценой
+/// 
+/// Снимает наблюдение накопительных цен, ждет окно и снимает второе.
+/// Пулы, для которых наблюдение не получено, пропускаются.
+async fn print_twap(provider: Arc<RpcProvider>, pools: &[Pool], ctx: &ConfigContext, window_secs: u64) {
+    let pools: Vec<&Pool> = pools.iter().filter(|pool| pool.is_constant_product()).collect();
+
+    log!("\n=== TWAP за {} с (USDC за WETH) ===", window_secs);
+    let start = observe_all(provider.clone(), &pools).await;
+    tokio::time::sleep(std::time::Duration::from_secs(window_secs)).await;
+    let end = observe_all(provider, &pools).await;
+
+    for ((pool, start), end) in pools.iter().zip(start).zip(end) {
+        let weth_is_token0 = pool.token0 == ctx.output_token;
+        let spot = pool.spot_price(weth_is_token0);
+        let twap_price = match (start, end) {
+            (Some(start), Some(end)) => twap::twap(&start, &end, pool.token0.decimals(), pool.token1.decimals())
+                .map(|(price0, price1)| if weth_is_token0 { price0 } else { price1 }),
+            _ => None,
+        };
+        match twap_price {
+            Some(price) => log!("  {}: TWAP {:.2}, спот {:.2}", pool.name, price, spot),
+            None => log!("  {}: TWAP недоступен, спот {:.2}", pool.name, spot),
+        }
+    }
+}
+
+/// Наблюдения накопительных цен для пулов (None при ошибке запроса)
+async fn observe_all(provider: Arc<RpcProvider>, pools: &[&Pool]) -> Vec<Option<twap::PriceObservation>> {
+    let mut observations = Vec::with_capacity(pools.len());
+    for pool in pools {
+        match get_price_observation(provider.clone(), pool.pool_address).await {
+            Ok(observation) => observations.push(Some(observation)),
+            Err(e) => {
+                log!("  {}: не удалось получить накопительные цены: {}", pool.name, e);
+                observations.push(None);
+            }
+        }
+    }
+    observations
+}
+
+/// Цена выходного токена во входном (за 1 выходной) или `None` для пустого пула
+fn output_price(pool: &PoolState, ctx: &ConfigContext) -> Option<f64> {
+    if pool.token1 == ctx.output_token {
+        pool.price_token1_in_token0_f64()
+    } else {
+        pool.price_token0_in_token1_f64()
+    }
+}
+
+/// Итог журнала свапов пула: поглощенный вход, отданный выход, изменение резервов и цена после
+fn print_swap_history(pool: &PoolState, ctx: &ConfigContext) {
+    let (Some(history), Some((delta0, delta1))) = (&pool.history, pool.reserve_delta()) else {
+        return;
+    };
+    let input_is_token0 = ctx.is_input_token(pool.token0);
+    let (token_in, token_out) = if input_is_token0 { (pool.token0, pool.token1) } else { (pool.token1, pool.token0) };
+    let (decimals_in, decimals_out) = pool.decimals_for(input_is_token0);
+    let (absorbed, emitted) = history.totals(input_is_token0);
+    log!("  {} ({:?}): свапов {}, вход {}, выход {}, Δ резервов {} / {}, цена после {}",
+        pool.name, pool.pool_address, history.events.len(),
+        DisplayAmount::with_decimals(absorbed, token_in, decimals_in),
+        DisplayAmount::with_decimals(emitted, token_out, decimals_out),
+        delta0, delta1,
+        output_price(pool, ctx).map_or_else(|| "-".to_string(), |price| format!("{:.2} {}/{}", price, token_in, token_out)));
+}
+
+/// Строка `--watch`: новая цена обновленного пула и лучшая цена среди всех пулов
+fn print_watch_update(tracker: &ReserveTracker, index: usize, block: u64, ctx: &ConfigContext) {
+    let pools = tracker.pools();
+    let Some(price) = output_price(&pools[index], ctx) else {
+        log!("[блок {}] {}: пустые резервы, цены нет", block, pools[index].name);
+        return;
+    };
+    // Пулы с незамкнутым выключателем не претендуют на лучшую цену
+    let best = pools
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !tracker.is_excluded(*index))
+        .filter_map(|(_, pool)| output_price(pool, ctx).map(|price| (pool, price)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    match best {
+        Some((best, best_price)) => log!("[блок {}] {}: {:.2} за 1 {}; лучшая {:.2} ({})",
+            block, pools[index].name, price, ctx.output_token, best_price, best.name),
+        None => log!("[блок {}] {}: {:.2} за 1 {}", block, pools[index].name, price, ctx.output_token),
+    }
+}
+
+/// Печатает цены выходного токена в пулах до маршрутизации (от дешевого к дорогому)
+/// и разброс между лучшей и худшей ценой в bps
+fn print_price_table(pools: &[PoolState], ctx: &ConfigContext) {
+    let mut prices: Vec<(&PoolState, f64)> = pools
+        .iter()
+        .filter_map(|pool| {
+            match output_price(pool, ctx) {
+                Some(price) => Some((pool, price)),
+                None => {
+                    log!("  {}: пустые резервы, цены нет", pool.name);
+                    None
+                }
+            }
+        })
+        .collect();
+    prices.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    log!("\nЦены пулов до маршрутизации (за 1 {}):", ctx.output_token);
+    for (pool, price) in &prices {
+        log!("  {}: {:.2}", pool.name, price);
+    }
+    if let (Some((best, best_price)), Some((worst, worst_price))) = (prices.first(), prices.last()) {
+        let dispersion_bps = (worst_price - best_price) / best_price * 10_000.0;
+        log!("  Разброс: {:.1} bps (дешевле всего {}, дороже всего {})", dispersion_bps, best.name, worst.name);
+    }
+}
+
+/// Печатает глубину ликвидности: сколько входного токена пула можно обменять,
+/// не превысив заданный price impact (комиссия 0.3% входит в impact)
+fn print_depth_table(pools: &[PoolState], ctx: &ConfigContext, impact_model: ImpactModelChoice) {
+    const BUDGETS_BPS: [u32; 3] = [10, 50, 100];
+
+    log!("\n=== Глубина ликвидности (макс. вход при impact не выше) ===");
+    log!("  {:<28} | {:>8} | {:>14} | {:>14} | {:>14}", "Пул", "Вход", "10 bps", "50 bps", "100 bps");
+    for pool in pools {
+        let Some(token_in) = ctx.input_tokens.iter().copied().find(|token| pool.other_token(*token).is_some()) else {
+            continue;
+        };
+        let depths: Vec<String> = BUDGETS_BPS
+            .iter()
+            .map(|&budget| match impact_model.model().max_input_for_impact(pool, budget, token_in) {
+                // Спот-модель: бюджет не выше комиссии; оракул: пул хуже оракула больше чем на бюджет
+                amount if amount.is_zero() && impact_model == ImpactModelChoice::SpotReference => "< комиссии".to_string(),
+                amount if amount.is_zero() => "< бюджета".to_string(),
+                amount => format!("{:.2}", to_decimal(amount, pool.decimals_for(token_in == pool.token0).0)),
+            })
+            .collect();
+        log!("  {:<28} | {:>8} | {:>14} | {:>14} | {:>14}", pool.name, token_in.to_string(), depths[0], depths[1], depths[2]);
+    }
+}
+
+/// Печатает, сколько USDC нужно, чтобы выровнять цены Quickswap и Sushiswap
+/// 
+/// Вход подается в пул с более дешевым WETH, пока его цена не дойдет до текущей
+/// цены второго пула (движение второго пула при арбитраже не учитывается).
+fn print_arbitrage_sizing(pools: &[PoolState], ctx: &ConfigContext) {
+    let find = |dex: DexId| {
+        pools.iter().find(|pool| pool.dex == dex && pool.other_token(TokenId::USDC) == Some(ctx.output_token))
+    };
+    let (Some(quickswap), Some(sushiswap)) = (find(DexId::QUICKSWAP), find(DexId::SUSHISWAP)) else {
+        return;
+    };
+
+    log!("\n=== Размер арбитража Quickswap/Sushiswap ===");
+    let reserves = |pool: &PoolState| pool.reserves_for(pool.token0 == TokenId::USDC);
+    let (quickswap_in, quickswap_out) = reserves(quickswap);
+    let (sushiswap_in, sushiswap_out) = reserves(sushiswap);
+
+    // Больше WETH за USDC - более дешевый WETH, туда и идет вход
+    let (cheap, target) = if quickswap_out * sushiswap_in > sushiswap_out * quickswap_in {
+        (quickswap, sushiswap)
+    } else {
+        (sushiswap, quickswap)
+    };
+    let (target_in, target_out) = reserves(target);
+
+    match cheap.amount_in_to_reach_price(target_out, target_in, cheap.token0 == TokenId::USDC) {
+        Some(amount) => log!("  Чтобы цена {} сравнялась с {}: {:.2} USDC",
+            cheap.name, target.name, usdc_to_decimal(amount)),
+        None => log!("  Цены пулов {} и {} уже совпадают", cheap.name, target.name),
+    }
+}
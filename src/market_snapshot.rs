@@ -0,0 +1,497 @@
+// src/market_snapshot.rs
+//! Компактный бинарный снимок состояния рынка для офлайн-инструментов
+//!
+//! Формат файла: магия `SAMS`, версия формата (u16 LE), байт флагов и
+//! полезная нагрузка postcard. С фичей `zstd` нагрузка может быть сжата
+//! (флаг `FLAG_ZSTD`). Версия проверяется при чтении: файлы старых версий
+//! читаются текущим кодом, файлы более новых версий отклоняются с ошибкой,
+//! а не разбираются наугад.
+//!
+//! Версии: 1 - без комиссии пула и времени резервов (при чтении берется
+//! комиссия DEX по умолчанию, время неизвестно), 2 - с `fee_bps` и `last_updated`,
+//! 3 - с пометкой подмены резервов (`reserves_overridden`).
+use crate::config::{default_dexes, dex_fee_bps, DexId, TokenId};
+use crate::pool::{PoolKind, PoolState};
+use alloy::primitives::{Address, B256, U256};
+use eyre::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Магические байты в начале файла снимка
+const MAGIC: &[u8; 4] = b"SAMS";
+/// Текущая версия формата
+pub const MARKET_SNAPSHOT_VERSION: u16 = 3;
+/// Нагрузка сжата zstd
+const FLAG_ZSTD: u8 = 0b0000_0001;
+/// Длина заголовка: магия + версия + флаги
+const HEADER_LEN: usize = 7;
+
+/// Метаданные токена в снимке
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotToken {
+    pub address: Address,
+    pub decimals: u8,
+}
+
+/// Тип кривой пула в снимке
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotPoolKind {
+    ConstantProduct,
+    Weighted { pool_id: B256, weight_token0: U256, weight_token1: U256, swap_fee: U256 },
+}
+
+/// Состояние одного пула в снимке
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotPool {
+    pub address: Address,
+    pub dex: String,
+    pub name: String,
+    pub token0: Address,
+    pub token1: Address,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub kind: SnapshotPoolKind,
+    pub protocol_fee_enabled: bool,
+    pub fee_bps: u32,
+    pub last_updated: u32, // blockTimestampLast резервов (0 - неизвестно)
+    pub reserves_overridden: bool, // Резервы заданы --override-reserves, а не прочитаны из сети
+}
+
+impl From<&PoolState> for SnapshotPool {
+    fn from(pool: &PoolState) -> Self {
+        SnapshotPool {
+            address: pool.pool_address,
+            dex: pool.dex.0.to_string(),
+            name: pool.name.clone(),
+            token0: pool.token0.address(),
+            token1: pool.token1.address(),
+            reserve0: pool.reserve_token0,
+            reserve1: pool.reserve_token1,
+            kind: match pool.kind {
+                PoolKind::ConstantProduct => SnapshotPoolKind::ConstantProduct,
+                PoolKind::Weighted { pool_id, weight_token0, weight_token1, swap_fee } => {
+                    SnapshotPoolKind::Weighted { pool_id, weight_token0, weight_token1, swap_fee }
+                }
+            },
+            protocol_fee_enabled: pool.protocol_fee_enabled,
+            fee_bps: pool.fee_bps,
+            last_updated: pool.last_updated,
+            reserves_overridden: pool.reserves_overridden,
+        }
+    }
+}
+
+impl SnapshotPool {
+    /// Состояние пула с decimals токенов из снимка (`decimals_fetched` не выставляется)
+    pub fn to_state(&self, token0_decimals: u8, token1_decimals: u8) -> PoolState {
+        let mut pool = PoolState::new(self.address, TokenId(self.token0), TokenId(self.token1), snapshot_dex(&self.dex), self.name.clone());
+        pool.token0_decimals = token0_decimals;
+        pool.token1_decimals = token1_decimals;
+        pool.reserve_token0 = self.reserve0;
+        pool.reserve_token1 = self.reserve1;
+        pool.kind = match self.kind {
+            SnapshotPoolKind::ConstantProduct => PoolKind::ConstantProduct,
+            SnapshotPoolKind::Weighted { pool_id, weight_token0, weight_token1, swap_fee } => {
+                PoolKind::Weighted { pool_id, weight_token0, weight_token1, swap_fee }
+            }
+        };
+        pool.protocol_fee_enabled = self.protocol_fee_enabled;
+        pool.fee_bps = self.fee_bps;
+        pool.last_updated = self.last_updated;
+        pool.reserves_overridden = self.reserves_overridden;
+        pool.invalidate_quote_cache();
+        pool
+    }
+}
+
+/// Состояние пула в снимке версии 1 (без комиссии и времени резервов)
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct SnapshotPoolV1 {
+    address: Address,
+    dex: String,
+    name: String,
+    token0: Address,
+    token1: Address,
+    reserve0: U256,
+    reserve1: U256,
+    kind: SnapshotPoolKind,
+    protocol_fee_enabled: bool,
+}
+
+/// Снимок версии 1
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct MarketSnapshotV1 {
+    block_number: u64,
+    timestamp: u64,
+    tokens: Vec<SnapshotToken>,
+    pools: Vec<SnapshotPoolV1>,
+}
+
+impl From<MarketSnapshotV1> for MarketSnapshot {
+    fn from(v1: MarketSnapshotV1) -> Self {
+        let pools = v1
+            .pools
+            .into_iter()
+            .map(|pool| SnapshotPool {
+                fee_bps: dex_fee_bps(&default_dexes(), snapshot_dex(&pool.dex)),
+                address: pool.address,
+                dex: pool.dex,
+                name: pool.name,
+                token0: pool.token0,
+                token1: pool.token1,
+                reserve0: pool.reserve0,
+                reserve1: pool.reserve1,
+                kind: pool.kind,
+                protocol_fee_enabled: pool.protocol_fee_enabled,
+                last_updated: 0,
+                reserves_overridden: false,
+            })
+            .collect();
+        MarketSnapshot { block_number: v1.block_number, timestamp: v1.timestamp, tokens: v1.tokens, pools }
+    }
+}
+
+/// Состояние пула в снимке версии 2 (без пометки подмены резервов)
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct SnapshotPoolV2 {
+    address: Address,
+    dex: String,
+    name: String,
+    token0: Address,
+    token1: Address,
+    reserve0: U256,
+    reserve1: U256,
+    kind: SnapshotPoolKind,
+    protocol_fee_enabled: bool,
+    fee_bps: u32,
+    last_updated: u32,
+}
+
+/// Снимок версии 2
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct MarketSnapshotV2 {
+    block_number: u64,
+    timestamp: u64,
+    tokens: Vec<SnapshotToken>,
+    pools: Vec<SnapshotPoolV2>,
+}
+
+impl From<MarketSnapshotV2> for MarketSnapshot {
+    fn from(v2: MarketSnapshotV2) -> Self {
+        let pools = v2
+            .pools
+            .into_iter()
+            .map(|pool| SnapshotPool {
+                address: pool.address,
+                dex: pool.dex,
+                name: pool.name,
+                token0: pool.token0,
+                token1: pool.token1,
+                reserve0: pool.reserve0,
+                reserve1: pool.reserve1,
+                kind: pool.kind,
+                protocol_fee_enabled: pool.protocol_fee_enabled,
+                fee_bps: pool.fee_bps,
+                last_updated: pool.last_updated,
+                reserves_overridden: false,
+            })
+            .collect();
+        MarketSnapshot { block_number: v2.block_number, timestamp: v2.timestamp, tokens: v2.tokens, pools }
+    }
+}
+
+/// DEX по имени из снимка; неизвестное конфигурации имя сохраняется как есть
+/// (строка живет до конца процесса)
+fn snapshot_dex(name: &str) -> DexId {
+    DexId::from_name(name).unwrap_or_else(|| DexId(Box::leak(name.to_string().into_boxed_str())))
+}
+
+/// Полное состояние рынка на блоке
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub tokens: Vec<SnapshotToken>,
+    pub pools: Vec<SnapshotPool>,
+}
+
+impl MarketSnapshot {
+    /// Снимает состояние пулов; токены собираются без повторов
+    pub fn from_pools(pools: &[PoolState], block_number: u64, timestamp: u64) -> Self {
+        let mut tokens: Vec<SnapshotToken> = Vec::new();
+        for pool in pools {
+            for token in [
+                SnapshotToken { address: pool.token0.address(), decimals: pool.token0_decimals },
+                SnapshotToken { address: pool.token1.address(), decimals: pool.token1_decimals },
+            ] {
+                if !tokens.iter().any(|known| known.address == token.address) {
+                    tokens.push(token);
+                }
+            }
+        }
+
+        let pools = pools.iter().map(SnapshotPool::from).collect();
+
+        MarketSnapshot { block_number, timestamp, tokens, pools }
+    }
+
+    /// Восстанавливает состояния пулов из снимка
+    ///
+    /// Котировки по восстановленным пулам считаются без сети. Имена DEX, неизвестных
+    /// конфигурации, сохраняются как есть (строка живет до конца процесса).
+    pub fn to_pools(&self) -> Vec<PoolState> {
+        let decimals_of = |address: Address| {
+            self.tokens
+                .iter()
+                .find(|token| token.address == address)
+                .map_or_else(|| TokenId(address).decimals(), |token| token.decimals)
+        };
+
+        self.pools
+            .iter()
+            .map(|state| {
+                let mut pool = state.to_state(decimals_of(state.token0), decimals_of(state.token1));
+                pool.decimals_fetched = [state.token0, state.token1]
+                    .iter()
+                    .all(|address| self.tokens.iter().any(|token| token.address == *address));
+                pool
+            })
+            .collect()
+    }
+
+    /// Кодирует снимок в байты файла (со сжатием, если включена фича `zstd`)
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let payload = postcard::to_stdvec(self)?;
+        #[cfg(feature = "zstd")]
+        let (flags, payload) = (FLAG_ZSTD, zstd::encode_all(payload.as_slice(), 0)?);
+        #[cfg(not(feature = "zstd"))]
+        let flags = 0u8;
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&MARKET_SNAPSHOT_VERSION.to_le_bytes());
+        bytes.push(flags);
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    /// Разбирает байты файла снимка
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            bail!("не файл снимка рынка");
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > MARKET_SNAPSHOT_VERSION {
+            bail!("снимок версии {} новее поддерживаемой {}", version, MARKET_SNAPSHOT_VERSION);
+        }
+        let flags = bytes[6];
+        let payload = &bytes[HEADER_LEN..];
+
+        if flags & FLAG_ZSTD != 0 {
+            #[cfg(feature = "zstd")]
+            return Self::decode_payload(version, &zstd::decode_all(payload)?);
+            #[cfg(not(feature = "zstd"))]
+            bail!("снимок сжат zstd, соберите с --features zstd");
+        }
+        Self::decode_payload(version, payload)
+    }
+
+    /// Разбирает нагрузку postcard в формате версии `version`
+    fn decode_payload(version: u16, payload: &[u8]) -> Result<Self> {
+        match version {
+            1 => Ok(postcard::from_bytes::<MarketSnapshotV1>(payload)?.into()),
+            2 => Ok(postcard::from_bytes::<MarketSnapshotV2>(payload)?.into()),
+            _ => Ok(postcard::from_bytes(payload)?),
+        }
+    }
+
+    /// Сохраняет снимок в файл
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Загружает снимок из файла
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigContext;
+    use crate::solver::{find_best_routes, SolverConfig};
+
+    fn market() -> Vec<PoolState> {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut quickswap = crate::pool::test_pool(0x11, TokenId::USDC, TokenId::WETH,
+            U256::from(2_000_000_000_000u64), U256::from(800u64) * weth);
+        quickswap.dex = DexId::QUICKSWAP;
+        quickswap.protocol_fee_enabled = true;
+        let weighted = crate::pool::test_pool(0x12, TokenId::USDC, TokenId::WETH,
+            U256::from(1_000_000_000_000u64), U256::from(400u64) * weth)
+            .into_weighted(B256::repeat_byte(0x42), [
+                (TokenId::USDC, U256::from(200_000_000_000_000_000u64)),
+                (TokenId::WETH, U256::from(800_000_000_000_000_000u64)),
+            ], U256::from(3_000_000_000_000_000u64));
+        vec![quickswap, weighted]
+    }
+
+    #[tokio::test]
+    async fn saved_snapshot_round_trips_and_quotes_identically() {
+        let pools = market();
+        let snapshot = MarketSnapshot::from_pools(&pools, 65_000_000, 1_760_000_000);
+        assert_eq!(snapshot.tokens.len(), 2);
+
+        let path = std::env::temp_dir().join(format!("market_snapshot_{}.bin", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = MarketSnapshot::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let restored = loaded.to_pools();
+        for (original, restored) in pools.iter().zip(&restored) {
+            assert_eq!(restored.dex, original.dex);
+            assert_eq!(restored.kind, original.kind);
+            assert_eq!((restored.reserve_token0, restored.reserve_token1), (original.reserve_token0, original.reserve_token1));
+            assert_eq!(restored.protocol_fee_enabled, original.protocol_fee_enabled);
+        }
+
+        let ctx = ConfigContext::default();
+        let solver_config = SolverConfig { num_chunks: 20, verbose: false, ..Default::default() };
+        let live = find_best_routes(pools, &ctx, &solver_config).await.unwrap();
+        let offline = find_best_routes(restored, &ctx, &solver_config).await.unwrap();
+        assert_eq!(offline.total_weth_out, live.total_weth_out);
+        let pool_names = |result: &crate::solver::SolverResult| -> Vec<String> {
+            result.chunk_routes.iter().map(|route| route.best_pool_name.clone()).collect()
+        };
+        assert_eq!(pool_names(&offline), pool_names(&live));
+    }
+
+    #[test]
+    fn pool_fee_survives_round_trip() {
+        let mut pools = market();
+        pools[0].fee_bps = 17;
+        pools[1].dex = DexId("Custom DEX");
+        pools[1].kind = PoolKind::ConstantProduct;
+        pools[1].fee_bps = 5;
+
+        let bytes = MarketSnapshot::from_pools(&pools, 1, 2).to_bytes().unwrap();
+        let restored = MarketSnapshot::from_bytes(&bytes).unwrap().to_pools();
+        assert_eq!(restored[0].fee_bps, 17);
+        assert_eq!((restored[1].dex.0, restored[1].fee_bps), ("Custom DEX", 5));
+    }
+
+    #[test]
+    fn reserve_age_survives_round_trip() {
+        let mut pools = market();
+        pools[0].last_updated = 1_760_000_000 - 7_200;
+        let bytes = MarketSnapshot::from_pools(&pools, 1, 1_760_000_000).to_bytes().unwrap();
+        let restored = MarketSnapshot::from_bytes(&bytes).unwrap().to_pools();
+        assert_eq!(restored[0].last_updated, pools[0].last_updated);
+        assert_eq!(restored[0].reserves_age_secs(1_760_000_000), Some(7_200));
+        assert_eq!(restored[1].reserves_age_secs(1_760_000_000), None);
+    }
+
+    #[test]
+    fn version_1_files_get_default_dex_fee() {
+        let current = MarketSnapshot::from_pools(&market(), 65_000_000, 1_760_000_000);
+        let v1 = MarketSnapshotV1 {
+            block_number: current.block_number,
+            timestamp: current.timestamp,
+            tokens: current.tokens.clone(),
+            pools: current
+                .pools
+                .iter()
+                .map(|pool| SnapshotPoolV1 {
+                    address: pool.address,
+                    dex: pool.dex.clone(),
+                    name: pool.name.clone(),
+                    token0: pool.token0,
+                    token1: pool.token1,
+                    reserve0: pool.reserve0,
+                    reserve1: pool.reserve1,
+                    kind: pool.kind,
+                    protocol_fee_enabled: pool.protocol_fee_enabled,
+                })
+                .collect(),
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&postcard::to_stdvec(&v1).unwrap());
+
+        let loaded = MarketSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.pools.len(), current.pools.len());
+        assert_eq!(loaded.pools[0].reserve0, current.pools[0].reserve0);
+        assert_eq!(loaded.pools[0].fee_bps, dex_fee_bps(&default_dexes(), DexId::QUICKSWAP));
+        assert_eq!(loaded.pools[0].last_updated, 0);
+    }
+
+    #[test]
+    fn version_2_files_load_without_override_marker() {
+        let current = MarketSnapshot::from_pools(&market(), 65_000_000, 1_760_000_000);
+        let v2 = MarketSnapshotV2 {
+            block_number: current.block_number,
+            timestamp: current.timestamp,
+            tokens: current.tokens.clone(),
+            pools: current
+                .pools
+                .iter()
+                .map(|pool| SnapshotPoolV2 {
+                    address: pool.address,
+                    dex: pool.dex.clone(),
+                    name: pool.name.clone(),
+                    token0: pool.token0,
+                    token1: pool.token1,
+                    reserve0: pool.reserve0,
+                    reserve1: pool.reserve1,
+                    kind: pool.kind,
+                    protocol_fee_enabled: pool.protocol_fee_enabled,
+                    fee_bps: pool.fee_bps,
+                    last_updated: pool.last_updated,
+                })
+                .collect(),
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&postcard::to_stdvec(&v2).unwrap());
+
+        assert_eq!(MarketSnapshot::from_bytes(&bytes).unwrap(), current);
+    }
+
+    #[test]
+    fn override_marker_survives_round_trip() {
+        let mut pools = market();
+        pools[0].override_reserves(U256::from(1_000u64), U256::from(2_000u64), 1_760_000_000).unwrap();
+        let bytes = MarketSnapshot::from_pools(&pools, 1, 2).to_bytes().unwrap();
+        let restored = MarketSnapshot::from_bytes(&bytes).unwrap().to_pools();
+        assert!(restored[0].reserves_overridden);
+        assert!(!restored[1].reserves_overridden);
+    }
+
+    #[test]
+    fn binary_format_is_smaller_than_json() {
+        let snapshot = MarketSnapshot::from_pools(&market(), 1, 2);
+        let binary = snapshot.to_bytes().unwrap();
+        let json = serde_json::to_vec(&snapshot).unwrap();
+        assert!(binary.len() * 2 < json.len(), "{} vs {}", binary.len(), json.len());
+    }
+
+    #[test]
+    fn rejects_newer_version_and_foreign_files() {
+        let mut bytes = MarketSnapshot::from_pools(&market(), 1, 2).to_bytes().unwrap();
+        bytes[4..6].copy_from_slice(&(MARKET_SNAPSHOT_VERSION + 1).to_le_bytes());
+        let error = MarketSnapshot::from_bytes(&bytes).unwrap_err().to_string();
+        assert!(error.contains("новее"), "{}", error);
+
+        assert!(MarketSnapshot::from_bytes(b"{\"json\": true}").is_err());
+        assert!(MarketSnapshot::from_bytes(b"SAM").is_err());
+    }
+}
@@ -1,10 +1,27 @@
-use alloy::primitives::U256;
+use alloy::primitives::{Uint, U256, U512};
+use std::cmp::Ordering;
+
+pub mod accumulator;
+pub mod stableswap;
+pub mod v3;
+pub mod weighted;
+
+/// Wide integer for intermediate products that may not fit even in 512 bits
+/// (e.g. `amount_in * 10000 * reserve_out` with all operands near 2^256).
+type U768 = Uint<768, 12>;
+
+/// Denominator for fee values expressed in basis points (1 bps = 0.01%).
+pub const BPS_DENOMINATOR: u32 = 10_000;
+
+/// Default Uniswap V2 trading fee in basis points (0.3%).
+pub const DEFAULT_FEE_BPS: u32 = 30;
 
 /// Calculates the output amount based on the Uniswap V2 formula.
 /// 
 /// Formula: amountOut = (amountIn * 997 * reserveOut) / (reserveIn * 1000 + amountIn * 997)
 /// 
 /// The 997/1000 factor accounts for the 0.3% trading fee (1000 - 3 = 997).
+/// Thin wrapper over [`get_amount_out_with_fee`] with `fee_bps = 30`.
 /// 
 /// # Arguments
 /// * `amount_in` - Amount of input tokens
@@ -17,6 +34,46 @@ pub fn get_amount_out(
     amount_in: U256,
     reserve_in: U256,
     reserve_out: U256,
+) -> U256 {
+    get_amount_out_with_fee(amount_in, reserve_in, reserve_out, DEFAULT_FEE_BPS)
+}
+
+/// Smallest input for which `amount_in * fee_bps / 10000` is non-zero.
+/// 
+/// Below this size the fee, computed separately in integer math, rounds to
+/// zero even though the swap formula itself still charges it.
+/// 
+/// # Returns
+/// `ceil(10000 / fee_bps)`, or `None` for a zero fee
+pub fn min_amount_with_nonzero_fee(fee_bps: u32) -> Option<U256> {
+    (fee_bps > 0).then(|| U256::from(BPS_DENOMINATOR.div_ceil(fee_bps)))
+}
+
+/// Calculates the Uniswap V2 output amount for an arbitrary fee in basis points.
+/// 
+/// Formula: amountOut = (amountIn * (10000 - fee) * reserveOut) / (reserveIn * 10000 + amountIn * (10000 - fee))
+/// 
+/// With `fee_bps = 30` the result is identical to the classic 997/1000 formula,
+/// since both numerator and denominator are simply scaled by 10.
+/// 
+/// For realistic reserves (uint112 on-chain) the intermediate product stays well
+/// below 2^256: amountIn * 10^4 * reserveOut < 2^112 * 2^14 * 2^112 = 2^238.
+/// Larger values are handled with wide intermediate arithmetic, so the function
+/// never panics and always returns the exact floor.
+/// 
+/// # Arguments
+/// * `amount_in` - Amount of input tokens
+/// * `reserve_in` - Reserve of input tokens in the pool
+/// * `reserve_out` - Reserve of output tokens in the pool
+/// * `fee_bps` - Trading fee in basis points (30 = 0.3%)
+/// 
+/// # Returns
+/// Amount of output tokens that will be received, or zero if `fee_bps >= 10000`
+pub fn get_amount_out_with_fee(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee_bps: u32,
 ) -> U256 {
     // Проверка на нулевые резервы - если один из резервов равен нулю,
     // то пул неликвиден и обмен невозможен
@@ -24,17 +81,634 @@ pub fn get_amount_out(
         return U256::ZERO;
     }
 
-    // amountIn * 997 (учет комиссии 0.3%)
-    let amount_in_with_fee = amount_in * U256::from(997);
-    
-    // Числитель: amountIn * 997 * reserveOut
-    let numerator = amount_in_with_fee * reserve_out;
-    
-    // Знаменатель: reserveIn * 1000 + amountIn * 997
-    let denominator = (reserve_in * U256::from(1000)) + amount_in_with_fee;
-    
-    // Финальный расчет: numerator / denominator
-    numerator / denominator
+    // Комиссия 100% и выше означает, что на выходе ничего не остается
+    if fee_bps >= BPS_DENOMINATOR {
+        return U256::ZERO;
+    }
+
+    let fee_multiplier = U256::from(BPS_DENOMINATOR - fee_bps);
+
+    // Быстрый путь: для реальных резервов (uint112) все промежуточные
+    // значения помещаются в U256
+    if let Some(reserve_in_scaled) = reserve_in.checked_mul(U256::from(BPS_DENOMINATOR)) {
+        if let Some(amount_out) =
+            get_amount_out_precomputed(amount_in, reserve_in_scaled, reserve_out, fee_multiplier)
+        {
+            return amount_out;
+        }
+    }
+
+    // Медленный путь: считаем в широкой арифметике, чтобы не было переполнения.
+    // Результат всегда < reserve_out, поэтому помещается обратно в U256
+    let amount_in_with_fee = U768::from(amount_in) * U768::from(fee_multiplier);
+    let numerator = amount_in_with_fee * U768::from(reserve_out);
+    let denominator = U768::from(reserve_in) * U768::from(BPS_DENOMINATOR) + amount_in_with_fee;
+
+    (numerator / denominator).to::<U256>()
+}
+
+/// Calculates the input required to receive `amount_out` from a V2 pool
+/// (Uniswap V2 `getAmountIn`, generalized to any fee).
+/// 
+/// Formula: amountIn = floor(reserveIn * amountOut * 10000 / ((reserveOut - amountOut) * (10000 - fee))) + 1
+/// 
+/// Like the router, the result is rounded up, so swapping it through
+/// [`get_amount_out_with_fee`] always yields at least `amount_out`.
+/// Intermediate products are computed in wide arithmetic.
+/// 
+/// # Arguments
+/// * `amount_out` - Desired amount of output tokens
+/// * `reserve_in` - Reserve of input tokens in the pool
+/// * `reserve_out` - Reserve of output tokens in the pool
+/// * `fee_bps` - Trading fee in basis points (30 = 0.3%)
+/// 
+/// # Returns
+/// Required input (zero for a zero output), or `None` if the pool is empty,
+/// the fee is 100% and more, `amount_out` is not below `reserve_out`
+/// or the input does not fit in U256
+pub fn get_amount_in(amount_out: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> Option<U256> {
+    if reserve_in == U256::ZERO || reserve_out == U256::ZERO || fee_bps >= BPS_DENOMINATOR || amount_out >= reserve_out {
+        return None;
+    }
+    if amount_out == U256::ZERO {
+        return Some(U256::ZERO);
+    }
+
+    let numerator = U768::from(reserve_in) * U768::from(amount_out) * U768::from(BPS_DENOMINATOR);
+    let denominator = U768::from(reserve_out - amount_out) * U768::from(BPS_DENOMINATOR - fee_bps);
+    let amount_in = numerator / denominator + U768::from(1u64);
+
+    (amount_in <= U768::from(U256::MAX)).then(|| amount_in.to::<U256>())
+}
+
+/// Updates a reserve pair after a swap: the input is added, the output removed.
+/// 
+/// Used by [`apply_swap`]. The input side saturates at
+/// `U256::MAX` and the output side at zero, so inconsistent arguments never panic.
+/// 
+/// # Returns
+/// `(new_reserve_in, new_reserve_out)`
+pub fn update_reserves(reserve_in: U256, reserve_out: U256, amount_in: U256, amount_out: U256) -> (U256, U256) {
+    (reserve_in.saturating_add(amount_in), reserve_out.saturating_sub(amount_out))
+}
+
+/// Performs a V2 swap on a reserve pair.
+/// 
+/// # Returns
+/// `(amount_out, new_reserve_in, new_reserve_out)`
+pub fn apply_swap(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> (U256, U256, U256) {
+    let amount_out = get_amount_out_with_fee(amount_in, reserve_in, reserve_out, fee_bps);
+    let (new_reserve_in, new_reserve_out) = update_reserves(reserve_in, reserve_out, amount_in, amount_out);
+    (amount_out, new_reserve_in, new_reserve_out)
+}
+
+/// Computes `floor(a * b / denominator)` with a full 512-bit intermediate product.
+/// 
+/// # Returns
+/// `None` if `denominator` is zero or the result does not fit in U256
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator == U256::ZERO {
+        return None;
+    }
+
+    let product: U512 = a.widening_mul(b);
+    let result = product / U512::from(denominator);
+
+    if result > U512::from(U256::MAX) {
+        None
+    } else {
+        Some(result.to::<U256>())
+    }
+}
+
+/// Calculates the Uniswap V2 output amount from precomputed pool terms.
+/// 
+/// Bit-identical to [`get_amount_out_with_fee`] when
+/// `reserve_in_scaled = reserve_in * 10000` and `fee_multiplier = 10000 - fee_bps`,
+/// but avoids recomputing these terms on every quote.
+/// 
+/// # Arguments
+/// * `amount_in` - Amount of input tokens
+/// * `reserve_in_scaled` - Reserve of input tokens multiplied by 10000
+/// * `reserve_out` - Reserve of output tokens in the pool
+/// * `fee_multiplier` - 10000 - fee_bps
+/// 
+/// # Returns
+/// Amount of output tokens that will be received, or `None` if an intermediate
+/// value overflows U256 (callers should fall back to [`get_amount_out_with_fee`])
+pub fn get_amount_out_precomputed(
+    amount_in: U256,
+    reserve_in_scaled: U256,
+    reserve_out: U256,
+    fee_multiplier: U256,
+) -> Option<U256> {
+    if reserve_in_scaled == U256::ZERO || reserve_out == U256::ZERO || fee_multiplier == U256::ZERO {
+        return Some(U256::ZERO);
+    }
+
+    let amount_in_with_fee = amount_in.checked_mul(fee_multiplier)?;
+    let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+    let denominator = reserve_in_scaled.checked_add(amount_in_with_fee)?;
+
+    Some(numerator / denominator)
+}
+
+/// Converts a U256 to the nearest representable f64 (lossy for values above 2^53).
+pub fn u256_to_f64(value: U256) -> f64 {
+    uint_to_f64(value)
+}
+
+/// Converts an unsigned integer of any width to f64 limb by limb.
+fn uint_to_f64<const BITS: usize, const LIMBS: usize>(value: Uint<BITS, LIMBS>) -> f64 {
+    value
+        .as_limbs()
+        .iter()
+        .rev()
+        .fold(0.0, |acc, &limb| acc * 18446744073709551616.0 + limb as f64)
+}
+
+/// Calculates the exact decimal-adjusted spot (marginal, pre-trade) price as a rational.
+/// 
+/// The price is expressed as units of output token per one unit of input token:
+/// price = (reserveOut / 10^decimalsOut) / (reserveIn / 10^decimalsIn)
+///       = reserveOut * 10^decimalsIn / (reserveIn * 10^decimalsOut)
+/// 
+/// # Arguments
+/// * `reserve_in` - Reserve of input tokens in the pool
+/// * `reserve_out` - Reserve of output tokens in the pool
+/// * `decimals_in` - Decimals of the input token
+/// * `decimals_out` - Decimals of the output token
+/// 
+/// # Returns
+/// `(numerator, denominator)` or `None` if one of the reserves is zero
+pub fn spot_price_rational(
+    reserve_in: U256,
+    reserve_out: U256,
+    decimals_in: u8,
+    decimals_out: u8,
+) -> Option<(U256, U256)> {
+    if reserve_in == U256::ZERO || reserve_out == U256::ZERO {
+        return None;
+    }
+
+    let scale_in = U256::from(10u64).pow(U256::from(decimals_in));
+    let scale_out = U256::from(10u64).pow(U256::from(decimals_out));
+
+    Some((reserve_out.checked_mul(scale_in)?, reserve_in.checked_mul(scale_out)?))
+}
+
+/// Calculates the decimal-adjusted spot price (output token per input token) as f64.
+/// 
+/// Without the decimal adjustment a USDC (6) / WETH (18) pool would be off by 10^12.
+/// 
+/// # Returns
+/// Spot price, or `0.0` if one of the reserves is zero
+pub fn spot_price(
+    reserve_in: U256,
+    reserve_out: U256,
+    decimals_in: u8,
+    decimals_out: u8,
+) -> f64 {
+    if reserve_in == U256::ZERO || reserve_out == U256::ZERO {
+        return 0.0;
+    }
+
+    let ratio = u256_to_f64(reserve_out) / u256_to_f64(reserve_in);
+    ratio * 10f64.powi(decimals_in as i32 - decimals_out as i32)
+}
+
+/// Calculates the decimal-adjusted execution price of a filled trade
+/// as input token per one output token (e.g. USDC per WETH).
+/// 
+/// # Returns
+/// Execution price, or `None` if nothing was received
+pub fn execution_price(amount_in: U256, amount_out: U256, decimals_in: u8, decimals_out: u8) -> Option<f64> {
+    if amount_out == U256::ZERO {
+        return None;
+    }
+    Some(spot_price(amount_out, amount_in, decimals_out, decimals_in))
+}
+
+/// Deviation of an execution price from a reference price in basis points.
+/// 
+/// Both prices are input token per output token, so paying more than the
+/// reference gives a negative deviation: (reference / execution - 1) * 10000.
+/// 
+/// # Returns
+/// Deviation in bps, or `None` if either price is not positive
+pub fn price_deviation_bps(execution_price: f64, reference_price: f64) -> Option<f64> {
+    if execution_price <= 0.0 || reference_price <= 0.0 {
+        return None;
+    }
+    Some((reference_price / execution_price - 1.0) * BPS_DENOMINATOR as f64)
+}
+
+/// Calculates the price impact of a trade: 1 - executionPrice / spotPrice.
+/// 
+/// Uses the exact (unrounded) V2 curve, so the result is monotone in `amount_in`
+/// and not distorted by integer flooring of the output for tiny trades.
+/// With m = 10000 - fee: executionPrice / spotPrice = m * reserveIn / (10000 * reserveIn + amountIn * m),
+/// hence impact = (fee * reserveIn + amountIn * m) / (10000 * reserveIn + amountIn * m).
+/// The fee is part of the impact, so an infinitesimal trade has impact = fee.
+/// Decimals cancel out in the ratio and are not needed.
+/// 
+/// # Arguments
+/// * `amount_in` - Amount of input tokens
+/// * `reserve_in` - Reserve of input tokens in the pool
+/// * `reserve_out` - Reserve of output tokens in the pool
+/// * `fee_bps` - Trading fee in basis points
+/// 
+/// # Returns
+/// Impact in [0, 1]; `1.0` for pools with a zero reserve or fee ≥ 100%, `0.0` for zero input
+pub fn price_impact(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> f64 {
+    if reserve_in == U256::ZERO || reserve_out == U256::ZERO || fee_bps >= BPS_DENOMINATOR {
+        return 1.0;
+    }
+    if amount_in == U256::ZERO {
+        return 0.0;
+    }
+
+    let fee_multiplier = U512::from(BPS_DENOMINATOR - fee_bps);
+    let amount_in_with_fee = U512::from(amount_in) * fee_multiplier;
+    let reserve_in = U512::from(reserve_in);
+
+    let numerator = reserve_in * U512::from(fee_bps) + amount_in_with_fee;
+    let denominator = reserve_in * U512::from(BPS_DENOMINATOR) + amount_in_with_fee;
+
+    uint_to_f64(numerator) / uint_to_f64(denominator)
+}
+
+/// Applies slippage tolerance to a quoted output: `floor(amount * (10000 - bps) / 10000)`.
+/// 
+/// Used for the minimal acceptable output of exact-input swaps (`amountOutMin`).
+/// 
+/// # Returns
+/// `None` if `slippage_bps` exceeds 100%
+pub fn apply_slippage(amount: U256, slippage_bps: u32) -> Option<U256> {
+    if slippage_bps > BPS_DENOMINATOR {
+        return None;
+    }
+    mul_div(amount, U256::from(BPS_DENOMINATOR - slippage_bps), U256::from(BPS_DENOMINATOR))
+}
+
+/// Applies slippage tolerance upwards: `ceil(amount * (10000 + bps) / 10000)`.
+/// 
+/// Used for the maximal acceptable input of exact-output swaps (`amountInMax`).
+/// 
+/// # Returns
+/// `None` if `slippage_bps` exceeds 100% or the result does not fit in U256
+pub fn apply_slippage_up(amount: U256, slippage_bps: u32) -> Option<U256> {
+    if slippage_bps > BPS_DENOMINATOR {
+        return None;
+    }
+    let product: U512 = amount.widening_mul(U256::from(BPS_DENOMINATOR + slippage_bps));
+    let denominator = U512::from(BPS_DENOMINATOR);
+    let result = product.div_ceil(denominator);
+    if result > U512::from(U256::MAX) {
+        None
+    } else {
+        Some(result.to::<U256>())
+    }
+}
+
+/// Calculates the largest input whose [`price_impact`] stays within a budget.
+/// 
+/// Solves the impact formula for the input analytically:
+/// impact ≤ B / 10000  ⇔  amountIn * m * (10000 - B) ≤ 10000 * reserveIn * (B - fee),
+/// so the answer is `floor(10000 * reserveIn * (B - fee) / (m * (10000 - B)))`.
+/// Since the fee is part of the impact, budgets at or below the fee allow no input.
+/// 
+/// # Arguments
+/// * `reserve_in` - Reserve of input tokens in the pool
+/// * `reserve_out` - Reserve of output tokens in the pool
+/// * `max_impact_bps` - Impact budget in basis points
+/// * `fee_bps` - Trading fee in basis points
+/// 
+/// # Returns
+/// Maximal input; zero for empty pools or budgets not above the fee,
+/// `U256::MAX` for budgets of 100% and more
+pub fn max_input_for_impact(reserve_in: U256, reserve_out: U256, max_impact_bps: u32, fee_bps: u32) -> U256 {
+    if reserve_in == U256::ZERO || reserve_out == U256::ZERO || fee_bps >= BPS_DENOMINATOR {
+        return U256::ZERO;
+    }
+    if max_impact_bps >= BPS_DENOMINATOR {
+        return U256::MAX;
+    }
+    if max_impact_bps <= fee_bps {
+        return U256::ZERO;
+    }
+
+    let numerator = U512::from(reserve_in)
+        * U512::from(BPS_DENOMINATOR)
+        * U512::from(max_impact_bps - fee_bps);
+    let denominator = U512::from(BPS_DENOMINATOR - fee_bps) * U512::from(BPS_DENOMINATOR - max_impact_bps);
+
+    (numerator / denominator).min(U512::from(U256::MAX)).to::<U256>()
+}
+
+/// Calculates the marginal rate d(amountOut)/d(amountIn) of the V2 curve
+/// after `allocated_in` has already been swapped into the pool.
+/// 
+/// With m = 10000 - fee and out(x) = x * m * reserveOut / (reserveIn * 10000 + x * m):
+/// out'(x) = m * 10000 * reserveIn * reserveOut / (reserveIn * 10000 + x * m)^2
+/// 
+/// The result is an exact rational so pools can be compared without f64
+/// (see [`cmp_rational`]).
+/// 
+/// # Arguments
+/// * `allocated_in` - Input already allocated to the pool
+/// * `reserve_in` - Reserve of input tokens in the pool (before the allocation)
+/// * `reserve_out` - Reserve of output tokens in the pool (before the allocation)
+/// * `fee_bps` - Trading fee in basis points
+/// 
+/// # Returns
+/// `(numerator, denominator)`, or `None` for empty pools, fee ≥ 100%
+/// or if the terms do not fit in U256 (reserves far beyond uint112)
+pub fn marginal_rate(
+    allocated_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee_bps: u32,
+) -> Option<(U256, U256)> {
+    if reserve_in == U256::ZERO || reserve_out == U256::ZERO || fee_bps >= BPS_DENOMINATOR {
+        return None;
+    }
+
+    let fee_multiplier = U256::from(BPS_DENOMINATOR - fee_bps);
+    let reserve_in_scaled = reserve_in.checked_mul(U256::from(BPS_DENOMINATOR))?;
+
+    let numerator = fee_multiplier
+        .checked_mul(reserve_in_scaled)?
+        .checked_mul(reserve_out)?;
+    let base = reserve_in_scaled.checked_add(allocated_in.checked_mul(fee_multiplier)?)?;
+    let denominator = base.checked_mul(base)?;
+
+    Some((numerator, denominator))
+}
+
+/// Compares two non-negative rationals `a.0 / a.1` and `b.0 / b.1` exactly
+/// using 512-bit cross multiplication.
+pub fn cmp_rational(a: (U256, U256), b: (U256, U256)) -> Ordering {
+    let lhs: U512 = a.0.widening_mul(b.1);
+    let rhs: U512 = b.0.widening_mul(a.1);
+    lhs.cmp(&rhs)
+}
+
+/// Chains [`get_amount_out_with_fee`] across a sequence of hops, like the
+/// Uniswap V2 router's `getAmountsOut`.
+/// 
+/// # Arguments
+/// * `amount_in` - Amount of input tokens for the first hop
+/// * `path` - Hops as `(reserve_in, reserve_out, fee_bps)`, in swap order
+/// 
+/// # Returns
+/// `path.len() + 1` amounts: the input followed by the output of every hop
+pub fn get_amounts_out(amount_in: U256, path: &[(U256, U256, u32)]) -> Vec<U256> {
+    let mut amounts = Vec::with_capacity(path.len() + 1);
+    amounts.push(amount_in);
+
+    let mut current = amount_in;
+    for &(reserve_in, reserve_out, fee_bps) in path {
+        current = get_amount_out_with_fee(current, reserve_in, reserve_out, fee_bps);
+        amounts.push(current);
+    }
+
+    amounts
+}
+
+/// Computes the output-maximizing split of `amount_in` between two V2 pools.
+/// 
+/// At the optimum the marginal rates of both pools are equal. With γ = m / 10000
+/// (m = 10000 - fee), sA = sqrt(reserveInA * reserveOutA) and sB likewise, equating
+/// out'_A(x) = out'_B(T - x) gives:
+/// 
+/// x = (sA * (reserveInB * 10000 + m * T) - sB * reserveInA * 10000) / (m * (sA + sB))
+/// 
+/// The result is clamped to [0, T], which covers the degenerate cases where the whole
+/// input should go to one pool. Square roots are integer floors over 512-bit products.
+/// 
+/// # Arguments
+/// * `amount_in` - Total input T
+/// * `pool_a` - `(reserve_in, reserve_out)` of pool A
+/// * `pool_b` - `(reserve_in, reserve_out)` of pool B
+/// * `fee_bps` - Trading fee in basis points (shared by both pools)
+/// 
+/// # Returns
+/// `(amount_to_a, amount_to_b)`, always summing to `amount_in`
+pub fn optimal_split_two(
+    amount_in: U256,
+    pool_a: (U256, U256),
+    pool_b: (U256, U256),
+    fee_bps: u32,
+) -> (U256, U256) {
+    let a_empty = pool_a.0 == U256::ZERO || pool_a.1 == U256::ZERO;
+    let b_empty = pool_b.0 == U256::ZERO || pool_b.1 == U256::ZERO;
+    if b_empty || fee_bps >= BPS_DENOMINATOR {
+        return (amount_in, U256::ZERO);
+    }
+    if a_empty {
+        return (U256::ZERO, amount_in);
+    }
+
+    let sqrt_a = pool_a.0.widening_mul::<256, 4, 512, 8>(pool_a.1).root(2);
+    let sqrt_b = pool_b.0.widening_mul::<256, 4, 512, 8>(pool_b.1).root(2);
+
+    let fee_multiplier = U768::from(BPS_DENOMINATOR - fee_bps);
+    let scale = U768::from(BPS_DENOMINATOR);
+    let total = U768::from(amount_in);
+    let sqrt_a = U768::from(sqrt_a);
+    let sqrt_b = U768::from(sqrt_b);
+
+    let lhs = sqrt_a * (U768::from(pool_b.0) * scale + fee_multiplier * total);
+    let rhs = sqrt_b * U768::from(pool_a.0) * scale;
+
+    let to_a = if lhs <= rhs {
+        U256::ZERO
+    } else {
+        let x = (lhs - rhs) / (fee_multiplier * (sqrt_a + sqrt_b));
+        x.min(total).to::<U256>()
+    };
+
+    (to_a, amount_in - to_a)
+}
+
+/// Computes the smallest input that pushes a V2 pool's price down to a target.
+/// 
+/// The pool price is `reserve_out / reserve_in` in raw units, and every swap of
+/// input lowers it. The no-fee closed form
+/// `sqrt(reserve_in * reserve_out * den / num) - reserve_in` (integer sqrt over
+/// 768 bits) gives the starting point; because the fee
+/// stays in the pool the real answer is slightly larger, so the bound is widened
+/// until it is reached and then narrowed by binary search on exact swap results.
+/// Converting a human price into raw units (decimals) is left to the caller.
+/// 
+/// # Arguments
+/// * `reserve_in` / `reserve_out` - Pool reserves for the swap direction
+/// * `target_price_num` / `target_price_den` - Target price as a fraction (out per in)
+/// * `fee_bps` - Trading fee in basis points
+/// 
+/// # Returns
+/// Minimal input after which the price is at or below the target; `Some(0)` if the
+/// pool is exactly at the target and `None` if it is already past it, the pool is
+/// empty, the target is degenerate or the input would not fit into U256.
+pub fn amount_in_to_reach_price(
+    reserve_in: U256,
+    reserve_out: U256,
+    target_price_num: U256,
+    target_price_den: U256,
+    fee_bps: u32,
+) -> Option<U256> {
+    if reserve_in == U256::ZERO
+        || reserve_out == U256::ZERO
+        || target_price_num == U256::ZERO
+        || target_price_den == U256::ZERO
+        || fee_bps >= BPS_DENOMINATOR
+    {
+        return None;
+    }
+
+    // Цена пула после свапа x не выше цели: (Rout - out) * den <= num * (Rin + x)
+    let reached = |amount_in: U256| -> Option<bool> {
+        let amount_out = get_amount_out_with_fee(amount_in, reserve_in, reserve_out, fee_bps);
+        let new_reserve_in = reserve_in.checked_add(amount_in)?;
+        let lhs: U512 = (reserve_out - amount_out).widening_mul(target_price_den);
+        let rhs: U512 = target_price_num.widening_mul(new_reserve_in);
+        Some(lhs <= rhs)
+    };
+
+    match cmp_rational((reserve_out, reserve_in), (target_price_num, target_price_den)) {
+        Ordering::Less => return None,
+        Ordering::Equal => return Some(U256::ZERO),
+        Ordering::Greater => {}
+    }
+
+    let k_scaled = U768::from(reserve_in) * U768::from(reserve_out) * U768::from(target_price_den);
+    let new_reserve_in = (k_scaled / U768::from(target_price_num)).root(2);
+    let estimate = new_reserve_in.saturating_sub(U768::from(reserve_in));
+    if estimate > U768::from(U256::MAX) {
+        return None;
+    }
+
+    let mut low = U256::ZERO;
+    let mut high = estimate.to::<U256>().max(U256::from(1u64));
+    while !reached(high)? {
+        low = high;
+        high = high.checked_mul(U256::from(2u64))?;
+    }
+    while low < high {
+        let mid = low + (high - low) / U256::from(2u64);
+        if reached(mid)? {
+            high = mid;
+        } else {
+            low = mid + U256::from(1u64);
+        }
+    }
+
+    Some(low)
+}
+
+/// Fixed-point scale of the common marginal price in [`optimal_split_n`]
+const MARGINAL_PRICE_SCALE: u128 = 1_000_000_000_000_000_000;
+
+/// Allocates `amount_in` across V2 pools so that their post-trade marginal rates are equal.
+/// 
+/// For a common marginal rate λ each pool receives the input that brings its marginal
+/// rate down to λ (see [`marginal_rate`]):
+/// 
+/// x(λ) = (sqrt(m * 10000 * reserveIn * reserveOut / λ) - reserveIn * 10000) / m
+/// 
+/// clamped at zero, so pools whose spot rate is already below λ get nothing. The total
+/// allocation is non-increasing in λ, and λ (fixed point, scaled by 10^18) is found by
+/// integer binary search as the smallest value whose allocations fit into `amount_in`.
+/// The few units left over by rounding go to the pool with the best remaining marginal rate.
+/// When there is nothing to equalize (every pool is empty or the fee is 100%), the whole
+/// amount goes to the first non-empty pool, or to the first pool if all are empty.
+/// 
+/// # Arguments
+/// * `amount_in` - Total input
+/// * `pools` - `(reserve_in, reserve_out)` of each pool
+/// * `fee_bps` - Trading fee in basis points (shared by all pools)
+/// 
+/// # Returns
+/// Allocation per pool in the same order as `pools`. Always sums to `amount_in` when
+/// `pools` is non-empty.
+pub fn optimal_split_n(amount_in: U256, pools: &[(U256, U256)], fee_bps: u32) -> Vec<U256> {
+    let mut allocations = vec![U256::ZERO; pools.len()];
+    if pools.is_empty() {
+        return allocations;
+    }
+
+    let fee_multiplier = U768::from(BPS_DENOMINATOR - fee_bps);
+    let scale = U768::from(BPS_DENOMINATOR);
+    let price_scale = U768::from(MARGINAL_PRICE_SCALE);
+    let total = U768::from(amount_in);
+    let active: Vec<(usize, U768, U768)> = pools
+        .iter()
+        .enumerate()
+        .filter(|(_, (reserve_in, reserve_out))| *reserve_in > U256::ZERO && *reserve_out > U256::ZERO)
+        .map(|(index, (reserve_in, reserve_out))| (index, U768::from(*reserve_in), U768::from(*reserve_out)))
+        .collect();
+    if active.is_empty() || fee_bps >= BPS_DENOMINATOR {
+        let first = active.first().map_or(0, |&(index, _, _)| index);
+        allocations[first] = amount_in;
+        return allocations;
+    }
+
+    // Вход, при котором маржинальный курс пула опускается до lambda
+    let allocation_at = |lambda: U768, reserve_in: U768, reserve_out: U768| -> U768 {
+        let target_base = (fee_multiplier * scale * reserve_in * reserve_out * price_scale / lambda).root(2);
+        let base = reserve_in * scale;
+        if target_base <= base {
+            U768::ZERO
+        } else {
+            ((target_base - base) / fee_multiplier).min(total)
+        }
+    };
+    let allocated_at = |lambda: U768| -> U768 {
+        active
+            .iter()
+            .map(|&(_, reserve_in, reserve_out)| allocation_at(lambda, reserve_in, reserve_out))
+            .fold(U768::ZERO, |sum, x| sum + x)
+    };
+
+    // При lambda выше лучшего спот-курса ни один пул не получает вход
+    let mut low = U768::from(1u64);
+    let mut high = active
+        .iter()
+        .map(|&(_, reserve_in, reserve_out)| fee_multiplier * reserve_out * price_scale / (reserve_in * scale))
+        .max()
+        .unwrap_or(U768::ZERO)
+        + U768::from(1u64);
+    while low < high {
+        let mid = low + (high - low) / U768::from(2u64);
+        if allocated_at(mid) <= total {
+            high = mid;
+        } else {
+            low = mid + U768::from(1u64);
+        }
+    }
+
+    for &(index, reserve_in, reserve_out) in &active {
+        allocations[index] = allocation_at(low, reserve_in, reserve_out).to::<U256>();
+    }
+
+    // Бинарный поиск гарантирует, что распределено не больше amount_in
+    let allocated: U256 = allocations.iter().copied().sum();
+    let leftover = amount_in - allocated;
+    if leftover > U256::ZERO {
+        let best = active
+            .iter()
+            .filter_map(|&(index, _, _)| {
+                let (reserve_in, reserve_out) = pools[index];
+                marginal_rate(allocations[index], reserve_in, reserve_out, fee_bps).map(|rate| (index, rate))
+            })
+            .max_by(|a, b| cmp_rational(a.1, b.1))
+            .map(|(index, _)| index)
+            .unwrap_or(active[0].0);
+        allocations[best] += leftover;
+    }
+
+    allocations
 }
 
 #[cfg(test)]
@@ -56,6 +730,18 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn min_amount_with_nonzero_fee_is_exact_threshold() {
+        for fee_bps in [1u32, 5, 25, 30, 100, 10_000] {
+            let min = min_amount_with_nonzero_fee(fee_bps).unwrap();
+            let fee = |amount: U256| amount * U256::from(fee_bps) / U256::from(BPS_DENOMINATOR);
+            assert_eq!(fee(min - U256::from(1u64)), U256::ZERO, "{}", fee_bps);
+            assert!(fee(min) > U256::ZERO, "{}", fee_bps);
+        }
+        assert_eq!(min_amount_with_nonzero_fee(30), Some(U256::from(334u64)));
+        assert_eq!(min_amount_with_nonzero_fee(0), None);
+    }
+
     #[test]
     fn test_get_amount_out_zero_reserves() {
         let amount_in = U256::from(1000u64);
@@ -94,4 +780,545 @@ mod tests {
         let expected_range = U256::from(498_000u64)..U256::from(499_000u64);
         assert!(result >= expected_range.start && result < expected_range.end);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_get_amount_out_with_fee_30_bps_matches_legacy_formula() {
+        let cases = [
+            (1000u64, 100000u64, 100000u64),
+            (1_000_000, 10_000_000_000, 5_000_000_000),
+            (1, 3, 7),
+            (123_456_789, 987_654_321, 555_555_555),
+        ];
+        for (amount_in, reserve_in, reserve_out) in cases {
+            let (a, r_in, r_out) = (U256::from(amount_in), U256::from(reserve_in), U256::from(reserve_out));
+            // Классическая формула 997/1000
+            let legacy = (a * U256::from(997) * r_out) / (r_in * U256::from(1000) + a * U256::from(997));
+            assert_eq!(get_amount_out_with_fee(a, r_in, r_out, 30), legacy);
+            assert_eq!(get_amount_out(a, r_in, r_out), legacy);
+        }
+    }
+
+    #[test]
+    fn test_get_amount_out_with_fee_various_bps() {
+        let amount_in = U256::from(1000u64);
+        let reserve = U256::from(100000u64);
+
+        // 0 bps: 1000 * 100000 / (100000 + 1000) = 990
+        assert_eq!(get_amount_out_with_fee(amount_in, reserve, reserve, 0), U256::from(990u64));
+        // 25 bps: 1000 * 9975 * 100000 / (100000 * 10000 + 1000 * 9975) ≈ 987.6
+        assert_eq!(get_amount_out_with_fee(amount_in, reserve, reserve, 25), U256::from(987u64));
+        // 30 bps: совпадает с базовым тестом
+        assert_eq!(get_amount_out_with_fee(amount_in, reserve, reserve, 30), U256::from(987u64));
+        // 100 bps: 1000 * 9900 * 100000 / (100000 * 10000 + 1000 * 9900) ≈ 980.2
+        assert_eq!(get_amount_out_with_fee(amount_in, reserve, reserve, 100), U256::from(980u64));
+
+        // Меньшая комиссия всегда дает не меньший выход
+        let large_in = U256::from(1_000_000_000u64);
+        let r_in = U256::from(50_000_000_000u64);
+        let r_out = U256::from(20_000_000_000u64);
+        let out_0 = get_amount_out_with_fee(large_in, r_in, r_out, 0);
+        let out_25 = get_amount_out_with_fee(large_in, r_in, r_out, 25);
+        let out_30 = get_amount_out_with_fee(large_in, r_in, r_out, 30);
+        let out_100 = get_amount_out_with_fee(large_in, r_in, r_out, 100);
+        assert!(out_0 > out_25 && out_25 > out_30 && out_30 > out_100);
+    }
+
+    #[test]
+    fn test_get_amount_out_with_fee_invalid_fee() {
+        let amount_in = U256::from(1000u64);
+        let reserve = U256::from(100000u64);
+
+        assert_eq!(get_amount_out_with_fee(amount_in, reserve, reserve, 10_000), U256::ZERO);
+        assert_eq!(get_amount_out_with_fee(amount_in, reserve, reserve, u32::MAX), U256::ZERO);
+    }
+
+    #[test]
+    fn test_get_amount_out_with_fee_realistic_18_decimal_reserves() {
+        // Резервы на пределе uint112 и вход того же порядка не должны переполнять U256
+        let max_reserve = (U256::from(1u64) << 112) - U256::from(1u64);
+        let result = get_amount_out_with_fee(max_reserve, max_reserve, max_reserve, 0);
+        // При amount_in == reserve_in без комиссии выход равен половине резерва
+        assert_eq!(result, max_reserve / U256::from(2u64));
+
+        // 1M WETH в пул с 100k WETH / 100k WETH-подобным токеном (18 decimals)
+        let one_token = U256::from(10u64).pow(U256::from(18u64));
+        let reserve = U256::from(100_000u64) * one_token;
+        let amount_in = U256::from(1_000_000u64) * one_token;
+        let result = get_amount_out_with_fee(amount_in, reserve, reserve, 30);
+        assert!(result < reserve);
+        assert!(result > reserve * U256::from(9u64) / U256::from(10u64));
+    }
+
+    #[test]
+    fn test_get_amount_out_huge_inputs_do_not_overflow() {
+        // Резервы и вход порядка 2^120..2^200: промежуточное произведение выходит за U256
+        for shift in [120usize, 128, 160, 200] {
+            let reserve = U256::from(1u64) << shift;
+
+            // При amount_in == reserve_in == reserve_out:
+            // out = R * 9970 * R / (R * 10000 + R * 9970) = R * 9970 / 19970
+            let result = get_amount_out_with_fee(reserve, reserve, reserve, 30);
+            let expected = reserve * U256::from(9970u64) / U256::from(19970u64);
+            assert_eq!(result, expected, "shift = {}", shift);
+
+            // Без комиссии ровно половина резерва
+            let result = get_amount_out_with_fee(reserve, reserve, reserve, 0);
+            assert_eq!(result, reserve / U256::from(2u64), "shift = {}", shift);
+        }
+    }
+
+    #[test]
+    fn test_get_amount_out_max_values() {
+        // Даже на U256::MAX функция не паникует и возвращает значение < reserve_out
+        let result = get_amount_out_with_fee(U256::MAX, U256::MAX, U256::MAX, 30);
+        assert!(result < U256::MAX);
+        assert_eq!(result, U256::MAX / U256::from(19970u64) * U256::from(9970u64)
+            + (U256::MAX % U256::from(19970u64)) * U256::from(9970u64) / U256::from(19970u64));
+    }
+
+    #[test]
+    fn test_mul_div() {
+        let big = U256::from(1u64) << 200;
+        assert_eq!(mul_div(big, big, big), Some(big));
+        assert_eq!(mul_div(U256::from(10u64), U256::from(3u64), U256::from(4u64)), Some(U256::from(7u64)));
+        assert_eq!(mul_div(big, big, U256::from(1u64)), None);
+        assert_eq!(mul_div(big, big, U256::ZERO), None);
+    }
+
+    #[test]
+    fn test_get_amount_in() {
+        let reserve = U256::from(1000u64);
+        // floor(1000 * 100 * 10000 / (900 * 9970)) + 1
+        assert_eq!(get_amount_in(U256::from(100u64), reserve, reserve, 30), Some(U256::from(112u64)));
+        assert_eq!(get_amount_out_with_fee(U256::from(112u64), reserve, reserve, 30), U256::from(100u64));
+        assert_eq!(get_amount_in(U256::ZERO, reserve, reserve, 30), Some(U256::ZERO));
+
+        assert_eq!(get_amount_in(reserve, reserve, reserve, 30), None);
+        assert_eq!(get_amount_in(U256::from(1u64), U256::ZERO, reserve, 30), None);
+        assert_eq!(get_amount_in(U256::from(1u64), reserve, reserve, 10_000), None);
+        // Вход для последней единицы резерва огромного пула не помещается в U256
+        assert_eq!(get_amount_in(U256::MAX - U256::from(1u64), U256::MAX, U256::MAX, 30), None);
+    }
+
+    /// Значения от 1 до 2^200; при сжатии стремятся к маленьким числам
+    fn positive_up_to_2_200() -> impl proptest::strategy::Strategy<Value = U256> {
+        use proptest::strategy::Strategy;
+        (proptest::prelude::any::<u128>(), 0usize..=72)
+            .prop_map(|(mantissa, shift)| U256::from(mantissa.max(1)) << shift)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_output_below_reserve_out(
+            amount_in in positive_up_to_2_200(),
+            reserve_in in positive_up_to_2_200(),
+            reserve_out in positive_up_to_2_200(),
+            fee_bps in 0u32..10_000,
+        ) {
+            proptest::prop_assert!(get_amount_out_with_fee(amount_in, reserve_in, reserve_out, fee_bps) < reserve_out);
+        }
+
+        #[test]
+        fn prop_amount_in_delivers_amount_out(
+            amount_out in positive_up_to_2_200(),
+            reserve_in in positive_up_to_2_200(),
+            reserve_out in positive_up_to_2_200(),
+            fee_bps in 0u32..10_000,
+        ) {
+            if let Some(amount_in) = get_amount_in(amount_out, reserve_in, reserve_out, fee_bps) {
+                proptest::prop_assert!(get_amount_out_with_fee(amount_in, reserve_in, reserve_out, fee_bps) >= amount_out);
+            } else {
+                // Недостижимый выход или вход сверх U256
+                proptest::prop_assert!(amount_out >= reserve_out || get_amount_out_with_fee(U256::MAX, reserve_in, reserve_out, fee_bps) < amount_out);
+            }
+        }
+
+        #[test]
+        fn prop_output_monotone_in_amount_in(
+            a in positive_up_to_2_200(),
+            b in positive_up_to_2_200(),
+            reserve_in in positive_up_to_2_200(),
+            reserve_out in positive_up_to_2_200(),
+            fee_bps in 0u32..10_000,
+        ) {
+            let (small, large) = if a <= b { (a, b) } else { (b, a) };
+            proptest::prop_assert!(
+                get_amount_out_with_fee(small, reserve_in, reserve_out, fee_bps)
+                    <= get_amount_out_with_fee(large, reserve_in, reserve_out, fee_bps)
+            );
+        }
+
+        #[test]
+        fn prop_split_swap_never_beats_single_swap(
+            a in positive_up_to_2_200(),
+            b in positive_up_to_2_200(),
+            reserve_in in positive_up_to_2_200(),
+            reserve_out in positive_up_to_2_200(),
+            fee_bps in 0u32..10_000,
+        ) {
+            let (first_out, reserve_in_after, reserve_out_after) = apply_swap(a, reserve_in, reserve_out, fee_bps);
+            let second_out = get_amount_out_with_fee(b, reserve_in_after, reserve_out_after, fee_bps);
+            let single_out = get_amount_out_with_fee(a + b, reserve_in, reserve_out, fee_bps);
+            proptest::prop_assert!(first_out + second_out <= single_out);
+        }
+
+        #[test]
+        fn prop_swap_never_decreases_k(
+            amount_in in positive_up_to_2_200(),
+            reserve_in in positive_up_to_2_200(),
+            reserve_out in positive_up_to_2_200(),
+            fee_bps in 0u32..10_000,
+        ) {
+            let (_, new_reserve_in, new_reserve_out) = apply_swap(amount_in, reserve_in, reserve_out, fee_bps);
+            let k_before: U512 = reserve_in.widening_mul(reserve_out);
+            let k_after: U512 = new_reserve_in.widening_mul(new_reserve_out);
+            proptest::prop_assert!(k_after >= k_before);
+        }
+
+        #[test]
+        fn prop_optimal_split_n_conserves_amount_in(
+            amount_in in positive_up_to_2_200(),
+            pools in proptest::collection::vec((positive_up_to_2_200(), positive_up_to_2_200(), proptest::prelude::any::<bool>()), 1..4),
+            fee_bps in 0u32..=10_000,
+        ) {
+            // Часть пулов пустая
+            let pools: Vec<(U256, U256)> = pools
+                .into_iter()
+                .map(|(reserve_in, reserve_out, empty)| if empty { (U256::ZERO, reserve_out) } else { (reserve_in, reserve_out) })
+                .collect();
+            let allocations = optimal_split_n(amount_in, &pools, fee_bps);
+            proptest::prop_assert_eq!(allocations.len(), pools.len());
+            proptest::prop_assert_eq!(allocations.iter().copied().sum::<U256>(), amount_in);
+        }
+
+        #[test]
+        fn prop_get_amount_out_never_panics(
+            amount_in in proptest::prelude::any::<[u64; 4]>(),
+            reserve_in in proptest::prelude::any::<[u64; 4]>(),
+            reserve_out in proptest::prelude::any::<[u64; 4]>(),
+            fee_bps in 0u32..10_000,
+        ) {
+            let amount_in = U256::from_limbs(amount_in);
+            let reserve_in = U256::from_limbs(reserve_in);
+            let reserve_out = U256::from_limbs(reserve_out);
+
+            let result = get_amount_out_with_fee(amount_in, reserve_in, reserve_out, fee_bps);
+            if reserve_out > U256::ZERO {
+                proptest::prop_assert!(result < reserve_out || result == U256::ZERO);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spot_price_decimal_adjustment() {
+        // 2,000,000 USDC (6 decimals) / 800 WETH (18 decimals) => 2500 USDC за WETH
+        let usdc_reserve = U256::from(2_000_000u64) * U256::from(10u64).pow(U256::from(6u64));
+        let weth_reserve = U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64));
+
+        let usdc_per_weth = spot_price(weth_reserve, usdc_reserve, 18, 6);
+        assert!((usdc_per_weth - 2500.0).abs() < 1e-9);
+
+        let weth_per_usdc = spot_price(usdc_reserve, weth_reserve, 6, 18);
+        assert!((weth_per_usdc - 0.0004).abs() < 1e-15);
+
+        // Наивное деление резервов ошибается ровно в 10^12 раз
+        let naive = u256_to_f64(usdc_reserve) / u256_to_f64(weth_reserve);
+        assert!((usdc_per_weth / naive - 1e12).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_spot_price_rational() {
+        let usdc_reserve = U256::from(2_000_000_000_000u64);
+        let weth_reserve = U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64));
+
+        let (num, den) = spot_price_rational(weth_reserve, usdc_reserve, 18, 6).unwrap();
+        assert_eq!(num / den, U256::from(2500u64));
+        assert_eq!(num % den, U256::ZERO);
+
+        assert_eq!(spot_price_rational(U256::ZERO, usdc_reserve, 18, 6), None);
+        assert_eq!(spot_price(U256::ZERO, usdc_reserve, 18, 6), 0.0);
+    }
+
+    #[test]
+    fn test_execution_price_and_deviation() {
+        // 3412.55 USDC за 1 WETH
+        let price = execution_price(U256::from(3_412_550_000u64), U256::from(10u64).pow(U256::from(18)), 6, 18).unwrap();
+        assert!((price - 3412.55).abs() < 1e-9);
+        assert_eq!(execution_price(U256::from(1_000_000u64), U256::ZERO, 6, 18), None);
+
+        let deviation = price_deviation_bps(price, 3410.12).unwrap();
+        assert!((deviation - (3410.12 / 3412.55 - 1.0) * 10_000.0).abs() < 1e-9);
+        assert!((deviation + 7.12).abs() < 0.01);
+        assert_eq!(price_deviation_bps(3400.0, 3400.0), Some(0.0));
+        assert_eq!(price_deviation_bps(0.0, 3400.0), None);
+        assert_eq!(price_deviation_bps(3400.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_u256_to_f64() {
+        assert_eq!(u256_to_f64(U256::ZERO), 0.0);
+        assert_eq!(u256_to_f64(U256::from(12345u64)), 12345.0);
+        assert_eq!(u256_to_f64(U256::from(1u64) << 200), 2f64.powi(200));
+    }
+
+    #[test]
+    fn test_price_impact_grows_with_amount() {
+        let reserve_in = U256::from(2_000_000_000_000u64); // 2M USDC
+        let reserve_out = U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64)); // 800 WETH
+
+        let mut previous = 0.0;
+        for amount in [0u64, 1, 1_000, 1_000_000, 1_000_000_000, 100_000_000_000, 1_000_000_000_000] {
+            let impact = price_impact(U256::from(amount), reserve_in, reserve_out, 30);
+            assert!(impact >= previous, "impact должен расти: {} < {}", impact, previous);
+            assert!(impact < 1.0);
+            previous = impact;
+        }
+
+        // Для маленькой сделки impact ≈ комиссия 0.3%
+        let tiny = price_impact(U256::from(1u64), reserve_in, reserve_out, 30);
+        assert!((tiny - 0.003).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_price_impact_edge_cases() {
+        let reserve = U256::from(1_000_000u64);
+        assert_eq!(price_impact(U256::from(10u64), U256::ZERO, reserve, 30), 1.0);
+        assert_eq!(price_impact(U256::from(10u64), reserve, U256::ZERO, 30), 1.0);
+        assert_eq!(price_impact(U256::ZERO, reserve, reserve, 30), 0.0);
+
+        // Без комиссии крошечная сделка в глубоком пуле дает положительный, но малый impact
+        let deep = U256::from(10u64).pow(U256::from(30u64));
+        let impact = price_impact(U256::from(10u64).pow(U256::from(6u64)), deep, deep, 0);
+        assert!(impact > 0.0 && impact < 1e-20);
+    }
+
+    #[test]
+    fn test_marginal_rate_strictly_decreases() {
+        let reserve_in = U256::from(2_000_000_000_000u64);
+        let reserve_out = U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64));
+
+        let mut previous = marginal_rate(U256::ZERO, reserve_in, reserve_out, 30).unwrap();
+        for allocated in [1u64, 1_000_000, 1_000_000_000, 100_000_000_000, 10_000_000_000_000] {
+            let rate = marginal_rate(U256::from(allocated), reserve_in, reserve_out, 30).unwrap();
+            assert_eq!(cmp_rational(rate, previous), Ordering::Less, "allocated = {}", allocated);
+            previous = rate;
+        }
+    }
+
+    #[test]
+    fn test_marginal_rate_at_zero_equals_fee_adjusted_spot() {
+        let reserve_in = U256::from(1_000_000u64);
+        let reserve_out = U256::from(4_000_000u64);
+
+        // При x = 0: out'(0) = (m / 10000) * reserveOut / reserveIn = 0.997 * 4
+        let rate = marginal_rate(U256::ZERO, reserve_in, reserve_out, 30).unwrap();
+        assert_eq!(cmp_rational(rate, (U256::from(3988u64), U256::from(1000u64))), Ordering::Equal);
+
+        assert_eq!(marginal_rate(U256::ZERO, U256::ZERO, reserve_out, 30), None);
+        assert_eq!(marginal_rate(U256::ZERO, reserve_in, reserve_out, 10_000), None);
+    }
+
+    #[test]
+    fn test_cmp_rational() {
+        let third = (U256::from(1u64), U256::from(3u64));
+        let half = (U256::from(2u64), U256::from(4u64));
+        assert_eq!(cmp_rational(third, half), Ordering::Less);
+        assert_eq!(cmp_rational(half, (U256::from(1u64), U256::from(2u64))), Ordering::Equal);
+        assert_eq!(cmp_rational((U256::MAX, U256::from(1u64)), (U256::MAX, U256::from(2u64))), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_get_amounts_out_chains_hops() {
+        let amount_in = U256::from(1_000_000u64);
+        let hop1 = (U256::from(50_000_000u64), U256::from(49_000_000u64), 30);
+        let hop2 = (U256::from(70_000_000u64), U256::from(20_000_000u64), 25);
+
+        let amounts = get_amounts_out(amount_in, &[hop1, hop2]);
+
+        let first = get_amount_out_with_fee(amount_in, hop1.0, hop1.1, hop1.2);
+        let second = get_amount_out_with_fee(first, hop2.0, hop2.1, hop2.2);
+        assert_eq!(amounts, vec![amount_in, first, second]);
+
+        // Пустой путь возвращает только входную сумму
+        assert_eq!(get_amounts_out(amount_in, &[]), vec![amount_in]);
+    }
+
+    #[test]
+    fn test_optimal_split_two_symmetric_and_degenerate() {
+        let amount_in = U256::from(1_000_000u64);
+        let pool = (U256::from(50_000_000u64), U256::from(20_000_000u64));
+
+        // Одинаковые пулы делят вход пополам
+        let (to_a, to_b) = optimal_split_two(amount_in, pool, pool, 30);
+        assert_eq!(to_a + to_b, amount_in);
+        assert!(to_a.abs_diff(to_b) <= U256::from(1u64));
+
+        // Пустой пул не получает ничего
+        let empty = (U256::ZERO, U256::ZERO);
+        assert_eq!(optimal_split_two(amount_in, pool, empty, 30), (amount_in, U256::ZERO));
+        assert_eq!(optimal_split_two(amount_in, empty, pool, 30), (U256::ZERO, amount_in));
+
+        // Пул B сильно дороже: маленький объем целиком идет в A
+        let expensive = (U256::from(50_000_000u64), U256::from(10_000_000u64));
+        assert_eq!(optimal_split_two(U256::from(1_000u64), pool, expensive, 30), (U256::from(1_000u64), U256::ZERO));
+    }
+
+    #[test]
+    fn test_optimal_split_two_is_local_optimum() {
+        let amount_in = U256::from(3_000_000_000u64);
+        let pool_a = (U256::from(20_000_000_000u64), U256::from(8_000_000_000u64));
+        let pool_b = (U256::from(7_000_000_000u64), U256::from(2_900_000_000u64));
+
+        let (to_a, to_b) = optimal_split_two(amount_in, pool_a, pool_b, 30);
+        let total_out = |a: U256, b: U256| {
+            get_amount_out(a, pool_a.0, pool_a.1) + get_amount_out(b, pool_b.0, pool_b.1)
+        };
+        let best = total_out(to_a, to_b);
+
+        // Сдвиг распределения в любую сторону не улучшает результат заметно
+        let step = U256::from(1_000_000u64);
+        assert!(total_out(to_a + step, to_b - step) <= best);
+        assert!(total_out(to_a - step, to_b + step) <= best);
+    }
+
+    #[test]
+    fn test_optimal_split_n_equalizes_marginal_rates() {
+        let amount_in = U256::from(5_000_000_000u64);
+        let pools = [
+            (U256::from(20_000_000_000u64), U256::from(8_000_000_000u64)),
+            (U256::from(7_000_000_000u64), U256::from(2_900_000_000u64)),
+            (U256::from(12_000_000_000u64), U256::from(4_700_000_000u64)),
+        ];
+
+        let allocations = optimal_split_n(amount_in, &pools, 30);
+        assert_eq!(allocations.iter().copied().sum::<U256>(), amount_in);
+
+        // Маржинальные курсы после распределения почти равны (относительная разница < 0.01%)
+        let rates: Vec<f64> = allocations
+            .iter()
+            .zip(pools.iter())
+            .map(|(x, (r_in, r_out))| {
+                let (num, den) = marginal_rate(*x, *r_in, *r_out, 30).unwrap();
+                u256_to_f64(num) / u256_to_f64(den)
+            })
+            .collect();
+        for rate in &rates {
+            assert!((rate - rates[0]).abs() / rates[0] < 1e-4);
+        }
+
+        // Совпадает с двухпуловым аналитическим решением
+        let two = optimal_split_n(amount_in, &pools[..2], 30);
+        let (to_a, _) = optimal_split_two(amount_in, pools[0], pools[1], 30);
+        assert!(two[0].abs_diff(to_a) <= U256::from(2u64));
+    }
+
+    #[test]
+    fn test_optimal_split_n_skips_worse_pools() {
+        let amount_in = U256::from(1_000u64);
+        let pools = [
+            (U256::from(50_000_000u64), U256::from(20_000_000u64)),
+            (U256::from(50_000_000u64), U256::from(10_000_000u64)),
+            (U256::ZERO, U256::ZERO),
+        ];
+        assert_eq!(optimal_split_n(amount_in, &pools, 30), vec![amount_in, U256::ZERO, U256::ZERO]);
+        // Выравнивать нечего, но вход не теряется: солвер покажет отклоненный свап
+        assert_eq!(optimal_split_n(amount_in, &pools[2..], 30), vec![amount_in]);
+    }
+
+    #[test]
+    fn test_amount_in_to_reach_price_lands_on_target() {
+        let reserve_in = U256::from(2_000_000_000_000u64);
+        let reserve_out = U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64));
+        // Цель: цена на 2% ниже текущей
+        let target_num = reserve_out * U256::from(98u64);
+        let target_den = reserve_in * U256::from(100u64);
+
+        let amount = amount_in_to_reach_price(reserve_in, reserve_out, target_num, target_den, 30).unwrap();
+        let price_after = |x: U256| {
+            let out = get_amount_out(x, reserve_in, reserve_out);
+            (reserve_out - out, reserve_in + x)
+        };
+
+        // Ровно найденный вход достигает цели, на единицу меньше - еще нет
+        assert_ne!(cmp_rational(price_after(amount), (target_num, target_den)), Ordering::Greater);
+        assert_eq!(cmp_rational(price_after(amount - U256::from(1u64)), (target_num, target_den)), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_amount_in_to_reach_price_degenerate() {
+        let reserve_in = U256::from(1_000_000u64);
+        let reserve_out = U256::from(500_000u64);
+
+        // Пул уже ниже цели
+        assert_eq!(amount_in_to_reach_price(reserve_in, reserve_out, U256::from(1u64), U256::from(1u64), 30), None);
+        // Пул ровно на цели
+        assert_eq!(amount_in_to_reach_price(reserve_in, reserve_out, U256::from(1u64), U256::from(2u64), 30), Some(U256::ZERO));
+        // Пустой пул и нулевая цель
+        assert_eq!(amount_in_to_reach_price(U256::ZERO, reserve_out, U256::from(1u64), U256::from(4u64), 30), None);
+        assert_eq!(amount_in_to_reach_price(reserve_in, reserve_out, U256::ZERO, U256::from(4u64), 30), None);
+    }
+
+    #[test]
+    fn test_max_input_for_impact_is_tight() {
+        let reserve_in = U256::from(2_000_000_000_000u64);
+        let reserve_out = U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64));
+
+        for budget_bps in [31u32, 50, 100, 500, 2_500] {
+            let amount = max_input_for_impact(reserve_in, reserve_out, budget_bps, 30);
+            let budget = budget_bps as f64 / 10_000.0;
+
+            // Ровно найденный вход укладывается в бюджет, на единицу больше - нет
+            assert!(price_impact(amount, reserve_in, reserve_out, 30) <= budget);
+            assert!(price_impact(amount + U256::from(1u64), reserve_in, reserve_out, 30) > budget);
+        }
+    }
+
+    #[test]
+    fn test_max_input_for_impact_edge_cases() {
+        let reserve = U256::from(1_000_000u64);
+
+        // Бюджет не выше комиссии не позволяет торговать
+        assert_eq!(max_input_for_impact(reserve, reserve, 10, 30), U256::ZERO);
+        assert_eq!(max_input_for_impact(reserve, reserve, 30, 30), U256::ZERO);
+        assert_eq!(max_input_for_impact(reserve, reserve, 10_000, 30), U256::MAX);
+        assert_eq!(max_input_for_impact(U256::ZERO, reserve, 100, 30), U256::ZERO);
+    }
+
+    #[test]
+    fn test_apply_slippage_boundaries() {
+        let amount = U256::from(1_000_000u64);
+
+        assert_eq!(apply_slippage(amount, 0), Some(amount));
+        assert_eq!(apply_slippage(amount, 50), Some(U256::from(995_000u64)));
+        assert_eq!(apply_slippage(amount, 10_000), Some(U256::ZERO));
+        assert_eq!(apply_slippage(amount, 10_001), None);
+        // Округление вниз: 1 * 9950 / 10000 = 0
+        assert_eq!(apply_slippage(U256::from(1u64), 50), Some(U256::ZERO));
+        assert_eq!(apply_slippage(U256::from(1u64), 0), Some(U256::from(1u64)));
+        assert_eq!(apply_slippage(U256::MAX, 0), Some(U256::MAX));
+
+        assert_eq!(apply_slippage_up(amount, 0), Some(amount));
+        assert_eq!(apply_slippage_up(amount, 50), Some(U256::from(1_005_000u64)));
+        assert_eq!(apply_slippage_up(amount, 10_000), Some(U256::from(2_000_000u64)));
+        assert_eq!(apply_slippage_up(amount, 10_001), None);
+        // Округление вверх: 1 * 10050 / 10000 = 2
+        assert_eq!(apply_slippage_up(U256::from(1u64), 50), Some(U256::from(2u64)));
+        assert_eq!(apply_slippage_up(U256::ZERO, 50), Some(U256::ZERO));
+        assert_eq!(apply_slippage_up(U256::MAX, 1), None);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn apply_slippage_never_exceeds_amount(
+            limbs in proptest::prelude::any::<[u64; 4]>(),
+            slippage_bps in 0u32..=10_000,
+        ) {
+            let amount = U256::from_limbs(limbs);
+            let min_out = apply_slippage(amount, slippage_bps).unwrap();
+            proptest::prop_assert!(min_out <= amount);
+            if let Some(max_in) = apply_slippage_up(amount, slippage_bps) {
+                proptest::prop_assert!(max_in >= amount);
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// src/math/accumulator.rs
+//! Exact integer aggregation of token amounts.
+//!
+//! Totals over routes, pools and output tokens are summed with checked
+//! arithmetic, and scaled terms (`amount * numerator / denominator`) use a
+//! 512-bit intermediate product. A result that does not fit in U256 is
+//! reported as an [`AggregationError`] naming the aggregate instead of
+//! panicking or wrapping.
+
+use super::mul_div;
+use alloy::primitives::U256;
+use std::fmt;
+
+/// Failure to aggregate amounts exactly in U256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregationError {
+    /// The running total exceeded U256.
+    Overflow(&'static str),
+    /// A scaled term `amount * numerator / denominator` exceeded U256.
+    ScaledOverflow(&'static str),
+    /// A scaled term was requested with a zero denominator.
+    ZeroDenominator(&'static str),
+}
+
+impl fmt::Display for AggregationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregationError::Overflow(label) => write!(f, "{label}: сумма не помещается в U256"),
+            AggregationError::ScaledOverflow(label) => write!(f, "{label}: масштабированная сумма не помещается в U256"),
+            AggregationError::ZeroDenominator(label) => write!(f, "{label}: нулевой знаменатель"),
+        }
+    }
+}
+
+impl std::error::Error for AggregationError {}
+
+/// Running U256 total that refuses to overflow.
+///
+/// `label` names the aggregate in errors (e.g. "общий выход").
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accumulator {
+    label: &'static str,
+    total: U256,
+}
+
+impl Accumulator {
+    pub const fn new(label: &'static str) -> Self {
+        Accumulator { label, total: U256::ZERO }
+    }
+
+    /// Adds `amount` to the total.
+    pub fn add(&mut self, amount: U256) -> Result<(), AggregationError> {
+        accumulate(&mut self.total, amount, self.label)
+    }
+
+    /// Adds `floor(amount * numerator / denominator)` to the total.
+    pub fn add_scaled(&mut self, amount: U256, numerator: U256, denominator: U256) -> Result<(), AggregationError> {
+        let term = scale(amount, numerator, denominator, self.label)?;
+        self.add(term)
+    }
+
+    /// Current total.
+    pub fn total(&self) -> U256 {
+        self.total
+    }
+
+    /// Sums `amounts` exactly.
+    pub fn sum(label: &'static str, amounts: impl IntoIterator<Item = U256>) -> Result<U256, AggregationError> {
+        let mut accumulator = Accumulator::new(label);
+        for amount in amounts {
+            accumulator.add(amount)?;
+        }
+        Ok(accumulator.total())
+    }
+}
+
+/// Adds `amount` to a total stored elsewhere (a struct field or a map entry).
+pub fn accumulate(total: &mut U256, amount: U256, label: &'static str) -> Result<(), AggregationError> {
+    *total = total.checked_add(amount).ok_or(AggregationError::Overflow(label))?;
+    Ok(())
+}
+
+/// Computes `floor(amount * numerator / denominator)` through a 512-bit product.
+pub fn scale(amount: U256, numerator: U256, denominator: U256, label: &'static str) -> Result<U256, AggregationError> {
+    if denominator == U256::ZERO {
+        return Err(AggregationError::ZeroDenominator(label));
+    }
+    mul_div(amount, numerator, denominator).ok_or(AggregationError::ScaledOverflow(label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::U512;
+    use proptest::prelude::*;
+
+    /// Values concentrated near the edges of U256: small, around 2^128 and near MAX.
+    fn extreme() -> impl Strategy<Value = U256> {
+        prop_oneof![
+            any::<u64>().prop_map(U256::from),
+            any::<u128>().prop_map(|low| U256::from(low) << 64),
+            any::<u128>().prop_map(|low| U256::MAX - U256::from(low)),
+            Just(U256::MAX),
+            Just(U256::ZERO),
+        ]
+    }
+
+    #[test]
+    fn overflow_is_reported_with_label() {
+        let mut total = Accumulator::new("общий выход");
+        total.add(U256::MAX).unwrap();
+        assert_eq!(total.add(U256::from(1u64)), Err(AggregationError::Overflow("общий выход")));
+        // The total is unchanged by a rejected addition
+        assert_eq!(total.total(), U256::MAX);
+
+        assert_eq!(scale(U256::MAX, U256::from(2u64), U256::from(1u64), "fee"), Err(AggregationError::ScaledOverflow("fee")));
+        assert_eq!(scale(U256::MAX, U256::MAX, U256::ZERO, "fee"), Err(AggregationError::ZeroDenominator("fee")));
+        // The product overflows U256 but the quotient fits
+        assert_eq!(scale(U256::MAX, U256::MAX, U256::MAX, "fee"), Ok(U256::MAX));
+    }
+
+    proptest! {
+        #[test]
+        fn sum_is_exact_or_reports_overflow(amounts in proptest::collection::vec(extreme(), 0..8)) {
+            let exact: U512 = amounts.iter().map(|&amount| U512::from(amount)).sum();
+            match Accumulator::sum("test", amounts.iter().copied()) {
+                Ok(total) => prop_assert_eq!(U512::from(total), exact),
+                Err(error) => {
+                    prop_assert_eq!(error, AggregationError::Overflow("test"));
+                    prop_assert!(exact > U512::from(U256::MAX));
+                }
+            }
+        }
+
+        #[test]
+        fn scaled_terms_match_wide_arithmetic(amount in extreme(), numerator in extreme(), denominator in extreme()) {
+            let mut accumulator = Accumulator::new("test");
+            let result = accumulator.add_scaled(amount, numerator, denominator);
+            if denominator == U256::ZERO {
+                prop_assert_eq!(result, Err(AggregationError::ZeroDenominator("test")));
+            } else {
+                let exact = amount.widening_mul::<256, 4, 512, 8>(numerator) / U512::from(denominator);
+                if exact > U512::from(U256::MAX) {
+                    prop_assert_eq!(result, Err(AggregationError::ScaledOverflow("test")));
+                } else {
+                    prop_assert!(result.is_ok());
+                    prop_assert_eq!(U512::from(accumulator.total()), exact);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// src/math/stableswap.rs
+//! StableSwap (Curve) invariant for pools of correlated assets.
+//! 
+//! Mirrors the reference Vyper/Python implementation: `get_D` and `get_y` are solved
+//! by Newton iteration with at most [`MAX_ITERATIONS`] steps. Balances are expected in
+//! a common precision (Curve's `xp`, usually 18 decimals); converting token amounts
+//! with different decimals is left to the caller.
+
+use alloy::primitives::U256;
+use std::fmt;
+
+/// Iteration limit of the Newton solvers, same as in Curve contracts
+pub const MAX_ITERATIONS: usize = 255;
+
+/// Fee denominator used by Curve pools (fee = 4_000_000 means 0.04%)
+pub const FEE_DENOMINATOR: u64 = 10_000_000_000;
+
+/// Errors of the StableSwap math
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StableSwapError {
+    /// Newton iteration did not converge within `MAX_ITERATIONS`
+    NoConvergence,
+    /// Coin index is out of range or `i == j`
+    InvalidIndex,
+    /// Pool has an empty balance or a zero amplification coefficient
+    EmptyPool,
+    /// Intermediate value does not fit into U256
+    Overflow,
+}
+
+impl fmt::Display for StableSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StableSwapError::NoConvergence => write!(f, "итерации StableSwap не сошлись за {} шагов", MAX_ITERATIONS),
+            StableSwapError::InvalidIndex => write!(f, "некорректные индексы монет"),
+            StableSwapError::EmptyPool => write!(f, "пустой баланс или нулевой коэффициент усиления"),
+            StableSwapError::Overflow => write!(f, "переполнение U256 в расчетах StableSwap"),
+        }
+    }
+}
+
+impl std::error::Error for StableSwapError {}
+
+fn checked(value: Option<U256>) -> Result<U256, StableSwapError> {
+    value.ok_or(StableSwapError::Overflow)
+}
+
+fn converged(a: U256, b: U256) -> bool {
+    a.abs_diff(b) <= U256::from(1u64)
+}
+
+/// Computes the invariant D for the given balances and amplification coefficient.
+/// 
+/// # Returns
+/// D, or `Ok(0)` for an all-zero pool
+pub fn get_d(balances: &[U256], amp: U256) -> Result<U256, StableSwapError> {
+    let n = U256::from(balances.len());
+    let sum: U256 = balances.iter().try_fold(U256::ZERO, |acc, x| acc.checked_add(*x)).ok_or(StableSwapError::Overflow)?;
+    if sum == U256::ZERO {
+        return Ok(U256::ZERO);
+    }
+    if amp == U256::ZERO || balances.contains(&U256::ZERO) {
+        return Err(StableSwapError::EmptyPool);
+    }
+
+    let ann = checked(amp.checked_mul(n))?;
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = d;
+        for x in balances {
+            d_p = checked(d_p.checked_mul(d))? / checked(x.checked_mul(n))?;
+        }
+        let d_prev = d;
+        let numerator = checked(checked(ann.checked_mul(sum))?.checked_add(checked(d_p.checked_mul(n))?))?;
+        let denominator = checked(
+            checked((ann - U256::from(1u64)).checked_mul(d))?
+                .checked_add(checked(d_p.checked_mul(n + U256::from(1u64)))?),
+        )?;
+        d = checked(numerator.checked_mul(d))? / denominator;
+        if converged(d, d_prev) {
+            return Ok(d);
+        }
+    }
+
+    Err(StableSwapError::NoConvergence)
+}
+
+/// Computes the new balance of coin `j` after the balance of coin `i` becomes `x`,
+/// keeping D constant.
+pub fn get_y(i: usize, j: usize, x: U256, balances: &[U256], amp: U256) -> Result<U256, StableSwapError> {
+    let n_coins = balances.len();
+    if i == j || i >= n_coins || j >= n_coins {
+        return Err(StableSwapError::InvalidIndex);
+    }
+
+    let d = get_d(balances, amp)?;
+    let n = U256::from(n_coins);
+    let ann = checked(amp.checked_mul(n))?;
+
+    let mut c = d;
+    let mut sum = U256::ZERO;
+    for (k, balance) in balances.iter().enumerate() {
+        let value = if k == i {
+            x
+        } else if k != j {
+            *balance
+        } else {
+            continue;
+        };
+        if value == U256::ZERO {
+            return Err(StableSwapError::EmptyPool);
+        }
+        sum = checked(sum.checked_add(value))?;
+        c = checked(c.checked_mul(d))? / checked(value.checked_mul(n))?;
+    }
+    c = checked(c.checked_mul(d))? / checked(ann.checked_mul(n))?;
+    let b = checked(sum.checked_add(d / ann))?;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        let numerator = checked(checked(y.checked_mul(y))?.checked_add(c))?;
+        let denominator = checked(checked(y.checked_mul(U256::from(2u64)))?.checked_add(b))?
+            .checked_sub(d)
+            .ok_or(StableSwapError::NoConvergence)?;
+        y = numerator / denominator;
+        if converged(y, y_prev) {
+            return Ok(y);
+        }
+    }
+
+    Err(StableSwapError::NoConvergence)
+}
+
+/// Output of coin `j` for `dx` of coin `i`, after the pool fee (Curve's `get_dy`).
+/// 
+/// # Arguments
+/// * `i`, `j` - Input and output coin indices
+/// * `dx` - Input amount in the balances' precision
+/// * `balances` - Pool balances in a common precision
+/// * `amp` - Amplification coefficient A
+/// * `fee` - Pool fee in units of [`FEE_DENOMINATOR`]
+pub fn get_dy(i: usize, j: usize, dx: U256, balances: &[U256], amp: U256, fee: U256) -> Result<U256, StableSwapError> {
+    if i >= balances.len() || j >= balances.len() {
+        return Err(StableSwapError::InvalidIndex);
+    }
+    let x = checked(balances[i].checked_add(dx))?;
+    let y = get_y(i, j, x, balances, amp)?;
+
+    // Как в Curve: -1 защищает от ошибок округления в пользу пула
+    let dy = balances[j].saturating_sub(y).saturating_sub(U256::from(1u64));
+    let fee_amount = checked(fee.checked_mul(dy))? / U256::from(FEE_DENOMINATOR);
+    Ok(dy - fee_amount.min(dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10u64).pow(U256::from(18u64))
+    }
+
+    fn value(decimal: &str) -> U256 {
+        decimal.parse().unwrap()
+    }
+
+    // Ожидаемые значения получены эталонной Python-реализацией Curve (get_D/get_y/get_dy)
+    #[test]
+    fn matches_curve_reference_values() {
+        assert_eq!(get_d(&[e18(1_000), e18(1_000)], U256::from(100u64)).unwrap(), e18(2_000));
+
+        let fee = U256::from(4_000_000u64);
+        assert_eq!(
+            get_dy(0, 1, e18(10), &[e18(1_000), e18(1_000)], U256::from(100u64), fee).unwrap(),
+            value("9995010298009604961")
+        );
+        assert_eq!(
+            get_dy(0, 1, e18(500), &[e18(1_000), e18(1_200)], U256::from(100u64), fee).unwrap(),
+            value("498244409964872716482")
+        );
+        assert_eq!(
+            get_dy(0, 2, e18(1_000_000), &[e18(30_000_000), e18(25_000_000), e18(28_000_000)], U256::from(2_000u64), U256::from(3_000_000u64)).unwrap(),
+            value("999650040742616279376105")
+        );
+        // Сильно несбалансированный пул с низким A
+        assert_eq!(
+            get_dy(1, 0, e18(1), &[e18(5), e18(1_000)], U256::from(10u64), U256::ZERO).unwrap(),
+            value("15584589128642982")
+        );
+    }
+
+    #[test]
+    fn stableswap_beats_constant_product_for_balanced_pool() {
+        let balances = [e18(1_000_000), e18(1_000_000)];
+        let dx = e18(100_000);
+        let stable = get_dy(0, 1, dx, &balances, U256::from(200u64), U256::ZERO).unwrap();
+        let constant_product = crate::math::get_amount_out_with_fee(dx, balances[0], balances[1], 0);
+        assert!(stable > constant_product);
+        assert!(stable < dx);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let balances = [e18(1_000), e18(1_000)];
+        assert_eq!(get_dy(0, 0, e18(1), &balances, U256::from(100u64), U256::ZERO), Err(StableSwapError::InvalidIndex));
+        assert_eq!(get_dy(0, 2, e18(1), &balances, U256::from(100u64), U256::ZERO), Err(StableSwapError::InvalidIndex));
+        assert_eq!(get_d(&[e18(1_000), U256::ZERO], U256::from(100u64)), Err(StableSwapError::EmptyPool));
+        assert_eq!(get_d(&balances, U256::ZERO), Err(StableSwapError::EmptyPool));
+        assert_eq!(get_d(&[U256::ZERO, U256::ZERO], U256::from(100u64)), Ok(U256::ZERO));
+        assert_eq!(get_d(&[U256::MAX, U256::MAX], U256::from(100u64)), Err(StableSwapError::Overflow));
+    }
+}
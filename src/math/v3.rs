@@ -0,0 +1,225 @@
+// src/math/v3.rs
+//! Uniswap V3 concentrated liquidity math for exact-input swaps within a single range.
+//! 
+//! A port of `SqrtPriceMath` and `SwapMath.computeSwapStep` on Q64.96 fixed point with
+//! the same rounding directions as the contracts. Tick crossing is not modelled: if the
+//! input would move the price past the range bound, the swap stops at the bound and the
+//! quote is flagged with `crossed_range`.
+
+use alloy::primitives::{U256, U512};
+
+/// Fee denominator of Uniswap V3 (fee = 500 means 0.05%)
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+/// 2^96, the Q64.96 scale of `sqrtPriceX96`
+pub fn q96() -> U256 {
+    U256::from(1u64) << 96
+}
+
+fn mul_div_down(a: U256, b: U256, denominator: U256) -> U256 {
+    let product: U512 = a.widening_mul(b);
+    (product / U512::from(denominator)).to::<U256>()
+}
+
+fn mul_div_up(a: U256, b: U256, denominator: U256) -> U256 {
+    let product: U512 = a.widening_mul(b);
+    product.div_ceil(U512::from(denominator)).to::<U256>()
+}
+
+/// Amount of token0 between two sqrt prices (`SqrtPriceMath.getAmount0Delta`)
+pub fn get_amount0_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> U256 {
+    let (lower, upper) = if sqrt_a <= sqrt_b { (sqrt_a, sqrt_b) } else { (sqrt_b, sqrt_a) };
+    if lower == U256::ZERO {
+        return U256::ZERO;
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = upper - lower;
+    if round_up {
+        mul_div_up(numerator1, numerator2, upper).div_ceil(lower)
+    } else {
+        mul_div_down(numerator1, numerator2, upper) / lower
+    }
+}
+
+/// Amount of token1 between two sqrt prices (`SqrtPriceMath.getAmount1Delta`)
+pub fn get_amount1_delta(sqrt_a: U256, sqrt_b: U256, liquidity: u128, round_up: bool) -> U256 {
+    let (lower, upper) = if sqrt_a <= sqrt_b { (sqrt_a, sqrt_b) } else { (sqrt_b, sqrt_a) };
+    if round_up {
+        mul_div_up(U256::from(liquidity), upper - lower, q96())
+    } else {
+        mul_div_down(U256::from(liquidity), upper - lower, q96())
+    }
+}
+
+/// Next sqrt price after adding `amount` of token0 (rounded up)
+fn next_sqrt_price_from_amount0(sqrt_price: U256, liquidity: u128, amount: U256) -> U256 {
+    // liquidity < 2^128 и sqrt_price < 2^160, поэтому произведения помещаются в U512
+    let numerator1: U256 = U256::from(liquidity) << 96;
+    let product: U512 = amount.widening_mul(sqrt_price);
+    let denominator = U512::from(numerator1) + product;
+    let numerator: U512 = numerator1.widening_mul(sqrt_price);
+    numerator.div_ceil(denominator).to::<U256>()
+}
+
+/// Next sqrt price after adding `amount` of token1 (rounded down)
+fn next_sqrt_price_from_amount1(sqrt_price: U256, liquidity: u128, amount: U256) -> U256 {
+    sqrt_price + mul_div_down(amount, q96(), U256::from(liquidity))
+}
+
+/// Result of a swap within the current range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V3Quote {
+    /// Output amount
+    pub amount_out: U256,
+    /// Input actually consumed, including the fee (less than requested if the range was exhausted)
+    pub amount_in_used: U256,
+    /// Fee part of `amount_in_used`
+    pub fee_amount: U256,
+    /// sqrtPriceX96 after the swap
+    pub sqrt_price_after_x96: U256,
+    /// true if the input would move the price past the range bound and the output is capped
+    pub crossed_range: bool,
+}
+
+/// State of a V3 pool within one liquidity range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct V3PoolState {
+    pub sqrt_price_x96: U256,
+    pub liquidity: u128,
+    pub fee: u32,                 // В единицах 1e-6 (500 = 0.05%)
+    pub sqrt_price_lower_x96: U256, // Нижняя граница текущего диапазона
+    pub sqrt_price_upper_x96: U256, // Верхняя граница текущего диапазона
+}
+
+impl V3PoolState {
+    /// Quotes an exact-input swap without changing the state (`computeSwapStep`)
+    /// 
+    /// # Arguments
+    /// * `amount_in` - Input amount including the fee
+    /// * `zero_for_one` - true for token0 -> token1 (price goes down)
+    pub fn get_amount_out(&self, amount_in: U256, zero_for_one: bool) -> V3Quote {
+        let target = if zero_for_one { self.sqrt_price_lower_x96 } else { self.sqrt_price_upper_x96 };
+        let current = self.sqrt_price_x96;
+        let fee = U256::from(self.fee);
+        let fee_complement = U256::from(FEE_DENOMINATOR - self.fee.min(FEE_DENOMINATOR));
+
+        let empty = V3Quote {
+            amount_out: U256::ZERO,
+            amount_in_used: U256::ZERO,
+            fee_amount: U256::ZERO,
+            sqrt_price_after_x96: current,
+            crossed_range: false,
+        };
+        if self.liquidity == 0 || fee_complement == U256::ZERO || amount_in == U256::ZERO {
+            return empty;
+        }
+        if (zero_for_one && current <= target) || (!zero_for_one && current >= target) {
+            return V3Quote { crossed_range: true, ..empty };
+        }
+
+        let amount_less_fee = mul_div_down(amount_in, fee_complement, U256::from(FEE_DENOMINATOR));
+        let to_target = if zero_for_one {
+            get_amount0_delta(target, current, self.liquidity, true)
+        } else {
+            get_amount1_delta(current, target, self.liquidity, true)
+        };
+
+        let crossed_range = amount_less_fee >= to_target;
+        let next = if crossed_range {
+            target
+        } else if zero_for_one {
+            next_sqrt_price_from_amount0(current, self.liquidity, amount_less_fee)
+        } else {
+            next_sqrt_price_from_amount1(current, self.liquidity, amount_less_fee)
+        };
+
+        let (amount_in_net, amount_out) = if zero_for_one {
+            let amount_in_net = if crossed_range { to_target } else { get_amount0_delta(next, current, self.liquidity, true) };
+            (amount_in_net, get_amount1_delta(next, current, self.liquidity, false))
+        } else {
+            let amount_in_net = if crossed_range { to_target } else { get_amount1_delta(current, next, self.liquidity, true) };
+            (amount_in_net, get_amount0_delta(current, next, self.liquidity, false))
+        };
+
+        let fee_amount = if crossed_range {
+            mul_div_up(amount_in_net, fee, fee_complement)
+        } else {
+            amount_in - amount_in_net
+        };
+
+        V3Quote {
+            amount_out,
+            amount_in_used: amount_in_net + fee_amount,
+            fee_amount,
+            sqrt_price_after_x96: next,
+            crossed_range,
+        }
+    }
+
+    /// Applies an exact-input swap and moves the price, like `Pool::mock_swap`
+    pub fn apply_swap(&mut self, amount_in: U256, zero_for_one: bool) -> V3Quote {
+        let quote = self.get_amount_out(amount_in, zero_for_one);
+        self.sqrt_price_x96 = quote.sqrt_price_after_x96;
+        quote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Цена 4e8 wei WETH за raw USDC (2500 USDC/WETH, token0 = USDC): sqrt = 20000
+    fn state(fee: u32) -> V3PoolState {
+        V3PoolState {
+            sqrt_price_x96: U256::from(20_000u64) * q96(),
+            liquidity: 3_000_000_000_000_000_000,
+            fee,
+            sqrt_price_lower_x96: U256::from(19_000u64) * q96(),
+            sqrt_price_upper_x96: U256::from(21_000u64) * q96(),
+        }
+    }
+
+    fn value(decimal: &str) -> U256 {
+        decimal.parse().unwrap()
+    }
+
+    // Эталонные значения получены построчным переносом SwapMath.computeSwapStep
+    // и SqrtPriceMath на целые числа Python
+    #[test]
+    fn matches_compute_swap_step_reference() {
+        let quote = state(500).get_amount_out(U256::from(1_000_000_000u64), true);
+        assert_eq!(quote.sqrt_price_after_x96, value("1584552691882516508035880460969862"));
+        assert_eq!(quote.amount_out, value("399797336017084339"));
+        assert_eq!(quote.fee_amount, U256::from(500_000u64));
+        assert_eq!(quote.amount_in_used, U256::from(1_000_000_000u64));
+        assert!(!quote.crossed_range);
+
+        let quote = state(3_000).get_amount_out(value("1000000000000000000"), false);
+        assert_eq!(quote.sqrt_price_after_x96, value("1584589580444628992385739261159494"));
+        assert_eq!(quote.amount_out, U256::from(2_492_458_583u64));
+        assert_eq!(quote.fee_amount, value("3000000000000000"));
+    }
+
+    #[test]
+    fn caps_output_at_range_bound() {
+        let quote = state(500).get_amount_out(U256::from(100_000_000_000_000u64), true);
+        assert!(quote.crossed_range);
+        assert_eq!(quote.sqrt_price_after_x96, U256::from(19_000u64) * q96());
+        assert_eq!(quote.amount_out, value("3000000000000000000000"));
+        assert_eq!(quote.amount_in_used, U256::from(7_894_736_842_106u64 + 3_949_343_093u64));
+    }
+
+    #[test]
+    fn apply_swap_moves_price_and_worsens_next_quote() {
+        let mut pool = state(500);
+        let amount_in = U256::from(1_000_000_000u64);
+        let first = pool.apply_swap(amount_in, true);
+        assert_eq!(pool.sqrt_price_x96, first.sqrt_price_after_x96);
+        let second = pool.get_amount_out(amount_in, true);
+        assert!(second.amount_out < first.amount_out);
+
+        // Обратный свап возвращает цену вверх
+        pool.apply_swap(first.amount_out, false);
+        assert!(pool.sqrt_price_x96 > first.sqrt_price_after_x96);
+    }
+}
@@ -0,0 +1,220 @@
+// src/math/weighted.rs
+//! Balancer V2 weighted pool math.
+//! 
+//! `out = balanceOut * (1 - (balanceIn / (balanceIn + amountIn))^(weightIn / weightOut))`
+//! 
+//! Weights and the swap fee use Balancer's 18-decimal fixed point. The fractional power
+//! is computed as `exp(-exponent * ln(1 / base))` with 36-decimal internal precision:
+//! `ln` by the atanh series after range reduction by powers of two, `exp` by the Taylor
+//! series after reduction by ln 2. The relative error of the power is below 1e-30, far
+//! under the 18-decimal rounding of the inputs. Like Balancer, rounding always favors
+//! the pool: the base and the power are rounded up, so the output is rounded down.
+
+use alloy::primitives::U256;
+use super::mul_div;
+
+/// Balancer fixed point one (18 decimals)
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+
+/// Balancer limits the input of a single swap to 30% of the input balance
+pub const MAX_IN_RATIO: u128 = 300_000_000_000_000_000;
+
+/// Internal fixed point one (36 decimals)
+const PRECISION: u128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
+
+/// ln 2 with 36 decimals
+const LN2: u128 = 693_147_180_559_945_309_417_232_121_458_176_568;
+
+fn precision() -> U256 {
+    U256::from(PRECISION)
+}
+
+/// Natural logarithm of `x / 10^36` for `x >= 10^36`, in 36-decimal fixed point
+fn ln_precise(x: U256) -> U256 {
+    let one = precision();
+    // Приводим x к [1, 2) делением на 2^k
+    let k = (x / one).bit_len().saturating_sub(1);
+    let reduced = x >> k;
+
+    // ln(a) = 2 * atanh((a - 1) / (a + 1)), ряд сходится быстро при a в [1, 2)
+    let z = (reduced - one) * one / (reduced + one);
+    let z_squared = z * z / one;
+    let mut term = z;
+    let mut sum = U256::ZERO;
+    let mut n = 1u64;
+    while term > U256::ZERO {
+        sum += term / U256::from(n);
+        term = term * z_squared / one;
+        n += 2;
+    }
+
+    U256::from(k) * U256::from(LN2) + sum * U256::from(2u64)
+}
+
+/// `exp(-y / 10^36)` in 36-decimal fixed point
+fn neg_exp_precise(y: U256) -> U256 {
+    let one = precision();
+    let ln2 = U256::from(LN2);
+    let k = y / ln2;
+    if k >= U256::from(120u64) {
+        return U256::ZERO;
+    }
+    let r = y - k * ln2;
+
+    // exp(r) для r в [0, ln 2) рядом Тейлора, затем 1 / exp(r) / 2^k
+    let mut term = one;
+    let mut sum = one;
+    let mut n = 1u64;
+    while term > U256::ZERO {
+        term = term * r / one / U256::from(n);
+        sum += term;
+        n += 1;
+    }
+
+    (one * one).div_ceil(sum) >> k.to::<usize>()
+}
+
+/// Computes `base^exponent` for `base <= 1`, both in 36-decimal fixed point, rounded up
+fn pow_up_precise(base: U256, exponent: U256) -> Option<U256> {
+    let one = precision();
+    if base >= one || exponent == U256::ZERO {
+        return Some(one);
+    }
+    if base == U256::ZERO {
+        return Some(U256::ZERO);
+    }
+
+    let ln_inverse = ln_precise((one * one) / base);
+    let y = mul_div(exponent, ln_inverse, one)?;
+    // +1 ulp компенсирует усечение в рядах, чтобы степень не была занижена
+    Some((neg_exp_precise(y) + U256::from(1u64)).min(one))
+}
+
+/// Calculates the output of a Balancer weighted pool swap (`calcOutGivenIn`).
+/// 
+/// # Arguments
+/// * `amount_in` - Input amount, raw units
+/// * `balance_in` / `balance_out` - Pool balances, raw units
+/// * `weight_in` / `weight_out` - Normalized weights, 18-decimal fixed point
+/// * `swap_fee` - Swap fee, 18-decimal fixed point (10^15 = 0.1%)
+/// 
+/// # Returns
+/// Output amount rounded down, or `None` if the input after fee exceeds
+/// 30% of `balance_in` (Balancer's `MAX_IN_RATIO`), the pool is empty,
+/// a weight is zero or the fee is 100% and more
+pub fn get_amount_out_weighted(
+    amount_in: U256,
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    swap_fee: U256,
+) -> Option<U256> {
+    let one = U256::from(ONE);
+    if balance_in == U256::ZERO
+        || balance_out == U256::ZERO
+        || weight_in == U256::ZERO
+        || weight_out == U256::ZERO
+        || swap_fee >= one
+    {
+        return None;
+    }
+
+    // Комиссия округляется вверх, как в Balancer
+    let fee_amount = amount_in.checked_mul(swap_fee)?.div_ceil(one);
+    let amount_in = amount_in - fee_amount;
+    if amount_in > mul_div(balance_in, U256::from(MAX_IN_RATIO), one)? {
+        return None;
+    }
+    if amount_in == U256::ZERO {
+        return Some(U256::ZERO);
+    }
+
+    let precision = precision();
+    let base = balance_in
+        .checked_mul(precision)?
+        .div_ceil(balance_in.checked_add(amount_in)?);
+    let exponent = mul_div(weight_in, precision, weight_out)?;
+    let power = pow_up_precise(base, exponent)?;
+
+    mul_div(balance_out, precision - power, precision)
+}
+
+/// Spot price of a weighted pool (output per input, raw units, without the fee)
+/// as a fraction: `(balance_out / weight_out) / (balance_in / weight_in)`
+pub fn spot_price_weighted(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+) -> Option<(U256, U256)> {
+    Some((balance_out.checked_mul(weight_in)?, balance_in.checked_mul(weight_out)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(decimal: &str) -> U256 {
+        decimal.parse().unwrap()
+    }
+
+    fn e18(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(ONE)
+    }
+
+    fn assert_close_below(actual: U256, reference_floor: U256) {
+        assert!(actual <= reference_floor, "{} > {}", actual, reference_floor);
+        // Допуск: 10^-15 от результата
+        let tolerance = reference_floor / U256::from(1_000_000_000_000_000u64) + U256::from(1u64);
+        assert!(reference_floor - actual <= tolerance, "{} vs {}", actual, reference_floor);
+    }
+
+    // Эталон: точная формула calcOutGivenIn, посчитанная с 80 знаками (Python decimal)
+    #[test]
+    fn matches_high_precision_reference() {
+        let half = U256::from(ONE / 2);
+        assert_close_below(
+            get_amount_out_weighted(e18(1_000), e18(1_000_000), half, e18(400_000_000), half, U256::from(3_000_000_000_000_000u64)).unwrap(),
+            value("398402792415961286597262"),
+        );
+        // 80/20 USDC/WETH, 6 и 18 decimals
+        assert_close_below(
+            get_amount_out_weighted(
+                U256::from(5_000_000_000u64),
+                U256::from(2_000_000_000_000u64),
+                U256::from(800_000_000_000_000_000u64),
+                e18(400),
+                U256::from(200_000_000_000_000_000u64),
+                U256::from(1_000_000_000_000_000u64),
+            ).unwrap(),
+            value("3971174057852555253"),
+        );
+        // 20/80 в обратную сторону
+        assert_close_below(
+            get_amount_out_weighted(
+                e18(1),
+                e18(100),
+                U256::from(200_000_000_000_000_000u64),
+                e18(1_000),
+                U256::from(800_000_000_000_000_000u64),
+                U256::from(2_500_000_000_000_000u64),
+            ).unwrap(),
+            value("2478318403393791185"),
+        );
+        // Равные веса без комиссии совпадают с constant product
+        assert_close_below(
+            get_amount_out_weighted(e18(29), e18(100), half, e18(100), half, U256::ZERO).unwrap(),
+            value("22480620155038759689"),
+        );
+    }
+
+    #[test]
+    fn rejects_inputs_above_max_in_ratio() {
+        let half = U256::from(ONE / 2);
+        assert!(get_amount_out_weighted(e18(30), e18(100), half, e18(100), half, U256::ZERO).is_some());
+        assert_eq!(get_amount_out_weighted(e18(31), e18(100), half, e18(100), half, U256::ZERO), None);
+        assert_eq!(get_amount_out_weighted(e18(1), U256::ZERO, half, e18(100), half, U256::ZERO), None);
+        assert_eq!(get_amount_out_weighted(U256::ZERO, e18(100), half, e18(100), half, U256::ZERO), Some(U256::ZERO));
+    }
+}
@@ -0,0 +1,248 @@
+// src/mock_chain.rs
+//! `ChainClient` с заданными ответами для тестов discovery и резервов
+//!
+//! В отличие от `mock_rpc.rs` здесь нет ни сервера, ни ABI: ответы задаются
+//! на уровне вызовов трейта (`add_pair`, `set_reserves`, ...), сбои
+//! включаются через `fail` / `fail_times`, а `calls` считает обращения.
+//! Неизвестные адреса ведут себя как в сети: `getPair` дает нулевой адрес,
+//! `getReserves` и `token0()` откатываются, `feeTo` - ноль. Откат приходит
+//! ошибкой узла (`ErrorResp`), а сбой из `fail` - ошибкой транспорта, как у
+//! `RpcProvider`.
+use crate::chain::{ChainClient, ChainFuture, PairReserves, WeightedPoolParams};
+use crate::config::TokenId;
+use alloy::primitives::{Address, B256, U256};
+use alloy::transports::{RpcError, TransportErrorKind};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Вызов клиента: ключ для сбоев и счетчиков
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockCall {
+    Reserves(Address),
+    ReservesBatch,
+    Pair(Address), // Factory
+    PairTokens(Address),
+    PairFactory(Address),
+    FeeTo(Address),
+    Decimals(Address),
+    Symbol(Address),
+    Code(Address),
+    WeightedPool(Address),
+    VaultPoolTokens(B256),
+    BlockNumber,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    reserves: HashMap<Address, PairReserves>,
+    pairs: HashMap<(Address, Address, Address), Address>, // (Factory, token0, token1) -> пара
+    pair_tokens: HashMap<Address, (Address, Address)>,
+    pair_factories: HashMap<Address, Address>,
+    fee_to: HashMap<Address, Address>,
+    decimals: HashMap<Address, u8>,
+    symbols: HashMap<Address, String>,
+    weighted: HashMap<Address, WeightedPoolParams>,
+    vault_tokens: HashMap<B256, (Vec<Address>, Vec<U256>)>,
+    block: u64,
+    failures: HashMap<MockCall, Option<u32>>, // None - всегда, Some(n) - еще n раз
+    calls: HashMap<MockCall, usize>,
+}
+
+/// Программируемый клиент блокчейна
+#[derive(Debug, Default)]
+pub struct MockChainClient {
+    state: Mutex<MockState>,
+}
+
+/// Откат вызова: ошибка узла с кодом 3, как у `eth_call`
+fn reverted(message: String) -> eyre::Report {
+    let payload = serde_json::from_value(serde_json::json!({ "code": 3, "message": message })).expect("ErrorPayload");
+    RpcError::<TransportErrorKind>::ErrorResp(payload).into()
+}
+
+/// Сбой транспорта (соединение, таймаут)
+fn unavailable(call: MockCall) -> eyre::Report {
+    TransportErrorKind::custom_str(&format!("mock: {:?} не удался", call)).into()
+}
+
+fn sorted(a: Address, b: Address) -> (Address, Address) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+impl MockChainClient {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Пара Factory: `getPair`, `token0/token1`, `factory()` и резервы (в порядке token0/token1)
+    pub fn add_pair(&self, factory: Address, token_a: TokenId, token_b: TokenId, pair: Address, reserves: PairReserves) {
+        let (token0, token1) = sorted(token_a.address(), token_b.address());
+        let mut state = self.state.lock().unwrap();
+        state.pairs.insert((factory, token0, token1), pair);
+        state.pair_tokens.insert(pair, (token0, token1));
+        state.pair_factories.insert(pair, factory);
+        state.reserves.insert(pair, reserves);
+    }
+
+    pub fn set_reserves(&self, pair: Address, reserves: PairReserves) {
+        self.state.lock().unwrap().reserves.insert(pair, reserves);
+    }
+
+    /// Номер последнего блока (`block_number` и блок multicall)
+    pub fn set_block(&self, block: u64) {
+        self.state.lock().unwrap().block = block;
+    }
+
+    pub fn set_fee_to(&self, factory: Address, fee_to: Address) {
+        self.state.lock().unwrap().fee_to.insert(factory, fee_to);
+    }
+
+    pub fn set_decimals(&self, token: TokenId, decimals: u8) {
+        self.state.lock().unwrap().decimals.insert(token.address(), decimals);
+    }
+
+    pub fn set_symbol(&self, token: TokenId, symbol: &str) {
+        self.state.lock().unwrap().symbols.insert(token.address(), symbol.to_string());
+    }
+
+    /// Взвешенный пул и его токены с балансами в Vault
+    pub fn add_weighted_pool(&self, pool: Address, params: WeightedPoolParams, tokens: Vec<(TokenId, U256)>) {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, balances) = tokens.into_iter().map(|(token, balance)| (token.address(), balance)).unzip();
+        state.vault_tokens.insert(params.pool_id, (tokens, balances));
+        state.weighted.insert(pool, params);
+    }
+
+    /// Вызов всегда завершается ошибкой
+    pub fn fail(&self, call: MockCall) {
+        self.state.lock().unwrap().failures.insert(call, None);
+    }
+
+    /// Следующие `times` вызовов завершаются ошибкой
+    pub fn fail_times(&self, call: MockCall, times: u32) {
+        self.state.lock().unwrap().failures.insert(call, Some(times));
+    }
+
+    /// Сколько раз был сделан вызов
+    pub fn calls(&self, call: MockCall) -> usize {
+        self.state.lock().unwrap().calls.get(&call).copied().unwrap_or(0)
+    }
+
+    /// Учитывает вызов и применяет сбой, если он задан
+    fn begin(&self, call: MockCall) -> eyre::Result<std::sync::MutexGuard<'_, MockState>> {
+        let mut state = self.state.lock().unwrap();
+        *state.calls.entry(call).or_default() += 1;
+        match state.take_failure(call) {
+            true => Err(unavailable(call)),
+            false => Ok(state),
+        }
+    }
+}
+
+impl MockState {
+    /// Должен ли вызов завершиться ошибкой; `fail_times` расходует одну ошибку,
+    /// исчерпанный счетчик (`Some(0)`) сбоя не дает
+    fn take_failure(&mut self, call: MockCall) -> bool {
+        match self.failures.get_mut(&call) {
+            Some(None) => true,
+            Some(Some(times)) if *times > 0 => {
+                *times -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Резервы пар внутри multicall: вызов пары со сбоем не удается, но сам multicall - нет
+    fn batch_reserves(&mut self, pairs: &[Address]) -> Vec<Option<PairReserves>> {
+        pairs
+            .iter()
+            .map(|pair| match self.take_failure(MockCall::Reserves(*pair)) {
+                true => None,
+                false => self.reserves.get(pair).copied(),
+            })
+            .collect()
+    }
+}
+
+impl ChainClient for MockChainClient {
+    fn get_reserves(&self, pair: Address) -> ChainFuture<'_, PairReserves> {
+        let result = self.begin(MockCall::Reserves(pair)).and_then(|state| {
+            state.reserves.get(&pair).copied().ok_or_else(|| reverted(format!("execution reverted: нет пары {:?}", pair)))
+        });
+        Box::pin(async move { result })
+    }
+
+    fn get_reserves_batch<'a>(&'a self, pairs: &'a [Address]) -> ChainFuture<'a, Vec<Option<PairReserves>>> {
+        let result = self.begin(MockCall::ReservesBatch).map(|mut state| state.batch_reserves(pairs));
+        Box::pin(async move { result })
+    }
+
+    fn get_reserves_batch_at_block<'a>(&'a self, pairs: &'a [Address]) -> ChainFuture<'a, (u64, Vec<Option<PairReserves>>)> {
+        let result = self.begin(MockCall::ReservesBatch).map(|mut state| (state.block, state.batch_reserves(pairs)));
+        Box::pin(async move { result })
+    }
+
+    fn block_number(&self) -> ChainFuture<'_, u64> {
+        let result = self.begin(MockCall::BlockNumber).map(|state| state.block);
+        Box::pin(async move { result })
+    }
+
+    fn get_pair(&self, factory: Address, token_a: Address, token_b: Address) -> ChainFuture<'_, Address> {
+        let (token0, token1) = sorted(token_a, token_b);
+        let result = self.begin(MockCall::Pair(factory)).map(|state| state.pairs.get(&(factory, token0, token1)).copied().unwrap_or(Address::ZERO));
+        Box::pin(async move { result })
+    }
+
+    fn get_pair_tokens(&self, pair: Address) -> ChainFuture<'_, (Address, Address)> {
+        let result = self
+            .begin(MockCall::PairTokens(pair))
+            .and_then(|state| state.pair_tokens.get(&pair).copied().ok_or_else(|| reverted(format!("execution reverted: {:?} не пара", pair))));
+        Box::pin(async move { result })
+    }
+
+    fn get_pair_factory(&self, pair: Address) -> ChainFuture<'_, Address> {
+        let result = self
+            .begin(MockCall::PairFactory(pair))
+            .and_then(|state| state.pair_factories.get(&pair).copied().ok_or_else(|| reverted(format!("execution reverted: {:?} не пара", pair))));
+        Box::pin(async move { result })
+    }
+
+    fn get_fee_to(&self, factory: Address) -> ChainFuture<'_, Address> {
+        let result = self.begin(MockCall::FeeTo(factory)).map(|state| state.fee_to.get(&factory).copied().unwrap_or(Address::ZERO));
+        Box::pin(async move { result })
+    }
+
+    fn get_decimals(&self, token: Address) -> ChainFuture<'_, u8> {
+        let result = self
+            .begin(MockCall::Decimals(token))
+            .and_then(|state| state.decimals.get(&token).copied().ok_or_else(|| reverted(format!("execution reverted: decimals() {:?}", token))));
+        Box::pin(async move { result })
+    }
+
+    fn get_symbol(&self, token: Address) -> ChainFuture<'_, Option<String>> {
+        let result = self.begin(MockCall::Symbol(token)).map(|state| state.symbols.get(&token).cloned());
+        Box::pin(async move { result })
+    }
+
+    fn is_contract(&self, address: Address) -> ChainFuture<'_, bool> {
+        let result = self.begin(MockCall::Code(address)).map(|state| {
+            state.reserves.contains_key(&address) || state.pair_tokens.contains_key(&address) || state.weighted.contains_key(&address)
+        });
+        Box::pin(async move { result })
+    }
+
+    fn get_weighted_pool(&self, pool: Address) -> ChainFuture<'_, WeightedPoolParams> {
+        let result = self
+            .begin(MockCall::WeightedPool(pool))
+            .and_then(|state| state.weighted.get(&pool).cloned().ok_or_else(|| reverted(format!("execution reverted: {:?} не пул Balancer", pool))));
+        Box::pin(async move { result })
+    }
+
+    fn get_vault_pool_tokens(&self, pool_id: B256) -> ChainFuture<'_, (Vec<Address>, Vec<U256>)> {
+        let result = self
+            .begin(MockCall::VaultPoolTokens(pool_id))
+            .and_then(|state| state.vault_tokens.get(&pool_id).cloned().ok_or_else(|| reverted(format!("execution reverted: пул {} не найден в Vault", pool_id))));
+        Box::pin(async move { result })
+    }
+}
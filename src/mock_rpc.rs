@@ -0,0 +1,249 @@
+// src/mock_rpc.rs
+//! Локальный JSON-RPC сервер для тестов провайдера
+//!
+//! Отвечает на запросы через обработчик `(method, params) -> result | error`,
+//! чтобы тестировать код, который ходит в сеть, без реального узла. Сервер
+//! слушает HTTP (`start`) или WebSocket (`start_ws`, подписки на логи -
+//! `start_ws_subscriptions`); провайдер создается через `create_provider`,
+//! поэтому транспорт выбирается так же, как в работе.
+use crate::provider::RpcProvider;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Обработчик запроса: результат или сообщение ошибки JSON-RPC
+pub type Handler = dyn Fn(&str, &Value) -> Result<Value, String> + Send + Sync;
+
+/// Запущенный сервер: адрес, провайдер на этот адрес и счетчик запросов
+pub struct MockRpc {
+    pub url: String,
+    pub provider: Arc<RpcProvider>,
+    pub requests: Arc<AtomicUsize>,
+}
+
+impl MockRpc {
+    /// Запускает сервер на случайном порту
+    pub async fn start(handler: impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handler: Arc<Handler> = Arc::new(handler);
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                let counter = Arc::clone(&counter);
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut reader = BufReader::new(read);
+                    // Keep-alive: несколько запросов в одном соединении
+                    while let Some(body) = read_request(&mut reader).await {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        // Обработчик может блокировать (медленный пул в тестах) - не занимаем воркер runtime
+                        let handler = Arc::clone(&handler);
+                        let (status, response) = tokio::task::spawn_blocking(move || respond(handler.as_ref(), &body))
+                            .await
+                            .unwrap();
+                        let http = format!(
+                            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            status,
+                            response.len(),
+                            response
+                        );
+                        if write.write_all(http.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let provider = crate::provider::create_provider(&url).await.unwrap();
+        MockRpc { url, provider, requests }
+    }
+
+    /// Запускает сервер JSON-RPC поверх WebSocket на случайном порту
+    ///
+    /// Каждое текстовое сообщение - запрос, ответ отправляется в то же
+    /// соединение. На `BAD_GATEWAY` сервер закрывает соединение.
+    pub async fn start_ws(handler: impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let handler: Arc<Handler> = Arc::new(handler);
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                let counter = Arc::clone(&counter);
+                tokio::spawn(async move {
+                    let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+                        return;
+                    };
+                    while let Some(Ok(message)) = socket.next().await {
+                        let Message::Text(body) = message else {
+                            continue;
+                        };
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let handler = Arc::clone(&handler);
+                        let (status, response) = tokio::task::spawn_blocking(move || respond(handler.as_ref(), body.as_bytes()))
+                            .await
+                            .unwrap();
+                        if status == BAD_GATEWAY || socket.send(Message::Text(response)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let provider = crate::provider::create_provider(&url).await.unwrap();
+        MockRpc { url, provider, requests }
+    }
+
+    /// Запускает сервер подписок WebSocket на случайном порту и возвращает его адрес
+    ///
+    /// `script(n)` задает n-е соединение (с нуля): `None` - соединение
+    /// закрывается без рукопожатия; `Some(logs)` - на `eth_subscribe` сервер
+    /// отвечает id подписки, отправляет `logs` уведомлениями и закрывает
+    /// соединение. Другие запросы получают пустой результат.
+    pub async fn start_ws_subscriptions(script: impl Fn(usize) -> Option<Vec<Value>> + Send + Sync + 'static) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let script = Arc::new(script);
+
+        tokio::spawn(async move {
+            let mut connection = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                let Some(logs) = script(connection) else {
+                    drop(stream);
+                    connection += 1;
+                    continue;
+                };
+                connection += 1;
+                tokio::spawn(async move {
+                    let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+                        return;
+                    };
+                    while let Some(Ok(message)) = socket.next().await {
+                        let Message::Text(body) = message else {
+                            continue;
+                        };
+                        let request: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+                        if request["method"] != "eth_subscribe" {
+                            let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": null });
+                            let _ = socket.send(Message::Text(response.to_string())).await;
+                            continue;
+                        }
+                        let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": "0x1" });
+                        let _ = socket.send(Message::Text(response.to_string())).await;
+                        // Клиент регистрирует подписку после ответа - не закрываем соединение раньше
+                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                        for log in &logs {
+                            let notification = json!({
+                                "jsonrpc": "2.0",
+                                "method": "eth_subscription",
+                                "params": { "subscription": "0x1", "result": log },
+                            });
+                            let _ = socket.send(Message::Text(notification.to_string())).await;
+                        }
+                        let _ = socket.close(None).await;
+                        return;
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    /// Сколько запросов обработано
+    pub fn request_count(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+/// Читает HTTP запрос и возвращает тело; `None` при закрытии соединения
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some(body)
+}
+
+/// Ошибка обработчика, на которую сервер отвечает HTTP 502 вместо JSON-RPC
+pub const BAD_GATEWAY: &str = "502 Bad Gateway";
+
+/// Статус HTTP и тело ответа
+fn respond(handler: &Handler, body: &[u8]) -> (&'static str, String) {
+    let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+    let id = request["id"].clone();
+    let method = request["method"].as_str().unwrap_or_default();
+    let response = match handler(method, &request["params"]) {
+        Err(message) if message == BAD_GATEWAY => return (BAD_GATEWAY, "bad gateway".to_string()),
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        // Hex-строка - данные revert (см. `revert_with`)
+        Err(data) if data.starts_with("0x") => {
+            json!({ "jsonrpc": "2.0", "id": id, "error": { "code": 3, "message": "execution reverted", "data": data } })
+        }
+        Err(message) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": 3, "message": message } }),
+    };
+    ("200 OK", response.to_string())
+}
+
+/// Ошибка обработчика: revert с `Error(string)` и данными revert в ответе
+pub fn revert_with(reason: &str) -> String {
+    use alloy::sol_types::SolError;
+    format!("0x{}", alloy::hex::encode(alloy::sol_types::Revert::from(reason).abi_encode()))
+}
+
+/// Calldata из параметров `eth_call`
+pub fn call_input(params: &Value) -> Option<Vec<u8>> {
+    let data = params[0]["input"].as_str().or_else(|| params[0]["data"].as_str())?;
+    alloy::hex::decode(data).ok()
+}
+
+/// Селектор (первые 4 байта calldata) из параметров `eth_call`
+pub fn call_selector(params: &Value) -> Option<[u8; 4]> {
+    call_input(params)?.get(..4)?.try_into().ok()
+}
+
+/// Адрес контракта из параметров `eth_call`
+pub fn call_target(params: &Value) -> Option<alloy::primitives::Address> {
+    params[0]["to"].as_str()?.parse().ok()
+}
+
+/// Отправитель (`from`) из параметров `eth_call`
+pub fn call_from(params: &Value) -> Option<alloy::primitives::Address> {
+    params[0]["from"].as_str()?.parse().ok()
+}
+
+/// ABI-кодированное слово uint256 в виде hex-строки результата
+pub fn encode_word(value: alloy::primitives::U256) -> Value {
+    json!(format!("0x{}", alloy::hex::encode(value.to_be_bytes::<32>())))
+}
+
+/// ABI-кодированный адрес (слово с нулями слева) в виде hex-строки результата
+pub fn encode_address(address: alloy::primitives::Address) -> Value {
+    encode_word(alloy::primitives::U256::from_be_slice(address.as_slice()))
+}
@@ -0,0 +1,169 @@
+// src/output.rs
+//! Вывод прогресса и отчетов
+//!
+//! Весь текстовый вывод анализа идет через `log!`: обычно в stdout, а в
+//! машинном режиме (`--quiet`) в stderr. Так stdout в машинном режиме
+//! содержит ровно одну строку `machine_line`, пригодную для awk:
+//! `OK <total_out_raw> <effective_price> <block>`,
+//! `PARTIAL <total_out_raw> <effective_price> <block> <unfilled_in_raw>` или `ERR <kind>`.
+use crate::chunk_guard::ChunkGuardAbort;
+use crate::compliance::ComplianceError;
+use crate::math::accumulator::AggregationError;
+use crate::solver::SolverError;
+use alloy::primitives::U256;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Включает машинный режим: `log!` пишет в stderr
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Печатает строку лога (используйте `log!`)
+pub fn write_line(args: fmt::Arguments) {
+    if is_quiet() {
+        eprintln!("{}", args);
+    } else {
+        println!("{}", args);
+    }
+}
+
+/// `println!` для вывода анализа; в машинном режиме уходит в stderr
+#[macro_export]
+macro_rules! log {
+    () => {
+        $crate::output::write_line(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write_line(format_args!($($arg)*))
+    };
+}
+
+/// Итог анализа для машинного режима
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunSummary {
+    /// Общий выход в raw units выходного токена
+    pub total_out: U256,
+    /// Цена исполнения (входной токен за выходной) с учетом decimals
+    pub execution_price: Option<f64>,
+    /// Блок, на котором прочитаны резервы
+    pub block: u64,
+    /// Не исполненный из-за предела цены чанка вход в raw units (0 - исполнено все)
+    pub unfilled_in: U256,
+}
+
+/// Анализ завершился без пулов для маршрутизации
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoPoolsFound;
+
+impl fmt::Display for NoPoolsFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "не найдено ни одного пула")
+    }
+}
+
+impl std::error::Error for NoPoolsFound {}
+
+/// Вид ошибки для строки `ERR <kind>`
+pub fn error_kind(error: &eyre::Report) -> &'static str {
+    if error.downcast_ref::<NoPoolsFound>().is_some() {
+        "no_pools"
+    } else if error.downcast_ref::<ComplianceError>().is_some() {
+        "compliance"
+    } else if error.downcast_ref::<alloy::transports::TransportError>().is_some()
+        || error.downcast_ref::<alloy::contract::Error>().is_some()
+    {
+        "rpc"
+    } else if error.downcast_ref::<SolverError>().is_some() {
+        "solver"
+    } else if error.downcast_ref::<ChunkGuardAbort>().is_some() {
+        "chunk_guard"
+    } else if error.downcast_ref::<AggregationError>().is_some() {
+        "overflow"
+    } else if error.downcast_ref::<std::io::Error>().is_some() {
+        "io"
+    } else if error.downcast_ref::<serde_json::Error>().is_some() {
+        "parse"
+    } else {
+        "other"
+    }
+}
+
+/// Единственная строка stdout машинного режима (без перевода строки)
+pub fn machine_line(result: &eyre::Result<RunSummary>) -> String {
+    match result {
+        Ok(RunSummary { total_out, execution_price: Some(price), block, unfilled_in }) if unfilled_in.is_zero() => {
+            format!("OK {} {:.6} {}", total_out, price, block)
+        }
+        // Частичное исполнение отличимо от полного: статус и неисполненный остаток
+        Ok(RunSummary { total_out, execution_price: Some(price), block, unfilled_in }) => {
+            format!("PARTIAL {} {:.6} {} {}", total_out, price, block, unfilled_in)
+        }
+        // Нулевой выход: цены исполнения нет
+        Ok(_) => "ERR no_output".to_string(),
+        Err(error) => format!("ERR {}", error_kind(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_line_is_a_single_awk_friendly_line() {
+        let summary = RunSummary { total_out: U256::from(1_234_567u64), execution_price: Some(2_500.123_456_7), block: 65_000_000, unfilled_in: U256::ZERO };
+        assert_eq!(machine_line(&Ok(summary)), "OK 1234567 2500.123457 65000000");
+        let partial = RunSummary { unfilled_in: U256::from(250_000_000u64), ..summary };
+        assert_eq!(machine_line(&Ok(partial)), "PARTIAL 1234567 2500.123457 65000000 250000000");
+        assert_eq!(machine_line(&Ok(RunSummary { execution_price: None, ..summary })), "ERR no_output");
+
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "нет файла\nвторая строка");
+        for (error, kind) in [
+            (eyre::Report::new(NoPoolsFound), "no_pools"),
+            (eyre::Report::new(ComplianceError {
+                sender: alloy::primitives::Address::ZERO,
+                token: crate::config::TokenId::USDC,
+                action: crate::compliance::TokenAction::Approve(alloy::primitives::Address::ZERO),
+                reason: "Pausable: paused".to_string(),
+            }), "compliance"),
+            (eyre::Report::new(SolverError::ZeroChunks), "solver"),
+            (eyre::Report::new(ChunkGuardAbort { chunk_index: 4, deviation_bps: -320.0, max_deviation_bps: 300 }), "chunk_guard"),
+            (eyre::Report::new(AggregationError::Overflow("общий выход")), "overflow"),
+            (eyre::Report::new(io), "io"),
+            (eyre::eyre!("многострочная\nошибка"), "other"),
+        ] {
+            let line = machine_line(&Err(error));
+            assert_eq!(line, format!("ERR {}", kind));
+            assert!(!line.contains('\n'));
+        }
+    }
+
+    #[tokio::test]
+    async fn wrapped_rpc_errors_keep_rpc_kind() {
+        use crate::mock_chain::{MockCall, MockChainClient};
+        use eyre::WrapErr;
+
+        // Так main оборачивает ошибку подключения провайдера
+        let error = crate::provider::create_provider("ws://127.0.0.1:1").await
+            .wrap_err("не удалось создать провайдер для подключения к Polygon")
+            .unwrap_err();
+        assert_eq!(error_kind(&error), "rpc", "{:#}", error);
+
+        // И ошибку --extra-pool, когда пара не отвечает на token0()/token1()
+        let pair = alloy::primitives::Address::repeat_byte(0x42);
+        let chain = MockChainClient::new();
+        chain.set_reserves(pair, (U256::from(5u64), U256::from(7u64), 0));
+        chain.fail(MockCall::PairTokens(pair));
+        let error = crate::provider::load_extra_pool(chain, pair, &crate::config::ConfigContext::default()).await
+            .wrap_err_with(|| format!("--extra-pool {:?}", pair))
+            .unwrap_err();
+        assert_eq!(error_kind(&error), "rpc", "{:#}", error);
+        assert!(format!("{:#}", error).contains("не похож на пару"), "{:#}", error);
+    }
+}
@@ -0,0 +1,153 @@
+// src/overrides.rs
+//! Подмена резервов пулов для анализа "что если" (--override-reserves)
+//!
+//! Файл - JSON-объект "адрес пула -> [reserve0, reserve1]" в raw units, в
+//! порядке token0/token1 контракта:
+//!
+//! ```json
+//! { "0x853Ee4b2A13f8a742d64C8F088bE7bA2131f670d": ["4000000000000", "1600000000000000000000"] }
+//! ```
+//!
+//! Резервы - десятичные (или `0x`) строки либо целые числа JSON. Подмена
+//! применяется после discovery и чтения резервов, до решения; пулы с
+//! подменой помечаются в выводе (`PoolState::reserves_overridden`).
+use crate::pool_registry::PoolRegistry;
+use alloy::primitives::{Address, U256};
+use eyre::{eyre, Result, WrapErr};
+use serde_json::Value;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Подмена резервов одного пула
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveOverride {
+    pub pool_address: Address,
+    pub reserve0: U256,
+    pub reserve1: U256,
+}
+
+/// Читает файл подмены
+pub fn load(path: &Path) -> Result<Vec<ReserveOverride>> {
+    let text = std::fs::read_to_string(path).wrap_err_with(|| format!("не удалось прочитать {}", path.display()))?;
+    parse(&text).wrap_err_with(|| format!("файл подмены резервов {}", path.display()))
+}
+
+/// Разбирает JSON подмены; ошибка называет пул и поле, которые не разобрались
+pub fn parse(json: &str) -> Result<Vec<ReserveOverride>> {
+    let value: Value = serde_json::from_str(json).map_err(|e| eyre!("некорректный JSON: {}", e))?;
+    let Value::Object(entries) = value else {
+        return Err(eyre!("ожидался объект \"адрес пула\": [reserve0, reserve1]"));
+    };
+    entries
+        .iter()
+        .map(|(key, reserves)| {
+            let pool_address = Address::from_str(key).map_err(|_| eyre!("некорректный адрес пула: {}", key))?;
+            let [reserve0, reserve1] = reserves.as_array().map(Vec::as_slice).unwrap_or_default() else {
+                return Err(eyre!("{}: ожидался массив из двух резервов [reserve0, reserve1], получено {}", key, reserves));
+            };
+            Ok(ReserveOverride {
+                pool_address,
+                reserve0: reserve(key, "reserve0", reserve0)?,
+                reserve1: reserve(key, "reserve1", reserve1)?,
+            })
+        })
+        .collect()
+}
+
+fn reserve(pool: &str, field: &str, value: &Value) -> Result<U256> {
+    let parsed = match value {
+        Value::String(text) => U256::from_str(text).ok(),
+        Value::Number(number) => number.as_u64().map(U256::from),
+        _ => None,
+    };
+    parsed.ok_or_else(|| eyre!("{}: {} должен быть неотрицательным целым в raw units (строкой для больших значений), получено {}", pool, field, value))
+}
+
+/// Применяет подмену к найденным пулам; `now` - время подмены (`last_updated` пулов)
+///
+/// Адрес, которого нет среди пулов, - ошибка: опечатка в адресе иначе
+/// молча дала бы результат без подмены.
+pub fn apply(pools: &mut PoolRegistry, overrides: &[ReserveOverride], now: u32) -> Result<()> {
+    for entry in overrides {
+        let pool = pools
+            .pools_mut()
+            .iter_mut()
+            .find(|pool| pool.pool_address == entry.pool_address)
+            .ok_or_else(|| eyre!("пул {:?} из файла подмены не найден среди пулов запуска; добавьте его через --extra-pool", entry.pool_address))?;
+        pool.state
+            .override_reserves(entry.reserve0, entry.reserve1, now)
+            .map_err(|e| eyre!("пул {} ({:?}): {}", pool.name, entry.pool_address, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigContext, TokenId};
+    use crate::pool::Pool;
+    use crate::solver::{find_best_routes, pool_usage, SolverConfig};
+
+    fn registry() -> PoolRegistry {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        PoolRegistry::from_pools([0x11, 0x22].into_iter().map(|byte| {
+            Pool::for_test(Address::repeat_byte(byte), TokenId::USDC, TokenId::WETH,
+                U256::from(2_000_000_000_000u64), U256::from(800u64) * weth, "Test USDC/WETH")
+        }))
+    }
+
+    #[test]
+    fn parses_strings_and_numbers_with_helpful_errors() {
+        let overrides = parse(r#"{"0x1111111111111111111111111111111111111111": ["4000000000000", 5]}"#).unwrap();
+        assert_eq!(overrides, vec![ReserveOverride {
+            pool_address: Address::repeat_byte(0x11),
+            reserve0: U256::from(4_000_000_000_000u64),
+            reserve1: U256::from(5u64),
+        }]);
+
+        let error = |json: &str| parse(json).unwrap_err().to_string();
+        assert!(error("[]").contains("ожидался объект"));
+        assert!(error(r#"{"0x12": ["1", "2"]}"#).contains("некорректный адрес пула: 0x12"));
+        assert!(error(r#"{"0x1111111111111111111111111111111111111111": ["1"]}"#).contains("массив из двух резервов"));
+        let negative = error(r#"{"0x1111111111111111111111111111111111111111": ["1", -2]}"#);
+        assert!(negative.contains("reserve1 должен быть неотрицательным"), "{}", negative);
+    }
+
+    #[test]
+    fn rejects_unknown_pools_and_zero_reserves() {
+        let mut pools = registry();
+        let unknown = [ReserveOverride { pool_address: Address::repeat_byte(0x99), reserve0: U256::from(1u64), reserve1: U256::from(1u64) }];
+        assert!(apply(&mut pools, &unknown, 0).unwrap_err().to_string().contains("не найден среди пулов"));
+
+        let zero = [ReserveOverride { pool_address: Address::repeat_byte(0x11), reserve0: U256::ZERO, reserve1: U256::from(1u64) }];
+        assert!(apply(&mut pools, &zero, 0).unwrap_err().to_string().contains("пустые резервы"));
+        assert!(pools.get(Address::repeat_byte(0x11)).is_some_and(|pool| !pool.reserves_overridden));
+    }
+
+    #[tokio::test]
+    async fn doubling_one_pool_shifts_the_allocation_towards_it() {
+        let ctx = ConfigContext::default();
+        let config = SolverConfig { total_amount_in: U256::from(200_000_000_000u64), num_chunks: 40, verbose: false, ..SolverConfig::default() };
+        let (ctx, config) = (&ctx, &config);
+        let share = |states, address| async move {
+            let result = find_best_routes(states, ctx, config).await.unwrap();
+            pool_usage(&result.chunk_routes).into_iter().find(|usage| usage.pool_address == address).map_or(0, |usage| usage.chunks)
+        };
+
+        let mut pools = registry();
+        let target = Address::repeat_byte(0x11);
+        let before = share(pools.states(), target).await;
+        assert_eq!(before, 20);
+
+        let pool = pools.get(target).unwrap();
+        let doubled = ReserveOverride { pool_address: target, reserve0: pool.reserve_token0 * U256::from(2u64), reserve1: pool.reserve_token1 * U256::from(2u64) };
+        apply(&mut pools, &[doubled], 1_760_000_000).unwrap();
+        assert!(pools.get(target).unwrap().reserves_overridden);
+        assert_eq!(pools.get(target).unwrap().last_updated, 1_760_000_000);
+        assert!(!pools.get(Address::repeat_byte(0x22)).unwrap().reserves_overridden);
+
+        // Вдвое более глубокий пул получает примерно две трети объема
+        let after = share(pools.states(), target).await;
+        assert!((26..=28).contains(&after), "{} -> {}", before, after);
+    }
+}
@@ -1,140 +1,2056 @@
 // src/pool.rs
-use alloy::primitives::{Address, U256};
-use alloy::providers::RootProvider;
-use alloy::transports::http::{Client, Http};
-use eyre::Result;
+use alloy::primitives::{Address, B256, I256, U256, U512};
+use eyre::{bail, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
-use crate::provider::get_pool_reserves;
-use crate::math::get_amount_out;
+use crate::log;
+use crate::config::{format_units, format_units_truncated, DexId, TokenId};
+use crate::chain::{ChainClient, PairReserves};
+use crate::market_snapshot::MarketSnapshot;
+use crate::provider::{get_token_decimals, get_token_symbol, get_weighted_pool_balances, pool_label, short_address};
+use crate::math::{amount_in_to_reach_price, get_amount_in, marginal_rate, max_input_for_impact, price_impact, spot_price, spot_price_rational, get_amount_out_precomputed, get_amount_out_with_fee, mul_div, u256_to_f64, BPS_DENOMINATOR, DEFAULT_FEE_BPS};
+use crate::math::weighted::{get_amount_out_weighted, spot_price_weighted};
 
-/// Структура для представления пула ликвидности
+/// Предвычисленные для котировок величины пула
+/// 
+/// Хранит резервы, умноженные на 10000, и множитель комиссии, чтобы
+/// не пересчитывать их на каждый чанк. Кэш привязан к резервам, из которых
+/// он построен, и должен пересоздаваться при любом их изменении.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuoteCache {
+    pub reserve_token0: U256,
+    pub reserve_token1: U256,
+    pub reserve_token0_scaled: Option<U256>,
+    pub reserve_token1_scaled: Option<U256>,
+    pub fee_multiplier: U256,
+}
+
+impl QuoteCache {
+    /// Строит кэш для заданных резервов и комиссии в basis points
+    pub fn new(reserve_token0: U256, reserve_token1: U256, fee_bps: u32) -> Self {
+        let scale = U256::from(BPS_DENOMINATOR);
+        QuoteCache {
+            reserve_token0,
+            reserve_token1,
+            reserve_token0_scaled: reserve_token0.checked_mul(scale),
+            reserve_token1_scaled: reserve_token1.checked_mul(scale),
+            fee_multiplier: U256::from(BPS_DENOMINATOR.saturating_sub(fee_bps)),
+        }
+    }
+
+    /// Проверяет, что кэш построен из указанных резервов
+    pub fn matches(&self, reserve_token0: U256, reserve_token1: U256) -> bool {
+        self.reserve_token0 == reserve_token0 && self.reserve_token1 == reserve_token1
+    }
+
+    /// Рассчитывает выход свапа по предвычисленным величинам
+    /// При переполнении U256 переходит на полную формулу с широкой арифметикой
+    pub fn quote(&self, amount_in: U256, input_is_token0: bool) -> U256 {
+        let (reserve_in, reserve_in_scaled, reserve_out) = if input_is_token0 {
+            (self.reserve_token0, self.reserve_token0_scaled, self.reserve_token1)
+        } else {
+            (self.reserve_token1, self.reserve_token1_scaled, self.reserve_token0)
+        };
+
+        reserve_in_scaled
+            .and_then(|scaled| get_amount_out_precomputed(amount_in, scaled, reserve_out, self.fee_multiplier))
+            .unwrap_or_else(|| {
+                let fee_bps = BPS_DENOMINATOR - self.fee_multiplier.to::<u32>();
+                get_amount_out_with_fee(amount_in, reserve_in, reserve_out, fee_bps)
+            })
+    }
+}
+
+/// Тип кривой пула
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PoolKind {
+    /// Uniswap V2 constant product (x * y = k) с комиссией `Pool::fee_bps`
+    #[default]
+    ConstantProduct,
+    /// Balancer V2 weighted pool; веса и комиссия в fixed point 18 decimals
+    Weighted {
+        pool_id: B256,
+        weight_token0: U256,
+        weight_token1: U256,
+        swap_fee: U256,
+    },
+}
+
+/// Ошибки симулированного свапа
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+    /// Нулевая сумма входа
+    ZeroAmount,
+    /// Один из резервов пула равен нулю
+    EmptyReserves,
+    /// Выход не меньше выходного резерва: свап опустошил бы пул
+    /// (у взвешенного пула - вход больше 30% баланса, `MAX_IN_RATIO` Balancer)
+    InsufficientLiquidity,
+    /// Выход свапа округляется до нуля: вход слишком мал для пула
+    InsufficientOutput,
+    /// Входной резерв после свапа не помещается в U256
+    Overflow,
+    /// Для суммы нет котировки (пулы с внешним котированием, например Uniswap V3)
+    MissingQuote,
+    /// Входной токен не торгуется в пуле
+    TokenNotInPool,
+    /// Произведение резервов constant product пула уменьшилось бы после свапа
+    /// (ошибка округления или логики в математике пула)
+    InvariantViolated,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::ZeroAmount => write!(f, "нулевая сумма свапа"),
+            PoolError::EmptyReserves => write!(f, "пустые резервы пула"),
+            PoolError::InsufficientLiquidity => write!(f, "выход свапа не меньше резерва пула"),
+            PoolError::InsufficientOutput => write!(f, "выход свапа округляется до нуля"),
+            PoolError::Overflow => write!(f, "переполнение резерва пула"),
+            PoolError::MissingQuote => write!(f, "нет котировки для суммы"),
+            PoolError::TokenNotInPool => write!(f, "токен не торгуется в пуле"),
+            PoolError::InvariantViolated => write!(f, "свап уменьшил бы k = reserve0 * reserve1"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+/// Сохраненные резервы пула для отката симулированных свапов
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReservesSnapshot {
+    pub reserve_token0: U256,
+    pub reserve_token1: U256,
+}
+
+/// Виртуальный свап, примененный к пулу через `mock_swap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapEvent {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub input_is_token0: bool,
+    pub reserves: ReservesSnapshot, // Резервы после свапа
+}
+
+/// Журнал виртуальных свапов пула от резервов `initial`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapHistory {
+    pub initial: ReservesSnapshot,
+    pub events: Vec<SwapEvent>,
+}
+
+impl SwapHistory {
+    /// Резервы, к которым приводят свапы журнала, если применить их к `initial`
+    ///
+    /// Считается только по входам и выходам событий, без математики пула;
+    /// `None`, если событие уводит резерв за границы U256.
+    pub fn replay(&self) -> Option<ReservesSnapshot> {
+        self.events.iter().try_fold(self.initial, |reserves, event| {
+            let (reserve_in, reserve_out) = if event.input_is_token0 {
+                (reserves.reserve_token0, reserves.reserve_token1)
+            } else {
+                (reserves.reserve_token1, reserves.reserve_token0)
+            };
+            let (reserve_in, reserve_out) = (reserve_in.checked_add(event.amount_in)?, reserve_out.checked_sub(event.amount_out)?);
+            Some(if event.input_is_token0 {
+                ReservesSnapshot { reserve_token0: reserve_in, reserve_token1: reserve_out }
+            } else {
+                ReservesSnapshot { reserve_token0: reserve_out, reserve_token1: reserve_in }
+            })
+        })
+    }
+
+    /// Сумма входа и выхода свапов в направлении `input_is_token0`
+    pub fn totals(&self, input_is_token0: bool) -> (U256, U256) {
+        self.events
+            .iter()
+            .filter(|event| event.input_is_token0 == input_is_token0)
+            .fold((U256::ZERO, U256::ZERO), |(amount_in, amount_out), event| {
+                (amount_in.saturating_add(event.amount_in), amount_out.saturating_add(event.amount_out))
+            })
+    }
+}
+
+/// Состояние пула ликвидности без подключения к сети
+///
+/// Вся математика котировок и симуляции свапов живет здесь, поэтому
+/// солвер и тесты работают с `PoolState` без провайдера. Резервы из
+/// блокчейна обновляет `Pool`, который хранит состояние вместе с провайдером.
 #[derive(Debug, Clone)]
-pub struct Pool {
+pub struct PoolState {
     pub pool_address: Address,
-    pub provider: Arc<RootProvider<Http<Client>>>,
-    pub token0_address: Address,
-    pub token1_address: Address,
+    pub dex: DexId,
+    pub token0: TokenId,
+    pub token1: TokenId,
+    pub token0_decimals: u8, // Из decimals() токена; до запроса - из метаданных TokenId
+    pub token1_decimals: u8,
+    pub decimals_fetched: bool, // decimals прочитаны у контракта или из сохраненных пулов
     pub reserve_token0: U256,
     pub reserve_token1: U256,
     pub name: String,
+    pub quote_cache: QuoteCache,
+    pub kind: PoolKind,
+    pub protocol_fee_enabled: bool, // У Factory задан feeTo: часть комиссии LP уходит протоколу
+    pub fee_bps: u32,               // Комиссия constant product пула (взвешенные используют swap_fee)
+    pub last_updated: u32,          // blockTimestampLast из getReserves (0 - неизвестно, например у Balancer)
+    pub reserves_overridden: bool,  // Резервы заданы вручную (--override-reserves), а не прочитаны из сети
+    pub history: Option<SwapHistory>, // Журнал mock_swap (None - не ведется, см. `record_history`)
 }
 
-impl Pool {
-    /// Создает новый экземпляр пула с нулевыми резервами
-    /// Для получения актуальных резервов используйте refresh_reserves()
+impl PoolState {
+    /// Создает состояние пула с нулевыми резервами
     /// 
     /// # Arguments
     /// * `pool_address` - Адрес контракта пула
-    /// * `token0_address` - Адрес первого токена (должен быть < token1_address)
-    /// * `token1_address` - Адрес второго токена
-    /// * `provider` - Провайдер для подключения к блокчейну
+    /// * `token_a` - Первый токен пары (порядок не важен)
+    /// * `token_b` - Второй токен пары
+    /// * `dex` - DEX, которому принадлежит пул
     /// * `name` - Имя пула для идентификации (например, "Uniswap V2 USDC/WETH")
     /// 
     /// # Returns
-    /// Новый экземпляр Pool с нулевыми резервами
+    /// Новый экземпляр PoolState с нулевыми резервами
     pub fn new(
         pool_address: Address,
-        token0_address: Address,
-        token1_address: Address,
-        provider: Arc<RootProvider<Http<Client>>>,
+        token_a: TokenId,
+        token_b: TokenId,
+        dex: DexId,
         name: String,
     ) -> Self {
         // Убеждаемся, что token0 < token1 (стандарт Uniswap V2)
-        let (token0, token1) = if token0_address < token1_address {
-            (token0_address, token1_address)
+        let (token0, token1) = if token_a < token_b {
+            (token_a, token_b)
         } else {
-            (token1_address, token0_address)
+            (token_b, token_a)
         };
         
-        Pool {
+        PoolState {
             pool_address,
-            provider,
-            token0_address: token0,
-            token1_address: token1,
+            dex,
+            token0,
+            token1,
+            token0_decimals: token0.decimals(),
+            token1_decimals: token1.decimals(),
+            decimals_fetched: false,
             reserve_token0: U256::ZERO,
             reserve_token1: U256::ZERO,
             name,
+            quote_cache: QuoteCache::new(U256::ZERO, U256::ZERO, DEFAULT_FEE_BPS),
+            kind: PoolKind::ConstantProduct,
+            protocol_fee_enabled: false,
+            fee_bps: DEFAULT_FEE_BPS,
+            last_updated: 0,
+            reserves_overridden: false,
+            history: None,
         }
     }
     
-    /// Создает Pool и сразу получает актуальные резервы из блокчейна
+    /// Делает пул взвешенным (Balancer), сопоставляя веса с token0/token1
     /// 
     /// # Arguments
-    /// * `pool_address` - Адрес контракта пула
-    /// * `token0_address` - Адрес первого токена
-    /// * `token1_address` - Адрес второго токена
-    /// * `provider` - Провайдер для подключения к блокчейну
-    /// * `name` - Имя пула для идентификации
-    /// 
-    /// # Returns
-    /// Pool с актуальными резервами или ошибка
-    pub async fn with_reserves(
-        pool_address: Address,
-        token0_address: Address,
-        token1_address: Address,
-        provider: Arc<RootProvider<Http<Client>>>,
-        name: String,
-    ) -> Result<Self> {
-        let mut pool = Self::new(pool_address, token0_address, token1_address, provider, name);
-        pool.refresh_reserves().await?;
-        Ok(pool)
+    /// * `pool_id` - Идентификатор пула в Balancer Vault
+    /// * `weights` - Нормализованные веса двух токенов пула (порядок не важен)
+    /// * `swap_fee` - Комиссия в fixed point 18 decimals
+    pub fn into_weighted(mut self, pool_id: B256, weights: [(TokenId, U256); 2], swap_fee: U256) -> Self {
+        let weight_of = |token: TokenId| {
+            weights.iter().find(|(id, _)| *id == token).map_or(U256::ZERO, |(_, weight)| *weight)
+        };
+        self.kind = PoolKind::Weighted {
+            pool_id,
+            weight_token0: weight_of(self.token0),
+            weight_token1: weight_of(self.token1),
+            swap_fee,
+        };
+        self
+    }
+    
+    /// Задает комиссию пула в bps (по умолчанию DEFAULT_FEE_BPS) и пересоздает кэш котировок
+    pub fn with_fee_bps(mut self, fee_bps: u32) -> Self {
+        self.fee_bps = fee_bps;
+        self.invalidate_quote_cache();
+        self
+    }
+
+    /// true для пулов Uniswap V2 (к ним применимы аналитические методы солвера)
+    pub fn is_constant_product(&self) -> bool {
+        self.kind == PoolKind::ConstantProduct
+    }
+
+    /// Комиссия пула для логов: "30 bps" или доля swap_fee взвешенного пула
+    pub fn fee_label(&self) -> String {
+        match self.kind {
+            PoolKind::ConstantProduct => format!("{} bps", self.fee_bps),
+            PoolKind::Weighted { swap_fee, .. } => {
+                let bps = mul_div(swap_fee, U256::from(BPS_DENOMINATOR), U256::from(crate::math::weighted::ONE));
+                format!("{} bps", bps.unwrap_or(U256::ZERO))
+            }
+        }
+    }
+
+    /// Комиссия свапа во входном токене (raw units)
+    pub fn swap_fee_amount(&self, amount_in: U256) -> U256 {
+        match self.kind {
+            PoolKind::ConstantProduct => {
+                // Комиссия меньше суммы, поэтому помещается в U256 даже для amount_in около U256::MAX
+                mul_div(amount_in, U256::from(self.fee_bps), U256::from(BPS_DENOMINATOR)).unwrap_or(U256::ZERO)
+            }
+            PoolKind::Weighted { swap_fee, .. } => {
+                mul_div(amount_in, swap_fee, U256::from(crate::math::weighted::ONE)).unwrap_or(U256::ZERO)
+            }
+        }
+    }
+    
+    /// Decimals (входного, выходного) токена для направления свапа
+    pub fn decimals_for(&self, input_is_token0: bool) -> (u8, u8) {
+        if input_is_token0 {
+            (self.token0_decimals, self.token1_decimals)
+        } else {
+            (self.token1_decimals, self.token0_decimals)
+        }
     }
     
+    /// Decimals токена пула, если они достоверны: прочитаны у контракта,
+    /// загружены из сохраненных пулов или известны из метаданных `TokenId`
+    /// 
+    /// `None` - токен неизвестен конфигурации, а decimals не запрашивались
+    /// (в полях стоит значение по умолчанию 18).
+    pub fn known_decimals(&self, token: TokenId) -> Option<u8> {
+        let decimals = if token == self.token0 { self.token0_decimals } else { self.token1_decimals };
+        (self.decimals_fetched || token.info().is_some()).then_some(decimals)
+    }
+
+    /// Строка таблицы пулов с выровненными колонками (заголовок - `summary_header`)
+    pub fn summary_row(&self) -> String {
+        format!("{:<28} | {:<14} | {:<13} | {:>28} | {:>28} | {:>20} | {:>9}",
+            self.name, self.dex.to_string(), short_address(self.pool_address),
+            self.reserve_label(self.token0), self.reserve_label(self.token1),
+            self.price_label().unwrap_or_else(|| "-".to_string()), self.fee_label())
+    }
+
+    /// Заголовок таблицы пулов для `summary_row`
+    pub fn summary_header() -> String {
+        format!("{:<28} | {:<14} | {:<13} | {:>28} | {:>28} | {:>20} | {:>9}",
+            "Пул", "DEX", "Адрес", "Резерв token0", "Резерв token1", "Цена token1", "Комиссия")
+    }
+
+    /// Резерв токена с учетом decimals; при неизвестных decimals - в raw units
+    fn reserve_label(&self, token: TokenId) -> String {
+        let reserve = if token == self.token0 { self.reserve_token0 } else { self.reserve_token1 };
+        match self.known_decimals(token) {
+            Some(decimals) => format!("{} {}", format_units_truncated(reserve, decimals, RESERVE_PRECISION), token_label(token)),
+            None => format!("{} raw {}", reserve, token_label(token)),
+        }
+    }
+
+    /// Цена token1 в token0 (`price_token1_in_token0`); `None` для пустого пула
+    /// или если decimals одного из токенов неизвестны
+    fn price_label(&self) -> Option<String> {
+        self.known_decimals(self.token0)?;
+        self.known_decimals(self.token1)?;
+        let (numerator, denominator) = self.price_token1_in_token0()?;
+        let price = mul_div(numerator, U256::from(10u64).pow(U256::from(PRICE_PRECISION)), denominator)?;
+        Some(format!("{} {}", format_units(price, PRICE_PRECISION), token_label(self.token0)))
+    }
+
     /// Вычисляет количество выходных токенов для заданного количества входных токенов
-    /// Использует формулу Uniswap V2 constant product
+    /// Использует формулу Uniswap V2 constant product или взвешенную формулу Balancer
+    /// Если резервы или комиссия менялись в обход `mock_swap`/`refresh_reserves`/`with_fee_bps`,
+    /// кэш не используется и расчет идет напрямую
     /// 
     /// # Arguments
     /// * `amount_in` - Количество входных токенов
     /// * `input_is_token0` - true если входной токен это token0, false если token1
     /// 
     /// # Returns
-    /// Количество выходных токенов
+    /// Количество выходных токенов (0, если вход Balancer превышает MAX_IN_RATIO)
     pub fn get_amount_out(&self, amount_in: U256, input_is_token0: bool) -> U256 {
-        if input_is_token0 {
+        self.try_get_amount_out(amount_in, input_is_token0).unwrap_or(U256::ZERO)
+    }
+
+    /// `get_amount_out` с причиной, по которой пул не котирует сумму
+    ///
+    /// Ноль у `get_amount_out` означает и пустой пул, и слишком малый вход,
+    /// и отказ Balancer; здесь это `EmptyReserves`, `InsufficientOutput` и
+    /// `InsufficientLiquidity`. Выход не проверяется на резерв (см. `simulate_swap`).
+    pub fn try_get_amount_out(&self, amount_in: U256, input_is_token0: bool) -> Result<U256, PoolError> {
+        let (reserve_in, reserve_out) = self.reserves_for(input_is_token0);
+        if reserve_in == U256::ZERO || reserve_out == U256::ZERO {
+            return Err(PoolError::EmptyReserves);
+        }
+        let amount_out = self.raw_amount_out(amount_in, input_is_token0).ok_or(PoolError::InsufficientLiquidity)?;
+        if amount_out == U256::ZERO {
+            return Err(PoolError::InsufficientOutput);
+        }
+        Ok(amount_out)
+    }
+
+    /// Выход по формуле пула; `None`, если взвешенный пул отклоняет вход
+    fn raw_amount_out(&self, amount_in: U256, input_is_token0: bool) -> Option<U256> {
+        if let PoolKind::Weighted { weight_token0, weight_token1, swap_fee, .. } = self.kind {
+            let (reserve_in, reserve_out) = self.reserves_for(input_is_token0);
+            let (weight_in, weight_out) = if input_is_token0 {
+                (weight_token0, weight_token1)
+            } else {
+                (weight_token1, weight_token0)
+            };
+            return get_amount_out_weighted(amount_in, reserve_in, weight_in, reserve_out, weight_out, swap_fee);
+        }
+
+        let fee_multiplier = U256::from(BPS_DENOMINATOR.saturating_sub(self.fee_bps));
+        if self.quote_cache.matches(self.reserve_token0, self.reserve_token1) && self.quote_cache.fee_multiplier == fee_multiplier {
+            return Some(self.quote_cache.quote(amount_in, input_is_token0));
+        }
+
+        Some(if input_is_token0 {
             // Обмениваем token0 на token1
-            get_amount_out(amount_in, self.reserve_token0, self.reserve_token1)
+            get_amount_out_with_fee(amount_in, self.reserve_token0, self.reserve_token1, self.fee_bps)
         } else {
             // Обмениваем token1 на token0
-            get_amount_out(amount_in, self.reserve_token1, self.reserve_token0)
+            get_amount_out_with_fee(amount_in, self.reserve_token1, self.reserve_token0, self.fee_bps)
+        })
+    }
+    
+    /// Возвращает спот-цену (маржинальную цену до сделки) с учетом decimals токенов
+    /// 
+    /// # Arguments
+    /// * `input_is_token0` - true если входной токен это token0, false если token1
+    /// 
+    /// # Returns
+    /// Количество выходного токена за один входной токен (0.0 для пустого пула)
+    pub fn spot_price(&self, input_is_token0: bool) -> f64 {
+        let decimals0 = self.token0_decimals;
+        let decimals1 = self.token1_decimals;
+        if input_is_token0 {
+            spot_price(self.reserve_token0, self.reserve_token1, decimals0, decimals1)
+        } else {
+            spot_price(self.reserve_token1, self.reserve_token0, decimals1, decimals0)
+        }
+    }
+
+    /// Точная цена token1 в единицах token0 с учетом decimals (например, USDC за WETH)
+    /// 
+    /// Для взвешенного пула резервы делятся на веса: цена = (r0 / w0) / (r1 / w1).
+    /// 
+    /// # Returns
+    /// `(числитель, знаменатель)` или `None` для пула с нулевым резервом
+    pub fn price_token1_in_token0(&self) -> Option<(U256, U256)> {
+        let (numerator, denominator) =
+            spot_price_rational(self.reserve_token1, self.reserve_token0, self.token1_decimals, self.token0_decimals)?;
+        match self.kind {
+            PoolKind::ConstantProduct => Some((numerator, denominator)),
+            PoolKind::Weighted { weight_token0, weight_token1, .. } => {
+                Some((numerator.checked_mul(weight_token1)?, denominator.checked_mul(weight_token0)?))
+            }
+        }
+    }
+
+    /// Точная цена token0 в единицах token1 (обратная к `price_token1_in_token0`)
+    pub fn price_token0_in_token1(&self) -> Option<(U256, U256)> {
+        self.price_token1_in_token0().map(|(numerator, denominator)| (denominator, numerator))
+    }
+
+    /// `price_token1_in_token0` в f64 (с потерей точности)
+    pub fn price_token1_in_token0_f64(&self) -> Option<f64> {
+        self.price_token1_in_token0().map(|(numerator, denominator)| u256_to_f64(numerator) / u256_to_f64(denominator))
+    }
+
+    /// `price_token0_in_token1` в f64 (с потерей точности)
+    pub fn price_token0_in_token1_f64(&self) -> Option<f64> {
+        self.price_token0_in_token1().map(|(numerator, denominator)| u256_to_f64(numerator) / u256_to_f64(denominator))
+    }
+    
+    /// Вычисляет price impact сделки: 1 - (цена исполнения / спот-цена)
+    /// 
+    /// # Arguments
+    /// * `amount_in` - Количество входных токенов
+    /// * `input_is_token0` - true если входной токен это token0, false если token1
+    /// 
+    /// # Returns
+    /// Impact от 0.0 до 1.0 (1.0 для пустого пула)
+    pub fn price_impact(&self, amount_in: U256, input_is_token0: bool) -> f64 {
+        if let PoolKind::Weighted { weight_token0, weight_token1, .. } = self.kind {
+            // Для взвешенного пула: 1 - (выход / вход) / спот-цена без комиссии
+            let (reserve_in, reserve_out) = self.reserves_for(input_is_token0);
+            let (weight_in, weight_out) = if input_is_token0 {
+                (weight_token0, weight_token1)
+            } else {
+                (weight_token1, weight_token0)
+            };
+            let spot = spot_price_weighted(reserve_in, weight_in, reserve_out, weight_out)
+                .filter(|(_, den)| *den > U256::ZERO)
+                .map(|(num, den)| u256_to_f64(num) / u256_to_f64(den));
+            return match spot {
+                Some(spot) if spot > 0.0 && amount_in > U256::ZERO => {
+                    let execution = u256_to_f64(self.get_amount_out(amount_in, input_is_token0)) / u256_to_f64(amount_in);
+                    (1.0 - execution / spot).clamp(0.0, 1.0)
+                }
+                Some(_) if amount_in == U256::ZERO => 0.0,
+                _ => 1.0,
+            };
+        }
+
+        if input_is_token0 {
+            price_impact(amount_in, self.reserve_token0, self.reserve_token1, self.fee_bps)
+        } else {
+            price_impact(amount_in, self.reserve_token1, self.reserve_token0, self.fee_bps)
+        }
+    }
+    
+    /// Вычисляет максимальный вход, при котором price impact не превышает
+    /// `max_impact_bps` (комиссия входит в impact, см. `math::max_input_for_impact`)
+    /// Для взвешенных пулов формула не применима и возвращается 0
+    pub fn max_input_for_impact(&self, max_impact_bps: u32, input_is_token0: bool) -> U256 {
+        if !self.is_constant_product() {
+            return U256::ZERO;
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(input_is_token0);
+        max_input_for_impact(reserve_in, reserve_out, max_impact_bps, self.fee_bps)
+    }
+    
+    /// Верхняя граница выхода `token_out` (raw units) при любом входе
+    /// 
+    /// Формула с округлением вниз никогда не отдает весь резерв, поэтому это
+    /// `reserve_out - 1`, если такой выход достижим входом в пределах U256,
+    /// иначе выход при входе `U256::MAX`. Комиссия 100% дает 0.
+    /// Для взвешенных пулов, пустых пулов и токена не из пула возвращается 0
+    pub fn max_output(&self, token_out: TokenId) -> U256 {
+        let Ok(output_is_token0) = self.is_token0(token_out) else {
+            return U256::ZERO;
+        };
+        if !self.is_constant_product() {
+            return U256::ZERO;
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(!output_is_token0);
+        let ceiling = reserve_out.saturating_sub(U256::from(1u64));
+        match get_amount_in(ceiling, reserve_in, reserve_out, self.fee_bps) {
+            Some(_) => ceiling,
+            None => get_amount_out_with_fee(U256::MAX, reserve_in, reserve_out, self.fee_bps),
+        }
+    }
+
+    /// Минимальный вход, дающий не меньше `desired_out` выходного токена
+    /// (см. `math::get_amount_in`)
+    /// 
+    /// # Arguments
+    /// * `desired_out` - Нужный выход (raw units)
+    /// * `output_is_token0` - true если выходной токен это token0, false если token1
+    /// 
+    /// # Returns
+    /// None, если пул не может отдать `desired_out` (выход не меньше резерва,
+    /// вход не помещается в U256, пустой пул) или пул не constant product
+    pub fn max_input_for_output(&self, desired_out: U256, output_is_token0: bool) -> Option<U256> {
+        if !self.is_constant_product() {
+            return None;
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(!output_is_token0);
+        get_amount_in(desired_out, reserve_in, reserve_out, self.fee_bps)
+    }
+
+    /// Вычисляет маржинальный курс d(amountOut)/d(amountIn) после того,
+    /// как в пул уже распределено `allocated` входных токенов
+    /// 
+    /// # Arguments
+    /// * `allocated` - Уже распределенный в пул вход
+    /// * `input_is_token0` - true если входной токен это token0, false если token1
+    /// 
+    /// # Returns
+    /// Точная дробь (числитель, знаменатель) или None для пустого
+    /// или не constant product пула
+    pub fn marginal_rate(&self, allocated: U256, input_is_token0: bool) -> Option<(U256, U256)> {
+        if !self.is_constant_product() {
+            return None;
+        }
+        if input_is_token0 {
+            marginal_rate(allocated, self.reserve_token0, self.reserve_token1, self.fee_bps)
+        } else {
+            marginal_rate(allocated, self.reserve_token1, self.reserve_token0, self.fee_bps)
+        }
+    }
+    
+    /// Вычисляет вход, который сдвигает цену пула (выход за вход, raw units) до цели
+    /// 
+    /// # Returns
+    /// None, если цена пула уже ниже цели (см. `math::amount_in_to_reach_price`)
+    /// или пул не constant product
+    pub fn amount_in_to_reach_price(
+        &self,
+        target_price_num: U256,
+        target_price_den: U256,
+        input_is_token0: bool,
+    ) -> Option<U256> {
+        if !self.is_constant_product() {
+            return None;
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(input_is_token0);
+        amount_in_to_reach_price(reserve_in, reserve_out, target_price_num, target_price_den, self.fee_bps)
+    }
+    
+    /// Сколько секунд резервы не менялись к моменту `block_timestamp`
+    /// 
+    /// `blockTimestampLast` хранится по модулю 2^32, поэтому сравнение идет
+    /// с младшими 32 битами времени блока. None, если время обновления неизвестно.
+    pub fn reserves_age_secs(&self, block_timestamp: u64) -> Option<u64> {
+        (self.last_updated != 0).then(|| u64::from((block_timestamp as u32).wrapping_sub(self.last_updated)))
+    }
+
+    /// Возвращает резервы (reserve_in, reserve_out) для направления свапа
+    pub fn reserves_for(&self, input_is_token0: bool) -> (U256, U256) {
+        if input_is_token0 {
+            (self.reserve_token0, self.reserve_token1)
+        } else {
+            (self.reserve_token1, self.reserve_token0)
+        }
+    }
+    
+    /// Резерв токена `token` в пуле (raw units) или `None`, если токен не из пула
+    pub fn liquidity_in(&self, token: TokenId) -> Option<U256> {
+        self.is_token0(token).ok().map(|is_token0| if is_token0 { self.reserve_token0 } else { self.reserve_token1 })
+    }
+
+    /// Возвращает токен на противоположной стороне пула
+    pub fn other_token(&self, token: TokenId) -> Option<TokenId> {
+        if token == self.token0 {
+            Some(self.token1)
+        } else if token == self.token1 {
+            Some(self.token0)
+        } else {
+            None
         }
     }
     
+    /// Сторона пула для токена: `true` для token0, `false` для token1
+    pub fn is_token0(&self, token: TokenId) -> Result<bool, PoolError> {
+        if token == self.token0 {
+            Ok(true)
+        } else if token == self.token1 {
+            Ok(false)
+        } else {
+            Err(PoolError::TokenNotInPool)
+        }
+    }
+
+    /// Выход свапа `amount_in` токена `token_in`, без изменения пула
+    /// 
+    /// В отличие от `get_amount_out` направление определяется по адресу
+    /// токена; токен не из пула дает `PoolError::TokenNotInPool`.
+    pub fn quote_by_token(&self, token_in: TokenId, amount_in: U256) -> Result<U256, PoolError> {
+        self.simulate_swap(amount_in, self.is_token0(token_in)?).map(|(amount_out, _, _)| amount_out)
+    }
+
+    /// Свап `amount_in` токена `token_in` с обновлением резервов (см. `mock_swap`)
+    pub fn swap_by_token(&mut self, token_in: TokenId, amount_in: U256) -> Result<U256, PoolError> {
+        let input_is_token0 = self.is_token0(token_in)?;
+        self.mock_swap(amount_in, input_is_token0)
+    }
+
+    /// Проверяет свап и считает выход и новые резервы, не меняя пул
+    /// 
+    /// # Returns
+    /// `(amount_out, new_reserve_in, new_reserve_out)` или `PoolError`
+    pub fn simulate_swap(&self, amount_in: U256, input_is_token0: bool) -> Result<(U256, U256, U256), PoolError> {
+        if amount_in == U256::ZERO {
+            return Err(PoolError::ZeroAmount);
+        }
+        let (reserve_in, reserve_out) = self.reserves_for(input_is_token0);
+        // Увеличиваем входной резерв, уменьшаем выходной
+        let new_reserve_in = reserve_in.checked_add(amount_in).ok_or(PoolError::Overflow)?;
+        let amount_out = self.try_get_amount_out(amount_in, input_is_token0)?;
+        if amount_out >= reserve_out {
+            return Err(PoolError::InsufficientLiquidity);
+        }
+        let new_reserve_out = reserve_out.checked_sub(amount_out).ok_or(PoolError::InsufficientLiquidity)?;
+        Ok((amount_out, new_reserve_in, new_reserve_out))
+    }
+
+    /// Инвариант constant product `k = reserve0 * reserve1` (в U512, без переполнения)
+    pub fn k(&self) -> U512 {
+        self.reserve_token0.widening_mul(self.reserve_token1)
+    }
+
     /// Симулирует свап и обновляет резервы без обращения к блокчейну
     /// 
+    /// В отладочной сборке для constant product пула проверяется, что `k`
+    /// не уменьшается (в контракте он растет на комиссию); нарушение
+    /// возвращается как `PoolError::InvariantViolated`.
+    /// 
     /// # Arguments
     /// * `amount_in` - Количество входных токенов
     /// * `input_is_token0` - true если входной токен это token0, false если token1
     /// 
     /// # Returns
-    /// Количество выходных токенов
-    pub fn mock_swap(&mut self, amount_in: U256, input_is_token0: bool) -> U256 {
-        let amount_out = self.get_amount_out(amount_in, input_is_token0);
-        
+    /// Количество выходных токенов или `PoolError`, если свап невозможен;
+    /// при ошибке резервы не меняются
+    pub fn mock_swap(&mut self, amount_in: U256, input_is_token0: bool) -> Result<U256, PoolError> {
+        let (amount_out, new_reserve_in, new_reserve_out) = self.simulate_swap(amount_in, input_is_token0)?;
+        if cfg!(debug_assertions)
+            && self.is_constant_product()
+            && new_reserve_in.widening_mul::<256, 4, 512, 8>(new_reserve_out) < self.k()
+        {
+            return Err(PoolError::InvariantViolated);
+        }
         if input_is_token0 {
-            // Обмениваем token0 на token1
-            // Увеличиваем резерв token0, уменьшаем резерв token1
-            self.reserve_token0 += amount_in;
-            self.reserve_token1 -= amount_out;
+            (self.reserve_token0, self.reserve_token1) = (new_reserve_in, new_reserve_out);
         } else {
-            // Обмениваем token1 на token0
-            // Увеличиваем резерв token1, уменьшаем резерв token0
-            self.reserve_token1 += amount_in;
-            self.reserve_token0 -= amount_out;
+            (self.reserve_token1, self.reserve_token0) = (new_reserve_in, new_reserve_out);
+        }
+        self.invalidate_quote_cache();
+        let reserves = self.snapshot();
+        if let Some(history) = &mut self.history {
+            history.events.push(SwapEvent { amount_in, amount_out, input_is_token0, reserves });
         }
         
-        amount_out
+        Ok(amount_out)
+    }
+
+    /// Начинает журнал свапов (`history`) от текущих резервов
+    ///
+    /// Прежний журнал отбрасывается. Резервы, измененные не через `mock_swap`
+    /// (`set_reserves`, подмена, скидка), начинают журнал заново.
+    pub fn record_history(&mut self) {
+        self.history = Some(SwapHistory { initial: self.snapshot(), events: Vec::new() });
+    }
+
+    /// Изменение резервов (token0, token1) относительно начала журнала; `None` без журнала
+    pub fn reserve_delta(&self) -> Option<(I256, I256)> {
+        let initial = self.history.as_ref()?.initial;
+        // Разность по модулю 2^256 в дополнительном коде точна, пока |Δ| < 2^255
+        Some((
+            I256::from_raw(self.reserve_token0.wrapping_sub(initial.reserve_token0)),
+            I256::from_raw(self.reserve_token1.wrapping_sub(initial.reserve_token1)),
+        ))
+    }
+
+    /// Перезапускает журнал, если он ведется, после изменения резервов не свапом
+    fn restart_history(&mut self) {
+        if self.history.is_some() {
+            self.record_history();
+        }
     }
     
-    /// Обновляет резервы пула из блокчейна
-    pub async fn refresh_reserves(&mut self) -> Result<()> {
-        let (reserve0, reserve1) = get_pool_reserves(
-            self.provider.clone(), 
-            self.pool_address
-        ).await?;
-        
+    /// Уменьшает выходной резерв на `haircut_bps` базисных пунктов
+    /// 
+    /// Используется только для консервативных котировок: резервы после
+    /// скидки не соответствуют реальному состоянию пула.
+    pub fn apply_reserve_haircut(&mut self, haircut_bps: u32, input_is_token0: bool) {
+        let keep = U256::from(BPS_DENOMINATOR.saturating_sub(haircut_bps));
+        let denominator = U256::from(BPS_DENOMINATOR);
+        let reserve_out = if input_is_token0 {
+            &mut self.reserve_token1
+        } else {
+            &mut self.reserve_token0
+        };
+        *reserve_out = mul_div(*reserve_out, keep, denominator).unwrap_or(U256::ZERO);
+        self.invalidate_quote_cache();
+        self.restart_history();
+    }
+    
+    /// Сохраняет текущие резервы, чтобы потом вернуться к ним через `restore`
+    pub fn snapshot(&self) -> ReservesSnapshot {
+        ReservesSnapshot {
+            reserve_token0: self.reserve_token0,
+            reserve_token1: self.reserve_token1,
+        }
+    }
+
+    /// Возвращает резервы к сохраненному состоянию
+    ///
+    /// Журнал свапов не откатывается: пробные свапы с откатом - через `with_snapshot`.
+    pub fn restore(&mut self, state: &ReservesSnapshot) {
+        self.reserve_token0 = state.reserve_token0;
+        self.reserve_token1 = state.reserve_token1;
+        self.invalidate_quote_cache();
+    }
+
+    /// Выполняет `f` над пулом и откатывает резервы после него
+    /// 
+    /// Позволяет попробовать свапы (`mock_swap`) и оценить результат,
+    /// не меняя состояние пула.
+    pub fn with_snapshot<R>(&mut self, f: impl FnOnce(&mut PoolState) -> R) -> R {
+        let state = self.snapshot();
+        let recorded = self.history.as_ref().map(|history| history.events.len());
+        let result = f(self);
+        self.restore(&state);
+        if let (Some(history), Some(len)) = (&mut self.history, recorded) {
+            history.events.truncate(len);
+        }
+        result
+    }
+    
+    /// Записывает резервы из блокчейна и время их обновления
+    pub fn set_reserves(&mut self, reserve0: U256, reserve1: U256, last_updated: u32) {
+        self.reserve_token0 = reserve0;
+        self.reserve_token1 = reserve1;
+        self.last_updated = last_updated;
+        self.reserves_overridden = false;
+        self.invalidate_quote_cache();
+        self.restart_history();
+    }
+
+    /// Подменяет резервы для анализа "что если" и помечает пул
+    ///
+    /// `last_updated` - время подмены: проверка устаревших резервов не должна
+    /// отбрасывать пул по времени старых резервов из сети. Нулевой резерв
+    /// отклоняется (`PoolError::EmptyReserves`): такой пул солвер все равно
+    /// пропустил бы. Следующее чтение резервов из сети (`set_reserves`) снимает подмену.
+    pub fn override_reserves(&mut self, reserve0: U256, reserve1: U256, last_updated: u32) -> Result<(), PoolError> {
+        if reserve0.is_zero() || reserve1.is_zero() {
+            return Err(PoolError::EmptyReserves);
+        }
         self.reserve_token0 = reserve0;
         self.reserve_token1 = reserve1;
+        self.last_updated = last_updated;
+        self.reserves_overridden = true;
+        self.invalidate_quote_cache();
+        self.restart_history();
+        Ok(())
+    }
+
+    /// Пересоздает кэш котировок после изменения резервов
+    pub fn invalidate_quote_cache(&mut self) {
+        self.quote_cache = QuoteCache::new(self.reserve_token0, self.reserve_token1, self.fee_bps);
+    }
+}
+
+/// Знаков после точки у резервов в `Display` и `summary_row`
+const RESERVE_PRECISION: u8 = 6;
+/// Знаков после точки у цены в `Display` и `summary_row`
+const PRICE_PRECISION: u8 = 6;
+
+/// Символ известного токена или сокращенный адрес
+fn token_label(token: TokenId) -> String {
+    token.info().map_or_else(|| short_address(token.address()), |info| info.symbol.to_string())
+}
+
+/// `Uniswap V2 USDC/WETH [Uniswap V2] 0x1234…abcd: 1000.000000 USDC / 0.500000 WETH, 1 WETH = 2000.000000 USDC, комиссия 30 bps`
+impl fmt::Display for PoolState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] {}: {} / {}, ", self.name, self.dex, short_address(self.pool_address),
+            self.reserve_label(self.token0), self.reserve_label(self.token1))?;
+        match self.price_label() {
+            Some(price) => write!(f, "1 {} = {}", token_label(self.token1), price)?,
+            None => write!(f, "цена неизвестна")?,
+        }
+        write!(f, ", комиссия {}", self.fee_label())
+    }
+}
+
+/// Пул с подключением к блокчейну: состояние `PoolState` и клиент для его обновления
+///
+/// Через `Deref` дает доступ к полям и методам состояния, поэтому код,
+/// которому не нужна сеть, может принимать `&PoolState`.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub state: PoolState,
+    pub client: Arc<dyn ChainClient>,
+}
+
+impl Deref for Pool {
+    type Target = PoolState;
+
+    fn deref(&self) -> &PoolState {
+        &self.state
+    }
+}
+
+impl DerefMut for Pool {
+    fn deref_mut(&mut self) -> &mut PoolState {
+        &mut self.state
+    }
+}
+
+/// Как у `PoolState`: клиент в вывод не попадает
+impl fmt::Display for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.state.fmt(f)
+    }
+}
+
+impl Pool {
+    /// Создает новый экземпляр пула с нулевыми резервами
+    /// Для получения актуальных резервов используйте refresh_reserves()
+    /// 
+    /// # Arguments
+    /// * `pool_address` - Адрес контракта пула
+    /// * `token_a` - Первый токен пары (порядок не важен)
+    /// * `token_b` - Второй токен пары
+    /// * `dex` - DEX, которому принадлежит пул
+    /// * `client` - Доступ к блокчейну (`RpcProvider` или мок в тестах)
+    /// * `name` - Имя пула для идентификации (например, "Uniswap V2 USDC/WETH")
+    /// 
+    /// # Returns
+    /// Новый экземпляр Pool с нулевыми резервами
+    pub fn new(
+        pool_address: Address,
+        token_a: TokenId,
+        token_b: TokenId,
+        dex: DexId,
+        client: Arc<dyn ChainClient>,
+        name: String,
+    ) -> Self {
+        Pool::from_state(PoolState::new(pool_address, token_a, token_b, dex, name), client)
+    }
+
+    /// Связывает готовое состояние пула с клиентом блокчейна
+    pub fn from_state(state: PoolState, client: Arc<dyn ChainClient>) -> Self {
+        Pool { state, client }
+    }
+
+    /// См. `PoolState::into_weighted`
+    pub fn into_weighted(self, pool_id: B256, weights: [(TokenId, U256); 2], swap_fee: U256) -> Self {
+        Pool { state: self.state.into_weighted(pool_id, weights, swap_fee), ..self }
+    }
+
+    /// См. `PoolState::with_fee_bps`
+    pub fn with_fee_bps(self, fee_bps: u32) -> Self {
+        Pool { state: self.state.with_fee_bps(fee_bps), ..self }
+    }
+
+    /// Копии состояний пулов для солвера
+    pub fn states(pools: &[Pool]) -> Vec<PoolState> {
+        pools.iter().map(|pool| pool.state.clone()).collect()
+    }
+
+    /// Создает Pool и сразу получает актуальные резервы из блокчейна
+    /// 
+    /// # Arguments
+    /// * `pool_address` - Адрес контракта пула
+    /// * `token_a` - Первый токен пары
+    /// * `token_b` - Второй токен пары
+    /// * `dex` - DEX, которому принадлежит пул
+    /// * `client` - Доступ к блокчейну (`RpcProvider` или мок в тестах)
+    /// * `name` - Имя пула для идентификации
+    /// 
+    /// # Returns
+    /// Pool с актуальными резервами или ошибка (в том числе если `token_a == token_b`)
+    pub async fn with_reserves(
+        pool_address: Address,
+        token_a: TokenId,
+        token_b: TokenId,
+        dex: DexId,
+        client: Arc<dyn ChainClient>,
+        name: String,
+    ) -> Result<Self> {
+        let mut pool = Self::with_decimals(pool_address, token_a, token_b, dex, client, name).await?;
+        pool.refresh_reserves().await?;
+        Ok(pool)
+    }
+
+    /// Проверяет, что контракт пары торгует именно `token0` / `token1` пула
+    /// 
+    /// Без проверки неверный адрес пула дал бы резервы чужих токенов и
+    /// бессмысленные котировки. Токены пула уже отсортированы, как и в контракте.
+    pub async fn verify_pair_tokens(&self) -> Result<()> {
+        let (token0, token1) = self.client.get_pair_tokens(self.pool_address)
+            .await
+            .wrap_err_with(|| format!("пул {} ({:?}): не удалось прочитать token0/token1", self.name, self.pool_address))?;
+        if (token0, token1) != (self.token0.address(), self.token1.address()) {
+            bail!("пул {} ({:?}): ожидалась пара {:?}/{:?}, контракт торгует {:?}/{:?}",
+                self.name, self.pool_address, self.token0.address(), self.token1.address(), token0, token1);
+        }
+        Ok(())
+    }
+
+    /// Создает Pool только по адресу пары: токены, decimals, символы и резервы читаются из блокчейна
+    /// 
+    /// Пул получает `DexId::EXTERNAL` и комиссию по умолчанию; без `name` имя
+    /// строится из символов токенов. Ошибка, если по адресу нет контракта (EOA)
+    /// или контракт не отвечает на `token0()` / `token1()` как пара Uniswap V2.
+    pub async fn from_address(
+        client: Arc<dyn ChainClient>,
+        pool_address: Address,
+        name: Option<String>,
+    ) -> Result<Self> {
+        if !client.is_contract(pool_address).await? {
+            bail!("по адресу {:?} нет контракта (EOA или пустой адрес), это не пул", pool_address);
+        }
+        let (token0, token1) = client.get_pair_tokens(pool_address).await.wrap_err_with(|| {
+            format!("контракт {:?} не похож на пару Uniswap V2: token0()/token1() не вызываются", pool_address)
+        })?;
+        let (token0, token1) = (TokenId(token0), TokenId(token1));
+        let name = match name {
+            Some(name) => name,
+            None => {
+                let symbol0 = get_token_symbol(client.clone(), token0).await;
+                let symbol1 = get_token_symbol(client.clone(), token1).await;
+                pool_label(DexId::EXTERNAL, (token0, &symbol0), (token1, &symbol1))
+            }
+        };
+        Self::with_reserves(pool_address, token0, token1, DexId::EXTERNAL, client, name).await
+    }
+
+    /// Создает Pool с decimals токенов, но без резервов
+    /// 
+    /// Резервы многих пулов выгоднее получить одним запросом (`refresh_all_reserves`).
+    /// Ошибка, если `token_a == token_b`.
+    pub async fn with_decimals(
+        pool_address: Address,
+        token_a: TokenId,
+        token_b: TokenId,
+        dex: DexId,
+        client: Arc<dyn ChainClient>,
+        name: String,
+    ) -> Result<Self> {
+        if token_a == token_b {
+            bail!("пул {} ({:?}): оба токена пары совпадают ({:?})", name, pool_address, token_a.address());
+        }
+        let mut pool = Self::new(pool_address, token_a, token_b, dex, client, name);
+        pool.fetch_decimals().await?;
+        Ok(pool)
+    }
+
+    /// Запрашивает decimals обоих токенов (повторные запросы берутся из кэша)
+    ///
+    /// При сбое транспорта decimals пула не меняются.
+    pub async fn fetch_decimals(&mut self) -> Result<()> {
+        let token0_decimals = get_token_decimals(self.client.clone(), self.token0).await?;
+        let token1_decimals = get_token_decimals(self.client.clone(), self.token1).await?;
+        self.token0_decimals = token0_decimals;
+        self.token1_decimals = token1_decimals;
+        self.decimals_fetched = true;
+        Ok(())
+    }
+
+    /// Обновляет резервы пула из блокчейна
+    pub async fn refresh_reserves(&mut self) -> Result<()> {
+        let (reserve0, reserve1, last_updated) = match self.kind {
+            PoolKind::ConstantProduct => self.client.get_reserves(self.pool_address).await?,
+            PoolKind::Weighted { pool_id, .. } => {
+                let (balance0, balance1) = get_weighted_pool_balances(
+                    self.client.clone(),
+                    pool_id,
+                    self.token0,
+                    self.token1,
+                ).await?;
+                (balance0, balance1, 0)
+            }
+        };
         
+        self.set_reserves(reserve0, reserve1, last_updated);
         Ok(())
     }
 }
+
+/// Обновляет резервы всех пулов: пары Uniswap V2 - одним запросом через Multicall3
+/// 
+/// Если multicall не удался целиком, резервы пар запрашиваются по одной;
+/// пара, чей вызов внутри multicall не удался, тоже запрашивается отдельно.
+/// Взвешенные пулы Balancer всегда обновляются отдельно через Vault.
+/// 
+/// # Returns
+/// Адреса пулов, которые обновить не удалось, с ошибками
+pub async fn refresh_all_reserves(
+    client: Arc<dyn ChainClient>,
+    pools: &mut [Pool],
+) -> Vec<(Address, eyre::Report)> {
+    let all: Vec<usize> = (0..pools.len()).collect();
+    refresh_reserves_of(client, pools, &all).await
+}
+
+/// Обновляет резервы пулов с индексами `indices` так же, как `refresh_all_reserves`
+pub async fn refresh_reserves_of(
+    client: Arc<dyn ChainClient>,
+    pools: &mut [Pool],
+    indices: &[usize],
+) -> Vec<(Address, eyre::Report)> {
+    let (batched, mut individual) = split_batched(pools, indices);
+    if !batched.is_empty() {
+        let addresses: Vec<Address> = batched.iter().map(|&index| pools[index].pool_address).collect();
+        let results = client.get_reserves_batch(&addresses).await;
+        individual.extend(apply_batch(pools, batched, results));
+    }
+    refresh_individually(pools, individual).await
+}
+
+/// `refresh_reserves_of` вместе с блоком, на котором прочитаны резервы
+///
+/// Блок приходит из того же multicall, что и резервы пар. Если multicall не
+/// удался или пар Uniswap V2 нет, блок запрашивается до чтения резервов по
+/// одному, поэтому он не новее прочитанных резервов. Ошибка - только если
+/// номер блока получить не удалось.
+pub async fn refresh_reserves_at_block(
+    client: Arc<dyn ChainClient>,
+    pools: &mut [Pool],
+    indices: &[usize],
+) -> Result<(u64, Vec<(Address, eyre::Report)>)> {
+    let (batched, mut individual) = split_batched(pools, indices);
+    let mut block = None;
+    if !batched.is_empty() {
+        let addresses: Vec<Address> = batched.iter().map(|&index| pools[index].pool_address).collect();
+        let results = client.get_reserves_batch_at_block(&addresses).await.map(|(at, results)| {
+            block = Some(at);
+            results
+        });
+        individual.extend(apply_batch(pools, batched, results));
+    }
+    let block = match block {
+        Some(block) => block,
+        None => client.block_number().await?,
+    };
+    Ok((block, refresh_individually(pools, individual).await))
+}
+
+/// Индексы пар Uniswap V2 (читаются одним multicall) и остальных пулов
+fn split_batched(pools: &[Pool], indices: &[usize]) -> (Vec<usize>, Vec<usize>) {
+    indices.iter().copied().partition(|&index| pools[index].is_constant_product())
+}
+
+/// Записывает резервы из multicall; возвращает пулы, которые нужно прочитать по одному
+fn apply_batch(pools: &mut [Pool], batched: Vec<usize>, results: Result<Vec<Option<PairReserves>>>) -> Vec<usize> {
+    match results {
+        Ok(results) => batched
+            .into_iter()
+            .zip(results)
+            .filter_map(|(index, result)| match result {
+                Some((reserve0, reserve1, last_updated)) => {
+                    pools[index].set_reserves(reserve0, reserve1, last_updated);
+                    None
+                }
+                None => Some(index),
+            })
+            .collect(),
+        Err(e) => {
+            log!("Multicall не удался ({}), запрашиваем резервы по одному пулу", e);
+            batched
+        }
+    }
+}
+
+async fn refresh_individually(pools: &mut [Pool], individual: Vec<usize>) -> Vec<(Address, eyre::Report)> {
+    let mut failures = Vec::new();
+    for index in individual {
+        if let Err(e) = pools[index].refresh_reserves().await {
+            failures.push((pools[index].pool_address, e));
+        }
+    }
+    failures
+}
+
+/// Пул, резервы которого не менялись дольше порога
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleReserves {
+    pub pool_address: Address,
+    pub pool_name: String,
+    pub age_secs: u64,
+}
+
+/// Пулы, чей `blockTimestampLast` старше `threshold_secs` относительно времени блока
+/// 
+/// Пулы с неизвестным временем обновления (`last_updated == 0`) не проверяются.
+pub fn stale_reserves(pools: &[Pool], block_timestamp: u64, threshold_secs: u64) -> Vec<StaleReserves> {
+    pools
+        .iter()
+        .filter_map(|pool| {
+            let age_secs = pool.reserves_age_secs(block_timestamp)?;
+            (age_secs > threshold_secs).then(|| StaleReserves {
+                pool_address: pool.pool_address,
+                pool_name: pool.name.clone(),
+                age_secs,
+            })
+        })
+        .collect()
+}
+
+/// Сохраняет пулы в JSON-файл: `MarketSnapshot` на блоке `block_number`
+///
+/// `Pool` не сериализуется целиком из-за клиента блокчейна, поэтому файл - тот
+/// же снимок рынка, что и `snapshot save`, только в JSON. Время блока не
+/// сохраняется (0).
+pub fn save_pools(path: &std::path::Path, pools: &[Pool], block_number: u64) -> Result<()> {
+    let states: Vec<PoolState> = pools.iter().map(|pool| pool.state.clone()).collect();
+    let snapshot = MarketSnapshot::from_pools(&states, block_number, 0);
+    std::fs::write(path, serde_json::to_string_pretty(&snapshot)?)?;
+    Ok(())
+}
+
+/// Загружает пулы из JSON-файла, сохраненного `save_pools`
+pub fn load_pools(path: &std::path::Path, client: Arc<dyn ChainClient>) -> Result<Vec<Pool>> {
+    load_pools_at_block(path, client).map(|(pools, _)| pools)
+}
+
+/// Загружает пулы вместе с блоком, на котором читались их резервы
+pub fn load_pools_at_block(path: &std::path::Path, client: Arc<dyn ChainClient>) -> Result<(Vec<Pool>, u64)> {
+    let snapshot: MarketSnapshot = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let pools = snapshot.to_pools().into_iter().map(|state| Pool::from_state(state, client.clone())).collect();
+    Ok((pools, snapshot.block_number))
+}
+
+/// Провайдер для тестов: HTTP клиент без реальных запросов к сети
+#[cfg(test)]
+pub(crate) fn test_provider() -> Arc<crate::provider::RpcProvider> {
+    use alloy::providers::ProviderBuilder;
+    Arc::new(ProviderBuilder::new().on_http("http://localhost:8545".parse().unwrap()).boxed())
+}
+
+/// Состояние пула с заданными резервами для тестов (без провайдера)
+#[cfg(test)]
+pub(crate) fn test_pool(
+    address_byte: u8,
+    token_a: TokenId,
+    token_b: TokenId,
+    reserve_a: U256,
+    reserve_b: U256,
+) -> PoolState {
+    let mut pool = PoolState::new(
+        Address::repeat_byte(address_byte),
+        token_a,
+        token_b,
+        DexId("Test"),
+        format!("Test {}/{}", token_a, token_b),
+    );
+    if pool.token0 == token_a {
+        pool.reserve_token0 = reserve_a;
+        pool.reserve_token1 = reserve_b;
+    } else {
+        pool.reserve_token0 = reserve_b;
+        pool.reserve_token1 = reserve_a;
+    }
+    pool.invalidate_quote_cache();
+    pool
+}
+
+#[cfg(test)]
+impl Pool {
+    /// Пул для тестов без сети: резервы `reserve0` / `reserve1` в порядке
+    /// переданных токенов (как у `test_pool`); клиент - пустой `MockChainClient`,
+    /// так что случайный сетевой вызов откатывается, а не идет на localhost
+    pub(crate) fn for_test(address: Address, token0: TokenId, token1: TokenId, reserve0: U256, reserve1: U256, name: &str) -> Pool {
+        let mut state = test_pool(0, token0, token1, reserve0, reserve1);
+        state.pool_address = address;
+        state.name = name.to_string();
+        Pool::from_state(state, crate::mock_chain::MockChainClient::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::get_amount_out;
+    use crate::market_snapshot::SnapshotPool;
+    use crate::mock_rpc::MockRpc;
+    use proptest::prelude::*;
+
+    fn u256_up_to_112_bits() -> impl Strategy<Value = U256> {
+        (any::<u64>(), any::<u64>()).prop_map(|(hi, lo)| {
+            (U256::from(hi & ((1u64 << 48) - 1)) << 64) | U256::from(lo)
+        })
+    }
+
+    fn any_u256() -> impl Strategy<Value = U256> {
+        any::<[u64; 4]>().prop_map(U256::from_limbs)
+    }
+
+    proptest! {
+        #[test]
+        fn quote_cache_matches_direct_formula(
+            amount_in in u256_up_to_112_bits(),
+            reserve0 in u256_up_to_112_bits(),
+            reserve1 in u256_up_to_112_bits(),
+            input_is_token0 in any::<bool>(),
+        ) {
+            let cache = QuoteCache::new(reserve0, reserve1, DEFAULT_FEE_BPS);
+            let expected = if input_is_token0 {
+                get_amount_out(amount_in, reserve0, reserve1)
+            } else {
+                get_amount_out(amount_in, reserve1, reserve0)
+            };
+            prop_assert_eq!(cache.quote(amount_in, input_is_token0), expected);
+        }
+
+        #[test]
+        fn quote_cache_matches_direct_formula_full_range(
+            amount_in in any_u256(),
+            reserve0 in any_u256(),
+            reserve1 in any_u256(),
+            input_is_token0 in any::<bool>(),
+        ) {
+            let cache = QuoteCache::new(reserve0, reserve1, DEFAULT_FEE_BPS);
+            let expected = if input_is_token0 {
+                get_amount_out(amount_in, reserve0, reserve1)
+            } else {
+                get_amount_out(amount_in, reserve1, reserve0)
+            };
+            prop_assert_eq!(cache.quote(amount_in, input_is_token0), expected);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn mock_swaps_never_decrease_k(
+            reserve0 in u256_up_to_112_bits(),
+            reserve1 in u256_up_to_112_bits(),
+            fee_bps in 0u32..100,
+            swaps in proptest::collection::vec((u256_up_to_112_bits(), any::<bool>()), 1..20),
+        ) {
+            let mut pool = test_pool(0x11, TokenId::USDC, TokenId::WETH, reserve0, reserve1).with_fee_bps(fee_bps);
+            for (amount_in, input_is_token0) in swaps {
+                let k_before = pool.k();
+                match pool.mock_swap(amount_in, input_is_token0) {
+                    Ok(_) => prop_assert!(pool.k() >= k_before),
+                    Err(error) => {
+                        prop_assert_ne!(error, PoolError::InvariantViolated);
+                        prop_assert_eq!(pool.k(), k_before);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn k_is_exact_beyond_u256() {
+        let pool = test_pool(0x11, TokenId::USDC, TokenId::WETH, U256::MAX, U256::from(2u64));
+        assert_eq!(pool.k(), U512::from(U256::MAX) * U512::from(2u64));
+    }
+
+    #[test]
+    fn exact_output_is_bounded_by_the_reserve() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let reserve_weth = U256::from(400u64) * weth;
+        let pool = test_pool(0x11, TokenId::USDC, TokenId::WETH, U256::from(1_000_000_000_000u64), reserve_weth);
+        let weth_is_token0 = pool.token0 == TokenId::WETH;
+
+        let max_out = pool.max_output(TokenId::WETH);
+        assert_eq!(max_out, reserve_weth - U256::from(1u64));
+        assert_eq!(pool.max_output(TokenId::USDC_E), U256::ZERO);
+
+        // Чем ближе к резерву, тем больше вход, но он конечен и дает нужный выход
+        let mut previous = U256::ZERO;
+        for desired in [weth, reserve_weth / U256::from(2u64), reserve_weth - weth, max_out] {
+            let amount_in = pool.max_input_for_output(desired, weth_is_token0).unwrap();
+            assert!(amount_in > previous);
+            assert!(pool.get_amount_out(amount_in, !weth_is_token0) >= desired);
+            previous = amount_in;
+        }
+        assert!(previous > U256::from(10u64).pow(U256::from(30u64)));
+        assert_eq!(pool.max_input_for_output(reserve_weth, weth_is_token0), None);
+        assert_eq!(pool.max_input_for_output(reserve_weth + U256::from(1u64), weth_is_token0), None);
+
+        // Последняя единица резерва огромного пула недостижима входом в пределах U256
+        let huge = test_pool(0x12, TokenId::USDC, TokenId::WETH, U256::MAX, U256::MAX);
+        let huge_max = huge.max_output(TokenId::WETH);
+        assert!(huge_max < U256::MAX - U256::from(1u64));
+        assert!(huge.max_input_for_output(huge_max, huge.token0 == TokenId::WETH).is_some());
+    }
+
+    #[test]
+    fn quote_cache_tracks_source_reserves() {
+        let cache = QuoteCache::new(U256::from(100u64), U256::from(200u64), DEFAULT_FEE_BPS);
+        assert!(cache.matches(U256::from(100u64), U256::from(200u64)));
+        assert!(!cache.matches(U256::from(101u64), U256::from(200u64)));
+    }
+
+    #[tokio::test]
+    async fn with_reserves_fetches_and_caches_token_decimals() {
+        use crate::mock_rpc::{call_selector, call_target, encode_word, MockRpc};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let eight_decimals = TokenId(Address::repeat_byte(0x0a));
+        let reverting = TokenId(Address::repeat_byte(0x0b));
+        let decimals_calls = Arc::new(AtomicUsize::new(0));
+        let calls = Arc::clone(&decimals_calls);
+        let rpc = MockRpc::start(move |method, params| {
+            assert_eq!(method, "eth_call");
+            match call_selector(params) {
+                // decimals()
+                Some([0x31, 0x3c, 0xe5, 0x67]) => {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    if call_target(params) == Some(eight_decimals.address()) {
+                        Ok(encode_word(U256::from(8u64)))
+                    } else {
+                        Err("execution reverted".to_string())
+                    }
+                }
+                // getReserves(): (reserve0, reserve1, blockTimestampLast)
+                Some([0x09, 0x02, 0xf1, 0xac]) => Ok(serde_json::json!(format!("0x{}{}{}",
+                    alloy::hex::encode(U256::from(5_000u64).to_be_bytes::<32>()),
+                    alloy::hex::encode(U256::from(7_000u64).to_be_bytes::<32>()),
+                    alloy::hex::encode(U256::ZERO.to_be_bytes::<32>())))),
+                other => Err(format!("неожиданный вызов {:?}", other)),
+            }
+        }).await;
+
+        let pool = Pool::with_reserves(Address::repeat_byte(0x0c), eight_decimals, reverting, DexId("Test"),
+            rpc.provider.clone(), "Test".to_string()).await.unwrap();
+        assert_eq!((pool.token0_decimals, pool.token1_decimals), (8, 18));
+        assert_eq!((pool.reserve_token0, pool.reserve_token1), (U256::from(5_000u64), U256::from(7_000u64)));
+        assert_eq!(decimals_calls.load(Ordering::SeqCst), 2);
+
+        // Второй пул с теми же токенами: decimals берутся из кэша
+        let again = Pool::with_reserves(Address::repeat_byte(0x0d), reverting, eight_decimals, DexId("Test"),
+            rpc.provider.clone(), "Test".to_string()).await.unwrap();
+        assert_eq!((again.token0_decimals, again.token1_decimals), (8, 18));
+        assert_eq!(decimals_calls.load(Ordering::SeqCst), 2);
+        assert_eq!(rpc.request_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn with_reserves_rejects_pair_of_identical_tokens() {
+        use crate::mock_rpc::MockRpc;
+
+        let rpc = MockRpc::start(|_, _| Err("неожиданный вызов".to_string())).await;
+        let result = Pool::with_reserves(Address::repeat_byte(0x0e), TokenId::USDC, TokenId::USDC, DexId("Test"),
+            rpc.provider.clone(), "Test".to_string()).await;
+        assert!(result.unwrap_err().to_string().contains("совпадают"));
+        assert_eq!(rpc.request_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn verify_pair_tokens_rejects_pair_contract_trading_other_tokens() {
+        use crate::mock_rpc::{call_selector, encode_address, encode_word, MockRpc};
+
+        let actual = TokenId(Address::repeat_byte(0x0f));
+        let word = |token: TokenId| encode_address(token.address());
+        let rpc = MockRpc::start(move |_, params| match call_selector(params) {
+            // decimals()
+            Some([0x31, 0x3c, 0xe5, 0x67]) => Ok(encode_word(U256::from(18u64))),
+            // token0() / token1(): контракт торгует USDC/другим токеном вместо USDC/WETH
+            Some([0x0d, 0xfe, 0x16, 0x81]) => Ok(word(TokenId::USDC.min(actual))),
+            Some([0xd2, 0x12, 0x20, 0xa7]) => Ok(word(TokenId::USDC.max(actual))),
+            // getReserves()
+            Some([0x09, 0x02, 0xf1, 0xac]) => Ok(serde_json::json!(format!("0x{}", "00".repeat(96)))),
+            other => Err(format!("неожиданный вызов {:?}", other)),
+        }).await;
+        // Сам пул создается: token0/token1 запрашиваются только при сверке
+        let pool = Pool::with_reserves(Address::repeat_byte(0x1e), TokenId::USDC, TokenId::WETH,
+            DexId("Test"), rpc.provider.clone(), "Test".to_string()).await.unwrap();
+        let before = rpc.request_count();
+
+        let error = pool.verify_pair_tokens().await.unwrap_err().to_string();
+        assert!(error.contains("контракт торгует"), "{}", error);
+        assert!(error.contains(&format!("{:?}", actual.address())), "{}", error);
+        assert_eq!(rpc.request_count() - before, 2);
+    }
+
+    #[tokio::test]
+    async fn from_address_reads_tokens_and_rejects_non_pairs() {
+        use crate::mock_rpc::{call_selector, call_target, encode_address, encode_word, MockRpc};
+        use alloy::sol_types::SolValue;
+
+        let (pair, not_pair, eoa) = (Address::repeat_byte(0x1e), Address::repeat_byte(0x2e), Address::repeat_byte(0x3e));
+        let (token0, token1) = if TokenId::USDC < TokenId::WETH { (TokenId::USDC, TokenId::WETH) } else { (TokenId::WETH, TokenId::USDC) };
+        let rpc = MockRpc::start(move |method, params| {
+            if method == "eth_getCode" {
+                let target: Address = params[0].as_str().unwrap().parse().unwrap();
+                return Ok(serde_json::json!(if target == eoa { "0x" } else { "0x6080" }));
+            }
+            let target = call_target(params).unwrap();
+            match call_selector(params) {
+                // token0() / token1(): второй контракт не пара и откатывает вызов
+                Some([0x0d, 0xfe, 0x16, 0x81]) if target == pair => Ok(encode_address(token0.address())),
+                Some([0xd2, 0x12, 0x20, 0xa7]) if target == pair => Ok(encode_address(token1.address())),
+                // decimals() / symbol()
+                Some([0x31, 0x3c, 0xe5, 0x67]) => Ok(encode_word(U256::from(if target == TokenId::WETH.address() { 18u64 } else { 6 }))),
+                Some([0x95, 0xd8, 0x9b, 0x41]) => {
+                    let symbol = if target == TokenId::WETH.address() { "WETH" } else { "USDC" };
+                    Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(symbol.to_string().abi_encode()))))
+                }
+                // getReserves()
+                Some([0x09, 0x02, 0xf1, 0xac]) => Ok(serde_json::json!(format!("0x{}{}{}",
+                    alloy::hex::encode(U256::from(5_000u64).to_be_bytes::<32>()),
+                    alloy::hex::encode(U256::from(7_000u64).to_be_bytes::<32>()),
+                    alloy::hex::encode(U256::ZERO.to_be_bytes::<32>())))),
+                _ => Err("execution reverted".to_string()),
+            }
+        }).await;
+
+        let pool = Pool::from_address(rpc.provider.clone(), pair, None).await.unwrap();
+        assert_eq!((pool.token0, pool.token1), (token0, token1));
+        assert_eq!((pool.token0_decimals, pool.token1_decimals), if token0 == TokenId::USDC { (6, 18) } else { (18, 6) });
+        assert_eq!((pool.reserve_token0, pool.reserve_token1), (U256::from(5_000u64), U256::from(7_000u64)));
+        assert_eq!(pool.dex, DexId::EXTERNAL);
+        assert!(pool.name.contains("USDC") && pool.name.contains("WETH"), "{}", pool.name);
+
+        let error = Pool::from_address(rpc.provider.clone(), eoa, None).await.unwrap_err().to_string();
+        assert!(error.contains("нет контракта"), "{}", error);
+        let error = Pool::from_address(rpc.provider.clone(), not_pair, None).await.unwrap_err().to_string();
+        assert!(error.contains("не похож на пару"), "{}", error);
+    }
+
+    #[test]
+    fn swaps_by_token_address_and_rejects_foreign_tokens() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pool = test_pool(0x31, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(800u64) * weth);
+        let amount = U256::from(1_000_000_000u64);
+
+        let expected = pool.get_amount_out(amount, pool.token0 == TokenId::USDC);
+        assert_eq!(pool.quote_by_token(TokenId::USDC, amount), Ok(expected));
+        assert_eq!(pool.swap_by_token(TokenId::USDC, amount), Ok(expected));
+        assert_eq!(pool.reserves_for(pool.token0 == TokenId::USDC).0, U256::from(2_000_000_000_000u64) + amount);
+
+        // USDC.e не торгуется в пуле USDC/WETH: ошибка, а не свап со стороны token1
+        let before = pool.snapshot();
+        assert_eq!(pool.quote_by_token(TokenId::USDC_E, amount), Err(PoolError::TokenNotInPool));
+        assert_eq!(pool.swap_by_token(TokenId::USDC_E, amount), Err(PoolError::TokenNotInPool));
+        assert_eq!(crate::amm::AmmPool::quote(&pool, amount, TokenId::USDC_E), Err(PoolError::TokenNotInPool));
+        assert_eq!(pool.snapshot(), before);
+    }
+
+    #[test]
+    fn restore_returns_reserves_byte_for_byte() {
+        let mut pool = test_pool(0x21, TokenId::USDC, TokenId::WETH,
+            U256::from(2_000_000_000_123u64), U256::from(800_000_000_000_000_000_777u128));
+        let before = pool.snapshot();
+        let bytes = |pool: &PoolState| (pool.reserve_token0.to_be_bytes::<32>(), pool.reserve_token1.to_be_bytes::<32>());
+        let original = bytes(&pool);
+
+        for i in 1..=5u64 {
+            pool.mock_swap(U256::from(i * 1_000_000_007), pool.token0 == TokenId::USDC).unwrap();
+        }
+        assert_ne!(bytes(&pool), original);
+
+        pool.restore(&before);
+        assert_eq!(bytes(&pool), original);
+        assert_eq!(pool.snapshot(), before);
+        assert!(pool.quote_cache.matches(pool.reserve_token0, pool.reserve_token1));
+    }
+
+    #[test]
+    fn with_snapshot_rolls_back_and_returns_result() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pool = test_pool(0x22, TokenId::USDC, TokenId::WETH, U256::from(1_000_000_000_000u64), U256::from(400u64) * weth)
+            .into_weighted(B256::repeat_byte(0x01), [
+                (TokenId::USDC, U256::from(500_000_000_000_000_000u64)),
+                (TokenId::WETH, U256::from(500_000_000_000_000_000u64)),
+            ], U256::from(3_000_000_000_000_000u64));
+        let input_is_token0 = pool.token0 == TokenId::USDC;
+        let before = pool.snapshot();
+        let amount = U256::from(5_000_000_000u64);
+
+        let (first, second) = pool.with_snapshot(|pool| {
+            (pool.mock_swap(amount, input_is_token0).unwrap(), pool.mock_swap(amount, input_is_token0).unwrap())
+        });
+        assert!(second < first);
+        assert_eq!(pool.snapshot(), before);
+        // После отката пул котирует так же, как до пробных свапов
+        assert_eq!(pool.mock_swap(amount, input_is_token0), Ok(first));
+    }
+
+    #[test]
+    fn swap_history_replays_to_final_reserves() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pool = test_pool(0x24, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_123u64), U256::from(800u64) * weth);
+        let usdc_is_token0 = pool.token0 == TokenId::USDC;
+        pool.mock_swap(U256::from(1_000_000u64), usdc_is_token0).unwrap();
+        assert_eq!(pool.history, None);
+        assert_eq!(pool.reserve_delta(), None);
+
+        pool.record_history();
+        let initial = pool.snapshot();
+        for (i, amount) in [5_000_000_007u64, 12_345_678_901, 3_000_000_000_000_000].into_iter().enumerate() {
+            // Один свап в обратную сторону: WETH в пул
+            let input_is_token0 = if i == 2 { !usdc_is_token0 } else { usdc_is_token0 };
+            pool.mock_swap(U256::from(amount), input_is_token0).unwrap();
+        }
+        // Пробные свапы с откатом и отклоненный свап в журнал не попадают
+        pool.with_snapshot(|pool| pool.mock_swap(U256::from(7_000_000u64), usdc_is_token0).unwrap());
+        assert!(pool.mock_swap(U256::ZERO, usdc_is_token0).is_err());
+
+        let history = pool.history.clone().unwrap();
+        assert_eq!(history.initial, initial);
+        assert_eq!(history.events.len(), 3);
+        assert_eq!(history.replay(), Some(pool.snapshot()));
+        assert_eq!(history.events.last().unwrap().reserves, pool.snapshot());
+
+        let (usdc_in, weth_out) = history.totals(usdc_is_token0);
+        let (weth_in, usdc_out) = history.totals(!usdc_is_token0);
+        assert_eq!(usdc_in, U256::from(5_000_000_007u64 + 12_345_678_901));
+        assert_eq!(weth_in, U256::from(3_000_000_000_000_000u64));
+        let (delta_usdc, delta_weth) = if usdc_is_token0 {
+            pool.reserve_delta().unwrap()
+        } else {
+            let (delta0, delta1) = pool.reserve_delta().unwrap();
+            (delta1, delta0)
+        };
+        assert_eq!(delta_usdc, I256::from_raw(usdc_in) - I256::from_raw(usdc_out));
+        assert_eq!(delta_weth, I256::from_raw(weth_in) - I256::from_raw(weth_out));
+        assert!(delta_usdc.is_positive() && delta_weth.is_negative());
+
+        // Резервы из сети начинают журнал заново
+        pool.set_reserves(U256::from(10u64), U256::from(20u64), 1);
+        assert_eq!(pool.history.as_ref().unwrap().events.len(), 0);
+        assert_eq!(pool.reserve_delta(), Some((I256::ZERO, I256::ZERO)));
+    }
+
+    #[test]
+    fn for_test_pool_swaps_offline_with_reserves_in_given_order() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        // WETH < USDC по адресу не гарантирован: резервы следуют порядку аргументов
+        let mut pool = Pool::for_test(Address::repeat_byte(0x31), TokenId::WETH, TokenId::USDC,
+            U256::from(800u64) * weth, U256::from(2_000_000_000_000u64), "Test");
+        assert_eq!(pool.name, "Test");
+        assert_eq!(pool.liquidity_in(TokenId::USDC), Some(U256::from(2_000_000_000_000u64)));
+        assert_eq!(pool.liquidity_in(TokenId::WETH), Some(U256::from(800u64) * weth));
+
+        let usdc_is_token0 = pool.token0 == TokenId::USDC;
+        let quoted = pool.get_amount_out(U256::from(1_000_000_000u64), usdc_is_token0);
+        assert_eq!(pool.swap_by_token(TokenId::USDC, U256::from(1_000_000_000u64)), Ok(quoted));
+        assert_eq!(pool.liquidity_in(TokenId::USDC), Some(U256::from(2_001_000_000_000u64)));
+
+        // Ошибки свапа не меняют резервы
+        let before = pool.snapshot();
+        assert_eq!(pool.mock_swap(U256::ZERO, usdc_is_token0), Err(PoolError::ZeroAmount));
+        assert_eq!(pool.swap_by_token(TokenId::USDC_E, U256::from(1u64)), Err(PoolError::TokenNotInPool));
+        assert_eq!(pool.mock_swap(U256::from(1u64), !usdc_is_token0), Err(PoolError::InsufficientOutput));
+        assert_eq!(pool.mock_swap(U256::MAX, usdc_is_token0), Err(PoolError::Overflow));
+        assert_eq!(pool.snapshot(), before);
+    }
+
+    #[test]
+    fn draining_tiny_pool_never_panics() {
+        let mut pool = test_pool(0x23, TokenId::USDC, TokenId::WETH, U256::from(10u64), U256::from(10u64));
+        let input_is_token0 = pool.token0 == TokenId::USDC;
+        for amount in [1u64, 5, 100, 1_000_000, u64::MAX] {
+            let before = pool.snapshot();
+            match pool.mock_swap(U256::from(amount), input_is_token0) {
+                Ok(_) => assert!(pool.reserves_for(input_is_token0).1 >= U256::from(1u64)),
+                Err(error) => {
+                    assert!(matches!(error, PoolError::InsufficientLiquidity | PoolError::InsufficientOutput), "{:?}", error);
+                    assert_eq!(pool.snapshot(), before);
+                }
+            }
+        }
+        // Выходной резерв не опускается до нуля: дальше выход округляется до нуля, но без паники
+        let (_, reserve_out) = pool.reserves_for(input_is_token0);
+        assert!(reserve_out >= U256::from(1u64));
+        assert_eq!(pool.mock_swap(U256::from(1u64), input_is_token0), Err(PoolError::InsufficientOutput));
+    }
+
+    #[test]
+    fn mock_swap_rejects_invalid_swaps_without_changing_reserves() {
+        let mut pool = test_pool(0x24, TokenId::USDC, TokenId::WETH, U256::from(1_000u64), U256::from(1_000u64));
+        let input_is_token0 = pool.token0 == TokenId::USDC;
+        assert_eq!(pool.mock_swap(U256::ZERO, input_is_token0), Err(PoolError::ZeroAmount));
+
+        let mut empty = test_pool(0x25, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::from(1_000u64));
+        assert_eq!(empty.mock_swap(U256::from(10u64), true), Err(PoolError::EmptyReserves));
+        assert_eq!(empty.mock_swap(U256::from(10u64), false), Err(PoolError::EmptyReserves));
+
+        // Входной резерв у границы U256
+        let (reserve_in, reserve_out) = (U256::MAX - U256::from(5u64), U256::from(1_000u64));
+        let mut full = test_pool(0x26, TokenId::USDC, TokenId::WETH, reserve_in, reserve_out);
+        let input_is_token0 = full.token0 == TokenId::USDC;
+        let before = full.snapshot();
+        assert_eq!(full.mock_swap(U256::from(10u64), input_is_token0), Err(PoolError::Overflow));
+        assert_eq!(full.snapshot(), before);
+    }
+
+    #[test]
+    fn unusable_pools_report_the_specific_reason() {
+        let pool = test_pool(0x2a, TokenId::USDC, TokenId::WETH, U256::from(1_000_000u64), U256::from(1_000_000u64));
+        let usdc_is_token0 = pool.token0 == TokenId::USDC;
+        assert_eq!(pool.try_get_amount_out(U256::from(1_000u64), usdc_is_token0), Ok(U256::from(996u64)));
+
+        let empty = test_pool(0x2b, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::from(1_000u64));
+        assert_eq!(empty.try_get_amount_out(U256::from(1_000u64), true), Err(PoolError::EmptyReserves));
+        assert_eq!(empty.quote_by_token(TokenId::USDC, U256::from(1_000u64)), Err(PoolError::EmptyReserves));
+        assert_eq!(pool.quote_by_token(TokenId::USDC_E, U256::from(1_000u64)), Err(PoolError::TokenNotInPool));
+        // Вход 1 при комиссии 0.3% дает 0: настоящий, но нулевой выход - отдельная причина
+        assert_eq!(pool.try_get_amount_out(U256::from(1u64), usdc_is_token0), Err(PoolError::InsufficientOutput));
+        assert_eq!(pool.quote_by_token(TokenId::USDC, U256::from(1u64)), Err(PoolError::InsufficientOutput));
+        assert_eq!(pool.get_amount_out(U256::from(1u64), usdc_is_token0), U256::ZERO);
+
+        let full = test_pool(0x2c, TokenId::USDC, TokenId::WETH, U256::MAX, U256::from(1_000u64));
+        assert_eq!(full.quote_by_token(TokenId::USDC, U256::from(1u64)), Err(PoolError::Overflow));
+
+        // Balancer отклоняет вход больше 30% баланса
+        let one = U256::from(10u64).pow(U256::from(18u64));
+        let half = one / U256::from(2u64);
+        let weighted = pool.clone().into_weighted(B256::ZERO, [(TokenId::USDC, half), (TokenId::WETH, half)], U256::ZERO);
+        assert!(weighted.quote_by_token(TokenId::USDC, U256::from(100_000u64)).is_ok());
+        assert_eq!(weighted.try_get_amount_out(U256::from(400_000u64), usdc_is_token0), Err(PoolError::InsufficientLiquidity));
+        assert_eq!(weighted.quote_by_token(TokenId::USDC, U256::from(400_000u64)), Err(PoolError::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn lower_fee_pool_quotes_strictly_more() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let standard = test_pool(0x27, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(800u64) * weth);
+        let cheaper = standard.clone().with_fee_bps(25);
+        assert_eq!(standard.fee_bps, DEFAULT_FEE_BPS);
+        assert_eq!(cheaper.fee_label(), "25 bps");
+
+        let input_is_token0 = standard.token0 == TokenId::USDC;
+        for amount in [1_000_000u64, 10_000_000_000, 500_000_000_000] {
+            let amount = U256::from(amount);
+            let (reserve_in, reserve_out) = cheaper.reserves_for(input_is_token0);
+            assert!(cheaper.get_amount_out(amount, input_is_token0) > standard.get_amount_out(amount, input_is_token0));
+            assert_eq!(cheaper.get_amount_out(amount, input_is_token0), get_amount_out_with_fee(amount, reserve_in, reserve_out, 25));
+            assert!(cheaper.swap_fee_amount(amount) < standard.swap_fee_amount(amount));
+        }
+
+        // Комиссия, измененная в обход with_fee_bps, не берется из устаревшего кэша
+        let mut changed = standard.clone();
+        changed.fee_bps = 25;
+        let amount = U256::from(10_000_000_000u64);
+        assert_eq!(changed.get_amount_out(amount, input_is_token0), cheaper.get_amount_out(amount, input_is_token0));
+        let mut swapped = cheaper.clone();
+        assert_eq!(swapped.mock_swap(amount, input_is_token0), Ok(cheaper.get_amount_out(amount, input_is_token0)));
+    }
+
+    #[test]
+    fn saved_pools_reload_and_quote_identically() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut quickswap = test_pool(0x21, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(800u64) * weth)
+            .with_fee_bps(25);
+        quickswap.dex = DexId::QUICKSWAP;
+        quickswap.protocol_fee_enabled = true;
+        let weighted = test_pool(0x22, TokenId::USDC_E, TokenId::WETH, U256::from(1_000_000_000_000u64), U256::from(400u64) * weth)
+            .into_weighted(B256::repeat_byte(0x42), [
+                (TokenId::USDC_E, U256::from(200_000_000_000_000_000u64)),
+                (TokenId::WETH, U256::from(800_000_000_000_000_000u64)),
+            ], U256::from(3_000_000_000_000_000u64));
+        let pools = vec![Pool::from_state(quickswap, test_provider()), Pool::from_state(weighted, test_provider())];
+
+        let path = std::env::temp_dir().join(format!("pools_{}.json", std::process::id()));
+        save_pools(&path, &pools, 65_000_000).unwrap();
+        let (loaded, block) = load_pools_at_block(&path, test_provider()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(block, 65_000_000);
+        for (original, loaded) in pools.iter().zip(&loaded) {
+            assert_eq!(SnapshotPool::from(&loaded.state), SnapshotPool::from(&original.state));
+            assert_eq!((loaded.token0_decimals, loaded.token1_decimals), (original.token0_decimals, original.token1_decimals));
+            assert_eq!(loaded.dex, original.dex);
+            for amount in [1_000_000u64, 50_000_000_000, 900_000_000_000] {
+                let input_is_token0 = original.token0 != TokenId::WETH;
+                assert_eq!(loaded.get_amount_out(U256::from(amount), input_is_token0),
+                    original.get_amount_out(U256::from(amount), input_is_token0));
+            }
+        }
+    }
+
+    /// Выход `UniswapV2Router02.getAmountsOut` (997/1000) для пула 3M USDC / 1200 WETH
+    const ROUTER_FIXTURES: [(u64, u64); 5] = [
+        (1, 398_799_999),
+        (10, 3_987_999_999),
+        (333, 132_800_399_985),
+        (334, 133_199_199_985),
+        (1_000, 398_799_999_867),
+    ];
+
+    #[test]
+    fn tiny_chunk_quotes_match_router_fixtures() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pool = test_pool(0x31, TokenId::USDC, TokenId::WETH, U256::from(3_000_000_000_000u64), U256::from(1_200u64) * weth);
+        let usdc_is_token0 = pool.token0 == TokenId::USDC;
+        let (reserve_in, reserve_out) = pool.reserves_for(usdc_is_token0);
+
+        for (amount, router_out) in ROUTER_FIXTURES {
+            let amount = U256::from(amount);
+            // Котировка совпадает с роутером и на чанках с округленной до нуля комиссией
+            assert_eq!(pool.get_amount_out(amount, usdc_is_token0), U256::from(router_out));
+
+            // Модель "вход минус округленная комиссия" на таких чанках завышает выход примерно на всю комиссию
+            let fee = pool.swap_fee_amount(amount);
+            let naive_out = get_amount_out_with_fee(amount - fee, reserve_in, reserve_out, 0);
+            let overstatement_bps = (u256_to_f64(naive_out) / router_out as f64 - 1.0) * 10_000.0;
+            if amount < crate::math::min_amount_with_nonzero_fee(pool.fee_bps).unwrap() {
+                assert_eq!(fee, U256::ZERO);
+                assert!((29.0..=30.5).contains(&overstatement_bps), "{}: {}", amount, overstatement_bps);
+            } else {
+                assert!(fee > U256::ZERO);
+                assert!(overstatement_bps < 0.1, "{}: {}", amount, overstatement_bps);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn old_block_timestamp_marks_reserves_stale() {
+        use crate::mock_chain::MockChainClient;
+
+        let now: u64 = 1_760_000_000;
+        let chain = MockChainClient::new();
+        chain.set_reserves(Address::repeat_byte(0x41), (U256::from(5_000u64), U256::from(7_000u64), (now - 2 * 3_600) as u32));
+        chain.set_reserves(Address::repeat_byte(0x42), (U256::from(6_000u64), U256::from(8_000u64), (now - 60) as u32));
+
+        let mut pools = Vec::new();
+        for byte in [0x41, 0x42] {
+            let mut pool = Pool::from_state(test_pool(byte, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), chain.clone());
+            pool.refresh_reserves().await.unwrap();
+            pools.push(pool);
+        }
+        assert_eq!(pools[0].last_updated, (now - 7_200) as u32);
+        assert_eq!(pools[0].reserves_age_secs(now), Some(7_200));
+        assert_eq!(pools[1].reserves_age_secs(now), Some(60));
+
+        let stale = stale_reserves(&pools, now, 3_600);
+        assert_eq!(stale, vec![StaleReserves { pool_address: Address::repeat_byte(0x41), pool_name: pools[0].name.clone(), age_secs: 7_200 }]);
+        assert!(stale_reserves(&pools, now, 10_000).is_empty());
+
+        // Время обновления попадает в JSON пула
+        let json = serde_json::to_string(&SnapshotPool::from(&pools[0].state)).unwrap();
+        assert!(json.contains(&format!("\"last_updated\":{}", now - 7_200)), "{}", json);
+        // Пулы без blockTimestampLast (Balancer) не считаются устаревшими
+        let weighted = Pool::from_state(test_pool(0x43, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), chain.clone());
+        assert_eq!(weighted.reserves_age_secs(now), None);
+    }
+
+    #[test]
+    fn prices_account_for_token_decimals() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        // 2 000 000 USDC и 800 WETH: 2500 USDC за WETH, а не 2500 * 10^-12
+        let pool = test_pool(0x51, TokenId::USDC, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(800u64) * weth);
+        let (usdc_per_weth, weth_per_usdc) = if pool.token0 == TokenId::USDC {
+            (pool.price_token1_in_token0(), pool.price_token0_in_token1())
+        } else {
+            (pool.price_token0_in_token1(), pool.price_token1_in_token0())
+        };
+        let (numerator, denominator) = usdc_per_weth.unwrap();
+        assert_eq!(numerator % denominator, U256::ZERO);
+        assert_eq!(numerator / denominator, U256::from(2_500u64));
+        assert_eq!(weth_per_usdc, Some((denominator, numerator)));
+
+        let usdc_per_weth = if pool.token0 == TokenId::USDC { pool.price_token1_in_token0_f64() } else { pool.price_token0_in_token1_f64() };
+        assert!((usdc_per_weth.unwrap() - 2_500.0).abs() < 1e-9);
+
+        // 1 000 000 USDC и 400 WETH при весах 80/20: (1e6 / 0.8) / (400 / 0.2) = 625
+        let weighted = test_pool(0x52, TokenId::USDC, TokenId::WETH, U256::from(1_000_000_000_000u64), U256::from(400u64) * weth)
+            .into_weighted(B256::repeat_byte(0x02), [
+                (TokenId::USDC, U256::from(800_000_000_000_000_000u64)),
+                (TokenId::WETH, U256::from(200_000_000_000_000_000u64)),
+            ], U256::from(3_000_000_000_000_000u64));
+        let usdc_per_weth = if weighted.token0 == TokenId::USDC { weighted.price_token1_in_token0_f64() } else { weighted.price_token0_in_token1_f64() };
+        assert!((usdc_per_weth.unwrap() - 625.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn empty_pool_has_no_price() {
+        let empty = test_pool(0x53, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::from(1u64));
+        assert_eq!(empty.price_token1_in_token0(), None);
+        assert_eq!(empty.price_token0_in_token1(), None);
+        assert_eq!(empty.price_token1_in_token0_f64(), None);
+        assert_eq!(empty.price_token0_in_token1_f64(), None);
+    }
+
+    #[tokio::test]
+    async fn quote_path_is_the_same_over_http_and_ws() {
+        use crate::mock_rpc::call_selector;
+        use crate::provider::IUniswapV2Pair;
+        use crate::solver::{find_best_routes, SolverConfig};
+        use alloy::sol_types::SolCall;
+
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let handler = move |_: &str, params: &serde_json::Value| {
+            assert_eq!(call_selector(params), Some(IUniswapV2Pair::getReservesCall::SELECTOR));
+            let reserves = IUniswapV2Pair::getReservesCall::abi_encode_returns(&(
+                alloy::primitives::Uint::<112, 2>::from(2_000_000_000_000u64),
+                alloy::primitives::Uint::<112, 2>::from(U256::from(1_000u64) * weth),
+                1_700_000_000u32,
+            ));
+            Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(reserves))))
+        };
+        let ctx = crate::config::ConfigContext::default();
+        let solver_config = SolverConfig { total_amount_in: U256::from(10_000_000_000u64), num_chunks: 10, verbose: false, ..Default::default() };
+
+        let mut totals = Vec::new();
+        for rpc in [MockRpc::start(handler).await, MockRpc::start_ws(handler).await] {
+            let mut pool = Pool::from_state(test_pool(0x01, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), rpc.provider.clone());
+            pool.refresh_reserves().await.unwrap();
+            assert_eq!(rpc.request_count(), 1);
+            let result = find_best_routes(vec![pool.state.clone()], &ctx, &solver_config).await.unwrap();
+            totals.push(result.total_weth_out);
+        }
+        assert!(!totals[0].is_zero());
+        assert_eq!(totals[0], totals[1]);
+    }
+
+    /// Мок getReserves напрямую и через Multicall3
+    /// 
+    /// Резервы пула - (байт адреса, 2 * байт адреса); вызов `failing` внутри
+    /// multicall не удается. Если `multicall_reverts`, весь aggregate3 откатывается.
+    async fn reserves_rpc(failing: Option<Address>, multicall_reverts: bool) -> MockRpc {
+        use crate::config::MULTICALL3_ADDRESS;
+        use crate::mock_rpc::{call_input, call_target};
+        use crate::provider::{IMulticall3, IUniswapV2Pair};
+        use alloy::sol_types::SolCall;
+
+        let reserves = |target: Address| IUniswapV2Pair::getReservesCall::abi_encode_returns(&(
+            alloy::primitives::Uint::<112, 2>::from(target[19]),
+            alloy::primitives::Uint::<112, 2>::from(2 * target[19] as u64),
+            1_700_000_000u32,
+        ));
+        MockRpc::start(move |_, params| {
+            let target = call_target(params).unwrap();
+            if target != MULTICALL3_ADDRESS {
+                return Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(reserves(target)))));
+            }
+            if multicall_reverts {
+                return Err("execution reverted".to_string());
+            }
+            let calls = IMulticall3::aggregate3Call::abi_decode(&call_input(params).unwrap(), true).unwrap().calls;
+            let results: Vec<IMulticall3::Result> = calls
+                .iter()
+                .map(|call| match Some(call.target) == failing {
+                    true => IMulticall3::Result { success: false, returnData: Default::default() },
+                    false => IMulticall3::Result { success: true, returnData: reserves(call.target).into() },
+                })
+                .collect();
+            Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(IMulticall3::aggregate3Call::abi_encode_returns(&(results,))))))
+        }).await
+    }
+
+    fn unrefreshed_pools(rpc: &MockRpc, count: u8) -> Vec<Pool> {
+        (1..=count)
+            .map(|byte| Pool::from_state(test_pool(byte, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), rpc.provider.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn multicall_refresh_matches_sequential_in_one_round_trip() {
+        let failing = Address::repeat_byte(0x05);
+        let rpc = reserves_rpc(Some(failing), false).await;
+
+        let mut sequential = unrefreshed_pools(&rpc, 12);
+        for pool in sequential.iter_mut() {
+            pool.refresh_reserves().await.unwrap();
+        }
+        let sequential_requests = rpc.request_count();
+
+        let mut batched = unrefreshed_pools(&rpc, 12);
+        let failures = refresh_all_reserves(rpc.provider.clone(), &mut batched).await;
+        let batched_requests = rpc.request_count() - sequential_requests;
+
+        assert!(failures.is_empty());
+        // Один aggregate3 и отдельный запрос для пула, чей вызов внутри multicall не удался
+        assert_eq!((sequential_requests, batched_requests), (12, 2));
+        for (batched, sequential) in batched.iter().zip(&sequential) {
+            assert_eq!(batched.snapshot(), sequential.snapshot());
+            assert_eq!(batched.last_updated, 1_700_000_000);
+        }
+        let pool = batched.iter().find(|pool| pool.pool_address == failing).unwrap();
+        assert_eq!((pool.reserve_token0, pool.reserve_token1), (U256::from(5u64), U256::from(10u64)));
+    }
+
+    #[tokio::test]
+    async fn reverted_multicall_falls_back_to_individual_calls() {
+        use crate::mock_chain::{MockCall, MockChainClient};
+
+        let chain = MockChainClient::new();
+        let pools_state: Vec<PoolState> = (1..=3).map(|byte| test_pool(byte, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO)).collect();
+        for (byte, pool) in (1u64..).zip(&pools_state) {
+            chain.set_reserves(pool.pool_address, (U256::from(byte), U256::from(2 * byte), 1_700_000_000));
+        }
+        chain.fail(MockCall::ReservesBatch);
+        let mut pools: Vec<Pool> = pools_state.into_iter().map(|state| Pool::from_state(state, chain.clone())).collect();
+        let failures = refresh_all_reserves(chain.clone(), &mut pools).await;
+
+        assert!(failures.is_empty());
+        assert_eq!(chain.calls(MockCall::ReservesBatch), 1);
+        assert!(pools.iter().all(|pool| chain.calls(MockCall::Reserves(pool.pool_address)) == 1));
+        assert!(pools.iter().all(|pool| pool.reserve_token0 > U256::ZERO && pool.reserve_token1 > U256::ZERO));
+    }
+
+    #[tokio::test]
+    async fn refresh_at_block_takes_block_from_multicall_or_before_individual_reads() {
+        use crate::mock_chain::{MockCall, MockChainClient};
+
+        let chain = MockChainClient::new();
+        let state = test_pool(1, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO);
+        chain.set_reserves(state.pool_address, (U256::from(5u64), U256::from(7u64), 1_700_000_000));
+        chain.set_block(100);
+        let mut pools = vec![Pool::from_state(state, chain.clone())];
+
+        let (block, failures) = refresh_reserves_at_block(chain.clone(), &mut pools, &[0]).await.unwrap();
+        assert_eq!(block, 100);
+        assert!(failures.is_empty());
+        // Блок пришел из того же multicall - отдельный запрос блока не нужен
+        assert_eq!(chain.calls(MockCall::BlockNumber), 0);
+
+        chain.set_block(101);
+        chain.fail(MockCall::ReservesBatch);
+        let (block, failures) = refresh_reserves_at_block(chain.clone(), &mut pools, &[0]).await.unwrap();
+        assert_eq!(block, 101);
+        assert!(failures.is_empty());
+        assert_eq!(chain.calls(MockCall::BlockNumber), 1);
+        assert_eq!(pools[0].reserve_token0, U256::from(5u64));
+
+        // Без номера блока события нельзя упорядочить относительно резервов
+        chain.fail(MockCall::BlockNumber);
+        assert!(refresh_reserves_at_block(chain.clone(), &mut pools, &[0]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_reports_failed_pairs_and_reads_weighted_balances_from_vault() {
+        use crate::chain::WeightedPoolParams;
+        use crate::mock_chain::{MockCall, MockChainClient};
+
+        let chain = MockChainClient::new();
+        let (live, broken) = (Address::repeat_byte(0x61), Address::repeat_byte(0x62));
+        chain.set_reserves(live, (U256::from(3u64), U256::from(4u64), 1_700_000_000));
+        chain.set_reserves(broken, (U256::from(5u64), U256::from(6u64), 1_700_000_000));
+        chain.fail(MockCall::Reserves(broken));
+
+        let pool_id = B256::repeat_byte(0x63);
+        let weights = [(TokenId::USDC, U256::from(800_000_000_000_000_000u64)), (TokenId::WETH, U256::from(200_000_000_000_000_000u64))];
+        let swap_fee = U256::from(3_000_000_000_000_000u64);
+        let params = WeightedPoolParams { pool_id, weights: weights.iter().map(|(_, weight)| *weight).collect(), swap_fee };
+        chain.add_weighted_pool(Address::repeat_byte(0x63), params, vec![(TokenId::WETH, U256::from(400u64)), (TokenId::USDC, U256::from(1_000u64))]);
+
+        let mut pools = vec![
+            Pool::from_state(test_pool(0x61, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), chain.clone()),
+            Pool::from_state(test_pool(0x62, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), chain.clone()),
+            Pool::from_state(test_pool(0x63, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO).into_weighted(pool_id, weights, swap_fee), chain.clone()),
+        ];
+        let failures = refresh_all_reserves(chain.clone(), &mut pools).await;
+
+        // Пара со сбоем внутри multicall запрашивается отдельно и попадает в ошибки
+        assert_eq!(failures.iter().map(|(address, _)| *address).collect::<Vec<_>>(), vec![broken]);
+        assert_eq!(chain.calls(MockCall::ReservesBatch), 1);
+        assert_eq!((chain.calls(MockCall::Reserves(live)), chain.calls(MockCall::Reserves(broken))), (0, 1));
+        assert_eq!((pools[0].reserve_token0, pools[0].reserve_token1), (U256::from(3u64), U256::from(4u64)));
+        assert_eq!(pools[1].reserve_token0, U256::ZERO);
+        // Балансы Vault сопоставляются токенам пула по адресу, а не по порядку в Vault
+        assert_eq!(crate::amm::AmmPool::reserves(&pools[2].state, TokenId::USDC), (U256::from(1_000u64), U256::from(400u64)));
+        assert_eq!(chain.calls(MockCall::VaultPoolTokens(pool_id)), 1);
+    }
+
+    #[tokio::test]
+    async fn exhausted_pair_failures_stop_failing_inside_multicall() {
+        use crate::mock_chain::{MockCall, MockChainClient};
+
+        let chain = MockChainClient::new();
+        let pair = Address::repeat_byte(0x64);
+        chain.set_reserves(pair, (U256::from(7u64), U256::from(8u64), 1_700_000_000));
+        chain.fail_times(MockCall::Reserves(pair), 1);
+        let mut pools = vec![Pool::from_state(test_pool(0x64, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), chain.clone())];
+
+        // Единственный сбой расходуется в multicall, отдельный запрос уже проходит
+        assert!(refresh_all_reserves(chain.clone(), &mut pools).await.is_empty());
+        assert_eq!(chain.calls(MockCall::Reserves(pair)), 1);
+        // Дальше пара снова читается одним multicall
+        assert!(refresh_all_reserves(chain.clone(), &mut pools).await.is_empty());
+        assert_eq!((chain.calls(MockCall::ReservesBatch), chain.calls(MockCall::Reserves(pair))), (2, 1));
+        assert_eq!(pools[0].reserve_token1, U256::from(8u64));
+    }
+
+    #[test]
+    fn display_and_summary_row_snapshot() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pool = Pool::for_test(Address::repeat_byte(0x31), TokenId::USDC, TokenId::WETH,
+            U256::from(2_000_123_456_789u64), U256::from(800u64) * weth + U256::from(1u64), "Test USDC/WETH");
+
+        assert_eq!(pool.to_string(), "Test USDC/WETH [Test] 0x3131…3131: 2000123.456789 USDC / 800.000000 WETH, 1 WETH = 2500.154320 USDC, комиссия 30 bps");
+        assert_eq!(pool.to_string(), pool.state.to_string());
+        assert_eq!(PoolState::summary_header(),
+            "Пул                          | DEX            | Адрес         |                Резерв token0 |                Резерв token1 |          Цена token1 |  Комиссия");
+        assert_eq!(pool.summary_row(),
+            "Test USDC/WETH               | Test           | 0x3131…3131   |          2000123.456789 USDC |              800.000000 WETH |     2500.154320 USDC |    30 bps");
+    }
+
+    #[test]
+    fn display_falls_back_to_raw_reserves_for_unknown_decimals() {
+        let unknown = TokenId(Address::repeat_byte(0xab));
+        let mut pool = test_pool(0x32, TokenId::USDC, unknown, U256::from(1_000_000_000u64), U256::from(5_000_000u64));
+        pool.name = "Test USDC/ABC".to_string();
+
+        assert_eq!(pool.known_decimals(unknown), None);
+        assert_eq!(pool.to_string(), "Test USDC/ABC [Test] 0x3232…3232: 1000.000000 USDC / 5000000 raw 0xabab…abab, цена неизвестна, комиссия 30 bps");
+        assert_eq!(pool.summary_row(),
+            "Test USDC/ABC                | Test           | 0x3232…3232   |             1000.000000 USDC |      5000000 raw 0xabab…abab |                    - |    30 bps");
+
+        // После запроса decimals у контракта резерв и цена выводятся с учетом decimals
+        pool.token1_decimals = 6;
+        pool.decimals_fetched = true;
+        assert_eq!(pool.to_string(), "Test USDC/ABC [Test] 0x3232…3232: 1000.000000 USDC / 5.000000 0xabab…abab, 1 0xabab…abab = 200.000000 USDC, комиссия 30 bps");
+    }
+}
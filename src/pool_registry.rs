@@ -0,0 +1,423 @@
+// src/pool_registry.rs
+//! Реестр найденных пулов без дубликатов
+//!
+//! Один и тот же адрес пары может прийти из разных путей discovery
+//! (например, Sushiswap запрашивается и для USDC, и для USDC.e, а
+//! статический пул может найтись и через Factory), причем с разными
+//! заявленными токенами. Две копии пула отслеживали бы резервы независимо,
+//! и солвер посчитал бы ликвидность дважды, а неверные токены ломали бы
+//! проверки входного токена. Поэтому пулы попадают в солвер только через
+//! `PoolRegistry`: адрес пары - ключ, повторный адрес отклоняется, токены
+//! пары Uniswap V2 один раз сверяются с блокчейном (`insert_verified`), и
+//! при расхождении побеждают данные контракта. Реестр индексирует пулы по
+//! паре токенов для поиска по паре (и по паре на одном DEX).
+use crate::log;
+use crate::config::{ConfigContext, DexId, TokenId};
+use crate::pool::{Pool, PoolKind, PoolState};
+use crate::provider::{get_token_symbol, pool_label};
+use alloy::primitives::{Address, U256};
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// Что произошло с пулом при добавлении
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// Новый пул, токены совпали с блокчейном (или пул не пара Uniswap V2)
+    Added,
+    /// Новый пул, заявленные токены заменены токенами из контракта
+    Relabeled,
+    /// Пул с этим адресом уже есть, новая копия отброшена
+    Duplicate,
+}
+
+/// Ключ индекса пар: токены в порядке адресов
+type PairKey = (TokenId, TokenId);
+
+/// Пулы в порядке добавления; через `Deref` доступны как `&[Pool]`
+#[derive(Debug, Default)]
+pub struct PoolRegistry {
+    pools: Vec<Pool>,
+    by_address: HashMap<Address, usize>,
+    by_pair: HashMap<PairKey, Vec<usize>>,
+}
+
+impl PoolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Реестр из готовых пулов (например, загруженных из файла); дубликаты отклоняются
+    pub fn from_pools(pools: impl IntoIterator<Item = Pool>) -> Self {
+        let mut registry = Self::new();
+        for pool in pools {
+            registry.insert(pool);
+        }
+        registry
+    }
+
+    /// Добавляет пул без проверки токенов; пул с уже известным адресом
+    /// отклоняется (`InsertOutcome::Duplicate`) с сообщением
+    pub fn insert(&mut self, pool: Pool) -> InsertOutcome {
+        if let Some(&index) = self.by_address.get(&pool.pool_address) {
+            let existing = &self.pools[index];
+            if (existing.token0, existing.token1) != (pool.token0, pool.token1) {
+                log!("Пул {:?} уже добавлен как {} ({}/{}); копия {} с токенами {}/{} отклонена, оставлен первый пул",
+                    pool.pool_address, existing.name, existing.token0, existing.token1, pool.name, pool.token0, pool.token1);
+            } else {
+                log!("Пул {:?} уже добавлен как {}; копия {} отклонена", pool.pool_address, existing.name, pool.name);
+            }
+            return InsertOutcome::Duplicate;
+        }
+
+        let index = self.pools.len();
+        self.by_address.insert(pool.pool_address, index);
+        self.by_pair.entry(pair_key(&pool)).or_default().push(index);
+        self.pools.push(pool);
+        InsertOutcome::Added
+    }
+
+    /// Добавляет пул, если его адреса еще нет
+    /// 
+    /// Для нового пула constant product токены сверяются с `token0()` /
+    /// `token1()` контракта; если запрос не удался, остаются заявленные.
+    /// Расхождения печатаются.
+    pub async fn insert_verified(&mut self, mut pool: Pool) -> InsertOutcome {
+        if self.by_address.contains_key(&pool.pool_address) {
+            return self.insert(pool);
+        }
+        let outcome = if pool.kind == PoolKind::ConstantProduct {
+            verify_tokens(&mut pool).await
+        } else {
+            InsertOutcome::Added
+        };
+        self.insert(pool);
+        outcome
+    }
+
+    /// Пул по адресу
+    pub fn get(&self, address: Address) -> Option<&Pool> {
+        self.by_address.get(&address).map(|&index| &self.pools[index])
+    }
+
+    /// Пулы пары токенов (в любом порядке) на всех DEX
+    pub fn by_pair(&self, token_a: TokenId, token_b: TokenId) -> Vec<&Pool> {
+        self.by_pair
+            .get(&sorted(token_a, token_b))
+            .map_or_else(Vec::new, |indices| indices.iter().map(|&index| &self.pools[index]).collect())
+    }
+
+    /// Пулы пары токенов на одном DEX
+    pub fn by_pair_on(&self, token_a: TokenId, token_b: TokenId, dex: DexId) -> Vec<&Pool> {
+        self.by_pair(token_a, token_b).into_iter().filter(|pool| pool.dex == dex).collect()
+    }
+
+    /// Пулы одного DEX
+    pub fn by_dex(&self, dex: DexId) -> Vec<&Pool> {
+        self.pools.iter().filter(|pool| pool.dex == dex).collect()
+    }
+
+    /// Пулы для обновления резервов; адреса и токены менять нельзя - по ним построены индексы
+    pub fn pools_mut(&mut self) -> &mut [Pool] {
+        &mut self.pools
+    }
+
+    /// Оставляет только пулы, для которых `keep` вернул true
+    pub fn retain(&mut self, keep: impl FnMut(&Pool) -> bool) {
+        let mut pools = std::mem::take(&mut self.pools);
+        pools.retain(keep);
+        *self = Self::from_pools(pools);
+    }
+
+    /// Упорядочивает пулы по глубине входного токена профиля, от самых глубоких
+    /// 
+    /// Солвер перебирает пулы в этом порядке, поэтому при равном выходе
+    /// чанк получает более глубокий пул. Глубина сравнивается после
+    /// приведения к 18 decimals (USDC и USDC.e с разными decimals сравниваются
+    /// честно); при равной глубине первым идет меньший адрес, так что порядок
+    /// не зависит от порядка discovery. Пулы без входного токена - в конце.
+    pub fn sort_by_liquidity(&mut self, ctx: &ConfigContext) {
+        let mut pools = std::mem::take(&mut self.pools);
+        pools.sort_by_cached_key(|pool| (std::cmp::Reverse(input_depth(pool, ctx)), pool.pool_address));
+        *self = Self::from_pools(pools);
+    }
+
+    /// Копии состояний пулов для солвера
+    pub fn states(&self) -> Vec<PoolState> {
+        Pool::states(&self.pools)
+    }
+
+    pub fn into_pools(self) -> Vec<Pool> {
+        self.pools
+    }
+}
+
+impl Deref for PoolRegistry {
+    type Target = [Pool];
+
+    fn deref(&self) -> &[Pool] {
+        &self.pools
+    }
+}
+
+impl<'a> IntoIterator for &'a PoolRegistry {
+    type Item = &'a Pool;
+    type IntoIter = std::slice::Iter<'a, Pool>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pools.iter()
+    }
+}
+
+/// Входной токен профиля в пуле, его резерв и decimals
+pub fn input_reserve(pool: &PoolState, ctx: &ConfigContext) -> Option<(TokenId, U256, u8)> {
+    [pool.token0, pool.token1]
+        .into_iter()
+        .find(|&token| ctx.is_input_token(token))
+        .and_then(|token| {
+            let reserve = pool.liquidity_in(token)?;
+            let decimals = if token == pool.token0 { pool.token0_decimals } else { pool.token1_decimals };
+            Some((token, reserve, decimals))
+        })
+}
+
+/// Резерв входного токена профиля, приведенный к 18 decimals
+/// (у токенов с большим числом decimals лишние разряды отбрасываются)
+pub fn input_depth(pool: &PoolState, ctx: &ConfigContext) -> Option<U256> {
+    let (_, reserve, decimals) = input_reserve(pool, ctx)?;
+    let ten = U256::from(10u64);
+    Some(match decimals.cmp(&18) {
+        std::cmp::Ordering::Less => reserve.saturating_mul(ten.pow(U256::from(18 - decimals))),
+        std::cmp::Ordering::Equal => reserve,
+        std::cmp::Ordering::Greater => reserve / ten.pow(U256::from(decimals - 18)),
+    })
+}
+
+fn sorted(token_a: TokenId, token_b: TokenId) -> (TokenId, TokenId) {
+    if token_a.address() <= token_b.address() { (token_a, token_b) } else { (token_b, token_a) }
+}
+
+fn pair_key(pool: &Pool) -> PairKey {
+    sorted(pool.token0, pool.token1)
+}
+
+/// Сверяет заявленные токены пары с контрактом и исправляет их при расхождении
+async fn verify_tokens(pool: &mut Pool) -> InsertOutcome {
+    let (token0, token1) = match pool.client.get_pair_tokens(pool.pool_address).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            log!("Предупреждение: не удалось проверить токены пула {} ({:?}): {}", pool.name, pool.pool_address, e);
+            return InsertOutcome::Added;
+        }
+    };
+    if (token0, token1) == (pool.token0.address(), pool.token1.address()) {
+        return InsertOutcome::Added;
+    }
+
+    let (token0, token1) = (TokenId(token0), TokenId(token1));
+    log!("Пул {:?}: заявлены токены {}/{}, в контракте {}/{} - используются токены контракта",
+        pool.pool_address, pool.token0, pool.token1, token0, token1);
+    // Резервы приходят из getReserves в порядке контракта, поэтому остаются верными
+    pool.token0 = token0;
+    pool.token1 = token1;
+    if let Err(e) = pool.fetch_decimals().await {
+        // decimals остаются из метаданных токенов до следующего запроса
+        log!("Предупреждение: не удалось получить decimals пула {} ({:?}): {}", pool.name, pool.pool_address, e);
+        pool.token0_decimals = token0.decimals();
+        pool.token1_decimals = token1.decimals();
+        pool.decimals_fetched = false;
+    }
+    let symbol0 = get_token_symbol(pool.client.clone(), token0).await;
+    let symbol1 = get_token_symbol(pool.client.clone(), token1).await;
+    pool.name = pool_label(pool.dex, (token0, &symbol0), (token1, &symbol1));
+    pool.invalidate_quote_cache();
+    InsertOutcome::Relabeled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DexId;
+    use crate::mock_rpc::{call_selector, call_target, encode_address, encode_word, MockRpc};
+    use crate::pool::test_pool;
+    use alloy::sol_types::SolValue;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn overlapping_discovery_keeps_one_pool_with_chain_tokens() {
+        let shared = Address::repeat_byte(0x61);
+        let other = Address::repeat_byte(0x62);
+        let token_calls = Arc::new(AtomicUsize::new(0));
+        let calls = Arc::clone(&token_calls);
+        let rpc = MockRpc::start(move |_, params| {
+            let target = call_target(params).unwrap();
+            match call_selector(params) {
+                // token0() / token1(): общий пул на самом деле USDC.e/WETH, второй - USDC/WETH
+                Some(selector @ ([0x0d, 0xfe, 0x16, 0x81] | [0xd2, 0x12, 0x20, 0xa7])) => {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    let input = if target == shared { TokenId::USDC_E } else { TokenId::USDC };
+                    let (token0, token1) = if input.address() < TokenId::WETH.address() {
+                        (input, TokenId::WETH)
+                    } else {
+                        (TokenId::WETH, input)
+                    };
+                    let token = if selector[0] == 0x0d { token0 } else { token1 };
+                    Ok(encode_address(token.address()))
+                }
+                // decimals()
+                Some([0x31, 0x3c, 0xe5, 0x67]) => {
+                    Ok(encode_word(U256::from(if target == TokenId::WETH.address() { 18u64 } else { 6 })))
+                }
+                // symbol()
+                Some([0x95, 0xd8, 0x9b, 0x41]) => {
+                    let symbol = if target == TokenId::WETH.address() { "WETH" } else { "USDC.e" };
+                    Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(symbol.to_string().abi_encode()))))
+                }
+                _ => Err("неожиданный вызов".to_string()),
+            }
+        }).await;
+
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let claimed = |address: Address, token_in: TokenId| {
+            let mut state = test_pool(0x00, token_in, TokenId::WETH, U256::from(1_000_000_000_000u64), U256::from(400u64) * weth);
+            state.pool_address = address;
+            state.dex = DexId::SUSHISWAP;
+            Pool::from_state(state, rpc.provider.clone())
+        };
+
+        let mut registry = PoolRegistry::new();
+        // Путь USDC заявляет пул USDC/WETH, путь USDC.e находит тот же адрес
+        assert_eq!(registry.insert_verified(claimed(shared, TokenId::USDC)).await, InsertOutcome::Relabeled);
+        assert_eq!(registry.insert_verified(claimed(shared, TokenId::USDC_E)).await, InsertOutcome::Duplicate);
+        assert_eq!(registry.insert_verified(claimed(shared, TokenId::USDC)).await, InsertOutcome::Duplicate);
+        assert_eq!(registry.insert_verified(claimed(other, TokenId::USDC)).await, InsertOutcome::Added);
+
+        assert_eq!(registry.len(), 2);
+        let pool = &registry[0];
+        assert_eq!(pool.pool_address, shared);
+        assert!([pool.token0, pool.token1].contains(&TokenId::USDC_E));
+        assert!([pool.token0, pool.token1].contains(&TokenId::WETH));
+        assert!(pool.token0.address() < pool.token1.address());
+        assert!(pool.name.starts_with("Sushiswap "), "{}", pool.name);
+        assert!(!pool.name.contains("Test"), "{}", pool.name);
+        // Резервы в порядке контракта не поменялись местами
+        let (reserve_usdc, _) = pool.reserves_for(pool.token0 == TokenId::USDC_E);
+        assert_eq!(reserve_usdc, U256::from(1_000_000_000_000u64));
+        // Токены каждого адреса проверяются один раз
+        assert_eq!(token_calls.load(Ordering::SeqCst), 4);
+    }
+
+    fn usdc_weth(address_byte: u8, dex: DexId, reserve_usdc: u64, reserve_weth: u64) -> Pool {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pool = Pool::for_test(Address::repeat_byte(address_byte), TokenId::USDC, TokenId::WETH,
+            U256::from(reserve_usdc) * U256::from(1_000_000u64), U256::from(reserve_weth) * weth, &format!("{} USDC/WETH", dex));
+        pool.dex = dex;
+        pool
+    }
+
+    #[tokio::test]
+    async fn duplicate_address_does_not_double_liquidity_in_solve() {
+        use crate::config::ConfigContext;
+        use crate::solver::{find_best_routes, SolverConfig};
+
+        // Статический пул Uniswap, найденный еще и через Factory
+        let mut registry = PoolRegistry::new();
+        assert_eq!(registry.insert(usdc_weth(0x71, DexId::UNISWAP_V2, 1_000_000, 400)), InsertOutcome::Added);
+        assert_eq!(registry.insert(usdc_weth(0x71, DexId::UNISWAP_V2, 1_000_000, 400)), InsertOutcome::Duplicate);
+        assert_eq!(registry.len(), 1);
+
+        let solver_config = SolverConfig {
+            total_amount_in: U256::from(200_000_000_000u64),
+            num_chunks: 20,
+            verbose: false,
+            ..SolverConfig::default()
+        };
+        let ctx = ConfigContext::default();
+        let result = find_best_routes(registry.states(), &ctx, &solver_config).await.unwrap();
+        let single = find_best_routes(vec![usdc_weth(0x71, DexId::UNISWAP_V2, 1_000_000, 400).state], &ctx, &solver_config)
+            .await
+            .unwrap();
+        assert_eq!(result.total_weth_out, single.total_weth_out);
+        assert!(result.chunk_routes.iter().all(|route| route.pool_address == Some(Address::repeat_byte(0x71))));
+
+        // Две копии одного адреса удвоили бы ликвидность
+        let doubled = find_best_routes(
+            vec![usdc_weth(0x71, DexId::UNISWAP_V2, 1_000_000, 400).state, usdc_weth(0x71, DexId::UNISWAP_V2, 1_000_000, 400).state],
+            &ctx,
+            &solver_config,
+        ).await.unwrap();
+        assert!(doubled.total_weth_out > result.total_weth_out);
+    }
+
+    #[test]
+    fn lookups_by_pair_and_dex() {
+        let mut registry = PoolRegistry::from_pools([
+            usdc_weth(0x72, DexId::QUICKSWAP, 3_000_000, 1_200),
+            usdc_weth(0x73, DexId::SUSHISWAP, 1_000_000, 400),
+            usdc_weth(0x72, DexId::SUSHISWAP, 1_000_000, 400),
+        ]);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.by_pair(TokenId::WETH, TokenId::USDC).len(), 2);
+        assert_eq!(registry.by_pair(TokenId::USDC_E, TokenId::WETH).len(), 0);
+        let sushiswap = registry.by_pair_on(TokenId::WETH, TokenId::USDC, DexId::SUSHISWAP);
+        assert_eq!(sushiswap.iter().map(|pool| pool.pool_address).collect::<Vec<_>>(), vec![Address::repeat_byte(0x73)]);
+        assert_eq!(registry.by_dex(DexId::QUICKSWAP).len(), 1);
+        assert_eq!(registry.get(Address::repeat_byte(0x72)).unwrap().dex, DexId::QUICKSWAP);
+
+        registry.retain(|pool| pool.dex != DexId::QUICKSWAP);
+        assert_eq!(registry.len(), 1);
+        assert!(registry.get(Address::repeat_byte(0x72)).is_none());
+        assert_eq!(registry.by_pair_on(TokenId::USDC, TokenId::WETH, DexId::SUSHISWAP).len(), 1);
+    }
+
+    #[test]
+    fn input_depth_scales_each_pool_by_its_own_input_decimals() {
+        let ctx = ConfigContext::default();
+        let usdc = test_pool(0x7a, TokenId::USDC, TokenId::WETH, U256::from(5_000_000u64), U256::from(1u64));
+        let (token, reserve, decimals) = input_reserve(&usdc, &ctx).unwrap();
+        assert_eq!((token, reserve, decimals), (TokenId::USDC, U256::from(5_000_000u64), 6));
+        assert_eq!(input_depth(&usdc, &ctx), Some(U256::from(5u64) * U256::from(10u64).pow(U256::from(18u64))));
+
+        // Вход с 24 decimals: лишние разряды отбрасываются, а не умножаются
+        let mut wide = test_pool(0x7b, TokenId::USDC, TokenId::WETH, U256::from(5u64) * U256::from(10u64).pow(U256::from(24u64)), U256::from(1u64));
+        if wide.token0 == TokenId::USDC {
+            wide.token0_decimals = 24;
+        } else {
+            wide.token1_decimals = 24;
+        }
+        assert_eq!(input_depth(&wide, &ctx), input_depth(&usdc, &ctx));
+    }
+
+    #[tokio::test]
+    async fn pools_are_sorted_deepest_first_with_address_tie_break() {
+        use crate::solver::{find_best_routes, SolverConfig};
+
+        let ctx = ConfigContext::default();
+        let mut usdc_e = usdc_weth(0x74, DexId::SUSHISWAP, 0, 0);
+        usdc_e.state = test_pool(0x74, TokenId::USDC_E, TokenId::WETH, U256::from(2_000_000_000_000u64), U256::from(10u64).pow(U256::from(21u64)));
+        let mut no_input = usdc_weth(0x70, DexId::QUICKSWAP, 0, 0);
+        no_input.state = test_pool(0x70, TokenId::WETH, TokenId(Address::repeat_byte(0xee)), U256::from(1u64), U256::from(1u64));
+        let mut registry = PoolRegistry::from_pools([
+            no_input,
+            usdc_weth(0x79, DexId::QUICKSWAP, 1_000_000, 400),
+            usdc_weth(0x75, DexId::SUSHISWAP, 500_000, 200),
+            usdc_e,
+            usdc_weth(0x77, DexId::UNISWAP_V2, 1_000_000, 400),
+        ]);
+        assert_eq!(registry.get(Address::repeat_byte(0x77)).unwrap().liquidity_in(TokenId::USDC), Some(U256::from(1_000_000_000_000u64)));
+        assert_eq!(registry.get(Address::repeat_byte(0x77)).unwrap().liquidity_in(TokenId::USDC_E), None);
+
+        registry.sort_by_liquidity(&ctx);
+        let order: Vec<u8> = registry.iter().map(|pool| pool.pool_address.0[0]).collect();
+        // USDC.e (2M) глубже всех; два пула по 1M - по возрастанию адреса; без входного токена - последний
+        assert_eq!(order, vec![0x74, 0x77, 0x79, 0x75, 0x70]);
+        assert_eq!(registry.get(Address::repeat_byte(0x79)).unwrap().dex, DexId::QUICKSWAP);
+
+        // Одинаковые пулы дают равный выход: первый чанк получает пул с меньшим адресом
+        registry.retain(|pool| matches!(pool.pool_address.0[0], 0x77 | 0x79));
+        let solver_config = SolverConfig { total_amount_in: U256::from(2_000_000_000u64), num_chunks: 2, verbose: false, ..SolverConfig::default() };
+        let result = find_best_routes(registry.states(), &ctx, &solver_config).await.unwrap();
+        assert_eq!(result.chunk_routes[0].pool_address, Some(Address::repeat_byte(0x77)));
+        assert_eq!(result.chunk_routes[1].pool_address, Some(Address::repeat_byte(0x79)));
+    }
+}
@@ -0,0 +1,178 @@
+// src/prefetch.rs
+//! Фоновая предзагрузка резервов для интерактивного режима
+//!
+//! После запуска REPL резервы найденных пулов обновляются заново, начиная
+//! с самых глубоких, пока не истечет отведенное время. Пулы, которые не
+//! успели обновиться, остаются с резервами discovery. Метаданные токенов
+//! (decimals, символы) уже закэшированы при discovery и не запрашиваются.
+use crate::config::ConfigContext;
+use crate::math::u256_to_f64;
+use crate::pool::Pool;
+use alloy::primitives::Address;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+/// Прогресс предзагрузки для строки статуса
+#[derive(Debug)]
+pub struct PrefetchProgress {
+    done: AtomicUsize,
+    total: usize,
+}
+
+impl PrefetchProgress {
+    pub fn new(total: usize) -> Self {
+        PrefetchProgress { done: AtomicUsize::new(0), total }
+    }
+
+    /// (обработано пулов, всего пулов)
+    pub fn get(&self) -> (usize, usize) {
+        (self.done.load(Ordering::SeqCst), self.total)
+    }
+}
+
+/// Итог предзагрузки
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchReport {
+    /// Адреса пулов с обновленными резервами, в порядке обновления
+    pub refreshed: Vec<Address>,
+    /// Пулы, запрос которых завершился ошибкой: (имя, ошибка)
+    pub failed: Vec<(String, String)>,
+    /// Время вышло до обработки всех пулов
+    pub timed_out: bool,
+}
+
+/// Порядок предзагрузки: по убыванию резерва входного токена профиля
+///
+/// Пулы без входного токена идут последними. Резерв сравнивается в
+/// decimal, чтобы USDC и USDC.e с разными decimals сравнивались честно.
+pub fn priority_order(pools: &[Pool], ctx: &ConfigContext) -> Vec<usize> {
+    let depth = |pool: &Pool| {
+        [(pool.token0, true), (pool.token1, false)]
+            .into_iter()
+            .find(|(token, _)| ctx.is_input_token(*token))
+            .map_or(0.0, |(_, input_is_token0)| {
+                let (reserve_in, _) = pool.reserves_for(input_is_token0);
+                let (decimals_in, _) = pool.decimals_for(input_is_token0);
+                u256_to_f64(reserve_in) / 10f64.powi(decimals_in as i32)
+            })
+    };
+    let mut order: Vec<usize> = (0..pools.len()).collect();
+    order.sort_by(|&a, &b| depth(&pools[b]).total_cmp(&depth(&pools[a])));
+    order
+}
+
+/// Обновляет резервы пулов в порядке `priority_order` в пределах `time_box`
+///
+/// Запросы идут по одному, чтобы самые глубокие пулы гарантированно
+/// обновились первыми. Запрос, не уложившийся в отведенное время,
+/// прерывается, а оставшиеся пулы не запрашиваются.
+pub async fn prefetch_reserves(
+    pools: &mut [Pool],
+    ctx: &ConfigContext,
+    time_box: Duration,
+    progress: &PrefetchProgress,
+) -> PrefetchReport {
+    let deadline = Instant::now() + time_box;
+    let mut report = PrefetchReport::default();
+
+    for index in priority_order(pools, ctx) {
+        let pool = &mut pools[index];
+        match timeout_at(deadline, pool.refresh_reserves()).await {
+            Ok(Ok(())) => report.refreshed.push(pool.pool_address),
+            Ok(Err(e)) => report.failed.push((pool.name.clone(), e.to_string())),
+            Err(_) => {
+                report.timed_out = true;
+                break;
+            }
+        }
+        progress.done.fetch_add(1, Ordering::SeqCst);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenId;
+    use crate::mock_rpc::{call_selector, call_target, MockRpc};
+    use crate::pool::test_pool;
+    use alloy::primitives::U256;
+    use std::sync::{Arc, Mutex};
+
+    fn reserves_response(reserve0: U256, reserve1: U256) -> serde_json::Value {
+        serde_json::json!(format!("0x{}{}{}",
+            alloy::hex::encode(reserve0.to_be_bytes::<32>()),
+            alloy::hex::encode(reserve1.to_be_bytes::<32>()),
+            alloy::hex::encode(U256::ZERO.to_be_bytes::<32>())))
+    }
+
+    /// Пулы USDC/WETH с разной глубиной; USDC.e сравнивается в decimal
+    fn pools(provider: Arc<crate::provider::RpcProvider>) -> Vec<Pool> {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let usdc = |amount: u64| U256::from(amount) * U256::from(1_000_000u64);
+        [(0x01, TokenId::USDC, 100_000), (0x02, TokenId::USDC_E, 5_000_000), (0x03, TokenId::USDC, 1_000_000)]
+            .into_iter()
+            .map(|(byte, token_in, depth)| {
+                let state = test_pool(byte, token_in, TokenId::WETH, usdc(depth), U256::from(depth / 2_500) * weth);
+                Pool::from_state(state, provider.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deepest_pools_come_first() {
+        let pools = pools(crate::pool::test_provider());
+        assert_eq!(priority_order(&pools, &ConfigContext::default()), vec![1, 2, 0]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn refreshes_in_priority_order_within_time_box() {
+        let slow_pool = Address::repeat_byte(0x03);
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requested);
+        let rpc = MockRpc::start(move |_, params| {
+            assert_eq!(call_selector(params), Some([0x09, 0x02, 0xf1, 0xac]));
+            let target = call_target(params).unwrap();
+            log.lock().unwrap().push(target);
+            if target == slow_pool {
+                std::thread::sleep(Duration::from_millis(1_500));
+            }
+            Ok(reserves_response(U256::from(7u64), U256::from(11u64)))
+        }).await;
+
+        let mut pools = pools(rpc.provider.clone());
+        let progress = PrefetchProgress::new(pools.len());
+        let started = std::time::Instant::now();
+        let report = prefetch_reserves(&mut pools, &ConfigContext::default(), Duration::from_millis(500), &progress).await;
+
+        // Самый глубокий пул обновлен, медленный прерван по времени, последний не запрашивался
+        assert!(started.elapsed() < Duration::from_millis(1_200), "{:?}", started.elapsed());
+        assert!(report.timed_out);
+        assert_eq!(report.refreshed, vec![Address::repeat_byte(0x02)]);
+        assert_eq!(*requested.lock().unwrap(), vec![Address::repeat_byte(0x02), slow_pool]);
+        assert_eq!(progress.get(), (1, 3));
+        assert_eq!((pools[1].reserve_token0, pools[1].reserve_token1), (U256::from(7u64), U256::from(11u64)));
+        assert_ne!(pools[0].reserve_token0, U256::from(7u64));
+    }
+
+    #[tokio::test]
+    async fn failed_pools_are_reported_and_skipped() {
+        let rpc = MockRpc::start(move |_, params| {
+            if call_target(params) == Some(Address::repeat_byte(0x02)) {
+                Err("execution reverted".to_string())
+            } else {
+                Ok(reserves_response(U256::from(7u64), U256::from(11u64)))
+            }
+        }).await;
+
+        let mut pools = pools(rpc.provider.clone());
+        let progress = PrefetchProgress::new(pools.len());
+        let report = prefetch_reserves(&mut pools, &ConfigContext::default(), Duration::from_secs(10), &progress).await;
+
+        assert!(!report.timed_out);
+        assert_eq!(report.refreshed, vec![Address::repeat_byte(0x03), Address::repeat_byte(0x01)]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(progress.get(), (3, 3));
+    }
+}
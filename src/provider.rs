@@ -1,17 +1,63 @@
 // src/provider.rs
-use alloy::primitives::{Address, U256};
-use alloy::providers::{Provider, ProviderBuilder, RootProvider};
+use alloy::primitives::aliases::{U160, U24};
+use alloy::primitives::{Address, B256, U256};
+use alloy::eips::{BlockId, BlockNumberOrTag};
+use alloy::providers::{Provider, ProviderBuilder, RootProvider, WsConnect};
+use alloy::rpc::types::BlockTransactionsKind;
 use alloy::sol;
-use alloy::transports::http::{Client, Http};
+use alloy::sol_types::SolValue;
+use alloy::rpc::client::ClientBuilder;
+use alloy::transports::{BoxTransport, RpcError, TransportError, TransportErrorKind, TransportFut};
 use eyre::Result;
-use std::sync::Arc;
-use crate::config::{usdc_to_decimal, weth_to_decimal};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::MissedTickBehavior;
+use tower::{Layer, Service};
+use crate::log;
+use crate::chain::{ChainClient, WeightedPoolParams};
+use crate::config::{format_units, pair_for, ConfigContext, DexConfig, DexId, DexSource, TokenId, UNISWAP_V3_FEE_TIERS, WETH_DECIMALS};
+use crate::discovery_cache::{DiscoveryCache, ProbeKey};
+use crate::pool::{refresh_all_reserves, Pool};
+use crate::pool_registry::PoolRegistry;
+use crate::twap::PriceObservation;
+use crate::v3_pool::V3Pool;
 
 // Определяем ABI для функции getReserves контракта Uniswap V2 Pair
 sol! {
     #[sol(rpc)]
     interface IUniswapV2Pair {
         function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+        function price0CumulativeLast() external view returns (uint256);
+        function price1CumulativeLast() external view returns (uint256);
+        function factory() external view returns (address);
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+
+        event Sync(uint112 reserve0, uint112 reserve1);
+        event Transfer(address indexed from, address indexed to, uint256 value);
+    }
+}
+
+// Определяем ABI для метаданных ERC20 токена
+sol! {
+    #[sol(rpc)]
+    interface IERC20Metadata {
+        function decimals() external view returns (uint8);
+        function symbol() external view returns (string);
+    }
+}
+
+// ABI ERC20 для проверки ограничений отправителя (transfer/approve через eth_call)
+sol! {
+    #[sol(rpc)]
+    interface IERC20 {
+        function transfer(address to, uint256 amount) external returns (bool);
+        function approve(address spender, uint256 amount) external returns (bool);
     }
 }
 
@@ -20,66 +66,622 @@ sol! {
     #[sol(rpc)]
     interface IUniswapV2Factory {
         function getPair(address tokenA, address tokenB) external view returns (address pair);
+        function feeTo() external view returns (address);
+    }
+}
+
+// Определяем ABI для пулов StableSwap (Curve)
+sol! {
+    #[sol(rpc)]
+    interface ICurvePool {
+        function balances(uint256 i) external view returns (uint256);
+        function A() external view returns (uint256);
+        function fee() external view returns (uint256);
+    }
+}
+
+// Определяем ABI для Balancer V2 Vault и взвешенного пула
+sol! {
+    #[sol(rpc)]
+    interface IBalancerVault {
+        function getPoolTokens(bytes32 poolId) external view returns (address[] tokens, uint256[] balances, uint256 lastChangeBlock);
+    }
+}
+
+// Определяем ABI для Uniswap V3: Factory, пул и QuoterV2
+sol! {
+    #[sol(rpc)]
+    interface IUniswapV3Factory {
+        function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface IUniswapV3Pool {
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked);
+        function liquidity() external view returns (uint128);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface IQuoterV2 {
+        struct QuoteExactInputSingleParams {
+            address tokenIn;
+            address tokenOut;
+            uint256 amountIn;
+            uint24 fee;
+            uint160 sqrtPriceLimitX96;
+        }
+        function quoteExactInputSingle(QuoteExactInputSingleParams memory params) external returns (uint256 amountOut, uint160 sqrtPriceX96After, uint32 initializedTicksCrossed, uint256 gasEstimate);
+    }
+}
+
+// Multicall3: несколько eth_call в одном запросе
+sol! {
+    #[sol(rpc)]
+    interface IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+        struct Result {
+            bool success;
+            bytes returnData;
+        }
+        function aggregate3(Call3[] calldata calls) external payable returns (Result[] memory returnData);
+        function getBlockNumber() external view returns (uint256 blockNumber);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface IWeightedPool {
+        function getPoolId() external view returns (bytes32);
+        function getNormalizedWeights() external view returns (uint256[]);
+        function getSwapFeePercentage() external view returns (uint256);
+    }
+}
+
+// Реестр DEX команды (--registry): одобренные Factory/роутеры сети
+sol! {
+    #[sol(rpc)]
+    interface IDexRegistry {
+        struct DexEntry {
+            string name;
+            address factory;
+            address router;
+            bytes32 pairInitCodeHash; // Ноль - адрес пары запрашивается через getPair
+            uint16 feeBps;            // Ноль - комиссия по умолчанию
+        }
+        function getDexCount() external view returns (uint256);
+        function getDex(uint256 index) external view returns (DexEntry memory entry);
+    }
+}
+
+/// Decimals уже запрошенных токенов: один запрос на токен за время работы
+static TOKEN_DECIMALS: OnceLock<Mutex<HashMap<Address, u8>>> = OnceLock::new();
+
+/// Получает decimals токена через `decimals()` ERC20 (с кэшем по адресу)
+/// 
+/// Нестандартные токены, у которых `decimals()` откатывается, получают
+/// 18 decimals с предупреждением; значение по умолчанию тоже кэшируется.
+/// Сбой транспорта (таймаут, 5xx) возвращается ошибкой и не кэшируется:
+/// запасные 18 decimals для USDC исказили бы пул в 10^12 раз.
+pub async fn get_token_decimals(client: Arc<dyn ChainClient>, token: TokenId) -> Result<u8> {
+    let cache = TOKEN_DECIMALS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(&decimals) = cache.lock().unwrap().get(&token.address()) {
+        return Ok(decimals);
+    }
+
+    let decimals = match client.get_decimals(token.address()).await {
+        Ok(decimals) => decimals,
+        Err(e) if is_contract_answer(&e) => {
+            log!("Предупреждение: decimals() токена {} недоступен ({}), используется {}", token, e, WETH_DECIMALS);
+            WETH_DECIMALS
+        }
+        Err(e) => return Err(e.wrap_err(format!("не удалось получить decimals() токена {}", token))),
+    };
+    cache.lock().unwrap().insert(token.address(), decimals);
+    Ok(decimals)
+}
+
+/// Символы уже запрошенных токенов
+static TOKEN_SYMBOLS: OnceLock<Mutex<HashMap<Address, String>>> = OnceLock::new();
+
+/// Получает символ токена через `symbol()` ERC20 (с кэшем по адресу)
+/// 
+/// Поддерживает как `string`, так и устаревший `bytes32` (MKR и подобные).
+/// Если символ недоступен, возвращается сокращенный адрес токена. Кэшируется
+/// только ответ контракта: после сбоя транспорта символ запрашивается снова.
+pub async fn get_token_symbol(client: Arc<dyn ChainClient>, token: TokenId) -> String {
+    let cache = TOKEN_SYMBOLS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(symbol) = cache.lock().unwrap().get(&token.address()) {
+        return symbol.clone();
+    }
+
+    let symbol = match client.get_symbol(token.address()).await {
+        Ok(symbol) => symbol,
+        Err(e) => {
+            log!("Предупреждение: symbol() токена {:?} недоступен ({})", token.address(), e);
+            if !is_contract_answer(&e) {
+                return short_address(token.address());
+            }
+            None
+        }
+    }
+    .unwrap_or_else(|| short_address(token.address()));
+    cache.lock().unwrap().insert(token.address(), symbol.clone());
+    symbol
+}
+
+/// Ответил ли контракт: revert или ответ, который не декодируется
+///
+/// Такой результат повторится при следующем вызове, его можно кэшировать.
+/// Сбой транспорта (соединение, таймаут, HTTP 5xx) и ограничение частоты
+/// узлом - нет; неизвестные ошибки тоже считаются сбоем.
+pub fn is_contract_answer(error: &eyre::Report) -> bool {
+    let transport = match error.downcast_ref::<alloy::contract::Error>() {
+        Some(alloy::contract::Error::TransportError(transport)) => transport,
+        Some(_) => return true,
+        None => match error.downcast_ref::<RpcError<TransportErrorKind>>() {
+            Some(transport) => transport,
+            None => return false,
+        },
+    };
+    matches!(transport, RpcError::ErrorResp(payload) if !payload.is_retry_err())
+}
+
+/// Декодирует ответ `symbol()`: ABI `string` или `bytes32` с нулями в конце
+/// 
+/// # Returns
+/// Символ или None, если ответ пустой или не является текстом
+pub fn decode_symbol(data: &[u8]) -> Option<String> {
+    if let Ok(symbol) = String::abi_decode(data, true) {
+        return (!symbol.is_empty()).then_some(symbol);
+    }
+    if data.len() != 32 {
+        return None;
+    }
+    let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+    let symbol = std::str::from_utf8(&data[..end]).ok()?;
+    (!symbol.is_empty()).then(|| symbol.to_string())
+}
+
+/// Сокращенный адрес вида "0x2791…4174" для токенов без символа
+pub fn short_address(address: Address) -> String {
+    let hex = alloy::hex::encode(address);
+    format!("0x{}…{}", &hex[..4], &hex[hex.len() - 4..])
+}
+
+/// Повторов вызова после временной ошибки RPC по умолчанию
+pub const DEFAULT_RPC_MAX_RETRIES: u32 = 3;
+/// Пауза перед первым повтором
+pub const DEFAULT_RPC_BASE_DELAY: Duration = Duration::from_millis(250);
+/// Предел удваивающейся паузы
+pub const DEFAULT_RPC_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Политика повторов вызовов контрактов (`getReserves`, `getPair`)
+///
+/// Публичные RPC Polygon регулярно отвечают 502 или обрывают соединение по
+/// таймауту; такие ошибки повторяются с экспоненциальной паузой и разбросом.
+/// Revert и другие ответы узла не повторяются: повтор дал бы тот же результат.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderConfig {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_requests_per_sec: Option<u32>, // Предел запросов в секунду на процесс (None - без предела)
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        ProviderConfig {
+            max_retries: DEFAULT_RPC_MAX_RETRIES,
+            base_delay: DEFAULT_RPC_BASE_DELAY,
+            max_delay: DEFAULT_RPC_MAX_DELAY,
+            max_requests_per_sec: None,
+        }
+    }
+}
+
+impl ProviderConfig {
+    /// Политика из переменных окружения `RPC_MAX_RETRIES`, `RPC_BASE_DELAY_MS`,
+    /// `RPC_MAX_DELAY_MS`, `RPC_MAX_RPS`; отсутствующие или некорректные - по
+    /// умолчанию, `RPC_MAX_RPS=0` - без предела
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().and_then(|value| value.trim().parse::<u64>().ok());
+        let defaults = ProviderConfig::default();
+        ProviderConfig {
+            max_retries: var("RPC_MAX_RETRIES").map_or(defaults.max_retries, |value| value.min(u64::from(u32::MAX)) as u32),
+            base_delay: var("RPC_BASE_DELAY_MS").map_or(defaults.base_delay, Duration::from_millis),
+            max_delay: var("RPC_MAX_DELAY_MS").map_or(defaults.max_delay, Duration::from_millis),
+            max_requests_per_sec: var("RPC_MAX_RPS")
+                .map(|value| value.min(u64::from(u32::MAX)) as u32)
+                .filter(|&rps| rps > 0)
+                .or(defaults.max_requests_per_sec),
+        }
+    }
+
+    /// Пауза перед повтором номер `attempt` (с нуля)
+    ///
+    /// `base * 2^attempt`, не больше `max_delay`, со случайной половиной:
+    /// результат в `[d / 2, d]`, чтобы параллельные запросы не повторялись
+    /// одновременно. `random` - источник разброса.
+    pub fn backoff(&self, attempt: u32, random: u64) -> Duration {
+        let delay = self.base_delay.saturating_mul(1u32 << attempt.min(16)).min(self.max_delay);
+        let half = delay / 2;
+        let spread = (delay - half).as_nanos() as u64;
+        half + Duration::from_nanos(random % spread.saturating_add(1))
+    }
+}
+
+/// Политика повторов процесса (см. `set_provider_config`)
+static PROVIDER_CONFIG: OnceLock<ProviderConfig> = OnceLock::new();
+
+/// Общий предел частоты запросов процесса (см. `set_provider_config`)
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Задает политику повторов и предел частоты для всех вызовов провайдера;
+/// действует только первый вызов
+///
+/// С `max_requests_per_sec` запускает пополнение `RateLimiter`, поэтому
+/// вызывается внутри runtime tokio и до `create_provider`.
+pub fn set_provider_config(config: ProviderConfig) {
+    if PROVIDER_CONFIG.set(config).is_ok() {
+        if let Some(requests_per_sec) = config.max_requests_per_sec {
+            let _ = RATE_LIMITER.set(RateLimiter::new(requests_per_sec));
+        }
+    }
+}
+
+/// Текущая политика повторов (по умолчанию, если не задана)
+pub fn provider_config() -> ProviderConfig {
+    PROVIDER_CONFIG.get().copied().unwrap_or_default()
+}
+
+/// Token bucket для исходящих запросов, общий для параллельных задач
+///
+/// Емкость - `requests_per_sec` запросов (допустимый всплеск за секунду),
+/// фоновая задача добавляет по одному разрешению каждые `1 / requests_per_sec`
+/// секунды, пока ведро не полно. Запрос забирает разрешение семафора, поэтому
+/// задачи discovery и солвера делят один бюджет, а не спят каждая отдельно.
+/// Пополнение останавливается, когда удалена последняя копия ограничителя.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    permits: Arc<Semaphore>,
+}
+
+impl RateLimiter {
+    /// Ограничитель на `requests_per_sec` запросов в секунду (не меньше 1); вызывается внутри runtime tokio
+    pub fn new(requests_per_sec: u32) -> Self {
+        let requests_per_sec = requests_per_sec.max(1);
+        let capacity = requests_per_sec as usize;
+        let permits = Arc::new(Semaphore::new(capacity));
+        let refill = Arc::downgrade(&permits);
+        // Выше 10^9 запросов в секунду период округлился бы до нуля, а interval с нулевым периодом паникует
+        let period = (Duration::from_secs(1) / requests_per_sec).max(Duration::from_nanos(1));
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        tokio::spawn(async move {
+            // Первый тик interval наступает сразу: ведро и так полно
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(permits) = refill.upgrade() else {
+                    break;
+                };
+                if permits.available_permits() < capacity {
+                    permits.add_permits(1);
+                }
+            }
+        });
+        RateLimiter { permits }
+    }
+
+    /// Ждет разрешения на один запрос
+    pub async fn acquire(&self) {
+        if let Ok(permit) = self.permits.acquire().await {
+            permit.forget();
+        }
+    }
+}
+
+/// Временная ли ошибка вызова: сбой транспорта (соединение, таймаут),
+/// HTTP 5xx, 408 или 429 (узел ограничил частоту). Revert и ошибки декодирования ответа - нет
+pub fn is_transient(error: &alloy::contract::Error) -> bool {
+    match error {
+        alloy::contract::Error::TransportError(RpcError::Transport(kind)) => match kind {
+            TransportErrorKind::HttpError(http) => http.status >= 500 || http.status == 408 || http.status == 429,
+            TransportErrorKind::Custom(_) | TransportErrorKind::BackendGone | TransportErrorKind::MissingBatchResponse(_) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Слой транспорта, через который каждый запрос JSON-RPC ждет разрешения `RateLimiter`
+///
+/// Стоит под провайдером, поэтому предел действует на все запросы
+/// `RpcProvider`: вызовы контрактов и их повторы, `eth_getCode`,
+/// `eth_blockNumber`, логи, котировки QuoterV2 и подписки.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitLayer {
+    limiter: Option<RateLimiter>, // None - без предела
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Option<RateLimiter>) -> Self {
+        RateLimitLayer { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner, limiter: self.limiter.clone() }
+    }
+}
+
+/// Транспорт `inner` с ожиданием разрешения перед каждым запросом (см. `RateLimitLayer`)
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: Option<RateLimiter>,
+}
+
+impl<S, Request> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Error = TransportError, Future = TransportFut<'static, <S as Service<Request>>::Response>> + Clone + Send + 'static,
+    Request: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = TransportError;
+    type Future = TransportFut<'static, S::Response>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // Готовый после poll_ready сервис уходит в запрос, на его место - копия
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            if let Some(limiter) = limiter {
+                limiter.acquire().await;
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+/// Выполняет вызов контракта с повторами временных ошибок по `config`
+///
+/// Каждый повтор пишется в лог как предупреждение; после `max_retries`
+/// повторов возвращается последняя ошибка. Предел частоты здесь не нужен:
+/// каждая попытка проходит через `RateLimitLayer` транспорта.
+pub async fn with_retry<T, F, Fut>(config: &ProviderConfig, what: &str, mut call: F) -> Result<T, alloy::contract::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, alloy::contract::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(error) if attempt < config.max_retries && is_transient(&error) => {
+                let delay = config.backoff(attempt, RandomState::new().hash_one(attempt));
+                attempt += 1;
+                log!("Предупреждение: {}: временная ошибка RPC ({}), повтор {}/{} через {} мс",
+                    what, error, attempt, config.max_retries, delay.as_millis());
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Провайдер RPC с транспортом, выбранным по схеме URL
+///
+/// Конкретный транспорт (HTTP, WebSocket или тестовый) скрыт за
+/// `BoxTransport`, поэтому пулы, солвер и запросы к контрактам не зависят от
+/// того, как подключен узел.
+pub type RpcProvider = RootProvider<BoxTransport>;
+
+/// Транспорт подключения к узлу
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcTransport {
+    Http,
+    Ws,
+}
+
+impl RpcTransport {
+    /// Транспорт по схеме URL: `http://`/`https://` или `ws://`/`wss://`
+    pub fn detect(rpc_url: &str) -> Option<Self> {
+        let (scheme, _) = rpc_url.split_once("://")?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "http" | "https" => Some(RpcTransport::Http),
+            "ws" | "wss" => Some(RpcTransport::Ws),
+            _ => None,
+        }
     }
 }
 
 /// Создает провайдер для подключения к сети Polygon через Infura
-pub async fn create_provider(rpc_url: &str) -> Result<Arc<RootProvider<Http<Client>>>> {
-    let provider = ProviderBuilder::new()
-        .on_http(rpc_url.parse()?);
+///
+/// `ws://` и `wss://` подключаются по WebSocket (меньшая задержка),
+/// `http://` и `https://` - по HTTP; другие схемы - ошибка. Запросы проходят
+/// через общий предел частоты процесса (`set_provider_config`). Из-за слоя
+/// транспорта подписки этим провайдером недоступны: `watch.rs` подписывается
+/// через отдельное соединение.
+pub async fn create_provider(rpc_url: &str) -> Result<Arc<RpcProvider>> {
+    create_provider_with_limiter(rpc_url, RATE_LIMITER.get().cloned()).await
+}
+
+/// `create_provider` с явным ограничителем частоты (`None` - без предела)
+pub async fn create_provider_with_limiter(rpc_url: &str, limiter: Option<RateLimiter>) -> Result<Arc<RpcProvider>> {
+    let client = ClientBuilder::default().layer(RateLimitLayer::new(limiter));
+    let provider = match RpcTransport::detect(rpc_url) {
+        Some(RpcTransport::Http) => ProviderBuilder::new().on_client(client.http(rpc_url.parse()?)).boxed(),
+        Some(RpcTransport::Ws) => ProviderBuilder::new().on_client(client.ws(WsConnect::new(rpc_url)).await?).boxed(),
+        None => return Err(eyre::eyre!("неподдерживаемая схема RPC URL {} (нужен http(s):// или ws(s)://)", rpc_url)),
+    };
     Ok(Arc::new(provider))
 }
 
-/// Получает резервы (reserve0, reserve1) из пула ликвидности
-/// Возвращает raw значения в наименьших единицах (без учета decimals)
+/// Получает состояние пула StableSwap: балансы монет, коэффициент A и комиссию
 /// 
 /// # Arguments
 /// * `provider` - Провайдер для подключения к блокчейну
 /// * `pool_address` - Адрес контракта пула
+/// * `n_coins` - Количество монет в пуле
+pub async fn get_stable_pool_state(
+    provider: Arc<RpcProvider>,
+    pool_address: Address,
+    n_coins: usize,
+) -> Result<(Vec<U256>, U256, U256)> {
+    let contract = ICurvePool::ICurvePoolInstance::new(pool_address, provider);
+
+    let mut balances = Vec::with_capacity(n_coins);
+    for index in 0..n_coins {
+        balances.push(contract.balances(U256::from(index)).call().await?._0);
+    }
+    let amp = contract.A().call().await?._0;
+    let fee = contract.fee().call().await?._0;
+
+    Ok((balances, amp, fee))
+}
+
+/// Получает балансы двух токенов взвешенного пула из Balancer Vault
 /// 
 /// # Returns
-/// Кортеж с резервами (reserve0, reserve1) в формате U256 в raw units
-pub async fn get_pool_reserves(
-    provider: Arc<RootProvider<Http<Client>>>,
-    pool_address: Address,
+/// Балансы (token0, token1) в raw units
+pub async fn get_weighted_pool_balances(
+    client: Arc<dyn ChainClient>,
+    pool_id: B256,
+    token0: TokenId,
+    token1: TokenId,
 ) -> Result<(U256, U256)> {
-    // Создаем экземпляр контракта по адресу
+    let (tokens, balances) = client.get_vault_pool_tokens(pool_id).await?;
+
+    let balance_of = |token: TokenId| {
+        tokens
+            .iter()
+            .position(|address| *address == token.address())
+            .and_then(|index| balances.get(index).copied())
+            .ok_or_else(|| eyre::eyre!("токен {} отсутствует в пуле Balancer {}", token, pool_id))
+    };
+
+    Ok((balance_of(token0)?, balance_of(token1)?))
+}
+
+/// Создает взвешенный пул Balancer: читает poolId, веса и комиссию пула
+/// 
+/// Балансы из Vault не запрашиваются: их загружает `refresh_all_reserves`.
+/// 
+/// # Returns
+/// Pool или None, если пул не содержит одного из токенов
+pub async fn create_weighted_pool(
+    client: Arc<dyn ChainClient>,
+    dex: &DexConfig,
+    pool_address: Address,
+    token_in: TokenId,
+    token_out: TokenId,
+) -> Result<Option<Pool>> {
+    let WeightedPoolParams { pool_id, weights, swap_fee } = client.get_weighted_pool(pool_address).await?;
+    let (tokens, _) = client.get_vault_pool_tokens(pool_id).await?;
+    let weight_of = |token: TokenId| {
+        tokens
+            .iter()
+            .position(|address| *address == token.address())
+            .and_then(|index| weights.get(index).copied())
+    };
+    let (Some(weight_in), Some(weight_out)) = (weight_of(token_in), weight_of(token_out)) else {
+        return Ok(None);
+    };
+
+    let name = pool_name(client.clone(), dex, token_in, token_out).await;
+    let mut pool = Pool::new(pool_address, token_in, token_out, dex.id, client, name)
+        .into_weighted(pool_id, [(token_in, weight_in), (token_out, weight_out)], swap_fee);
+    pool.fetch_decimals().await?;
+    Ok(Some(pool))
+}
+
+/// Читает накопительные цены пары Uniswap V2 и экстраполирует их
+/// на время последнего блока (см. `twap::PriceObservation::current`)
+/// 
+/// # Arguments
+/// * `provider` - Провайдер для подключения к блокчейну
+/// * `pool_address` - Адрес контракта пула
+pub async fn get_price_observation(
+    provider: Arc<RpcProvider>,
+    pool_address: Address,
+) -> Result<PriceObservation> {
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Latest, BlockTransactionsKind::Hashes)
+        .await?
+        .ok_or_else(|| eyre::eyre!("последний блок не найден"))?;
+    let block_id = BlockId::number(block.header.number);
+    // Время блока по модулю 2^32, как в контракте пары
+    let now = block.header.timestamp as u32;
+
     let contract = IUniswapV2Pair::IUniswapV2PairInstance::new(pool_address, provider);
-    
-    // Вызываем функцию getReserves
-    println!("Отправляем запрос к контракту по адресу: {:?}", pool_address);
-    let reserves = contract.getReserves().call().await?;
-    
-    // Конвертируем uint112 в U256 для большей совместимости
-    let reserve0 = U256::from(reserves.reserve0);
-    let reserve1 = U256::from(reserves.reserve1);
-    
-    println!("Получены резервы: reserve0={}, reserve1={}", reserve0, reserve1);
-    
-    Ok((reserve0, reserve1))
+    let reserves = contract.getReserves().block(block_id).call().await?;
+    let price0_cumulative = contract.price0CumulativeLast().block(block_id).call().await?._0;
+    let price1_cumulative = contract.price1CumulativeLast().block(block_id).call().await?._0;
+
+    Ok(PriceObservation::current(
+        price0_cumulative,
+        price1_cumulative,
+        U256::from(reserves.reserve0),
+        U256::from(reserves.reserve1),
+        reserves.blockTimestampLast,
+        now,
+    ))
+}
+
+/// Проверяет, включен ли protocol fee у Factory (ненулевой `feeTo`)
+/// 
+/// Ошибка запроса не прерывает discovery: выводится предупреждение
+/// и считается, что protocol fee выключен.
+pub async fn get_protocol_fee_enabled(
+    client: Arc<dyn ChainClient>,
+    factory_address: Address,
+) -> bool {
+    match client.get_fee_to(factory_address).await {
+        Ok(fee_to) => fee_to != Address::ZERO,
+        Err(e) => {
+            log!("  Предупреждение: не удалось прочитать feeTo у {:?}: {}", factory_address, e);
+            false
+        }
+    }
 }
 
 /// Определяет порядок токенов в пуле (token0 < token1 по адресу)
 /// и возвращает резервы в правильном порядке для USDC/WETH пары
 /// 
 /// # Arguments
-/// * `provider` - Провайдер для подключения к блокчейну
+/// * `client` - Доступ к блокчейну (`RpcProvider` или мок в тестах)
 /// * `pool_address` - Адрес контракта пула
-/// * `usdc_address` - Адрес токена USDC
-/// * `weth_address` - Адрес токена WETH
+/// * `usdc` - Токен USDC
+/// * `weth` - Токен WETH
 /// 
 /// # Returns
 /// Кортеж (usdc_reserve_raw, weth_reserve_raw) в правильном порядке в raw units
 pub async fn get_usdc_weth_reserves(
-    provider: Arc<RootProvider<Http<Client>>>,
+    client: Arc<dyn ChainClient>,
     pool_address: Address,
-    usdc_address: Address,
-    weth_address: Address,
+    usdc: TokenId,
+    weth: TokenId,
 ) -> Result<(U256, U256)> {
-    let (reserve0, reserve1) = get_pool_reserves(provider, pool_address).await?;
+    let (reserve0, reserve1, _) = client.get_reserves(pool_address).await?;
     
     // В Uniswap V2 token0 < token1 по лексикографическому порядку адресов
-    let (usdc_reserve_raw, weth_reserve_raw) = if usdc_address < weth_address {
+    let (usdc_reserve_raw, weth_reserve_raw) = if usdc < weth {
         // USDC = token0, WETH = token1
         (reserve0, reserve1)
     } else {
@@ -88,180 +690,763 @@ pub async fn get_usdc_weth_reserves(
     };
     
     // Логируем человекочитаемые значения для проверки
-    let usdc_decimal = usdc_to_decimal(usdc_reserve_raw);
-    let weth_decimal = weth_to_decimal(weth_reserve_raw);
-    println!("Резервы пула (decimal): USDC={:.2}, WETH={:.6}", usdc_decimal, weth_decimal);
+    let usdc_decimals = get_token_decimals(client.clone(), usdc).await?;
+    let weth_decimals = get_token_decimals(client, weth).await?;
+    log!("Резервы пула (decimal): USDC={}, WETH={}",
+        format_units(usdc_reserve_raw, usdc_decimals), format_units(weth_reserve_raw, weth_decimals));
     
     Ok((usdc_reserve_raw, weth_reserve_raw))
 }
 
 /// Создает Pool объект через Factory контракт
 /// 
+/// Если у DEX задан `pair_init_code_hash`, адрес пары вычисляется локально
+/// (`pair_for`) без вызова `getPair`. Такой адрес есть и у несуществующей
+/// пары: существование проверяется последующим чтением резервов
+/// (`get_all_pool_addresses` отбрасывает пары без контракта или резервов).
+/// 
 /// # Arguments
-/// * `provider` - Провайдер для подключения к блокчейну
+/// * `client` - Доступ к блокчейну (`RpcProvider` или мок в тестах)
+/// * `dex` - Конфигурация DEX
 /// * `factory_address` - Адрес Factory контракта
-/// * `token_a` - Адрес первого токена
-/// * `token_b` - Адрес второго токена
+/// * `token_in` - Входной токен
+/// * `token_out` - Выходной токен
 /// 
 /// # Returns
-/// Pool объект или None если пул не существует
+/// Pool объект без резервов (см. `refresh_all_reserves`) или None, если пул не существует
 pub async fn create_pool_from_factory(
-    provider: Arc<RootProvider<Http<Client>>>,
+    client: Arc<dyn ChainClient>,
+    dex: &DexConfig,
     factory_address: Address,
-    token_a: Address,
-    token_b: Address,
-) -> Result<Option<crate::pool::Pool>> {
-    use crate::pool::Pool;
-    
-    // Создаем экземпляр Factory контракта
-    let factory = IUniswapV2Factory::IUniswapV2FactoryInstance::new(factory_address, provider.clone());
-    
-    println!("Запрашиваем пул через Factory: {:?}", factory_address);
-    println!("  Токены: {:?} / {:?}", token_a, token_b);
-    
-    // Вызываем функцию getPair
-    let pair_address = factory.getPair(token_a, token_b).call().await?;
+    token_in: TokenId,
+    token_out: TokenId,
+) -> Result<Option<Pool>> {
+    let pair_address = match dex.pair_init_code_hash {
+        Some(init_code_hash) => {
+            let pair_address = pair_for(factory_address, init_code_hash, token_in, token_out);
+            log!("Адрес пары {}/{} вычислен через CREATE2: {:?}", token_in, token_out, pair_address);
+            pair_address
+        }
+        None => {
+            log!("Запрашиваем пул через Factory: {:?}", factory_address);
+            log!("  Токены: {:?} / {:?}", token_in.address(), token_out.address());
+
+            // Вызываем функцию getPair (у RpcProvider временные ошибки RPC повторяются)
+            client.get_pair(factory_address, token_in.address(), token_out.address()).await?
+        }
+    };
     
     // Проверяем, что адрес не нулевой (пул существует)
-    if pair_address.pair == Address::ZERO {
-        println!("  Пул не найден");
+    if pair_address == Address::ZERO {
+        log!("  Пул не найден");
         Ok(None)
     } else {
-        println!("  Найден адрес пула: {:?}", pair_address.pair);
-        
-        // Определяем имя DEX на основе Factory адреса
-        let dex_name = match factory_address {
-            addr if addr == crate::config::QUICKSWAP_V2_FACTORY => "Quickswap",
-            addr if addr == crate::config::SUSHISWAP_V2_FACTORY => {
-                if token_a == crate::config::USDC_ADDRESS || token_b == crate::config::USDC_ADDRESS {
-                    "Sushiswap"
-                } else {
-                    "Sushiswap USDC.e"
-                }
-            },
-            _ => "Unknown DEX"
-        };
-        
-        let pool_name = format!("{} USDC/WETH", dex_name);
+        log!("  Найден адрес пула: {:?}", pair_address);
         
-        // Создаем Pool объект с резервами
-        match Pool::with_reserves(
-            pair_address.pair,
-            token_a,
-            token_b,
-            provider,
-            pool_name,
+        // Создаем Pool объект; резервы загружаются для всех пулов сразу
+        match Pool::with_decimals(
+            pair_address,
+            token_in,
+            token_out,
+            dex.id,
+            client.clone(),
+            pool_name(client.clone(), dex, token_in, token_out).await,
         ).await {
             Ok(pool) => {
-                println!("  Pool объект создан успешно");
+                let mut pool = pool.with_fee_bps(dex.fee_bps);
+                pool.protocol_fee_enabled = get_protocol_fee_enabled(client.clone(), factory_address).await;
+                log!("  Pool объект создан успешно");
                 Ok(Some(pool))
             }
             Err(e) => {
-                println!("  Ошибка создания Pool объекта: {}", e);
+                log!("  Ошибка создания Pool объекта: {}", e);
                 Err(e)
             }
         }
     }
 }
 
-/// Получает все пулы USDC/WETH через Factory контракты
+/// Формирует имя пула вида "Sushiswap USDC.e/WETH" из on-chain символов токенов
+/// 
+/// Одинаковые символы у разных токенов пары - признак подозрительного пула:
+/// выводится предупреждение, а имя дополняется адресами (см. `pool_label`).
+async fn pool_name(
+    client: Arc<dyn ChainClient>,
+    dex: &DexConfig,
+    token_in: TokenId,
+    token_out: TokenId,
+) -> String {
+    let symbol_in = get_token_symbol(client.clone(), token_in).await;
+    let symbol_out = get_token_symbol(client, token_out).await;
+    if symbols_collide(&symbol_in, &symbol_out) {
+        log!("Предупреждение: токены {:?} и {:?} ({}) сообщают одинаковый символ {}",
+            token_in.address(), token_out.address(), dex.id, symbol_in);
+    }
+    pool_label(dex.id, (token_in, &symbol_in), (token_out, &symbol_out))
+}
+
+/// Имя пула "{dex} {symbol_in}/{symbol_out}" для вывода
+/// 
+/// Совпадающие символы дополняются сокращенными адресами токенов:
+/// "Quickswap USDC(0x3c49…3359)/USDC(0x2791…4174)". Имя только для
+/// отображения: пулы и токены везде идентифицируются адресами.
+pub fn pool_label(dex: DexId, (token_in, symbol_in): (TokenId, &str), (token_out, symbol_out): (TokenId, &str)) -> String {
+    if symbols_collide(symbol_in, symbol_out) {
+        format!("{} {}({})/{}({})", dex, symbol_in, short_address(token_in.address()),
+            symbol_out, short_address(token_out.address()))
+    } else {
+        format!("{} {}/{}", dex, symbol_in, symbol_out)
+    }
+}
+
+/// Символы неразличимы для пользователя (без учета регистра и пробелов по краям)
+fn symbols_collide(symbol_a: &str, symbol_b: &str) -> bool {
+    symbol_a.trim().eq_ignore_ascii_case(symbol_b.trim())
+}
+
+/// Добавляет найденный пул в реестр, сверяя пару с контрактом, если это включено в профиле
+async fn insert_pool(pools: &mut PoolRegistry, pool: Pool, ctx: &ConfigContext) {
+    if ctx.verify_pairs {
+        pools.insert_verified(pool).await;
+    } else {
+        pools.insert(pool);
+    }
+}
+
+/// Получает все пулы через DEX из профиля конфигурации
 /// 
 /// # Arguments
-/// * `provider` - Провайдер для подключения к блокчейну
-/// * `usdc_address` - Адрес токена USDC
-/// * `weth_address` - Адрес токена WETH
+/// * `client` - Доступ к блокчейну (`RpcProvider` или мок в тестах)
+/// * `ctx` - Профиль конфигурации (DEX, входные и выходной токены)
 /// 
 /// # Returns
-/// Вектор найденных пулов Pool со всеми данными
+/// Реестр найденных пулов Pool со всеми данными, без повторяющихся адресов
 pub async fn get_all_pool_addresses(
-    provider: Arc<RootProvider<Http<Client>>>,
-    usdc_address: Address,
-    weth_address: Address,
-) -> Result<Vec<crate::pool::Pool>> {
-    use crate::config::{QUICKSWAP_V2_FACTORY, SUSHISWAP_V2_FACTORY, UNISWAP_V2_POOL_ADDRESS};
-    
-    let mut pools = Vec::new();
+    client: Arc<dyn ChainClient>,
+    ctx: &ConfigContext,
+) -> Result<PoolRegistry> {
+    // Один адрес может прийти из нескольких путей discovery - PoolRegistry оставляет одну копию
+    let mut pools = PoolRegistry::new();
+    // Адреса пар, вычисленные через CREATE2: их существование еще не проверено
+    let mut computed: Vec<Address> = Vec::new();
     
-    // Создаем статический пул Uniswap V2
-    match crate::pool::Pool::with_reserves(
-        UNISWAP_V2_POOL_ADDRESS,
-        usdc_address,
-        weth_address,
-        provider.clone(),
-        "Uniswap V2 USDC/WETH".to_string(),
-    ).await {
-        Ok(uniswap_pool) => {
-            println!("Uniswap V2 Pool создан (статический адрес)");
-            pools.push(uniswap_pool);
-        }
-        Err(e) => {
-            println!("Ошибка создания Uniswap V2 Pool: {}", e);
+    for dex in &ctx.dexes {
+        // Эквивалентные выходные токены ищутся только через Factory:
+        // статический и взвешенный пулы заданы адресом для основной пары
+        let output_tokens = match dex.source {
+            DexSource::Factory(_) => ctx.output_tokens(),
+            _ => vec![ctx.output_token],
+        };
+        for &token_in in &dex.input_tokens {
+            for &token_out in &output_tokens {
+                // Пара из одного токена не бывает настоящим пулом
+                if token_in == token_out {
+                    log!("{}: пропускаем пару {}/{} - входной и выходной токен совпадают", dex.id, token_in, token_out);
+                    continue;
+                }
+                let name = pool_name(client.clone(), dex, token_in, token_out).await;
+                match dex.source {
+                    // Создаем статический пул
+                    DexSource::StaticPool(pool_address) => {
+                        match Pool::with_decimals(
+                            pool_address,
+                            token_in,
+                            ctx.output_token,
+                            dex.id,
+                            client.clone(),
+                            name.clone(),
+                        ).await {
+                            Ok(pool) => {
+                                let mut pool = pool.with_fee_bps(dex.fee_bps);
+                                // Factory статического пула берется из самой пары
+                                match client.get_pair_factory(pool_address).await {
+                                    Ok(factory) => {
+                                        pool.protocol_fee_enabled = get_protocol_fee_enabled(client.clone(), factory).await;
+                                    }
+                                    Err(e) => log!("  Предупреждение: не удалось прочитать factory у {}: {}", name, e),
+                                }
+                                log!("{} Pool создан (статический адрес)", name);
+                                insert_pool(&mut pools, pool, ctx).await;
+                            }
+                            Err(e) => {
+                                log!("Ошибка создания {} Pool: {}", name, e);
+                            }
+                        }
+                    }
+                    // Взвешенный пул Balancer: балансы берутся из Vault
+                    DexSource::WeightedPool(pool_address) => {
+                        match create_weighted_pool(client.clone(), dex, pool_address, token_in, ctx.output_token).await {
+                            Ok(Some(pool)) => {
+                                log!("{} Pool создан (Balancer weighted)", name);
+                                insert_pool(&mut pools, pool, ctx).await;
+                            }
+                            Ok(None) => {
+                                log!("{}: пул не содержит {}/{}", dex.id, token_in, ctx.output_token);
+                            }
+                            Err(e) => {
+                                log!("Ошибка создания {} Pool: {}", name, e);
+                            }
+                        }
+                    }
+                    // Запрашиваем пул через Factory
+                    DexSource::Factory(factory_address) => {
+                        match create_pool_from_factory(
+                            client.clone(),
+                            dex,
+                            factory_address,
+                            token_in,
+                            token_out,
+                        ).await {
+                            Ok(Some(pool)) => {
+                                log!("{} Pool получен через Factory", name);
+                                if dex.pair_init_code_hash.is_some() {
+                                    // Адрес CREATE2 выведен из самих токенов: сверять token0/token1 незачем
+                                    computed.push(pool.pool_address);
+                                    pools.insert(pool);
+                                } else {
+                                    insert_pool(&mut pools, pool, ctx).await;
+                                }
+                            }
+                            Ok(None) => {
+                                log!("{}: пул {}/{} не найден", dex.id, token_in, token_out);
+                            }
+                            Err(e) => {
+                                log!("Ошибка получения {} Pool: {}", name, e);
+                            }
+                        }
+                    }
+                }
+            }
         }
     }
     
-    // Проверяем Quickswap
-    match create_pool_from_factory(
-        provider.clone(),
-        QUICKSWAP_V2_FACTORY,
-        usdc_address,
-        weth_address,
-    ).await {
-        Ok(Some(quickswap_pool)) => {
-            println!("Quickswap Pool получен через Factory");
-            pools.push(quickswap_pool);
+    // Резервы всех пулов одним multicall вместо запроса на каждый пул.
+    // Для адресов, вычисленных через CREATE2, это же и проверка существования пары
+    let failures = refresh_all_reserves(client, pools.pools_mut()).await;
+    for (address, e) in &failures {
+        // Revert или пустой ответ - ответ самого адреса; сбой RPC о существовании пары ничего не говорит
+        if computed.contains(address) && is_contract_answer(e) {
+            log!("Пара {:?} не найдена (нет контракта по адресу CREATE2): {}", address, e);
+        } else if computed.contains(address) {
+            log!("Не удалось проверить пару {:?} по адресу CREATE2 (сбой RPC), пул пропущен: {}", address, e);
+        } else {
+            log!("Не удалось получить резервы пула {:?}, пул пропущен: {}", address, e);
         }
-        Ok(None) => {
-            println!("Quickswap: пул USDC/WETH не найден");
+    }
+    pools.retain(|pool| failures.iter().all(|(address, _)| *address != pool.pool_address));
+    pools.retain(|pool| {
+        let missing = computed.contains(&pool.pool_address) && pool.reserve_token0.is_zero() && pool.reserve_token1.is_zero();
+        if missing {
+            log!("Пара {} ({:?}) не найдена: нет резервов", pool.name, pool.pool_address);
         }
-        Err(e) => {
-            println!("Ошибка получения Quickswap Pool: {}", e);
+        !missing
+    });
+
+    log!("Создано {} Pool объектов через Factory контракты", pools.len());
+    
+    Ok(pools)
+}
+
+/// Загружает пул по адресу пары (`--extra-pool`) и проверяет, что он подходит профилю
+/// 
+/// Пул должен содержать один из входных токенов профиля и выходной токен
+/// (или эквивалентный ему). Если `factory()` пары совпадает с Factory DEX
+/// из профиля, пул получает его имя DEX, комиссию и признак protocol fee.
+pub async fn load_extra_pool(
+    client: Arc<dyn ChainClient>,
+    pool_address: Address,
+    ctx: &ConfigContext,
+) -> Result<Pool> {
+    let mut pool = Pool::from_address(client.clone(), pool_address, None).await?;
+    let has_input = ctx.input_tokens.iter().any(|&token| pool.other_token(token).is_some());
+    let has_output = ctx.output_tokens().iter().any(|&token| pool.other_token(token).is_some());
+    if !has_input || !has_output {
+        eyre::bail!("пул {} ({:?}) торгует {}/{}: нужны входной токен профиля и {}",
+            pool.name, pool_address, pool.token0, pool.token1, ctx.output_token);
+    }
+
+    let Ok(factory) = client.get_pair_factory(pool_address).await else {
+        return Ok(pool);
+    };
+    if let Some(dex) = ctx.dexes.iter().find(|dex| dex.source == DexSource::Factory(factory)) {
+        pool = pool.with_fee_bps(dex.fee_bps);
+        pool.dex = dex.id;
+        let symbol0 = get_token_symbol(client.clone(), pool.token0).await;
+        let symbol1 = get_token_symbol(client.clone(), pool.token1).await;
+        pool.name = pool_label(dex.id, (pool.token0, &symbol0), (pool.token1, &symbol1));
+    }
+    pool.protocol_fee_enabled = get_protocol_fee_enabled(client, factory).await;
+    Ok(pool)
+}
+
+/// Находит пулы Uniswap V3 входных токенов профиля с выходным токеном
+/// 
+/// Перебираются все уровни комиссии `UNISWAP_V3_FEE_TIERS`. Пулы без
+/// ликвидности в текущем диапазоне пропускаются. Котировки QuoterV2 здесь
+/// не запрашиваются: их загружает `V3Pool::load_quotes` под сумму обмена.
+/// Результаты `getPool` берутся из `cache` и записываются в него; перед
+/// вызовом нужно начать запуск кэша (`DiscoveryCache::begin_run`).
+/// 
+/// # Arguments
+/// * `provider` - Провайдер для подключения к блокчейну
+/// * `ctx` - Профиль конфигурации (входные и выходной токены)
+/// * `factory` - Адрес Factory Uniswap V3
+/// * `quoter` - Адрес QuoterV2
+/// * `cache` - Кэш результатов discovery
+/// * `block` - Текущий блок для срока отрицательных записей
+pub async fn discover_v3_pools(
+    provider: Arc<RpcProvider>,
+    ctx: &ConfigContext,
+    factory: Address,
+    quoter: Address,
+    cache: &mut DiscoveryCache,
+    block: u64,
+) -> Vec<V3Pool> {
+    let factory_contract = IUniswapV3Factory::IUniswapV3FactoryInstance::new(factory, provider.clone());
+    let mut pools = Vec::new();
+
+    for &token_in in &ctx.input_tokens {
+        for fee in UNISWAP_V3_FEE_TIERS {
+            let key = ProbeKey {
+                dex: DexId::UNISWAP_V3.0.to_string(),
+                token_a: token_in.address(),
+                token_b: ctx.output_token.address(),
+                fee,
+            };
+            let cached = cache.lookup(&key, block);
+            let pool_address = match cached {
+                Some(Some(pool_address)) => pool_address,
+                Some(None) => continue,
+                None => match factory_contract
+                    .getPool(token_in.address(), ctx.output_token.address(), U24::from(fee))
+                    .call()
+                    .await
+                {
+                    Ok(result) if result.pool != Address::ZERO => {
+                        cache.record(key, Some(result.pool), block);
+                        result.pool
+                    }
+                    Ok(_) => {
+                        log!("{}: пул {}/{} с комиссией {} не найден", DexId::UNISWAP_V3, token_in, ctx.output_token, fee);
+                        cache.record(key, None, block);
+                        continue;
+                    }
+                    Err(e) => {
+                        log!("Ошибка запроса пула {} ({}): {}", DexId::UNISWAP_V3, fee, e);
+                        continue;
+                    }
+                },
+            };
+
+            let contract = IUniswapV3Pool::IUniswapV3PoolInstance::new(pool_address, provider.clone());
+            let (slot0, liquidity) = match (contract.slot0().call().await, contract.liquidity().call().await) {
+                (Ok(slot0), Ok(liquidity)) => (slot0, liquidity._0),
+                (Err(e), _) | (_, Err(e)) => {
+                    log!("Ошибка чтения состояния пула {:?}: {}", pool_address, e);
+                    continue;
+                }
+            };
+            if liquidity == 0 {
+                log!("{}: пул {:?} без ликвидности в текущем диапазоне", DexId::UNISWAP_V3, pool_address);
+                continue;
+            }
+
+            let symbol_in = get_token_symbol(provider.clone(), token_in).await;
+            let symbol_out = get_token_symbol(provider.clone(), ctx.output_token).await;
+            let label = pool_label(DexId::UNISWAP_V3, (token_in, &symbol_in), (ctx.output_token, &symbol_out));
+            let mut pool = V3Pool::new(pool_address, token_in, ctx.output_token, fee, provider.clone(), quoter, String::new());
+            pool.name = format!("{} {}", label, pool.fee_label());
+            let client: Arc<dyn ChainClient> = provider.clone();
+            match (get_token_decimals(client.clone(), pool.token0).await, get_token_decimals(client, pool.token1).await) {
+                (Ok(decimals0), Ok(decimals1)) => (pool.token0_decimals, pool.token1_decimals) = (decimals0, decimals1),
+                (Err(e), _) | (_, Err(e)) => {
+                    log!("Ошибка чтения decimals пула {:?}: {}", pool_address, e);
+                    continue;
+                }
+            }
+            pool.sqrt_price_x96 = U256::from(slot0.sqrtPriceX96);
+            pool.liquidity = liquidity;
+            log!("{} Pool получен через Factory V3", pool.name);
+            pools.push(pool);
         }
     }
-    
-    // Проверяем Sushiswap
-    match create_pool_from_factory(
-        provider.clone(),
-        SUSHISWAP_V2_FACTORY,
-        usdc_address,
-        weth_address,
-    ).await {
-        Ok(Some(sushiswap_pool)) => {
-            println!("Sushiswap Pool получен через Factory");
-            pools.push(sushiswap_pool);
+    pools
+}
+
+/// Котирует exact-input свап в пуле Uniswap V3 через `QuoterV2.quoteExactInputSingle`
+/// 
+/// QuoterV2 не view-функция: она выполняет свап и откатывает его, поэтому
+/// вызывается только через eth_call и стоит заметно дороже чтения резервов.
+pub async fn quote_v3_exact_input(
+    provider: Arc<RpcProvider>,
+    quoter: Address,
+    token_in: TokenId,
+    token_out: TokenId,
+    fee: u32,
+    amount_in: U256,
+) -> Result<U256> {
+    let contract = IQuoterV2::IQuoterV2Instance::new(quoter, provider);
+    let params = IQuoterV2::QuoteExactInputSingleParams {
+        tokenIn: token_in.address(),
+        tokenOut: token_out.address(),
+        amountIn: amount_in,
+        fee: U24::from(fee),
+        sqrtPriceLimitX96: U160::ZERO,
+    };
+    let quote = contract.quoteExactInputSingle(params).call().await?;
+    Ok(quote.amountOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, SUSHISWAP_V2_FACTORY, UNISWAP_V2_POOL_ADDRESS};
+    use crate::mock_chain::{MockCall, MockChainClient};
+    use alloy::sol_types::SolCall;
+    use crate::mock_rpc::{call_selector, call_target, encode_address, encode_word, MockRpc, BAD_GATEWAY};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Символ в формате bytes32, как у MKR: текст, дополненный нулями справа
+    fn bytes32_symbol(symbol: &str) -> Vec<u8> {
+        let mut word = [0u8; 32];
+        word[..symbol.len()].copy_from_slice(symbol.as_bytes());
+        word.to_vec()
+    }
+
+    #[test]
+    fn decodes_string_and_bytes32_symbols() {
+        assert_eq!(decode_symbol(&"USDC.e".to_string().abi_encode()), Some("USDC.e".to_string()));
+        assert_eq!(decode_symbol(&bytes32_symbol("MKR")), Some("MKR".to_string()));
+        // Все 32 байта заняты текстом
+        let full = "A".repeat(32);
+        assert_eq!(decode_symbol(&bytes32_symbol(&full)), Some(full));
+    }
+
+    #[test]
+    fn rejects_empty_and_non_text_symbols() {
+        assert_eq!(decode_symbol(&[]), None);
+        assert_eq!(decode_symbol(&[0u8; 32]), None);
+        assert_eq!(decode_symbol(&String::new().abi_encode()), None);
+        let mut invalid_utf8 = bytes32_symbol("M");
+        invalid_utf8[1] = 0xff;
+        assert_eq!(decode_symbol(&invalid_utf8), None);
+        assert_eq!(decode_symbol(&[0x4d; 20]), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay_with_jitter() {
+        let config = ProviderConfig { max_retries: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_millis(300), max_requests_per_sec: None };
+        // Без разброса - нижняя граница d / 2, с максимальным - сама d
+        assert_eq!(config.backoff(0, 0), Duration::from_millis(50));
+        assert_eq!(config.backoff(0, u64::MAX), Duration::from_nanos(50_000_000 + u64::MAX % 50_000_001));
+        assert_eq!(config.backoff(1, 0), Duration::from_millis(100));
+        // 100 * 2^2 = 400 ограничено 300
+        assert_eq!(config.backoff(2, 0), Duration::from_millis(150));
+        assert_eq!(config.backoff(40, 0), Duration::from_millis(150));
+        for random in [1, 7, 12_345_678, u64::MAX / 3] {
+            let delay = config.backoff(1, random);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200), "{:?}", delay);
         }
-        Ok(None) => {
-            println!("Sushiswap: пул USDC/WETH не найден");
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_is_shared_by_concurrent_calls() {
+        const RATE: u32 = 20;
+        const CALLS: usize = 30;
+        let timestamps = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&timestamps);
+        let rpc = MockRpc::start(move |method, _| {
+            recorded.lock().unwrap().push(std::time::Instant::now());
+            if method == "eth_blockNumber" {
+                return Ok(serde_json::json!("0x10"));
+            }
+            let words = [U256::from(1_000u64), U256::from(2_000u64), U256::from(77u64)];
+            Ok(serde_json::json!(format!("0x{}", words.iter().map(|word| alloy::hex::encode(word.to_be_bytes::<32>())).collect::<String>())))
+        }).await;
+
+        // Предел стоит в транспорте: его проходят и вызовы контрактов, и прочие запросы
+        let provider = create_provider_with_limiter(&rpc.url, Some(RateLimiter::new(RATE))).await.unwrap();
+        let started = std::time::Instant::now();
+        let tasks: Vec<_> = (0..CALLS)
+            .map(|i| {
+                let provider = provider.clone();
+                tokio::spawn(async move {
+                    if i % 2 == 0 {
+                        provider.get_block_number().await.unwrap();
+                    } else {
+                        let contract = IUniswapV2Pair::IUniswapV2PairInstance::new(Address::repeat_byte(i as u8), provider);
+                        assert_eq!(contract.getReserves().call().await.unwrap().reserve0, alloy::primitives::aliases::U112::from(1_000u64));
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
         }
-        Err(e) => {
-            println!("Ошибка получения Sushiswap Pool: {}", e);
+
+        // Всплеск до RATE запросов, дальше не чаще RATE в секунду
+        // (запас в два запроса - на неравную задержку доставки до сервера)
+        let mut timestamps = timestamps.lock().unwrap().clone();
+        timestamps.sort();
+        assert_eq!(timestamps.len(), CALLS);
+        let min_elapsed = Duration::from_secs(1) * (CALLS as u32 - RATE) / RATE;
+        assert!(started.elapsed() >= min_elapsed * 9 / 10, "{:?}", started.elapsed());
+        for (i, first) in timestamps.iter().enumerate() {
+            for (j, last) in timestamps.iter().enumerate().skip(i) {
+                let allowed = f64::from(RATE) + f64::from(RATE) * last.duration_since(*first).as_secs_f64() + 2.0;
+                assert!((j - i + 1) as f64 <= allowed, "{} запросов за {:?}", j - i + 1, last.duration_since(*first));
+            }
         }
     }
-    
-    // Также проверяем с USDC.e для Sushiswap
-    let usdc_e_address = Address::from([
-        0x27, 0x91, 0xBc, 0xa1, 0xf2, 0xde, 0x46, 0x61,
-        0xED, 0x88, 0xA3, 0x0C, 0x99, 0xA7, 0xa9, 0x44,
-        0x9A, 0xa8, 0x41, 0x74
-    ]);
-    match create_pool_from_factory(
-        provider.clone(),
-        SUSHISWAP_V2_FACTORY,
-        usdc_e_address,
-        weth_address,
-    ).await {
-        Ok(Some(sushiswap_usdc_e_pool)) => {
-            println!("Sushiswap USDC.e Pool получен через Factory");
-            pools.push(sushiswap_usdc_e_pool);
-        }
-        Ok(None) => {
-            println!("Sushiswap: пул USDC.e/WETH не найден");
+
+    #[tokio::test]
+    async fn rate_limiter_accepts_rates_above_one_per_nanosecond() {
+        let limiter = RateLimiter::new(u32::MAX);
+        for _ in 0..1_000 {
+            limiter.acquire().await;
         }
-        Err(e) => {
-            println!("Ошибка получения Sushiswap USDC.e Pool: {}", e);
+    }
+
+    #[tokio::test]
+    async fn transport_is_chosen_by_url_scheme() {
+        assert_eq!(RpcTransport::detect("https://polygon-mainnet.infura.io/v3/KEY"), Some(RpcTransport::Http));
+        assert_eq!(RpcTransport::detect("http://localhost:8545"), Some(RpcTransport::Http));
+        assert_eq!(RpcTransport::detect("wss://polygon-mainnet.infura.io/ws/v3/KEY"), Some(RpcTransport::Ws));
+        assert_eq!(RpcTransport::detect("WS://localhost:8546"), Some(RpcTransport::Ws));
+        assert_eq!(RpcTransport::detect("ipc:///tmp/geth.ipc"), None);
+        assert_eq!(RpcTransport::detect("localhost:8545"), None);
+        assert!(create_provider("ftp://localhost").await.is_err());
+    }
+
+    /// Повторы без долгих пауз: тесты не ждут паузы по умолчанию
+    const FAST_RETRIES: ProviderConfig =
+        ProviderConfig { max_retries: 2, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(2), max_requests_per_sec: None };
+
+    /// Сервер, отвечающий на getReserves 502 первые `failures` раз
+    async fn flaky_reserves_rpc(failures: usize) -> MockRpc {
+        let calls = AtomicUsize::new(0);
+        MockRpc::start(move |_, params| {
+            assert_eq!(call_selector(params), Some(IUniswapV2Pair::getReservesCall::SELECTOR));
+            if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                return Err(BAD_GATEWAY.to_string());
+            }
+            let words = [U256::from(1_000u64), U256::from(2_000u64), U256::from(77u64)];
+            Ok(serde_json::json!(format!("0x{}", words.iter().map(|word| alloy::hex::encode(word.to_be_bytes::<32>())).collect::<String>())))
+        }).await
+    }
+
+    #[tokio::test]
+    async fn get_reserves_recovers_after_transient_failures() {
+        let rpc = flaky_reserves_rpc(2).await;
+        let reserves = crate::chain::get_reserves_with(&rpc.provider, FAST_RETRIES, Address::repeat_byte(0x41)).await.unwrap();
+        assert_eq!(reserves, (U256::from(1_000u64), U256::from(2_000u64), 77));
+        assert_eq!(rpc.request_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up_after_max_retries_and_skips_reverts() {
+        let rpc = flaky_reserves_rpc(usize::MAX).await;
+        let contract = &IUniswapV2Pair::IUniswapV2PairInstance::new(Address::repeat_byte(0x42), rpc.provider.clone());
+        let error = with_retry(&FAST_RETRIES, "getReserves", || async move { contract.getReserves().call().await.map(|reserves| reserves.reserve0) }).await.unwrap_err();
+        assert!(is_transient(&error));
+        assert_eq!(rpc.request_count(), 3);
+
+        // Revert - ответ узла, а не сбой: повтор дал бы тот же результат
+        let reverting = MockRpc::start(|_, _| Err("execution reverted".to_string())).await;
+        let contract = &IUniswapV2Pair::IUniswapV2PairInstance::new(Address::repeat_byte(0x43), reverting.provider.clone());
+        let error = with_retry(&FAST_RETRIES, "getReserves", || async move { contract.getReserves().call().await.map(|reserves| reserves.reserve0) }).await.unwrap_err();
+        assert!(!is_transient(&error));
+        assert_eq!(reverting.request_count(), 1);
+    }
+
+    #[test]
+    fn short_address_keeps_both_ends() {
+        let address: Address = "0x2791bca1f2de4661ed88a30c99a7a9449aa84174".parse().unwrap();
+        assert_eq!(short_address(address), "0x2791…4174");
+    }
+
+    #[tokio::test]
+    async fn token_symbol_falls_back_to_bytes32_and_address() {
+        let bytes32_token = TokenId(Address::repeat_byte(0x1a));
+        let reverting = TokenId(Address::repeat_byte(0x1b));
+        let rpc = MockRpc::start(move |method, params| {
+            assert_eq!(method, "eth_call");
+            // symbol()
+            assert_eq!(call_selector(params), Some([0x95, 0xd8, 0x9b, 0x41]));
+            if call_target(params) == Some(bytes32_token.address()) {
+                Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(bytes32_symbol("MKR")))))
+            } else {
+                Err("execution reverted".to_string())
+            }
+        }).await;
+
+        assert_eq!(get_token_symbol(rpc.provider.clone(), bytes32_token).await, "MKR");
+        assert_eq!(get_token_symbol(rpc.provider.clone(), reverting).await, "0x1b1b…1b1b");
+        // Повторный запрос берется из кэша
+        assert_eq!(get_token_symbol(rpc.provider.clone(), bytes32_token).await, "MKR");
+        assert_eq!(rpc.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn transport_failures_do_not_cache_decimals_or_symbol_fallbacks() {
+        let flaky = TokenId(Address::repeat_byte(0x1c));
+        let reverting = TokenId(Address::repeat_byte(0x1d));
+        let chain = MockChainClient::new();
+        chain.set_decimals(flaky, 6);
+        chain.set_symbol(flaky, "FLKY");
+        chain.fail_times(MockCall::Decimals(flaky.address()), 1);
+        chain.fail_times(MockCall::Symbol(flaky.address()), 1);
+
+        // Сбой транспорта: decimals - ошибка, символ - адрес только для этого вызова
+        assert!(get_token_decimals(chain.clone(), flaky).await.is_err());
+        assert_eq!(get_token_symbol(chain.clone(), flaky).await, "0x1c1c…1c1c");
+        assert_eq!(get_token_decimals(chain.clone(), flaky).await.unwrap(), 6);
+        assert_eq!(get_token_symbol(chain.clone(), flaky).await, "FLKY");
+
+        // Revert - ответ контракта: запасные значения кэшируются
+        assert_eq!(get_token_decimals(chain.clone(), reverting).await.unwrap(), WETH_DECIMALS);
+        assert_eq!(get_token_decimals(chain.clone(), reverting).await.unwrap(), WETH_DECIMALS);
+        assert_eq!(get_token_symbol(chain.clone(), reverting).await, "0x1d1d…1d1d");
+        assert_eq!(get_token_symbol(chain.clone(), reverting).await, "0x1d1d…1d1d");
+        assert_eq!(chain.calls(MockCall::Decimals(reverting.address())), 1);
+        assert_eq!(chain.calls(MockCall::Symbol(reverting.address())), 1);
+    }
+
+    #[test]
+    fn colliding_symbols_are_disambiguated_by_address() {
+        let fake = TokenId(Address::repeat_byte(0x3c));
+        assert_eq!(pool_label(DexId::QUICKSWAP, (TokenId::USDC_E, "USDC.e"), (TokenId::WETH, "WETH")), "Quickswap USDC.e/WETH");
+        assert_eq!(pool_label(DexId::QUICKSWAP, (TokenId::USDC, "USDC"), (fake, " usdc")),
+            "Quickswap USDC(0x3c49…3359)/ usdc(0x3c3c…3c3c)");
+    }
+
+    #[tokio::test]
+    async fn pool_name_with_crafted_symbol_collision_keeps_tokens_apart() {
+        let (token_a, token_b) = (TokenId(Address::repeat_byte(0x2a)), TokenId(Address::repeat_byte(0x2b)));
+        // Оба токена выдают себя за WETH
+        let rpc = MockRpc::start(|_, _| Ok(serde_json::json!(format!("0x{}", alloy::hex::encode("WETH".to_string().abi_encode()))))).await;
+        let dex = &crate::config::default_dexes()[0];
+
+        let name = pool_name(rpc.provider.clone(), dex, token_a, token_b).await;
+        assert_eq!(name, format!("{} WETH(0x2a2a…2a2a)/WETH(0x2b2b…2b2b)", dex.id));
+        assert_ne!(name, pool_name(rpc.provider.clone(), dex, token_b, token_a).await);
+    }
+
+    #[tokio::test]
+    async fn extra_pool_takes_dex_from_factory_and_must_match_profile() {
+        let (quickswap_pair, foreign_pair) = (Address::repeat_byte(0x4e), Address::repeat_byte(0x5e));
+        let other = TokenId(Address::repeat_byte(0x0f));
+        let sorted = |a: TokenId, b: TokenId| if a < b { (a, b) } else { (b, a) };
+        let rpc = MockRpc::start(move |method, params| {
+            if method == "eth_getCode" {
+                return Ok(serde_json::json!("0x6080"));
+            }
+            let target = call_target(params).unwrap();
+            // Вторая пара торгует USDC и посторонним токеном
+            let (token0, token1) = if target == quickswap_pair { sorted(TokenId::USDC, TokenId::WETH) } else { sorted(TokenId::USDC, other) };
+            match call_selector(params) {
+                Some(IUniswapV2Pair::token0Call::SELECTOR) => Ok(encode_address(token0.address())),
+                Some(IUniswapV2Pair::token1Call::SELECTOR) => Ok(encode_address(token1.address())),
+                Some(IUniswapV2Pair::factoryCall::SELECTOR) => Ok(encode_address(QUICKSWAP_V2_FACTORY)),
+                Some(IUniswapV2Factory::feeToCall::SELECTOR) => Ok(encode_address(Address::ZERO)),
+                Some(IERC20Metadata::decimalsCall::SELECTOR) => Ok(encode_word(U256::from(if target == TokenId::WETH.address() { 18u64 } else { 6 }))),
+                Some(IUniswapV2Pair::getReservesCall::SELECTOR) => Ok(serde_json::json!(format!("0x{}", "00".repeat(96)))),
+                _ => Err("execution reverted".to_string()),
+            }
+        }).await;
+        let ctx = ConfigContext::default();
+
+        let pool = load_extra_pool(rpc.provider.clone(), quickswap_pair, &ctx).await.unwrap();
+        assert_eq!(pool.dex, DexId::QUICKSWAP);
+        assert!(pool.name.starts_with("Quickswap "), "{}", pool.name);
+        assert!(!pool.protocol_fee_enabled);
+
+        let error = load_extra_pool(rpc.provider.clone(), foreign_pair, &ctx).await.unwrap_err().to_string();
+        assert!(error.contains("нужны входной токен профиля"), "{}", error);
+    }
+
+    /// Мок с decimals и символами токенов профиля по умолчанию (кэши токенов общие для всех тестов)
+    fn profile_chain() -> Arc<MockChainClient> {
+        let chain = MockChainClient::new();
+        for (token, decimals, symbol) in [(TokenId::USDC, 6, "USDC"), (TokenId::USDC_E, 6, "USDC.e"), (TokenId::WETH, 18, "WETH")] {
+            chain.set_decimals(token, decimals);
+            chain.set_symbol(token, symbol);
         }
+        chain
+    }
+
+    #[tokio::test]
+    async fn factory_pairs_with_init_code_hash_skip_get_pair() {
+        let live = pair_for(QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, TokenId::USDC_E, TokenId::WETH);
+        let empty = pair_for(QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, TokenId::USDC, TokenId::WETH);
+        let chain = profile_chain();
+        // Пустая пара (резервы 0) и живая пара; multicall падает, резервы читаются по одной
+        chain.set_reserves(empty, (U256::ZERO, U256::ZERO, 0));
+        chain.set_reserves(live, (U256::from(7u64), U256::from(9u64), 0));
+        chain.fail(MockCall::ReservesBatch);
+        let ctx = ConfigContext {
+            dexes: vec![DexConfig { input_tokens: vec![TokenId::USDC, TokenId::USDC_E], ..crate::config::default_dexes()[1].clone() }],
+            verify_pairs: false,
+            ..ConfigContext::default()
+        };
+
+        let pools = get_all_pool_addresses(chain.clone(), &ctx).await.unwrap();
+        let addresses: Vec<Address> = pools.states().iter().map(|pool| pool.pool_address).collect();
+        assert_eq!(addresses, vec![live]);
+        assert_eq!(pools.states()[0].reserve_token0, U256::from(7u64));
+        assert_eq!(chain.calls(MockCall::Pair(QUICKSWAP_V2_FACTORY)), 0);
+        assert_eq!((chain.calls(MockCall::Reserves(empty)), chain.calls(MockCall::Reserves(live))), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn discovery_keeps_live_pairs_and_skips_failed_calls() {
+        let chain = profile_chain();
+        let reserves = (U256::from(2_000_000_000_000u64), U256::from(10u64).pow(U256::from(21u64)), 1_700_000_000);
+        // Статический пул Uniswap V2: его Factory берется из пары, feeTo включен
+        let uniswap_factory = Address::repeat_byte(0xf1);
+        chain.add_pair(uniswap_factory, TokenId::USDC, TokenId::WETH, UNISWAP_V2_POOL_ADDRESS, reserves);
+        chain.set_fee_to(uniswap_factory, Address::repeat_byte(0xfe));
+        let quickswap = pair_for(QUICKSWAP_V2_FACTORY, QUICKSWAP_V2_INIT_CODE_HASH, TokenId::USDC, TokenId::WETH);
+        chain.add_pair(QUICKSWAP_V2_FACTORY, TokenId::USDC, TokenId::WETH, quickswap, reserves);
+        // Sushiswap: getPair для USDC падает, пара USDC.e/WETH есть, но ее резервы не читаются
+        let sushiswap = Address::repeat_byte(0x5e);
+        chain.add_pair(SUSHISWAP_V2_FACTORY, TokenId::USDC_E, TokenId::WETH, sushiswap, reserves);
+        chain.fail_times(MockCall::Pair(SUSHISWAP_V2_FACTORY), 1);
+        chain.fail(MockCall::Reserves(sushiswap));
+
+        let pools = get_all_pool_addresses(chain.clone(), &ConfigContext::default()).await.unwrap();
+        let addresses: Vec<Address> = pools.states().iter().map(|pool| pool.pool_address).collect();
+        assert_eq!(addresses, vec![UNISWAP_V2_POOL_ADDRESS, quickswap]);
+        assert!(pools[0].protocol_fee_enabled);
+        assert!(!pools[1].protocol_fee_enabled);
+        assert!(pools.iter().all(|pool| pool.reserve_token0 == reserves.0 && pool.last_updated == reserves.2));
+
+        assert_eq!(chain.calls(MockCall::Pair(SUSHISWAP_V2_FACTORY)), 2);
+        // Один multicall; отдельно перечитывается только пара, чей вызов в нем не удался
+        assert_eq!(chain.calls(MockCall::ReservesBatch), 1);
+        assert_eq!((chain.calls(MockCall::Reserves(quickswap)), chain.calls(MockCall::Reserves(sushiswap))), (0, 1));
+        // Профиль сверяет токены пар из getPair; адрес CREATE2 выведен из токенов и не сверяется
+        assert_eq!((chain.calls(MockCall::PairTokens(quickswap)), chain.calls(MockCall::PairTokens(sushiswap))), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn factory_pairs_without_init_code_hash_use_get_pair() {
+        // Sushiswap без хэша init code: адрес пары берется из getPair, а не из CREATE2
+        let sushiswap_dex = crate::config::default_dexes().into_iter().find(|dex| dex.id == DexId::SUSHISWAP).unwrap();
+        assert_eq!(sushiswap_dex.pair_init_code_hash, None);
+        let chain = profile_chain();
+        let pair = Address::repeat_byte(0x5e);
+        chain.add_pair(SUSHISWAP_V2_FACTORY, TokenId::USDC, TokenId::WETH, pair, (U256::from(7u64), U256::from(9u64), 0));
+        let ctx = ConfigContext {
+            dexes: vec![DexConfig { input_tokens: vec![TokenId::USDC, TokenId::USDC_E], ..sushiswap_dex }],
+            ..ConfigContext::default()
+        };
+
+        let pools = get_all_pool_addresses(chain.clone(), &ctx).await.unwrap();
+        let addresses: Vec<Address> = pools.states().iter().map(|pool| pool.pool_address).collect();
+        assert_eq!(addresses, vec![pair]);
+        // getPair для каждой пары профиля; пары USDC.e/WETH нет
+        assert_eq!(chain.calls(MockCall::Pair(SUSHISWAP_V2_FACTORY)), 2);
+        assert_eq!(chain.calls(MockCall::PairTokens(pair)), 1);
     }
-    
-    println!("Создано {} Pool объектов через Factory контракты", pools.len());
-    
-    Ok(pools)
 }
@@ -0,0 +1,165 @@
+// src/refresher.rs
+//! Фоновое обновление резервов для долгоживущего процесса
+//!
+//! `ReserveRefresher` забирает пулы в фоновую задачу, которая каждые
+//! `interval` перечитывает резервы (`refresh_all_reserves`: пары одним
+//! multicall) и публикует снимок состояний через `tokio::sync::watch`.
+//! Котировки не ждут обновления: солвер получает копию последнего снимка
+//! (`find_best_routes` принимает состояния по значению), поэтому обновление
+//! посреди решения не меняет резервы, на которых идет жадный учет.
+use crate::log;
+use crate::pool::{refresh_all_reserves, Pool, PoolState};
+use eyre::{eyre, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// Интервал обновления по умолчанию: примерно пять блоков Polygon
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Согласованный снимок резервов всех пулов на одном круге обновления
+#[derive(Debug, Clone)]
+pub struct ReserveSnapshot {
+    pub round: u64,      // Номер круга (0 - состояния до первого обновления)
+    pub failures: usize, // Пулы, резервы которых на этом круге не обновились
+    pub states: Vec<PoolState>,
+}
+
+/// Фоновая задача обновления резервов
+pub struct ReserveRefresher {
+    snapshot: watch::Receiver<Arc<ReserveSnapshot>>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Vec<Pool>>,
+}
+
+impl ReserveRefresher {
+    /// Запускает обновление; первый круг начинается сразу
+    pub fn spawn(mut pools: Vec<Pool>, interval: Duration) -> Self {
+        let initial = ReserveSnapshot { round: 0, failures: 0, states: Pool::states(&pools) };
+        let (sender, snapshot) = watch::channel(Arc::new(initial));
+        let (shutdown, mut stop) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            let Some(client) = pools.first().map(|pool| pool.client.clone()) else {
+                return pools;
+            };
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut round = 0;
+            loop {
+                // Остановка проверяется между кругами: начатый круг доводится до конца
+                tokio::select! {
+                    _ = &mut stop => break,
+                    _ = ticker.tick() => {}
+                }
+                round += 1;
+                let failures = refresh_all_reserves(client.clone(), &mut pools).await;
+                for (address, e) in &failures {
+                    log!("Фоновое обновление: не удалось обновить резервы {:?}: {}", address, e);
+                }
+                let snapshot = ReserveSnapshot { round, failures: failures.len(), states: Pool::states(&pools) };
+                // Получатели закрыты только вместе с ReserveRefresher
+                if sender.send(Arc::new(snapshot)).is_err() {
+                    break;
+                }
+            }
+            pools
+        });
+
+        ReserveRefresher { snapshot, shutdown, task }
+    }
+
+    /// Последний опубликованный снимок
+    pub fn snapshot(&self) -> Arc<ReserveSnapshot> {
+        Arc::clone(&self.snapshot.borrow())
+    }
+
+    /// Копия состояний последнего снимка для солвера
+    pub fn states(&self) -> Vec<PoolState> {
+        self.snapshot().states.clone()
+    }
+
+    /// Получатель снимков: `changed()` завершается после каждого круга
+    pub fn subscribe(&self) -> watch::Receiver<Arc<ReserveSnapshot>> {
+        self.snapshot.clone()
+    }
+
+    /// Останавливает задачу после текущего круга и возвращает пулы с последними резервами
+    pub async fn shutdown(self) -> Result<Vec<Pool>> {
+        // Задача могла уже завершиться сама (нет пулов): тогда сигнал некому принять
+        let _ = self.shutdown.send(());
+        self.task.await.map_err(|e| eyre!("задача обновления резервов завершилась с ошибкой: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TokenId, MULTICALL3_ADDRESS};
+    use crate::mock_rpc::{call_input, call_target, MockRpc};
+    use crate::pool::test_pool;
+    use crate::provider::{IMulticall3, IUniswapV2Pair};
+    use alloy::primitives::{aliases::U112, Address, U256};
+    use alloy::sol_types::SolCall;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[tokio::test]
+    async fn snapshot_follows_reserves_between_ticks_and_stops_on_shutdown() {
+        // Резервы растут с каждым multicall: круг N видит reserve0 = N
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        let rpc = MockRpc::start(move |_, params| {
+            assert_eq!(call_target(params), Some(MULTICALL3_ADDRESS));
+            let requested = IMulticall3::aggregate3Call::abi_decode(&call_input(params).unwrap(), true).unwrap().calls;
+            let round = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let reserves = IUniswapV2Pair::getReservesCall::abi_encode_returns(&(U112::from(round), U112::from(1_000u64), 0u32));
+            let results: Vec<IMulticall3::Result> = requested
+                .iter()
+                .map(|_| IMulticall3::Result { success: true, returnData: reserves.clone().into() })
+                .collect();
+            Ok(serde_json::json!(alloy::hex::encode_prefixed(IMulticall3::aggregate3Call::abi_encode_returns(&(results,)))))
+        }).await;
+        let pools: Vec<Pool> = [0x11, 0x12]
+            .into_iter()
+            .map(|byte| Pool::from_state(test_pool(byte, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), rpc.provider.clone()))
+            .collect();
+
+        let refresher = ReserveRefresher::spawn(pools, Duration::from_millis(50));
+        assert_eq!(refresher.snapshot().round, 0);
+        let mut updates = refresher.subscribe();
+        let mut seen = Vec::new();
+        while seen.len() < 3 {
+            updates.changed().await.unwrap();
+            let snapshot = updates.borrow_and_update().clone();
+            assert_eq!(snapshot.failures, 0);
+            // Все пулы снимка прочитаны на одном круге
+            assert!(snapshot.states.iter().all(|state| state.reserve_token0 == U256::from(snapshot.round)), "{:?}", snapshot);
+            seen.push(snapshot.round);
+        }
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", seen);
+
+        // Копия для солвера не меняется следующими кругами
+        let states = refresher.states();
+        updates.changed().await.unwrap();
+        assert!(refresher.snapshot().round > states[0].reserve_token0.to::<u64>());
+        assert_eq!(states[0].reserve_token0, states[1].reserve_token0);
+
+        let pools = refresher.shutdown().await.unwrap();
+        let rounds = calls.load(Ordering::SeqCst);
+        assert_eq!(pools[0].reserve_token0, U256::from(rounds));
+        assert_eq!(pools.iter().map(|pool| pool.pool_address).collect::<Vec<_>>(),
+            vec![Address::repeat_byte(0x11), Address::repeat_byte(0x12)]);
+        // После остановки запросов больше нет
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), rounds);
+    }
+
+    #[tokio::test]
+    async fn refresher_without_pools_shuts_down() {
+        let refresher = ReserveRefresher::spawn(Vec::new(), Duration::from_millis(10));
+        assert!(refresher.states().is_empty());
+        assert!(refresher.shutdown().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+// src/regress.rs
+//! Регрессионный прогон солвера по записанным манифестам
+//!
+//! Манифест хранит резервы пулов, параметры солвера и ожидаемый результат:
+//! суммарный выход и хэш маршрута. Прогон заново решает каждый случай текущим
+//! кодом и сравнивает выход с ожидаемым в bps. Сеть не нужна: пулы строятся
+//! из резервов манифеста, провайдер создается, но не используется.
+//!
+//! Ожидаемый результат помечен версией алгоритма солвера, которой он записан.
+//! Если версия отличается от текущей и результат разошелся, случай отмечается
+//! как смена алгоритма, а не как регрессия: такие манифесты нужно перезаписать
+//! (`--update`), а не искать порчу данных.
+use crate::config::{ConfigContext, DexId, TokenId, DEFAULT_SLIPPAGE_BPS};
+use crate::pool::PoolState;
+use crate::solver::{find_best_routes, ChunkJitter, SolverConfig, SolverResult, Strategy, SOLVER_ALGORITHM_VERSION};
+use alloy::primitives::{keccak256, Address, U256};
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Пул USDC/WETH с зафиксированными резервами (raw units)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestPool {
+    pub name: String,
+    #[serde(with = "decimal")]
+    pub reserve_usdc: U256,
+    #[serde(with = "decimal")]
+    pub reserve_weth: U256,
+}
+
+/// Ожидаемый результат прогона
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Expected {
+    #[serde(with = "decimal")]
+    pub total_weth_out: U256,
+    pub route_hash: String,
+    /// Версия алгоритма, которой записан результат (в старых манифестах ее нет)
+    #[serde(default = "unversioned_algorithm")]
+    pub solver_algorithm_version: u32,
+}
+
+/// Записанный случай: резервы, параметры солвера и ожидаемый результат
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(with = "decimal")]
+    pub total_amount_in: U256,
+    pub num_chunks: u64,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub reserve_haircut_bps: u32,
+    #[serde(default = "default_slippage_bps")]
+    pub slippage_bps: u32,
+    /// Разброс размеров чанков и его seed: без них маршрут не воспроизводится
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_jitter: Option<ChunkJitter>,
+    pub pools: Vec<ManifestPool>,
+    pub expected: Expected,
+}
+
+fn default_slippage_bps() -> u32 {
+    DEFAULT_SLIPPAGE_BPS
+}
+
+/// Манифесты без версии записаны до ее появления, то есть версией 1
+fn unversioned_algorithm() -> u32 {
+    1
+}
+
+/// Результат сравнения одного случая с ожидаемым
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseReport {
+    pub name: String,
+    pub expected_out: U256,
+    pub actual_out: U256,
+    pub delta_bps: f64,     // (фактический - ожидаемый) / ожидаемый в bps
+    pub route_changed: bool, // Хэш маршрута отличается от записанного
+    pub regressed: bool,     // Выход изменился больше допуска той же версией алгоритма
+    pub recorded_version: u32,   // Версия алгоритма, которой записан ожидаемый результат
+    pub algorithm_changed: bool, // Результат разошелся, но записан другой версией алгоритма
+}
+
+/// Хэш маршрута: keccak256 от последовательности (чанк, пул, вход, выход)
+pub fn route_hash(result: &SolverResult) -> String {
+    let mut encoded = String::new();
+    for route in &result.chunk_routes {
+        let _ = writeln!(encoded, "{}:{}:{}:{}",
+            route.chunk_index, route.best_pool_name, route.amount_in, route.amount_out);
+    }
+    keccak256(encoded.as_bytes()).to_string()
+}
+
+impl Manifest {
+    /// Пулы USDC/WETH с резервами манифеста
+    pub fn pool_states(&self) -> Vec<PoolState> {
+        self.pools
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                let mut pool = PoolState::new(
+                    Address::with_last_byte(i as u8 + 1),
+                    TokenId::USDC,
+                    TokenId::WETH,
+                    DexId("Replay"),
+                    spec.name.clone(),
+                );
+                if pool.token0 == TokenId::USDC {
+                    pool.reserve_token0 = spec.reserve_usdc;
+                    pool.reserve_token1 = spec.reserve_weth;
+                } else {
+                    pool.reserve_token0 = spec.reserve_weth;
+                    pool.reserve_token1 = spec.reserve_usdc;
+                }
+                pool.invalidate_quote_cache();
+                pool
+            })
+            .collect()
+    }
+
+    /// Параметры солвера манифеста (без вывода хода решения)
+    pub fn solver_config(&self) -> SolverConfig {
+        SolverConfig {
+            total_amount_in: self.total_amount_in,
+            num_chunks: self.num_chunks,
+            verbose: false,
+            strategy: self.strategy,
+            reserve_haircut_bps: self.reserve_haircut_bps,
+            slippage_bps: self.slippage_bps,
+            chunk_jitter: self.chunk_jitter,
+            ..SolverConfig::default()
+        }
+    }
+}
+
+/// Заново решает случай текущим кодом солвера
+pub async fn replay(manifest: &Manifest) -> Result<SolverResult> {
+    find_best_routes(manifest.pool_states(), &ConfigContext::default(), &manifest.solver_config()).await
+}
+
+/// Отклонение фактического выхода от ожидаемого в bps (отрицательное - меньше ожидаемого)
+pub fn delta_bps(expected_out: U256, actual_out: U256) -> f64 {
+    if expected_out.is_zero() {
+        if actual_out.is_zero() { 0.0 } else { f64::INFINITY }
+    } else {
+        let expected = crate::math::u256_to_f64(expected_out);
+        (crate::math::u256_to_f64(actual_out) - expected) / expected * 10_000.0
+    }
+}
+
+/// Параметры солвера, воспроизводящие поведение записанной версии алгоритма
+///
+/// При повышении `SOLVER_ALGORITHM_VERSION` сюда добавляется ветка для
+/// предыдущей версии, если ее поведение можно вернуть параметрами
+/// `SolverConfig`; остальные версии (`None`) прогоняются текущим алгоритмом.
+/// Версия 1 отличается только разбиением с разбросом чанков, поэтому случаи
+/// без разброса она решает так же, как текущая.
+fn compatible_config(manifest: &Manifest, version: u32) -> Option<SolverConfig> {
+    match version {
+        SOLVER_ALGORITHM_VERSION => Some(manifest.solver_config()),
+        1 if manifest.chunk_jitter.is_none() => Some(manifest.solver_config()),
+        _ => None,
+    }
+}
+
+/// Решает случай так, как его решала записанная в манифесте версия алгоритма
+///
+/// Если эта версия не воспроизводится, результат получен текущим алгоритмом
+/// и `algorithm_version` у него текущая, что `compare` и отметит.
+pub async fn replay_recorded(manifest: &Manifest) -> Result<SolverResult> {
+    let version = manifest.expected.solver_algorithm_version;
+    match compatible_config(manifest, version) {
+        Some(solver_config) => {
+            let mut result = find_best_routes(manifest.pool_states(), &ConfigContext::default(), &solver_config).await?;
+            result.algorithm_version = version;
+            Ok(result)
+        }
+        None => replay(manifest).await,
+    }
+}
+
+/// Сравнивает результат прогона с ожидаемым в манифесте
+///
+/// Расхождение (маршрут или выход сверх допуска) при совпадающей версии
+/// алгоритма - регрессия или порча данных; при разных версиях - смена алгоритма.
+pub fn compare(manifest: &Manifest, result: &SolverResult, tolerance_bps: f64) -> CaseReport {
+    let expected_out = manifest.expected.total_weth_out;
+    let actual_out = result.total_weth_out;
+    let delta_bps = delta_bps(expected_out, actual_out);
+    let route_changed = route_hash(result) != manifest.expected.route_hash;
+    let out_changed = delta_bps.abs() > tolerance_bps;
+    let recorded_version = manifest.expected.solver_algorithm_version;
+    let same_algorithm = result.algorithm_version == recorded_version;
+    CaseReport {
+        name: manifest.name.clone(),
+        expected_out,
+        actual_out,
+        delta_bps,
+        route_changed,
+        regressed: same_algorithm && out_changed,
+        recorded_version,
+        algorithm_changed: !same_algorithm && (route_changed || out_changed),
+    }
+}
+
+/// Записывает текущий результат как ожидаемый
+pub fn bless(manifest: &mut Manifest, result: &SolverResult) {
+    manifest.expected = Expected {
+        total_weth_out: result.total_weth_out,
+        route_hash: route_hash(result),
+        solver_algorithm_version: result.algorithm_version,
+    };
+}
+
+/// Файлы манифестов корпуса (*.json), отсортированные по имени
+fn manifest_paths(corpus: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(corpus).wrap_err_with(|| format!("не удалось прочитать корпус {}", corpus.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Прогоняет все манифесты корпуса
+///
+/// С `update = true` текущие результаты записываются в манифесты как ожидаемые,
+/// а отчет показывает изменения относительно прежних ожиданий.
+pub async fn run_corpus(corpus: &Path, tolerance_bps: f64, update: bool) -> Result<Vec<CaseReport>> {
+    let mut reports = Vec::new();
+    for path in manifest_paths(corpus)? {
+        let text = std::fs::read_to_string(&path)?;
+        let mut manifest: Manifest = serde_json::from_str(&text)
+            .wrap_err_with(|| format!("некорректный манифест {}", path.display()))?;
+        let result = replay_recorded(&manifest).await?;
+        reports.push(compare(&manifest, &result, tolerance_bps));
+        if update {
+            // Ожидание всегда перезаписывается текущим алгоритмом
+            let result = if result.algorithm_version == SOLVER_ALGORITHM_VERSION { result } else { replay(&manifest).await? };
+            bless(&mut manifest, &result);
+            std::fs::write(&path, serde_json::to_string_pretty(&manifest)? + "\n")?;
+        }
+    }
+    Ok(reports)
+}
+
+/// Таблица отчета по случаям
+pub fn format_report(reports: &[CaseReport]) -> String {
+    let mut out = format!("  {:<32} | {:>24} | {:>24} | {:>10} | {:>8} | {}\n",
+        "Случай", "Ожидалось", "Получено", "Δ bps", "Маршрут", "Статус");
+    for report in reports {
+        let _ = writeln!(out, "  {:<32} | {:>24} | {:>24} | {:>10.4} | {:>8} | {}",
+            report.name,
+            report.expected_out,
+            report.actual_out,
+            report.delta_bps,
+            if report.route_changed { "изменен" } else { "тот же" },
+            status(report));
+    }
+    out
+}
+
+fn status(report: &CaseReport) -> String {
+    if report.regressed {
+        "РЕГРЕССИЯ".to_string()
+    } else if report.algorithm_changed {
+        format!("алгоритм изменен (v{} -> v{})", report.recorded_version, SOLVER_ALGORITHM_VERSION)
+    } else {
+        "ok".to_string()
+    }
+}
+
+/// Сериализация U256 десятичной строкой, чтобы манифесты читались глазами
+pub(crate) mod decimal {
+    use alloy::primitives::U256;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_pool_manifest() -> Manifest {
+        Manifest {
+            name: "two-pools".to_string(),
+            description: String::new(),
+            total_amount_in: U256::from(100_000_000_000u64),
+            num_chunks: 10,
+            strategy: Strategy::Greedy,
+            reserve_haircut_bps: 0,
+            slippage_bps: DEFAULT_SLIPPAGE_BPS,
+            chunk_jitter: None,
+            pools: vec![
+                ManifestPool {
+                    name: "A".to_string(),
+                    reserve_usdc: U256::from(2_000_000_000_000u64),
+                    reserve_weth: U256::from(800u64) * U256::from(10u64).pow(U256::from(18)),
+                },
+                ManifestPool {
+                    name: "B".to_string(),
+                    reserve_usdc: U256::from(1_000_000_000_000u64),
+                    reserve_weth: U256::from(405u64) * U256::from(10u64).pow(U256::from(18)),
+                },
+            ],
+            expected: Expected { total_weth_out: U256::ZERO, route_hash: String::new(), solver_algorithm_version: SOLVER_ALGORITHM_VERSION },
+        }
+    }
+
+    #[tokio::test]
+    async fn blessed_manifest_passes_and_perturbed_expectation_is_detected() {
+        let mut manifest = two_pool_manifest();
+        let result = replay(&manifest).await.unwrap();
+        bless(&mut manifest, &result);
+
+        let report = compare(&manifest, &result, 0.0);
+        assert!(!report.regressed);
+        assert!(!report.route_changed);
+        assert_eq!(report.delta_bps, 0.0);
+
+        // Ожидание на 1% выше фактического выхода -> регрессия около -99 bps
+        manifest.expected.total_weth_out = result.total_weth_out * U256::from(101) / U256::from(100);
+        let report = compare(&manifest, &result, 1.0);
+        assert!(report.regressed);
+        assert!(report.delta_bps < -98.0 && report.delta_bps > -100.0);
+    }
+
+    #[tokio::test]
+    async fn jitter_seed_in_manifest_reproduces_the_route() {
+        let mut manifest = Manifest { chunk_jitter: Some(ChunkJitter { bps: 1_500, seed: 7 }), ..two_pool_manifest() };
+        let result = replay(&manifest).await.unwrap();
+        bless(&mut manifest, &result);
+
+        let json = serde_json::to_string_pretty(&manifest).unwrap();
+        assert!(json.contains("\"seed\": 7"));
+        let restored: Manifest = serde_json::from_str(&json).unwrap();
+        assert!(!compare(&restored, &replay(&restored).await.unwrap(), 0.0).route_changed);
+        // Без seed разбиение равное: маршрут другой
+        let uniform = Manifest { chunk_jitter: None, ..restored };
+        assert!(compare(&uniform, &replay(&uniform).await.unwrap(), 0.0).route_changed);
+        assert!(!serde_json::to_string(&uniform).unwrap().contains("chunk_jitter"));
+    }
+
+    #[tokio::test]
+    async fn changed_route_is_reported() {
+        let mut manifest = two_pool_manifest();
+        let result = replay(&manifest).await.unwrap();
+        bless(&mut manifest, &result);
+
+        manifest.strategy = Strategy::MarginalEqualization;
+        let result = replay(&manifest).await.unwrap();
+        assert!(compare(&manifest, &result, f64::INFINITY).route_changed);
+    }
+
+    #[tokio::test]
+    async fn algorithm_drift_is_told_apart_from_corrupted_data() {
+        let mut manifest = two_pool_manifest();
+        let result = replay_recorded(&manifest).await.unwrap();
+        bless(&mut manifest, &result);
+        assert_eq!(manifest.expected.solver_algorithm_version, SOLVER_ALGORITHM_VERSION);
+
+        // Повышение версии: новый алгоритм дает другой маршрут на тех же данных
+        manifest.strategy = Strategy::MarginalEqualization;
+        let mut bumped = replay(&manifest).await.unwrap();
+        bumped.algorithm_version = SOLVER_ALGORITHM_VERSION + 1;
+        let report = compare(&manifest, &bumped, 0.0);
+        assert!(report.algorithm_changed && report.route_changed);
+        assert!(!report.regressed);
+        assert!(format_report(&[report]).contains("алгоритм изменен"));
+
+        // Та же версия, испорченное ожидание: регрессия, а не смена алгоритма
+        manifest.strategy = Strategy::Greedy;
+        manifest.expected.total_weth_out += U256::from(10u64).pow(U256::from(16));
+        let report = compare(&manifest, &replay_recorded(&manifest).await.unwrap(), 0.0);
+        assert!(report.regressed);
+        assert!(!report.algorithm_changed);
+    }
+
+    #[tokio::test]
+    async fn unreproducible_versions_replay_with_the_current_algorithm() {
+        let mut manifest = two_pool_manifest();
+        let result = replay(&manifest).await.unwrap();
+        bless(&mut manifest, &result);
+
+        // Без расхождений другая версия ничего не меняет в отчете
+        manifest.expected.solver_algorithm_version = SOLVER_ALGORITHM_VERSION + 1;
+        let result = replay_recorded(&manifest).await.unwrap();
+        assert_eq!(result.algorithm_version, SOLVER_ALGORITHM_VERSION);
+        let report = compare(&manifest, &result, 0.0);
+        assert!(!report.regressed && !report.algorithm_changed);
+
+        manifest.expected.route_hash = String::new();
+        let report = compare(&manifest, &result, 0.0);
+        assert!(report.algorithm_changed && !report.regressed);
+    }
+
+    #[tokio::test]
+    async fn version_one_replays_only_without_chunk_jitter() {
+        let mut manifest = two_pool_manifest();
+        let result = replay(&manifest).await.unwrap();
+        bless(&mut manifest, &result);
+        manifest.expected.solver_algorithm_version = 1;
+        assert_eq!(replay_recorded(&manifest).await.unwrap().algorithm_version, 1);
+
+        // Разбиение с разбросом в версии 1 было другим - прогон текущим алгоритмом
+        let jittered = Manifest { chunk_jitter: Some(ChunkJitter { bps: 1_500, seed: 7 }), ..manifest };
+        assert_eq!(replay_recorded(&jittered).await.unwrap().algorithm_version, SOLVER_ALGORITHM_VERSION);
+    }
+
+    #[test]
+    fn manifests_without_a_version_were_recorded_by_version_one() {
+        let mut json = serde_json::to_value(two_pool_manifest()).unwrap();
+        json["expected"].as_object_mut().unwrap().remove("solver_algorithm_version");
+        let manifest: Manifest = serde_json::from_value(json).unwrap();
+        assert_eq!(manifest.expected.solver_algorithm_version, 1);
+    }
+
+    #[test]
+    fn manifest_round_trips_with_decimal_amounts() {
+        let manifest = two_pool_manifest();
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains("\"total_amount_in\":\"100000000000\""));
+        assert_eq!(serde_json::from_str::<Manifest>(&json).unwrap(), manifest);
+    }
+
+    #[tokio::test]
+    async fn seeded_corpus_has_no_regressions() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("regress");
+        let reports = run_corpus(&corpus, 0.0, false).await.unwrap();
+        assert!(!reports.is_empty());
+        for report in &reports {
+            assert!(!report.regressed && !report.route_changed && !report.algorithm_changed, "{}", format_report(&reports));
+        }
+    }
+}
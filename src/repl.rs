@@ -0,0 +1,518 @@
+// src/repl.rs
+//! Интерактивный режим: команды выполняются над "теплым" набором пулов,
+//! без повторного discovery на каждый запрос.
+
+use crate::config::{self, ConfigContext};
+use crate::log;
+use crate::pool::{refresh_all_reserves, Pool};
+use crate::prefetch::{prefetch_reserves, PrefetchProgress, PrefetchReport};
+use crate::solver::{find_best_routes, pool_usage, SolverConfig};
+use crate::stats::UsageStats;
+use eyre::{eyre, Result};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+const COMMANDS: [&str; 9] = ["pools", "quote", "use", "refresh", "snapshot", "stats", "help", "exit", "quit"];
+
+const HELP: &str = "Команды:
+  pools                         список активных пулов и резервов
+  quote <USDC> [--chunks N]     котировка на текущих резервах (пулы не меняются)
+  use only <dex или пул>        оставить только пулы, чье имя содержит подстроку
+  use all                       вернуть все найденные пулы
+  refresh                       обновить резервы всех пулов из сети
+  snapshot save|restore <имя>   сохранить или восстановить резервы пулов
+  stats [reset]                 накопительная статистика котировок (или ее сброс)
+  exit                          выход";
+
+/// Состояние интерактивной сессии
+pub struct ReplSession {
+    ctx: ConfigContext,
+    all_pools: Vec<Pool>,              // Все пулы, найденные при запуске
+    active: Vec<Pool>,                 // Пулы после фильтра `use only`
+    snapshots: HashMap<String, Vec<Pool>>,
+    prefetch: Option<PendingPrefetch>,
+    stats: UsageStats,
+    stats_path: Option<PathBuf>, // Файл статистики: сохраняется пачками, см. `save_stats`
+    stats_saved_at: Instant,     // Время последнего сохранения статистики
+    stats_dirty: bool,           // Есть изменения, еще не записанные в файл
+}
+
+/// Запущенная фоновая предзагрузка резервов
+struct PendingPrefetch {
+    progress: Arc<PrefetchProgress>,
+    handle: JoinHandle<(Vec<Pool>, PrefetchReport)>,
+}
+
+/// Результат выполнения одной строки
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplOutcome {
+    Output(String),
+    Exit,
+}
+
+impl ReplSession {
+    pub fn new(pools: Vec<Pool>, ctx: ConfigContext) -> Self {
+        ReplSession {
+            ctx,
+            all_pools: pools.clone(),
+            active: pools,
+            snapshots: HashMap::new(),
+            prefetch: None,
+            stats: UsageStats::new(),
+            stats_path: None,
+            stats_saved_at: Instant::now(),
+            stats_dirty: false,
+        }
+    }
+
+    /// Продолжает накопленную статистику и сохраняет ее в `path` (см. `save_stats`)
+    pub fn with_stats(mut self, stats: UsageStats, path: PathBuf) -> Self {
+        self.stats = stats;
+        self.stats_path = Some(path);
+        self
+    }
+
+    /// Запускает фоновое обновление резервов всех пулов в пределах `time_box`
+    ///
+    /// Команды, работающие с пулами, дожидаются окончания предзагрузки
+    /// (не дольше `time_box`), поэтому первая котировка идет по свежим резервам.
+    pub fn start_prefetch(&mut self, time_box: Duration) {
+        let mut pools = self.all_pools.clone();
+        let ctx = self.ctx.clone();
+        let progress = Arc::new(PrefetchProgress::new(pools.len()));
+        let task_progress = Arc::clone(&progress);
+        let handle = tokio::spawn(async move {
+            let report = prefetch_reserves(&mut pools, &ctx, time_box, &task_progress).await;
+            (pools, report)
+        });
+        self.prefetch = Some(PendingPrefetch { progress, handle });
+    }
+
+    /// Строка статуса, пока предзагрузка не закончилась
+    pub fn status_line(&self) -> Option<String> {
+        let prefetch = self.prefetch.as_ref()?;
+        let (done, total) = prefetch.progress.get();
+        Some(format!("предзагрузка {}/{}", done, total))
+    }
+
+    /// Закончилась ли предзагрузка (ее результат еще не применен)
+    pub fn prefetch_finished(&self) -> bool {
+        self.prefetch.as_ref().is_some_and(|prefetch| prefetch.handle.is_finished())
+    }
+
+    /// Дожидается предзагрузки и переносит свежие резервы в пулы сессии
+    ///
+    /// # Returns
+    /// Сводка для вывода или `None`, если предзагрузка не запускалась
+    pub async fn finish_prefetch(&mut self) -> Option<String> {
+        let prefetch = self.prefetch.take()?;
+        let (fresh, report) = match prefetch.handle.await {
+            Ok(result) => result,
+            Err(e) => return Some(format!("Предзагрузка прервана: {}", e)),
+        };
+        for pool in fresh.iter().filter(|pool| report.refreshed.contains(&pool.pool_address)) {
+            let state = pool.snapshot();
+            for target in self.all_pools.iter_mut().chain(self.active.iter_mut()) {
+                if target.pool_address == pool.pool_address {
+                    target.restore(&state);
+                }
+            }
+        }
+
+        let mut summary = format!("Предзагрузка: резервы обновлены для {} из {} пулов", report.refreshed.len(), fresh.len());
+        if report.timed_out {
+            summary.push_str(" (время вышло)");
+        }
+        for (name, error) in &report.failed {
+            summary.push_str(&format!("\n  {}: {}", name, error));
+        }
+        Some(summary)
+    }
+
+    /// Имена всех пулов (для автодополнения)
+    pub fn pool_names(&self) -> Vec<String> {
+        self.all_pools.iter().map(|pool| pool.name.clone()).collect()
+    }
+
+    /// Выполняет одну команду и возвращает текст для вывода
+    pub async fn execute(&mut self, line: &str) -> Result<ReplOutcome> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // Команды над пулами работают с данными предзагрузки
+        let prefetched = if matches!(words.as_slice(), [] | ["help"] | ["exit"] | ["quit"]) {
+            None
+        } else {
+            self.finish_prefetch().await
+        };
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["help"] => HELP.to_string(),
+            ["exit"] | ["quit"] => {
+                self.flush_stats();
+                return Ok(ReplOutcome::Exit);
+            }
+            ["pools"] => self.list_pools(),
+            ["quote", args @ ..] => self.quote(args).await?,
+            ["use", "all"] => {
+                self.active = self.all_pools.clone();
+                format!("Активно пулов: {}", self.active.len())
+            }
+            ["use", "only", filter @ ..] if !filter.is_empty() => self.use_only(&filter.join(" "))?,
+            ["refresh"] => {
+                // Все резервы одним multicall; при его ошибке - по одному пулу.
+                // Обновляются все пулы, чтобы `use all` и `use only` не вернули старые резервы
+                if let Some(client) = self.all_pools.first().map(|pool| pool.client.clone()) {
+                    let failures = refresh_all_reserves(client, &mut self.all_pools).await;
+                    self.sync_active();
+                    self.stats.record_refresh(failures.len());
+                    self.save_stats();
+                    if let Some((address, e)) = failures.into_iter().next() {
+                        return Err(e.wrap_err(format!("не удалось обновить резервы пула {:?}", address)));
+                    }
+                }
+                format!("Резервы обновлены для {} пулов", self.all_pools.len())
+            }
+            ["snapshot", "save", name] => {
+                self.snapshots.insert(name.to_string(), self.active.clone());
+                format!("Снимок {} сохранен", name)
+            }
+            ["snapshot", "restore", name] => {
+                let pools = self.snapshots.get(*name).ok_or_else(|| eyre!("снимок {} не найден", name))?;
+                self.active = pools.clone();
+                format!("Снимок {} восстановлен", name)
+            }
+            ["stats"] => self.stats.format(),
+            ["stats", "reset"] => {
+                self.stats.reset();
+                // Сброс записывается сразу: иначе перезапуск вернул бы старые счетчики
+                self.stats_dirty = true;
+                self.flush_stats();
+                "Статистика сброшена".to_string()
+            }
+            _ => return Err(eyre!("неизвестная команда: {} (см. help)", line.trim())),
+        };
+        let output = match prefetched {
+            Some(summary) => format!("{}\n{}", summary, output),
+            None => output,
+        };
+        Ok(ReplOutcome::Output(output))
+    }
+
+    fn list_pools(&self) -> String {
+        self.active
+            .iter()
+            .map(|pool| format!("{} - {:?} (reserves: {} / {})",
+                pool.name, pool.pool_address, pool.reserve_token0, pool.reserve_token1))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Переносит резервы из `all_pools` в активные пулы с тем же адресом
+    fn sync_active(&mut self) {
+        for target in self.active.iter_mut() {
+            if let Some(pool) = self.all_pools.iter().find(|pool| pool.pool_address == target.pool_address) {
+                *target = pool.clone();
+            }
+        }
+    }
+
+    fn use_only(&mut self, filter: &str) -> Result<String> {
+        let filter = filter.to_lowercase();
+        let selected: Vec<Pool> = self
+            .all_pools
+            .iter()
+            .filter(|pool| pool.name.to_lowercase().contains(&filter))
+            .cloned()
+            .collect();
+        if selected.is_empty() {
+            return Err(eyre!("нет пулов, подходящих под {}", filter));
+        }
+        self.active = selected;
+        Ok(format!("Активно пулов: {}", self.active.len()))
+    }
+
+    /// Отмечает изменение статистики и сохраняет ее, если с прошлой записи
+    /// прошло `STATS_SAVE_INTERVAL_SECS`: котировка не ждет записи файла
+    fn save_stats(&mut self) {
+        self.stats_dirty = true;
+        if self.stats_saved_at.elapsed() >= Duration::from_secs(config::STATS_SAVE_INTERVAL_SECS) {
+            self.flush_stats();
+        }
+    }
+
+    /// Записывает несохраненные изменения статистики, если задан файл;
+    /// ошибка записи не прерывает сессию
+    pub fn flush_stats(&mut self) {
+        let Some(path) = self.stats_path.as_ref().filter(|_| self.stats_dirty) else {
+            return;
+        };
+        if let Err(e) = self.stats.save(path) {
+            log!("Не удалось сохранить статистику {}: {}", path.display(), e);
+        }
+        self.stats_dirty = false;
+        self.stats_saved_at = Instant::now();
+    }
+
+    /// Decimals токена из пулов сессии (или из метаданных токена)
+    fn token_decimals(&self, token: config::TokenId) -> u8 {
+        self.all_pools
+            .iter()
+            .filter(|pool| pool.other_token(token).is_some())
+            .find_map(|pool| pool.known_decimals(token))
+            .unwrap_or_else(|| token.decimals())
+    }
+
+    async fn quote(&mut self, args: &[&str]) -> Result<String> {
+        let (amount, chunks) = match args {
+            [amount] => (*amount, None),
+            [amount, "--chunks", chunks] => (*amount, Some(*chunks)),
+            _ => return Err(eyre!("использование: quote <USDC> [--chunks N]")),
+        };
+        let mut solver_config = SolverConfig {
+            total_amount_in: config::parse_units(amount, config::USDC_DECIMALS)?,
+            verbose: false,
+            ..SolverConfig::from_context(&self.ctx)
+        };
+        if let Some(chunks) = chunks {
+            solver_config.num_chunks = chunks.parse().map_err(|_| eyre!("некорректное количество чанков: {}", chunks))?;
+        }
+
+        // Котируем на копии, чтобы теплый набор пулов не менялся
+        let result = find_best_routes(Pool::states(&self.active), &self.ctx, &solver_config).await?;
+        let token_in = (config::TokenId::USDC, self.token_decimals(config::TokenId::USDC));
+        let token_out = (self.ctx.output_token, self.token_decimals(self.ctx.output_token));
+        self.stats.record_quote(token_in, token_out, solver_config.total_amount_in, &result);
+        self.save_stats();
+
+        let mut lines = vec![format!(
+            "{} USDC -> {} WETH (чанков: {}, impact: {:.4}%)",
+            config::format_units(solver_config.total_amount_in, config::USDC_DECIMALS),
+            config::format_units(result.total_weth_out, config::WETH_DECIMALS),
+            result.chunk_routes.len(),
+            result.cumulative_price_impact * 100.0
+        )];
+        // Пулы различаются по адресу: имена с одинаковыми символами не сливаются
+        lines.extend(pool_usage(&result.chunk_routes).into_iter().map(|usage| format!("  {}: {}", usage.pool_name, usage.chunks)));
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Автодополнение команд и имен пулов
+struct ReplHelper {
+    candidates: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |index| index + 1);
+        let prefix = line[start..pos].to_lowercase();
+        let matches = self
+            .candidates
+            .iter()
+            .filter(|candidate| candidate.to_lowercase().starts_with(&prefix))
+            .map(|candidate| Pair { display: candidate.clone(), replacement: candidate.clone() })
+            .collect();
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Запускает интерактивный цикл с историей команд и автодополнением
+pub async fn run(mut session: ReplSession) -> Result<()> {
+    let mut candidates: Vec<String> = COMMANDS.iter().map(|command| command.to_string()).collect();
+    candidates.extend(session.pool_names());
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper { candidates }));
+    println!("{}", HELP);
+
+    loop {
+        if session.prefetch_finished() {
+            if let Some(summary) = session.finish_prefetch().await {
+                println!("{}", summary);
+            }
+        }
+        let prompt = match session.status_line() {
+            Some(status) => format!("swap [{}]> ", status),
+            None => "swap> ".to_string(),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        editor.add_history_entry(line.as_str())?;
+
+        match session.execute(&line).await {
+            Ok(ReplOutcome::Exit) => break,
+            Ok(ReplOutcome::Output(output)) if !output.is_empty() => println!("{}", output),
+            Ok(ReplOutcome::Output(_)) => {}
+            Err(err) => println!("Ошибка: {}", err),
+        }
+    }
+
+    session.flush_stats();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenId;
+    use alloy::primitives::{Address, U256};
+
+    fn session() -> ReplSession {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let quickswap = Pool::for_test(Address::repeat_byte(0x11), TokenId::USDC, TokenId::WETH,
+            U256::from(3_000_000_000_000u64), U256::from(1_200u64) * weth, "Quickswap USDC/WETH");
+        let sushiswap = Pool::for_test(Address::repeat_byte(0x22), TokenId::USDC, TokenId::WETH,
+            U256::from(800_000_000_000u64), U256::from(330u64) * weth, "Sushiswap USDC/WETH");
+        ReplSession::new(vec![quickswap, sushiswap], ConfigContext::default())
+    }
+
+    async fn output(session: &mut ReplSession, line: &str) -> String {
+        match session.execute(line).await.unwrap() {
+            ReplOutcome::Output(output) => output,
+            ReplOutcome::Exit => panic!("неожиданный выход на {}", line),
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_accumulate_across_restarts_until_reset() {
+        let path = std::env::temp_dir().join(format!("swap_aggregator_repl_stats_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut first = session().with_stats(UsageStats::load(&path).unwrap(), path.clone());
+        assert!(output(&mut first, "stats").await.contains("Котировок не было"));
+        output(&mut first, "quote 5000").await;
+        output(&mut first, "quote 1000").await;
+        assert!(output(&mut first, "stats").await.contains("котировок 2, объем 6000.000000 -> "));
+        // Котировки не пишут файл сразу - только при выходе или по интервалу
+        assert!(!path.exists());
+        assert_eq!(first.execute("exit").await.unwrap(), ReplOutcome::Exit);
+
+        // Перезапуск: счетчики продолжаются из файла
+        let mut second = session().with_stats(UsageStats::load(&path).unwrap(), path.clone());
+        output(&mut second, "quote 4000").await;
+        let report = output(&mut second, "stats").await;
+        assert!(report.contains("котировок 3, объем 10000.000000 -> "), "{}", report);
+        assert!(report.contains("Пулы по объему:\n  Sushiswap USDC/WETH"), "{}", report);
+        assert!(report.contains(" USDC, чанков "), "{}", report);
+
+        assert_eq!(output(&mut second, "stats reset").await, "Статистика сброшена");
+        assert!(UsageStats::load(&path).unwrap().pairs.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scripted_session() {
+        let mut session = session();
+
+        let pools = output(&mut session, "pools").await;
+        assert!(pools.contains("Quickswap USDC/WETH") && pools.contains("Sushiswap USDC/WETH"));
+
+        let both = output(&mut session, "quote 5000").await;
+        assert!(both.starts_with("5000.000000 USDC -> "));
+        // Котировка не меняет теплые резервы
+        assert_eq!(output(&mut session, "quote 5000").await, both);
+
+        let chunked = output(&mut session, "quote 5000 --chunks 10").await;
+        assert!(chunked.contains("чанков: 10"));
+
+        assert_eq!(output(&mut session, "use only quickswap").await, "Активно пулов: 1");
+        let quickswap_only = output(&mut session, "quote 5000").await;
+        assert!(!quickswap_only.contains("Sushiswap"));
+
+        assert_eq!(output(&mut session, "snapshot save s1").await, "Снимок s1 сохранен");
+        assert_eq!(output(&mut session, "use all").await, "Активно пулов: 2");
+        assert_eq!(output(&mut session, "snapshot restore s1").await, "Снимок s1 восстановлен");
+        assert_eq!(output(&mut session, "quote 5000").await, quickswap_only);
+
+        assert!(session.execute("snapshot restore missing").await.is_err());
+        assert!(session.execute("frobnicate").await.is_err());
+        assert!(session.execute("quote abc").await.is_err());
+        // Та же ошибка точности, что у swap_aggregator convert
+        let error = session.execute("quote 1.0000001").await.unwrap_err();
+        assert_eq!(error.to_string(), config::UnitsError::TooPrecise { value: "1.0000001".to_string(), decimals: 6 }.to_string());
+        assert_eq!(session.execute("exit").await.unwrap(), ReplOutcome::Exit);
+    }
+
+    #[tokio::test]
+    async fn first_command_uses_prefetched_reserves() {
+        use crate::mock_rpc::{call_target, MockRpc};
+
+        // Пул 0x11 обновляется, пул 0x22 отвечает ошибкой и остается с резервами discovery
+        let rpc = MockRpc::start(|_, params| {
+            if call_target(params) == Some(alloy::primitives::Address::repeat_byte(0x11)) {
+                Ok(serde_json::json!(format!("0x{}{}{}",
+                    alloy::hex::encode(U256::from(1_234u64).to_be_bytes::<32>()),
+                    alloy::hex::encode(U256::from(5_678u64).to_be_bytes::<32>()),
+                    alloy::hex::encode(U256::ZERO.to_be_bytes::<32>()))))
+            } else {
+                Err("execution reverted".to_string())
+            }
+        }).await;
+        let mut session = session();
+        for pool in session.all_pools.iter_mut().chain(session.active.iter_mut()) {
+            pool.client = rpc.provider.clone();
+        }
+
+        session.start_prefetch(Duration::from_secs(10));
+        assert!(session.status_line().unwrap().starts_with("предзагрузка "));
+        // help не трогает пулы и не ждет предзагрузку
+        assert_eq!(output(&mut session, "help").await, HELP);
+
+        let pools = output(&mut session, "pools").await;
+        assert!(pools.starts_with("Предзагрузка: резервы обновлены для 1 из 2 пулов"), "{}", pools);
+        assert!(pools.contains("  Sushiswap USDC/WETH: ") && pools.contains("execution reverted"), "{}", pools);
+        assert!(pools.contains("(reserves: 1234 / 5678)"), "{}", pools);
+        assert!(session.status_line().is_none());
+
+        assert_eq!(output(&mut session, "use only quickswap").await, "Активно пулов: 1");
+        assert!(output(&mut session, "pools").await.contains("(reserves: 1234 / 5678)"));
+    }
+
+    #[tokio::test]
+    async fn refresh_then_use_all_keeps_fresh_reserves() {
+        use crate::mock_chain::MockChainClient;
+
+        let client = MockChainClient::new();
+        client.set_reserves(Address::repeat_byte(0x11), (U256::from(1_111u64), U256::from(2_222u64), 0));
+        client.set_reserves(Address::repeat_byte(0x22), (U256::from(3_333u64), U256::from(4_444u64), 0));
+        let mut session = session();
+        for pool in session.all_pools.iter_mut().chain(session.active.iter_mut()) {
+            pool.client = client.clone();
+        }
+
+        // Обновление при фильтре затрагивает и неактивные пулы
+        assert_eq!(output(&mut session, "use only quickswap").await, "Активно пулов: 1");
+        assert_eq!(output(&mut session, "refresh").await, "Резервы обновлены для 2 пулов");
+        assert!(output(&mut session, "pools").await.contains("(reserves: 1111 / 2222)"));
+
+        assert_eq!(output(&mut session, "use all").await, "Активно пулов: 2");
+        let pools = output(&mut session, "pools").await;
+        assert!(pools.contains("(reserves: 1111 / 2222)") && pools.contains("(reserves: 3333 / 4444)"), "{}", pools);
+        assert_eq!(output(&mut session, "use only sushiswap").await, "Активно пулов: 1");
+        assert!(output(&mut session, "pools").await.contains("(reserves: 3333 / 4444)"));
+    }
+}
@@ -0,0 +1,353 @@
+// src/reserve_recorder.rs
+//! Временной ряд резервов пулов в режиме `--watch` (`--record-reserves`)
+//!
+//! Каждое примененное событие Sync превращается в `ReserveSample` и уходит
+//! в ограниченную очередь фоновой задачи записи. Наблюдатель никогда не ждет
+//! диска: если очередь заполнена, образец отбрасывается и учитывается в
+//! счетчике `dropped`. Задача пишет по CSV-файлу на пул и начинает новый
+//! файл при превышении размера или возраста (`Rotation`). Открытый файл
+//! имеет суффикс `.partial` и получает итоговое имя только после сброса на
+//! диск - при ротации или в `ReserveRecorder::finish`, поэтому читатель
+//! каталога видит лишь законченные файлы.
+use crate::config::{RESERVE_RECORD_MAX_BYTES, RESERVE_RECORD_MAX_SECS, RESERVE_RECORD_QUEUE};
+use crate::log;
+use crate::pool::PoolState;
+use alloy::primitives::{Address, U256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+/// Заголовок каждого файла ряда
+pub const RESERVE_CSV_HEADER: &str = "block,timestamp,reserve0,reserve1,price_token1_in_token0";
+
+/// Расширение файла, который еще пишется
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// Состояние пула после одного события
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReserveSample {
+    pub pool_address: Address,
+    pub block_number: u64,
+    pub timestamp: u64, // Unix-время получения события, с
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub spot_price: Option<f64>, // `price_token1_in_token0_f64`; `None` для пустого пула
+}
+
+impl ReserveSample {
+    /// Образец из текущего состояния пула
+    pub fn from_pool(pool: &PoolState, block_number: u64, timestamp: u64) -> Self {
+        ReserveSample {
+            pool_address: pool.pool_address,
+            block_number,
+            timestamp,
+            reserve0: pool.reserve_token0,
+            reserve1: pool.reserve_token1,
+            spot_price: pool.price_token1_in_token0_f64(),
+        }
+    }
+
+    /// Строка CSV без перевода строки; пустая цена - пустое поле
+    fn csv_row(&self) -> String {
+        let price = self.spot_price.map(|price| price.to_string()).unwrap_or_default();
+        format!("{},{},{},{},{}", self.block_number, self.timestamp, self.reserve0, self.reserve1, price)
+    }
+}
+
+/// Когда начинать новый файл пула
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: u64,     // Размер файла, после которого он закрывается
+    pub max_age: Duration,  // Возраст файла, после которого он закрывается при следующей записи
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation { max_bytes: RESERVE_RECORD_MAX_BYTES, max_age: Duration::from_secs(RESERVE_RECORD_MAX_SECS) }
+    }
+}
+
+/// Итог записи после `finish`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecorderSummary {
+    pub written: u64,        // Записано образцов
+    pub dropped: u64,        // Отброшено из-за заполненной очереди или остановленной записи
+    pub files: Vec<PathBuf>, // Законченные файлы в порядке закрытия
+}
+
+/// Фоновая запись ряда резервов в каталог
+pub struct ReserveRecorder {
+    sender: mpsc::Sender<ReserveSample>,
+    dropped: Arc<AtomicU64>,
+    task: JoinHandle<io::Result<RecorderSummary>>,
+}
+
+impl ReserveRecorder {
+    /// Создает каталог и запускает задачу записи с очередью `RESERVE_RECORD_QUEUE`
+    pub fn start(dir: &Path, rotation: Rotation) -> io::Result<Self> {
+        Self::with_queue(dir, rotation, RESERVE_RECORD_QUEUE)
+    }
+
+    /// То же с очередью на `capacity` образцов
+    pub fn with_queue(dir: &Path, rotation: Rotation, capacity: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let (sender, mut receiver) = mpsc::channel(capacity.max(1));
+        let mut writer = SeriesWriter { dir: dir.to_path_buf(), rotation, files: HashMap::new(), summary: RecorderSummary::default() };
+        // Запись в файлы блокирующая, поэтому задача живет в пуле blocking-потоков
+        let task = tokio::task::spawn_blocking(move || {
+            while let Some(sample) = receiver.blocking_recv() {
+                // Ошибка видна сразу, а не только в `finish`: дальше образцы отбрасываются
+                if let Err(e) = writer.write(&sample) {
+                    log!("Запись ряда резервов в {} остановлена: {}", writer.dir.display(), e);
+                    return Err(e);
+                }
+            }
+            writer.close_all()
+        });
+        Ok(ReserveRecorder { sender, dropped: Arc::new(AtomicU64::new(0)), task })
+    }
+
+    /// Ставит образец в очередь, не дожидаясь записи
+    ///
+    /// `false` - очередь заполнена или запись остановлена ошибкой; образец
+    /// отброшен и учтен в `dropped`.
+    pub fn record(&self, sample: ReserveSample) -> bool {
+        match self.sender.try_send(sample) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Отброшено образцов с момента запуска
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Дописывает очередь, закрывает файлы и возвращает итог
+    pub async fn finish(self) -> eyre::Result<RecorderSummary> {
+        drop(self.sender);
+        let mut summary = self.task.await??;
+        summary.dropped = self.dropped.load(Ordering::Relaxed);
+        Ok(summary)
+    }
+}
+
+/// Открытый файл ряда одного пула
+struct SeriesFile {
+    writer: BufWriter<File>,
+    partial: PathBuf,
+    path: PathBuf,
+    bytes: u64,
+    opened: Instant,
+}
+
+impl SeriesFile {
+    /// Новый файл `<пул>-<первый блок>-<часть>.csv.partial` с заголовком
+    fn create(dir: &Path, pool_address: Address, first_block: u64) -> io::Result<Self> {
+        // Часть растет, пока имя занято: файлы прошлых запусков не перезаписываются
+        let (path, partial) = (0u32..)
+            .map(|part| dir.join(format!("{:#x}-{:010}-{:03}.csv", pool_address, first_block, part)))
+            .map(|path| {
+                let partial = path.with_extension(format!("csv.{}", PARTIAL_EXTENSION));
+                (path, partial)
+            })
+            .find(|(path, partial)| !path.exists() && !partial.exists())
+            .expect("свободное имя файла");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        writeln!(writer, "{}", RESERVE_CSV_HEADER)?;
+        Ok(SeriesFile { writer, partial, path, bytes: RESERVE_CSV_HEADER.len() as u64 + 1, opened: Instant::now() })
+    }
+
+    fn expired(&self, rotation: &Rotation) -> bool {
+        self.bytes >= rotation.max_bytes || self.opened.elapsed() >= rotation.max_age
+    }
+
+    /// Сбрасывает файл на диск и дает ему итоговое имя
+    fn close(mut self) -> io::Result<PathBuf> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        fs::rename(&self.partial, &self.path)?;
+        Ok(self.path)
+    }
+}
+
+/// Состояние задачи записи
+struct SeriesWriter {
+    dir: PathBuf,
+    rotation: Rotation,
+    files: HashMap<Address, SeriesFile>,
+    summary: RecorderSummary,
+}
+
+impl SeriesWriter {
+    fn write(&mut self, sample: &ReserveSample) -> io::Result<()> {
+        if self.files.get(&sample.pool_address).is_some_and(|file| file.expired(&self.rotation)) {
+            let file = self.files.remove(&sample.pool_address).expect("файл пула открыт");
+            self.summary.files.push(file.close()?);
+        }
+        let file = match self.files.entry(sample.pool_address) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert(SeriesFile::create(&self.dir, sample.pool_address, sample.block_number)?)
+            }
+        };
+        let row = sample.csv_row();
+        writeln!(file.writer, "{}", row)?;
+        file.bytes += row.len() as u64 + 1;
+        self.summary.written += 1;
+        Ok(())
+    }
+
+    fn close_all(mut self) -> io::Result<RecorderSummary> {
+        let mut files: Vec<SeriesFile> = self.files.into_values().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        for file in files {
+            self.summary.files.push(file.close()?);
+        }
+        Ok(self.summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("swap_aggregator_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn sample(pool_byte: u8, block: u64) -> ReserveSample {
+        ReserveSample {
+            pool_address: Address::repeat_byte(pool_byte),
+            block_number: block,
+            timestamp: 1_700_000_000 + block,
+            reserve0: U256::from(1_000 + block),
+            reserve1: U256::from(2_000 + block),
+            spot_price: block.is_multiple_of(2).then_some(0.5),
+        }
+    }
+
+    /// Строки данных всех файлов пула в порядке имен (заголовок проверяется)
+    fn read_series(dir: &Path, pool_byte: u8) -> (usize, Vec<Vec<String>>) {
+        let prefix = format!("{:#x}-", Address::repeat_byte(pool_byte));
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with(&prefix))
+            .collect();
+        paths.sort();
+        let mut rows = Vec::new();
+        for path in &paths {
+            let content = fs::read_to_string(path).unwrap();
+            let mut lines = content.lines();
+            assert_eq!(lines.next(), Some(RESERVE_CSV_HEADER), "{}", path.display());
+            rows.extend(lines.map(|line| line.split(',').map(str::to_string).collect::<Vec<_>>()));
+        }
+        (paths.len(), rows)
+    }
+
+    #[tokio::test]
+    async fn writes_series_per_pool_and_reads_back_in_order() {
+        let dir = temp_dir("reserve_series");
+        let recorder = ReserveRecorder::start(&dir, Rotation::default()).unwrap();
+        for block in 100..110 {
+            assert!(recorder.record(sample(0x11, block)));
+            if block.is_multiple_of(3) {
+                assert!(recorder.record(sample(0x12, block)));
+            }
+        }
+        let summary = recorder.finish().await.unwrap();
+        assert_eq!(summary.written, 13);
+        assert_eq!(summary.dropped, 0);
+        assert_eq!(summary.files.len(), 2);
+
+        // Незаконченных файлов не осталось
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| entry.unwrap().path().extension().unwrap() == "csv"));
+
+        let (files, rows) = read_series(&dir, 0x11);
+        assert_eq!(files, 1);
+        let blocks: Vec<u64> = rows.iter().map(|row| row[0].parse().unwrap()).collect();
+        assert_eq!(blocks, (100..110).collect::<Vec<_>>());
+        assert_eq!(rows[0], ["100", "1700000100", "1100", "2100", "0.5"]);
+        assert_eq!(rows[1], ["101", "1700000101", "1101", "2101", ""]);
+        assert!(rows.iter().all(|row| row.len() == RESERVE_CSV_HEADER.split(',').count()));
+
+        let (_, rows) = read_series(&dir, 0x12);
+        let blocks: Vec<&str> = rows.iter().map(|row| row[0].as_str()).collect();
+        assert_eq!(blocks, ["102", "105", "108"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rotates_by_size_without_losing_order() {
+        let dir = temp_dir("reserve_rotation");
+        // Заголовок уже больше предела: каждый файл получает ровно одну строку
+        let rotation = Rotation { max_bytes: 10, max_age: Duration::from_secs(3600) };
+        let recorder = ReserveRecorder::start(&dir, rotation).unwrap();
+        for block in 1..=5 {
+            recorder.record(sample(0x11, block));
+        }
+        let summary = recorder.finish().await.unwrap();
+        assert_eq!(summary.files.len(), 5);
+
+        let (files, rows) = read_series(&dir, 0x11);
+        assert_eq!(files, 5);
+        let blocks: Vec<u64> = rows.iter().map(|row| row[0].parse().unwrap()).collect();
+        assert_eq!(blocks, [1, 2, 3, 4, 5]);
+
+        // Повторный запуск с теми же блоками не перезаписывает файлы
+        let recorder = ReserveRecorder::start(&dir, rotation).unwrap();
+        recorder.record(sample(0x11, 1));
+        recorder.finish().await.unwrap();
+        assert_eq!(read_series(&dir, 0x11).0, 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_queue_drops_and_counts_instead_of_blocking() {
+        let dir = temp_dir("reserve_backpressure");
+        let recorder = ReserveRecorder::with_queue(&dir, Rotation::default(), 1).unwrap();
+        let sent = 2_000;
+        let accepted = (0..sent).filter(|block| recorder.record(sample(0x11, *block))).count() as u64;
+        assert_eq!(recorder.dropped(), sent - accepted);
+        let summary = recorder.finish().await.unwrap();
+        assert_eq!(summary.written, accepted);
+        assert_eq!(summary.written + summary.dropped, sent);
+
+        // Отброшенные образцы выпадают из ряда, но порядок оставшихся сохраняется
+        let (_, rows) = read_series(&dir, 0x11);
+        let blocks: Vec<u64> = rows.iter().map(|row| row[0].parse().unwrap()).collect();
+        assert_eq!(blocks.len() as u64, accepted);
+        assert!(blocks.windows(2).all(|pair| pair[0] < pair[1]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_error_stops_recording_before_finish() {
+        let dir = temp_dir("reserve_write_error");
+        let recorder = ReserveRecorder::start(&dir, Rotation::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(recorder.record(sample(0x11, 1)));
+        // Задача останавливается на первой ошибке: следующие образцы отбрасываются
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while recorder.record(sample(0x11, 2)) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(recorder.dropped() >= 1);
+        assert!(recorder.finish().await.is_err());
+    }
+}
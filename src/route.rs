@@ -0,0 +1,225 @@
+// src/route.rs
+use alloy::primitives::U256;
+use std::cmp::Ordering;
+use crate::config::TokenId;
+use crate::math::{get_amounts_out, mul_div, BPS_DENOMINATOR};
+use crate::pool::PoolState;
+
+/// Один шаг маршрута: пул и направление свапа через него
+#[derive(Debug, Clone, Copy)]
+pub struct RouteHop<'a> {
+    pub pool: &'a PoolState,
+    pub input_is_token0: bool,
+}
+
+/// Многошаговый маршрут через последовательность пулов (например, USDC -> USDT -> WETH)
+#[derive(Debug, Clone)]
+pub struct Route<'a> {
+    pub hops: Vec<RouteHop<'a>>,
+}
+
+impl<'a> Route<'a> {
+    /// Строит маршрут по списку пулов, начиная с входного токена
+    /// 
+    /// Направление каждого шага определяется автоматически: выходной токен
+    /// предыдущего пула должен присутствовать в следующем.
+    /// 
+    /// # Arguments
+    /// * `pools` - Пулы в порядке прохождения
+    /// * `token_in` - Входной токен первого пула
+    /// 
+    /// # Returns
+    /// Маршрут или None, если пулы не образуют непрерывный путь
+    pub fn new(pools: &[&'a PoolState], token_in: TokenId) -> Option<Self> {
+        let mut hops = Vec::with_capacity(pools.len());
+        let mut current = token_in;
+
+        for &pool in pools {
+            let next = pool.other_token(current)?;
+            hops.push(RouteHop { pool, input_is_token0: pool.token0 == current });
+            current = next;
+        }
+
+        Some(Route { hops })
+    }
+
+    /// Токены маршрута по порядку, включая входной и выходной
+    pub fn tokens(&self) -> Vec<TokenId> {
+        let mut tokens = Vec::with_capacity(self.hops.len() + 1);
+        for hop in &self.hops {
+            let (token_in, token_out) = if hop.input_is_token0 {
+                (hop.pool.token0, hop.pool.token1)
+            } else {
+                (hop.pool.token1, hop.pool.token0)
+            };
+            if tokens.is_empty() {
+                tokens.push(token_in);
+            }
+            tokens.push(token_out);
+        }
+        tokens
+    }
+
+    /// Промежуточные суммы по всем шагам (как `getAmountsOut` у роутера)
+    pub fn amounts_out(&self, amount_in: U256) -> Vec<U256> {
+        let path: Vec<(U256, U256, u32)> = self
+            .hops
+            .iter()
+            .map(|hop| {
+                let (reserve_in, reserve_out) = hop.pool.reserves_for(hop.input_is_token0);
+                (reserve_in, reserve_out, hop.pool.fee_bps)
+            })
+            .collect();
+        get_amounts_out(amount_in, &path)
+    }
+
+    /// Итоговый выход маршрута для заданного входа
+    pub fn quote(&self, amount_in: U256) -> U256 {
+        self.amounts_out(amount_in).last().copied().unwrap_or(amount_in)
+    }
+}
+
+/// Кандидат маршрута для чанка при ранжировании
+///
+/// `route_id` - позиции пулов маршрута в списке пулов солвера, по шагам;
+/// прямой пул - маршрут из одного шага.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteRank<'a> {
+    pub output: U256,
+    pub route_id: &'a [usize],
+}
+
+impl RouteRank<'_> {
+    /// Количество шагов (пулов) маршрута
+    pub fn hops(&self) -> usize {
+        self.route_id.len()
+    }
+
+    /// Выход за вычетом штрафа `hop_penalty_bps` за каждый шаг сверх первого
+    /// (суммарный штраф не больше 100%)
+    pub fn net_output(&self, hop_penalty_bps: u32) -> U256 {
+        let extra_hops = self.hops().saturating_sub(1) as u64;
+        let penalty_bps = (u64::from(hop_penalty_bps) * extra_hops).min(u64::from(BPS_DENOMINATOR));
+        let denominator = U256::from(BPS_DENOMINATOR);
+        // Результат не больше выхода, поэтому mul_div не переполняется
+        mul_div(self.output, denominator - U256::from(penalty_bps), denominator).unwrap_or(U256::ZERO)
+    }
+}
+
+/// Сравнивает кандидатов маршрута для чанка: `Ordering::Greater` - `a` лучше
+///
+/// Порядок критериев:
+/// 1. выход за вычетом штрафа за лишние шаги (`RouteRank::net_output`) - больше лучше;
+/// 2. количество шагов - меньше лучше: при точном равенстве прямой пул
+///    исполняется дешевле по газу и с меньшим риском, чем маршрут через
+///    промежуточный токен;
+/// 3. `route_id` лексикографически - меньше лучше. Солвер перебирает пулы от
+///    самых глубоких, поэтому при полном равенстве чанк достается более
+///    глубокому пулу.
+///
+/// Сравнение антисимметрично: `compare_routes(a, b) == compare_routes(b, a).reverse()`,
+/// так что выбор не зависит от порядка кандидатов.
+pub fn compare_routes(a: &RouteRank, b: &RouteRank, hop_penalty_bps: u32) -> Ordering {
+    a.net_output(hop_penalty_bps)
+        .cmp(&b.net_output(hop_penalty_bps))
+        .then_with(|| b.hops().cmp(&a.hops()))
+        .then_with(|| b.route_id.cmp(a.route_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::test_pool;
+    use alloy::primitives::Address;
+
+    #[test]
+    fn two_hop_route_matches_manual_computation() {
+        let usdt = TokenId(Address::repeat_byte(0xc2));
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+
+        let usdc_usdt = test_pool(0x01, TokenId::USDC, usdt, U256::from(5_000_000_000_000u64), U256::from(4_990_000_000_000u64));
+        let usdt_weth = test_pool(0x02, usdt, TokenId::WETH, U256::from(3_000_000_000_000u64), U256::from(1_200u64) * weth);
+
+        let route = Route::new(&[&usdc_usdt, &usdt_weth], TokenId::USDC).unwrap();
+        assert_eq!(route.tokens(), vec![TokenId::USDC, usdt, TokenId::WETH]);
+
+        let amount_in = U256::from(10_000_000_000u64);
+        let amounts = route.amounts_out(amount_in);
+
+        // Пошаговый расчет вручную
+        let usdc_is_token0 = usdc_usdt.token0 == TokenId::USDC;
+        let first = usdc_usdt.get_amount_out(amount_in, usdc_is_token0);
+        let usdt_is_token0 = usdt_weth.token0 == usdt;
+        let second = usdt_weth.get_amount_out(first, usdt_is_token0);
+
+        assert_eq!(amounts, vec![amount_in, first, second]);
+        assert_eq!(route.quote(amount_in), second);
+    }
+
+    #[test]
+    fn two_hop_route_matches_token_addressed_quotes() {
+        let usdt = TokenId(Address::repeat_byte(0xc2));
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+
+        let usdc_usdt = test_pool(0x01, TokenId::USDC, usdt, U256::from(5_000_000_000_000u64), U256::from(4_990_000_000_000u64));
+        let usdt_weth = test_pool(0x02, usdt, TokenId::WETH, U256::from(3_000_000_000_000u64), U256::from(1_200u64) * weth);
+        let route = Route::new(&[&usdc_usdt, &usdt_weth], TokenId::USDC).unwrap();
+
+        let amount_in = U256::from(10_000_000_000u64);
+        let first = usdc_usdt.quote_by_token(TokenId::USDC, amount_in).unwrap();
+        let second = usdt_weth.quote_by_token(usdt, first).unwrap();
+        assert_eq!(route.amounts_out(amount_in), vec![amount_in, first, second]);
+    }
+
+    #[test]
+    fn disconnected_route_is_rejected() {
+        let usdt = TokenId(Address::repeat_byte(0xc2));
+        let dai = TokenId(Address::repeat_byte(0xd1));
+
+        let usdc_usdt = test_pool(0x01, TokenId::USDC, usdt, U256::from(1u64), U256::from(1u64));
+        let dai_weth = test_pool(0x02, dai, TokenId::WETH, U256::from(1u64), U256::from(1u64));
+
+        assert!(Route::new(&[&usdc_usdt, &dai_weth], TokenId::USDC).is_none());
+        assert!(Route::new(&[&usdc_usdt], TokenId::WETH).is_none());
+    }
+
+    #[test]
+    fn exact_tie_prefers_fewer_hops_in_both_orders() {
+        let output = U256::from(1_000_000u64);
+        let direct = RouteRank { output, route_id: &[3] };
+        let two_hop = RouteRank { output, route_id: &[0, 1] };
+
+        // Без штрафа выходы равны, решает количество шагов, а не порядок или id
+        assert_eq!(compare_routes(&direct, &two_hop, 0), Ordering::Greater);
+        assert_eq!(compare_routes(&two_hop, &direct, 0), Ordering::Less);
+
+        // При равных шагах меньший id (более глубокий пул) лучше
+        let deeper = RouteRank { output, route_id: &[1] };
+        assert_eq!(compare_routes(&deeper, &direct, 0), Ordering::Greater);
+        assert_eq!(compare_routes(&direct, &deeper, 0), Ordering::Less);
+        assert_eq!(compare_routes(&direct, &direct, 0), Ordering::Equal);
+    }
+
+    #[test]
+    fn near_tie_is_decided_by_hop_penalty() {
+        let direct = RouteRank { output: U256::from(1_000_000u64), route_id: &[0] };
+
+        // Двухшаговый маршрут лучше на 10 bps: штраф 9 bps его не останавливает, 10 bps - уже да
+        let better_two_hop = RouteRank { output: U256::from(1_001_000u64), route_id: &[1, 2] };
+        assert_eq!(compare_routes(&better_two_hop, &direct, 9), Ordering::Greater);
+        assert_eq!(compare_routes(&direct, &better_two_hop, 9), Ordering::Less);
+        assert_eq!(compare_routes(&better_two_hop, &direct, 10), Ordering::Less);
+        assert_eq!(compare_routes(&direct, &better_two_hop, 10), Ordering::Greater);
+
+        // На 1 raw unit лучше без штрафа: выход важнее количества шагов в обе стороны
+        let marginal_two_hop = RouteRank { output: U256::from(1_000_001u64), route_id: &[1, 2] };
+        assert_eq!(compare_routes(&marginal_two_hop, &direct, 0), Ordering::Greater);
+        let marginal_direct = RouteRank { output: U256::from(1_000_001u64), route_id: &[3] };
+        let two_hop = RouteRank { output: U256::from(1_000_000u64), route_id: &[0, 1] };
+        assert_eq!(compare_routes(&marginal_direct, &two_hop, 0), Ordering::Greater);
+
+        // Суммарный штраф ограничен 100%
+        let long = RouteRank { output: U256::from(1_000_000u64), route_id: &[0, 1, 2] };
+        assert_eq!(long.net_output(u32::MAX), U256::ZERO);
+    }
+}
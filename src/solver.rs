@@ -1,129 +1,2418 @@
 // src/solver.rs
-use crate::{config, math, provider};
+use crate::log;
+use crate::config::{self, ConfigContext, DexId, DisplayAmount, TokenId};
+use crate::amm::{AmmPool, SimulationFidelity};
+use crate::chunk_guard::{spot_deviation_bps, ChunkGuard, ChunkGuardAbort, GuardAction, GuardEvent, GuardFallback, GuardRemedy};
+use crate::impact::{ImpactModel, ImpactModelChoice};
+use crate::math;
+use crate::math::accumulator::{accumulate, scale, AggregationError, Accumulator};
+use crate::pool::{PoolError, PoolState};
+use crate::route::{compare_routes, RouteRank};
 use alloy::primitives::{Address, U256};
-use eyre::Result;
-use std::sync::Arc;
-use tokio::task::JoinSet;
-use alloy::providers::RootProvider;
-use alloy::transports::http::{Client, Http};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Печатает сообщение солвера, если подробный вывод включен в конфигурации
+macro_rules! solver_log {
+    ($solver_config:expr, $($arg:tt)*) => {
+        if $solver_config.verbose {
+            log!($($arg)*);
+        }
+    };
+}
+
+/// Ошибки валидации параметров солвера
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SolverError {
+    /// Общая сумма обмена равна нулю
+    ZeroAmount,
+    /// Количество чанков равно нулю
+    ZeroChunks,
+    /// Скидка на резервы больше 100% (в базисных пунктах)
+    InvalidHaircut(u32),
+    /// Допустимое проскальзывание больше 100% (в базисных пунктах)
+    InvalidSlippage(u32),
+    /// Порог закрепления больше 100% (в базисных пунктах)
+    InvalidCommitThreshold(u32),
+    /// Разброс размеров чанков 100% и больше (в базисных пунктах): чанк мог бы стать нулевым
+    InvalidChunkJitter(u32),
+    /// Предел отклонения цены чанка больше 100% (в базисных пунктах)
+    InvalidChunkGuard(u32),
+}
+
+impl fmt::Display for SolverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolverError::ZeroAmount => write!(f, "сумма обмена должна быть больше нуля"),
+            SolverError::ZeroChunks => write!(f, "количество чанков должно быть больше нуля"),
+            SolverError::InvalidHaircut(bps) => write!(f, "скидка на резервы {} bps превышает 10000 bps", bps),
+            SolverError::InvalidSlippage(bps) => write!(f, "проскальзывание {} bps превышает 10000 bps", bps),
+            SolverError::InvalidCommitThreshold(bps) => write!(f, "порог закрепления {} bps превышает 10000 bps", bps),
+            SolverError::InvalidChunkJitter(bps) => write!(f, "разброс размеров чанков {} bps должен быть меньше 10000 bps", bps),
+            SolverError::InvalidChunkGuard(bps) => write!(f, "предел отклонения цены чанка {} bps превышает 10000 bps", bps),
+        }
+    }
+}
+
+impl std::error::Error for SolverError {}
+
+/// Алгоритм распределения суммы по пулам
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Жадный выбор лучшего пула для каждого чанка
+    #[default]
+    Greedy,
+    /// Аналитическое оптимальное разбиение между ровно двумя пулами
+    TwoPoolAnalytic,
+    /// Разбиение между всеми пулами с выравниванием маржинальных цен
+    MarginalEqualization,
+}
+
+/// Версия алгоритма солвера
+///
+/// Увеличивается при любом изменении, которое меняет маршруты на тех же
+/// входных данных (тай-брейки, эвристика жадного выбора, округления). Версия
+/// пишется в результат и в манифесты `regress`, чтобы расхождение с записанным
+/// маршрутом после смены алгоритма не выглядело как порча данных.
+///
+/// 2 - поправка суммы разброса чанков идет по перемешанным чанкам.
+pub const SOLVER_ALGORITHM_VERSION: u32 = 2;
+
+/// Псевдослучайный разброс размеров чанков
+///
+/// Каждый чанк отклоняется от равного разбиения не больше чем на `bps` от
+/// размера чанка; сумма чанков остается точно равной общей сумме. Размеры
+/// полностью определяются `seed`: тот же seed воспроизводит то же разбиение.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkJitter {
+    pub bps: u32,
+    pub seed: u64,
+}
+
+/// Параметры запуска солвера
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolverConfig {
+    pub total_amount_in: U256, // В raw units (USDC с 6 decimals)
+    pub num_chunks: u64,
+    pub verbose: bool,         // Печатать ход решения по чанкам
+    pub strategy: Strategy,
+    pub reserve_haircut_bps: u32, // Консервативная скидка на выходные резервы (только для котировок)
+    pub slippage_bps: u32,        // Допустимое проскальзывание для min_amount_out
+    pub commit_threshold_bps: Option<u32>, // Доля суммы, после которой пул закрепляется (None - без закрепления)
+    pub commit_switch_bps: u32,            // Насколько другой пул должен быть лучше закрепленного
+    pub bump_tiny_chunks: bool,            // Укрупнять чанки, на которых комиссия пула округляется до нуля
+    pub requote_step_bps: u32,             // Рост распределения пула RequoteOnly (доля суммы), после которого он перекотируется
+    pub chunk_jitter: Option<ChunkJitter>, // Разброс размеров чанков (None - равные чанки)
+    pub impact_model: ImpactModelChoice,   // Эталон цены для price impact чанков и сплита
+    pub chunk_guard: Option<ChunkGuard>,   // Предел отклонения цены чанка от лучшего спота (None - без предела)
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        Self::from_context(&ConfigContext::default())
+    }
+}
+
+impl SolverConfig {
+    /// Берет сумму и количество чанков из профиля конфигурации
+    pub fn from_context(ctx: &ConfigContext) -> Self {
+        SolverConfig {
+            total_amount_in: ctx.total_amount_in,
+            num_chunks: ctx.num_chunks,
+            verbose: true,
+            strategy: Strategy::default(),
+            reserve_haircut_bps: 0,
+            slippage_bps: config::DEFAULT_SLIPPAGE_BPS,
+            commit_threshold_bps: None,
+            commit_switch_bps: config::DEFAULT_COMMIT_SWITCH_BPS,
+            bump_tiny_chunks: false,
+            requote_step_bps: config::DEFAULT_REQUOTE_STEP_BPS,
+            chunk_jitter: None,
+            impact_model: ImpactModelChoice::default(),
+            chunk_guard: None,
+        }
+    }
+
+    /// Проверяет параметры и возвращает конфигурацию, готовую к запуску
+    /// 
+    /// - нулевая сумма -> `SolverError::ZeroAmount`
+    /// - нулевое количество чанков -> `SolverError::ZeroChunks`
+    /// - скидка на резервы больше 10000 bps -> `SolverError::InvalidHaircut`
+    /// - проскальзывание больше 10000 bps -> `SolverError::InvalidSlippage`
+    /// - порог закрепления больше 10000 bps -> `SolverError::InvalidCommitThreshold`
+    /// - разброс чанков от 10000 bps -> `SolverError::InvalidChunkJitter`
+    /// - предел отклонения цены чанка больше 10000 bps -> `SolverError::InvalidChunkGuard`
+    /// - сумма меньше количества чанков (в raw units) схлопывается в один чанк
+    ///   с предупреждением, иначе большинство чанков были бы нулевыми
+    pub fn validate(&self) -> Result<SolverConfig, SolverError> {
+        if self.total_amount_in == U256::ZERO {
+            return Err(SolverError::ZeroAmount);
+        }
+        if self.num_chunks == 0 {
+            return Err(SolverError::ZeroChunks);
+        }
+        if self.reserve_haircut_bps > math::BPS_DENOMINATOR {
+            return Err(SolverError::InvalidHaircut(self.reserve_haircut_bps));
+        }
+        if self.slippage_bps > math::BPS_DENOMINATOR {
+            return Err(SolverError::InvalidSlippage(self.slippage_bps));
+        }
+        if let Some(bps) = self.commit_threshold_bps.filter(|&bps| bps > math::BPS_DENOMINATOR) {
+            return Err(SolverError::InvalidCommitThreshold(bps));
+        }
+        if let Some(jitter) = self.chunk_jitter.filter(|jitter| jitter.bps >= math::BPS_DENOMINATOR) {
+            return Err(SolverError::InvalidChunkJitter(jitter.bps));
+        }
+        if let Some(guard) = self.chunk_guard.filter(|guard| guard.max_deviation_bps > math::BPS_DENOMINATOR) {
+            return Err(SolverError::InvalidChunkGuard(guard.max_deviation_bps));
+        }
+
+        if self.total_amount_in < U256::from(self.num_chunks) {
+            solver_log!(self, "Предупреждение: сумма {} меньше количества чанков {}, используется один чанк",
+                DisplayAmount::new(self.total_amount_in, TokenId::USDC), self.num_chunks);
+            return Ok(SolverConfig {
+                num_chunks: 1,
+                ..self.clone()
+            });
+        }
+
+        Ok(self.clone())
+    }
+
+    /// Суммы чанков в raw units: `total / n` каждому и по 1 raw unit первым
+    /// `total % n` чанкам, чтобы сумма чанков точно равнялась общей сумме
+    ///
+    /// С `chunk_jitter` каждый чанк сдвигается на псевдослучайную величину в
+    /// пределах `±total / n * bps / 10000`. Сдвиги не складываются в ноль,
+    /// поэтому их сумма затем гасится, начиная с первого чанка, в тех же
+    /// пределах: запаса хватает всегда, так как каждый сдвиг не больше предела.
+    pub fn chunk_plan(&self) -> Vec<U256> {
+        let num_chunks = U256::from(self.num_chunks);
+        let base = self.total_amount_in / num_chunks;
+        let remainder = (self.total_amount_in % num_chunks).to::<u64>();
+        let plan: Vec<U256> = (0..self.num_chunks)
+            .map(|i| if i < remainder { base + U256::from(1u64) } else { base })
+            .collect();
+        match self.chunk_jitter {
+            Some(jitter) => jitter_plan(plan, base * U256::from(jitter.bps) / U256::from(math::BPS_DENOMINATOR), jitter.seed),
+            None => plan,
+        }
+    }
+}
+
+/// Сдвигает суммы равного разбиения не больше чем на `max_shift` каждую, сохраняя сумму
+///
+/// Поправка суммы проходит чанки в перемешанном по тому же seed порядке:
+/// иначе она всегда прижимала бы к границе первые чанки маршрута.
+fn jitter_plan(plan: Vec<U256>, max_shift: U256, seed: u64) -> Vec<U256> {
+    if max_shift.is_zero() {
+        return plan;
+    }
+    let mut rng = SplitMix64(seed);
+    let span = max_shift * U256::from(2u64) + U256::from(1u64);
+    // После validate разброс меньше 10000 bps: max_shift < base и нижняя граница положительна
+    let bounds: Vec<(U256, U256)> = plan.iter().map(|&amount| (amount.saturating_sub(max_shift), amount + max_shift)).collect();
+    let mut jittered: Vec<U256> = bounds.iter().map(|&(low, _)| low + rng.next_u256() % span).collect();
+
+    // Фишер - Йетс
+    let mut order: Vec<usize> = (0..plan.len()).collect();
+    for i in (1..order.len()).rev() {
+        order.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+    }
+
+    let target: U256 = plan.iter().copied().sum();
+    let total: U256 = jittered.iter().copied().sum();
+    if total > target {
+        let mut excess = total - target;
+        for &index in &order {
+            let step = excess.min(jittered[index] - bounds[index].0);
+            jittered[index] -= step;
+            excess -= step;
+        }
+    } else {
+        let mut deficit = target - total;
+        for &index in &order {
+            let step = deficit.min(bounds[index].1 - jittered[index]);
+            jittered[index] += step;
+            deficit -= step;
+        }
+    }
+    jittered
+}
+
+/// Детерминированный генератор SplitMix64: разбиение должно воспроизводиться по seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_u256(&mut self) -> U256 {
+        U256::from_limbs([self.next_u64(), self.next_u64(), self.next_u64(), self.next_u64()])
+    }
+}
+
+/// Причина, по которой пул не участвовал в выборе для чанка
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// Пул не содержит ни один из входных токенов
+    MissingInputToken,
+    /// Резерв пула пуст (в том числе исчерпан предыдущими чанками)
+    EmptyReserves,
+    /// Котировка равна нулю: выход округлился до нуля или вход превышает лимит пула
+    ZeroQuote,
+    /// Второй токен пула не выходной и не эквивалентный ему
+    UnsupportedOutputToken,
+    /// Пул отклонил свап (см. `PoolError`): выход исчерпал бы резерв или резерв переполнился бы
+    SwapRejected,
+    /// Цена чанка в пуле за пределом `ChunkGuard`, а исправить чанк не удалось
+    PriceGuard,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::MissingInputToken => write!(f, "нет входного токена"),
+            SkipReason::EmptyReserves => write!(f, "пустые резервы"),
+            SkipReason::ZeroQuote => write!(f, "нулевая котировка"),
+            SkipReason::UnsupportedOutputToken => write!(f, "нет выходного токена"),
+            SkipReason::SwapRejected => write!(f, "свап отклонен пулом"),
+            SkipReason::PriceGuard => write!(f, "цена чанка за пределом отклонения"),
+        }
+    }
+}
+
+/// Пул, пропущенный при выборе для чанка
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSkip {
+    pub pool_name: String,
+    pub reason: SkipReason,
+}
+
+/// Сколько чанков пул пропустил по одной причине
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkipCount {
+    pub pool_name: String,
+    pub reason: SkipReason,
+    pub chunks: u64,
+}
+
+/// Чанки пула, на которых комиссия `amount_in * fee_bps / 10000` округляется до нуля
+/// 
+/// Формула выхода Uniswap V2 учитывает комиссию внутри себя, поэтому котировка
+/// совпадает с роутером. Округляется отдельно посчитанная комиссия: учет
+/// доходов LP (`fee_revenue`) и любая модель "вход минус комиссия" на таких
+/// чанках занижают комиссию и завышают выход.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeRoundingWarning {
+    pub pool_address: Address,
+    pub pool_name: String,
+    pub fee_bps: u32,
+    pub token_in: TokenId,    // Входной токен чанков (в нем считается комиссия)
+    pub chunks: u64,          // Чанки с нулевой комиссией
+    pub smallest_chunk: U256, // Самый маленький такой чанк (raw units)
+    pub min_chunk: U256,      // Минимальный чанк с ненулевой комиссией
+}
+
+impl fmt::Display for FeeRoundingWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: комиссия {} bps округляется до нуля в {} чанках (от {}); \
+            учет комиссий занижен, минимальный чанк без округления {} (--bump-tiny-chunks)",
+            self.pool_name, self.fee_bps, self.chunks,
+            DisplayAmount::new(self.smallest_chunk, self.token_in),
+            DisplayAmount::new(self.min_chunk, self.token_in))
+    }
+}
+
+/// Диагностика решения: агрегированные причины пропуска пулов и влияние закрепления
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SolverDiagnostics {
+    pub skip_counts: Vec<SkipCount>, // По убыванию количества чанков
+    pub committed_chunks: u64,       // Чанки, где закрепление изменило выбор пула
+    pub fee_rounding: Vec<FeeRoundingWarning>, // Пулы, на чанках которых комиссия округлилась до нуля
+    pub requotes: u64,               // Перекотировок пулов RequoteOnly у источника
+    pub guard_events: Vec<GuardEvent>, // Срабатывания предела отклонения цены чанка
+    pub unfilled_in: U256,           // Вход, не исполненный ни одним пулом (чанки без выхода и остатки уменьшенных)
+}
+
+impl SolverDiagnostics {
+    /// Собирает счетчики причин пропуска, закрепленных чанков и округления комиссии по всем чанкам
+    fn from_routes<P: AmmPool>(chunk_routes: &[ChunkRoute], pools: &[P]) -> Self {
+        let mut counts: std::collections::BTreeMap<(&str, SkipReason), u64> = std::collections::BTreeMap::new();
+        for skip in chunk_routes.iter().flat_map(|route| &route.skipped_pools) {
+            *counts.entry((skip.pool_name.as_str(), skip.reason)).or_insert(0) += 1;
+        }
+
+        let mut skip_counts: Vec<SkipCount> = counts
+            .into_iter()
+            .map(|((pool_name, reason), chunks)| SkipCount { pool_name: pool_name.to_string(), reason, chunks })
+            .collect();
+        skip_counts.sort_by_key(|count| std::cmp::Reverse(count.chunks));
+        let committed_chunks = chunk_routes.iter().filter(|route| route.committed).count() as u64;
+        SolverDiagnostics {
+            skip_counts,
+            committed_chunks,
+            fee_rounding: fee_rounding_warnings(chunk_routes, pools),
+            requotes: 0,
+            guard_events: Vec::new(),
+            unfilled_in: U256::ZERO,
+        }
+    }
+
+    /// Самые частые причины пропуска в виде строк для сводки
+    pub fn top_skip_reasons(&self, limit: usize) -> Vec<String> {
+        self.skip_counts
+            .iter()
+            .take(limit)
+            .map(|count| format!("{} пропущен в {} чанках: {}", count.pool_name, count.chunks, count.reason))
+            .collect()
+    }
+}
 
 #[derive(Debug)]
 pub struct ChunkRoute {
     pub chunk_index: u64,
-    pub best_pool_name: String,
+    pub best_pool_name: String,     // Имя только для вывода: символы токенов могут совпадать
+    pub pool_address: Option<Address>, // Адрес выбранного пула - идентичность пула в маршруте
+    pub dex: Option<DexId>,       // DEX выбранного пула (None, если ни один пул не дал выхода)
+    pub token_in: Option<TokenId>, // Фактический входной токен (USDC или USDC.e)
     pub amount_in: U256,     // В raw units (USDC с 6 decimals)
-    pub amount_out: U256,    // В raw units (WETH с 18 decimals)
+    pub amount_out: U256,    // В raw units (WETH с 18 decimals), после конвертации эквивалентного токена
+    pub token_out: Option<TokenId>, // Токен, который отдает пул (выходной или эквивалентный ему)
+    pub amount_out_native: U256,    // Выход в token_out до конвертации
+    pub min_amount_out: U256, // amountOutMin для исполнения в token_out: реальные резервы минус slippage
     pub amount_in_decimal: f64,   // Человекочитаемое значение USDC
     pub amount_out_decimal: f64,  // Человекочитаемое значение WETH
+    pub price_impact: f64,        // Impact чанка на выбранный пул (0.0 - 1.0)
+    pub execution_price: Option<f64>, // USDC за WETH по факту чанка (None при нулевом выходе)
+    pub spot_deviation_bps: Option<f64>, // Отклонение цены чанка от лучшего спота снимка; отрицательное - хуже
+    pub committed: bool,          // Пул выбран закреплением, хотя другой пул давал больше
+    pub skipped_pools: Vec<PoolSkip>, // Пулы, не участвовавшие в выборе для этого чанка
 }
 
 #[derive(Debug)]
 pub struct SolverResult {
-    pub total_weth_out: U256,        // Общий выход в raw units
+    pub total_weth_out: U256,        // Общий выход в raw units (эквивалентные токены сконвертированы)
+    pub output_by_token: Vec<(TokenId, U256)>, // Выход по конечным токенам до конвертации
     pub total_weth_out_decimal: f64, // Общий выход в человекочитаемом виде
+    pub cumulative_price_impact: f64, // Impact всего сплита относительно лучшей начальной спот-цены
+    pub reserve_haircut_bps: u32,     // Скидка на резервы, с которой получена котировка (0 - обычный режим)
+    pub execution_price: Option<f64>, // USDC за WETH по всему сплиту (None при нулевом выходе)
+    pub spot_price: f64,              // Спот USDC за WETH до сделки, взвешенный по ликвидности (0.0 без пулов)
+    pub price_deviation_bps: Option<f64>, // Отклонение цены исполнения от спота; отрицательное - хуже спота
+    pub confidence: f64,              // 1 минус бюджет ошибки приближенных пулов, взвешенный по выходу (1.0 - все точно)
+    pub algorithm_version: u32,       // SOLVER_ALGORITHM_VERSION, которой получен результат
     pub chunk_routes: Vec<ChunkRoute>,
+    pub diagnostics: SolverDiagnostics,
 }
 
-pub async fn find_best_routes(
-    provider: Arc<RootProvider<Http<Client>>>,
-    mut pools: Vec<crate::pool::Pool>
+/// Жадно распределяет сумму обмена по пулам, чанк за чанком
+/// 
+/// Параметры валидируются через `SolverConfig::validate`, поэтому нулевая
+/// сумма возвращает `SolverError::ZeroAmount` вместо пустого результата.
+/// 
+/// Если задан `reserve_haircut_bps`, выходной резерв каждого пула уменьшается
+/// на эту долю до начала решения. Скидка применяется последней, поверх тех
+/// резервов, с которыми пулы переданы в солвер, и влияет только на котировку:
+/// минимальные выходы для исполнения должны считаться по реальным резервам.
+/// Поэтому `min_amount_out` каждого чанка считается на отдельной копии пулов
+/// без скидки, к которой применяются те же свапы.
+pub async fn find_best_routes<P: AmmPool + Clone>(
+    pools: Vec<P>,
+    ctx: &ConfigContext,
+    solver_config: &SolverConfig,
 ) -> Result<SolverResult> {
-    let mut chunk_routes = Vec::with_capacity(config::NUM_CHUNKS as usize);
-    let mut total_weth_out = U256::ZERO;
+    solve(pools, ctx, solver_config, None).map(|(result, _)| result)
+}
 
-    println!("Начинаем поиск лучших маршрутов для {} чанков", config::NUM_CHUNKS);
-    let chunk_amount_raw = config::get_chunk_usdc_amount();
-    println!("Размер чанка: {} USDC (raw: {})", 
-        config::CHUNK_USDC_DECIMAL, 
-        chunk_amount_raw);
+/// Котировка пула для чанка в трассировке решения
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteTrace {
+    pub pool_name: String,
+    pub pool_address: Address,
+    pub token_in: TokenId,
+    pub token_out: TokenId,
+    pub reserve_in: U256,    // Резервы до чанка (после скидки и предыдущих чанков)
+    pub reserve_out: U256,
+    pub allocated_in: U256,  // Вход, уже отданный пулу предыдущими чанками
+    pub native_output: U256, // Выход в token_out
+    pub output: U256,        // Выход после конвертации в выходной токен
+    pub price_impact: f64,   // Impact чанка на пул (0.0 - 1.0)
+}
 
-    for i in 0..config::NUM_CHUNKS {
-        let mut best_output = U256::ZERO;
-        let mut best_pool_name = String::new();
-        let mut best_pool_index = 0;
-        let mut best_input_is_token0 = false;
+/// Решение жадного алгоритма для одного чанка со всеми промежуточными данными
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkTrace {
+    pub chunk_index: u64,
+    pub amount_in: U256,
+    pub quotes: Vec<QuoteTrace>,        // Пулы с ненулевой котировкой в порядке перебора
+    pub skipped_pools: Vec<PoolSkip>,
+    pub best_pool: Option<String>,      // Лучший выход до закрепления
+    pub tied_pools: Vec<String>,        // Пулы с тем же выходом, уступившие первому пулу
+    pub committed_pool: Option<String>, // Пул, выбранный закреплением вместо лучшего
+}
+
+/// Решает задачу жадным алгоритмом и записывает решение для чанка `chunk_index` (с 1)
+/// 
+/// Маршрут совпадает с `find_best_routes`: трассировка только читает
+/// промежуточные данные. Аналитические стратегии не принимают решений
+/// по чанкам, поэтому для них возвращается ошибка.
+pub fn trace_chunk<P: AmmPool + Clone>(
+    pools: Vec<P>,
+    ctx: &ConfigContext,
+    solver_config: &SolverConfig,
+    chunk_index: u64,
+) -> Result<(SolverResult, ChunkTrace)> {
+    if solver_config.strategy != Strategy::Greedy {
+        return Err(eyre::eyre!("трассировка чанка доступна только для стратегии greedy"));
+    }
+    let (result, trace) = solve(pools, ctx, solver_config, Some(chunk_index))?;
+    let trace = trace.ok_or_else(|| eyre::eyre!("чанк {} вне маршрута из {} чанков", chunk_index, result.chunk_routes.len()))?;
+    Ok((result, trace))
+}
+
+/// Общая часть `find_best_routes` и `trace_chunk`
+fn solve<P: AmmPool + Clone>(
+    mut pools: Vec<P>,
+    ctx: &ConfigContext,
+    solver_config: &SolverConfig,
+    trace_chunk: Option<u64>,
+) -> Result<(SolverResult, Option<ChunkTrace>)> {
+    let mut solver_config = solver_config.validate()?;
+    if solver_config.bump_tiny_chunks {
+        solver_config = bump_tiny_chunks(solver_config, &pools, ctx);
+    }
+    if solver_config.chunk_guard.is_some() && solver_config.strategy != Strategy::Greedy {
+        // Выбранная пользователем стратегия меняется - сообщаем и без verbose
+        log!("Предел цены чанка проверяется только по чанкам: стратегия {:?} заменена жадным алгоритмом", solver_config.strategy);
+        solver_config.strategy = Strategy::Greedy;
+    }
+    let mut min_out = MinOutTracker { real_pools: pools.clone(), slippage_bps: solver_config.slippage_bps };
+    if solver_config.reserve_haircut_bps > 0 {
+        for pool in pools.iter_mut() {
+            if let Some(token_in) = input_token(pool, ctx) {
+                pool.apply_reserve_haircut(solver_config.reserve_haircut_bps, token_in);
+            }
+        }
+        solver_log!(solver_config, "Консервативный режим: выходные резервы уменьшены на {} bps",
+            solver_config.reserve_haircut_bps);
+    }
+    let initial_spot = PreTradeSpot::new(&pools, ctx);
 
-        println!("\nОбрабатываем чанк #{}", i + 1);
+    // Аналитические стратегии используют формулы Uniswap V2 с одной комиссией
+    // для всех пулов и не учитывают конвертацию выхода
+    let fee_bps = pools.first().map_or(Some(math::DEFAULT_FEE_BPS), |pool| pool.constant_product_fee_bps());
+    let analytic_supported = fee_bps.is_some() && pools.iter().all(|pool| {
+        pool.constant_product_fee_bps() == fee_bps
+            && input_token(pool, ctx).is_none_or(|token_in| output_token(pool, token_in) == ctx.output_token)
+    });
+    let fee_bps = fee_bps.unwrap_or(math::DEFAULT_FEE_BPS);
+    if solver_config.strategy != Strategy::Greedy && !analytic_supported {
+        solver_log!(solver_config, "Аналитические стратегии поддерживают только пулы constant product с прямым выходом и одинаковой комиссией, используем жадный алгоритм");
+    }
+    if solver_config.strategy == Strategy::TwoPoolAnalytic && analytic_supported {
+        let eligible: Vec<usize> = (0..pools.len())
+            .filter(|&index| input_token(&pools[index], ctx).is_some())
+            .collect();
+        if let [index_a, index_b] = eligible[..] {
+            return Ok((solve_two_pool_analytic(&mut pools, &mut min_out, ctx, &solver_config, [index_a, index_b], fee_bps, initial_spot)?, None));
+        }
+        solver_log!(solver_config, "Стратегия two-pool-analytic требует ровно 2 пула (найдено {}), используем жадный алгоритм",
+            eligible.len());
+    }
+    if solver_config.strategy == Strategy::MarginalEqualization && analytic_supported {
+        return Ok((solve_marginal_equalization(&mut pools, &mut min_out, ctx, &solver_config, fee_bps, initial_spot)?, None));
+    }
+
+    let mut chunk_routes = Vec::with_capacity(solver_config.num_chunks as usize);
+    let mut requotes = RequoteSchedule::new(pools.len(), &solver_config, initial_spot.best_raw)?;
+
+    solver_log!(solver_config, "Начинаем поиск лучших маршрутов для {} чанков", solver_config.num_chunks);
+    let chunk_plan = solver_config.chunk_plan();
+    match solver_config.chunk_jitter {
+        Some(jitter) => solver_log!(solver_config, "Размеры чанков: {} - {} (разброс {} bps, seed {})",
+            DisplayAmount::new(chunk_plan.iter().copied().min().unwrap_or_default(), TokenId::USDC),
+            DisplayAmount::new(chunk_plan.iter().copied().max().unwrap_or_default(), TokenId::USDC),
+            jitter.bps, jitter.seed),
+        None => solver_log!(solver_config, "Размер чанка: {} (остаток распределен по первым чанкам)",
+            DisplayAmount::new(chunk_plan[0], TokenId::USDC)),
+    }
+
+    // Сколько входа уже получил каждый пул и пул предыдущего чанка (для закрепления)
+    let mut allocated_in = vec![U256::ZERO; pools.len()];
+    let mut previous_pool = None;
+    let mut trace = None;
+    let mut guard_events = Vec::new();
+
+    for (i, chunk_amount_raw) in (0u64..).zip(chunk_plan) {
+        let mut candidates: Vec<Candidate> = Vec::new();
+        let mut skipped_pools = Vec::new();
+        let tracing = trace_chunk == Some(i + 1);
+        let mut quotes = Vec::new();
+
+        solver_log!(solver_config, "\nОбрабатываем чанк #{}", i + 1);
 
         // Проверяем каждый пул для текущего чанка
-        for (pool_index, pool) in pools.iter().enumerate() {
-            // Проверяем, какой тип USDC содержит пул
-            let usdc_is_token0 = pool.token0_address == config::USDC_ADDRESS;
-            let usdc_is_token1 = pool.token1_address == config::USDC_ADDRESS;
-            let usdc_e_is_token0 = pool.token0_address == config::USDC_E_ADDRESS;
-            let usdc_e_is_token1 = pool.token1_address == config::USDC_E_ADDRESS;
-            
-            // Определяем, содержит ли пул любой тип USDC
-            let has_usdc = usdc_is_token0 || usdc_is_token1;
-            let has_usdc_e = usdc_e_is_token0 || usdc_e_is_token1;
-            
-            // Пропускаем пулы, которые не содержат ни USDC, ни USDC.e
-            if !has_usdc && !has_usdc_e {
-                println!("Пул {:?}: {} -> Пропущен (не содержит USDC или USDC.e)", 
-                    pool.pool_address, pool.name);
+        for (pool_index, pool) in pools.iter_mut().enumerate() {
+            // Определяем входной токен пула (USDC или USDC.e) и его сторону.
+            // Пропускаем пулы, которые не содержат ни один из входных токенов
+            let Some(token_in) = input_token(pool, ctx) else {
+                solver_log!(solver_config, "Пул {:?}: {} -> Пропущен (не содержит входной токен)", 
+                    pool.address(), pool.name());
+                skipped_pools.push(PoolSkip { pool_name: pool.name().to_string(), reason: SkipReason::MissingInputToken });
+                continue;
+            };
+            let token_out = output_token(pool, token_in);
+            if ctx.output_rate(token_out).is_none() {
+                solver_log!(solver_config, "Пул {:?}: {} -> Пропущен (не содержит выходной токен)",
+                    pool.address(), pool.name());
+                skipped_pools.push(PoolSkip { pool_name: pool.name().to_string(), reason: SkipReason::UnsupportedOutputToken });
                 continue;
             }
             
-            // Определяем, является ли входной токен token0
-            let input_is_token0 = usdc_is_token0 || usdc_e_is_token0;
-            
-            // Рассчитываем output без обновления резервов для сравнения пулов
-            let output = pool.get_amount_out(chunk_amount_raw, input_is_token0);
+            // Пробуем свап и откатываем резервы: выход совпадает с тем, что даст
+            // реальный mock_swap. Эквивалентный токен сравнивается после конвертации
+            let native_output = match pool.quote(chunk_amount_raw, token_in) {
+                Ok(native_output) => native_output,
+                Err(error) => {
+                    let reason = match error {
+                        PoolError::EmptyReserves => SkipReason::EmptyReserves,
+                        PoolError::InsufficientOutput => SkipReason::ZeroQuote,
+                        _ => SkipReason::SwapRejected,
+                    };
+                    solver_log!(solver_config, "Пул {:?}: {} -> Пропущен ({})", pool.address(), pool.name(), error);
+                    skipped_pools.push(PoolSkip { pool_name: pool.name().to_string(), reason });
+                    continue;
+                }
+            };
+            let output = ctx.convert_output(token_out, native_output).unwrap_or(U256::ZERO);
+            if output == U256::ZERO {
+                let (reserve_in, reserve_out) = pool.reserves(token_in);
+                let reason = if reserve_in == U256::ZERO || reserve_out == U256::ZERO {
+                    SkipReason::EmptyReserves
+                } else {
+                    SkipReason::ZeroQuote
+                };
+                solver_log!(solver_config, "Пул {:?}: {} -> Пропущен ({})", pool.address(), pool.name(), reason);
+                skipped_pools.push(PoolSkip { pool_name: pool.name().to_string(), reason });
+                continue;
+            }
             
-            // Определяем тип токена для вывода
-            let token_type = if has_usdc { "USDC" } else { "USDC.e" };
-
-            println!("Пул {:?}: {} -> WETH выход = {:.6} (raw: {}) [входной токен: {}]", 
-                pool.pool_address,
-                pool.name,
-                config::weth_to_decimal(output), 
-                output,
-                token_type);
+            solver_log!(solver_config, "Пул {:?}: {} -> выход = {} [входной токен: {}]",
+                pool.address(),
+                pool.name(),
+                DisplayAmount::with_decimals(native_output, token_out, pool.decimals(token_out)),
+                token_in);
 
-            if output > best_output {
-                best_output = output;
-                best_pool_name = pool.name.clone();
-                best_pool_index = pool_index;
-                best_input_is_token0 = input_is_token0;
+            if tracing {
+                let (reserve_in, reserve_out) = pool.reserves(token_in);
+                quotes.push(QuoteTrace {
+                    pool_name: pool.name().to_string(),
+                    pool_address: pool.address(),
+                    token_in,
+                    token_out,
+                    reserve_in,
+                    reserve_out,
+                    allocated_in: allocated_in[pool_index],
+                    native_output,
+                    output,
+                    price_impact: solver_config.impact_model.model().price_impact(pool, chunk_amount_raw, token_in),
+                });
             }
+            candidates.push(Candidate { pool_index, token_in, token_out, native_output, output });
         }
-        
-        // Применяем реальный swap только к лучшему пулу (обновляем резервы)
-        if best_output > U256::ZERO {
-            let actual_output = pools[best_pool_index].mock_swap(chunk_amount_raw, best_input_is_token0);
-            println!("Применен mock_swap к пулу {}: обновлены резервы, фактический выход = {:.6} WETH", 
-                best_pool_name, config::weth_to_decimal(actual_output));
-            
-            // Используем фактический выход вместо расчетного (должны совпадать, но проверяем)
-            if actual_output != best_output {
-                println!("Предупреждение: расчетный выход ({}) != фактический выход ({})", 
-                    best_output, actual_output);
+
+        // Лучший кандидат по compare_routes; при равном выходе остается более глубокий пул
+        let best = candidates
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| compare_routes(&a.rank(), &b.rank(), DIRECT_HOP_PENALTY_BPS))
+            .map(|(position, _)| position);
+        let committed_choice = best.and_then(|best| {
+            committed_candidate(&candidates, best, previous_pool, &allocated_in, &solver_config)
+        });
+        if let (Some(best), Some(committed)) = (best, committed_choice) {
+            solver_log!(solver_config, "Закрепление: остаемся в {} вместо {}",
+                pools[candidates[committed].pool_index].name(), pools[candidates[best].pool_index].name());
+        }
+        let chosen = committed_choice.or(best).map(|position| candidates[position]);
+        if tracing {
+            let name = |position: usize| pools[candidates[position].pool_index].name().to_string();
+            trace = Some(ChunkTrace {
+                chunk_index: i + 1,
+                amount_in: chunk_amount_raw,
+                quotes: std::mem::take(&mut quotes),
+                skipped_pools: skipped_pools.clone(),
+                best_pool: best.map(name),
+                tied_pools: best.map_or_else(Vec::new, |best| {
+                    (0..candidates.len())
+                        .filter(|&position| position != best && candidates[position].output == candidates[best].output)
+                        .map(name)
+                        .collect()
+                }),
+                committed_pool: committed_choice.map(name),
+            });
+        }
+
+        // Предел отклонения цены: другой пул, часть чанка или неисполненный чанк
+        let mut chunk_amount_raw = chunk_amount_raw;
+        let mut chosen = chosen;
+        let mut committed = committed_choice.is_some();
+        if let (Some(guard), Some(candidate)) = (solver_config.chunk_guard, chosen) {
+            let deviation = spot_deviation_bps(chunk_amount_raw, candidate.output, initial_spot.best_raw);
+            if !guard.allows(deviation) {
+                let deviation_bps = deviation.unwrap_or(-f64::from(math::BPS_DENOMINATOR));
+                let from = pools[candidate.pool_index].name().to_string();
+                let action = match guard_remedy(guard, &pools, &candidates, chunk_amount_raw, initial_spot.best_raw, ctx) {
+                    Some((replacement, amount)) if amount == chunk_amount_raw => {
+                        chosen = Some(replacement);
+                        committed = false;
+                        GuardAction::Rerouted { from, to: pools[replacement.pool_index].name().to_string() }
+                    }
+                    Some((replacement, amount)) => {
+                        let unfilled_in = chunk_amount_raw - amount;
+                        chunk_amount_raw = amount;
+                        chosen = Some(replacement);
+                        committed = committed && replacement.pool_index == candidate.pool_index;
+                        GuardAction::Shrunk { pool: pools[replacement.pool_index].name().to_string(), amount_in: amount, unfilled_in }
+                    }
+                    None if guard.fallback == GuardFallback::Abort => {
+                        return Err(ChunkGuardAbort { chunk_index: i + 1, deviation_bps, max_deviation_bps: guard.max_deviation_bps }.into());
+                    }
+                    None => {
+                        chosen = None;
+                        committed = false;
+                        skipped_pools.extend(candidates.iter().map(|candidate| PoolSkip {
+                            pool_name: pools[candidate.pool_index].name().to_string(),
+                            reason: SkipReason::PriceGuard,
+                        }));
+                        GuardAction::Unfilled
+                    }
+                };
+                let event = GuardEvent { chunk_index: i + 1, deviation_bps, action };
+                solver_log!(solver_config, "Предел цены чанка: {}", event);
+                guard_events.push(event);
             }
-            best_output = actual_output;
         }
-        
-        total_weth_out += best_output;
+
+        let mut best_output = U256::ZERO;
+        let mut best_native_output = U256::ZERO;
+        let mut best_min_amount_out = U256::ZERO;
+        let mut best_pool_name = String::new();
+        let mut best_price_impact = 0.0;
+        let mut best_decimals = (0, 0);
+        // Применяем реальный swap только к выбранному пулу (обновляем резервы)
+        if let Some(candidate) = chosen {
+            let pool = &pools[candidate.pool_index];
+            best_pool_name = pool.name().to_string();
+            best_price_impact = solver_config.impact_model.model().price_impact(pool, chunk_amount_raw, candidate.token_in);
+            best_decimals = (pool.decimals(candidate.token_in), output_decimals(pool, candidate.token_out, ctx));
+
+            best_min_amount_out = min_out.record(candidate.pool_index, chunk_amount_raw, candidate.token_in);
+            pools[candidate.pool_index].apply(chunk_amount_raw, candidate.token_in)?;
+            solver_log!(solver_config, "Применен mock_swap к пулу {}: обновлены резервы, выход = {}",
+                best_pool_name,
+                DisplayAmount::with_decimals(candidate.native_output, candidate.token_out, pools[candidate.pool_index].decimals(candidate.token_out)));
+            best_native_output = candidate.native_output;
+            best_output = candidate.output;
+            accumulate(&mut allocated_in[candidate.pool_index], chunk_amount_raw, "вход пула")?;
+            previous_pool = Some(candidate.pool_index);
+        }
         
         // Создаем запись маршрута с человекочитаемыми значениями
         chunk_routes.push(ChunkRoute {
             chunk_index: i + 1,
             best_pool_name: best_pool_name.clone(),
+            pool_address: chosen.map(|candidate| pools[candidate.pool_index].address()),
+            dex: chosen.map(|candidate| pools[candidate.pool_index].dex()),
+            token_in: chosen.map(|candidate| candidate.token_in),
             amount_in: chunk_amount_raw,
             amount_out: best_output,
-            amount_in_decimal: config::CHUNK_USDC_DECIMAL,
-            amount_out_decimal: config::weth_to_decimal(best_output),
+            token_out: chosen.map(|candidate| candidate.token_out),
+            amount_out_native: best_native_output,
+            min_amount_out: best_min_amount_out,
+            amount_in_decimal: config::to_decimal(chunk_amount_raw, best_decimals.0),
+            amount_out_decimal: config::to_decimal(best_output, best_decimals.1),
+            price_impact: best_price_impact,
+            execution_price: chosen.and_then(|_| {
+                math::execution_price(chunk_amount_raw, best_output, best_decimals.0, best_decimals.1)
+            }),
+            spot_deviation_bps: spot_deviation_bps(chunk_amount_raw, best_output, initial_spot.best_raw),
+            committed,
+            skipped_pools,
         });
 
-        println!("Лучший пул для чанка #{}: {} -> {:.6} WETH", 
-            i + 1, best_pool_name, config::weth_to_decimal(best_output));
+        solver_log!(solver_config, "Лучший пул для чанка #{}: {} -> {} {}", 
+            i + 1, best_pool_name, config::format_units(best_output, best_decimals.1), ctx.output_token);
+
+        if let Some(candidate) = chosen.filter(|candidate| pools[candidate.pool_index].fidelity() == SimulationFidelity::RequoteOnly) {
+            requotes.record(&mut pools, candidate.pool_index, allocated_in[candidate.pool_index], &mut chunk_routes, ctx)?;
+        }
+    }
+    requotes.finish(&mut pools, &allocated_in, &mut chunk_routes, ctx)?;
+    if requotes.count > 0 {
+        solver_log!(solver_config, "Перекотировок у источника: {}", requotes.count);
+    }
+
+    let total_weth_out = Accumulator::sum("общий выход", chunk_routes.iter().map(|route| route.amount_out))?;
+    let mut result = finish_result(&pools, chunk_routes, total_weth_out, initial_spot, &solver_config)?;
+    result.diagnostics.requotes = requotes.count;
+    result.diagnostics.guard_events = guard_events;
+    Ok((result, trace))
+}
+
+/// Перекотировка пулов `SimulationFidelity::RequoteOnly` по мере роста их распределения
+/// 
+/// Выход таких пулов в маршруте сначала берется из локальной модели. Когда
+/// распределение пула вырастает на `requote_step_bps` от суммы обмена с
+/// прошлой перекотировки (и в конце решения), весь вход пула котируется у
+/// источника, и выход пула в маршруте заменяется ответом источника.
+struct RequoteSchedule {
+    step: U256,                      // Рост распределения, после которого пул перекотируется
+    slippage_bps: u32,
+    requoted_in: Vec<U256>,          // Распределение пула на момент последней перекотировки
+    recorded_out: Vec<U256>,         // Выход пула в маршруте (в token_out), с учетом перекотировок
+    routes: Vec<Vec<usize>>,         // Чанки маршрута через пул, по порядку
+    best_spot_raw: f64,              // Лучшая спот-цена снимка для отклонения перекотированного чанка
+    count: u64,
+}
+
+impl RequoteSchedule {
+    fn new(num_pools: usize, solver_config: &SolverConfig, best_spot_raw: f64) -> Result<Self, AggregationError> {
+        Ok(RequoteSchedule {
+            step: scale(solver_config.total_amount_in, U256::from(solver_config.requote_step_bps), U256::from(math::BPS_DENOMINATOR), "шаг перекотировки")?,
+            slippage_bps: solver_config.slippage_bps,
+            requoted_in: vec![U256::ZERO; num_pools],
+            recorded_out: vec![U256::ZERO; num_pools],
+            routes: vec![Vec::new(); num_pools],
+            best_spot_raw,
+            count: 0,
+        })
+    }
+
+    /// Учитывает последний чанк маршрута, отданный пулу `pool_index` с
+    /// распределением `allocated`, и перекотирует пул, если пора
+    fn record<P: AmmPool>(
+        &mut self,
+        pools: &mut [P],
+        pool_index: usize,
+        allocated: U256,
+        chunk_routes: &mut [ChunkRoute],
+        ctx: &ConfigContext,
+    ) -> Result<()> {
+        let route_index = chunk_routes.len() - 1;
+        self.routes[pool_index].push(route_index);
+        accumulate(&mut self.recorded_out[pool_index], chunk_routes[route_index].amount_out_native, "выход пула")?;
+        if allocated - self.requoted_in[pool_index] >= self.step {
+            self.requote(pools, pool_index, allocated, chunk_routes, ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Перекотирует пулы, распределение которых выросло после последней перекотировки
+    fn finish<P: AmmPool>(
+        &mut self,
+        pools: &mut [P],
+        allocated_in: &[U256],
+        chunk_routes: &mut [ChunkRoute],
+        ctx: &ConfigContext,
+    ) -> Result<()> {
+        for (pool_index, &allocated) in allocated_in.iter().enumerate() {
+            if !self.routes[pool_index].is_empty() && allocated > self.requoted_in[pool_index] {
+                self.requote(pools, pool_index, allocated, chunk_routes, ctx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Заменяет выход пула в маршруте ответом источника за весь его вход
+    /// 
+    /// Разница с локальной моделью относится к последнему чанку пула. Если
+    /// источник дает меньше, чем уже записано в предыдущих чанках, выход
+    /// последнего чанка становится нулевым, а недостача снимается с
+    /// предыдущих чанков пула, от поздних к ранним. Сумма выходов чанков пула
+    /// после перекотировки всегда равна ответу источника.
+    fn requote<P: AmmPool>(
+        &mut self,
+        pools: &mut [P],
+        pool_index: usize,
+        allocated: U256,
+        chunk_routes: &mut [ChunkRoute],
+        ctx: &ConfigContext,
+    ) -> Result<()> {
+        let Some((&last, earlier)) = self.routes[pool_index].split_last() else {
+            return Ok(());
+        };
+        let (Some(token_in), Some(token_out)) = (chunk_routes[last].token_in, chunk_routes[last].token_out) else {
+            return Ok(());
+        };
+        let pool = &mut pools[pool_index];
+        let cumulative_out = pool.requote(allocated, token_in)?;
+        self.count += 1;
+
+        let earlier_out = self.recorded_out[pool_index] - chunk_routes[last].amount_out_native;
+        let mut shortfall = earlier_out.saturating_sub(cumulative_out);
+        let (decimals_in, decimals_out) = (pool.decimals(token_in), output_decimals(pool, token_out, ctx));
+        let set_output = |route: &mut ChunkRoute, native_output: U256| {
+            route.amount_out_native = native_output;
+            route.amount_out = ctx.convert_output(token_out, native_output).unwrap_or(U256::ZERO);
+            route.amount_out_decimal = config::to_decimal(route.amount_out, decimals_out);
+            route.execution_price = math::execution_price(route.amount_in, route.amount_out, decimals_in, decimals_out);
+            route.spot_deviation_bps = spot_deviation_bps(route.amount_in, route.amount_out, self.best_spot_raw);
+            route.min_amount_out = math::apply_slippage(native_output, self.slippage_bps).unwrap_or(U256::ZERO);
+        };
+        set_output(&mut chunk_routes[last], cumulative_out.saturating_sub(earlier_out));
+        for &route_index in earlier.iter().rev() {
+            if shortfall.is_zero() {
+                break;
+            }
+            let route = &mut chunk_routes[route_index];
+            let cut = shortfall.min(route.amount_out_native);
+            shortfall -= cut;
+            set_output(route, route.amount_out_native - cut);
+        }
+
+        self.recorded_out[pool_index] = cumulative_out;
+        self.requoted_in[pool_index] = allocated;
+        Ok(())
     }
+}
+
+/// Штраф за лишние шаги при ранжировании кандидатов: кандидаты солвера -
+/// прямые пулы из одного шага, поэтому штраф на них не влияет
+const DIRECT_HOP_PENALTY_BPS: u32 = 0;
 
+/// Пул, давший ненулевую котировку для чанка
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    pool_index: usize,
+    token_in: TokenId,
+    token_out: TokenId,
+    native_output: U256, // Выход в token_out
+    output: U256,        // Выход после конвертации в выходной токен
+}
+
+impl Candidate {
+    /// Прямой пул - маршрут из одного шага
+    fn rank(&self) -> RouteRank<'_> {
+        RouteRank { output: self.output, route_id: std::slice::from_ref(&self.pool_index) }
+    }
+}
+
+/// Кандидат закрепленного пула, если закрепление должно перебить лучший выбор
+/// 
+/// Закреплен пул предыдущего чанка, если он уже получил больше
+/// `commit_threshold_bps` от общей суммы. Он остается выбранным, пока лучший
+/// пул выигрывает не больше `commit_switch_bps`; после перехода закрепляется
+/// новый пул, так что маршрут не колеблется между почти равными пулами.
+fn committed_candidate(
+    candidates: &[Candidate],
+    best: usize,
+    previous_pool: Option<usize>,
+    allocated_in: &[U256],
+    solver_config: &SolverConfig,
+) -> Option<usize> {
+    let threshold_bps = solver_config.commit_threshold_bps?;
+    let committed_pool = previous_pool?;
+    let denominator = U256::from(math::BPS_DENOMINATOR);
+    if allocated_in[committed_pool] * denominator <= solver_config.total_amount_in * U256::from(threshold_bps) {
+        return None;
+    }
+    if candidates[best].pool_index == committed_pool {
+        return None;
+    }
+    let position = candidates.iter().position(|candidate| candidate.pool_index == committed_pool)?;
+    let switch = U256::from(math::BPS_DENOMINATOR + solver_config.commit_switch_bps);
+    (candidates[best].output * denominator <= candidates[position].output * switch).then_some(position)
+}
+
+/// Замена кандидата чанка, цена которого за пределом `ChunkGuard`
+/// 
+/// Сначала - лучший кандидат, укладывающийся в предел на весь чанк. С
+/// `GuardRemedy::Shrink` затем - кандидат, в котором в предел укладывается
+/// наибольшая часть чанка, с выходом за эту часть. `None` - исправить чанк нельзя.
+fn guard_remedy<P: AmmPool>(
+    guard: ChunkGuard,
+    pools: &[P],
+    candidates: &[Candidate],
+    amount_in: U256,
+    best_spot_raw: f64,
+    ctx: &ConfigContext,
+) -> Option<(Candidate, U256)> {
+    let rerouted = candidates
+        .iter()
+        .filter(|candidate| guard.allows(spot_deviation_bps(amount_in, candidate.output, best_spot_raw)))
+        .max_by(|a, b| compare_routes(&a.rank(), &b.rank(), DIRECT_HOP_PENALTY_BPS));
+    if let Some(candidate) = rerouted {
+        return Some((*candidate, amount_in));
+    }
+    if guard.remedy == GuardRemedy::Reroute {
+        return None;
+    }
+
+    // Выход кандидата за часть чанка: (в token_out, в выходном токене)
+    let quote = |candidate: &Candidate, amount: U256| {
+        let native_output = pools[candidate.pool_index].quote(amount, candidate.token_in).ok()?;
+        Some((native_output, ctx.convert_output(candidate.token_out, native_output)?))
+    };
+    let (candidate, amount) = candidates
+        .iter()
+        .map(|candidate| {
+            let amount = guard.max_input_within(amount_in, best_spot_raw, |amount| {
+                quote(candidate, amount).map_or(U256::ZERO, |(_, output)| output)
+            });
+            (candidate, amount)
+        })
+        .filter(|(_, amount)| !amount.is_zero())
+        .max_by(|(a, amount_a), (b, amount_b)| {
+            amount_a.cmp(amount_b).then_with(|| compare_routes(&a.rank(), &b.rank(), DIRECT_HOP_PENALTY_BPS))
+        })?;
+    let (native_output, output) = quote(candidate, amount)?;
+    Some((Candidate { native_output, output, ..*candidate }, amount))
+}
+
+/// Спот-цены пулов до сделки
+#[derive(Debug, Clone, Copy)]
+struct PreTradeSpot {
+    best_raw: f64,           // Лучшая спот-цена (WETH за USDC в raw units) для оценки общего impact
+    liquidity_weighted: f64, // USDC за WETH, среднее по пулам с весом входного резерва
+}
+
+impl PreTradeSpot {
+    fn new<P: AmmPool>(pools: &[P], ctx: &ConfigContext) -> Self {
+        let mut best_raw: f64 = 0.0;
+        let mut weighted_sum = 0.0;
+        let mut weight_total = 0.0;
+        for pool in pools {
+            let Some(token_in) = input_token(pool, ctx) else {
+                continue;
+            };
+            let token_out = output_token(pool, token_in);
+            let Some(rate) = ctx.output_rate(token_out) else {
+                continue;
+            };
+            let (reserve_in, reserve_out) = pool.reserves(token_in);
+            if reserve_in == U256::ZERO || reserve_out == U256::ZERO {
+                continue;
+            }
+            // Резерв эквивалентного токена пересчитывается в выходной по курсу
+            let rate = math::u256_to_f64(rate) / config::CONVERSION_RATE_SCALE as f64;
+            best_raw = best_raw.max(math::u256_to_f64(reserve_out) * rate / math::u256_to_f64(reserve_in));
+
+            let weight = math::u256_to_f64(reserve_in);
+            weighted_sum += weight * pool.spot_price(token_out) / rate;
+            weight_total += weight;
+        }
+        let liquidity_weighted = if weight_total > 0.0 { weighted_sum / weight_total } else { 0.0 };
+        PreTradeSpot { best_raw, liquidity_weighted }
+    }
+}
+
+/// Собирает итоговый результат и считает общий price impact
+/// 
+/// Суммы по маршрутам считаются точно; переполнение U256 - ошибка, а не паника.
+fn finish_result<P: AmmPool>(
+    pools: &[P],
+    chunk_routes: Vec<ChunkRoute>,
+    total_weth_out: U256,
+    initial_spot: PreTradeSpot,
+    solver_config: &SolverConfig,
+) -> Result<SolverResult, AggregationError> {
     let total_weth_decimal = config::weth_to_decimal(total_weth_out);
-    println!("\nИтого WETH получено: {:.6} (raw: {})", total_weth_decimal, total_weth_out);
+    solver_log!(solver_config, "\nИтого WETH получено: {}", DisplayAmount::new(total_weth_out, TokenId::WETH));
+
+    let total_amount_in = Accumulator::sum("общий вход", chunk_routes.iter().map(|route| route.amount_in))?;
+    let reference = solver_config.impact_model.model()
+        .split_reference_price(initial_spot.best_raw, config::USDC_DECIMALS, config::WETH_DECIMALS);
+    let cumulative_price_impact = match reference {
+        Some(reference) => 1.0 - math::u256_to_f64(total_weth_out) / (math::u256_to_f64(total_amount_in) * reference),
+        None => 1.0,
+    };
+    solver_log!(solver_config, "Общий price impact: {:.4}%", cumulative_price_impact * 100.0);
+
+    // Чанки без выхода не исполняются, их вход в цену не входит
+    let filled_amount_in = Accumulator::sum("исполненный вход", chunk_routes
+        .iter()
+        .filter(|route| route.amount_out > U256::ZERO)
+        .map(|route| route.amount_in))?;
+    let execution_price = math::execution_price(filled_amount_in, total_weth_out, config::USDC_DECIMALS, config::WETH_DECIMALS);
+    let price_deviation_bps = execution_price
+        .and_then(|price| math::price_deviation_bps(price, initial_spot.liquidity_weighted));
 
+    let mut output_by_token: Vec<(TokenId, U256)> = Vec::new();
+    for route in &chunk_routes {
+        let Some(token_out) = route.token_out else {
+            continue;
+        };
+        match output_by_token.iter_mut().find(|(token, _)| *token == token_out) {
+            Some((_, amount)) => accumulate(amount, route.amount_out_native, "выход по токену")?,
+            None => output_by_token.push((token_out, route.amount_out_native)),
+        }
+    }
+
+    // Бюджет ошибки приближенных пулов, взвешенный по выходу чанков
+    let weighted_error_bps: f64 = chunk_routes
+        .iter()
+        .filter_map(|route| {
+            let pool = pools.iter().find(|pool| Some(pool.address()) == route.pool_address)?;
+            Some(math::u256_to_f64(route.amount_out) * pool.fidelity().error_bps() as f64)
+        })
+        .sum();
+    let confidence = if total_weth_out.is_zero() {
+        1.0
+    } else {
+        (1.0 - weighted_error_bps / (math::u256_to_f64(total_weth_out) * math::BPS_DENOMINATOR as f64)).max(0.0)
+    };
+
+    let mut diagnostics = SolverDiagnostics::from_routes(&chunk_routes, pools);
+    diagnostics.unfilled_in = solver_config.total_amount_in.saturating_sub(filled_amount_in);
+    for warning in &diagnostics.fee_rounding {
+        solver_log!(solver_config, "Предупреждение: {}", warning);
+    }
     Ok(SolverResult { 
         total_weth_out, 
+        output_by_token,
         total_weth_out_decimal: total_weth_decimal,
-        chunk_routes 
+        cumulative_price_impact,
+        reserve_haircut_bps: solver_config.reserve_haircut_bps,
+        execution_price,
+        spot_price: initial_spot.liquidity_weighted,
+        price_deviation_bps,
+        confidence,
+        algorithm_version: SOLVER_ALGORITHM_VERSION,
+        chunk_routes,
+        diagnostics,
     })
-} 
\ No newline at end of file
+}
+
+/// Пулы маршрута, на чанках которых комиссия constant product округлилась до нуля
+fn fee_rounding_warnings<P: AmmPool>(chunk_routes: &[ChunkRoute], pools: &[P]) -> Vec<FeeRoundingWarning> {
+    let mut warnings: Vec<FeeRoundingWarning> = Vec::new();
+    for route in chunk_routes.iter().filter(|route| route.amount_in > U256::ZERO) {
+        let (Some(pool), Some(token_in)) = (pools.iter().find(|pool| Some(pool.address()) == route.pool_address), route.token_in) else {
+            continue;
+        };
+        let Some(fee_bps) = pool.constant_product_fee_bps() else {
+            continue;
+        };
+        let Some(min_chunk) = math::min_amount_with_nonzero_fee(fee_bps).filter(|min_chunk| route.amount_in < *min_chunk) else {
+            continue;
+        };
+        match warnings.iter_mut().find(|warning| warning.pool_address == pool.address()) {
+            Some(warning) => {
+                warning.chunks += 1;
+                warning.smallest_chunk = warning.smallest_chunk.min(route.amount_in);
+            }
+            None => warnings.push(FeeRoundingWarning {
+                pool_address: pool.address(),
+                pool_name: pool.name().to_string(),
+                fee_bps,
+                token_in,
+                chunks: 1,
+                smallest_chunk: route.amount_in,
+                min_chunk,
+            }),
+        }
+    }
+    warnings
+}
+
+/// Уменьшает количество чанков, чтобы комиссия ни одного пула с входным токеном не округлялась до нуля
+/// 
+/// Минимальный чанк - наибольший из `math::min_amount_with_nonzero_fee` по пулам
+/// constant product. Если даже один чанк меньше него, остается один чанк.
+/// С разбросом размеров минимальным должен быть самый мелкий чанк, поэтому
+/// средний чанк увеличивается на долю разброса.
+fn bump_tiny_chunks<P: AmmPool>(solver_config: SolverConfig, pools: &[P], ctx: &ConfigContext) -> SolverConfig {
+    let Some(min_smallest_chunk) = pools
+        .iter()
+        .filter(|pool| input_token(*pool, ctx).is_some())
+        .filter_map(|pool| pool.constant_product_fee_bps().and_then(math::min_amount_with_nonzero_fee))
+        .max()
+    else {
+        return solver_config;
+    };
+    let jitter_bps = solver_config.chunk_jitter.map_or(0, |jitter| jitter.bps);
+    let min_chunk = (min_smallest_chunk * U256::from(math::BPS_DENOMINATOR)).div_ceil(U256::from(math::BPS_DENOMINATOR - jitter_bps));
+    let chunk = solver_config.total_amount_in / U256::from(solver_config.num_chunks);
+    if chunk >= min_chunk {
+        return solver_config;
+    }
+    let num_chunks = (solver_config.total_amount_in / min_chunk).to::<u64>().clamp(1, solver_config.num_chunks);
+    solver_log!(solver_config, "Чанк {} меньше минимального {} для ненулевой комиссии, чанков: {} -> {}",
+        DisplayAmount::new(chunk, TokenId::USDC), DisplayAmount::new(min_chunk, TokenId::USDC), solver_config.num_chunks, num_chunks);
+    SolverConfig { num_chunks, ..solver_config }
+}
+
+/// Реальные (без скидки) резервы пулов для расчета `min_amount_out`
+struct MinOutTracker<P> {
+    real_pools: Vec<P>,
+    slippage_bps: u32,
+}
+
+impl<P: AmmPool> MinOutTracker<P> {
+    /// Применяет свап к реальной копии пула и возвращает выход минус slippage
+    /// и бюджет ошибки приближенного пула (`SimulationFidelity::Approximate`)
+    /// 
+    /// Реальные резервы не меньше резервов со скидкой, поэтому свап, прошедший
+    /// на пуле солвера, проходит и здесь; при ошибке минимальный выход нулевой.
+    fn record(&mut self, pool_index: usize, amount_in: U256, token_in: TokenId) -> U256 {
+        let pool = &mut self.real_pools[pool_index];
+        let slippage_bps = self.slippage_bps.saturating_add(pool.fidelity().error_bps()).min(math::BPS_DENOMINATOR);
+        pool.apply(amount_in, token_in)
+            .ok()
+            .and_then(|real_out| math::apply_slippage(real_out, slippage_bps))
+            .unwrap_or(U256::ZERO)
+    }
+}
+
+/// Применяет готовое распределение входа по пулам и строит по одной записи на пул
+/// 
+/// # Arguments
+/// * `pools` - Пулы (резервы обновляются через `mock_swap`)
+/// * `min_out` - Реальные резервы для `min_amount_out`
+/// * `ctx` - Профиль конфигурации
+/// * `impact_model` - Модель price impact записей
+/// * `best_spot_raw` - Лучшая спот-цена снимка для отклонения цены записей
+/// * `allocations` - Пары (индекс пула, сумма входа); нулевые суммы пропускаются
+fn apply_allocations<P: AmmPool>(
+    pools: &mut [P],
+    min_out: &mut MinOutTracker<P>,
+    ctx: &ConfigContext,
+    impact_model: &dyn ImpactModel,
+    best_spot_raw: f64,
+    allocations: &[(usize, U256)],
+) -> Result<(Vec<ChunkRoute>, U256), AggregationError> {
+    let mut chunk_routes = Vec::with_capacity(allocations.len());
+    let mut total_out = Accumulator::new("общий выход");
+
+    for &(pool_index, amount_in) in allocations {
+        if amount_in == U256::ZERO {
+            continue;
+        }
+        let pool = &mut pools[pool_index];
+        let Some(token_in) = input_token(pool, ctx) else {
+            continue;
+        };
+
+        let (decimals_in, decimals_out) = (pool.decimals(token_in), pool.decimals(output_token(pool, token_in)));
+        let price_impact = impact_model.price_impact(pool, amount_in, token_in);
+        // Отклоненный свап остается в маршруте нулевой записью с причиной пропуска
+        let (amount_out, min_amount_out, skipped_pools) = match pool.apply(amount_in, token_in) {
+            Ok(amount_out) => (amount_out, min_out.record(pool_index, amount_in, token_in), Vec::new()),
+            Err(_) => (
+                U256::ZERO,
+                U256::ZERO,
+                vec![PoolSkip { pool_name: pool.name().to_string(), reason: SkipReason::SwapRejected }],
+            ),
+        };
+        total_out.add(amount_out)?;
+
+        chunk_routes.push(ChunkRoute {
+            chunk_index: chunk_routes.len() as u64 + 1,
+            best_pool_name: pool.name().to_string(),
+            pool_address: Some(pool.address()),
+            dex: Some(pool.dex()),
+            token_in: Some(token_in),
+            amount_in,
+            amount_out,
+            token_out: Some(ctx.output_token),
+            amount_out_native: amount_out,
+            min_amount_out,
+            amount_in_decimal: config::to_decimal(amount_in, decimals_in),
+            amount_out_decimal: config::to_decimal(amount_out, decimals_out),
+            price_impact,
+            execution_price: math::execution_price(amount_in, amount_out, decimals_in, decimals_out),
+            spot_deviation_bps: spot_deviation_bps(amount_in, amount_out, best_spot_raw),
+            committed: false,
+            skipped_pools,
+        });
+    }
+
+    Ok((chunk_routes, total_out.total()))
+}
+
+/// Аналитически делит всю сумму между двумя пулами (см. `math::optimal_split_two`)
+fn solve_two_pool_analytic<P: AmmPool>(
+    pools: &mut [P],
+    min_out: &mut MinOutTracker<P>,
+    ctx: &ConfigContext,
+    solver_config: &SolverConfig,
+    [index_a, index_b]: [usize; 2],
+    fee_bps: u32,
+    initial_spot: PreTradeSpot,
+) -> Result<SolverResult, AggregationError> {
+    let reserves = |index: usize| {
+        let token_in = input_token(&pools[index], ctx).expect("пул отобран по входному токену");
+        pools[index].reserves(token_in)
+    };
+
+    let (to_a, to_b) = math::optimal_split_two(
+        solver_config.total_amount_in,
+        reserves(index_a),
+        reserves(index_b),
+        fee_bps,
+    );
+    solver_log!(solver_config, "Аналитическое разбиение: {} -> {} USDC, {} -> {} USDC",
+        pools[index_a].name(), config::usdc_to_decimal(to_a),
+        pools[index_b].name(), config::usdc_to_decimal(to_b));
+
+    let (chunk_routes, total_out) = apply_allocations(pools, min_out, ctx, solver_config.impact_model.model(), initial_spot.best_raw, &[(index_a, to_a), (index_b, to_b)])?;
+    finish_result(pools, chunk_routes, total_out, initial_spot, solver_config)
+}
+
+/// Распределяет всю сумму по всем пулам так, чтобы маржинальные цены
+/// после свапа совпали (см. `math::optimal_split_n`)
+fn solve_marginal_equalization<P: AmmPool>(
+    pools: &mut [P],
+    min_out: &mut MinOutTracker<P>,
+    ctx: &ConfigContext,
+    solver_config: &SolverConfig,
+    fee_bps: u32,
+    initial_spot: PreTradeSpot,
+) -> Result<SolverResult, AggregationError> {
+    let eligible: Vec<(usize, (U256, U256))> = pools
+        .iter()
+        .enumerate()
+        .filter_map(|(index, pool)| {
+            input_token(pool, ctx).map(|token_in| (index, pool.reserves(token_in)))
+        })
+        .collect();
+    let reserves: Vec<(U256, U256)> = eligible.iter().map(|(_, reserves)| *reserves).collect();
+
+    let split = math::optimal_split_n(solver_config.total_amount_in, &reserves, fee_bps);
+    let allocations: Vec<(usize, U256)> = eligible
+        .iter()
+        .zip(split)
+        .map(|((index, _), amount)| (*index, amount))
+        .collect();
+    for &(index, amount) in &allocations {
+        solver_log!(solver_config, "Выравнивание маржинальных цен: {} -> {} USDC",
+            pools[index].name(), config::usdc_to_decimal(amount));
+    }
+
+    let (chunk_routes, total_out) = apply_allocations(pools, min_out, ctx, solver_config.impact_model.model(), initial_spot.best_raw, &allocations)?;
+    finish_result(pools, chunk_routes, total_out, initial_spot, solver_config)
+}
+
+/// Точка кривой гранулярности: общий выход при заданном количестве чанков
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GranularityPoint {
+    pub num_chunks: u64,
+    pub total_out: U256,
+}
+
+/// Результат перебора количества чанков
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GranularitySweep {
+    pub points: Vec<GranularityPoint>,
+    /// Первое количество чанков, выход которого в пределах 0.5 bps от максимума
+    pub recommended_chunks: u64,
+}
+
+/// Допустимое отставание рекомендованной точки от максимума: 0.5 bps = 1 / 20000
+const RECOMMENDATION_TOLERANCE_DENOMINATOR: u64 = 20_000;
+
+/// Строит кривую выигрыша от гранулярности: общий выход при 1, 2, 4, ..., N чанках
+/// 
+/// Каждая точка считается на копии исходных пулов, поэтому все точки
+/// используют один и тот же снимок резервов. Последняя точка всегда равна
+/// `max_chunks`, даже если это не степень двойки.
+/// 
+/// Жадный алгоритм не гарантирует строгой монотонности: когда чанк крупный
+/// относительно мелкого пула, соседние точки могут отличаться на единицы ppm
+/// в обе стороны. Рекомендация поэтому считается от максимума по всей кривой.
+/// 
+/// # Arguments
+/// * `pools` - Пулы с исходными резервами (не изменяются)
+/// * `ctx` - Профиль конфигурации
+/// * `total_amount_in` - Общая сумма обмена в raw units
+/// * `max_chunks` - Максимальное количество чанков
+pub async fn granularity_sweep<P: AmmPool + Clone>(
+    pools: &[P],
+    ctx: &ConfigContext,
+    total_amount_in: U256,
+    max_chunks: u64,
+) -> Result<GranularitySweep> {
+    let mut chunk_counts = Vec::new();
+    let mut num_chunks = 1;
+    while num_chunks < max_chunks {
+        chunk_counts.push(num_chunks);
+        num_chunks *= 2;
+    }
+    chunk_counts.push(max_chunks.max(1));
+
+    let mut points = Vec::with_capacity(chunk_counts.len());
+    for num_chunks in chunk_counts {
+        let solver_config = SolverConfig { total_amount_in, num_chunks, verbose: false, ..Default::default() };
+        let result = find_best_routes(pools.to_vec(), ctx, &solver_config).await?;
+        points.push(GranularityPoint { num_chunks, total_out: result.total_weth_out });
+    }
+
+    let recommended_chunks = recommend_chunks(&points);
+    Ok(GranularitySweep { points, recommended_chunks })
+}
+
+/// Выбирает первую точку, выход которой отстает от максимума не более чем на 0.5 bps
+fn recommend_chunks(points: &[GranularityPoint]) -> u64 {
+    let max_out = points.iter().map(|point| point.total_out).max().unwrap_or(U256::ZERO);
+    points
+        .iter()
+        .find(|point| {
+            (max_out - point.total_out) * U256::from(RECOMMENDATION_TOLERANCE_DENOMINATOR) <= max_out
+        })
+        .map_or(1, |point| point.num_chunks)
+}
+
+/// Комиссии, уплаченные маршрутом в одном пуле (во входном токене, raw units)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeRevenue {
+    pub pool_address: Address,
+    pub pool_name: String,
+    pub total_fee: U256,
+    pub lp_fee: U256,       // Достается поставщикам ликвидности
+    pub protocol_fee: U256, // Уходит протоколу, если у пула включен protocol fee
+}
+
+/// Делит комиссии маршрута между LP и протоколом по пулам
+/// 
+/// Доля протокола берется из `DexConfig::protocol_fee_share` DEX пула и
+/// применяется только к пулам с `protocol_fee_enabled`. На выход свапа
+/// protocol fee не влияет: он отчеканивается из роста k при изменении ликвидности.
+pub fn fee_revenue<P: AmmPool>(routes: &[ChunkRoute], pools: &[P], ctx: &ConfigContext) -> Result<Vec<FeeRevenue>, AggregationError> {
+    let mut revenue: Vec<FeeRevenue> = Vec::new();
+    for route in routes {
+        let Some(pool) = pools.iter().find(|pool| Some(pool.address()) == route.pool_address) else {
+            continue;
+        };
+        let total_fee = pool.swap_fee_amount(route.amount_in);
+        let protocol_fee = match ctx.dexes.iter().find(|dex| dex.id == pool.dex()) {
+            Some(dex) if pool.protocol_fee_enabled() && dex.protocol_fee_share.1 > 0 => {
+                let (numerator, denominator) = dex.protocol_fee_share;
+                scale(total_fee, U256::from(numerator), U256::from(denominator), "protocol fee")?
+            }
+            _ => U256::ZERO,
+        };
+
+        let index = match revenue.iter().position(|entry| entry.pool_address == pool.address()) {
+            Some(index) => index,
+            None => {
+                revenue.push(FeeRevenue {
+                    pool_address: pool.address(),
+                    pool_name: pool.name().to_string(),
+                    total_fee: U256::ZERO,
+                    lp_fee: U256::ZERO,
+                    protocol_fee: U256::ZERO,
+                });
+                revenue.len() - 1
+            }
+        };
+        let entry = &mut revenue[index];
+        accumulate(&mut entry.total_fee, total_fee, "комиссия пула")?;
+        accumulate(&mut entry.protocol_fee, protocol_fee, "protocol fee")?;
+        accumulate(&mut entry.lp_fee, total_fee.saturating_sub(protocol_fee), "комиссия LP")?;
+    }
+    Ok(revenue)
+}
+
+/// Использование одного пула маршрутом
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolUsage {
+    pub pool_address: Address,
+    pub pool_name: String, // Имя первого чанка пула, только для вывода
+    pub chunks: u64,
+    pub amount_in: U256,
+}
+
+/// Группирует чанки маршрута по пулам в порядке первого использования
+///
+/// Ключ - адрес пула: у пулов разных DEX с одинаковыми символами токенов
+/// совпадают имена, но статистика не сливается. Чанки без пула и с нулевым
+/// выходом не учитываются.
+pub fn pool_usage(routes: &[ChunkRoute]) -> Vec<PoolUsage> {
+    let mut usage: Vec<PoolUsage> = Vec::new();
+    for route in routes.iter().filter(|route| route.amount_out > U256::ZERO) {
+        let Some(pool_address) = route.pool_address else {
+            continue;
+        };
+        match usage.iter_mut().find(|entry| entry.pool_address == pool_address) {
+            Some(entry) => {
+                entry.chunks += 1;
+                entry.amount_in = entry.amount_in.saturating_add(route.amount_in);
+            }
+            None => usage.push(PoolUsage {
+                pool_address,
+                pool_name: route.best_pool_name.clone(),
+                chunks: 1,
+                amount_in: route.amount_in,
+            }),
+        }
+    }
+    usage
+}
+
+/// Повторяет свапы маршрута на копиях пулов с журналом свапов (`PoolState::history`)
+///
+/// Возвращает использованные пулы в порядке первого использования; их
+/// журнал - последовательность виртуальных свапов решения. Выход каждого
+/// чанка сверяется с маршрутом (`amount_out_native`), поэтому повтор
+/// заодно проверяет, что суммы по пулам сходятся с чанками. Чанки пулов не
+/// из `states` (Uniswap V3) и отклоненные свапы пропускаются. Маршрут,
+/// решенный со скидкой на резервы, на реальных резервах не повторяется.
+pub fn replay_routes(states: &[PoolState], routes: &[ChunkRoute]) -> Result<Vec<PoolState>> {
+    let mut replayed: Vec<PoolState> = Vec::new();
+    for route in routes.iter().filter(|route| route.amount_out > U256::ZERO) {
+        let (Some(pool_address), Some(token_in)) = (route.pool_address, route.token_in) else {
+            continue;
+        };
+        let index = match replayed.iter().position(|pool| pool.pool_address == pool_address) {
+            Some(index) => index,
+            None => {
+                let Some(state) = states.iter().find(|state| state.pool_address == pool_address) else {
+                    continue;
+                };
+                let mut pool = state.clone();
+                pool.record_history();
+                replayed.push(pool);
+                replayed.len() - 1
+            }
+        };
+        let pool = &mut replayed[index];
+        let amount_out = pool
+            .swap_by_token(token_in, route.amount_in)
+            .map_err(|e| eyre!("чанк {}: свап в {} не повторяется: {}", route.chunk_index, pool.name, e))?;
+        if amount_out != route.amount_out_native {
+            return Err(eyre!("чанк {}: {} при повторе выдал {}, в маршруте {}",
+                route.chunk_index, pool.name, amount_out, route.amount_out_native));
+        }
+    }
+    Ok(replayed)
+}
+
+/// Токен, который пул отдает за `token_in`
+fn output_token<P: AmmPool>(pool: &P, token_in: TokenId) -> TokenId {
+    pool.other_token(token_in).expect("входной токен принадлежит пулу")
+}
+
+/// Decimals выходного токена профиля: из пула, если он отдает выходной токен напрямую,
+/// иначе (эквивалентный токен после конвертации) из метаданных `TokenId`
+fn output_decimals<P: AmmPool>(pool: &P, token_out: TokenId, ctx: &ConfigContext) -> u8 {
+    if token_out == ctx.output_token {
+        pool.decimals(token_out)
+    } else {
+        ctx.output_token.decimals()
+    }
+}
+
+/// Определяет входной токен пула
+/// 
+/// # Returns
+/// Входной токен профиля (token0 имеет приоритет) или `None`,
+/// если в пуле нет ни одного входного токена профиля
+fn input_token<P: AmmPool>(pool: &P, ctx: &ConfigContext) -> Option<TokenId> {
+    let (token0, token1) = pool.tokens();
+    [token0, token1].into_iter().find(|&token| ctx.is_input_token(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::Pool;
+
+    fn quiet_config(total_amount_in: U256, num_chunks: u64) -> SolverConfig {
+        SolverConfig { total_amount_in, num_chunks, verbose: false, ..Default::default() }
+    }
+
+    fn test_pool(reserve_usdc: u64, reserve_weth: U256) -> PoolState {
+        test_pool_at(0x11, reserve_usdc, reserve_weth)
+    }
+
+    fn test_pool_at(address_byte: u8, reserve_usdc: u64, reserve_weth: U256) -> PoolState {
+        crate::pool::test_pool(address_byte, TokenId::USDC, TokenId::WETH, U256::from(reserve_usdc), reserve_weth)
+    }
+
+    #[test]
+    fn validate_rejects_zero_amount() {
+        let solver_config = quiet_config(U256::ZERO, 100);
+        assert_eq!(solver_config.validate(), Err(SolverError::ZeroAmount));
+    }
+
+    #[test]
+    fn validate_collapses_small_amount_to_single_chunk() {
+        let one_unit = quiet_config(U256::from(1u64), 100);
+        assert_eq!(one_unit.validate().unwrap().num_chunks, 1);
+
+        let below_chunks = quiet_config(U256::from(99u64), 100);
+        let validated = below_chunks.validate().unwrap();
+        assert_eq!(validated.num_chunks, 1);
+        assert_eq!(validated.chunk_plan(), vec![U256::from(99u64)]);
+
+        let exact = quiet_config(U256::from(100u64), 100);
+        assert_eq!(exact.validate().unwrap().num_chunks, 100);
+    }
+
+    #[test]
+    fn chunk_plan_distributes_remainder() {
+        let plan = quiet_config(U256::from(1_000_003u64), 7).chunk_plan();
+        assert_eq!(plan.len(), 7);
+        assert_eq!(plan.iter().copied().sum::<U256>(), U256::from(1_000_003u64));
+        // 1_000_003 = 7 * 142_857 + 4: первые 4 чанка на 1 raw unit больше
+        assert_eq!(&plan[..4], &[U256::from(142_858u64); 4]);
+        assert_eq!(&plan[4..], &[U256::from(142_857u64); 3]);
+    }
+
+    #[test]
+    fn jittered_chunk_plan_keeps_total_and_bounds_and_is_reproducible() {
+        let jittered = |total: u64, chunks: u64, bps: u32, seed: u64| {
+            SolverConfig { chunk_jitter: Some(ChunkJitter { bps, seed }), ..quiet_config(U256::from(total), chunks) }.chunk_plan()
+        };
+        for (total, chunks, bps) in [(1_000_003u64, 7u64, 500u32), (999_999_999_999, 100, 2_000), (101, 100, 5_000), (10_000_000_000, 3, 9_999)] {
+            let base = U256::from(total / chunks);
+            let max_shift = base * U256::from(bps) / U256::from(10_000u64);
+            for seed in 0..20 {
+                let plan = jittered(total, chunks, bps, seed);
+                assert_eq!(plan.len() as u64, chunks);
+                assert_eq!(plan.iter().copied().sum::<U256>(), U256::from(total), "seed {}", seed);
+                // Отклонение от равного чанка в пределах разброса (+1 raw unit остатка)
+                for &amount in &plan {
+                    assert!(amount > U256::ZERO);
+                    let deviation = if amount > base { amount - base } else { base - amount };
+                    assert!(deviation <= max_shift + U256::from(1u64), "{} vs {} ± {}", amount, base, max_shift);
+                }
+                assert_eq!(plan, jittered(total, chunks, bps, seed));
+            }
+        }
+
+        let plan = jittered(999_999_999_999, 100, 2_000, 7);
+        assert!(plan.iter().collect::<std::collections::HashSet<_>>().len() > 90, "чанки почти все разные");
+        assert_ne!(plan, jittered(999_999_999_999, 100, 2_000, 8));
+
+        // Поправка суммы прижимает к границе разброса разные чанки, а не всегда первые
+        let mut pinned = [0usize; 10];
+        for seed in 0..200 {
+            let plan = jittered(10_000_000, 10, 5_000, seed);
+            for (index, &amount) in plan.iter().enumerate() {
+                if amount == U256::from(500_000u64) || amount == U256::from(1_500_000u64) {
+                    pinned[index] += 1;
+                }
+            }
+        }
+        assert!(pinned[5..].iter().sum::<usize>() * 2 > pinned[..5].iter().sum::<usize>(), "{:?}", pinned);
+
+        let invalid = SolverConfig { chunk_jitter: Some(ChunkJitter { bps: 10_000, seed: 0 }), ..quiet_config(U256::from(100u64), 10) };
+        assert_eq!(invalid.validate(), Err(SolverError::InvalidChunkJitter(10_000)));
+    }
+
+    #[tokio::test]
+    async fn jittered_routes_carry_heterogeneous_chunks_and_min_outs() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![test_pool(2_000_000_000_000, U256::from(800u64) * weth)];
+        let solver_config = SolverConfig {
+            chunk_jitter: Some(ChunkJitter { bps: 3_000, seed: 42 }),
+            ..quiet_config(U256::from(50_000_000_000u64), 20)
+        };
+        let result = find_best_routes(pools, &ConfigContext::default(), &solver_config).await.unwrap();
+
+        let amounts: Vec<U256> = result.chunk_routes.iter().map(|route| route.amount_in).collect();
+        assert_eq!(amounts, solver_config.chunk_plan());
+        assert_eq!(amounts.iter().copied().sum::<U256>(), solver_config.total_amount_in);
+        let [usage] = &pool_usage(&result.chunk_routes)[..] else { panic!("один пул") };
+        assert_eq!(usage.amount_in, solver_config.total_amount_in);
+        // min_amount_out считается по фактическому размеру каждого чанка
+        for route in &result.chunk_routes {
+            assert!(route.min_amount_out > U256::ZERO && route.min_amount_out <= route.amount_out);
+        }
+    }
+
+    #[tokio::test]
+    async fn greedy_routes_sum_to_total_amount() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        for (total, chunks) in [(1_000_003u64, 7u64), (999_999_999_999, 100), (101, 100), (12_345_678_901, 13)] {
+            let pools = vec![test_pool(2_000_000_000_000, U256::from(800u64) * weth)];
+            let result = find_best_routes(pools, &ConfigContext::default(), &quiet_config(U256::from(total), chunks)).await.unwrap();
+
+            let routed: U256 = result.chunk_routes.iter().map(|route| route.amount_in).sum();
+            assert_eq!(routed, U256::from(total));
+            for route in &result.chunk_routes {
+                assert_eq!(route.amount_in_decimal, config::usdc_to_decimal(route.amount_in));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_pools_built_without_network() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = [
+            Pool::for_test(Address::repeat_byte(0x11), TokenId::USDC, TokenId::WETH,
+                U256::from(3_000_000_000_000u64), U256::from(1_200u64) * weth, "Deep"),
+            Pool::for_test(Address::repeat_byte(0x22), TokenId::WETH, TokenId::USDC,
+                U256::from(200u64) * weth, U256::from(500_000_000_000u64), "Shallow"),
+            Pool::for_test(Address::repeat_byte(0x33), TokenId::WETH, TokenId(Address::repeat_byte(0xee)),
+                U256::from(10u64) * weth, U256::from(10u64) * weth, "Foreign"),
+        ];
+        let solver_config = quiet_config(U256::from(100_000_000_000u64), 25);
+        let result = find_best_routes(Pool::states(&pools), &ConfigContext::default(), &solver_config).await.unwrap();
+
+        let usage = pool_usage(&result.chunk_routes);
+        let chunks = |name: &str| usage.iter().find(|usage| usage.pool_name == name).map_or(0, |usage| usage.chunks);
+        assert_eq!(chunks("Deep") + chunks("Shallow"), 25);
+        assert!(chunks("Deep") > chunks("Shallow") && chunks("Shallow") > 0, "{:?}", usage);
+        assert_eq!(usage.iter().map(|usage| usage.amount_in).sum::<U256>(), solver_config.total_amount_in);
+        assert!(result.chunk_routes.iter().all(|route| route.skipped_pools
+            == [PoolSkip { pool_name: "Foreign".to_string(), reason: SkipReason::MissingInputToken }]));
+        // Решение не меняет исходные пулы
+        assert_eq!(pools[0].liquidity_in(TokenId::USDC), Some(U256::from(3_000_000_000_000u64)));
+    }
+
+    #[tokio::test]
+    async fn find_best_routes_zero_amount_is_error() {
+        let pools = vec![test_pool(1_000_000_000_000, U256::from(10u64).pow(U256::from(21u64)))];
+        let solver_config = quiet_config(U256::ZERO, 100);
+
+        let err = find_best_routes(pools, &ConfigContext::default(), &solver_config).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<SolverError>(), Some(&SolverError::ZeroAmount));
+    }
+
+    #[tokio::test]
+    async fn impact_model_switches_all_impacts_consistently() {
+        use crate::impact::OracleReferenceModel;
+        // Спот 0.0004 WETH за USDC
+        let pools = vec![test_pool(2_000_000_000_000, U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64)))];
+        let spot_config = quiet_config(U256::from(100_000_000_000u64), 10);
+        let oracle_config = |price: &str| SolverConfig {
+            impact_model: ImpactModelChoice::OracleReference(OracleReferenceModel::from_decimal(price).unwrap()),
+            ..spot_config.clone()
+        };
+
+        let spot = find_best_routes(pools.clone(), &ConfigContext::default(), &spot_config).await.unwrap();
+        let at_spot = find_best_routes(pools.clone(), &ConfigContext::default(), &oracle_config("0.0004")).await.unwrap();
+        let above = find_best_routes(pools, &ConfigContext::default(), &oracle_config("0.0005")).await.unwrap();
+
+        // Модель меняет только оценку impact, но не маршрут
+        assert_eq!(above.total_weth_out, spot.total_weth_out);
+        assert!((at_spot.cumulative_price_impact - spot.cumulative_price_impact).abs() < 1e-9);
+        // Эталон выше в 1.25 раза: 1 - impact сплита умножается на 0.8
+        let expected = 1.0 - 0.8 * (1.0 - spot.cumulative_price_impact);
+        assert!((above.cumulative_price_impact - expected).abs() < 1e-9);
+        // Impact каждого чанка считается от того же эталона: оракул выше спота на 25%
+        for (spot_route, oracle_route) in spot.chunk_routes.iter().zip(&above.chunk_routes) {
+            assert!(oracle_route.price_impact > spot_route.price_impact);
+            assert!(oracle_route.price_impact > 0.2);
+        }
+    }
+
+    #[tokio::test]
+    async fn find_best_routes_small_amount_uses_single_chunk() {
+        let pools = vec![test_pool(1_000_000_000_000, U256::from(10u64).pow(U256::from(21u64)))];
+        let solver_config = quiet_config(U256::from(1u64), 100);
+
+        let result = find_best_routes(pools, &ConfigContext::default(), &solver_config).await.unwrap();
+        assert_eq!(result.chunk_routes.len(), 1);
+        assert_eq!(result.chunk_routes[0].amount_in, U256::from(1u64));
+    }
+
+    #[tokio::test]
+    async fn find_best_routes_records_price_impact() {
+        let pools = vec![test_pool(2_000_000_000_000, U256::from(800u64) * U256::from(10u64).pow(U256::from(18u64)))];
+        let solver_config = quiet_config(U256::from(1_000_000_000_000u64), 10);
+
+        let result = find_best_routes(pools, &ConfigContext::default(), &solver_config).await.unwrap();
+
+        // Impact чанка считается от текущей (уже сдвинутой) цены пула
+        for route in &result.chunk_routes {
+            assert!(route.price_impact > 0.003 && route.price_impact < 1.0);
+        }
+        // Общий impact считается от начальной цены и больше impact любого отдельного чанка
+        let max_chunk_impact = result.chunk_routes.iter().map(|r| r.price_impact).fold(0.0, f64::max);
+        assert!(result.cumulative_price_impact > max_chunk_impact);
+        assert!(result.cumulative_price_impact < 1.0);
+    }
+
+    #[tokio::test]
+    async fn granularity_sweep_is_non_decreasing() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        // Два пула с одинаковой ценой, но вдвое разной глубиной
+        let pools = vec![
+            test_pool_at(0x11, 2_000_000_000_000, U256::from(800u64) * weth),
+            test_pool_at(0x22, 1_000_000_000_000, U256::from(400u64) * weth),
+        ];
+        // Сумма делится на все степени двойки до 64 без остатка
+        let total = U256::from(1u64 << 20) * U256::from(1_000_000u64);
+
+        let sweep = granularity_sweep(&pools, &ConfigContext::default(), total, 64).await.unwrap();
+
+        let counts: Vec<u64> = sweep.points.iter().map(|p| p.num_chunks).collect();
+        assert_eq!(counts, vec![1, 2, 4, 8, 16, 32, 64]);
+        for pair in sweep.points.windows(2) {
+            assert!(pair[1].total_out >= pair[0].total_out, "{:?}", sweep.points);
+        }
+        // Разбиение на части должно помогать по сравнению с одним пулом целиком
+        assert!(sweep.points.last().unwrap().total_out > sweep.points[0].total_out);
+        // Исходные пулы не изменились
+        assert_eq!(pools[0].reserve_token0, U256::from(2_000_000_000_000u64));
+
+        let json = serde_json::to_string(&sweep.points).unwrap();
+        assert!(json.starts_with("[{\"num_chunks\":1,"));
+    }
+
+    #[test]
+    fn recommend_chunks_picks_first_point_within_half_bps() {
+        let point = |num_chunks, total_out: u64| GranularityPoint { num_chunks, total_out: U256::from(total_out) };
+        let points = vec![
+            point(1, 9_000_000),
+            point(2, 9_990_000),
+            point(4, 9_999_600), // отставание 0.4 bps от максимума
+            point(8, 10_000_000),
+        ];
+        assert_eq!(recommend_chunks(&points), 4);
+
+        let points = vec![point(1, 9_999_000), point(2, 10_000_000)];
+        assert_eq!(recommend_chunks(&points), 2);
+    }
+
+    #[tokio::test]
+    async fn two_pool_analytic_beats_or_equals_greedy() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![
+            test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth),
+            test_pool_at(0x22, 800_000_000_000, U256::from(330u64) * weth),
+        ];
+        let ctx = ConfigContext::default();
+        let total = U256::from(1_000_000_000_000u64);
+
+        let greedy = find_best_routes(pools.clone(), &ctx, &quiet_config(total, 100)).await.unwrap();
+        let analytic_config = SolverConfig { strategy: Strategy::TwoPoolAnalytic, ..quiet_config(total, 100) };
+        let analytic = find_best_routes(pools, &ctx, &analytic_config).await.unwrap();
+
+        assert!(analytic.total_weth_out >= greedy.total_weth_out);
+        // По одной записи на каждый пул, суммы входа сходятся
+        assert_eq!(analytic.chunk_routes.len(), 2);
+        let allocated: U256 = analytic.chunk_routes.iter().map(|r| r.amount_in).sum();
+        assert_eq!(allocated, total);
+    }
+
+    #[tokio::test]
+    async fn two_pool_analytic_falls_back_to_greedy() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![test_pool(3_000_000_000_000, U256::from(1_200u64) * weth)];
+        let analytic_config = SolverConfig { strategy: Strategy::TwoPoolAnalytic, ..quiet_config(U256::from(1_000_000u64), 10) };
+
+        let result = find_best_routes(pools, &ConfigContext::default(), &analytic_config).await.unwrap();
+        assert_eq!(result.chunk_routes.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn reserve_haircut_lowers_output_monotonically() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![
+            test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth),
+            test_pool_at(0x22, 800_000_000_000, U256::from(330u64) * weth),
+        ];
+        let ctx = ConfigContext::default();
+        let total = U256::from(100_000_000_000u64);
+
+        let mut previous = None;
+        for haircut in [0u32, 10, 50, 100, 1_000] {
+            let config = SolverConfig { reserve_haircut_bps: haircut, ..quiet_config(total, 20) };
+            let result = find_best_routes(pools.clone(), &ctx, &config).await.unwrap();
+            assert_eq!(result.reserve_haircut_bps, haircut);
+            if let Some(previous_out) = previous {
+                assert!(result.total_weth_out < previous_out);
+            }
+            previous = Some(result.total_weth_out);
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_haircut_matches_normal_quote() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![
+            test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth),
+            test_pool_at(0x22, 800_000_000_000, U256::from(330u64) * weth),
+        ];
+        let ctx = ConfigContext::default();
+        let total = U256::from(100_000_000_000u64);
+
+        let normal = find_best_routes(pools.clone(), &ctx, &quiet_config(total, 20)).await.unwrap();
+        let zero = SolverConfig { reserve_haircut_bps: 0, ..quiet_config(total, 20) };
+        let haircut = find_best_routes(pools, &ctx, &zero).await.unwrap();
+        assert_eq!(format!("{:?}", normal), format!("{:?}", haircut));
+
+        let invalid = SolverConfig { reserve_haircut_bps: 10_001, ..quiet_config(total, 20) };
+        assert_eq!(invalid.validate(), Err(SolverError::InvalidHaircut(10_001)));
+    }
+
+    #[tokio::test]
+    async fn marginal_equalization_never_worse_than_greedy() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        // Заметно более дорогой пул не должен получить вход, пока объем умеренный
+        let mut expensive = test_pool_at(0x44, 500_000_000_000, U256::from(150u64) * weth);
+        expensive.name = "Expensive".to_string();
+        let pools = vec![
+            test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth),
+            test_pool_at(0x22, 800_000_000_000, U256::from(330u64) * weth),
+            test_pool_at(0x33, 1_500_000_000_000, U256::from(590u64) * weth),
+            expensive,
+        ];
+        let ctx = ConfigContext::default();
+
+        for total in [1_000_000_000u64, 200_000_000_000, 2_000_000_000_000] {
+            let total = U256::from(total);
+            let greedy = find_best_routes(pools.clone(), &ctx, &quiet_config(total, 1000)).await.unwrap();
+            let equalized_config = SolverConfig { strategy: Strategy::MarginalEqualization, ..quiet_config(total, 1000) };
+            let equalized = find_best_routes(pools.clone(), &ctx, &equalized_config).await.unwrap();
+
+            assert!(equalized.total_weth_out >= greedy.total_weth_out);
+            let allocated: U256 = equalized.chunk_routes.iter().map(|r| r.amount_in).sum();
+            assert_eq!(allocated, total);
+            if total <= U256::from(200_000_000_000u64) {
+                assert!(equalized.chunk_routes.iter().all(|r| r.best_pool_name != "Expensive"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn min_amount_out_uses_real_reserves_and_slippage() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![
+            test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth),
+            test_pool_at(0x22, 800_000_000_000, U256::from(330u64) * weth),
+        ];
+        let ctx = ConfigContext::default();
+        let total = U256::from(100_000_000_000u64);
+
+        let plain = find_best_routes(pools.clone(), &ctx, &quiet_config(total, 20)).await.unwrap();
+        for route in &plain.chunk_routes {
+            assert_eq!(Some(route.min_amount_out), math::apply_slippage(route.amount_out, config::DEFAULT_SLIPPAGE_BPS));
+        }
+
+        // Со скидкой на резервы котировка падает, а min_amount_out считается по реальным резервам
+        for strategy in [Strategy::Greedy, Strategy::MarginalEqualization] {
+            let haircut = SolverConfig { reserve_haircut_bps: 100, slippage_bps: 0, strategy, ..quiet_config(total, 20) };
+            let result = find_best_routes(pools.clone(), &ctx, &haircut).await.unwrap();
+            for route in &result.chunk_routes {
+                assert!(route.min_amount_out > route.amount_out);
+            }
+        }
+
+        let invalid = SolverConfig { slippage_bps: 10_001, ..quiet_config(total, 20) };
+        assert_eq!(invalid.validate(), Err(SolverError::InvalidSlippage(10_001)));
+    }
+
+    #[tokio::test]
+    async fn greedy_compares_weighted_and_constant_product_pools() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let half = U256::from(500_000_000_000_000_000u64);
+        let mut balancer = test_pool_at(0x55, 3_000_000_000_000, U256::from(1_200u64) * weth)
+            .into_weighted(alloy::primitives::B256::ZERO, [(TokenId::USDC, half), (TokenId::WETH, half)], U256::from(1_000_000_000_000_000u64));
+        balancer.name = "Balancer".to_string();
+        let pools = vec![test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth), balancer];
+        let ctx = ConfigContext::default();
+        let total = U256::from(100_000_000_000u64);
+
+        let result = find_best_routes(pools.clone(), &ctx, &quiet_config(total, 50)).await.unwrap();
+        // При одинаковых резервах пул с комиссией 0.1% выгоднее V2 с 0.3%
+        assert!(result.chunk_routes.iter().any(|route| route.best_pool_name == "Balancer"));
+        assert!(result.chunk_routes.iter().all(|route| route.price_impact > 0.0 && route.price_impact < 1.0));
+
+        // Аналитическая стратегия откатывается к жадной
+        let analytic = SolverConfig { strategy: Strategy::MarginalEqualization, ..quiet_config(total, 50) };
+        let fallback = find_best_routes(pools, &ctx, &analytic).await.unwrap();
+        assert_eq!(fallback.chunk_routes.len(), 50);
+        assert_eq!(fallback.total_weth_out, result.total_weth_out);
+    }
+
+    #[tokio::test]
+    async fn skip_reasons_are_tracked_and_aggregated() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut foreign = crate::pool::test_pool(0x21, TokenId::WETH, TokenId(alloy::primitives::Address::repeat_byte(0x77)),
+            U256::from(1_000u64) * weth, U256::from(1_000u64) * weth);
+        foreign.name = "Foreign".to_string();
+        let mut empty = test_pool_at(0x22, 0, U256::ZERO);
+        empty.name = "Empty".to_string();
+        // Один wei WETH: любой чанк дает нулевой выход
+        let mut dust = test_pool_at(0x23, 1_000_000_000_000, U256::from(1u64));
+        dust.name = "Dust".to_string();
+        let pools = vec![test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth), foreign, empty, dust];
+
+        let result = find_best_routes(pools, &ConfigContext::default(), &quiet_config(U256::from(10_000_000_000u64), 10)).await.unwrap();
+
+        let first_chunk: Vec<(&str, SkipReason)> = result.chunk_routes[0]
+            .skipped_pools
+            .iter()
+            .map(|skip| (skip.pool_name.as_str(), skip.reason))
+            .collect();
+        assert_eq!(first_chunk, vec![
+            ("Foreign", SkipReason::MissingInputToken),
+            ("Empty", SkipReason::EmptyReserves),
+            ("Dust", SkipReason::ZeroQuote),
+        ]);
+
+        assert_eq!(result.diagnostics.skip_counts.len(), 3);
+        assert!(result.diagnostics.skip_counts.iter().all(|count| count.chunks == 10));
+        let summary = result.diagnostics.top_skip_reasons(5);
+        assert!(summary.contains(&"Dust пропущен в 10 чанках: нулевая котировка".to_string()));
+    }
+
+    #[tokio::test]
+    async fn execution_price_is_compared_to_liquidity_weighted_spot() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        // Спот 2500 и 2600 USDC/WETH, входные резервы 3:1 -> взвешенный спот 2525
+        let pools = vec![
+            test_pool_at(0x11, 3_000_000_000_000, U256::from(1_200u64) * weth),
+            test_pool_at(0x12, 1_000_000_000_000, U256::from(1_000_000_000_000u64) * weth / U256::from(2_600_000_000u64)),
+        ];
+        let result = find_best_routes(pools, &ConfigContext::default(), &quiet_config(U256::from(100_000_000_000u64), 10)).await.unwrap();
+
+        assert!((result.spot_price - 2525.0).abs() < 1e-6);
+        let expected_price = 100_000.0 / result.total_weth_out_decimal;
+        let execution_price = result.execution_price.unwrap();
+        assert!((execution_price - expected_price).abs() < 1e-6);
+        let deviation = result.price_deviation_bps.unwrap();
+        assert!((deviation - (2525.0 / execution_price - 1.0) * 10_000.0).abs() < 1e-9);
+        // Комиссия и impact: цена хуже лучшего спота среди пулов
+        assert!(execution_price > 2500.0);
+
+        for route in &result.chunk_routes {
+            let price = route.execution_price.unwrap();
+            assert!((price - route.amount_in_decimal / route.amount_out_decimal).abs() < 1e-6);
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_output_has_no_execution_price() {
+        // Один wei WETH: каждый чанк дает нулевой выход
+        let pools = vec![test_pool(1_000_000_000_000, U256::from(1u64))];
+        let result = find_best_routes(pools, &ConfigContext::default(), &quiet_config(U256::from(10_000_000_000u64), 10)).await.unwrap();
+
+        assert_eq!(result.total_weth_out, U256::ZERO);
+        assert_eq!(result.execution_price, None);
+        assert_eq!(result.price_deviation_bps, None);
+        assert!(result.chunk_routes.iter().all(|route| route.execution_price.is_none()));
+        assert!(result.spot_price > 0.0);
+    }
+
+    #[tokio::test]
+    async fn wrapper_route_wins_only_with_favorable_rate() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let wrapper = TokenId(alloy::primitives::Address::repeat_byte(0x5e));
+        // WETH по 2500 USDC, обертка по 3000 USDC
+        let direct = test_pool_at(0x11, 2_500_000_000_000, U256::from(1_000u64) * weth);
+        let mut wrapped = crate::pool::test_pool(0x12, TokenId::USDC, wrapper,
+            U256::from(3_000_000_000_000u64), U256::from(1_000u64) * weth);
+        wrapped.name = "Wrapped".to_string();
+        let with_rate = |rate: u64| ConfigContext {
+            output_equivalents: vec![config::OutputEquivalent { token: wrapper, rate: U256::from(rate) }],
+            ..ConfigContext::default()
+        };
+        let solver_config = quiet_config(U256::from(1_000_000_000u64), 1);
+
+        // 1.25 WETH за обертку: 2400 USDC/WETH - обертка выгоднее
+        let ctx = with_rate(1_250_000_000_000_000_000);
+        let result = find_best_routes(vec![direct.clone(), wrapped.clone()], &ctx, &solver_config).await.unwrap();
+        let route = &result.chunk_routes[0];
+        assert_eq!(route.best_pool_name, "Wrapped");
+        assert_eq!(route.token_out, Some(wrapper));
+        assert_eq!(route.amount_out, ctx.convert_output(wrapper, route.amount_out_native).unwrap());
+        assert_eq!(result.output_by_token, vec![(wrapper, route.amount_out_native)]);
+        assert_eq!(result.total_weth_out, route.amount_out);
+
+        // 1.1 WETH за обертку: ~2727 USDC/WETH - прямой пул выгоднее
+        let result = find_best_routes(vec![direct.clone(), wrapped.clone()], &with_rate(1_100_000_000_000_000_000), &solver_config).await.unwrap();
+        assert_eq!(result.chunk_routes[0].token_out, Some(TokenId::WETH));
+        assert_eq!(result.chunk_routes[0].amount_out, result.chunk_routes[0].amount_out_native);
+
+        // Без настроенного курса обертка не участвует
+        let result = find_best_routes(vec![direct, wrapped], &ConfigContext::default(), &solver_config).await.unwrap();
+        assert_eq!(result.chunk_routes[0].token_out, Some(TokenId::WETH));
+        assert_eq!(result.chunk_routes[0].skipped_pools, vec![
+            PoolSkip { pool_name: "Wrapped".to_string(), reason: SkipReason::UnsupportedOutputToken },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn pools_with_colliding_names_stay_distinct_in_routes() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        // Второй пул подделывает имя первого
+        let genuine = test_pool_at(0x11, 2_000_000_000_000, U256::from(800u64) * weth);
+        let mut impostor = test_pool_at(0x22, 1_000_000_000_000, U256::from(400u64) * weth);
+        impostor.name = genuine.name.clone();
+        let pools = vec![genuine.clone(), impostor.clone()];
+        let ctx = ConfigContext::default();
+        let result = find_best_routes(pools.clone(), &ctx, &quiet_config(U256::from(90_000_000_000u64), 30)).await.unwrap();
+
+        let chunks_in = |address| result.chunk_routes.iter().filter(|route| route.pool_address == Some(address)).count() as u64;
+        let (genuine_chunks, impostor_chunks) = (chunks_in(genuine.pool_address), chunks_in(impostor.pool_address));
+        // Глубина 2:1 - объем делится примерно так же, имена не сливают пулы
+        assert_eq!(genuine_chunks + impostor_chunks, 30);
+        assert!(genuine_chunks > impostor_chunks && impostor_chunks > 0);
+
+        let revenue = fee_revenue(&result.chunk_routes, &pools, &ctx).unwrap();
+        assert_eq!(revenue.len(), 2);
+        let chunk_fee = U256::from(9_000_000u64); // 0.3% от 3000 USDC
+        for (address, chunks) in [(genuine.pool_address, genuine_chunks), (impostor.pool_address, impostor_chunks)] {
+            let entry = revenue.iter().find(|entry| entry.pool_address == address).unwrap();
+            assert_eq!(entry.total_fee, chunk_fee * U256::from(chunks));
+        }
+    }
+
+    #[tokio::test]
+    async fn pool_usage_aggregates_by_address_not_name() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let genuine = test_pool_at(0x11, 2_000_000_000_000, U256::from(800u64) * weth);
+        let mut twin = test_pool_at(0x22, 2_000_000_000_000, U256::from(800u64) * weth);
+        twin.name = genuine.name.clone();
+        twin.dex = DexId::SUSHISWAP;
+        let result = find_best_routes(vec![genuine.clone(), twin.clone()], &ConfigContext::default(),
+            &quiet_config(U256::from(60_000_000_000u64), 20)).await.unwrap();
+
+        let usage = pool_usage(&result.chunk_routes);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].pool_address, result.chunk_routes[0].pool_address.unwrap());
+        assert!(usage.iter().all(|entry| entry.pool_name == genuine.name));
+        assert_eq!(usage.iter().map(|entry| entry.chunks).sum::<u64>(), 20);
+        assert_eq!(usage.iter().map(|entry| entry.amount_in).sum::<U256>(), U256::from(60_000_000_000u64));
+        for entry in &usage {
+            let routed: U256 = result.chunk_routes.iter()
+                .filter(|route| route.pool_address == Some(entry.pool_address))
+                .map(|route| route.amount_in)
+                .sum();
+            assert_eq!(entry.amount_in, routed);
+        }
+    }
+
+    #[test]
+    fn pool_usage_skips_routes_without_output() {
+        let pool = test_pool_at(0x11, 1_000_000, U256::from(1_000u64));
+        let other = test_pool_at(0x22, 1_000_000, U256::from(1_000u64));
+        let route = |pool: &PoolState, amount_out: u64| bare_route(pool, U256::from(100u64), U256::from(amount_out));
+        let mut unrouted = route(&other, 0);
+        unrouted.pool_address = None;
+        let usage = pool_usage(&[route(&pool, 5), unrouted, route(&other, 0), route(&pool, 7)]);
+        assert_eq!(usage, vec![PoolUsage {
+            pool_address: pool.pool_address,
+            pool_name: pool.name.clone(),
+            chunks: 2,
+            amount_in: U256::from(200u64),
+        }]);
+    }
+
+    #[tokio::test]
+    async fn replayed_routes_match_usage_and_reach_final_reserves() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let states = vec![
+            test_pool_at(0x11, 2_000_000_000_000, U256::from(800u64) * weth),
+            test_pool_at(0x22, 900_000_000_000, U256::from(365u64) * weth),
+            test_pool_at(0x33, 1_000, U256::from(1u64)), // Не получает чанков
+        ];
+        let result = find_best_routes(states.clone(), &ConfigContext::default(),
+            &quiet_config(U256::from(150_000_000_000u64), 30)).await.unwrap();
+
+        let replayed = replay_routes(&states, &result.chunk_routes).unwrap();
+        let usage = pool_usage(&result.chunk_routes);
+        assert_eq!(replayed.iter().map(|pool| pool.pool_address).collect::<Vec<_>>(),
+            usage.iter().map(|usage| usage.pool_address).collect::<Vec<_>>());
+        let mut emitted = U256::ZERO;
+        for (pool, usage) in replayed.iter().zip(&usage) {
+            let history = pool.history.as_ref().unwrap();
+            let original = states.iter().find(|state| state.pool_address == pool.pool_address).unwrap();
+            assert_eq!(history.initial, original.snapshot());
+            assert_eq!(history.events.len() as u64, usage.chunks);
+            assert_eq!(history.replay(), Some(pool.snapshot()));
+            let (absorbed, out) = history.totals(pool.token0 == TokenId::USDC);
+            assert_eq!(absorbed, usage.amount_in);
+            emitted += out;
+        }
+        assert_eq!(emitted, result.total_weth_out);
+
+        // Выход, не совпадающий с повтором, - ошибка с номером чанка
+        let mut tampered = result.chunk_routes;
+        tampered[3].amount_out_native += U256::from(1u64);
+        assert!(replay_routes(&states, &tampered).unwrap_err().to_string().starts_with("чанк 4:"));
+    }
+
+    #[tokio::test]
+    async fn fee_revenue_splits_protocol_share_only_when_enabled() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pool = test_pool(2_000_000_000_000, U256::from(800u64) * weth);
+        pool.dex = DexId::QUICKSWAP;
+        let ctx = ConfigContext::default();
+        // 120_000 USDC: комиссия 0.3% = 360 USDC
+        let result = find_best_routes(vec![pool.clone()], &ctx, &quiet_config(U256::from(120_000_000_000u64), 10)).await.unwrap();
+
+        let fee_off = fee_revenue(&result.chunk_routes, std::slice::from_ref(&pool), &ctx).unwrap();
+        assert_eq!(fee_off, vec![FeeRevenue {
+            pool_address: pool.pool_address,
+            pool_name: pool.name.clone(),
+            total_fee: U256::from(360_000_000u64),
+            lp_fee: U256::from(360_000_000u64),
+            protocol_fee: U256::ZERO,
+        }]);
+
+        // feeTo задан: протокол получает 1/6 комиссии
+        pool.protocol_fee_enabled = true;
+        let fee_on = fee_revenue(&result.chunk_routes, std::slice::from_ref(&pool), &ctx).unwrap();
+        assert_eq!(fee_on[0].protocol_fee, U256::from(60_000_000u64));
+        assert_eq!(fee_on[0].lp_fee, U256::from(300_000_000u64));
+
+        // Доля настраивается для DEX
+        let mut custom = ctx.clone();
+        custom.dexes.iter_mut().find(|dex| dex.id == DexId::QUICKSWAP).unwrap().protocol_fee_share = (1, 3);
+        let fee_custom = fee_revenue(&result.chunk_routes, std::slice::from_ref(&pool), &custom).unwrap();
+        assert_eq!(fee_custom[0].protocol_fee, U256::from(120_000_000u64));
+    }
+
+    /// Маршрут из одного чанка через `pool` без диагностических полей
+    fn bare_route(pool: &PoolState, amount_in: U256, amount_out: U256) -> ChunkRoute {
+        ChunkRoute {
+            chunk_index: 1,
+            best_pool_name: pool.name.clone(),
+            pool_address: Some(pool.pool_address),
+            dex: Some(pool.dex),
+            token_in: Some(TokenId::USDC),
+            amount_in,
+            amount_out,
+            token_out: Some(TokenId::WETH),
+            amount_out_native: amount_out,
+            min_amount_out: amount_out,
+            amount_in_decimal: 0.0,
+            amount_out_decimal: 0.0,
+            price_impact: 0.0,
+            execution_price: None,
+            spot_deviation_bps: None,
+            committed: false,
+            skipped_pools: Vec::new(),
+        }
+    }
+
+    fn extreme_amount() -> impl proptest::strategy::Strategy<Value = U256> {
+        use proptest::prelude::*;
+        prop_oneof![
+            any::<u64>().prop_map(U256::from),
+            any::<u128>().prop_map(|low| U256::MAX - U256::from(low)),
+            Just(U256::MAX),
+        ]
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn route_aggregates_are_exact_or_typed_errors(amounts in proptest::collection::vec((extreme_amount(), extreme_amount()), 1..6)) {
+            use alloy::primitives::U512;
+            let weth = U256::from(10u64).pow(U256::from(18u64));
+            let mut pool = test_pool(2_000_000_000_000, U256::from(800u64) * weth);
+            pool.dex = DexId::QUICKSWAP;
+            pool.protocol_fee_enabled = true;
+            let routes: Vec<ChunkRoute> = amounts.iter().map(|&(amount_in, amount_out)| bare_route(&pool, amount_in, amount_out)).collect();
+            let exact = |values: Vec<U256>| values.into_iter().map(U512::from).fold(U512::ZERO, |total, value| total + value);
+            let fits = |value: U512| value <= U512::from(U256::MAX);
+            let exact_in = exact(amounts.iter().map(|&(amount_in, _)| amount_in).collect());
+            let exact_out = exact(amounts.iter().map(|&(_, amount_out)| amount_out).collect());
+
+            let spot = PreTradeSpot { best_raw: 0.0, liquidity_weighted: 0.0 };
+            let total_out = Accumulator::sum("общий выход", amounts.iter().map(|&(_, amount_out)| amount_out));
+            proptest::prop_assert_eq!(total_out.is_ok(), fits(exact_out));
+            let result = finish_result(std::slice::from_ref(&pool), routes, total_out.unwrap_or(U256::MAX), spot, &quiet_config(U256::from(1u64), 1));
+            match result {
+                Ok(result) => {
+                    proptest::prop_assert!(fits(exact_in) && fits(exact_out));
+                    proptest::prop_assert_eq!(result.output_by_token.clone(), vec![(TokenId::WETH, exact_out.to::<U256>())]);
+
+                    let revenue = fee_revenue(&result.chunk_routes, std::slice::from_ref(&pool), &ConfigContext::default());
+                    let exact_fee = exact(result.chunk_routes.iter().map(|route| pool.swap_fee_amount(route.amount_in)).collect());
+                    match revenue {
+                        Ok(revenue) => {
+                            proptest::prop_assert_eq!(U512::from(revenue[0].total_fee), exact_fee);
+                            proptest::prop_assert_eq!(revenue[0].lp_fee + revenue[0].protocol_fee, revenue[0].total_fee);
+                        }
+                        Err(error) => proptest::prop_assert!(matches!(error, AggregationError::Overflow(_)) && !fits(exact_fee)),
+                    }
+                }
+                Err(error) => {
+                    proptest::prop_assert!(matches!(error, AggregationError::Overflow(_)));
+                    proptest::prop_assert!(!fits(exact_in) || !fits(exact_out));
+                }
+            }
+        }
+    }
+
+    /// Количество смен пула между соседними чанками
+    fn pool_switches(result: &SolverResult) -> usize {
+        result.chunk_routes.windows(2).filter(|pair| pair[0].best_pool_name != pair[1].best_pool_name).count()
+    }
+
+    #[tokio::test]
+    async fn commitment_damps_oscillation_with_bounded_loss() {
+        // Два почти одинаковых пула: жадный алгоритм переключается между ними почти на каждом чанке
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pools = vec![
+            test_pool_at(0x11, 2_000_000_000_000, U256::from(800u64) * weth),
+            test_pool_at(0x12, 2_000_000_000_000, U256::from(801u64) * weth),
+        ];
+        pools[0].name = "A".to_string();
+        pools[1].name = "B".to_string();
+        let ctx = ConfigContext::default();
+        let total = U256::from(400_000_000_000u64);
+        let greedy = find_best_routes(pools.clone(), &ctx, &quiet_config(total, 400)).await.unwrap();
+        assert_eq!(greedy.diagnostics.committed_chunks, 0);
+
+        let committed_config = SolverConfig {
+            commit_threshold_bps: Some(100),
+            commit_switch_bps: 30,
+            ..quiet_config(total, 400)
+        };
+        let committed = find_best_routes(pools, &ctx, &committed_config).await.unwrap();
+        assert!(committed.diagnostics.committed_chunks > 0);
+        assert!(committed.chunk_routes.iter().any(|route| route.committed));
+        assert!(pool_switches(&committed) * 4 < pool_switches(&greedy),
+            "{} vs {}", pool_switches(&committed), pool_switches(&greedy));
+
+        // Каждый закрепленный чанк теряет не больше commit_switch_bps
+        let loss_bps = (math::u256_to_f64(greedy.total_weth_out) - math::u256_to_f64(committed.total_weth_out))
+            / math::u256_to_f64(greedy.total_weth_out) * 10_000.0;
+        assert!((0.0..=30.0).contains(&loss_bps), "{}", loss_bps);
+    }
+
+    #[test]
+    fn validate_rejects_commit_threshold_above_100_percent() {
+        let solver_config = SolverConfig { commit_threshold_bps: Some(10_001), ..quiet_config(U256::from(100u64), 1) };
+        assert_eq!(solver_config.validate(), Err(SolverError::InvalidCommitThreshold(10_001)));
+    }
+
+    #[tokio::test]
+    async fn tiny_chunks_report_fee_rounding_and_can_be_bumped() {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![test_pool(3_000_000_000_000, U256::from(1_200u64) * weth)];
+        let ctx = ConfigContext::default();
+
+        // 10_000 raw / 100 чанков = 100 raw: комиссия 0.3% округляется до нуля
+        let tiny = quiet_config(U256::from(10_000u64), 100);
+        let result = find_best_routes(pools.clone(), &ctx, &tiny).await.unwrap();
+        let [warning] = &result.diagnostics.fee_rounding[..] else {
+            panic!("{:?}", result.diagnostics.fee_rounding);
+        };
+        assert_eq!((warning.fee_bps, warning.chunks), (30, 100));
+        assert_eq!((warning.smallest_chunk, warning.min_chunk), (U256::from(100u64), U256::from(334u64)));
+        assert_eq!(warning.to_string(), format!("{}: комиссия 30 bps округляется до нуля в 100 чанках (от 0.000100 USDC (raw 100)); \
+            учет комиссий занижен, минимальный чанк без округления 0.000334 USDC (raw 334) (--bump-tiny-chunks)", pools[0].name));
+
+        let bumped = SolverConfig { bump_tiny_chunks: true, ..tiny };
+        let result = find_best_routes(pools.clone(), &ctx, &bumped).await.unwrap();
+        assert_eq!(result.chunk_routes.len(), 29);
+        assert!(result.diagnostics.fee_rounding.is_empty());
+        assert!(result.chunk_routes.iter().all(|route| route.amount_in >= U256::from(334u64)));
+
+        // Достаточно крупные чанки флаг не меняет
+        let regular = SolverConfig { bump_tiny_chunks: true, ..quiet_config(U256::from(1_000_000_000u64), 100) };
+        let result = find_best_routes(pools, &ctx, &regular).await.unwrap();
+        assert_eq!(result.chunk_routes.len(), 100);
+        assert!(result.diagnostics.fee_rounding.is_empty());
+    }
+
+    /// Предел 300 bps для пула 2 000 000 USDC / 1000 WETH: из чанков по 10 000 USDC
+    /// укладываются первые три, из чанков по 20 000 USDC - первый и часть второго
+    fn guarded_config(chunk_usdc: u64, num_chunks: u64, remedy: GuardRemedy, fallback: GuardFallback) -> SolverConfig {
+        SolverConfig {
+            chunk_guard: Some(ChunkGuard { max_deviation_bps: 300, remedy, fallback }),
+            ..quiet_config(U256::from(chunk_usdc * 1_000_000 * num_chunks), num_chunks)
+        }
+    }
+
+    fn guard_pool(address_byte: u8, name: &str) -> PoolState {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let mut pool = test_pool_at(address_byte, 2_000_000_000_000, U256::from(1_000u64) * weth);
+        pool.name = name.to_string();
+        pool
+    }
+
+    #[tokio::test]
+    async fn chunk_guard_reroutes_committed_chunk_beyond_bound() {
+        // Закрепление держит все чанки в A, хотя B лучше почти на 1%
+        let pools = vec![guard_pool(0x11, "A"), guard_pool(0x12, "B")];
+        let ctx = ConfigContext::default();
+        let committed = |chunk_guard| SolverConfig {
+            commit_threshold_bps: Some(100),
+            commit_switch_bps: 1_000,
+            chunk_guard,
+            ..guarded_config(10_000, 6, GuardRemedy::Reroute, GuardFallback::Partial)
+        };
+        let unguarded = find_best_routes(pools.clone(), &ctx, &committed(None)).await.unwrap();
+        assert!(unguarded.chunk_routes.iter().all(|route| route.best_pool_name == "A"));
+        assert!(unguarded.chunk_routes[3].spot_deviation_bps.unwrap() < -300.0);
+
+        let guard = ChunkGuard { max_deviation_bps: 300, remedy: GuardRemedy::Reroute, fallback: GuardFallback::Partial };
+        let result = find_best_routes(pools, &ctx, &committed(Some(guard))).await.unwrap();
+        let names: Vec<&str> = result.chunk_routes.iter().map(|route| route.best_pool_name.as_str()).collect();
+        assert_eq!(names, ["A", "A", "A", "B", "B", "B"]);
+        assert_eq!(result.diagnostics.guard_events.len(), 1);
+        assert_eq!(result.diagnostics.guard_events[0].chunk_index, 4);
+        assert_eq!(result.diagnostics.guard_events[0].action, GuardAction::Rerouted { from: "A".to_string(), to: "B".to_string() });
+        assert!(!result.chunk_routes[3].committed);
+        assert_eq!(result.diagnostics.unfilled_in, U256::ZERO);
+        assert!(result.chunk_routes.iter().all(|route| route.spot_deviation_bps.unwrap() >= -300.0));
+    }
+
+    #[tokio::test]
+    async fn chunk_guard_leaves_late_chunks_unfilled_or_aborts() {
+        let ctx = ConfigContext::default();
+        let partial = find_best_routes(vec![guard_pool(0x11, "A")], &ctx, &guarded_config(10_000, 10, GuardRemedy::Reroute, GuardFallback::Partial))
+            .await
+            .unwrap();
+        let filled: Vec<u64> = partial.chunk_routes.iter().filter(|route| route.pool_address.is_some()).map(|route| route.chunk_index).collect();
+        assert_eq!(filled, [1, 2, 3]);
+        let events = &partial.diagnostics.guard_events;
+        assert_eq!(events.iter().map(|event| event.chunk_index).collect::<Vec<_>>(), (4..=10).collect::<Vec<_>>());
+        assert!(events.iter().all(|event| event.action == GuardAction::Unfilled && event.deviation_bps < -300.0));
+        assert_eq!(partial.diagnostics.unfilled_in, U256::from(70_000_000_000u64));
+        assert_eq!(partial.diagnostics.skip_counts[0].reason, SkipReason::PriceGuard);
+        assert_eq!(partial.diagnostics.skip_counts[0].chunks, 7);
+        assert!(partial.chunk_routes[3..].iter().all(|route| route.amount_out.is_zero() && route.spot_deviation_bps.is_none()));
+
+        let error = find_best_routes(vec![guard_pool(0x11, "A")], &ctx, &guarded_config(20_000, 5, GuardRemedy::Shrink, GuardFallback::Abort))
+            .await
+            .unwrap_err();
+        let abort = error.downcast_ref::<ChunkGuardAbort>().unwrap();
+        // Чанк 2 уменьшается до границы, а чанк 3 не исправить
+        assert_eq!((abort.chunk_index, abort.max_deviation_bps), (3, 300));
+        assert!(abort.deviation_bps < -300.0);
+    }
+
+    #[tokio::test]
+    async fn chunk_guard_shrinks_first_chunk_beyond_bound() {
+        let ctx = ConfigContext::default();
+        let result = find_best_routes(vec![guard_pool(0x11, "A")], &ctx, &guarded_config(20_000, 5, GuardRemedy::Shrink, GuardFallback::Partial))
+            .await
+            .unwrap();
+        let chunk = U256::from(20_000_000_000u64);
+        assert_eq!(result.chunk_routes[0].amount_in, chunk);
+        let shrunk = &result.chunk_routes[1];
+        assert!(shrunk.amount_in > U256::ZERO && shrunk.amount_in < chunk, "{}", shrunk.amount_in);
+        assert!(shrunk.spot_deviation_bps.unwrap() >= -300.0);
+        assert_eq!(result.diagnostics.guard_events[0].action, GuardAction::Shrunk {
+            pool: "A".to_string(),
+            amount_in: shrunk.amount_in,
+            unfilled_in: chunk - shrunk.amount_in,
+        });
+        // После уменьшенного чанка пул на границе: остальные чанки не исполняются
+        assert!(result.diagnostics.guard_events[1..].iter().all(|event| event.action == GuardAction::Unfilled));
+        assert_eq!(result.diagnostics.guard_events.len(), 4);
+        assert_eq!(result.diagnostics.unfilled_in, U256::from(80_000_000_000u64) - shrunk.amount_in);
+        let filled_in = Accumulator::sum("вход", result.chunk_routes.iter().filter(|route| !route.amount_out.is_zero()).map(|route| route.amount_in)).unwrap();
+        assert_eq!(filled_in + result.diagnostics.unfilled_in, U256::from(100_000_000_000u64));
+    }
+
+    #[test]
+    fn validate_rejects_chunk_guard_above_100_percent() {
+        let solver_config = SolverConfig {
+            chunk_guard: Some(ChunkGuard { max_deviation_bps: 10_001, remedy: GuardRemedy::Shrink, fallback: GuardFallback::Partial }),
+            ..quiet_config(U256::from(1_000_000u64), 10)
+        };
+        assert_eq!(solver_config.validate(), Err(SolverError::InvalidChunkGuard(10_001)));
+    }
+}
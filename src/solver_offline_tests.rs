@@ -0,0 +1,132 @@
+// src/solver_offline_tests.rs
+//! Тесты солвера без сети: пулы строятся как `PoolState` напрямую, без
+//! провайдера и без вспомогательных пулов других модулей. Проверяют
+//! сохранение суммы, детерминированность, согласие стратегий и то, что
+//! переданные в солвер состояния не меняются.
+
+use crate::config::{ConfigContext, DexId, TokenId};
+use crate::pool::PoolState;
+use crate::solver::{fee_revenue, find_best_routes, SolverConfig, Strategy};
+use alloy::primitives::{Address, U256};
+
+fn weth(amount: u64) -> U256 {
+    U256::from(amount) * U256::from(10u64).pow(U256::from(18u64))
+}
+
+fn usdc(amount: u64) -> U256 {
+    U256::from(amount) * U256::from(1_000_000u64)
+}
+
+/// Пул USDC/WETH с резервами в decimal
+fn state(address_byte: u8, dex: DexId, reserve_usdc: u64, reserve_weth: u64) -> PoolState {
+    let mut pool = PoolState::new(
+        Address::repeat_byte(address_byte),
+        TokenId::USDC,
+        TokenId::WETH,
+        dex,
+        format!("{} USDC/WETH", dex),
+    );
+    if pool.token0 == TokenId::USDC {
+        (pool.reserve_token0, pool.reserve_token1) = (usdc(reserve_usdc), weth(reserve_weth));
+    } else {
+        (pool.reserve_token0, pool.reserve_token1) = (weth(reserve_weth), usdc(reserve_usdc));
+    }
+    pool.invalidate_quote_cache();
+    pool
+}
+
+fn market() -> Vec<PoolState> {
+    vec![
+        state(0x11, DexId::QUICKSWAP, 3_000_000, 1_200),
+        state(0x12, DexId::SUSHISWAP, 1_000_000, 402),
+    ]
+}
+
+fn config(total_usdc: u64, strategy: Strategy) -> SolverConfig {
+    SolverConfig { total_amount_in: usdc(total_usdc), num_chunks: 50, verbose: false, strategy, ..Default::default() }
+}
+
+#[tokio::test]
+async fn split_conserves_input_and_beats_single_pool() {
+    let pools = market();
+    let solver_config = config(200_000, Strategy::Greedy);
+    let result = find_best_routes(pools.clone(), &ConfigContext::default(), &solver_config).await.unwrap();
+
+    let total_in: U256 = result.chunk_routes.iter().map(|route| route.amount_in).sum();
+    assert_eq!(total_in, solver_config.total_amount_in);
+    let total_out: U256 = result.chunk_routes.iter().map(|route| route.amount_out).sum();
+    assert_eq!(total_out, result.total_weth_out);
+
+    for pool in &pools {
+        let single = pool.get_amount_out(solver_config.total_amount_in, pool.token0 == TokenId::USDC);
+        assert!(result.total_weth_out > single, "{}: {} <= {}", pool.name, result.total_weth_out, single);
+    }
+    // Оба пула участвуют в сплите
+    for pool in &pools {
+        assert!(result.chunk_routes.iter().any(|route| route.pool_address == Some(pool.pool_address)));
+    }
+}
+
+#[tokio::test]
+async fn split_beats_token_addressed_single_pool_quote() {
+    let pools = market();
+    let solver_config = config(200_000, Strategy::Greedy);
+    let result = find_best_routes(pools.clone(), &ConfigContext::default(), &solver_config).await.unwrap();
+
+    for pool in &pools {
+        let single = pool.quote_by_token(TokenId::USDC, solver_config.total_amount_in).unwrap();
+        assert_eq!(single, pool.get_amount_out(solver_config.total_amount_in, pool.token0 == TokenId::USDC));
+        assert!(result.total_weth_out > single, "{}: {} <= {}", pool.name, result.total_weth_out, single);
+    }
+}
+
+#[tokio::test]
+async fn caller_states_are_untouched_and_results_repeat() {
+    let pools = market();
+    let before: Vec<_> = pools.iter().map(|pool| pool.snapshot()).collect();
+    let solver_config = config(500_000, Strategy::Greedy);
+
+    let first = find_best_routes(pools.clone(), &ConfigContext::default(), &solver_config).await.unwrap();
+    let second = find_best_routes(pools.clone(), &ConfigContext::default(), &solver_config).await.unwrap();
+
+    let after: Vec<_> = pools.iter().map(|pool| pool.snapshot()).collect();
+    assert_eq!(after, before);
+    assert_eq!(first.total_weth_out, second.total_weth_out);
+    let routes = |result: &crate::solver::SolverResult| -> Vec<(Option<Address>, U256, U256)> {
+        result.chunk_routes.iter().map(|route| (route.pool_address, route.amount_in, route.amount_out)).collect()
+    };
+    assert_eq!(routes(&first), routes(&second));
+}
+
+#[tokio::test]
+async fn strategies_agree_within_a_few_basis_points() {
+    let ctx = ConfigContext::default();
+    let greedy = find_best_routes(market(), &ctx, &config(300_000, Strategy::Greedy)).await.unwrap();
+    for strategy in [Strategy::TwoPoolAnalytic, Strategy::MarginalEqualization] {
+        let result = find_best_routes(market(), &ctx, &config(300_000, strategy)).await.unwrap();
+        let diff = result.total_weth_out.abs_diff(greedy.total_weth_out);
+        assert!(diff * U256::from(10_000u64) <= U256::from(5u64) * greedy.total_weth_out, "{:?}: {} vs {}", strategy, result.total_weth_out, greedy.total_weth_out);
+        // Аналитические стратегии не хуже жадной с конечным числом чанков
+        assert!(result.total_weth_out + U256::from(1u64) >= greedy.total_weth_out, "{:?}", strategy);
+    }
+}
+
+#[tokio::test]
+async fn fee_revenue_matches_pool_fees_per_route() {
+    let pools = market();
+    let ctx = ConfigContext::default();
+    let result = find_best_routes(pools.clone(), &ctx, &config(100_000, Strategy::Greedy)).await.unwrap();
+
+    let revenue = fee_revenue(&result.chunk_routes, &pools, &ctx).unwrap();
+    let total_fee: U256 = revenue.iter().map(|revenue| revenue.total_fee).sum();
+    let expected: U256 = result
+        .chunk_routes
+        .iter()
+        .map(|route| {
+            let pool = pools.iter().find(|pool| Some(pool.pool_address) == route.pool_address).unwrap();
+            pool.swap_fee_amount(route.amount_in)
+        })
+        .sum();
+    assert_eq!(total_fee, expected);
+    assert!(revenue.iter().all(|revenue| revenue.lp_fee + revenue.protocol_fee == revenue.total_fee));
+}
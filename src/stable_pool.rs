@@ -0,0 +1,160 @@
+// src/stable_pool.rs
+use alloy::primitives::{Address, U256};
+use eyre::Result;
+use std::sync::Arc;
+use crate::config::{DexId, TokenId};
+use crate::math::stableswap::{get_dy, StableSwapError};
+use crate::provider::{get_stable_pool_state, RpcProvider};
+
+/// Точность, к которой Curve приводит балансы перед расчетом (18 decimals)
+const CURVE_PRECISION_DECIMALS: u8 = 18;
+
+/// Пул StableSwap (Curve) для коррелированных активов
+/// 
+/// Балансы хранятся в raw units токенов и приводятся к 18 decimals
+/// только на время расчета, как `_xp()` в контрактах Curve.
+#[derive(Debug, Clone)]
+pub struct StablePool {
+    pub pool_address: Address,
+    pub provider: Arc<RpcProvider>,
+    pub dex: DexId,
+    pub coins: Vec<TokenId>,
+    pub balances: Vec<U256>,  // В raw units каждого токена
+    pub amp: U256,            // Коэффициент усиления A
+    pub fee: U256,            // Комиссия в единицах 10^10 (4_000_000 = 0.04%)
+    pub name: String,
+}
+
+impl StablePool {
+    /// Создает пул с известным состоянием
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        pool_address: Address,
+        coins: Vec<TokenId>,
+        balances: Vec<U256>,
+        amp: U256,
+        fee: U256,
+        dex: DexId,
+        provider: Arc<RpcProvider>,
+        name: String,
+    ) -> Self {
+        StablePool { pool_address, provider, dex, coins, balances, amp, fee, name }
+    }
+
+    /// Создает пул и загружает балансы, A и комиссию из блокчейна
+    pub async fn with_state(
+        pool_address: Address,
+        coins: Vec<TokenId>,
+        dex: DexId,
+        provider: Arc<RpcProvider>,
+        name: String,
+    ) -> Result<Self> {
+        let mut pool = StablePool::new(pool_address, coins, Vec::new(), U256::ZERO, U256::ZERO, dex, provider, name);
+        pool.refresh_state().await?;
+        Ok(pool)
+    }
+
+    /// Индекс токена в пуле
+    pub fn coin_index(&self, token: TokenId) -> Option<usize> {
+        self.coins.iter().position(|coin| *coin == token)
+    }
+
+    /// Множитель для приведения raw units токена к 18 decimals
+    fn precision_multiplier(&self, index: usize) -> U256 {
+        let decimals = self.coins[index].decimals();
+        U256::from(10u64).pow(U256::from(CURVE_PRECISION_DECIMALS.saturating_sub(decimals)))
+    }
+
+    /// Рассчитывает выход `token_out` для `amount_in` токена `token_in` (raw units)
+    pub fn get_amount_out(&self, amount_in: U256, token_in: TokenId, token_out: TokenId) -> Result<U256, StableSwapError> {
+        let (Some(i), Some(j)) = (self.coin_index(token_in), self.coin_index(token_out)) else {
+            return Err(StableSwapError::InvalidIndex);
+        };
+
+        let xp: Vec<U256> = self
+            .balances
+            .iter()
+            .enumerate()
+            .map(|(index, balance)| balance.checked_mul(self.precision_multiplier(index)).ok_or(StableSwapError::Overflow))
+            .collect::<Result<_, _>>()?;
+        let dx = amount_in.checked_mul(self.precision_multiplier(i)).ok_or(StableSwapError::Overflow)?;
+
+        let dy = get_dy(i, j, dx, &xp, self.amp, self.fee)?;
+        Ok(dy / self.precision_multiplier(j))
+    }
+
+    /// Симулирует свап и обновляет балансы без обращения к блокчейну
+    /// 
+    /// Комиссия целиком остается в пуле (admin fee не учитывается)
+    pub fn mock_swap(&mut self, amount_in: U256, token_in: TokenId, token_out: TokenId) -> Result<U256, StableSwapError> {
+        let amount_out = self.get_amount_out(amount_in, token_in, token_out)?;
+        let (Some(i), Some(j)) = (self.coin_index(token_in), self.coin_index(token_out)) else {
+            return Err(StableSwapError::InvalidIndex);
+        };
+        self.balances[i] += amount_in;
+        self.balances[j] -= amount_out;
+        Ok(amount_out)
+    }
+
+    /// Обновляет балансы, A и комиссию пула из блокчейна
+    pub async fn refresh_state(&mut self) -> Result<()> {
+        let (balances, amp, fee) = get_stable_pool_state(
+            self.provider.clone(),
+            self.pool_address,
+            self.coins.len(),
+        ).await?;
+
+        self.balances = balances;
+        self.amp = amp;
+        self.fee = fee;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::test_provider;
+
+    fn usdc_pool(balance_usdc: u64, balance_usdc_e: u64) -> StablePool {
+        StablePool::new(
+            Address::repeat_byte(0x33),
+            vec![TokenId::USDC, TokenId::USDC_E],
+            vec![U256::from(balance_usdc), U256::from(balance_usdc_e)],
+            U256::from(200u64),
+            U256::from(4_000_000u64),
+            DexId("Test Curve"),
+            test_provider(),
+            "Test Curve USDC/USDC.e".to_string(),
+        )
+    }
+
+    #[test]
+    fn quotes_near_parity_for_balanced_stable_pool() {
+        let pool = usdc_pool(10_000_000_000_000, 10_000_000_000_000);
+        let amount_in = U256::from(100_000_000_000u64); // 100k USDC
+
+        let out = pool.get_amount_out(amount_in, TokenId::USDC, TokenId::USDC_E).unwrap();
+        // Комиссия 0.04% и небольшое проскальзывание, но намного лучше constant product
+        assert!(out < amount_in);
+        assert!(out > U256::from(99_900_000_000u64));
+        assert!(out > crate::math::get_amount_out_with_fee(amount_in, pool.balances[0], pool.balances[1], 4));
+
+        assert_eq!(pool.get_amount_out(amount_in, TokenId::USDC, TokenId::WETH), Err(StableSwapError::InvalidIndex));
+    }
+
+    #[test]
+    fn mock_swap_updates_balances() {
+        let mut pool = usdc_pool(10_000_000_000_000, 10_000_000_000_000);
+        let amount_in = U256::from(1_000_000_000_000u64);
+
+        let first = pool.mock_swap(amount_in, TokenId::USDC, TokenId::USDC_E).unwrap();
+        assert_eq!(pool.balances[0], U256::from(11_000_000_000_000u64));
+        assert_eq!(pool.balances[1], U256::from(10_000_000_000_000u64) - first);
+
+        // Пул стал несбалансированным, следующий такой же свап дает меньше
+        let second = pool.get_amount_out(amount_in, TokenId::USDC, TokenId::USDC_E).unwrap();
+        assert!(second < first);
+    }
+}
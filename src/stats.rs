@@ -0,0 +1,243 @@
+// src/stats.rs
+//! Накопительная статистика использования за время жизни процесса (repl)
+//!
+//! Считает котировки и котированный объем по парам, объем по пулам
+//! маршрутов и обновления резервов. Счетчики переживают перезапуск: сессия
+//! сохраняет их в файл (`--stats-file`) не чаще раза в
+//! `STATS_SAVE_INTERVAL_SECS` и при выходе, формат - JSON, как у кэша
+//! discovery. Суммы хранятся в raw units десятичными строками, без потери
+//! точности, вместе с decimals своего токена.
+use crate::config::{format_units, TokenId};
+use crate::regress::decimal;
+use crate::solver::{pool_usage, SolverResult};
+use alloy::primitives::{Address, U256};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Сколько пулов показывать в `stats`
+pub const TOP_POOLS: usize = 5;
+
+/// Котировки одной пары
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairStats {
+    pub token_in: Address, // Адреса: TokenId не сериализуется
+    pub token_out: Address,
+    pub token_in_decimals: u8,
+    pub token_out_decimals: u8,
+    pub quotes: u64,
+    #[serde(with = "decimal")]
+    pub volume_in: U256,
+    #[serde(with = "decimal")]
+    pub volume_out: U256,
+}
+
+/// Объем одного входного токена, направленный маршрутами через один пул
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolVolume {
+    pub pool_address: Address,
+    pub pool_name: String,
+    pub token_in: Address,
+    pub token_in_decimals: u8,
+    pub chunks: u64,
+    #[serde(with = "decimal")]
+    pub volume_in: U256,
+}
+
+/// Счетчики с момента `since` (запуск или последний сброс)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageStats {
+    pub since: u64, // Unix time начала учета
+    pub pairs: Vec<PairStats>,
+    pub pools: Vec<PoolVolume>,
+    pub refreshes: u64,
+    pub refresh_failures: u64, // Пулы, резервы которых обновить не удалось
+}
+
+impl UsageStats {
+    pub fn new() -> Self {
+        UsageStats { since: unix_now(), ..Default::default() }
+    }
+
+    /// Загружает счетчики из файла; отсутствующий файл дает новые счетчики
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(UsageStats::new());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Сохраняет счетчики в файл
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Обнуляет счетчики и начинает учет заново
+    pub fn reset(&mut self) {
+        *self = UsageStats::new();
+    }
+
+    /// Учитывает выданную котировку; токены передаются вместе с их decimals
+    pub fn record_quote(&mut self, (token_in, token_in_decimals): (TokenId, u8), (token_out, token_out_decimals): (TokenId, u8),
+        amount_in: U256, result: &SolverResult) {
+        let (token_in, token_out) = (token_in.address(), token_out.address());
+        let pair = match self.pairs.iter_mut().find(|pair| pair.token_in == token_in && pair.token_out == token_out) {
+            Some(pair) => pair,
+            None => {
+                self.pairs.push(PairStats {
+                    token_in,
+                    token_out,
+                    token_in_decimals,
+                    token_out_decimals,
+                    quotes: 0,
+                    volume_in: U256::ZERO,
+                    volume_out: U256::ZERO,
+                });
+                self.pairs.last_mut().expect("пара только что добавлена")
+            }
+        };
+        pair.quotes += 1;
+        pair.volume_in = pair.volume_in.saturating_add(amount_in);
+        pair.volume_out = pair.volume_out.saturating_add(result.total_weth_out);
+
+        for usage in pool_usage(&result.chunk_routes) {
+            match self.pools.iter_mut().find(|pool| pool.pool_address == usage.pool_address && pool.token_in == token_in) {
+                Some(pool) => {
+                    pool.chunks += usage.chunks;
+                    pool.volume_in = pool.volume_in.saturating_add(usage.amount_in);
+                }
+                None => self.pools.push(PoolVolume {
+                    pool_address: usage.pool_address,
+                    pool_name: usage.pool_name,
+                    token_in,
+                    token_in_decimals,
+                    chunks: usage.chunks,
+                    volume_in: usage.amount_in,
+                }),
+            }
+        }
+    }
+
+    /// Учитывает обновление резервов, на котором `failures` пулов не обновились
+    pub fn record_refresh(&mut self, failures: usize) {
+        self.refreshes += 1;
+        self.refresh_failures += failures as u64;
+    }
+
+    /// Пулы по убыванию направленного через них объема (приведенного к 18 decimals)
+    pub fn top_pools(&self, limit: usize) -> Vec<&PoolVolume> {
+        let mut pools: Vec<&PoolVolume> = self.pools.iter().collect();
+        pools.sort_by_cached_key(|pool| (std::cmp::Reverse(volume_18(pool)), pool.pool_address));
+        pools.truncate(limit);
+        pools
+    }
+
+    /// Отчет для команды `stats`
+    pub fn format(&self) -> String {
+        let mut out = format!("Статистика с {} (unix), обновлений резервов: {} (сбоев пулов: {})",
+            self.since, self.refreshes, self.refresh_failures);
+        if self.pairs.is_empty() {
+            out.push_str("\nКотировок не было");
+        }
+        for pair in &self.pairs {
+            let _ = write!(out, "\n  {} -> {}: котировок {}, объем {} -> {}",
+                TokenId(pair.token_in), TokenId(pair.token_out), pair.quotes,
+                format_units(pair.volume_in, pair.token_in_decimals),
+                format_units(pair.volume_out, pair.token_out_decimals));
+        }
+        let top = self.top_pools(TOP_POOLS);
+        if !top.is_empty() {
+            out.push_str("\nПулы по объему:");
+        }
+        for pool in top {
+            let _ = write!(out, "\n  {} ({:?}): {} {}, чанков {}",
+                pool.pool_name, pool.pool_address, format_units(pool.volume_in, pool.token_in_decimals), TokenId(pool.token_in), pool.chunks);
+        }
+        out
+    }
+}
+
+/// Объем пула в 18 decimals: объемы токенов с разными decimals сравнимы
+fn volume_18(pool: &PoolVolume) -> U256 {
+    let ten = U256::from(10u64);
+    match pool.token_in_decimals {
+        decimals @ 0..=18 => pool.volume_in.saturating_mul(ten.pow(U256::from(18 - decimals))),
+        decimals => pool.volume_in / ten.pow(U256::from(decimals - 18)),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigContext;
+    use crate::pool::test_pool;
+    use crate::solver::{find_best_routes, SolverConfig};
+
+    async fn quote(amount_in: U256) -> SolverResult {
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let pools = vec![
+            test_pool(0x11, TokenId::USDC, TokenId::WETH, U256::from(3_000_000_000_000u64), U256::from(1_200u64) * weth),
+            test_pool(0x22, TokenId::USDC, TokenId::WETH, U256::from(800_000_000_000u64), U256::from(330u64) * weth),
+        ];
+        let config = SolverConfig { total_amount_in: amount_in, num_chunks: 20, verbose: false, ..SolverConfig::default() };
+        find_best_routes(pools, &ConfigContext::default(), &config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn counters_accumulate_and_survive_a_save_load_round_trip() {
+        let mut stats = UsageStats::new();
+        let mut expected_out = U256::ZERO;
+        for amount in [1_000_000_000u64, 50_000_000_000, 200_000_000_000] {
+            let result = quote(U256::from(amount)).await;
+            expected_out += result.total_weth_out;
+            stats.record_quote((TokenId::USDC, 6), (TokenId::WETH, 18), U256::from(amount), &result);
+        }
+        stats.record_refresh(0);
+        stats.record_refresh(2);
+
+        let [pair] = &stats.pairs[..] else { panic!("{:?}", stats.pairs) };
+        assert_eq!(pair.quotes, 3);
+        assert_eq!(pair.volume_in, U256::from(251_000_000_000u64));
+        assert_eq!(pair.volume_out, expected_out);
+        assert_eq!((stats.refreshes, stats.refresh_failures), (2, 2));
+        // Объем пулов складывается во весь котированный вход; глубокий пул первый
+        assert_eq!(stats.pools.iter().map(|pool| pool.volume_in).sum::<U256>(), pair.volume_in);
+        assert_eq!(stats.top_pools(1)[0].pool_address, Address::repeat_byte(0x11));
+        assert!(stats.format().contains("USDC -> WETH: котировок 3, объем 251000.000000 -> "));
+
+        let path = std::env::temp_dir().join(format!("swap_aggregator_stats_{}.json", std::process::id()));
+        stats.save(&path).unwrap();
+        let restored = UsageStats::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored, stats);
+
+        let mut reset = restored;
+        reset.reset();
+        assert!(reset.pairs.is_empty() && reset.pools.is_empty() && reset.refreshes == 0);
+        assert!(reset.since >= stats.since);
+        assert_eq!(UsageStats::load(&path).unwrap().refreshes, 0);
+    }
+
+    #[tokio::test]
+    async fn pool_volume_is_kept_per_input_token_with_its_decimals() {
+        let mut stats = UsageStats::new();
+        let result = quote(U256::from(1_000_000_000u64)).await;
+        let wide = TokenId(Address::repeat_byte(0xdd));
+        stats.record_quote((TokenId::USDC, 6), (TokenId::WETH, 18), U256::from(1_000_000_000u64), &result);
+        // Тот же raw объем в токене с 18 decimals - в 10^12 раз меньше
+        stats.record_quote((wide, 18), (TokenId::WETH, 18), U256::from(1_000_000_000u64), &result);
+
+        let pools = pool_usage(&result.chunk_routes).len();
+        assert_eq!(stats.pools.len(), 2 * pools);
+        assert!(stats.top_pools(pools).iter().all(|pool| pool.token_in == TokenId::USDC.address()));
+        assert!(stats.format().contains("1000.000000 USDC, чанков "), "{}", stats.format());
+        assert!(stats.format().contains(&format!("{} -> WETH: котировок 1, объем 0.000000001000000000 -> ", wide)), "{}", stats.format());
+    }
+}
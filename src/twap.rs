@@ -0,0 +1,151 @@
+// src/twap.rs
+//! Средневзвешенная по времени цена (TWAP) из накопительных цен Uniswap V2
+//!
+//! Пара хранит `price0CumulativeLast` и `price1CumulativeLast` - сумму цен
+//! в формате UQ112x112, умноженных на секунды, - и обновляет их только при
+//! изменении резервов. Текущее значение счетчика получается экстраполяцией
+//! от `blockTimestampLast` по текущим резервам (как `currentCumulativePrices`
+//! в UniswapV2OracleLibrary). Счетчики и время переполняются по модулю 2^256
+//! и 2^32, поэтому разности считаются с переполнением (wrapping).
+use alloy::primitives::U256;
+use crate::math::u256_to_f64;
+
+/// Количество дробных бит в формате UQ112x112
+const RESOLUTION: usize = 112;
+
+/// Наблюдение накопительных цен пары в момент `timestamp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceObservation {
+    pub price0_cumulative: U256,
+    pub price1_cumulative: U256,
+    /// Время блока по модулю 2^32, как в контракте пары
+    pub timestamp: u32,
+}
+
+impl PriceObservation {
+    /// Наблюдение на момент `now` по состоянию пары
+    ///
+    /// Если с `block_timestamp_last` прошло время, к счетчикам добавляется
+    /// текущая цена, умноженная на прошедшие секунды.
+    ///
+    /// # Arguments
+    /// * `price0_cumulative_last`, `price1_cumulative_last` - Счетчики из контракта
+    /// * `reserve0`, `reserve1` - Текущие резервы пары
+    /// * `block_timestamp_last` - Время последнего обновления резервов
+    /// * `now` - Время текущего блока (по модулю 2^32)
+    pub fn current(
+        price0_cumulative_last: U256,
+        price1_cumulative_last: U256,
+        reserve0: U256,
+        reserve1: U256,
+        block_timestamp_last: u32,
+        now: u32,
+    ) -> Self {
+        let mut observation = PriceObservation {
+            price0_cumulative: price0_cumulative_last,
+            price1_cumulative: price1_cumulative_last,
+            timestamp: now,
+        };
+        let elapsed = U256::from(now.wrapping_sub(block_timestamp_last));
+        if elapsed.is_zero() || reserve0.is_zero() || reserve1.is_zero() {
+            return observation;
+        }
+        observation.price0_cumulative = observation
+            .price0_cumulative
+            .wrapping_add(encode_price(reserve0, reserve1).wrapping_mul(elapsed));
+        observation.price1_cumulative = observation
+            .price1_cumulative
+            .wrapping_add(encode_price(reserve1, reserve0).wrapping_mul(elapsed));
+        observation
+    }
+}
+
+/// Цена `reserve_out / reserve_in` в формате UQ112x112 (как `UQ112x112.encode(...).uqdiv(...)`)
+pub fn encode_price(reserve_in: U256, reserve_out: U256) -> U256 {
+    (reserve_out << RESOLUTION) / reserve_in
+}
+
+/// Средняя цена между двумя наблюдениями в формате UQ112x112
+///
+/// # Returns
+/// `(price0_average, price1_average)` или `None`, если наблюдения в одну секунду
+pub fn average_price_x112(start: &PriceObservation, end: &PriceObservation) -> Option<(U256, U256)> {
+    let elapsed = U256::from(end.timestamp.wrapping_sub(start.timestamp));
+    if elapsed.is_zero() {
+        return None;
+    }
+    Some((
+        end.price0_cumulative.wrapping_sub(start.price0_cumulative) / elapsed,
+        end.price1_cumulative.wrapping_sub(start.price1_cumulative) / elapsed,
+    ))
+}
+
+/// TWAP с учетом decimals: token1 за token0 и token0 за token1
+///
+/// # Returns
+/// `(price0, price1)` или `None`, если наблюдения в одну секунду
+pub fn twap(start: &PriceObservation, end: &PriceObservation, decimals0: u8, decimals1: u8) -> Option<(f64, f64)> {
+    let (price0_x112, price1_x112) = average_price_x112(start, end)?;
+    let scale = 2f64.powi(RESOLUTION as i32);
+    let shift = decimals0 as i32 - decimals1 as i32;
+    Some((
+        u256_to_f64(price0_x112) / scale * 10f64.powi(shift),
+        u256_to_f64(price1_x112) / scale * 10f64.powi(-shift),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weth(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10u64).pow(U256::from(18u64))
+    }
+
+    #[test]
+    fn constant_price_twap_equals_spot() {
+        // token0 = USDC (6), token1 = WETH (18): 2500 USDC за WETH
+        let (reserve0, reserve1) = (U256::from(2_500_000_000_000u64), weth(1_000));
+        let start = PriceObservation::current(U256::ZERO, U256::ZERO, reserve0, reserve1, 1_000, 1_000);
+        let end = PriceObservation::current(U256::ZERO, U256::ZERO, reserve0, reserve1, 1_000, 1_300);
+
+        let (price0, price1) = twap(&start, &end, 6, 18).unwrap();
+        assert!((price0 - 1.0 / 2500.0).abs() < 1e-12);
+        assert!((price1 - 2500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn twap_weights_prices_by_time() {
+        // 100 секунд по цене 2000 и 300 секунд по цене 3000 -> 2750
+        let usdc = |amount: u64| U256::from(amount) * U256::from(1_000_000u64);
+        let start = PriceObservation::current(U256::ZERO, U256::ZERO, usdc(2_000_000), weth(1_000), 0, 0);
+        let middle = PriceObservation::current(start.price0_cumulative, start.price1_cumulative,
+            usdc(2_000_000), weth(1_000), 0, 100);
+        let end = PriceObservation::current(middle.price0_cumulative, middle.price1_cumulative,
+            usdc(3_000_000), weth(1_000), 100, 400);
+
+        let (_, price1) = twap(&start, &end, 6, 18).unwrap();
+        assert!((price1 - 2750.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn counters_and_timestamps_wrap() {
+        let (reserve0, reserve1) = (U256::from(2_500_000_000_000u64), weth(1_000));
+        // Счетчик в 10 единицах от 2^256 и время в 50 секундах от 2^32
+        let near_max = U256::MAX - U256::from(9u64);
+        let start_time = u32::MAX - 49;
+        let start = PriceObservation::current(near_max, near_max, reserve0, reserve1, start_time, start_time);
+        let end = PriceObservation::current(near_max, near_max, reserve0, reserve1, start_time, 250);
+        assert!(end.price1_cumulative < start.price1_cumulative);
+
+        let (_, price1) = twap(&start, &end, 6, 18).unwrap();
+        assert!((price1 - 2500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn same_second_has_no_average() {
+        let observation = PriceObservation { price0_cumulative: U256::from(1u64), price1_cumulative: U256::from(1u64), timestamp: 7 };
+        assert_eq!(average_price_x112(&observation, &observation), None);
+        assert_eq!(twap(&observation, &observation, 6, 18), None);
+    }
+}
@@ -0,0 +1,528 @@
+// src/v3_pool.rs
+//! Пул Uniswap V3 с котировками через QuoterV2
+//!
+//! Пересечение тиков локально не моделируется (см. `math::v3`), поэтому
+//! выход берется у QuoterV2 по сетке сумм от нуля до суммы обмена и
+//! линейно интерполируется между узлами. Кривая выхода вогнута, так что
+//! интерполяция занижает выход и оценка остается консервативной.
+//! Котировки кэшируются по сумме на время запуска; для пулов, выбранных
+//! солвером, сетка уточняется (`requote_allocation`), только если
+//! распределение отошло от ближайшего узла дальше порога.
+use crate::amm::{AmmPool, SimulationFidelity};
+use crate::log;
+use crate::config::{ConfigContext, DexId, DisplayAmount, TokenId, V3_QUOTE_STEPS, V3_REQUOTE_BPS};
+use crate::math::{self, u256_to_f64};
+use crate::math::accumulator::Accumulator;
+use crate::pool::{PoolError, PoolState};
+use crate::provider::{quote_v3_exact_input, RpcProvider};
+use crate::solver::{find_best_routes, SolverConfig, SolverResult};
+use alloy::primitives::{Address, U256};
+use eyre::Result;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Знаменатель комиссии Uniswap V3 (fee = 500 означает 0.05%)
+const FEE_DENOMINATOR: u32 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct V3Pool {
+    pub pool_address: Address,
+    pub token0: TokenId,
+    pub token1: TokenId,
+    pub token0_decimals: u8,
+    pub token1_decimals: u8,
+    pub fee: u32,                 // В единицах 1e-6 (500 = 0.05%)
+    pub name: String,
+    pub sqrt_price_x96: U256,     // Из slot0 на момент discovery
+    pub liquidity: u128,          // Ликвидность текущего диапазона
+    pub provider: Arc<RpcProvider>,
+    pub quoter: Address,
+    quote_token_in: Option<TokenId>, // Направление, для которого загружены котировки
+    quotes: BTreeMap<U256, U256>,    // Сумма входа -> выход QuoterV2 (raw units)
+    consumed_in: U256,               // Вход, уже распределенный в пул во время решения
+    haircut_bps: u32,
+    /// Количество вызовов QuoterV2 за время жизни пула
+    pub quoter_calls: usize,
+}
+
+impl V3Pool {
+    /// Создает пул без котировок; токены упорядочиваются как в контракте
+    pub fn new(
+        pool_address: Address,
+        token_a: TokenId,
+        token_b: TokenId,
+        fee: u32,
+        provider: Arc<RpcProvider>,
+        quoter: Address,
+        name: String,
+    ) -> Self {
+        let (token0, token1) = if token_a < token_b { (token_a, token_b) } else { (token_b, token_a) };
+        V3Pool {
+            pool_address,
+            token0,
+            token1,
+            token0_decimals: token0.decimals(),
+            token1_decimals: token1.decimals(),
+            fee,
+            name,
+            sqrt_price_x96: U256::ZERO,
+            liquidity: 0,
+            provider,
+            quoter,
+            quote_token_in: None,
+            quotes: BTreeMap::new(),
+            consumed_in: U256::ZERO,
+            haircut_bps: 0,
+            quoter_calls: 0,
+        }
+    }
+
+    /// Уровень комиссии для вывода: "0.05%"
+    pub fn fee_label(&self) -> String {
+        format!("{}%", self.fee as f64 / 10_000.0)
+    }
+
+    /// Равномерная сетка сумм `total / steps, 2 * total / steps, ..., total`
+    pub fn quote_grid(total: U256, steps: u64) -> Vec<U256> {
+        let steps = steps.max(1);
+        let mut grid: Vec<U256> = (1..=steps)
+            .map(|step| total * U256::from(step) / U256::from(steps))
+            .filter(|amount| !amount.is_zero())
+            .collect();
+        grid.dedup();
+        grid
+    }
+
+    /// Запрашивает у QuoterV2 выход для сумм, которых еще нет в кэше
+    ///
+    /// Котировки загружаются для одного направления: смена `token_in`
+    /// сбрасывает кэш. Если QuoterV2 откатывается (не хватает ликвидности),
+    /// большие суммы не запрашиваются - квоты выше последнего узла нет.
+    ///
+    /// # Returns
+    /// Количество новых вызовов QuoterV2
+    pub async fn load_quotes(&mut self, token_in: TokenId, amounts: &[U256]) -> Result<usize> {
+        if self.quote_token_in != Some(token_in) {
+            self.quotes.clear();
+            self.quote_token_in = Some(token_in);
+        }
+        let token_out = self.other_token(token_in).ok_or_else(|| eyre::eyre!("{} не входит в пул {}", token_in, self.name))?;
+
+        let mut amounts = amounts.to_vec();
+        amounts.sort();
+        let mut calls = 0;
+        for amount in amounts {
+            if amount.is_zero() || self.quotes.contains_key(&amount) {
+                continue;
+            }
+            calls += 1;
+            self.quoter_calls += 1;
+            match quote_v3_exact_input(self.provider.clone(), self.quoter, token_in, token_out, self.fee, amount).await {
+                Ok(amount_out) => {
+                    self.quotes.insert(amount, amount_out);
+                }
+                Err(e) => {
+                    log!("  {}: QuoterV2 не котирует {}: {}", self.name, DisplayAmount::new(amount, token_in), e);
+                    break;
+                }
+            }
+        }
+        Ok(calls)
+    }
+
+    /// Уточняет котировку для итогового распределения пула
+    ///
+    /// Запрос к QuoterV2 делается, только если `allocated` отстоит от
+    /// ближайшего узла сетки больше чем на `threshold_bps` от самой суммы.
+    ///
+    /// # Returns
+    /// true, если добавлена новая котировка
+    pub async fn requote_allocation(&mut self, allocated: U256, threshold_bps: u32) -> Result<bool> {
+        let Some(token_in) = self.quote_token_in else {
+            return Ok(false);
+        };
+        if allocated.is_zero() {
+            return Ok(false);
+        }
+        let distance = self
+            .quotes
+            .keys()
+            .map(|&point| if point > allocated { point - allocated } else { allocated - point })
+            .min()
+            .unwrap_or(allocated);
+        if distance * U256::from(math::BPS_DENOMINATOR) <= allocated * U256::from(threshold_bps) {
+            return Ok(false);
+        }
+        Ok(self.load_quotes(token_in, &[allocated]).await? > 0)
+    }
+
+    /// Выход за суммарный вход `amount_in` по сетке котировок
+    ///
+    /// Между узлами (и между нулем и первым узлом) выход интерполируется
+    /// линейно; выше последнего узла котировки нет.
+    fn cumulative_out(&self, amount_in: U256) -> Option<U256> {
+        if amount_in.is_zero() {
+            return Some(U256::ZERO);
+        }
+        let (&upper_in, &upper_out) = self.quotes.range(amount_in..).next()?;
+        let (lower_in, lower_out) = self
+            .quotes
+            .range(..amount_in)
+            .next_back()
+            .map_or((U256::ZERO, U256::ZERO), |(&point, &out)| (point, out));
+        let amount_out = if upper_in == amount_in {
+            upper_out
+        } else {
+            lower_out + math::mul_div(upper_out.saturating_sub(lower_out), amount_in - lower_in, upper_in - lower_in)?
+        };
+        Some(amount_out - amount_out * U256::from(self.haircut_bps) / U256::from(math::BPS_DENOMINATOR))
+    }
+
+    /// Виртуальные резервы (reserve0, reserve1) текущего диапазона: L / sqrtP и L * sqrtP
+    fn virtual_reserves(&self) -> (U256, U256) {
+        if self.sqrt_price_x96.is_zero() {
+            return (U256::ZERO, U256::ZERO);
+        }
+        let liquidity = U256::from(self.liquidity);
+        let q96 = math::v3::q96();
+        (
+            math::mul_div(liquidity, q96, self.sqrt_price_x96).unwrap_or(U256::ZERO),
+            math::mul_div(liquidity, self.sqrt_price_x96, q96).unwrap_or(U256::ZERO),
+        )
+    }
+}
+
+/// Общий список пулов для солвера: constant product пулы и пулы V3
+pub fn combined_pools(pools: &[PoolState], v3_pools: &[V3Pool]) -> Vec<Box<dyn AmmPool>> {
+    pools
+        .iter()
+        .map(|pool| pool.clone_box())
+        .chain(v3_pools.iter().map(|pool| pool.clone_box()))
+        .collect()
+}
+
+/// Решает обмен по пулам вместе с пулами Uniswap V3
+///
+/// Котировки V3 загружаются по сетке из `V3_QUOTE_STEPS` сумм до суммы обмена.
+/// Если итоговое распределение пула V3 дальше `V3_REQUOTE_BPS` от узлов
+/// сетки, его котировка уточняется и решение пересчитывается один раз.
+///
+/// # Returns
+/// Результат солвера и количество вызовов QuoterV2 за решение
+pub async fn solve_with_v3(
+    pools: &[PoolState],
+    v3_pools: &mut [V3Pool],
+    ctx: &ConfigContext,
+    solver_config: &SolverConfig,
+) -> Result<(SolverResult, usize)> {
+    let grid = V3Pool::quote_grid(solver_config.total_amount_in, V3_QUOTE_STEPS);
+    let mut quoter_calls = 0;
+    for pool in v3_pools.iter_mut() {
+        let (token0, token1) = pool.tokens();
+        let Some(token_in) = [token0, token1].into_iter().find(|&token| ctx.is_input_token(token)) else {
+            continue;
+        };
+        quoter_calls += pool.load_quotes(token_in, &grid).await?;
+    }
+
+    let result = find_best_routes(combined_pools(pools, v3_pools), ctx, solver_config).await?;
+
+    let mut requoted = false;
+    for pool in v3_pools.iter_mut() {
+        let allocated = Accumulator::sum("вход пула V3", result
+            .chunk_routes
+            .iter()
+            .filter(|route| route.pool_address == Some(pool.pool_address))
+            .map(|route| route.amount_in))?;
+        if pool.requote_allocation(allocated, V3_REQUOTE_BPS).await? {
+            quoter_calls += 1;
+            requoted = true;
+        }
+    }
+    if !requoted {
+        return Ok((result, quoter_calls));
+    }
+    let result = find_best_routes(combined_pools(pools, v3_pools), ctx, solver_config).await?;
+    Ok((result, quoter_calls))
+}
+
+impl AmmPool for V3Pool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn address(&self) -> Address {
+        self.pool_address
+    }
+
+    fn dex(&self) -> DexId {
+        DexId::UNISWAP_V3
+    }
+
+    fn tokens(&self) -> (TokenId, TokenId) {
+        (self.token0, self.token1)
+    }
+
+    fn decimals(&self, token: TokenId) -> u8 {
+        if token == self.token0 { self.token0_decimals } else { self.token1_decimals }
+    }
+
+    fn reserves(&self, token_in: TokenId) -> (U256, U256) {
+        let (reserve0, reserve1) = self.virtual_reserves();
+        if token_in == self.token0 { (reserve0, reserve1) } else { (reserve1, reserve0) }
+    }
+
+    fn quote(&self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        if amount_in.is_zero() {
+            return Err(PoolError::ZeroAmount);
+        }
+        if self.quote_token_in != Some(token_in) {
+            return Err(PoolError::MissingQuote);
+        }
+        let before = self.cumulative_out(self.consumed_in).ok_or(PoolError::MissingQuote)?;
+        let consumed_after = self.consumed_in.checked_add(amount_in).ok_or(PoolError::Overflow)?;
+        let after = self.cumulative_out(consumed_after).ok_or(PoolError::MissingQuote)?;
+        Ok(after.saturating_sub(before))
+    }
+
+    fn apply(&mut self, amount_in: U256, token_in: TokenId) -> Result<U256, PoolError> {
+        let amount_out = self.quote(amount_in, token_in)?;
+        self.consumed_in += amount_in;
+        Ok(amount_out)
+    }
+
+    fn spot_price(&self, token_in: TokenId) -> f64 {
+        let (reserve_in, reserve_out) = self.reserves(token_in);
+        let token_out = self.other_token(token_in).unwrap_or(token_in);
+        math::spot_price(reserve_in, reserve_out, self.decimals(token_in), self.decimals(token_out))
+    }
+
+    fn price_impact(&self, amount_in: U256, token_in: TokenId) -> f64 {
+        let (reserve_in, reserve_out) = self.reserves(token_in);
+        match self.quote(amount_in, token_in) {
+            Ok(amount_out) if !reserve_in.is_zero() && !reserve_out.is_zero() => {
+                let execution = u256_to_f64(amount_out) / u256_to_f64(amount_in);
+                let spot = u256_to_f64(reserve_out) / u256_to_f64(reserve_in);
+                (1.0 - execution / spot).clamp(0.0, 1.0)
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn apply_reserve_haircut(&mut self, haircut_bps: u32, _token_in: TokenId) {
+        self.haircut_bps = haircut_bps.min(math::BPS_DENOMINATOR);
+    }
+
+    fn swap_fee_amount(&self, amount_in: U256) -> U256 {
+        amount_in * U256::from(self.fee) / U256::from(FEE_DENOMINATOR)
+    }
+
+    /// Выход интерполируется по сетке QuoterV2, которая уточняется до
+    /// `V3_REQUOTE_BPS` от итогового распределения (см. `solve_with_v3`)
+    fn fidelity(&self) -> SimulationFidelity {
+        SimulationFidelity::Approximate { max_error_bps: V3_REQUOTE_BPS }
+    }
+
+    fn clone_box(&self) -> Box<dyn AmmPool> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::v3::{q96, V3PoolState};
+    use crate::mock_rpc::{call_target, MockRpc};
+    use crate::provider::IQuoterV2;
+    use alloy::sol_types::{SolCall, SolValue};
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Состояние диапазона: 2500 USDC/WETH (token0 = USDC), широкие границы
+    fn range_state(fee: u32, liquidity: u128) -> V3PoolState {
+        V3PoolState {
+            sqrt_price_x96: U256::from(20_000u64) * q96(),
+            liquidity,
+            fee,
+            sqrt_price_lower_x96: U256::from(10_000u64) * q96(),
+            sqrt_price_upper_x96: U256::from(40_000u64) * q96(),
+        }
+    }
+
+    /// Ответ QuoterV2, посчитанный по математике одного диапазона
+    fn quoter_response(state: &V3PoolState, params: &Value) -> Result<Value, String> {
+        let input = params[0]["input"].as_str().or_else(|| params[0]["data"].as_str()).unwrap();
+        let call = IQuoterV2::quoteExactInputSingleCall::abi_decode(&alloy::hex::decode(input).unwrap(), true)
+            .map_err(|e| e.to_string())?;
+        let zero_for_one = call.params.tokenIn < call.params.tokenOut;
+        let quote = state.get_amount_out(call.params.amountIn, zero_for_one);
+        if quote.crossed_range {
+            return Err("execution reverted: SPL".to_string());
+        }
+        let words = (quote.amount_out, quote.sqrt_price_after_x96, 0u32, U256::from(100_000u64)).abi_encode_params();
+        Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(words))))
+    }
+
+    fn pool(rpc: &MockRpc, fee: u32) -> V3Pool {
+        let state = range_state(fee, 3_000_000_000_000_000_000);
+        let mut pool = V3Pool::new(Address::repeat_byte(0x33), TokenId::USDC, TokenId::WETH, fee, rpc.provider.clone(),
+            Address::repeat_byte(0x99), "Uniswap V3 USDC/WETH 0.05%".to_string());
+        pool.sqrt_price_x96 = state.sqrt_price_x96;
+        pool.liquidity = state.liquidity;
+        pool
+    }
+
+    fn usdc(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(1_000_000u64)
+    }
+
+    async fn quoter_rpc(fee: u32) -> (MockRpc, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let state = range_state(fee, 3_000_000_000_000_000_000);
+        let rpc = MockRpc::start(move |_, params| {
+            assert_eq!(call_target(params), Some(Address::repeat_byte(0x99)));
+            counter.fetch_add(1, Ordering::SeqCst);
+            quoter_response(&state, params)
+        }).await;
+        (rpc, calls)
+    }
+
+    #[test]
+    fn grid_and_fee_label() {
+        assert_eq!(V3Pool::quote_grid(U256::from(100u64), 4), vec![
+            U256::from(25u64), U256::from(50u64), U256::from(75u64), U256::from(100u64),
+        ]);
+        assert_eq!(V3Pool::quote_grid(U256::from(2u64), 4), vec![U256::from(1u64), U256::from(2u64)]);
+        let provider = crate::pool::test_provider();
+        let label = |fee| V3Pool::new(Address::ZERO, TokenId::USDC, TokenId::WETH, fee, provider.clone(), Address::ZERO, String::new()).fee_label();
+        assert_eq!((label(500), label(3_000), label(10_000)), ("0.05%".to_string(), "0.3%".to_string(), "1%".to_string()));
+    }
+
+    #[tokio::test]
+    async fn quotes_match_quoter_at_nodes_and_are_cached() {
+        let (rpc, calls) = quoter_rpc(500).await;
+        let mut pool = pool(&rpc, 500);
+        let grid = V3Pool::quote_grid(usdc(100_000), 4);
+        assert_eq!(pool.load_quotes(TokenId::USDC, &grid).await.unwrap(), 4);
+        // Повторная загрузка тех же сумм идет из кэша
+        assert_eq!(pool.load_quotes(TokenId::USDC, &grid).await.unwrap(), 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let state = range_state(500, 3_000_000_000_000_000_000);
+        let exact = |amount| state.get_amount_out(amount, true).amount_out;
+        assert_eq!(pool.quote(usdc(50_000), TokenId::USDC).unwrap(), exact(usdc(50_000)));
+
+        // Между узлами - интерполяция, не выше точного выхода вогнутой кривой
+        let between = pool.quote(usdc(60_000), TokenId::USDC).unwrap();
+        assert!(between <= exact(usdc(60_000)) && between > exact(usdc(50_000)));
+
+        // apply сдвигает позицию по кривой: 25k + 25k дают столько же, сколько 50k
+        let first = pool.apply(usdc(25_000), TokenId::USDC).unwrap();
+        let second = pool.apply(usdc(25_000), TokenId::USDC).unwrap();
+        assert_eq!(first + second, exact(usdc(50_000)));
+
+        assert_eq!(pool.quote(usdc(60_000), TokenId::USDC), Err(PoolError::MissingQuote));
+        assert_eq!(pool.quote(U256::from(1u64), TokenId::WETH), Err(PoolError::MissingQuote));
+    }
+
+    #[tokio::test]
+    async fn solver_routes_majority_to_deeper_005_tier() {
+        use crate::discovery_cache::DiscoveryCache;
+        use crate::provider::{discover_v3_pools, IUniswapV3Factory, IUniswapV3Pool};
+        use crate::mock_rpc::{call_selector, encode_word};
+
+        // Виртуальная глубина по USDC: 0.05% - 20M, 0.3% - 10M, 1% - 2M
+        let tiers: [(u32, u8, u128); 3] = [
+            (500, 0x51, 400_000_000_000_000_000),
+            (3_000, 0x52, 200_000_000_000_000_000),
+            (10_000, 0x53, 40_000_000_000_000_000),
+        ];
+        let quoter = Address::repeat_byte(0x99);
+        let rpc = MockRpc::start(move |_, params| {
+            let input = params[0]["input"].as_str().or_else(|| params[0]["data"].as_str()).unwrap();
+            let data = alloy::hex::decode(input).unwrap();
+            let target = call_target(params).unwrap();
+            let tier = |byte: u8| tiers.iter().find(|tier| tier.1 == byte).copied();
+            match call_selector(params).unwrap() {
+                IUniswapV3Factory::getPoolCall::SELECTOR => {
+                    let call = IUniswapV3Factory::getPoolCall::abi_decode(&data, true).unwrap();
+                    let fee: u32 = call.fee.to();
+                    let pool = tiers.iter().find(|tier| tier.0 == fee).map_or(Address::ZERO, |tier| Address::repeat_byte(tier.1));
+                    Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(pool.abi_encode()))))
+                }
+                IUniswapV3Pool::slot0Call::SELECTOR => {
+                    let words = (range_state(500, 1).sqrt_price_x96, 0i32, 0u16, 0u16, 0u16, 0u16, true).abi_encode_params();
+                    Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(words))))
+                }
+                IUniswapV3Pool::liquidityCall::SELECTOR => Ok(encode_word(U256::from(tier(target.0[0]).unwrap().2))),
+                // decimals()
+                [0x31, 0x3c, 0xe5, 0x67] => Ok(encode_word(U256::from(TokenId(target).decimals()))),
+                // symbol()
+                [0x95, 0xd8, 0x9b, 0x41] => {
+                    let symbol = if target == TokenId::WETH.address() { "WETH" } else { "USDC" };
+                    Ok(serde_json::json!(format!("0x{}", alloy::hex::encode(symbol.to_string().abi_encode()))))
+                }
+                IQuoterV2::quoteExactInputSingleCall::SELECTOR => {
+                    assert_eq!(target, quoter);
+                    let call = IQuoterV2::quoteExactInputSingleCall::abi_decode(&data, true).unwrap();
+                    let (fee, _, liquidity) = tiers.iter().find(|tier| tier.0 == call.params.fee.to::<u32>()).copied().unwrap();
+                    quoter_response(&range_state(fee, liquidity), params)
+                }
+                other => Err(format!("неожиданный вызов {:?}", other)),
+            }
+        }).await;
+
+        let ctx = ConfigContext { input_tokens: vec![TokenId::USDC], ..ConfigContext::default() };
+        let mut cache = DiscoveryCache::new(1_000);
+        cache.begin_run(100, 0);
+        let mut v3_pools = discover_v3_pools(rpc.provider.clone(), &ctx, Address::repeat_byte(0x77), quoter, &mut cache, 100).await;
+        let names: Vec<&str> = v3_pools.iter().map(|pool| pool.name.as_str()).collect();
+        assert_eq!(names, ["Uniswap V3 USDC/WETH 0.05%", "Uniswap V3 USDC/WETH 0.3%", "Uniswap V3 USDC/WETH 1%"]);
+
+        // Повторный discovery берет адреса пулов из кэша, getPool не вызывается
+        let requests = rpc.request_count();
+        cache.begin_run(101, 0);
+        let again = discover_v3_pools(rpc.provider.clone(), &ctx, Address::repeat_byte(0x77), quoter, &mut cache, 101).await;
+        assert_eq!(again.len(), 3);
+        assert_eq!(cache.stats().hits, 3);
+        assert_eq!(rpc.request_count() - requests, 3 * 2);
+
+        let weth = U256::from(10u64).pow(U256::from(18u64));
+        let v2 = vec![crate::pool::test_pool(0x11, TokenId::USDC, TokenId::WETH, usdc(2_000_000), U256::from(800u64) * weth)];
+        let solver_config = SolverConfig { total_amount_in: usdc(500_000), num_chunks: 50, verbose: false, ..Default::default() };
+        let (result, quoter_calls) = solve_with_v3(&v2, &mut v3_pools, &ctx, &solver_config).await.unwrap();
+
+        let volume_in = |name: &str| -> U256 {
+            result.chunk_routes.iter().filter(|route| route.best_pool_name == name).map(|route| route.amount_in).sum()
+        };
+        let deep = volume_in("Uniswap V3 USDC/WETH 0.05%");
+        assert!(deep * U256::from(2u64) > solver_config.total_amount_in, "0.05%: {} из {}", deep, solver_config.total_amount_in);
+        // Отчет и статистика различают пулы по адресу и уровню комиссии
+        assert!(result.chunk_routes.iter().any(|route| route.dex == Some(DexId::UNISWAP_V3)
+            && route.pool_address == Some(Address::repeat_byte(0x51))));
+        // Сетка на каждый пул плюс не более одного уточнения на пул
+        assert!(quoter_calls >= 3 * V3_QUOTE_STEPS as usize && quoter_calls <= 3 * (V3_QUOTE_STEPS as usize + 1), "{}", quoter_calls);
+
+        // Повторное решение той же суммы не обращается к QuoterV2
+        let (again, calls) = solve_with_v3(&v2, &mut v3_pools, &ctx, &solver_config).await.unwrap();
+        assert_eq!(calls, 0);
+        assert_eq!(again.total_weth_out, result.total_weth_out);
+    }
+
+    #[tokio::test]
+    async fn requotes_only_when_allocation_is_far_from_grid() {
+        let (rpc, calls) = quoter_rpc(500).await;
+        let mut pool = pool(&rpc, 500);
+        pool.load_quotes(TokenId::USDC, &V3Pool::quote_grid(usdc(100_000), 4)).await.unwrap();
+
+        // 50.1k в 20 bps от узла 50k: порог 50 bps не превышен
+        assert!(!pool.requote_allocation(usdc(50_100), 50).await.unwrap());
+        assert!(pool.requote_allocation(usdc(60_000), 50).await.unwrap());
+        assert!(!pool.requote_allocation(usdc(60_000), 50).await.unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+        let state = range_state(500, 3_000_000_000_000_000_000);
+        assert_eq!(pool.quote(usdc(60_000), TokenId::USDC).unwrap(), state.get_amount_out(usdc(60_000), true).amount_out);
+    }
+}
@@ -0,0 +1,452 @@
+// src/watch.rs
+//! Отслеживание резервов по событиям Sync через WebSocket
+//!
+//! Пара Uniswap V2 испускает `Sync(reserve0, reserve1)` после каждого
+//! изменения резервов, поэтому подписки на эти события достаточно, чтобы
+//! держать резервы актуальными без опроса. Пулы принадлежат одному
+//! `ReserveTracker`, который меняет их на месте; котировки читают его
+//! состояние без запросов к сети.
+//!
+//! Событие несет абсолютные резервы, поэтому пропущенное событие не
+//! восстановить из следующих. При каждом (пере)подключении, при отставании
+//! подписки и при откате блока (`removed`) резервы перечитываются целиком.
+//! Пулы, чтение которых раз за разом падает, выключаются автоматическими
+//! выключателями (`breaker`): их не перечитывают и не учитывают в ценах.
+use crate::breaker::{BreakerConfig, BreakerState, BreakerTarget, Breakers};
+use crate::log;
+use crate::pool::{refresh_reserves_at_block, Pool, PoolState};
+use crate::provider::{IUniswapV2Pair, RpcProvider};
+use alloy::primitives::{Address, U256};
+use alloy::providers::{Provider, ProviderBuilder, WsConnect};
+use alloy::rpc::types::{Filter, Log};
+use alloy::sol_types::SolEvent;
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Первая пауза перед переподключением; дальше удваивается до `MAX_RECONNECT_DELAY`
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Разобранное событие Sync пары
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncEvent {
+    pub pool_address: Address,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub block_number: u64,
+    pub log_index: u64,
+    /// Лог отменен реорганизацией цепочки
+    pub removed: bool,
+}
+
+/// Разбирает лог Sync; `None` для чужих событий и логов без блока (pending)
+pub fn decode_sync(log: &Log) -> Option<SyncEvent> {
+    let decoded = log.log_decode::<IUniswapV2Pair::Sync>().ok()?;
+    Some(SyncEvent {
+        pool_address: log.address(),
+        reserve0: U256::from(decoded.inner.data.reserve0),
+        reserve1: U256::from(decoded.inner.data.reserve1),
+        block_number: log.block_number?,
+        log_index: log.log_index.unwrap_or(0),
+        removed: log.removed,
+    })
+}
+
+/// Результат применения события к пулам
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// Резервы пула с этим индексом обновлены
+    Applied(usize),
+    /// Событие старше уже примененного состояния пула
+    Stale,
+    /// Пул не отслеживается
+    Unknown,
+    /// Событие отменено: состояние нужно перечитать целиком
+    NeedsRefresh,
+}
+
+/// Владелец пулов, резервы которых обновляются событиями Sync
+#[derive(Debug)]
+pub struct ReserveTracker {
+    pools: Vec<Pool>,
+    by_address: HashMap<Address, usize>,
+    /// (блок, индекс лога) последнего примененного состояния каждого пула
+    positions: Vec<(u64, u64)>,
+    breakers: Breakers,
+}
+
+impl ReserveTracker {
+    /// Отслеживаются только пары constant product: у взвешенных пулов Balancer
+    /// нет события Sync, их резервы обновляются только полным перечитыванием
+    pub fn new(pools: Vec<Pool>) -> Self {
+        let by_address = pools
+            .iter()
+            .enumerate()
+            .filter(|(_, pool)| pool.is_constant_product())
+            .map(|(index, pool)| (pool.pool_address, index))
+            .collect();
+        let positions = vec![(0, 0); pools.len()];
+        ReserveTracker { pools, by_address, positions, breakers: Breakers::default() }
+    }
+
+    /// Заменяет параметры выключателей (по умолчанию `BreakerConfig::default()`)
+    pub fn with_breaker_config(mut self, config: BreakerConfig) -> Self {
+        self.breakers = Breakers::new(config);
+        self
+    }
+
+    pub fn pools(&self) -> &[Pool] {
+        &self.pools
+    }
+
+    /// Состояния пулов для солвера, без пулов с незамкнутым выключателем
+    pub fn states(&self) -> Vec<PoolState> {
+        self.pools
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.is_excluded(*index))
+            .map(|(_, pool)| pool.state.clone())
+            .collect()
+    }
+
+    /// Пул исключен из маршрутизации выключателем пула или его DEX
+    pub fn is_excluded(&self, index: usize) -> bool {
+        let pool = &self.pools[index];
+        self.breakers.excluded(pool.pool_address, pool.dex)
+    }
+
+    /// Незамкнутые выключатели для отчета о состоянии
+    pub fn tripped_breakers(&self) -> Vec<(BreakerTarget, BreakerState)> {
+        self.breakers.tripped()
+    }
+
+    /// Адреса пар для фильтра подписки
+    pub fn tracked_addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.by_address.keys().copied().collect();
+        addresses.sort();
+        addresses
+    }
+
+    /// Применяет событие Sync к пулу
+    ///
+    /// События одного пула применяются в порядке (блок, индекс лога):
+    /// пришедшее позже более старое событие не откатывает резервы.
+    pub fn apply(&mut self, event: &SyncEvent) -> SyncOutcome {
+        if event.removed {
+            return SyncOutcome::NeedsRefresh;
+        }
+        let Some(&index) = self.by_address.get(&event.pool_address) else {
+            return SyncOutcome::Unknown;
+        };
+        let position = (event.block_number, event.log_index);
+        if position <= self.positions[index] {
+            return SyncOutcome::Stale;
+        }
+        self.positions[index] = position;
+        // Sync не несет времени блока: время обновления остается от getReserves
+        let last_updated = self.pools[index].last_updated;
+        self.pools[index].set_reserves(event.reserve0, event.reserve1, last_updated);
+        SyncOutcome::Applied(index)
+    }
+
+    /// Перечитывает резервы всех пулов и возвращает блок, на котором они прочитаны;
+    /// события не новее этого блока после этого считаются устаревшими
+    pub async fn refresh(&mut self, provider: Arc<RpcProvider>) -> Result<u64> {
+        self.refresh_at(provider, Instant::now()).await
+    }
+
+    /// `refresh` в момент `now`: пулы с разомкнутым выключателем пропускаются,
+    /// результаты чтения остальных переключают выключатели
+    pub async fn refresh_at(&mut self, provider: Arc<RpcProvider>, now: Instant) -> Result<u64> {
+        let allowed: Vec<usize> = (0..self.pools.len())
+            .filter(|&index| self.breakers.allow(self.pools[index].pool_address, self.pools[index].dex, now))
+            .collect();
+        if allowed.len() < self.pools.len() {
+            log!("Пропущено пулов с выключателем: {}", self.pools.len() - allowed.len());
+        }
+
+        // Блок из того же multicall, что и резервы: иначе события между чтением
+        // резервов и запросом блока считались бы устаревшими и терялись
+        let (block, failures) = refresh_reserves_at_block(provider, &mut self.pools, &allowed).await?;
+        for (address, e) in &failures {
+            log!("Не удалось обновить резервы {:?}: {}", address, e);
+        }
+        let outcomes: Vec<_> = allowed
+            .iter()
+            .map(|&index| {
+                let pool = &self.pools[index];
+                (pool.pool_address, pool.dex, !failures.iter().any(|(address, _)| *address == pool.pool_address))
+            })
+            .collect();
+        for event in self.breakers.record_round(&outcomes, now) {
+            log!("Выключатель: {}", event);
+        }
+        self.positions.fill((block, u64::MAX));
+        Ok(block)
+    }
+}
+
+/// Следит за резервами пулов, пока не будет прерван
+///
+/// `on_sync` вызывается после каждого примененного события с индексом
+/// обновленного пула. При обрыве соединения подключение повторяется с
+/// растущей паузой, а резервы перечитываются через HTTP `provider`.
+pub async fn watch(
+    provider: Arc<RpcProvider>,
+    ws_url: &str,
+    tracker: &mut ReserveTracker,
+    mut on_sync: impl FnMut(&ReserveTracker, usize, u64),
+) -> Result<()> {
+    if tracker.tracked_addresses().is_empty() {
+        return Err(eyre!("нет пар Uniswap V2 для подписки на Sync"));
+    }
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        let error = run_subscription(provider.clone(), ws_url, tracker, &mut delay, &mut on_sync).await;
+        log!("Подписка на Sync прервана: {}; переподключение через {} с", error, delay.as_secs());
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Одно подключение: подписка, полное перечитывание резервов, применение событий
+///
+/// Подписка создается до перечитывания, поэтому события между ними не
+/// теряются. Возвращает причину обрыва.
+async fn run_subscription(
+    provider: Arc<RpcProvider>,
+    ws_url: &str,
+    tracker: &mut ReserveTracker,
+    delay: &mut Duration,
+    on_sync: &mut impl FnMut(&ReserveTracker, usize, u64),
+) -> eyre::Report {
+    let ws = match ProviderBuilder::new().on_ws(WsConnect::new(ws_url)).await {
+        Ok(ws) => ws,
+        Err(e) => return eyre!("не удалось подключиться к {}: {}", ws_url, e),
+    };
+    let filter = Filter::new().address(tracker.tracked_addresses()).event_signature(IUniswapV2Pair::Sync::SIGNATURE_HASH);
+    let mut subscription = match ws.subscribe_logs(&filter).await {
+        Ok(subscription) => subscription,
+        Err(e) => return eyre!("подписка на логи отклонена: {}", e),
+    };
+    if let Err(e) = full_refresh(provider.clone(), tracker).await {
+        return e;
+    }
+    log!("Подписка на Sync активна: {} пар", tracker.tracked_addresses().len());
+    *delay = RECONNECT_DELAY;
+
+    loop {
+        match subscription.recv().await {
+            Ok(log) => {
+                let Some(event) = decode_sync(&log) else { continue };
+                match tracker.apply(&event) {
+                    SyncOutcome::Applied(index) => on_sync(tracker, index, event.block_number),
+                    SyncOutcome::NeedsRefresh => {
+                        log!("Событие блока {} отменено реорганизацией, перечитываем резервы", event.block_number);
+                        if let Err(e) = full_refresh(provider.clone(), tracker).await {
+                            return e;
+                        }
+                    }
+                    SyncOutcome::Stale | SyncOutcome::Unknown => {}
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                log!("Пропущено {} событий, перечитываем резервы", skipped);
+                if let Err(e) = full_refresh(provider.clone(), tracker).await {
+                    return e;
+                }
+            }
+            Err(RecvError::Closed) => return eyre!("сервер закрыл подписку"),
+        }
+    }
+}
+
+async fn full_refresh(provider: Arc<RpcProvider>, tracker: &mut ReserveTracker) -> Result<()> {
+    let block = tracker.refresh(provider).await?;
+    log!("Резервы перечитаны на блоке {}", block);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TokenId;
+    use crate::pool::{test_pool, test_provider};
+    use alloy::primitives::{aliases::U112, LogData};
+
+    fn sync_log(pool_byte: u8, reserve0: u64, reserve1: u64, block: u64, log_index: u64) -> Log {
+        let event = IUniswapV2Pair::Sync { reserve0: U112::from(reserve0), reserve1: U112::from(reserve1) };
+        let data: LogData = event.encode_log_data();
+        Log {
+            inner: alloy::primitives::Log { address: Address::repeat_byte(pool_byte), data },
+            block_number: Some(block),
+            log_index: Some(log_index),
+            ..Default::default()
+        }
+    }
+
+    fn tracker() -> ReserveTracker {
+        let provider = test_provider();
+        let pools = [0x11, 0x12]
+            .into_iter()
+            .map(|byte| Pool::from_state(test_pool(byte, TokenId::USDC, TokenId::WETH, U256::from(1_000u64), U256::from(1u64)), provider.clone()))
+            .collect();
+        ReserveTracker::new(pools)
+    }
+
+    #[test]
+    fn decodes_sync_and_ignores_other_logs() {
+        let event = decode_sync(&sync_log(0x11, 5, 7, 100, 3)).unwrap();
+        assert_eq!(event.pool_address, Address::repeat_byte(0x11));
+        assert_eq!((event.reserve0, event.reserve1), (U256::from(5u64), U256::from(7u64)));
+        assert_eq!((event.block_number, event.log_index, event.removed), (100, 3, false));
+
+        let mut pending = sync_log(0x11, 5, 7, 100, 3);
+        pending.block_number = None;
+        assert_eq!(decode_sync(&pending), None);
+
+        let mut other = sync_log(0x11, 5, 7, 100, 3);
+        other.inner.data = LogData::new_unchecked(vec![alloy::primitives::B256::repeat_byte(0xaa)], other.inner.data.data.clone());
+        assert_eq!(decode_sync(&other), None);
+    }
+
+    #[test]
+    fn applies_events_in_order_per_pool() {
+        let mut tracker = tracker();
+        let apply = |tracker: &mut ReserveTracker, log: Log| tracker.apply(&decode_sync(&log).unwrap());
+
+        assert_eq!(apply(&mut tracker, sync_log(0x12, 20, 30, 100, 5)), SyncOutcome::Applied(1));
+        let pool = &tracker.pools()[1];
+        assert_eq!((pool.reserve_token0, pool.reserve_token1), (U256::from(20u64), U256::from(30u64)));
+        // Котировка видит новые резервы без обращения к сети
+        assert_eq!(tracker.states()[1].reserve_token0, U256::from(20u64));
+
+        // Более старое событие того же пула не откатывает резервы
+        assert_eq!(apply(&mut tracker, sync_log(0x12, 1, 1, 100, 4)), SyncOutcome::Stale);
+        assert_eq!(apply(&mut tracker, sync_log(0x12, 1, 1, 99, 9)), SyncOutcome::Stale);
+        assert_eq!(tracker.pools()[1].reserve_token0, U256::from(20u64));
+        // Порядок отслеживается отдельно для каждого пула
+        assert_eq!(apply(&mut tracker, sync_log(0x11, 40, 50, 100, 1)), SyncOutcome::Applied(0));
+        assert_eq!(apply(&mut tracker, sync_log(0x12, 21, 31, 101, 0)), SyncOutcome::Applied(1));
+
+        assert_eq!(apply(&mut tracker, sync_log(0x99, 1, 1, 200, 0)), SyncOutcome::Unknown);
+        let mut removed = sync_log(0x11, 1, 1, 101, 2);
+        removed.removed = true;
+        assert_eq!(apply(&mut tracker, removed), SyncOutcome::NeedsRefresh);
+        assert_eq!(tracker.pools()[0].reserve_token0, U256::from(40u64));
+    }
+
+    #[tokio::test]
+    async fn failing_pool_is_switched_off_and_recovers_after_probe() {
+        use crate::config::MULTICALL3_ADDRESS;
+        use crate::mock_rpc::{call_target, MockRpc};
+        use alloy::sol_types::SolCall;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let failing = Arc::new(AtomicBool::new(true));
+        let flag = failing.clone();
+        let rpc = MockRpc::start(move |method, params| {
+            if method == "eth_blockNumber" {
+                return Ok(serde_json::json!("0x64"));
+            }
+            let target = call_target(params).unwrap();
+            if target == MULTICALL3_ADDRESS || (target == Address::repeat_byte(0x11) && flag.load(Ordering::SeqCst)) {
+                return Err("execution reverted".to_string());
+            }
+            let reserves = (U112::from(target[19]), U112::from(2 * target[19] as u64), 1_700_000_000u32);
+            Ok(serde_json::json!(alloy::hex::encode_prefixed(IUniswapV2Pair::getReservesCall::abi_encode_returns(&reserves))))
+        }).await;
+        let pools = [0x11, 0x12]
+            .into_iter()
+            .map(|byte| Pool::from_state(test_pool(byte, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), rpc.provider.clone()))
+            .collect();
+        let config = BreakerConfig { failure_threshold: 2, base_backoff: Duration::from_secs(10), max_backoff: Duration::from_secs(60) };
+        let mut tracker = ReserveTracker::new(pools).with_breaker_config(config);
+        let start = Instant::now();
+
+        tracker.refresh_at(rpc.provider.clone(), start).await.unwrap();
+        assert!(!tracker.is_excluded(0) && tracker.states().len() == 2);
+        tracker.refresh_at(rpc.provider.clone(), start + Duration::from_secs(1)).await.unwrap();
+        assert!(tracker.is_excluded(0) && !tracker.is_excluded(1));
+        assert_eq!(tracker.states().len(), 1);
+        assert_eq!(tracker.tripped_breakers(), vec![(BreakerTarget::Pool(Address::repeat_byte(0x11)),
+            BreakerState::Open { until: start + Duration::from_secs(11) })]);
+
+        // Пока выключатель разомкнут, пул не опрашивается: multicall, номер блока и getReserves второго пула
+        let requests = rpc.request_count();
+        tracker.refresh_at(rpc.provider.clone(), start + Duration::from_secs(5)).await.unwrap();
+        assert_eq!(rpc.request_count() - requests, 3);
+
+        // Пул восстановился: проба после паузы замыкает выключатель
+        failing.store(false, Ordering::SeqCst);
+        tracker.refresh_at(rpc.provider.clone(), start + Duration::from_secs(11)).await.unwrap();
+        assert!(!tracker.is_excluded(0));
+        assert!(tracker.tripped_breakers().is_empty());
+        assert_eq!(tracker.states().len(), 2);
+        assert_eq!(tracker.pools()[0].reserve_token0, U256::from(0x11u64));
+    }
+
+    #[tokio::test]
+    async fn reconnect_rereads_reserves_and_resumes_events() {
+        use crate::config::MULTICALL3_ADDRESS;
+        use crate::mock_rpc::{call_input, MockRpc};
+        use crate::provider::IMulticall3;
+        use alloy::sol_types::SolCall;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // Каждое чтение резервов идет на 100 блоков позже предыдущего; reserve0 - номер блока
+        let last_block = Arc::new(AtomicU64::new(0));
+        let counter = last_block.clone();
+        let rpc = MockRpc::start(move |_, params| {
+            let calls = IMulticall3::aggregate3Call::abi_decode(&call_input(params).unwrap(), true).unwrap().calls;
+            let block = counter.fetch_add(100, Ordering::SeqCst) + 100;
+            let results: Vec<IMulticall3::Result> = calls
+                .iter()
+                .map(|call| {
+                    let data = match call.target == MULTICALL3_ADDRESS {
+                        true => IMulticall3::getBlockNumberCall::abi_encode_returns(&(U256::from(block),)),
+                        false => IUniswapV2Pair::getReservesCall::abi_encode_returns(&(U112::from(block), U112::from(1u64), 0u32)),
+                    };
+                    IMulticall3::Result { success: true, returnData: data.into() }
+                })
+                .collect();
+            Ok(serde_json::json!(alloy::hex::encode_prefixed(IMulticall3::aggregate3Call::abi_encode_returns(&(results,)))))
+        }).await;
+        // Соединение 1 - переподключение самого транспорта - отклоняется, подписка закрывается
+        let ws_url = MockRpc::start_ws_subscriptions(|connection| {
+            let logs = match connection {
+                0 => vec![sync_log(0x12, 5, 5, 100, 0), sync_log(0x11, 7, 7, 150, 0)],
+                2 => vec![sync_log(0x12, 8, 8, 150, 1), sync_log(0x12, 9, 9, 201, 0)],
+                _ => return None,
+            };
+            Some(logs.iter().map(|log| serde_json::to_value(log).unwrap()).collect())
+        }).await;
+        let pools = [0x11, 0x12]
+            .into_iter()
+            .map(|byte| Pool::from_state(test_pool(byte, TokenId::USDC, TokenId::WETH, U256::ZERO, U256::ZERO), rpc.provider.clone()))
+            .collect();
+        let mut tracker = ReserveTracker::new(pools);
+        let mut applied = Vec::new();
+        let mut on_sync = |_: &ReserveTracker, index: usize, block: u64| applied.push((index, block));
+        let mut delay = MAX_RECONNECT_DELAY;
+
+        let error = run_subscription(rpc.provider.clone(), &ws_url, &mut tracker, &mut delay, &mut on_sync).await;
+        assert!(error.to_string().contains("закрыл"), "{}", error);
+        assert_eq!(delay, RECONNECT_DELAY);
+        // Резервы прочитаны на блоке 100 из того же multicall: событие блока 100 устарело
+        assert_eq!(tracker.pools()[1].reserve_token0, U256::from(100u64));
+        assert_eq!(tracker.pools()[0].reserve_token0, U256::from(7u64));
+
+        // Повторное подключение перечитывает резервы (блок 200) и снова применяет события
+        delay = MAX_RECONNECT_DELAY;
+        let error = run_subscription(rpc.provider.clone(), &ws_url, &mut tracker, &mut delay, &mut on_sync).await;
+        assert!(error.to_string().contains("закрыл"), "{}", error);
+        assert_eq!(delay, RECONNECT_DELAY);
+        assert_eq!(tracker.pools()[0].reserve_token0, U256::from(200u64));
+        assert_eq!(tracker.pools()[1].reserve_token0, U256::from(9u64));
+        assert_eq!(applied, vec![(0, 150), (1, 201)]);
+    }
+}
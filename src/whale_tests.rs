@@ -0,0 +1,154 @@
+// src/whale_tests.rs
+//! Регрессионный набор для "китовых" котировок: суммы порядка 10^9 USDC и
+//! синтетические пары 18/18 decimals с резервами около максимума uint112.
+//! Проверяет, что весь конвейер (математика, солвер, вывод) не паникует,
+//! выводит точные значения и возвращает явные ошибки там, где результат
+//! не представим.
+
+use crate::config::{format_units, usdc_to_decimal, weth_to_decimal, ConfigContext, TokenId, USDC_DECIMALS, WETH_DECIMALS};
+use crate::math::{
+    amount_in_to_reach_price, get_amount_out, marginal_rate, mul_div, optimal_split_n, price_impact,
+    spot_price, DEFAULT_FEE_BPS,
+};
+use crate::pool::test_pool;
+use crate::solver::{find_best_routes, granularity_sweep, SolverConfig, Strategy};
+use alloy::primitives::{Address, U256};
+
+/// Максимальное значение резерва в Uniswap V2 (uint112)
+fn uint112_max() -> U256 {
+    (U256::from(1u64) << 112) - U256::from(1u64)
+}
+
+fn weth(amount: u64) -> U256 {
+    U256::from(amount) * U256::from(10u64).pow(U256::from(18u64))
+}
+
+/// Синтетические токены без метаданных: decimals по умолчанию 18
+fn synthetic_tokens() -> (TokenId, TokenId) {
+    (TokenId(Address::repeat_byte(0xA1)), TokenId(Address::repeat_byte(0xB2)))
+}
+
+fn synthetic_context(total_amount_in: U256) -> ConfigContext {
+    let (token_in, token_out) = synthetic_tokens();
+    ConfigContext {
+        input_tokens: vec![token_in],
+        output_token: token_out,
+        total_amount_in,
+        ..ConfigContext::default()
+    }
+}
+
+#[test]
+fn decimal_conversions_do_not_panic_beyond_u64_and_u128() {
+    // 10^9 USDC помещается в u64, но 10^14 USDC и uint112 WETH - уже нет
+    assert_eq!(usdc_to_decimal(U256::from(1_000_000_000_000_000u64)), 1e9);
+    assert!(usdc_to_decimal(U256::from(10u64).pow(U256::from(20u64))).is_finite());
+    assert!(weth_to_decimal(U256::MAX).is_finite());
+    assert!(weth_to_decimal(uint112_max()) > 5e15);
+}
+
+#[test]
+fn fixed_point_rendering_is_exact() {
+    assert_eq!(format_units(U256::from(1_000_000_000_000_000u64), USDC_DECIMALS), "1000000000.000000");
+    assert_eq!(format_units(U256::from(1u64), USDC_DECIMALS), "0.000001");
+    assert_eq!(format_units(U256::ZERO, WETH_DECIMALS), "0.000000000000000000");
+    assert_eq!(format_units(U256::from(42u64), 0), "42");
+    // 2^112 - 1 = 5192296858534827628530496329220095
+    assert_eq!(format_units(uint112_max(), WETH_DECIMALS), "5192296858534827.628530496329220095");
+}
+
+#[test]
+fn math_handles_uint112_reserves_on_both_sides() {
+    let reserve = uint112_max();
+    let amount_in = reserve / U256::from(3u64);
+
+    let out = get_amount_out(amount_in, reserve, reserve);
+    assert!(out > U256::ZERO && out < reserve);
+    assert_eq!(get_amount_out(U256::MAX, reserve, reserve), reserve - U256::from(1u64));
+
+    let impact = price_impact(amount_in, reserve, reserve, DEFAULT_FEE_BPS);
+    assert!(impact > 0.0 && impact < 1.0);
+    assert!((spot_price(reserve, reserve, 18, 18) - 1.0).abs() < 1e-12);
+    assert!(marginal_rate(amount_in, reserve, reserve, DEFAULT_FEE_BPS).is_some());
+
+    let split = optimal_split_n(amount_in, &[(reserve, reserve), (reserve / U256::from(2u64), reserve)], DEFAULT_FEE_BPS);
+    assert_eq!(split.iter().copied().sum::<U256>(), amount_in);
+
+    let to_half_price = amount_in_to_reach_price(reserve, reserve, U256::from(1u64), U256::from(2u64), DEFAULT_FEE_BPS);
+    assert!(to_half_price.is_some());
+}
+
+#[test]
+fn unrepresentable_results_are_reported_not_panicked() {
+    assert_eq!(mul_div(U256::MAX, U256::MAX, U256::from(1u64)), None);
+    assert_eq!(mul_div(U256::MAX, U256::MAX, U256::MAX), Some(U256::MAX));
+    // Цель требует входа больше U256
+    assert_eq!(amount_in_to_reach_price(U256::MAX, U256::MAX, U256::from(1u64), U256::MAX, DEFAULT_FEE_BPS), None);
+}
+
+#[tokio::test]
+async fn billion_usdc_quote_through_every_strategy() {
+    let pools = vec![
+        test_pool(0x11, TokenId::USDC, TokenId::WETH, U256::from(3_000_000_000_000u64), weth(1_200)),
+        test_pool(0x22, TokenId::USDC, TokenId::WETH, U256::from(800_000_000_000u64), weth(330)),
+    ];
+    let ctx = ConfigContext::default();
+    let total = U256::from(1_000_000_000_000_000u64); // 10^9 USDC
+
+    for strategy in [Strategy::Greedy, Strategy::TwoPoolAnalytic, Strategy::MarginalEqualization] {
+        let config = SolverConfig { total_amount_in: total, num_chunks: 1_000, verbose: false, strategy, ..Default::default() };
+        let result = find_best_routes(pools.clone(), &ctx, &config).await.unwrap();
+
+        // Почти весь WETH выкуплен, но резервы не исчерпаны
+        assert!(result.total_weth_out < weth(1_530));
+        assert!(result.total_weth_out > weth(1_500));
+        assert!(result.cumulative_price_impact > 0.99 && result.cumulative_price_impact < 1.0);
+        assert!(result.chunk_routes.iter().all(|route| route.amount_in_decimal.is_finite()));
+    }
+
+    let sweep = granularity_sweep(&pools, &ctx, total, 64).await.unwrap();
+    assert!(sweep.points.iter().all(|point| point.total_out > U256::ZERO));
+}
+
+#[tokio::test]
+async fn synthetic_18_decimal_pair_near_uint112_max() {
+    let (token_in, token_out) = synthetic_tokens();
+    let reserve = uint112_max();
+    let pools = vec![
+        test_pool(0x11, token_in, token_out, reserve / U256::from(2u64), reserve),
+        test_pool(0x22, token_in, token_out, reserve / U256::from(4u64), reserve / U256::from(3u64)),
+    ];
+    let total = reserve / U256::from(5u64);
+    let ctx = synthetic_context(total);
+
+    for strategy in [Strategy::Greedy, Strategy::TwoPoolAnalytic, Strategy::MarginalEqualization] {
+        let config = SolverConfig { total_amount_in: total, num_chunks: 256, verbose: false, strategy, ..Default::default() };
+        let result = find_best_routes(pools.clone(), &ctx, &config).await.unwrap();
+
+        assert!(result.total_weth_out > U256::ZERO);
+        assert!(result.total_weth_out < reserve + reserve / U256::from(3u64));
+        assert!(result.total_weth_out_decimal.is_finite());
+        let allocated: U256 = result.chunk_routes.iter().map(|route| route.amount_in).sum();
+        assert!(allocated <= total);
+    }
+
+    let mut pool = pools[0].clone();
+    let input_is_token0 = pool.token0 == token_in;
+    assert!((pool.spot_price(input_is_token0) - 2.0).abs() < 1e-9);
+    let out = pool.mock_swap(total, input_is_token0).unwrap();
+    assert_eq!(pool.reserves_for(input_is_token0), (reserve / U256::from(2u64) + total, reserve - out));
+}
+
+#[test]
+fn swap_by_token_near_uint112_max_matches_boolean_swap() {
+    let (token_in, token_out) = synthetic_tokens();
+    let reserve = uint112_max();
+    let pool = test_pool(0x11, token_in, token_out, reserve / U256::from(2u64), reserve);
+    let total = reserve / U256::from(5u64);
+
+    let mut by_token = pool.clone();
+    let mut by_side = pool.clone();
+    let out = by_token.swap_by_token(token_in, total).unwrap();
+    assert_eq!(out, by_side.mock_swap(total, pool.token0 == token_in).unwrap());
+    assert_eq!(by_token.reserves_for(true), by_side.reserves_for(true));
+}
@@ -0,0 +1,109 @@
+// tests/quiet_mode.rs
+//! Контракт `--quiet`: stdout процесса содержит ровно одну строку
+//! `OK <total_out_raw> <effective_price> <block>`,
+//! `PARTIAL <total_out_raw> <effective_price> <block> <unfilled_in_raw>` или `ERR <kind>`.
+//! Запускается собранный бинарник; RPC недоступен, поэтому успешный
+//! прогон идет по пулам из файла `--load-pools`.
+
+use alloy::primitives::{Address, U256};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use swap_aggregator::config::{DexId, TokenId};
+use swap_aggregator::market_snapshot::MarketSnapshot;
+use swap_aggregator::pool::PoolState;
+
+/// Закрытый порт: любой запрос к сети завершается ошибкой
+const UNREACHABLE_RPC: &str = "http://127.0.0.1:9";
+
+fn run_quiet(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_swap_aggregator"))
+        .arg("--quiet")
+        .args(args)
+        .env("INFURA_POLYGON_URL", UNREACHABLE_RPC)
+        .output()
+        .expect("бинарник запускается")
+}
+
+fn stdout_lines(output: &Output) -> Vec<String> {
+    String::from_utf8(output.stdout.clone()).unwrap().lines().map(str::to_string).collect()
+}
+
+/// Файл пулов USDC/WETH на блоке `block` в формате `--save-pools`
+fn write_pools(path: &Path, block: u64) {
+    let weth = U256::from(10u64).pow(U256::from(18u64));
+    let pools: Vec<PoolState> = [(0x11, 2_000_000_000_000u64, 800u64), (0x12, 1_000_000_000_000, 405)]
+        .into_iter()
+        .map(|(byte, reserve_usdc, reserve_weth)| {
+            let mut pool = PoolState::new(Address::repeat_byte(byte), TokenId::USDC, TokenId::WETH, DexId::QUICKSWAP,
+                format!("Quickswap USDC/WETH #{}", byte));
+            let (usdc, weth_reserve) = (U256::from(reserve_usdc), U256::from(reserve_weth) * weth);
+            if pool.token0 == TokenId::USDC {
+                (pool.reserve_token0, pool.reserve_token1) = (usdc, weth_reserve);
+                (pool.token0_decimals, pool.token1_decimals) = (6, 18);
+            } else {
+                (pool.reserve_token0, pool.reserve_token1) = (weth_reserve, usdc);
+                (pool.token0_decimals, pool.token1_decimals) = (18, 6);
+            }
+            pool
+        })
+        .collect();
+    let snapshot = MarketSnapshot::from_pools(&pools, block, 0);
+    std::fs::write(path, serde_json::to_string(&snapshot).unwrap()).unwrap();
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("quiet_mode_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn success_prints_exactly_one_ok_line() {
+    let path = temp_path("pools.json");
+    write_pools(&path, 65_432_100);
+    let output = run_quiet(&["--load-pools", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    let lines = stdout_lines(&output);
+    assert_eq!(lines.len(), 1, "stdout: {:?}\nstderr: {}", lines, String::from_utf8_lossy(&output.stderr));
+    let fields: Vec<&str> = lines[0].split(' ').collect();
+    assert_eq!(fields.len(), 4, "{}", lines[0]);
+    assert_eq!(fields[0], "OK");
+    assert!(fields[1].parse::<U256>().unwrap() > U256::ZERO);
+    let price: f64 = fields[2].parse().unwrap();
+    // Не лучше спота пулов (~2470-2500 USDC/WETH): крупная сделка двигает цену
+    assert!(price.is_finite() && price > 2_400.0, "{}", price);
+    assert_eq!(fields[3], "65432100");
+    assert!(output.status.success());
+    // Лог анализа никуда не пропал - он в stderr
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn guarded_partial_fill_prints_partial_line_with_unfilled_input() {
+    let path = temp_path("partial_pools.json");
+    write_pools(&path, 65_432_100);
+    // Предел 200 bps: конец крупной сделки выходит за предел и не исполняется
+    let output = run_quiet(&["--load-pools", path.to_str().unwrap(), "--max-chunk-price-deviation-bps", "200",
+        "--chunk-guard-remedy", "reroute"]);
+    std::fs::remove_file(&path).unwrap();
+
+    let lines = stdout_lines(&output);
+    assert_eq!(lines.len(), 1, "stdout: {:?}\nstderr: {}", lines, String::from_utf8_lossy(&output.stderr));
+    let fields: Vec<&str> = lines[0].split(' ').collect();
+    assert_eq!((fields[0], fields.len()), ("PARTIAL", 5), "{}", lines[0]);
+    assert_eq!(fields[3], "65432100");
+    assert!(fields[4].parse::<U256>().unwrap() > U256::ZERO);
+    assert!(output.status.success());
+}
+
+#[test]
+fn failures_print_exactly_one_err_line() {
+    let missing = temp_path("missing.json");
+    for (args, kind) in [
+        (vec!["--load-pools", missing.to_str().unwrap()], "ERR io"),
+        (vec!["regress"], "ERR other"),
+    ] {
+        let output = run_quiet(&args);
+        assert_eq!(stdout_lines(&output), vec![kind.to_string()], "{:?}", args);
+        assert!(!output.status.success());
+    }
+}